@@ -0,0 +1,459 @@
+//! Client of the ad-server API.  The requests that go through the queue of the server return once
+//! they complete, polling their state in `GET /request/{req_id}`.
+
+use std::time::Duration;
+
+use alloy::primitives::{B256, TxHash};
+use app::{Group, Op};
+use common::api::QueueResp;
+pub use common::api::{QueryProofResponse, TxCostInfo};
+use reqwest::{RequestBuilder, StatusCode};
+use serde::{Deserialize, de::DeserializeOwned};
+use serde_json::Value;
+use tokio::time::{Instant, sleep};
+use uuid::Uuid;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+    // error response of the server
+    #[error("{status} {kind}: {message}")]
+    Server {
+        status: StatusCode,
+        kind: String,
+        message: String,
+    },
+    // the queued request ended in its error state
+    #[error("request {req_id} failed, {kind}: {message}")]
+    Request {
+        req_id: Uuid,
+        kind: String,
+        // cause of a failed send of the payload, e.g. "insufficient_funds"
+        code: Option<String>,
+        message: String,
+    },
+    #[error("request {0} didn't complete in time")]
+    Timeout(Uuid),
+    #[error("unexpected response: {0}")]
+    Unexpected(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Error of the error responses and of the error states of the requests
+#[derive(Debug, Deserialize)]
+struct ErrorInfo {
+    kind: String,
+    #[serde(default)]
+    code: Option<String>,
+    message: String,
+}
+
+/// Body of the error responses, `{"error": {...}}`
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    error: ErrorInfo,
+}
+
+/// Returns the kind and message of an error response body, falling back to
+/// the raw body when it's not an error envelope
+fn parse_error(body: String) -> (String, String) {
+    match serde_json::from_str::<ErrorBody>(&body) {
+        Ok(ErrorBody { error }) => (error.kind, error.message),
+        Err(_) => ("unknown".to_string(), body),
+    }
+}
+
+/// Membership list created by `Client::create_list`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Created {
+    pub id: i64,
+    pub tx_hash: TxHash,
+    // None if the payload was sent as calldata
+    pub blob_versioned_hash: Option<B256>,
+    pub cost: TxCostInfo,
+}
+
+/// Update posted by `Client::update_list`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Updated {
+    pub tx_hash: TxHash,
+    // None if the payload was sent as calldata
+    pub blob_versioned_hash: Option<B256>,
+    pub cost: TxCostInfo,
+}
+
+/// Groups of a user, with the proof against the reverse index of the membership list
+#[derive(Debug, Clone, Deserialize)]
+pub struct UserGroups {
+    // in group order
+    pub groups: Vec<Group>,
+    pub proof: QueryProofResponse,
+}
+
+/// Value of a user in a group of a kv membership list, with the proof of the value against the
+/// group (`root` is the commitment of the group) and the proof of the group against the state of
+/// the list.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct UserValue {
+    pub group: Group,
+    pub value: i64,
+    pub proof: QueryProofResponse,
+    pub group_proof: QueryProofResponse,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserValues {
+    values: Vec<UserValue>,
+}
+
+pub struct Config {
+    // e.g. "http://localhost:8000"
+    pub base_url: String,
+    // sent as `Authorization: Bearer {auth_token}` to the endpoints that require it
+    pub auth_token: Option<String>,
+    // interval between polls of the state of a queued request, and time after which the
+    // request is given up
+    pub poll_interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Config {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            base_url: base_url.into(),
+            auth_token: None,
+            poll_interval: Duration::from_millis(500),
+            // proving and sending the tx of an update takes minutes with groth16
+            timeout: Duration::from_secs(600),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    client: reqwest::Client,
+    base_url: String,
+    auth_token: Option<String>,
+    poll_interval: Duration,
+    timeout: Duration,
+}
+
+/// Percent-encodes a segment of the path of a url, keeping only the unreserved characters of
+/// RFC 3986, so that e.g. a `/` or `?` in a user can't change the request
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+// steps that report their progress, as `{"<step>": {"elapsed_secs": ..}}`
+const PROGRESS_STEPS: [&str; 2] = ["ProvingMainPod", "ProvingRevMainPod"];
+
+fn is_progress(progress: &Value) -> bool {
+    progress.as_object().is_some_and(|progress| {
+        progress.len() == 1 && progress.get("elapsed_secs").is_some_and(Value::is_u64)
+    })
+}
+
+/// Returns the completed state of a request, or `None` while it's in progress.  The states are
+/// serialized as `{"<request>": <state>}`, where the state is the name of a step (`"Pending"`,
+/// `"SendingBlobTx"`, ...), a step with its progress (`{"ProvingMainPod": {"elapsed_secs": ..}}`),
+/// `"Complete"`, `{"Complete": ..}` or `{"Error": ..}`.
+fn parse_state<T: DeserializeOwned>(req_id: Uuid, state: Value) -> Result<Option<T>> {
+    let unexpected = |state: &Value| Error::Unexpected(format!("request state {}", state));
+    let state = match &state {
+        Value::Object(request) if request.len() == 1 => request.values().next().expect("len 1"),
+        _ => return Err(unexpected(&state)),
+    };
+    let complete = match state {
+        Value::String(step) if step == "Complete" => Value::Null,
+        Value::String(_) => return Ok(None),
+        Value::Object(variant) if variant.len() == 1 => {
+            match variant.iter().next().expect("len 1") {
+                (name, complete) if name == "Complete" => complete.clone(),
+                (name, error) if name == "Error" => {
+                    let info: ErrorInfo =
+                        serde_json::from_value(error.clone()).map_err(|_| unexpected(state))?;
+                    return Err(Error::Request {
+                        req_id,
+                        kind: info.kind,
+                        code: info.code,
+                        message: info.message,
+                    });
+                }
+                (name, progress)
+                    if PROGRESS_STEPS.contains(&name.as_str()) && is_progress(progress) =>
+                {
+                    return Ok(None);
+                }
+                // unknown states fail instead of being polled forever
+                _ => return Err(unexpected(state)),
+            }
+        }
+        _ => return Err(unexpected(state)),
+    };
+    serde_json::from_value(complete)
+        .map(Some)
+        .map_err(|e| Error::Unexpected(format!("complete state: {}", e)))
+}
+
+impl Client {
+    pub fn new(config: Config) -> Self {
+        Self::with_client(reqwest::Client::new(), config)
+    }
+
+    pub fn with_client(client: reqwest::Client, config: Config) -> Self {
+        Self {
+            client,
+            base_url: config.base_url.trim_end_matches('/').to_string(),
+            auth_token: config.auth_token,
+            poll_interval: config.poll_interval,
+            timeout: config.timeout,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    fn with_auth(&self, req: RequestBuilder) -> RequestBuilder {
+        match &self.auth_token {
+            Some(auth_token) => req.bearer_auth(auth_token),
+            None => req,
+        }
+    }
+
+    async fn send<T: DeserializeOwned>(&self, req: RequestBuilder) -> Result<T> {
+        let res = req.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            let body = res.text().await?;
+            let (kind, message) = parse_error(body);
+            return Err(Error::Server {
+                status,
+                kind,
+                message,
+            });
+        }
+        Ok(res.json().await?)
+    }
+
+    /// Returns the raw state of a queued request.
+    pub async fn request_state(&self, req_id: Uuid) -> Result<Value> {
+        self.send(self.client.get(self.url(&format!("/request/{}", req_id))))
+            .await
+    }
+
+    /// Polls the state of the request until it completes or fails.
+    async fn wait<T: DeserializeOwned>(&self, req_id: Uuid) -> Result<T> {
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            if let Some(complete) = parse_state(req_id, self.request_state(req_id).await?)? {
+                return Ok(complete);
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout(req_id));
+            }
+            sleep(self.poll_interval).await;
+        }
+    }
+
+    /// Creates a membership list and waits for its payload to be posted.
+    pub async fn create_list(&self) -> Result<Created> {
+        let req = self.with_auth(self.client.post(self.url("/membership_list")));
+        let QueueResp { req_id } = self.send(req).await?;
+        self.wait(req_id).await
+    }
+
+    /// Like `create_list` for a kv membership list, whose groups map their users to a value.
+    pub async fn create_kv_list(&self) -> Result<Created> {
+        let req = self.with_auth(self.client.post(self.url("/membership_list?kind=kv")));
+        let QueueResp { req_id } = self.send(req).await?;
+        self.wait(req_id).await
+    }
+
+    /// Applies `op` to the membership list `id` and waits for the payload of the update to be
+    /// posted.
+    pub async fn update_list(&self, id: i64, op: &Op) -> Result<Updated> {
+        let req = self
+            .with_auth(
+                self.client
+                    .post(self.url(&format!("/membership_list/{}", id))),
+            )
+            .json(op);
+        let QueueResp { req_id } = self.send(req).await?;
+        self.wait(req_id).await
+    }
+
+    /// Returns the groups of `user` in the membership list `id`.
+    pub async fn query_user(&self, id: i64, user: &str) -> Result<UserGroups> {
+        let req = self
+            .client
+            .get(self.url(&format!("/user/{}/{}", id, encode_path_segment(user))));
+        let QueueResp { req_id } = self.send(req).await?;
+        self.wait(req_id).await
+    }
+
+    /// Like `query_user` in the state of the membership list `id` after the update `at`.  Fails
+    /// with a `not_found` error if the server doesn't have the history of the list back to `at`.
+    pub async fn query_user_at(&self, id: i64, user: &str, at: i64) -> Result<UserGroups> {
+        let req = self.client.get(self.url(&format!(
+            "/user/{}/{}?at={}",
+            id,
+            encode_path_segment(user),
+            at
+        )));
+        let QueueResp { req_id } = self.send(req).await?;
+        self.wait(req_id).await
+    }
+
+    /// Returns the values of `user` in the groups of the kv membership list `id`.
+    pub async fn query_user_values(&self, id: i64, user: &str) -> Result<Vec<UserValue>> {
+        let req = self
+            .client
+            .get(self.url(&format!("/user/{}/{}", id, encode_path_segment(user))));
+        let QueueResp { req_id } = self.send(req).await?;
+        let UserValues { values } = self.wait(req_id).await?;
+        Ok(values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_state() -> Result<()> {
+        let req_id = Uuid::nil();
+        let tx_hash = format!("0x{}", "00".repeat(32));
+        let cost = json!({
+            "gas_used": 21000,
+            "effective_gas_price": 3,
+            "blob_gas_used": 0,
+            "blob_gas_price": 0,
+            "total_fee": 63000
+        });
+
+        for step in [
+            json!("Pending"),
+            json!({"ProvingMainPod": {"elapsed_secs": 3}}),
+            json!({"ProvingRevMainPod": {"elapsed_secs": 0}}),
+            json!("SendingBlobTx"),
+        ] {
+            assert_eq!(
+                parse_state::<Updated>(req_id, json!({ "Update": step }))?,
+                None
+            );
+        }
+        // unknown object states aren't taken as in progress
+        for step in [
+            json!({"Retrying": {"attempt": 2}}),
+            json!({"ProvingMainPod": {"elapsed": 3}}),
+            json!({"ProvingMainPod": null}),
+        ] {
+            assert!(parse_state::<Updated>(req_id, json!({ "Update": step })).is_err());
+        }
+        let updated: Option<Updated> = parse_state(
+            req_id,
+            json!({"Update": {"Complete": {
+                "tx_hash": tx_hash,
+                "blob_versioned_hash": null,
+                "cost": cost
+            }}}),
+        )?;
+        assert_eq!(
+            updated,
+            Some(Updated {
+                tx_hash: TxHash::ZERO,
+                blob_versioned_hash: None,
+                cost: TxCostInfo {
+                    gas_used: 21000,
+                    effective_gas_price: 3,
+                    blob_gas_used: 0,
+                    blob_gas_price: 0,
+                    total_fee: 63000,
+                    sender: None,
+                },
+            })
+        );
+        // unit complete state
+        assert_eq!(
+            parse_state::<()>(req_id, json!({"UpdateRev": "Complete"}))?,
+            Some(())
+        );
+
+        // the error of the server is surfaced
+        match parse_state::<Updated>(
+            req_id,
+            json!({"Update": {"Error": {"kind": "conflict", "message": "alice is in red"}}}),
+        ) {
+            Err(Error::Request { kind, message, .. }) => {
+                assert_eq!(
+                    (kind.as_str(), message.as_str()),
+                    ("conflict", "alice is in red")
+                )
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+        // with the cause of a failed send
+        match parse_state::<Updated>(
+            req_id,
+            json!({"Update": {"Error": {
+                "kind": "eth_rpc",
+                "code": "insufficient_funds",
+                "message": "eth rpc: insufficient funds"
+            }}}),
+        ) {
+            Err(Error::Request { code, .. }) => {
+                assert_eq!(code.as_deref(), Some("insufficient_funds"))
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+
+        // not a request state, or a complete state of another request
+        assert!(parse_state::<Updated>(req_id, json!("Pending")).is_err());
+        assert!(
+            parse_state::<Created>(
+                req_id,
+                json!({"Update": {"Complete": {"tx_hash": tx_hash, "cost": cost}}})
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let body = json!({"error": {"kind": "not_found", "message": "list 3 not found"}});
+        assert_eq!(
+            parse_error(body.to_string()),
+            ("not_found".to_string(), "list 3 not found".to_string())
+        );
+        // Bodies outside the envelope are kept as is
+        let body = json!({"kind": "not_found", "message": "list 3 not found"}).to_string();
+        assert_eq!(parse_error(body.clone()), ("unknown".to_string(), body));
+        assert_eq!(
+            parse_error("bad gateway".to_string()),
+            ("unknown".to_string(), "bad gateway".to_string())
+        );
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(encode_path_segment("alice_1-b.c~"), "alice_1-b.c~");
+        assert_eq!(encode_path_segment("a/b?at=1"), "a%2Fb%3Fat%3D1");
+        assert_eq!(encode_path_segment("%20 é"), "%2520%20%C3%A9");
+        assert_eq!(encode_path_segment(""), "");
+    }
+}
@@ -0,0 +1,433 @@
+//! Complete archives of membership lists.  Unlike a `snapshot`, an archive keeps the whole history
+//! of the list: the rows of the list and of its reverse index, the op log and every pod file on
+//! disk.  Archives are tar files streamed in both directions, so the pods of long lists never have
+//! to fit in memory.
+//!
+//! ```text
+//! manifest.json             ArchiveManifest
+//! membership_list.json      db::AdState
+//! rev_membership_list.json  db::AdState
+//! op_log.json               [db::OpLogEntry]
+//! pods/{name}.pod2.json     pod files as stored on disk, gzipped or not
+//! ```
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs::{File, create_dir_all, remove_dir_all},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    slice,
+    sync::Arc,
+};
+
+use app::apply_ops;
+use common::disk::{self, PodKey, PodKind};
+use futures_util::{Stream, TryStreamExt};
+use pod2::middleware::{
+    Hash, Key, TypedValue, Value,
+    containers::{Dictionary, Set},
+};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{io::DuplexStream, task};
+use tokio_util::io::{ReaderStream, StreamReader, SyncIoBridge};
+use tracing::warn;
+use uuid::Uuid;
+use warp::hyper::body::Buf;
+
+use crate::{
+    Context, Error, PodConfig, bloom, db,
+    snapshot::{self, ListSnapshot, SnapshotPod},
+};
+
+// bump when the layout of the archive changes
+const ARCHIVE_VERSION: u32 = 1;
+
+const MANIFEST_FILE: &str = "manifest.json";
+const MEMBERSHIP_LIST_FILE: &str = "membership_list.json";
+const REV_MEMBERSHIP_LIST_FILE: &str = "rev_membership_list.json";
+const OP_LOG_FILE: &str = "op_log.json";
+const PODS_DIR: &str = "pods/";
+
+// size of the pipe between the thread that writes the archive and the response body
+const EXPORT_BUF_SIZE: usize = 64 * 1024;
+
+/// `manifest.json` of an archive
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub version: u32,
+    pub id: i64,
+    pub num: i64,
+    // commitment of the state at `num`
+    pub commitment: Hash,
+    pub rev_num: i64,
+    // commitment of the reverse index at `rev_num`
+    pub rev_commitment: Hash,
+    // file names of the pods in `pods/`, without the `.pod2.json` extension
+    pub pods: Vec<String>,
+    // first update of the pods of `pods` that prove a batch of updates, by file name, see
+    // `PodStore::store_batch`
+    #[serde(default)]
+    pub batches: BTreeMap<String, i64>,
+}
+
+/// Streams the archive of the membership list `id`.  The list stays locked until the archive is
+/// written, so that no update is applied while its pods are read.
+pub async fn export(ctx: Arc<Context>, id: i64) -> Result<ReaderStream<DuplexStream>, Error> {
+    let list_guard = ctx.list_locks.lock(id).await;
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    let rev_membership_list = db::get_rev_membership_list(&ctx.db_pool, id).await?;
+    let op_log = db::get_op_log(&ctx.db_pool, id, 1, membership_list.num).await?;
+    let pods_path = PathBuf::from(&ctx.cfg.pods_path);
+    // the membership pods of the users aren't needed to continue the list
+    let pods: Vec<PodKey> = disk::list_pods(&pods_path)?
+        .into_iter()
+        .filter(|key| key.id == id && !matches!(key.kind, PodKind::Membership(_)))
+        .collect();
+    let manifest = ArchiveManifest {
+        version: ARCHIVE_VERSION,
+        id,
+        num: membership_list.num,
+        commitment: membership_list.state.0.commitment(),
+        rev_num: rev_membership_list.num,
+        rev_commitment: rev_membership_list.state.0.commitment(),
+        pods: Vec::new(),
+        batches: ctx
+            .pod_store
+            .entries()
+            .into_iter()
+            .filter(|entry| entry.key.id == id)
+            .filter_map(|entry| Some((entry.key.file_name(), entry.first_num?)))
+            .collect(),
+    };
+
+    let (writer, reader) = tokio::io::duplex(EXPORT_BUF_SIZE);
+    let writer = SyncIoBridge::new(writer);
+    task::spawn_blocking(move || {
+        let _list_guard = list_guard;
+        let res = write_archive(
+            writer,
+            &pods_path,
+            &pods,
+            manifest,
+            &membership_list,
+            &rev_membership_list,
+            &op_log,
+        );
+        if let Err(err) = res {
+            // the client gets a truncated archive, which can't be imported
+            warn!("failed to export membership list {}: {:#}", id, err);
+        }
+    });
+    Ok(ReaderStream::new(reader))
+}
+
+fn write_archive(
+    writer: impl Write,
+    pods_path: &Path,
+    pods: &[PodKey],
+    mut manifest: ArchiveManifest,
+    membership_list: &db::AdState,
+    rev_membership_list: &db::AdState,
+    op_log: &[db::OpLogEntry],
+) -> anyhow::Result<()> {
+    let mut builder = tar::Builder::new(writer);
+    for key in pods {
+        let name = format!("{}.pod2.json", key.file_name());
+        let mut file = match File::open(pods_path.join(&name)) {
+            Ok(file) => file,
+            // pruned since the pods were listed.  Pruning keeps the pods the next updates are
+            // built from, which are checked on import.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err.into()),
+        };
+        builder.append_file(format!("{}{}", PODS_DIR, name), &mut file)?;
+        manifest.pods.push(key.file_name());
+    }
+    append_json(&mut builder, MEMBERSHIP_LIST_FILE, membership_list)?;
+    append_json(&mut builder, REV_MEMBERSHIP_LIST_FILE, rev_membership_list)?;
+    append_json(&mut builder, OP_LOG_FILE, &op_log)?;
+    // last, so that it lists the pods that were actually written
+    append_json(&mut builder, MANIFEST_FILE, &manifest)?;
+    builder.into_inner()?.flush()?;
+    Ok(())
+}
+
+fn append_json<W: Write>(
+    builder: &mut tar::Builder<W>,
+    path: &str,
+    value: &impl Serialize,
+) -> anyhow::Result<()> {
+    let json = serde_json::to_vec(value)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(json.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(db::unix_now() as u64);
+    builder.append_data(&mut header, path, json.as_slice())?;
+    Ok(())
+}
+
+/// Archive being imported, with its pods written to a staging directory
+struct Archive {
+    manifest: ArchiveManifest,
+    membership_list: db::AdState,
+    rev_membership_list: db::AdState,
+    op_log: Vec<db::OpLogEntry>,
+    pods: Vec<PodKey>,
+}
+
+fn invalid(msg: impl Into<String>) -> Error {
+    Error::InvalidArchive(msg.into())
+}
+
+fn read_json<T: DeserializeOwned>(reader: impl Read, path: &str) -> Result<T, Error> {
+    serde_json::from_reader(reader).map_err(|e| invalid(format!("{}: {}", path, e)))
+}
+
+/// Reads the archive, writing its pods to `staging`.
+fn unpack(reader: impl Read, staging: &Path) -> Result<Archive, Error> {
+    create_dir_all(staging).map_err(anyhow::Error::from)?;
+    let (mut manifest, mut membership_list, mut rev_membership_list, mut op_log) =
+        (None, None, None, None);
+    let mut pods = Vec::new();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(|e| invalid(e.to_string()))? {
+        let mut entry = entry.map_err(|e| invalid(e.to_string()))?;
+        if entry.header().entry_type().is_dir() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(|e| invalid(e.to_string()))?
+            .to_string_lossy()
+            .into_owned();
+        match path.as_str() {
+            MANIFEST_FILE => manifest = Some(read_json(&mut entry, &path)?),
+            MEMBERSHIP_LIST_FILE => membership_list = Some(read_json(&mut entry, &path)?),
+            REV_MEMBERSHIP_LIST_FILE => rev_membership_list = Some(read_json(&mut entry, &path)?),
+            OP_LOG_FILE => op_log = Some(read_json(&mut entry, &path)?),
+            _ => {
+                // the pod is staged under the name of its key, never under the path of the entry
+                let key = path
+                    .strip_prefix(PODS_DIR)
+                    .and_then(|name| name.strip_suffix(".pod2.json"))
+                    .and_then(PodKey::from_file_name)
+                    .ok_or_else(|| invalid(format!("unexpected file {}", path)))?;
+                let file_path = staging.join(format!("{}.pod2.json", key.file_name()));
+                let mut file = File::create(file_path).map_err(anyhow::Error::from)?;
+                io::copy(&mut entry, &mut file).map_err(|e| invalid(format!("{}: {}", path, e)))?;
+                pods.push(key);
+            }
+        }
+    }
+    let missing = |path: &str| invalid(format!("missing {}", path));
+    Ok(Archive {
+        manifest: manifest.ok_or_else(|| missing(MANIFEST_FILE))?,
+        membership_list: membership_list.ok_or_else(|| missing(MEMBERSHIP_LIST_FILE))?,
+        rev_membership_list: rev_membership_list
+            .ok_or_else(|| missing(REV_MEMBERSHIP_LIST_FILE))?,
+        op_log: op_log.ok_or_else(|| missing(OP_LOG_FILE))?,
+        pods,
+    })
+}
+
+/// Returns the reverse index of the membership list `state`: the groups of every user that is in
+/// at least one.
+pub(crate) fn rev_index(state: &Dictionary, depth: usize) -> anyhow::Result<Dictionary> {
+    let mut user_groups: HashMap<String, HashSet<Value>> = HashMap::new();
+    for (group, value) in state.kvs() {
+        // skip the epoch
+        let TypedValue::Set(members) = value.typed() else {
+            continue;
+        };
+        for user in members.set() {
+            user_groups
+                .entry(String::try_from(user.typed())?)
+                .or_default()
+                .insert(Value::from(group.name()));
+        }
+    }
+    let kvs = user_groups
+        .into_iter()
+        .map(|(user, groups)| Ok((Key::from(user), Value::from(Set::new(depth, groups)?))))
+        .collect::<anyhow::Result<HashMap<_, _>>>()?;
+    Ok(Dictionary::new(depth, kvs)?)
+}
+
+/// Checks that the archive is a complete and consistent history: the ops of the log replay from
+/// the empty state into the state of the list, the reverse index matches the state at its num, and
+/// the latest pods prove both like in a snapshot.
+fn verify(pod_config: &PodConfig, archive: &Archive, staging: &Path) -> Result<(), Error> {
+    let Archive {
+        manifest,
+        membership_list,
+        rev_membership_list,
+        op_log,
+        pods,
+    } = archive;
+    let (id, num, rev_num) = (manifest.id, manifest.num, manifest.rev_num);
+    if manifest.version != ARCHIVE_VERSION {
+        return Err(invalid(format!("unsupported version {}", manifest.version)));
+    }
+    if (membership_list.id, membership_list.num) != (id, num)
+        || (rev_membership_list.id, rev_membership_list.num) != (id, rev_num)
+    {
+        return Err(invalid("the rows don't match the manifest"));
+    }
+    if !(0 <= rev_num && rev_num <= num) {
+        return Err(invalid(format!("rev_num {} > num {}", rev_num, num)));
+    }
+    let staged: BTreeSet<String> = pods.iter().map(PodKey::file_name).collect();
+    if staged.len() != pods.len()
+        || staged != manifest.pods.iter().cloned().collect::<BTreeSet<_>>()
+    {
+        return Err(invalid("the pods don't match the manifest"));
+    }
+    if let Some(key) = pods.iter().find(|key| key.id != id) {
+        return Err(invalid(format!("pod {} of another list", key.file_name())));
+    }
+    if !op_log.iter().map(|entry| entry.num).eq(1..=num) {
+        // lists restored from a snapshot, or updated before the op log, have gaps
+        return Err(invalid(
+            "incomplete op log, the list can only be moved as a snapshot",
+        ));
+    }
+
+    let depth = pod_config.params.max_depth_mt_containers;
+    let mut state = Dictionary::new(depth, HashMap::new()).map_err(anyhow::Error::from)?;
+    let mut state_at_rev = state.clone();
+    let genesis = &pod_config.state_predicates.genesis;
+    for entry in op_log {
+        let new_state = apply_ops(genesis, false, state, slice::from_ref(&entry.op))
+            .map_err(|e| invalid(format!("op {}: {:#}", entry.num, e)))?;
+        if new_state.commitment() != entry.state {
+            return Err(invalid(format!(
+                "op {} doesn't lead to the logged state",
+                entry.num
+            )));
+        }
+        state = new_state;
+        if entry.num == rev_num {
+            state_at_rev = state.clone();
+        }
+    }
+    if state.commitment() != manifest.commitment
+        || membership_list.state.0.commitment() != manifest.commitment
+    {
+        return Err(invalid("the state doesn't match the commitment"));
+    }
+    if rev_index(&state_at_rev, depth)?.commitment() != manifest.rev_commitment
+        || rev_membership_list.state.0.commitment() != manifest.rev_commitment
+    {
+        return Err(invalid("the reverse index doesn't match the commitment"));
+    }
+
+    // the key of an update resolves to the first staged pod from it on, if it's its own or the one
+    // of a batch that has it
+    let resolve = |key: PodKey| {
+        pods.iter()
+            .filter(|pod| (pod.kind, pod.id) == (key.kind, key.id) && pod.num >= key.num)
+            .min()
+            .filter(|pod| {
+                pod.num == key.num
+                    || manifest
+                        .batches
+                        .get(&pod.file_name())
+                        .is_some_and(|first_num| *first_num <= key.num)
+            })
+            .copied()
+            .unwrap_or(key)
+    };
+    let pods = snapshot::latest_pod_keys(id, num, rev_num, resolve)
+        .into_iter()
+        .map(|key| {
+            let pod = disk::load_pod(staging, &key.file_name())
+                .map_err(|e| invalid(format!("pod {}: {:#}", key.file_name(), e)))?;
+            Ok(SnapshotPod {
+                key,
+                pod,
+                first_num: manifest.batches.get(&key.file_name()).copied(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    ListSnapshot {
+        version: snapshot::SNAPSHOT_VERSION,
+        id,
+        num,
+        commitment: manifest.commitment,
+        state,
+        rev_num,
+        rev_state: rev_membership_list.state.0.clone(),
+        // the ops history is built from the op log once the list is updated
+        ops: None,
+        created_at: membership_list.created_at,
+        updated_at: membership_list.updated_at,
+        pods,
+    }
+    .verify(pod_config)
+    .map_err(|e| match e {
+        Error::InvalidSnapshot(msg) => invalid(msg),
+        e => e,
+    })
+}
+
+/// Restores a membership list from an archive under the id it had, which is the one its payloads
+/// were sent with.  Returns the id and num of the list.  The list must not exist on this server.
+pub async fn import<S, B>(ctx: Arc<Context>, body: S) -> Result<(i64, i64), Error>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf + Send + 'static,
+{
+    let reader = SyncIoBridge::new(StreamReader::new(Box::pin(body.map_err(io::Error::other))));
+    let staging =
+        Path::new(&ctx.cfg.pods_path).join(format!(".import-{}", Uuid::now_v7().simple()));
+    let res = import_staged(&ctx, reader, &staging).await;
+    match remove_dir_all(&staging) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            warn!("failed to remove {}: {}", staging.display(), err)
+        }
+        _ => {}
+    }
+    res
+}
+
+async fn import_staged(
+    ctx: &Arc<Context>,
+    reader: impl Read + Send + 'static,
+    staging: &Path,
+) -> Result<(i64, i64), Error> {
+    let archive = task::spawn_blocking({
+        let (ctx, staging) = (ctx.clone(), staging.to_path_buf());
+        move || {
+            let archive = unpack(reader, &staging)?;
+            verify(ctx.pod_config()?, &archive, &staging)?;
+            Ok::<_, Error>(archive)
+        }
+    })
+    .await??;
+    let (id, num) = (archive.manifest.id, archive.manifest.num);
+    let _list_guard = ctx.list_locks.lock(id).await;
+    match db::get_membership_list(&ctx.db_pool, id).await {
+        Ok(_) => return Err(Error::Conflict(format!("membership list {} exists", id))),
+        Err(Error::NotFound(_)) => {}
+        Err(err) => return Err(err),
+    }
+
+    // the pods are stored first so that they are there once the list is visible
+    for key in &archive.pods {
+        let file_path = staging.join(format!("{}.pod2.json", key.file_name()));
+        // the payloads of all the pods were included on the server the list comes from
+        let first_num = archive.manifest.batches.get(&key.file_name()).copied();
+        ctx.pod_store
+            .insert_file(*key, first_num, &file_path, true)?;
+    }
+    let blooms = bloom::group_blooms(&archive.membership_list.state.0);
+    db::insert_archived_list(
+        &ctx.db_pool,
+        &archive.membership_list,
+        &archive.rev_membership_list,
+        &archive.op_log,
+        &blooms,
+    )
+    .await?;
+    Ok((id, num))
+}
@@ -0,0 +1,297 @@
+//! Consistency audit of the membership lists between the three places their state is kept: the
+//! row in the db, the latest pod in `PODS_PATH` that proves it, and the latest update on chain as
+//! indexed by the synchronizer at `SYNCHRONIZER_URL`, if set.  A crash between the steps of an
+//! update can leave them disagreeing, e.g. a db at num 5 whose latest pod is the one of the update
+//! 4 while the chain has the update 6.
+//!
+//! The audit runs at startup and in `POST /admin/audit`.  The lists found inconsistent are flagged
+//! in the db and their updates are refused with `Error::Flagged` until the flag is cleared, by an
+//! operator in `POST /admin/audit/{id}/clear` or by an audit that finds the list consistent again,
+//! e.g. after repairing it with the `recover` command.
+//!
+//! A list ahead of the chain is only flagged once it stays ahead for `AHEAD_OF_CHAIN_GRACE`, if
+//! the txs of its updates missing on chain were all included: the synchronizer indexes the slots
+//! `CONFIRMATION_DEPTH` behind the head and can lag behind, e.g. after a restart.
+
+use std::{
+    cmp::Ordering,
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use common::disk::PodKey;
+use pod2::middleware::RawValue;
+use serde::{Deserialize, Serialize};
+use tokio::task;
+use tracing::{info, warn};
+
+use crate::{Context, Error, PodConfig, db, recover, snapshot};
+
+/// Time a list whose txs were included can be ahead of the chain indexed by the synchronizer
+/// before it's flagged
+pub const AHEAD_OF_CHAIN_GRACE: Duration = Duration::from_secs(30 * 60);
+
+/// Outcome of the audit of a membership list, stored in its `audit_flag` column unless it's
+/// `Consistent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStatus {
+    Consistent,
+    // the pod of the latest update isn't on disk
+    PodMissing,
+    // the pod of the latest update doesn't load or doesn't verify
+    PodInvalid,
+    // the state in the db isn't the one proven by the latest pod or published on chain
+    CommitmentMismatch,
+    // the chain has updates the db doesn't have
+    BehindChain,
+    // the db has updates the chain doesn't have
+    AheadOfChain,
+}
+
+impl AuditStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditStatus::Consistent => "consistent",
+            AuditStatus::PodMissing => "pod_missing",
+            AuditStatus::PodInvalid => "pod_invalid",
+            AuditStatus::CommitmentMismatch => "commitment_mismatch",
+            AuditStatus::BehindChain => "behind_chain",
+            AuditStatus::AheadOfChain => "ahead_of_chain",
+        }
+    }
+}
+
+impl fmt::Display for AuditStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for AuditStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "consistent" => Ok(AuditStatus::Consistent),
+            "pod_missing" => Ok(AuditStatus::PodMissing),
+            "pod_invalid" => Ok(AuditStatus::PodInvalid),
+            "commitment_mismatch" => Ok(AuditStatus::CommitmentMismatch),
+            "behind_chain" => Ok(AuditStatus::BehindChain),
+            "ahead_of_chain" => Ok(AuditStatus::AheadOfChain),
+            _ => Err(anyhow!("unknown audit status {:?}", s)),
+        }
+    }
+}
+
+/// Audit report of a membership list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListAudit {
+    pub id: i64,
+    // num of the list in the db
+    pub num: i64,
+    pub status: AuditStatus,
+    // num of the latest update on chain, none if the chain wasn't checked or has no update of
+    // the list
+    pub chain_num: Option<i64>,
+    // what was found inconsistent
+    pub detail: Option<String>,
+    // why the chain wasn't checked, none if it was or `SYNCHRONIZER_URL` isn't set
+    #[serde(default)]
+    pub chain_error: Option<String>,
+}
+
+/// Checks the latest pod of the list against its state in the db, and the state against the
+/// latest update on chain in `history` if it's known.
+async fn audit_list(
+    ctx: &Context,
+    pod_config: &PodConfig,
+    id: i64,
+    history: Option<&[recover::ChainUpdate]>,
+) -> Result<ListAudit, Error> {
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    let kind = db::get_membership_list_kind(&ctx.db_pool, id).await?;
+    let num = membership_list.num;
+    let commitment = RawValue::from(membership_list.state.0.commitment());
+    let report = |status, detail: Option<String>| ListAudit {
+        id,
+        num,
+        status,
+        chain_num: history
+            .and_then(|history| history.last())
+            .map(|update| update.num),
+        detail,
+        chain_error: None,
+    };
+
+    // the create has no pod, the pods start with the update 1
+    if num > 0 {
+        let key = PodKey::membership_list(id, num);
+        if !ctx.pod_store.file_path(key).exists() {
+            return Ok(report(AuditStatus::PodMissing, Some(key.file_name())));
+        }
+        // read from disk, bypassing the pod cache
+        let pod = match ctx.pod_store.load(key) {
+            Ok(pod) => pod,
+            Err(err) => return Ok(report(AuditStatus::PodInvalid, Some(format!("{:#}", err)))),
+        };
+        let verified = task::spawn_blocking({
+            let pod = pod.clone();
+            move || pod.pod.verify()
+        })
+        .await?;
+        if let Err(err) = verified {
+            return Ok(report(AuditStatus::PodInvalid, Some(err.to_string())));
+        }
+        if snapshot::proven_state_at(&pod, pod_config.update_predicate(kind), num)
+            != Some(commitment)
+        {
+            return Ok(report(
+                AuditStatus::CommitmentMismatch,
+                Some(format!("pod {} doesn't prove the state", key.file_name())),
+            ));
+        }
+    }
+
+    let Some(history) = history else {
+        return Ok(report(AuditStatus::Consistent, None));
+    };
+    let Some(latest) = history.last() else {
+        return Ok(report(
+            AuditStatus::AheadOfChain,
+            Some("no update on chain".to_string()),
+        ));
+    };
+    if latest.num > num {
+        // an update (or batch of updates) interrupted after its tx was included, which the
+        // resumed wrap applies
+        let pending = match db::get_pending_wrap(&ctx.db_pool, id, latest.num).await {
+            Ok(pending_wrap) => {
+                pending_wrap.first_num.unwrap_or(pending_wrap.num) == num + 1
+                    && RawValue::from(pending_wrap.new_state.0.commitment()) == latest.state
+            }
+            Err(Error::NotFound(_)) => false,
+            Err(err) => return Err(err),
+        };
+        if pending {
+            return Ok(report(
+                AuditStatus::Consistent,
+                Some(format!("update {} pending", latest.num)),
+            ));
+        }
+    }
+    if latest.num >= num {
+        ctx.ahead_of_chain_since.lock().expect("lock").remove(&id);
+    } else {
+        // the updates the chain doesn't have yet, pending while the synchronizer catches up
+        let sent: Vec<i64> = db::get_update_costs(&ctx.db_pool, id)
+            .await?
+            .iter()
+            .map(|cost| cost.num)
+            .collect();
+        if (latest.num + 1..=num).all(|num| sent.contains(&num)) {
+            let since = *ctx
+                .ahead_of_chain_since
+                .lock()
+                .expect("lock")
+                .entry(id)
+                .or_insert_with(Instant::now);
+            if since.elapsed() < AHEAD_OF_CHAIN_GRACE {
+                return Ok(report(
+                    AuditStatus::Consistent,
+                    Some(format!(
+                        "updates {}..={} sent, not indexed yet",
+                        latest.num + 1,
+                        num
+                    )),
+                ));
+            }
+        }
+    }
+    Ok(match latest.num.cmp(&num) {
+        Ordering::Greater => report(AuditStatus::BehindChain, None),
+        Ordering::Less => report(AuditStatus::AheadOfChain, None),
+        Ordering::Equal if latest.state != commitment => report(
+            AuditStatus::CommitmentMismatch,
+            Some("the state isn't the one on chain".to_string()),
+        ),
+        Ordering::Equal => report(AuditStatus::Consistent, None),
+    })
+}
+
+/// Audits every membership list, flagging the inconsistent ones and clearing the flag of the
+/// consistent ones.  The chain is only checked if `SYNCHRONIZER_URL` is set and the synchronizer
+/// answers, otherwise the report says why, and only as far as the synchronizer indexed it: a list
+/// updated in the last blocks is found ahead of a synchronizer that lags behind.
+pub async fn audit(ctx: &Context) -> Result<Vec<ListAudit>, Error> {
+    let pod_config = ctx.pod_config()?;
+    let mut reports = Vec::new();
+    for id in db::get_membership_list_ids(&ctx.db_pool).await? {
+        // fetched before taking the lock, so that a slow synchronizer doesn't hold the updates of
+        // the list.  An update applied in between leaves the list ahead of the fetched history,
+        // which is tolerated as its tx was sent.
+        let (history, chain_error) = match &ctx.cfg.synchronizer_url {
+            Some(synchronizer_url) => match recover::fetch_history(synchronizer_url, id).await {
+                Ok(history) => (Some(history), None),
+                Err(err) => {
+                    warn!(id, "can't check the membership list on chain: {:#}", err);
+                    (None, Some(format!("{:#}", err)))
+                }
+            },
+            None => (None, None),
+        };
+        // no update of the list is applied while it's audited
+        let _list_guard = ctx.list_locks.lock(id).await;
+        let mut report = audit_list(ctx, pod_config, id, history.as_deref()).await?;
+        report.chain_error = chain_error;
+        let flag = (report.status != AuditStatus::Consistent).then_some(report.status);
+        db::set_audit_flag(&ctx.db_pool, id, flag).await?;
+        match flag {
+            Some(status) => warn!(
+                id,
+                num = report.num,
+                chain_num = ?report.chain_num,
+                detail = ?report.detail,
+                "membership list flagged as {}",
+                status
+            ),
+            None => info!(
+                id,
+                num = report.num,
+                chain_error = ?report.chain_error,
+                "membership list consistent"
+            ),
+        }
+        reports.push(report);
+    }
+    Ok(reports)
+}
+
+/// Runs the audit at startup, once the setup is available.
+pub async fn startup_audit(ctx: Arc<Context>) {
+    match audit(&ctx).await {
+        Ok(reports) => {
+            let flagged = reports
+                .iter()
+                .filter(|report| report.status != AuditStatus::Consistent)
+                .count();
+            info!(
+                "audited {} membership lists, {} flagged",
+                reports.len(),
+                flagged
+            );
+        }
+        Err(err) => warn!("failed to audit the membership lists: {}", err),
+    }
+}
+
+/// Refuses the updates of the membership list `id` while it's flagged by the audit.
+pub async fn check_not_flagged(ctx: &Context, id: i64) -> Result<(), Error> {
+    match db::get_audit_flag(&ctx.db_pool, id).await? {
+        Some(status) => Err(Error::Flagged(id, status)),
+        None => Ok(()),
+    }
+}
@@ -0,0 +1,140 @@
+//! Bloom filters of the members of the groups of a membership list, which let the queries rule out
+//! the users that are in no group without loading the reverse index.  They're only a cache: a user
+//! that may be a member is always looked up in the reverse index, which gives the proof.
+
+use anyhow::{Result, anyhow};
+use plonky2::field::types::PrimeField64;
+use pod2::middleware::{
+    TypedValue, Value,
+    containers::{Dictionary, Set},
+};
+
+// ~1% false positives
+const BITS_PER_MEMBER: usize = 10;
+const HASHES: u64 = 7;
+
+/// Positions of the bits of `member` in a filter of `words` 64-bit words.  They're derived from
+/// the hash of the value, which unlike the std hashers is stable across runs and versions, so that
+/// the persisted filters stay valid.
+fn indices(words: usize, member: &Value) -> impl Iterator<Item = usize> {
+    let raw = member.raw();
+    let h1 = raw.0[0].to_canonical_u64();
+    let h2 = raw.0[1].to_canonical_u64() | 1;
+    let len = words as u64 * 64;
+    (0..HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bloom {
+    bits: Vec<u64>,
+}
+
+impl Bloom {
+    pub fn with_capacity(members: usize) -> Self {
+        Self {
+            bits: vec![0; (members * BITS_PER_MEMBER).div_ceil(64).max(1)],
+        }
+    }
+
+    pub fn from_set(set: &Set) -> Self {
+        let mut bloom = Self::with_capacity(set.set().len());
+        for member in set.set() {
+            bloom.insert(member);
+        }
+        bloom
+    }
+
+    pub fn insert(&mut self, member: &Value) {
+        for i in indices(self.bits.len(), member) {
+            self.bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+
+    /// False if `member` was never inserted, true if it was or, rarely, if it wasn't.
+    pub fn may_contain(&self, member: &Value) -> bool {
+        indices(self.bits.len(), member).all(|i| self.bits[i / 64] & (1 << (i % 64)) != 0)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.bits
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.is_empty() || bytes.len() % 8 != 0 {
+            return Err(anyhow!("invalid bloom filter length {}", bytes.len()));
+        }
+        let bits = bytes
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().expect("8 bytes")))
+            .collect();
+        Ok(Self { bits })
+    }
+}
+
+/// Returns the bloom filter of the members of every group of the membership list `state`.
+pub fn group_blooms(state: &Dictionary) -> Vec<(String, Bloom)> {
+    state
+        .kvs()
+        .iter()
+        .filter_map(|(key, value)| match value.typed() {
+            TypedValue::Set(set) => Some((key.name().to_string(), Bloom::from_set(set))),
+            // the epoch
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use pod2::middleware::Params;
+
+    use super::*;
+
+    #[test]
+    fn test_bloom() -> Result<()> {
+        let depth = Params::default().max_depth_mt_containers;
+        let members: Vec<Value> = (0..200)
+            .map(|i| Value::from(format!("user{}", i)))
+            .collect();
+        let set = Set::new(depth, members.iter().cloned().collect()).unwrap();
+        let bloom = Bloom::from_set(&set);
+
+        // no false negatives
+        assert!(members.iter().all(|member| bloom.may_contain(member)));
+        let false_positives = (0..1000)
+            .filter(|i| bloom.may_contain(&Value::from(format!("other{}", i))))
+            .count();
+        assert!(false_positives < 50, "{} false positives", false_positives);
+
+        assert_eq!(Bloom::from_bytes(&bloom.to_bytes())?, bloom);
+        assert!(Bloom::from_bytes(&[]).is_err());
+        assert!(Bloom::from_bytes(&[0; 7]).is_err());
+
+        // an empty group rules out everyone
+        let empty_set = Set::new(depth, HashSet::new()).unwrap();
+        let empty = Bloom::from_set(&empty_set);
+        assert!(!empty.may_contain(&members[0]));
+
+        let state = app::dict!(depth, {
+            "red" => Value::from(set),
+            "blue" => Value::from(empty_set),
+            "epoch" => 2
+        });
+        let mut blooms = group_blooms(&state);
+        blooms.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            blooms
+                .iter()
+                .map(|(group, _)| group.as_str())
+                .collect::<Vec<_>>(),
+            ["blue", "red"]
+        );
+        assert_eq!(blooms[1].1, bloom);
+        Ok(())
+    }
+}
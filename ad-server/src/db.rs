@@ -1,7 +1,18 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use alloy::primitives::{Address, B256, TxHash};
+use app::Op;
+use common::api::TxCostInfo;
 pub use common::db_connection;
-use pod2::middleware::containers;
+use pod2::middleware::{Hash, RawValue, containers};
 use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, SqlitePool};
+use sqlx::{FromRow, SqliteConnection, SqlitePool};
+
+use crate::{Error, audit::AuditStatus, bloom::Bloom, queue};
 
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct AdState {
@@ -9,9 +20,59 @@ pub struct AdState {
     pub num: i64, // maybe use u64 (check db compat)
     #[sqlx(try_from = "Vec<u8>")]
     pub state: DictContainerSql,
+    // unix seconds
+    pub created_at: i64,
+    // unix seconds of the latest update
+    pub updated_at: i64,
     // maybe store also: pod, proof, etc
 }
 
+/// Predicates a membership list is proven with, chosen when it's created: the groups of the `set`
+/// lists are sets of users, the ones of the `kv` lists map their users to an int value.  Stored in
+/// the `kind` column of the list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ListKind {
+    #[default]
+    Set,
+    Kv,
+}
+
+impl ListKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ListKind::Set => "set",
+            ListKind::Kv => "kv",
+        }
+    }
+}
+
+impl fmt::Display for ListKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ListKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "set" => Ok(ListKind::Set),
+            "kv" => Ok(ListKind::Kv),
+            _ => Err(anyhow::anyhow!("unknown list kind {:?}", s)),
+        }
+    }
+}
+
+/// Current time in unix seconds
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time after the unix epoch")
+        .as_secs() as i64
+}
+
 // TODO: Use better serialisation.
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct DictContainerSql(pub containers::Dictionary);
@@ -30,13 +91,83 @@ impl DictContainerSql {
     }
 }
 
-pub async fn init_db(db_pool: &SqlitePool) -> Result<(), sqlx::Error> {
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RawValueSql(pub RawValue);
+
+impl TryFrom<Vec<u8>> for RawValueSql {
+    type Error = anyhow::Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        Ok(Self(minicbor_serde::from_slice(&bytes)?))
+    }
+}
+
+impl RawValueSql {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        minicbor_serde::to_vec(&self.0).unwrap()
+    }
+}
+
+/// Update whose main pod has been proven and stored but which has not been wrapped, sent and
+/// applied yet.
+#[derive(Debug, FromRow)]
+pub struct PendingWrap {
+    pub id: i64,
+    pub num: i64,
+    pub pod_name: String,
+    // commitment of the op dictionary
+    #[sqlx(try_from = "Vec<u8>")]
+    pub op: RawValueSql,
+    #[sqlx(try_from = "Vec<u8>")]
+    pub new_state: DictContainerSql,
+    // number of times the wrapping has been started
+    pub attempts: i64,
+    // first update of the batch of updates `first_num..=num` the pod proves, none for the rows
+    // written before the batches, whose pod only proves `num`
+    pub first_num: Option<i64>,
+    // tx that sent the payload of the updates, none until it's sent
+    pub tx_hash: Option<Vec<u8>>,
+    // blob of the tx, none if it isn't sent yet or it was sent as calldata
+    pub blob_versioned_hash: Option<Vec<u8>>,
+}
+
+impl PendingWrap {
+    /// Whether the updates can still be resumed: their payload was sent and only needs to be
+    /// applied, or the wrapping has been started less than `max_attempts` times.
+    pub fn resumable(&self, max_attempts: i64) -> bool {
+        self.tx_hash.is_some() || self.attempts < max_attempts
+    }
+}
+
+/// Proving of a main pod given up after `Config::proving_timeout`, whose thread keeps running
+/// until the proving completes or the server restarts.
+#[derive(Debug, PartialEq, Eq, FromRow)]
+pub struct ProvingIncident {
+    pub id: i64,
+    pub num: i64,
+    // name of the pod being proven
+    pub pod_name: String,
+    pub elapsed_ms: i64,
+    pub created_at: i64,
+}
+
+pub async fn init_db(db_pool: &SqlitePool) -> Result<(), Error> {
     sqlx::query(
         r#"
         CREATE TABLE IF NOT EXISTS membership_list (
             id INTEGER PRIMARY KEY,
             num INTEGER NOT NULL,
-            state BLOB NOT NULL
+            state BLOB NOT NULL,
+            -- versioned hash of the blob that published the latest update, NULL if
+            -- it was published as calldata
+            blob_versioned_hash BLOB,
+            created_at INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL DEFAULT 0,
+            -- `ListKind` of the list
+            kind TEXT NOT NULL DEFAULT 'set',
+            -- `AuditStatus` of the list if the audit found it inconsistent, its updates are
+            -- refused until it's cleared
+            audit_flag TEXT
         )
         "#,
     )
@@ -48,100 +179,1116 @@ pub async fn init_db(db_pool: &SqlitePool) -> Result<(), sqlx::Error> {
         CREATE TABLE IF NOT EXISTS rev_membership_list (
             id INTEGER PRIMARY KEY,
             num INTEGER NOT NULL,
-            state BLOB NOT NULL
+            state BLOB NOT NULL,
+            created_at INTEGER NOT NULL DEFAULT 0,
+            updated_at INTEGER NOT NULL DEFAULT 0
+        )
+        "#,
+    )
+    .execute(db_pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS pending_wrap (
+            id INTEGER NOT NULL,
+            num INTEGER NOT NULL,
+            pod_name TEXT NOT NULL,
+            op BLOB NOT NULL,
+            new_state BLOB NOT NULL,
+            attempts INTEGER NOT NULL,
+            first_num INTEGER,
+            tx_hash BLOB,
+            blob_versioned_hash BLOB,
+
+            PRIMARY KEY (id, num)
+        )
+        "#,
+    )
+    .execute(db_pool)
+    .await?;
+
+    // amounts in wei are u128, stored as decimal strings
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS update_cost (
+            id INTEGER NOT NULL,
+            num INTEGER NOT NULL,
+            tx_hash BLOB NOT NULL,
+            gas_used INTEGER NOT NULL,
+            effective_gas_price TEXT NOT NULL,
+            blob_gas_used INTEGER NOT NULL,
+            blob_gas_price TEXT NOT NULL,
+            total_fee TEXT NOT NULL,
+            PRIMARY KEY (id, num)
+        )
+        "#,
+    )
+    .execute(db_pool)
+    .await?;
+
+    // op of each update as JSON, with the commitment of the state after it.  The rows are
+    // written once the main pod of the update is proven, so the ones past the num of the list
+    // belong to updates in flight (or failed) and are overwritten by the next update.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS op_log (
+            id INTEGER NOT NULL,
+            num INTEGER NOT NULL,
+            op TEXT NOT NULL,
+            state BLOB NOT NULL,
+            PRIMARY KEY (id, num)
+        )
+        "#,
+    )
+    .execute(db_pool)
+    .await?;
+
+    // ops history (`common::ops`) of the lists after the update `num`, written with the op log.
+    // The rows before the num of the list are pruned once an update is applied, so the ops history
+    // is extended from the latest row instead of being rebuilt from the whole op log.
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS list_ops (
+            id INTEGER NOT NULL,
+            num INTEGER NOT NULL,
+            ops BLOB NOT NULL,
+            PRIMARY KEY (id, num)
+        )
+        "#,
+    )
+    .execute(db_pool)
+    .await?;
+
+    // bloom filters of the members of each group of the lists at `num`, see `bloom`
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS group_bloom (
+            id INTEGER NOT NULL,
+            grp TEXT NOT NULL,
+            num INTEGER NOT NULL,
+            bloom BLOB NOT NULL,
+            PRIMARY KEY (id, grp)
+        )
+        "#,
+    )
+    .execute(db_pool)
+    .await?;
+
+    // requests left in the queue by a shutdown, as JSON, resumed on the next start
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS queue_request (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            request TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(db_pool)
+    .await?;
+
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS proving_incident (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            id INTEGER NOT NULL,
+            num INTEGER NOT NULL,
+            pod_name TEXT NOT NULL,
+            elapsed_ms INTEGER NOT NULL,
+            created_at INTEGER NOT NULL
         )
         "#,
     )
     .execute(db_pool)
     .await?;
 
+    // columns added after the tables were first created
+    for table in ["membership_list", "rev_membership_list"] {
+        for column in ["created_at", "updated_at"] {
+            add_column_if_missing(db_pool, table, column, "INTEGER NOT NULL DEFAULT 0").await?;
+        }
+    }
+    // address of the signer, null for the mock sends and the costs recorded before it was kept
+    add_column_if_missing(db_pool, "update_cost", "sender", "BLOB").await?;
+    // the lists created before the kv lists are set lists
+    add_column_if_missing(
+        db_pool,
+        "membership_list",
+        "kind",
+        "TEXT NOT NULL DEFAULT 'set'",
+    )
+    .await?;
+    add_column_if_missing(db_pool, "membership_list", "audit_flag", "TEXT").await?;
+    add_column_if_missing(db_pool, "membership_list", "blob_versioned_hash", "BLOB").await?;
+    add_column_if_missing(db_pool, "pending_wrap", "first_num", "INTEGER").await?;
+    add_column_if_missing(db_pool, "pending_wrap", "tx_hash", "BLOB").await?;
+    add_column_if_missing(db_pool, "pending_wrap", "blob_versioned_hash", "BLOB").await?;
+
+    Ok(())
+}
+
+async fn add_column_if_missing(
+    db_pool: &SqlitePool,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), Error> {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?")
+            .bind(table)
+            .bind(column)
+            .fetch_one(db_pool)
+            .await?;
+    if count == 0 {
+        sqlx::query(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .execute(db_pool)
+        .await?;
+    }
     Ok(())
 }
 
 // DB METHODS:
 
-pub async fn get_latest_membership_list(pool: &SqlitePool) -> Result<Option<AdState>, sqlx::Error> {
-    sqlx::query_as::<_, AdState>(
-        "SELECT id, num, state FROM membership_list ORDER BY id DESC LIMIT 1",
+pub async fn get_latest_membership_list(pool: &SqlitePool) -> Result<Option<AdState>, Error> {
+    Ok(sqlx::query_as::<_, AdState>(
+        "SELECT id, num, state, created_at, updated_at FROM membership_list ORDER BY id DESC LIMIT 1",
     )
     .fetch_optional(pool)
-    .await
+    .await?)
 }
 
 pub async fn insert_membership_list(
     pool: &SqlitePool,
     membership_list: &AdState,
-) -> Result<(), sqlx::Error> {
-    sqlx::query("INSERT INTO membership_list (id, num, state) VALUES (?, ?, ?);")
-        .bind(membership_list.id)
-        .bind(membership_list.num)
-        .bind(membership_list.state.to_bytes())
-        .execute(pool)
-        .await?;
+    kind: ListKind,
+    blob_versioned_hash: Option<B256>,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO membership_list (id, num, state, blob_versioned_hash, created_at, updated_at, kind) VALUES (?, ?, ?, ?, ?, ?, ?);",
+    )
+    .bind(membership_list.id)
+    .bind(membership_list.num)
+    .bind(membership_list.state.to_bytes())
+    .bind(blob_versioned_hash.as_ref().map(|h| h.as_slice()))
+    .bind(membership_list.created_at)
+    .bind(membership_list.updated_at)
+    .bind(kind.as_str())
+    .execute(pool)
+    .await?;
     Ok(())
 }
 
 pub async fn insert_rev_membership_list(
     pool: &SqlitePool,
     rev_membership_list: &AdState,
-) -> Result<(), sqlx::Error> {
-    sqlx::query("INSERT INTO rev_membership_list (id, num, state) VALUES (?, ?, ?);")
-        .bind(rev_membership_list.id)
-        .bind(rev_membership_list.num)
-        .bind(rev_membership_list.state.to_bytes())
-        .execute(pool)
-        .await?;
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO rev_membership_list (id, num, state, created_at, updated_at) VALUES (?, ?, ?, ?, ?);",
+    )
+    .bind(rev_membership_list.id)
+    .bind(rev_membership_list.num)
+    .bind(rev_membership_list.state.to_bytes())
+    .bind(rev_membership_list.created_at)
+    .bind(rev_membership_list.updated_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Inserts a membership list restored from a snapshot together with its reverse index.
+pub async fn insert_membership_list_snapshot(
+    pool: &SqlitePool,
+    membership_list: &AdState,
+    rev_membership_list: &AdState,
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    insert_list_rows(&mut tx, membership_list, rev_membership_list).await?;
+    tx.commit().await?;
     Ok(())
 }
 
-pub async fn get_membership_list(pool: &SqlitePool, id: i64) -> Result<AdState, sqlx::Error> {
-    let (state_bytes, num): (Vec<u8>, i64) =
-        sqlx::query_as("SELECT state, num FROM membership_list WHERE id = ?;")
+/// Inserts a membership list restored from an archive together with its reverse index, its op
+/// log and the bloom filters of its groups, all or none of them.
+pub async fn insert_archived_list(
+    pool: &SqlitePool,
+    membership_list: &AdState,
+    rev_membership_list: &AdState,
+    op_log: &[OpLogEntry],
+    blooms: &[(String, Bloom)],
+) -> Result<(), Error> {
+    let id = membership_list.id;
+    let mut tx = pool.begin().await?;
+    for entry in op_log {
+        sqlx::query("INSERT OR REPLACE INTO op_log (id, num, op, state) VALUES (?, ?, ?, ?);")
+            .bind(id)
+            .bind(entry.num)
+            .bind(serde_json::to_string(&entry.op).map_err(anyhow::Error::from)?)
+            .bind(RawValueSql(RawValue::from(entry.state)).to_bytes())
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query("DELETE FROM group_bloom WHERE id = ?;")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    for (group, bloom) in blooms {
+        sqlx::query("INSERT INTO group_bloom (id, grp, num, bloom) VALUES (?, ?, ?, ?);")
             .bind(id)
-            .fetch_one(pool)
+            .bind(group)
+            .bind(membership_list.num)
+            .bind(bloom.to_bytes())
+            .execute(&mut *tx)
             .await?;
-    let state = DictContainerSql::try_from(state_bytes).expect("Invalid encoding");
+    }
+    insert_list_rows(&mut tx, membership_list, rev_membership_list).await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+async fn insert_list_rows(
+    conn: &mut SqliteConnection,
+    membership_list: &AdState,
+    rev_membership_list: &AdState,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO membership_list (id, num, state, blob_versioned_hash, created_at, updated_at) VALUES (?, ?, ?, NULL, ?, ?);",
+    )
+    .bind(membership_list.id)
+    .bind(membership_list.num)
+    .bind(membership_list.state.to_bytes())
+    .bind(membership_list.created_at)
+    .bind(membership_list.updated_at)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query(
+        "INSERT INTO rev_membership_list (id, num, state, created_at, updated_at) VALUES (?, ?, ?, ?, ?);",
+    )
+    .bind(rev_membership_list.id)
+    .bind(rev_membership_list.num)
+    .bind(rev_membership_list.state.to_bytes())
+    .bind(rev_membership_list.created_at)
+    .bind(rev_membership_list.updated_at)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Sets the state of the membership list `id` mirrored from another server at `num`, and its
+/// reverse index if it's a `set` list, inserting them if the list isn't mirrored yet.
+pub async fn upsert_mirrored_list(
+    pool: &SqlitePool,
+    id: i64,
+    kind: ListKind,
+    num: i64,
+    state: &containers::Dictionary,
+    rev_state: Option<&containers::Dictionary>,
+) -> Result<(), Error> {
+    let now = unix_now();
+    let mut tx = pool.begin().await?;
+    sqlx::query(
+        "INSERT INTO membership_list (id, num, state, blob_versioned_hash, created_at, updated_at, kind) VALUES (?, ?, ?, NULL, ?, ?, ?) ON CONFLICT (id) DO UPDATE SET num = excluded.num, state = excluded.state, updated_at = excluded.updated_at;",
+    )
+    .bind(id)
+    .bind(num)
+    .bind(DictContainerSql(state.clone()).to_bytes())
+    .bind(now)
+    .bind(now)
+    .bind(kind.as_str())
+    .execute(&mut *tx)
+    .await?;
+    if let Some(rev_state) = rev_state {
+        sqlx::query(
+            "INSERT INTO rev_membership_list (id, num, state, created_at, updated_at) VALUES (?, ?, ?, ?, ?) ON CONFLICT (id) DO UPDATE SET num = excluded.num, state = excluded.state, updated_at = excluded.updated_at;",
+        )
+        .bind(id)
+        .bind(num)
+        .bind(DictContainerSql(rev_state.clone()).to_bytes())
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_membership_list(pool: &SqlitePool, id: i64) -> Result<AdState, Error> {
+    sqlx::query_as::<_, AdState>(
+        "SELECT id, num, state, created_at, updated_at FROM membership_list WHERE id = ?;",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| Error::NotFound(format!("membership list {}", id)))
+}
+
+pub async fn get_membership_list_kind(pool: &SqlitePool, id: i64) -> Result<ListKind, Error> {
+    let (kind,): (String,) = sqlx::query_as("SELECT kind FROM membership_list WHERE id = ?;")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("membership list {}", id)))?;
+    Ok(kind.parse()?)
+}
 
-    Ok(AdState { id, num, state })
+/// Returns the ids of the membership lists, in id order.
+pub async fn get_membership_list_ids(pool: &SqlitePool) -> Result<Vec<i64>, Error> {
+    let rows: Vec<(i64,)> = sqlx::query_as("SELECT id FROM membership_list ORDER BY id;")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|(id,)| id).collect())
 }
 
-pub async fn get_rev_membership_list(pool: &SqlitePool, id: i64) -> Result<AdState, sqlx::Error> {
-    let (state_bytes, num): (Vec<u8>, i64) =
-        sqlx::query_as("SELECT state, num FROM rev_membership_list WHERE id = ?;")
+/// Returns the status the audit flagged the membership list `id` with, none if it isn't flagged.
+pub async fn get_audit_flag(pool: &SqlitePool, id: i64) -> Result<Option<AuditStatus>, Error> {
+    let (flag,): (Option<String>,) =
+        sqlx::query_as("SELECT audit_flag FROM membership_list WHERE id = ?;")
             .bind(id)
-            .fetch_one(pool)
-            .await?;
-    let state = DictContainerSql::try_from(state_bytes).expect("Invalid encoding");
+            .fetch_optional(pool)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("membership list {}", id)))?;
+    Ok(flag.map(|flag| flag.parse::<AuditStatus>()).transpose()?)
+}
+
+/// Flags the membership list `id` with `flag`, or clears its flag if none.
+pub async fn set_audit_flag(
+    pool: &SqlitePool,
+    id: i64,
+    flag: Option<AuditStatus>,
+) -> Result<(), Error> {
+    let result = sqlx::query("UPDATE membership_list SET audit_flag = ? WHERE id = ?;")
+        .bind(flag.map(|flag| flag.as_str()))
+        .bind(id)
+        .execute(pool)
+        .await?;
+    if result.rows_affected() == 0 {
+        return Err(Error::NotFound(format!("membership list {}", id)));
+    }
+    Ok(())
+}
+
+pub async fn get_rev_membership_list(pool: &SqlitePool, id: i64) -> Result<AdState, Error> {
+    sqlx::query_as::<_, AdState>(
+        "SELECT id, num, state, created_at, updated_at FROM rev_membership_list WHERE id = ?;",
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| Error::NotFound(format!("reverse membership list {}", id)))
+}
 
-    Ok(AdState { id, num, state })
+/// Returns the reverse membership lists with id greater than `after_id`, in id order and at most
+/// `limit` of them.
+pub async fn get_rev_membership_lists(
+    pool: &SqlitePool,
+    after_id: i64,
+    limit: i64,
+) -> Result<Vec<AdState>, Error> {
+    Ok(sqlx::query_as::<_, AdState>(
+        "SELECT id, num, state, created_at, updated_at FROM rev_membership_list WHERE id > ? ORDER BY id LIMIT ?;",
+    )
+    .bind(after_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?)
 }
 
+/// Sets the state of the membership list `id` at `num` if it's still at `prev_num`.  Returns the
+/// number of rows updated, which is 0 if another update was applied since `prev_num` was read.
 pub async fn update_membership_list(
+    pool: &SqlitePool,
+    id: i64,
+    prev_num: i64,
+    num: i64,
+    state: containers::Dictionary,
+    blob_versioned_hash: Option<B256>,
+) -> Result<u64, Error> {
+    let result = sqlx::query(
+        "UPDATE membership_list SET state = ?, num = ?, blob_versioned_hash = ?, updated_at = ? WHERE id = ? AND num = ?",
+    )
+    .bind(DictContainerSql(state).to_bytes())
+    .bind(num)
+    .bind(blob_versioned_hash.as_ref().map(|h| h.as_slice()))
+    .bind(unix_now())
+    .bind(id)
+    .bind(prev_num)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn update_rev_membership_list(
     pool: &SqlitePool,
     id: i64,
     num: i64,
     state: containers::Dictionary,
-) -> Result<(), sqlx::Error> {
-    sqlx::query("UPDATE membership_list SET state = ?, num = ? WHERE id = ?")
+) -> Result<(), Error> {
+    sqlx::query("UPDATE rev_membership_list SET state = ?, num = ?, updated_at = ? WHERE id = ?")
         .bind(DictContainerSql(state).to_bytes())
         .bind(num)
+        .bind(unix_now())
         .bind(id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-pub async fn update_rev_membership_list(
+/// Num of a membership list and of its reverse index, which lags behind while the reverse index
+/// updates are pending
+#[derive(Debug, Clone, PartialEq, Eq, FromRow, Serialize, Deserialize)]
+pub struct RevLag {
+    pub id: i64,
+    pub num: i64,
+    pub rev_num: i64,
+}
+
+pub async fn get_rev_lags(pool: &SqlitePool) -> Result<Vec<RevLag>, Error> {
+    Ok(sqlx::query_as(
+        "SELECT m.id AS id, m.num AS num, r.num AS rev_num FROM membership_list m JOIN rev_membership_list r ON m.id = r.id ORDER BY m.id;",
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+/// Cost of the tx that posted the update `num` of a membership list
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UpdateCost {
+    pub num: i64,
+    pub tx_hash: TxHash,
+    pub cost: TxCostInfo,
+}
+
+pub async fn insert_update_cost(
     pool: &SqlitePool,
     id: i64,
     num: i64,
-    state: containers::Dictionary,
-) -> Result<(), sqlx::Error> {
-    sqlx::query("UPDATE rev_membership_list SET state = ?, num = ? WHERE id = ?")
-        .bind(DictContainerSql(state).to_bytes())
+    tx_hash: TxHash,
+    cost: &TxCostInfo,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO update_cost (id, num, tx_hash, gas_used, effective_gas_price, blob_gas_used, blob_gas_price, total_fee, sender) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);",
+    )
+    .bind(id)
+    .bind(num)
+    .bind(tx_hash.as_slice())
+    .bind(cost.gas_used as i64)
+    .bind(cost.effective_gas_price.to_string())
+    .bind(cost.blob_gas_used as i64)
+    .bind(cost.blob_gas_price.to_string())
+    .bind(cost.total_fee.to_string())
+    .bind(cost.sender.as_ref().map(|sender| sender.as_slice()))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_update_costs(pool: &SqlitePool, id: i64) -> Result<Vec<UpdateCost>, Error> {
+    #[allow(clippy::type_complexity)]
+    let rows: Vec<(i64, Vec<u8>, i64, String, i64, String, String, Option<Vec<u8>>)> = sqlx::query_as(
+        "SELECT num, tx_hash, gas_used, effective_gas_price, blob_gas_used, blob_gas_price, total_fee, sender FROM update_cost WHERE id = ? ORDER BY num",
+    )
+    .bind(id)
+    .fetch_all(pool)
+    .await?;
+    let parse_wei = |wei: &str| wei.parse::<u128>().map_err(anyhow::Error::from);
+    let mut costs = Vec::with_capacity(rows.len());
+    for (
+        num,
+        tx_hash,
+        gas_used,
+        effective_gas_price,
+        blob_gas_used,
+        blob_gas_price,
+        total_fee,
+        sender,
+    ) in rows
+    {
+        costs.push(UpdateCost {
+            num,
+            tx_hash: TxHash::try_from(tx_hash.as_slice()).map_err(anyhow::Error::from)?,
+            cost: TxCostInfo {
+                gas_used: gas_used as u64,
+                effective_gas_price: parse_wei(&effective_gas_price)?,
+                blob_gas_used: blob_gas_used as u64,
+                blob_gas_price: parse_wei(&blob_gas_price)?,
+                total_fee: parse_wei(&total_fee)?,
+                sender: sender
+                    .map(|sender| Address::try_from(sender.as_slice()))
+                    .transpose()
+                    .map_err(anyhow::Error::from)?,
+            },
+        });
+    }
+    Ok(costs)
+}
+
+/// Op of the update `num` of a membership list
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OpLogEntry {
+    pub num: i64,
+    pub op: Op,
+    // commitment of the state after the op
+    pub state: Hash,
+}
+
+pub async fn insert_op_log(pool: &SqlitePool, id: i64, entry: &OpLogEntry) -> Result<(), Error> {
+    sqlx::query("INSERT OR REPLACE INTO op_log (id, num, op, state) VALUES (?, ?, ?, ?);")
+        .bind(id)
+        .bind(entry.num)
+        .bind(serde_json::to_string(&entry.op).map_err(anyhow::Error::from)?)
+        .bind(RawValueSql(RawValue::from(entry.state)).to_bytes())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Returns the logged ops of the updates `from_num..=to_num` of a membership list, in num order.
+pub async fn get_op_log(
+    pool: &SqlitePool,
+    id: i64,
+    from_num: i64,
+    to_num: i64,
+) -> Result<Vec<OpLogEntry>, Error> {
+    let rows: Vec<(i64, String, Vec<u8>)> = sqlx::query_as(
+        "SELECT num, op, state FROM op_log WHERE id = ? AND num >= ? AND num <= ? ORDER BY num;",
+    )
+    .bind(id)
+    .bind(from_num)
+    .bind(to_num)
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|(num, op, state)| {
+            Ok(OpLogEntry {
+                num,
+                op: serde_json::from_str(&op).map_err(anyhow::Error::from)?,
+                state: Hash::from(RawValueSql::try_from(state)?.0),
+            })
+        })
+        .collect()
+}
+
+pub async fn insert_list_ops(
+    pool: &SqlitePool,
+    id: i64,
+    num: i64,
+    ops: &containers::Dictionary,
+) -> Result<(), Error> {
+    sqlx::query("INSERT OR REPLACE INTO list_ops (id, num, ops) VALUES (?, ?, ?);")
+        .bind(id)
+        .bind(num)
+        .bind(minicbor_serde::to_vec(ops).unwrap())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Returns the ops history of the membership list `id` after the update `num`, if it's stored.
+pub async fn get_list_ops(
+    pool: &SqlitePool,
+    id: i64,
+    num: i64,
+) -> Result<Option<containers::Dictionary>, Error> {
+    let row: Option<(Vec<u8>,)> =
+        sqlx::query_as("SELECT ops FROM list_ops WHERE id = ? AND num = ?;")
+            .bind(id)
+            .bind(num)
+            .fetch_optional(pool)
+            .await?;
+    row.map(|(ops,)| Ok(DictContainerSql::try_from(ops)?.0))
+        .transpose()
+}
+
+/// Returns whether the ops history of the membership list `id` is stored at any num, i.e. whether
+/// the list publishes it.
+pub async fn has_list_ops(pool: &SqlitePool, id: i64) -> Result<bool, Error> {
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM list_ops WHERE id = ?;")
+        .bind(id)
+        .fetch_one(pool)
+        .await?;
+    Ok(count > 0)
+}
+
+/// Deletes the ops histories of the membership list `id` before the update `num`.
+pub async fn delete_list_ops_before(pool: &SqlitePool, id: i64, num: i64) -> Result<(), Error> {
+    sqlx::query("DELETE FROM list_ops WHERE id = ? AND num < ?;")
+        .bind(id)
+        .bind(num)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Replaces the bloom filters of the groups of the membership list `id` with the ones of its state
+/// at `num`.
+pub async fn replace_group_blooms(
+    pool: &SqlitePool,
+    id: i64,
+    num: i64,
+    blooms: &[(String, Bloom)],
+) -> Result<(), Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM group_bloom WHERE id = ?;")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+    for (group, bloom) in blooms {
+        sqlx::query("INSERT INTO group_bloom (id, grp, num, bloom) VALUES (?, ?, ?, ?);")
+            .bind(id)
+            .bind(group)
+            .bind(num)
+            .bind(bloom.to_bytes())
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Returns the bloom filters of the groups of the membership list `id` if they were built from
+/// the state its reverse index is at, and none otherwise.
+pub async fn get_group_blooms_at_rev(pool: &SqlitePool, id: i64) -> Result<Vec<Bloom>, Error> {
+    let rows: Vec<(Vec<u8>,)> = sqlx::query_as(
+        "SELECT b.bloom FROM group_bloom b JOIN rev_membership_list r ON r.id = b.id AND r.num = b.num WHERE b.id = ?;",
+    )
+    .bind(id)
+    .fetch_all(pool)
+    .await?;
+    rows.into_iter()
+        .map(|(bloom,)| Ok(Bloom::from_bytes(&bloom)?))
+        .collect()
+}
+
+pub async fn insert_pending_wrap(
+    pool: &SqlitePool,
+    pending_wrap: &PendingWrap,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO pending_wrap (id, num, pod_name, op, new_state, attempts, first_num, tx_hash, blob_versioned_hash) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?);",
+    )
+    .bind(pending_wrap.id)
+    .bind(pending_wrap.num)
+    .bind(&pending_wrap.pod_name)
+    .bind(pending_wrap.op.to_bytes())
+    .bind(pending_wrap.new_state.to_bytes())
+    .bind(pending_wrap.attempts)
+    .bind(pending_wrap.first_num)
+    .bind(&pending_wrap.tx_hash)
+    .bind(&pending_wrap.blob_versioned_hash)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_pending_wrap(pool: &SqlitePool, id: i64, num: i64) -> Result<PendingWrap, Error> {
+    sqlx::query_as::<_, PendingWrap>(
+        "SELECT id, num, pod_name, op, new_state, attempts, first_num, tx_hash, blob_versioned_hash FROM pending_wrap WHERE id = ? AND num = ?",
+    )
+    .bind(id)
+    .bind(num)
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| Error::NotFound(format!("pending wrap {}-{}", id, num)))
+}
+
+pub async fn get_pending_wraps(pool: &SqlitePool) -> Result<Vec<PendingWrap>, Error> {
+    Ok(sqlx::query_as::<_, PendingWrap>(
+        "SELECT id, num, pod_name, op, new_state, attempts, first_num, tx_hash, blob_versioned_hash FROM pending_wrap ORDER BY id, num",
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+pub async fn update_pending_wrap_attempts(
+    pool: &SqlitePool,
+    id: i64,
+    num: i64,
+    attempts: i64,
+) -> Result<(), Error> {
+    sqlx::query("UPDATE pending_wrap SET attempts = ? WHERE id = ? AND num = ?")
+        .bind(attempts)
+        .bind(id)
         .bind(num)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Records the tx that sent the payload of the pending wrap, so that it isn't sent again.
+pub async fn set_pending_wrap_sent(
+    pool: &SqlitePool,
+    id: i64,
+    num: i64,
+    tx_hash: TxHash,
+    blob_versioned_hash: Option<B256>,
+) -> Result<(), Error> {
+    sqlx::query(
+        "UPDATE pending_wrap SET tx_hash = ?, blob_versioned_hash = ? WHERE id = ? AND num = ?",
+    )
+    .bind(tx_hash.as_slice())
+    .bind(blob_versioned_hash.as_ref().map(|h| h.as_slice()))
+    .bind(id)
+    .bind(num)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_pending_wrap(pool: &SqlitePool, id: i64, num: i64) -> Result<(), Error> {
+    sqlx::query("DELETE FROM pending_wrap WHERE id = ? AND num = ?")
         .bind(id)
+        .bind(num)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn insert_queue_request(
+    pool: &SqlitePool,
+    request: &queue::Request,
+) -> Result<(), Error> {
+    sqlx::query("INSERT INTO queue_request (request) VALUES (?);")
+        .bind(serde_json::to_string(request).map_err(anyhow::Error::from)?)
         .execute(pool)
         .await?;
     Ok(())
 }
+
+/// Removes and returns the stored queue requests in the order they were stored.
+pub async fn take_queue_requests(pool: &SqlitePool) -> Result<Vec<queue::Request>, Error> {
+    let mut tx = pool.begin().await?;
+    let rows: Vec<(String,)> = sqlx::query_as("SELECT request FROM queue_request ORDER BY seq;")
+        .fetch_all(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM queue_request;")
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    rows.into_iter()
+        .map(|(request,)| Ok(serde_json::from_str(&request).map_err(anyhow::Error::from)?))
+        .collect()
+}
+
+pub async fn insert_proving_incident(
+    pool: &SqlitePool,
+    incident: &ProvingIncident,
+) -> Result<(), Error> {
+    sqlx::query(
+        "INSERT INTO proving_incident (id, num, pod_name, elapsed_ms, created_at) VALUES (?, ?, ?, ?, ?);",
+    )
+    .bind(incident.id)
+    .bind(incident.num)
+    .bind(&incident.pod_name)
+    .bind(incident.elapsed_ms)
+    .bind(incident.created_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns the proving incidents in the order they were recorded.
+pub async fn get_proving_incidents(pool: &SqlitePool) -> Result<Vec<ProvingIncident>, Error> {
+    Ok(sqlx::query_as(
+        "SELECT id, num, pod_name, elapsed_ms, created_at FROM proving_incident ORDER BY seq;",
+    )
+    .fetch_all(pool)
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use pod2::middleware::{Hash, Params};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pending_wrap() -> anyhow::Result<()> {
+        let db_pool = SqlitePool::connect(":memory:").await?;
+        init_db(&db_pool).await?;
+
+        let new_state =
+            containers::Dictionary::new(Params::default().max_depth_mt_containers, HashMap::new())
+                .unwrap();
+        let pending_wrap = PendingWrap {
+            id: 1,
+            num: 2,
+            pod_name: "0000000000000000001-0000000000000000002-membership_list".to_string(),
+            op: RawValueSql(RawValue::from(Hash::default())),
+            new_state: DictContainerSql(new_state),
+            attempts: 1,
+            first_num: Some(1),
+            tx_hash: None,
+            blob_versioned_hash: None,
+        };
+        insert_pending_wrap(&db_pool, &pending_wrap).await?;
+        update_pending_wrap_attempts(&db_pool, 1, 2, 2).await?;
+        assert!(get_pending_wrap(&db_pool, 1, 2).await?.resumable(3));
+        assert!(!get_pending_wrap(&db_pool, 1, 2).await?.resumable(2));
+
+        let pending_wraps = get_pending_wraps(&db_pool).await?;
+        assert_eq!(pending_wraps.len(), 1);
+        assert_eq!(pending_wraps[0].pod_name, pending_wrap.pod_name);
+        assert_eq!(pending_wraps[0].op, pending_wrap.op);
+        assert_eq!(pending_wraps[0].new_state, pending_wrap.new_state);
+        assert_eq!(get_pending_wrap(&db_pool, 1, 2).await?.attempts, 2);
+        assert_eq!(get_pending_wrap(&db_pool, 1, 2).await?.first_num, Some(1));
+
+        // once sent, it's resumed whatever its attempts
+        set_pending_wrap_sent(&db_pool, 1, 2, TxHash::repeat_byte(1), None).await?;
+        let pending_wrap = get_pending_wrap(&db_pool, 1, 2).await?;
+        assert_eq!(pending_wrap.tx_hash, Some(vec![1; 32]));
+        assert_eq!(pending_wrap.blob_versioned_hash, None);
+        assert!(pending_wrap.resumable(2));
+
+        delete_pending_wrap(&db_pool, 1, 2).await?;
+        assert!(get_pending_wraps(&db_pool).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_costs() -> anyhow::Result<()> {
+        let db_pool = SqlitePool::connect(":memory:").await?;
+        init_db(&db_pool).await?;
+
+        // mock sends cost nothing
+        insert_update_cost(&db_pool, 1, 0, TxHash::ZERO, &TxCostInfo::default()).await?;
+        // over i64::MAX wei
+        let cost = TxCostInfo {
+            sender: Some(Address::repeat_byte(3)),
+            ..TxCostInfo::new(21_000, u64::MAX as u128, 131_072, 1)
+        };
+        insert_update_cost(&db_pool, 1, 1, TxHash::repeat_byte(1), &cost).await?;
+        insert_update_cost(&db_pool, 2, 1, TxHash::repeat_byte(2), &cost).await?;
+
+        let costs = get_update_costs(&db_pool, 1).await?;
+        assert_eq!(
+            costs,
+            vec![
+                UpdateCost {
+                    num: 0,
+                    tx_hash: TxHash::ZERO,
+                    cost: TxCostInfo::default(),
+                },
+                UpdateCost {
+                    num: 1,
+                    tx_hash: TxHash::repeat_byte(1),
+                    cost,
+                },
+            ]
+        );
+        assert!(get_update_costs(&db_pool, 3).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queue_requests() -> anyhow::Result<()> {
+        let db_pool = SqlitePool::connect(":memory:").await?;
+        init_db(&db_pool).await?;
+
+        let req_ids = [uuid::Uuid::now_v7(), uuid::Uuid::now_v7()];
+        insert_queue_request(
+            &db_pool,
+            &queue::Request::Update {
+                req_id: req_ids[0],
+                id: 1,
+                op: app::Op::Init,
+            },
+        )
+        .await?;
+        insert_queue_request(
+            &db_pool,
+            &queue::Request::Create {
+                req_id: req_ids[1],
+                kind: ListKind::Set,
+            },
+        )
+        .await?;
+
+        let requests = take_queue_requests(&db_pool).await?;
+        assert_eq!(
+            requests.iter().map(|r| r.req_id()).collect::<Vec<_>>(),
+            req_ids
+        );
+        assert!(matches!(requests[0], queue::Request::Update { id: 1, .. }));
+        assert!(take_queue_requests(&db_pool).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rev_lags() -> anyhow::Result<()> {
+        let db_pool = SqlitePool::connect(":memory:").await?;
+        init_db(&db_pool).await?;
+
+        let state =
+            containers::Dictionary::new(Params::default().max_depth_mt_containers, HashMap::new())
+                .unwrap();
+        for id in [1, 2] {
+            let ad_state = AdState {
+                id,
+                num: 0,
+                state: DictContainerSql(state.clone()),
+                created_at: 1000,
+                updated_at: 1000,
+            };
+            insert_membership_list(&db_pool, &ad_state, ListKind::Set, None).await?;
+            insert_rev_membership_list(&db_pool, &ad_state).await?;
+        }
+        update_membership_list(&db_pool, 2, 0, 1, state.clone(), None).await?;
+        update_membership_list(&db_pool, 2, 1, 2, state.clone(), None).await?;
+        update_rev_membership_list(&db_pool, 2, 1, state).await?;
+
+        assert_eq!(
+            get_rev_lags(&db_pool).await?,
+            vec![
+                RevLag {
+                    id: 1,
+                    num: 0,
+                    rev_num: 0
+                },
+                RevLag {
+                    id: 2,
+                    num: 2,
+                    rev_num: 1
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_group_blooms() -> anyhow::Result<()> {
+        let db_pool = SqlitePool::connect(":memory:").await?;
+        init_db(&db_pool).await?;
+
+        let state =
+            containers::Dictionary::new(Params::default().max_depth_mt_containers, HashMap::new())
+                .unwrap();
+        insert_rev_membership_list(
+            &db_pool,
+            &AdState {
+                id: 1,
+                num: 1,
+                state: DictContainerSql(state.clone()),
+                created_at: 1000,
+                updated_at: 1000,
+            },
+        )
+        .await?;
+        // no blooms yet
+        assert!(get_group_blooms_at_rev(&db_pool, 1).await?.is_empty());
+
+        let bloom = |n| Bloom::with_capacity(n);
+        let blooms = [
+            ("red".to_string(), bloom(1)),
+            ("blue".to_string(), bloom(100)),
+        ];
+        replace_group_blooms(&db_pool, 1, 1, &blooms).await?;
+        assert_eq!(get_group_blooms_at_rev(&db_pool, 1).await?.len(), 2);
+        // the blooms of a state the reverse index hasn't caught up with aren't used
+        replace_group_blooms(&db_pool, 1, 2, &blooms[..1]).await?;
+        assert!(get_group_blooms_at_rev(&db_pool, 1).await?.is_empty());
+        update_rev_membership_list(&db_pool, 1, 2, state).await?;
+        assert_eq!(get_group_blooms_at_rev(&db_pool, 1).await?, vec![bloom(1)]);
+        // unknown list
+        assert!(get_group_blooms_at_rev(&db_pool, 2).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_membership_list_timestamps() -> anyhow::Result<()> {
+        let db_pool = SqlitePool::connect(":memory:").await?;
+        init_db(&db_pool).await?;
+        // init_db is idempotent
+        init_db(&db_pool).await?;
+
+        let state =
+            containers::Dictionary::new(Params::default().max_depth_mt_containers, HashMap::new())
+                .unwrap();
+        insert_membership_list(
+            &db_pool,
+            &AdState {
+                id: 1,
+                num: 0,
+                state: DictContainerSql(state.clone()),
+                created_at: 1000,
+                updated_at: 1000,
+            },
+            ListKind::Kv,
+            None,
+        )
+        .await?;
+        let membership_list = get_membership_list(&db_pool, 1).await?;
+        assert_eq!(get_membership_list_kind(&db_pool, 1).await?, ListKind::Kv);
+        assert!(get_membership_list_kind(&db_pool, 2).await.is_err());
+        assert_eq!(membership_list.created_at, 1000);
+        assert_eq!(membership_list.updated_at, 1000);
+
+        assert_eq!(
+            update_membership_list(&db_pool, 1, 0, 1, state.clone(), None).await?,
+            1
+        );
+        let membership_list = get_membership_list(&db_pool, 1).await?;
+        assert_eq!(membership_list.created_at, 1000);
+        assert!(membership_list.updated_at >= unix_now() - 60);
+
+        // another update read the list at num 0 too and lost the race
+        assert_eq!(
+            update_membership_list(&db_pool, 1, 0, 1, state, None).await?,
+            0
+        );
+        assert_eq!(get_membership_list(&db_pool, 1).await?.num, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_init_db_adds_missing_columns() -> anyhow::Result<()> {
+        let db_pool = SqlitePool::connect(":memory:").await?;
+        // the list table as first created
+        sqlx::query(
+            "CREATE TABLE membership_list (
+                id INTEGER PRIMARY KEY,
+                num INTEGER NOT NULL,
+                state BLOB NOT NULL
+            )",
+        )
+        .execute(&db_pool)
+        .await?;
+        init_db(&db_pool).await?;
+
+        let state =
+            containers::Dictionary::new(Params::default().max_depth_mt_containers, HashMap::new())
+                .unwrap();
+        insert_membership_list(
+            &db_pool,
+            &AdState {
+                id: 1,
+                num: 0,
+                state: DictContainerSql(state),
+                created_at: 1000,
+                updated_at: 1000,
+            },
+            ListKind::Set,
+            Some(B256::repeat_byte(1)),
+        )
+        .await?;
+        let blob_versioned_hash: Option<Vec<u8>> =
+            sqlx::query_scalar("SELECT blob_versioned_hash FROM membership_list WHERE id = 1")
+                .fetch_one(&db_pool)
+                .await?;
+        assert_eq!(blob_versioned_hash, Some(vec![1; 32]));
+        assert_eq!(get_membership_list_kind(&db_pool, 1).await?, ListKind::Set);
+
+        Ok(())
+    }
+}
@@ -1,15 +1,44 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::Infallible,
+    io,
+    path::Path,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use app::Op;
+use anyhow::{Result, anyhow};
+use app::{BatchNames, Group, Op, UserId};
 use common::{
-    CustomError,
-    disk::{load_pod, rev_membership_list_pod_file_name},
+    api::{QueryProofResponse, QueueResp},
+    disk::{self, PodKey},
+    payload::params_fingerprint,
+    rejection,
 };
+use hex::ToHex;
+use pod2::middleware::{Hash, Key, TypedValue, containers::Dictionary};
 use serde::{Deserialize, Serialize};
+use tracing::info;
 use uuid::Uuid;
-use warp::Filter;
+use warp::{
+    Filter, Rejection, Reply,
+    http::{
+        HeaderValue,
+        header::{CONTENT_TYPE, RETRY_AFTER},
+    },
+    hyper::body::{Body, Buf, Bytes},
+};
 
-use crate::{Context, db, queue};
+use crate::{
+    Context, Error, SetupProgress, archive,
+    audit::{self, AuditStatus},
+    db,
+    error::{ErrorInfo, ErrorKind},
+    limits,
+    mirror::ServerMode,
+    queue,
+    snapshot::{self, ListSnapshot},
+};
 
 // HANDLERS:
 
@@ -20,20 +49,355 @@ pub async fn handler_request_get(
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let state = match ctx.queue_state.read().await.get(&req_id).cloned() {
         Some(s) => s,
-        None => return Err(CustomError("req_id not found".to_string()).into()),
+        None => return Err(Error::NotFound(format!("request {}", req_id)).into()),
     };
     Ok(warp::reply::json(&state))
 }
 
-// GET /membership_list/{id}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    // dictionary rendered as `{"group": ["user", ...]}`
+    Json,
+    // dictionary serialized as is
+    Raw,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormatQuery {
+    format: Option<Format>,
+}
+
+/// Human readable view of an `AdState`.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdStateView {
+    pub id: i64,
+    pub num: i64,
+    // hex encoded commitment of the state dictionary
+    pub commitment: String,
+    pub state: BTreeMap<String, Vec<String>>,
+    // epoch committed in the state, equal to `num` once the list is initialized
+    pub epoch: Option<i64>,
+    // unix seconds
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+impl TryFrom<&db::AdState> for AdStateView {
+    type Error = anyhow::Error;
+
+    fn try_from(ad_state: &db::AdState) -> Result<Self> {
+        let mut groups = ad_state.state.0.clone();
+        let epoch = match groups.get(&Key::from("epoch")) {
+            Ok(epoch) => {
+                let epoch = match epoch.typed() {
+                    TypedValue::Int(epoch) => *epoch,
+                    _ => return Err(anyhow!("epoch is not an Int: {}", epoch)),
+                };
+                groups.delete(&Key::from("epoch"))?;
+                Some(epoch)
+            }
+            Err(_) => None,
+        };
+        Ok(Self {
+            id: ad_state.id,
+            num: ad_state.num,
+            commitment: ad_state.state.0.commitment().encode_hex::<String>(),
+            state: dict_of_string_sets(&groups)?,
+            epoch,
+            created_at: ad_state.created_at,
+            updated_at: ad_state.updated_at,
+        })
+    }
+}
+
+/// Converts a dictionary whose values are sets of strings (like the membership list, which maps
+/// groups to users, or the reverse membership list, which maps users to groups) into a map of
+/// sorted string lists.  The groups of the kv lists are listed by their users, without the values.
+pub fn dict_of_string_sets(dict: &Dictionary) -> Result<BTreeMap<String, Vec<String>>> {
+    dict.kvs()
+        .iter()
+        .map(|(key, value)| {
+            let mut members = match value.typed() {
+                TypedValue::Set(set) => set
+                    .set()
+                    .iter()
+                    .map(|member| match member.typed() {
+                        TypedValue::String(s) => Ok(s.clone()),
+                        _ => Err(anyhow!(
+                            "member of key {} is not a String: {}",
+                            key.name(),
+                            member
+                        )),
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+                TypedValue::Dictionary(users) => users
+                    .kvs()
+                    .keys()
+                    .map(|user| user.name().to_string())
+                    .collect(),
+                _ => {
+                    return Err(anyhow!(
+                        "value of key {} is not a Set: {}",
+                        key.name(),
+                        value
+                    ));
+                }
+            };
+            members.sort();
+            Ok((key.name().to_string(), members))
+        })
+        .collect()
+}
+
+// GET /membership_list/{id}?format={json,raw}
 pub async fn handler_membership_list_get(
     id: i64,
+    query: FormatQuery,
     ctx: Arc<Context>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let membership_list = db::get_membership_list(&ctx.db_pool, id)
-        .await
-        .map_err(|e| CustomError(e.to_string()))?;
-    Ok(warp::reply::json(&membership_list))
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    match query.format.unwrap_or(Format::Json) {
+        Format::Raw => Ok(warp::reply::json(&membership_list)),
+        Format::Json => {
+            let view = AdStateView::try_from(&membership_list).map_err(Error::Internal)?;
+            Ok(warp::reply::json(&view))
+        }
+    }
+}
+
+/// Costs of the txs that posted the updates of a membership list, and their totals.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CostsView {
+    pub id: i64,
+    pub updates: Vec<db::UpdateCost>,
+    pub total_gas_used: u64,
+    pub total_blob_gas_used: u64,
+    // wei
+    pub total_fee: u128,
+}
+
+// GET /membership_list/{id}/costs
+pub async fn handler_membership_list_costs_get(
+    id: i64,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // unknown lists are a 404 rather than an empty list of costs
+    db::get_membership_list(&ctx.db_pool, id).await?;
+    let updates = db::get_update_costs(&ctx.db_pool, id).await?;
+    let costs = CostsView {
+        id,
+        total_gas_used: updates.iter().map(|u| u.cost.gas_used).sum(),
+        total_blob_gas_used: updates.iter().map(|u| u.cost.blob_gas_used).sum(),
+        total_fee: updates.iter().map(|u| u.cost.total_fee).sum(),
+        updates,
+    };
+    Ok(warp::reply::json(&costs))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    from: i64,
+    to: i64,
+}
+
+/// Users added to and removed from a group
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+/// Changes of a membership list between the updates `from` and `to`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DiffView {
+    pub id: i64,
+    pub from: i64,
+    pub to: i64,
+    // hex encoded commitments of the state at `from` and at `to`
+    pub from_commitment: String,
+    pub to_commitment: String,
+    // only the groups that changed
+    pub groups: BTreeMap<String, GroupDiff>,
+}
+
+/// Accumulates the net changes of `ops` per group: a user added and removed again (or the other
+/// way around) within the ops doesn't appear.
+pub fn diff_ops<'a>(ops: impl IntoIterator<Item = &'a Op>) -> BTreeMap<String, GroupDiff> {
+    // +1 for a net add and -1 for a net del of the user
+    let mut changes: BTreeMap<(String, &str), i64> = BTreeMap::new();
+    for op in ops {
+        let (group, user, delta) = match op {
+            // the groups that are added or dropped have no members
+            Op::Init | Op::AddGroup { .. } | Op::DropGroup { .. } => continue,
+            Op::Add { group, user } | Op::AddKv { group, user, .. } => (group, user.as_str(), 1),
+            Op::Del { group, user } | Op::DelKv { group, user } => (group, user.as_str(), -1),
+        };
+        *changes.entry((group.to_string(), user)).or_default() += delta;
+    }
+    let mut groups: BTreeMap<String, GroupDiff> = BTreeMap::new();
+    for ((group, user), delta) in changes {
+        match delta.signum() {
+            1 => groups
+                .entry(group)
+                .or_default()
+                .added
+                .push(user.to_string()),
+            -1 => groups
+                .entry(group)
+                .or_default()
+                .removed
+                .push(user.to_string()),
+            _ => {}
+        }
+    }
+    groups
+}
+
+// GET /membership_list/{id}/diff?from={num}&to={num}
+pub async fn handler_membership_list_diff_get(
+    id: i64,
+    query: DiffQuery,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    let DiffQuery { from, to } = query;
+    if !(0 <= from && from <= to && to <= membership_list.num) {
+        return Err(Error::InvalidRange(format!(
+            "{}..{} of membership list {} at num {}",
+            from, to, id, membership_list.num
+        ))
+        .into());
+    }
+    // the entry at `from` is only read for its commitment
+    let entries = db::get_op_log(&ctx.db_pool, id, from.max(1), to).await?;
+    if entries.len() as i64 != to - from.max(1) + 1 {
+        // lists restored from a snapshot, or updated before the op log, have gaps
+        return Err(Error::NotFound(format!(
+            "op log of membership list {} between {} and {}",
+            id, from, to
+        ))
+        .into());
+    }
+    let empty_state = Dictionary::new(
+        ctx.pod_config()?.params.max_depth_mt_containers,
+        HashMap::new(),
+    )
+    .map_err(anyhow::Error::from)?;
+    let commitment_at = |num: i64| {
+        entries
+            .iter()
+            .find(|e| e.num == num)
+            .map_or(empty_state.commitment(), |e| e.state)
+            .encode_hex::<String>()
+    };
+    let diff = DiffView {
+        id,
+        from,
+        to,
+        from_commitment: commitment_at(from),
+        to_commitment: commitment_at(to),
+        groups: diff_ops(entries.iter().filter(|e| e.num > from).map(|e| &e.op)),
+    };
+    Ok(warp::reply::json(&diff))
+}
+
+/// Op of the update `num` of a membership list, with the Merkle proof of its commitment (`value`)
+/// in the ops history of the list after its latest update `at`.  `root` is the ops root published
+/// by that update, as indexed by the synchronizer in `GET /ad/{id}/ops_root`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OpProofView {
+    pub id: i64,
+    pub num: i64,
+    pub op: Op,
+    pub at: i64,
+    pub proof: QueryProofResponse,
+}
+
+// GET /membership_list/{id}/op_proof/{num}
+pub async fn handler_membership_list_op_proof_get(
+    id: i64,
+    num: i64,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    if !(1..=membership_list.num).contains(&num) {
+        return Err(Error::InvalidRange(format!(
+            "op {} of membership list {} at num {}",
+            num, id, membership_list.num
+        ))
+        .into());
+    }
+    let at = membership_list.num;
+    let not_found = || {
+        Error::NotFound(format!(
+            "ops history of membership list {} at num {}",
+            id, at
+        ))
+    };
+    let ops = queue::ops_at(&ctx, id, at).await?.ok_or_else(not_found)?;
+    let entry = db::get_op_log(&ctx.db_pool, id, num, num)
+        .await?
+        .pop()
+        .ok_or_else(not_found)?;
+    let proof = queue::prove_op(&ops, num)?;
+    Ok(warp::reply::json(&OpProofView {
+        id,
+        num,
+        op: entry.op,
+        at,
+        proof: QueryProofResponse::from(&proof),
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ListStatus {
+    id: i64,
+    num: i64,
+    rev_num: i64,
+    // updates of the membership list not yet applied to its reverse index
+    rev_lag: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StatusView {
+    // "initializing" until the setup is available, then "ready"
+    status: String,
+    progress: SetupProgress,
+    // `params_fingerprint` of the params of the proofs, published in the create payloads and
+    // checked by the synchronizer.  None until the setup is available.
+    params_fingerprint: Option<Hash>,
+    lists: Vec<ListStatus>,
+}
+
+// GET /status
+pub async fn handler_status_get(ctx: Arc<Context>) -> Result<impl warp::Reply, warp::Rejection> {
+    let lists = db::get_rev_lags(&ctx.db_pool)
+        .await?
+        .into_iter()
+        .map(|lag| ListStatus {
+            id: lag.id,
+            num: lag.num,
+            rev_num: lag.rev_num,
+            rev_lag: lag.num - lag.rev_num,
+        })
+        .collect();
+    let progress = *ctx.setup_progress.borrow();
+    let status = if progress.ready {
+        "ready"
+    } else {
+        "initializing"
+    };
+    let params_fingerprint = ctx
+        .pod_config()
+        .ok()
+        .map(|pod_config| params_fingerprint(&pod_config.params));
+    Ok(warp::reply::json(&StatusView {
+        status: status.to_string(),
+        progress,
+        params_fingerprint,
+        lists,
+    }))
 }
 
 // GET /reverse_membership_list_pod/{id}
@@ -41,92 +405,523 @@ pub async fn handler_reverse_membership_list_pod_get(
     id: i64,
     ctx: Arc<Context>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let num = db::get_rev_membership_list(&ctx.db_pool, id)
-        .await
-        .map_err(|e| CustomError(e.to_string()))?
-        .num;
-    let rev_name = rev_membership_list_pod_file_name(id, num);
-    let reverse_index_pod = load_pod(Path::new(&ctx.cfg.pods_path), &rev_name)
-        .map_err(|e| CustomError(e.to_string()))?;
+    let num = db::get_rev_membership_list(&ctx.db_pool, id).await?.num;
+    let reverse_index_pod = ctx
+        .load_pod(PodKey::rev_membership_list(id, num))
+        .map_err(Error::Internal)?;
     Ok(warp::reply::json(&reverse_index_pod))
 }
 
 #[derive(Serialize, Deserialize)]
-pub struct QueueResp {
-    req_id: Uuid,
+pub struct PodInfo {
+    name: String,
+    // error of the verification, none if the pod verifies
+    verify_error: Option<String>,
+    // hash of the params the pod was proven with, in their JSON form
+    params_digest: Hash,
+    // whether the params and the vd set are the ones of this server
+    params_match: bool,
+    vd_set_root: Hash,
+    vd_set_match: bool,
+    pub_statements: Vec<String>,
+}
+
+// GET /admin/pod/{name}
+pub async fn handler_admin_pod_get(
+    name: String,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // names are the file names of the pod store, which keeps the path inside the pods directory
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(Error::NotFound(format!("pod {}", name)).into());
+    }
+    let pod = disk::load_pod(Path::new(&ctx.cfg.pods_path), &name).map_err(|e| match e
+        .downcast_ref::<io::Error>()
+    {
+        Some(io_err) if io_err.kind() == io::ErrorKind::NotFound => {
+            Error::NotFound(format!("pod {}", name))
+        }
+        _ => Error::Internal(e),
+    })?;
+
+    let params = pod.pod.params();
+    let vd_set_root = pod.pod.vd_set().root();
+    let pod_config = ctx.pod_config()?;
+    let batch_names = BatchNames::new(
+        &pod_config.state_predicates,
+        &pod_config.rev_predicates,
+        &pod_config.kv_predicates,
+    );
+    let info = PodInfo {
+        verify_error: pod.pod.verify().err().map(|e| e.to_string()),
+        params_digest: params_fingerprint(params),
+        params_match: *params == pod_config.params,
+        vd_set_match: vd_set_root == pod_config.vd_set.root(),
+        vd_set_root,
+        pub_statements: pod
+            .pod
+            .pub_statements()
+            .iter()
+            .map(|st| batch_names.render(st))
+            .collect(),
+        name,
+    };
+    Ok(warp::reply::json(&info))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PredicateView {
+    index: usize,
+    name: String,
+}
+
+/// Custom predicate batch of the app pods, with the podlang source it's parsed from
+#[derive(Serialize, Deserialize)]
+pub struct PredicateBatchView {
+    name: String,
+    // hex encoded, as in the `use .. from 0x{id}` of the sources of the batches that use it
+    id: String,
+    predicates: Vec<PredicateView>,
+    source: String,
+}
+
+// GET /predicates
+pub async fn handler_predicates_get(
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let pod_config = ctx.pod_config()?;
+    let batches: Vec<_> = pod_config
+        .batches
+        .iter()
+        .zip(&pod_config.sources)
+        .map(|(batch, (name, source))| PredicateBatchView {
+            name: name.clone(),
+            id: batch.id().encode_hex(),
+            predicates: batch
+                .predicates()
+                .iter()
+                .enumerate()
+                .map(|(index, predicate)| PredicateView {
+                    index,
+                    name: predicate.name.clone(),
+                })
+                .collect(),
+            source: source.clone(),
+        })
+        .collect();
+    Ok(warp::reply::json(&batches))
+}
+
+// GET /snapshot/{id}
+pub async fn handler_snapshot_get(
+    id: i64,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let snapshot = snapshot::export(&ctx, id).await?;
+    Ok(warp::reply::json(&snapshot))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotResp {
+    id: i64,
+    num: i64,
+}
+
+// POST /snapshot
+pub async fn handler_snapshot_post(
+    snapshot: ListSnapshot,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let resp = SnapshotResp {
+        id: snapshot.id,
+        num: snapshot.num,
+    };
+    snapshot::import(&ctx, snapshot).await?;
+    Ok(warp::reply::json(&resp))
+}
+
+// GET /admin/export/{id}
+pub async fn handler_admin_export_get(
+    id: i64,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let archive = archive::export(ctx, id).await?;
+    let mut res = warp::reply::Response::new(Body::wrap_stream(archive));
+    res.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/x-tar"));
+    Ok(res)
+}
+
+// POST /admin/import
+pub async fn handler_admin_import_post<S, B>(
+    body: S,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection>
+where
+    S: futures_util::Stream<Item = Result<B, warp::Error>> + Send + 'static,
+    B: Buf + Send + 'static,
+{
+    let (id, num) = archive::import(ctx, body).await?;
+    Ok(warp::reply::json(&SnapshotResp { id, num }))
+}
+
+#[derive(Deserialize)]
+pub struct CreateQuery {
+    // `?kind=kv` creates a list whose groups map their users to a value
+    #[serde(default)]
+    kind: db::ListKind,
 }
 
 // POST /membership_list
 pub async fn handler_membership_list_create(
+    query: CreateQuery,
     ctx: Arc<Context>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let req_id = Uuid::now_v7();
-    ctx.queue_state
-        .write()
-        .await
-        .insert(req_id, queue::State::Create(queue::StateCreate::Pending));
-    ctx.queue_tx
-        .send(queue::Request::Create { req_id })
-        .await
-        .map_err(|e| CustomError(e.to_string()))?;
+    let req_id = queue::enqueue(
+        &ctx,
+        queue::Request::Create {
+            req_id: Uuid::now_v7(),
+            kind: query.kind,
+        },
+    )
+    .await?;
     Ok(warp::reply::json(&QueueResp { req_id }))
 }
 
+/// Checks that the group and user of `op` have at most `max_len` characters.  Their charset, and
+/// the length bound of the app, are checked when the op is deserialized.
+fn check_op_names(op: &Op, max_len: usize) -> Result<(), Error> {
+    let names = match op {
+        Op::Init => vec![],
+        Op::Add { group, user }
+        | Op::Del { group, user }
+        | Op::AddKv { group, user, .. }
+        | Op::DelKv { group, user } => {
+            vec![("group", group.as_str()), ("user", user.as_str())]
+        }
+        Op::AddGroup { group } | Op::DropGroup { group } => vec![("group", group.as_str())],
+    };
+    match names.into_iter().find(|(_, name)| name.len() > max_len) {
+        Some((kind, name)) => Err(Error::InvalidOp(format!(
+            "{} {:?} has more than {} characters",
+            kind, name, max_len
+        ))),
+        None => Ok(()),
+    }
+}
+
 // POST /membership_list/{id}
 pub async fn handler_membership_list_update(
     id: i64,
     op: Op,
     ctx: Arc<Context>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let req_id = Uuid::now_v7();
-    ctx.queue_state
-        .write()
-        .await
-        .insert(req_id, queue::State::Update(queue::StateUpdate::Pending));
-    ctx.queue_tx
-        .send(queue::Request::Update { req_id, id, op })
-        .await
-        .map_err(|e| CustomError(e.to_string()))?;
+    check_op_names(&op, ctx.cfg.name_max_len)?;
+    // reject redundant ops and flagged lists right away instead of after waiting in the queue
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    let kind = db::get_membership_list_kind(&ctx.db_pool, id).await?;
+    audit::check_not_flagged(&ctx, id).await?;
+    queue::check_op(&membership_list, kind, &op)?;
+
+    let req_id = queue::enqueue(
+        &ctx,
+        queue::Request::Update {
+            req_id: Uuid::now_v7(),
+            id,
+            op,
+        },
+    )
+    .await?;
+    Ok(warp::reply::json(&QueueResp { req_id }))
+}
+
+// POST /membership_list/{id}/rev/{num}/retry
+pub async fn handler_update_rev_retry(
+    id: i64,
+    num: i64,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let req_id = queue::retry_update_rev(&ctx, id, num).await?;
     Ok(warp::reply::json(&QueueResp { req_id }))
 }
 
-// GET /user/{id}/{user}
+/// Rejects the users that can't be in a membership list before their query is enqueued.
+fn check_user(user: String) -> Result<String, Error> {
+    UserId::new(user)
+        .map(String::from)
+        .map_err(|e| Error::InvalidUser(format!("{:#}", e)))
+}
+
+/// Enqueues the query `req`, or handles it right away on a mirror, which doesn't run the queue.
+async fn submit_query(ctx: &Arc<Context>, req: queue::Request) -> Result<Uuid, Error> {
+    match ctx.cfg.server_mode {
+        ServerMode::Primary => queue::enqueue(ctx, req).await,
+        ServerMode::Mirror => queue::spawn_query(ctx.clone(), req).await,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserQuery {
+    // num of the update whose state is queried, the latest one if unset
+    at: Option<i64>,
+}
+
+// GET /user/{id}/{user}?at={num}
 // TODO: Maybe allow types other than strings?
 pub async fn handler_user_get(
     id: i64,
     user: String, // user to insert
+    query: UserQuery,
     ctx: Arc<Context>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let req_id = Uuid::now_v7();
-    ctx.queue_state.write().await.insert(
-        req_id,
-        queue::State::Query(Box::new(queue::StateQuery::Pending)),
-    );
-    ctx.queue_tx
-        .send(queue::Request::Query { req_id, id, user })
-        .await
-        .map_err(|e| CustomError(e.to_string()))?;
+    let user = check_user(user)?;
+    // a num without history is rejected before the query is enqueued
+    if let Some(at) = query.at {
+        queue::history_at(&ctx, id, at).await?;
+    }
+    let req_id = submit_query(
+        &ctx,
+        queue::Request::Query {
+            req_id: Uuid::now_v7(),
+            id,
+            user,
+            at: query.at,
+        },
+    )
+    .await?;
+    Ok(warp::reply::json(&QueueResp { req_id }))
+}
+
+// lists queried per page by `GET /user/{user}`, default and max
+const USER_LISTS_PAGE_LIMIT: i64 = 20;
+const USER_LISTS_PAGE_LIMIT_MAX: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    // only the lists with a greater id, from the first list if unset
+    after_id: Option<i64>,
+    limit: Option<i64>,
+}
+
+// GET /user/{user}?after_id={id}&limit={n}
+pub async fn handler_user_all_get(
+    user: String,
+    query: PageQuery,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = check_user(user)?;
+    let limit = query
+        .limit
+        .unwrap_or(USER_LISTS_PAGE_LIMIT)
+        .clamp(1, USER_LISTS_PAGE_LIMIT_MAX);
+    let req_id = submit_query(
+        &ctx,
+        queue::Request::QueryAll {
+            req_id: Uuid::now_v7(),
+            user,
+            after_id: query.after_id.unwrap_or(0),
+            limit,
+        },
+    )
+    .await?;
+    Ok(warp::reply::json(&QueueResp { req_id }))
+}
+
+// GET /membership_pod/{id}/{group}/{user}
+pub async fn handler_membership_pod_get(
+    id: i64,
+    group: Group,
+    user: String,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let user = check_user(user)?;
+    let req_id = queue::enqueue(
+        &ctx,
+        queue::Request::ProveMembership {
+            req_id: Uuid::now_v7(),
+            id,
+            user,
+            group,
+        },
+    )
+    .await?;
+    Ok(warp::reply::json(&QueueResp { req_id }))
+}
+
+// POST /admin/prune_pods
+pub async fn handler_prune_pods(ctx: Arc<Context>) -> Result<impl warp::Reply, warp::Rejection> {
+    let req_id = queue::enqueue(
+        &ctx,
+        queue::Request::PrunePods {
+            req_id: Uuid::now_v7(),
+        },
+    )
+    .await?;
     Ok(warp::reply::json(&QueueResp { req_id }))
 }
 
+// POST /admin/audit
+pub async fn handler_audit_post(ctx: Arc<Context>) -> Result<impl warp::Reply, warp::Rejection> {
+    let reports = audit::audit(&ctx).await?;
+    Ok(warp::reply::json(&reports))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AuditClearResp {
+    id: i64,
+    // the flag that was cleared, none if the list wasn't flagged
+    cleared: Option<AuditStatus>,
+}
+
+// POST /admin/audit/{id}/clear
+pub async fn handler_audit_clear_post(
+    id: i64,
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let cleared = db::get_audit_flag(&ctx.db_pool, id).await?;
+    db::set_audit_flag(&ctx.db_pool, id, None).await?;
+    if let Some(status) = cleared {
+        info!(
+            id,
+            "cleared the audit flag {} of the membership list", status
+        );
+    }
+    Ok(warp::reply::json(&AuditClearResp { id, cleared }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QueueEntry {
+    req_id: Uuid,
+    state: queue::State,
+    // seconds since the request was queued, from the timestamp of its v7 req_id
+    age_secs: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct QueueView {
+    // requests in the queue channel, not yet taken by the queue loop
+    backlog: usize,
+    capacity: usize,
+    // updates buffered behind the update of their list, see `queue::PendingUpdates`
+    buffered_updates: usize,
+    // requests neither complete nor failed, oldest first
+    requests: Vec<QueueEntry>,
+}
+
+// GET /admin/queue
+pub async fn handler_admin_queue_get(
+    ctx: Arc<Context>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut requests: Vec<QueueEntry> = ctx
+        .queue_state
+        .read()
+        .await
+        .iter()
+        .filter(|(_, state)| !state.is_terminal())
+        .map(|(req_id, state)| QueueEntry {
+            req_id: *req_id,
+            state: state.clone(),
+            age_secs: req_id.get_timestamp().map(|timestamp| {
+                let (secs, _) = timestamp.to_unix();
+                now.as_secs().saturating_sub(secs)
+            }),
+        })
+        .collect();
+    // the v7 req_ids sort by time
+    requests.sort_by_key(|entry| entry.req_id);
+    Ok(warp::reply::json(&QueueView {
+        backlog: ctx.queue_tx.max_capacity() - ctx.queue_tx.capacity(),
+        capacity: ctx.queue_tx.max_capacity(),
+        buffered_updates: ctx.pending_updates.buffered(),
+        requests,
+    }))
+}
+
+// GET /metrics
+pub async fn handler_metrics_get(ctx: Arc<Context>) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::with_header(
+        ctx.metrics.render(),
+        "content-type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
+// converts rejections into a status code and a JSON `ErrorBody` of an `ErrorInfo`
+pub async fn handle_rejection(err: Rejection) -> Result<impl warp::Reply, Infallible> {
+    let info = if let Some(err) = err.find::<Error>() {
+        ErrorInfo::from(err)
+    } else if let Some(info) = rejection::find_rejection(&err) {
+        ErrorInfo {
+            kind: ErrorKind::from(info.kind),
+            code: None,
+            message: info.message,
+        }
+    } else {
+        ErrorInfo {
+            kind: ErrorKind::Internal,
+            code: None,
+            message: format!("{:?}", err),
+        }
+    };
+    let status = info.kind.status();
+    let mut res = warp::reply::with_status(
+        warp::reply::json(&rejection::ErrorBody { error: info }),
+        status,
+    )
+    .into_response();
+    if let Some(Error::RateLimited(retry_after) | Error::QueueFull(retry_after)) =
+        err.find::<Error>()
+    {
+        res.headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(*retry_after));
+    }
+    Ok(res)
+}
+
 // ROUTES:
 
 // build the routes
 pub fn routes(
     ctx: Arc<Context>,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+) -> impl Filter<Extract = impl warp::Reply, Error = Infallible> + Clone {
     membership_list_get(ctx.clone())
+        .or(membership_list_costs_get(ctx.clone()))
+        .or(membership_list_diff_get(ctx.clone()))
+        .or(membership_list_op_proof_get(ctx.clone()))
         .or(reverse_membership_list_pod_get(ctx.clone()))
         .or(request_get(ctx.clone()))
         .or(membership_list_create(ctx.clone()))
         .or(membership_list_update(ctx.clone()))
+        .or(update_rev_retry(ctx.clone()))
         .or(user_get(ctx.clone()))
+        .or(user_all_get(ctx.clone()))
+        .or(membership_pod_get(ctx.clone()))
+        .or(prune_pods(ctx.clone()))
+        .or(audit_post(ctx.clone()))
+        .or(audit_clear_post(ctx.clone()))
+        .or(admin_pod_get(ctx.clone()))
+        .or(admin_queue_get(ctx.clone()))
+        .or(predicates_get(ctx.clone()))
+        .or(snapshot_get(ctx.clone()))
+        .or(snapshot_post(ctx.clone()))
+        .or(admin_export_get(ctx.clone()))
+        .or(admin_import_post(ctx.clone()))
+        .or(status_get(ctx.clone()))
+        .or(metrics_get(ctx.clone()))
+        .recover(handle_rejection)
 }
 fn request_get(
     ctx: Arc<Context>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("request" / Uuid)
         .and(warp::get())
+        .and(limits::rate_limit(ctx.rate_limiter.clone()))
         .and(with_ctx(ctx))
         .and_then(handler_request_get)
 }
@@ -135,14 +930,42 @@ fn membership_list_get(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("membership_list" / i64)
         .and(warp::get())
+        .and(warp::query::<FormatQuery>())
         .and(with_ctx(ctx))
         .and_then(handler_membership_list_get)
 }
+fn membership_list_costs_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("membership_list" / i64 / "costs")
+        .and(warp::get())
+        .and(with_ctx(ctx))
+        .and_then(handler_membership_list_costs_get)
+}
+fn membership_list_diff_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("membership_list" / i64 / "diff")
+        .and(warp::get())
+        .and(warp::query::<DiffQuery>())
+        .and(with_ctx(ctx))
+        .and_then(handler_membership_list_diff_get)
+}
+fn membership_list_op_proof_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("membership_list" / i64 / "op_proof" / i64)
+        .and(warp::get())
+        .and(limits::rate_limit(ctx.rate_limiter.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_membership_list_op_proof_get)
+}
 fn reverse_membership_list_pod_get(
     ctx: Arc<Context>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("reverse_membership_list_pod" / i64)
         .and(warp::get())
+        .and(primary_only(ctx.clone()))
         .and(with_ctx(ctx))
         .and_then(handler_reverse_membership_list_pod_get)
 }
@@ -152,90 +975,483 @@ fn membership_list_create(
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("membership_list")
         .and(warp::post())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(ready(ctx.clone()))
+        .and(warp::query::<CreateQuery>())
         .and(with_ctx(ctx))
         .and_then(handler_membership_list_create)
 }
+
+/// Body of an op.  Unlike `warp::body::json`, a body that isn't an op is rejected with the error of
+/// its deserialization, e.g. "unknown variant `purge`, expected one of ..".
+fn op_body() -> impl Filter<Extract = (Op,), Error = warp::Rejection> + Clone {
+    warp::body::bytes().and_then(|body: Bytes| async move {
+        serde_json::from_slice::<Op>(&body)
+            .map_err(|err| warp::reject::custom(Error::MalformedOp(err.to_string())))
+    })
+}
+
 fn membership_list_update(
     ctx: Arc<Context>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("membership_list" / i64)
         .and(warp::post())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(ready(ctx.clone()))
         .and(warp::body::content_length_limit(1024 * 16)) // max 16kb
-        .and(warp::body::json())
+        .and(op_body())
         .and(with_ctx(ctx))
         .and_then(handler_membership_list_update)
 }
+fn update_rev_retry(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("membership_list" / i64 / "rev" / i64 / "retry")
+        .and(warp::post())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(ready(ctx.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_update_rev_retry)
+}
 
 fn user_get(
     ctx: Arc<Context>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
     warp::path!("user" / i64 / String)
         .and(warp::get())
+        .and(limits::rate_limit(ctx.rate_limiter.clone()))
+        .and(warp::query::<UserQuery>())
         .and(with_ctx(ctx))
         .and_then(handler_user_get)
 }
 
-fn with_ctx(
+fn user_all_get(
     ctx: Arc<Context>,
-) -> impl Filter<Extract = (Arc<Context>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || ctx.clone())
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("user" / String)
+        .and(warp::get())
+        .and(limits::rate_limit(ctx.rate_limiter.clone()))
+        .and(warp::query::<PageQuery>())
+        .and(with_ctx(ctx))
+        .and_then(handler_user_all_get)
 }
 
-#[cfg(test)]
-mod tests {
-    use app::Group;
-    use common::shrink::ShrunkMainPodSetup;
-    use pod2::{
-        backends::plonky2::basetypes::DEFAULT_VD_SET,
-        frontend::MainPod,
-        middleware::{Params, Value},
-    };
-    use tokio::{
-        sync::mpsc,
-        task,
-        time::{Duration, sleep},
-    };
-    use warp::{Rejection, Reply, http::StatusCode};
+fn membership_pod_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("membership_pod" / i64 / Group / String)
+        .and(warp::get())
+        .and(primary_only(ctx.clone()))
+        .and(limits::rate_limit(ctx.rate_limiter.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_membership_pod_get)
+}
 
-    use super::*;
-    use crate::{Config, PodConfig};
+fn prune_pods(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "prune_pods")
+        .and(warp::post())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(ready(ctx.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_prune_pods)
+}
 
-    async fn helper_membership_list_update(
-        api: &(impl Filter<Extract = impl Reply, Error = Rejection> + Clone + 'static),
-        op: Op,
-    ) {
-        let res = warp::test::request()
-            .method("POST")
-            .path("/membership_list/1")
-            .json(&op)
-            .reply(api)
-            .await;
-        assert_eq!(res.status(), StatusCode::OK);
+fn audit_post(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "audit")
+        .and(warp::post())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(ready(ctx.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_audit_post)
+}
 
-        let resp: QueueResp = serde_json::from_slice(res.body()).expect("");
-        loop {
-            let res = warp::test::request()
-                .method("GET")
-                .path(&format!("/request/{}", resp.req_id))
-                .reply(api)
+fn audit_clear_post(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "audit" / i64 / "clear")
+        .and(warp::post())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_audit_clear_post)
+}
+
+fn admin_pod_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "pod" / String)
+        .and(warp::get())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_admin_pod_get)
+}
+
+fn admin_queue_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "queue")
+        .and(warp::get())
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_admin_queue_get)
+}
+
+fn snapshot_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("snapshot" / i64)
+        .and(warp::get())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_snapshot_get)
+}
+
+fn snapshot_post(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("snapshot")
+        .and(warp::post())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(ready(ctx.clone()))
+        .and(warp::body::content_length_limit(1024 * 1024 * 64)) // max 64mb, mostly pods
+        .and(warp::body::json())
+        .and(with_ctx(ctx))
+        .and_then(handler_snapshot_post)
+}
+
+fn admin_export_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "export" / i64)
+        .and(warp::get())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_admin_export_get)
+}
+
+fn admin_import_post(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("admin" / "import")
+        .and(warp::post())
+        .and(primary_only(ctx.clone()))
+        .and(limits::bearer_auth(ctx.cfg.auth_token.clone()))
+        .and(ready(ctx.clone()))
+        // streamed to disk, the size of the archive isn't limited
+        .and(warp::body::stream())
+        .and(with_ctx(ctx))
+        .and_then(handler_admin_import_post)
+}
+
+fn status_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("status")
+        .and(warp::get())
+        .and(limits::rate_limit(ctx.rate_limiter.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_status_get)
+}
+
+fn predicates_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("predicates")
+        .and(warp::get())
+        .and(limits::rate_limit(ctx.rate_limiter.clone()))
+        .and(with_ctx(ctx))
+        .and_then(handler_predicates_get)
+}
+
+fn metrics_get(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    warp::path!("metrics")
+        .and(warp::get())
+        .and(with_ctx(ctx))
+        .and_then(handler_metrics_get)
+}
+
+/// Rejects with `Error::ReadOnlyMirror` on a mirror, for the endpoints that update the lists or
+/// need their pods, which only the primary has.
+fn primary_only(ctx: Arc<Context>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let res = match ctx.cfg.server_mode {
+                ServerMode::Primary => Ok(()),
+                ServerMode::Mirror => Err(warp::reject::custom(Error::ReadOnlyMirror(
+                    ctx.cfg.primary_url.clone().unwrap_or_default(),
+                ))),
+            };
+            async move { res }
+        })
+        .untuple_one()
+}
+
+/// Rejects with `Error::Initializing` until the setup is available, for the endpoints that build
+/// pods.
+fn ready(ctx: Arc<Context>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::any()
+        .and_then(move || {
+            let ready = ctx.setup.get().is_some();
+            async move {
+                if ready {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Error::Initializing))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn with_ctx(
+    ctx: Arc<Context>,
+) -> impl Filter<Extract = (Arc<Context>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || ctx.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use alloy::primitives::{B256, TxHash};
+    use app::{Group, UserId, dict};
+    use common::{api::TxCostInfo, shrink::ShrunkMainPodSetup};
+    use pod2::{
+        backends::plonky2::basetypes::DEFAULT_VD_SET,
+        frontend::MainPod,
+        middleware::{Params, RawValue, Value, containers::Set},
+    };
+    use tokio::{
+        sync::mpsc,
+        task,
+        time::{Duration, sleep},
+    };
+    use tokio_util::sync::CancellationToken;
+    use warp::{Reply, http::StatusCode};
+
+    use super::*;
+    use crate::{Config, PodConfig, Setup};
+
+    fn depth() -> usize {
+        Params::default().max_depth_mt_containers
+    }
+
+    fn set(members: &[&str]) -> Value {
+        Value::from(Set::new(depth(), members.iter().map(|m| Value::from(*m)).collect()).unwrap())
+    }
+
+    /// Set of the groups of a query response, whose commitment is the value of its proof
+    fn group_set(groups: &[Group]) -> Value {
+        set(&groups.iter().map(Group::as_str).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_dict_of_string_sets() -> anyhow::Result<()> {
+        let state = dict!(depth(), {
+            "red" => set(&["bob", "alice"]),
+            "green" => set(&[]),
+            "blue" => set(&["bob"]),
+            "epoch" => 3
+        });
+        let view = AdStateView::try_from(&db::AdState {
+            id: 1,
+            num: 3,
+            state: db::DictContainerSql(state.clone()),
+            created_at: 10,
+            updated_at: 20,
+        })?;
+        assert_eq!((view.num, view.epoch), (3, Some(3)));
+        assert_eq!((view.created_at, view.updated_at), (10, 20));
+        assert_eq!(view.commitment, state.commitment().encode_hex::<String>());
+        assert_eq!(
+            serde_json::to_value(&view.state)?,
+            serde_json::json!({"red": ["alice", "bob"], "green": [], "blue": ["bob"]})
+        );
+
+        // uninitialized membership list
+        let empty = Dictionary::new(depth(), HashMap::new()).unwrap();
+        assert!(dict_of_string_sets(&empty)?.is_empty());
+
+        // non-string members
+        let state = dict!(depth(), {
+            "red" => Value::from(Set::new(depth(), HashSet::from([Value::from(1)])).unwrap())
+        });
+        assert!(dict_of_string_sets(&state).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_ops() {
+        let op = |add: bool, group: Group, user: &str| {
+            let user = UserId::new(user).unwrap();
+            match add {
+                true => Op::Add { group, user },
+                false => Op::Del { group, user },
+            }
+        };
+        let ops = [
+            Op::Init,
+            op(true, Group::RED, "bob"),
+            op(true, Group::RED, "alice"),
+            op(true, Group::BLUE, "alice"),
+            op(false, Group::RED, "alice"),
+            op(false, Group::GREEN, "carol"),
+        ];
+        let diff = diff_ops(&ops);
+        let group_diff = |added: &[&str], removed: &[&str]| GroupDiff {
+            added: added.iter().map(|u| u.to_string()).collect(),
+            removed: removed.iter().map(|u| u.to_string()).collect(),
+        };
+        // alice was added to and removed from red, which is a net zero change
+        assert_eq!(
+            diff,
+            BTreeMap::from([
+                ("red".to_string(), group_diff(&["bob"], &[])),
+                ("blue".to_string(), group_diff(&["alice"], &[])),
+                ("green".to_string(), group_diff(&[], &["carol"])),
+            ])
+        );
+        // a del followed by an add of the same user cancel out too
+        let ops = [
+            op(false, Group::RED, "alice"),
+            op(true, Group::RED, "alice"),
+        ];
+        assert!(diff_ops(&ops).is_empty());
+        assert!(diff_ops(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_handle_rejection_internal() -> anyhow::Result<()> {
+        let rejection = warp::reject::custom(Error::Internal(anyhow!("boom")));
+        let res = handle_rejection(rejection).await?.into_response();
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_rejection_body() -> anyhow::Result<()> {
+        // the body filters of the update and snapshot endpoints, with a smaller limit
+        let op = warp::path!("op")
+            .and(warp::body::content_length_limit(64))
+            .and(op_body())
+            .map(|_: Op| "ok");
+        let snapshot = warp::path!("snapshot")
+            .and(warp::body::json())
+            .map(|_: ListSnapshot| "ok");
+        let filter = op.or(snapshot).recover(handle_rejection);
+        let post = async |path: &str, body: &str| -> anyhow::Result<(StatusCode, ErrorInfo)> {
+            let res = warp::test::request()
+                .method("POST")
+                .path(path)
+                .body(body)
+                .reply(&filter)
                 .await;
-            assert_eq!(res.status(), StatusCode::OK);
-            let resp: queue::State = serde_json::from_slice(res.body()).expect("");
-            match resp {
-                queue::State::Update(state_update) => match state_update {
-                    queue::StateUpdate::Complete { tx_hash } => {
-                        // should contain the mocked tx hash
-                        assert_eq!(
-                            tx_hash.to_string(),
-                            "0x0000000000000000000000000000000000000000000000000000000000000000"
-                        ); // mock tx hash
-                        break;
-                    }
-                    queue::StateUpdate::Error(e) => panic!("StateUpdate::Error: {}", e),
-                    _ => sleep(Duration::from_millis(100)).await,
-                },
-                state => panic!("{:?} != StateUpdate::Complete", state),
+            let body: rejection::ErrorBody<ErrorInfo> = serde_json::from_slice(res.body())?;
+            Ok((res.status(), body.error))
+        };
+
+        let (status, info) = post("/op", &format!("\"{}\"", "a".repeat(64))).await?;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(
+            info,
+            ErrorInfo {
+                kind: ErrorKind::PayloadTooLarge,
+                code: None,
+                message: "request body is over the size limit of the endpoint".to_string(),
+            }
+        );
+        let (status, info) = post("/op", "{\"Add\": ").await?;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(info.kind, ErrorKind::MalformedOp);
+        let (status, info) = post("/snapshot", "{\"num\": ").await?;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(info.kind, ErrorKind::InvalidBody);
+        assert!(info.message.contains("EOF"), "{}", info.message);
+        Ok(())
+    }
+
+    async fn helper_membership_list_update(client: &ad_client::Client, op: Op) {
+        let updated = client
+            .update_list(1, &op)
+            .await
+            .unwrap_or_else(|e| panic!("update {:?}: {}", op, e));
+        // should contain the mocked tx hash
+        assert_eq!(updated.tx_hash, TxHash::ZERO);
+        assert_eq!(updated.cost, ad_client::TxCostInfo::default());
+    }
+
+    /// Context of a test server that proves the app pods with `params`, returned once its setup is
+    /// built.  The setup is built on a blocking thread, whose errors are returned.
+    async fn test_context(
+        cfg: Config,
+        db_pool: sqlx::SqlitePool,
+        shutdown: CancellationToken,
+        params: Params,
+    ) -> anyhow::Result<(Arc<Context>, mpsc::Receiver<queue::Request>)> {
+        let (queue_tx, queue_rx) = mpsc::channel::<queue::Request>(cfg.queue_len);
+        let ctx = Arc::new(Context::new(cfg, db_pool, None, queue_tx, shutdown)?);
+        task::spawn_blocking({
+            let ctx = ctx.clone();
+            move || -> anyhow::Result<()> {
+                // initialize pod data
+                println!("Prebuilding circuits to calculate vd_set...");
+                let vd_set = common::params::vd_set(&params)?;
+                println!("vd_set calculation complete");
+                let shrunk_main_pod_build =
+                    ShrunkMainPodSetup::new(&Params::default(), false).build()?;
+                ctx.set_setup(Setup {
+                    pod_config: PodConfig::new(params, vd_set),
+                    shrunk_main_pod_build,
+                });
+                Ok(())
+            }
+        })
+        .await??;
+        Ok((ctx, queue_rx))
+    }
+
+    /// Waits until the setup of the context is available.
+    async fn wait_ready(ctx: &Context) {
+        ctx.setup_progress
+            .subscribe()
+            .wait_for(|progress| progress.ready)
+            .await
+            .expect("setup progress sender is in the context");
+    }
+
+    /// Waits until the state of the request `req_id` satisfies `done` and returns it.
+    async fn wait_state(
+        ctx: &Context,
+        req_id: Uuid,
+        done: impl Fn(&queue::State) -> bool,
+    ) -> queue::State {
+        loop {
+            if let Some(state) = ctx
+                .queue_state
+                .read()
+                .await
+                .get(&req_id)
+                .filter(|s| done(s))
+            {
+                return state.clone();
             }
+            sleep(Duration::from_millis(100)).await;
         }
     }
 
@@ -251,7 +1467,18 @@ mod tests {
 
         common::load_dotenv()?;
         let mut cfg = Config::from_env()?;
-        cfg.priv_key = "".to_string();
+        cfg.priv_keys = Vec::new();
+        // the test polls the request status in a loop
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        // prune down to the last pod of every list
+        cfg.pods_retain_last_n = 1;
+        // the server the snapshot of the list is restored on
+        let mut restore_cfg = cfg.clone();
+        restore_cfg.pods_path = std::env::temp_dir()
+            .join(format!("ad-server-restore-test-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
 
         let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
             .min_connections(1) // db config for tests
@@ -263,93 +1490,177 @@ mod tests {
             .expect("cannot connect to db");
         db::init_db(&db_pool).await?;
 
-        // initialize pod data
-        let params = Params::default();
-        println!("Prebuilding circuits to calculate vd_set...");
-        let vd_set = &*DEFAULT_VD_SET;
-        println!("vd_set calculation complete");
-        let (state_predicates, rev_predicates) = app::build_predicates(&params);
-        let shrunk_main_pod_build = ShrunkMainPodSetup::new(&params).build()?;
-        let pod_config = PodConfig {
-            params,
-            vd_set: vd_set.clone(),
-            state_predicates,
-            rev_predicates,
-        };
-
-        let (queue_tx, queue_rx) = mpsc::channel::<queue::Request>(8);
-        let ctx = Arc::new(Context::new(
-            cfg,
-            db_pool,
-            pod_config,
-            shrunk_main_pod_build,
-            queue_tx,
-        ));
+        let (ctx, queue_rx) =
+            test_context(cfg, db_pool, CancellationToken::new(), Params::default()).await?;
 
         let api = routes(ctx.clone());
-        task::spawn(async move {
-            queue::handle_loop(ctx.clone(), queue_rx).await;
+        {
+            let ctx = ctx.clone();
+            task::spawn(async move {
+                queue::handle_loop(ctx, queue_rx).await;
+            });
+        }
+        // the requests that go through the queue are sent with the client over a real server
+        let (addr, server) = warp::serve(api.clone()).bind_ephemeral(([127, 0, 0, 1], 0));
+        task::spawn(server);
+        let client = ad_client::Client::new(ad_client::Config {
+            poll_interval: Duration::from_millis(100),
+            ..ad_client::Config::new(format!("http://{}", addr))
         });
 
         // create new membership_list
+        let created = client.create_list().await?;
+        assert_eq!(created.id, 1); // membership_list's id always starts at 1
+        assert_eq!(created.tx_hash, TxHash::ZERO); // mock tx hash
+
+        // unknown membership_list
         let res = warp::test::request()
-            .method("POST")
-            .path("/membership_list")
+            .method("GET")
+            .path("/membership_list/42")
             .reply(&api)
             .await;
-        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let info = serde_json::from_slice::<rejection::ErrorBody<ErrorInfo>>(res.body())?.error;
+        assert_eq!(info.kind, ErrorKind::NotFound);
 
-        // let s = std::str::from_utf8(res.body()).expect("Invalid UTF-8");
-        // let received_id: i64 = s.parse()?;
-        let resp: QueueResp = serde_json::from_slice(res.body()).expect("");
-        loop {
+        // malformed ops, with the reason in the message
+        for (op, reason) in [
+            (
+                serde_json::json!({"add": {"group": "op.group", "user": "alice"}}),
+                "op.group",
+            ),
+            (
+                serde_json::json!({"purge": {"group": "red"}}),
+                "unknown variant `purge`",
+            ),
+            (
+                serde_json::json!({"add": {"group": "red"}}),
+                "missing field `user`",
+            ),
+        ] {
             let res = warp::test::request()
-                .method("GET")
-                .path(&format!("/request/{}", resp.req_id))
+                .method("POST")
+                .path("/membership_list/1")
+                .json(&op)
                 .reply(&api)
                 .await;
-            assert_eq!(res.status(), StatusCode::OK);
-            let resp: queue::State = serde_json::from_slice(res.body()).expect("");
-            match resp {
-                queue::State::Create(state_init) => match state_init {
-                    queue::StateCreate::Complete { id, tx_hash } => {
-                        assert_eq!(id, 1); // membership_list's id always starts at 1
-                        assert_eq!(
-                            // mock tx hash
-                            tx_hash.to_string(),
-                            "0x0000000000000000000000000000000000000000000000000000000000000000"
-                        );
-                        break;
-                    }
-                    queue::StateCreate::Error(e) => panic!("StateInit::Error: {}", e),
-                    _ => sleep(Duration::from_millis(100)).await,
-                },
-                state => panic!("{:?} != StateInit::Complete", state),
-            }
+            assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let info = serde_json::from_slice::<rejection::ErrorBody<ErrorInfo>>(res.body())?.error;
+            assert_eq!(info.kind, ErrorKind::MalformedOp);
+            assert!(info.message.contains(reason), "{}", info.message);
         }
+        // invalid users are rejected before anything is enqueued
+        let res = warp::test::request()
+            .method("POST")
+            .path("/membership_list/1")
+            .json(&serde_json::json!({"add": {"group": "red", "user": "op.user"}}))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!("/user/1/{}", "a".repeat(65)))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let info = serde_json::from_slice::<rejection::ErrorBody<ErrorInfo>>(res.body())?.error;
+        assert_eq!(info.kind, ErrorKind::InvalidRequest);
+        assert_eq!(ctx.queue_state.read().await.len(), 1);
 
         // init the membership_list
-        helper_membership_list_update(&api, Op::Init).await;
+        helper_membership_list_update(&client, Op::Init).await;
 
         // augment the membership_list
         // insert "alice" into "red" group
         helper_membership_list_update(
-            &api,
+            &client,
             Op::Add {
-                group: Group::Red,
-                user: "alice".to_string(),
+                group: Group::RED,
+                user: UserId::new("alice").unwrap(),
             },
         )
         .await;
 
-        // Query Alice's membership.
+        // Alice is already in the red group
+        let res = warp::test::request()
+            .method("POST")
+            .path("/membership_list/1")
+            .json(&Op::Add {
+                group: Group::RED,
+                user: UserId::new("alice").unwrap(),
+            })
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let info = serde_json::from_slice::<rejection::ErrorBody<ErrorInfo>>(res.body())?.error;
+        assert_eq!(info.kind, ErrorKind::Conflict);
+        // and Bob isn't in the blue group
+        let res = warp::test::request()
+            .method("POST")
+            .path("/membership_list/1")
+            .json(&Op::Del {
+                group: Group::BLUE,
+                user: UserId::new("bob").unwrap(),
+            })
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        // Query Alice's membership in the groups of membership_list 1
+        let user_groups = client.query_user(1, "alice").await?;
+        assert_eq!(
+            user_groups.proof.value,
+            group_set(&user_groups.groups).raw().encode_hex::<String>()
+        );
+        assert_eq!(
+            user_groups.proof.key,
+            Value::from("alice").raw().encode_hex::<String>()
+        );
+
+        // Query Alice's membership in all the lists
+        for (path, expected_ids) in [("/user/alice", vec![1]), ("/user/alice?after_id=1", vec![])] {
+            let res = warp::test::request()
+                .method("GET")
+                .path(path)
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let resp: QueueResp = serde_json::from_slice(res.body())?;
+            loop {
+                let res = warp::test::request()
+                    .method("GET")
+                    .path(&format!("/request/{}", resp.req_id))
+                    .reply(&api)
+                    .await;
+                let resp: queue::State = serde_json::from_slice(res.body())?;
+                match resp {
+                    queue::State::QueryAll(state) => match *state {
+                        queue::StateQueryAll::Complete {
+                            lists,
+                            next_after_id,
+                        } => {
+                            let ids: Vec<i64> = lists.iter().map(|l| l.id).collect();
+                            assert_eq!(ids, expected_ids);
+                            assert!(lists.iter().all(|l| l.proof.value
+                                == group_set(&l.groups).raw().encode_hex::<String>()));
+                            assert_eq!(next_after_id, None);
+                            break;
+                        }
+                        queue::StateQueryAll::Error(e) => panic!("StateQueryAll::Error: {:?}", e),
+                        _ => sleep(Duration::from_millis(100)).await,
+                    },
+                    state => panic!("{:?} != StateQueryAll::Complete", state),
+                }
+            }
+        }
+
+        // Get a membership pod for Alice
         let res = warp::test::request()
             .method("GET")
-            .path("/user/1/alice") // Query Alice's membership in the groups of membership_list 1
+            .path("/membership_pod/1/red/alice")
             .reply(&api)
             .await;
         assert_eq!(res.status(), StatusCode::OK);
-
         let resp: QueueResp = serde_json::from_slice(res.body()).expect("");
         loop {
             let res = warp::test::request()
@@ -357,19 +1668,38 @@ mod tests {
                 .path(&format!("/request/{}", resp.req_id))
                 .reply(&api)
                 .await;
-            assert_eq!(res.status(), StatusCode::OK);
             let resp: queue::State = serde_json::from_slice(res.body()).expect("");
             match resp {
-                queue::State::Query(state_query) => match *state_query {
-                    queue::StateQuery::Complete { groups, proof } => {
-                        assert_eq!(proof.value, Value::from(groups).raw());
-                        assert_eq!(proof.key, Value::from("alice").raw());
-                        break;
-                    }
-                    queue::StateQuery::Error(e) => panic!("StateQuery::Error: {}", e),
-                    _ => sleep(Duration::from_millis(100)).await,
-                },
-                state => panic!("{:?} != StateQuery::Complete", state),
+                queue::State::ProveMembership(queue::StateProveMembership::Complete {
+                    num,
+                    state,
+                    pod,
+                }) => {
+                    assert_eq!(num, 2);
+                    let membership_list = db::get_membership_list(&ctx.db_pool, 1).await?;
+                    assert_eq!(state, membership_list.state.0.commitment());
+                    pod.pod.verify()?;
+                    // the DictContains statement is over the state commitment
+                    let state_raw = Value::from(membership_list.state.0).raw();
+                    assert!(pod.pod.pub_statements().iter().any(|st| {
+                        st.args()
+                            .first()
+                            .and_then(|arg| arg.literal())
+                            .is_some_and(|v| v.raw() == state_raw)
+                    }));
+                    // stored in the pod store, like the state pods
+                    let pod_key = PodKey::membership(1, 2, "red", "alice");
+                    assert!(
+                        ctx.pod_store
+                            .entry(pod_key)
+                            .is_some_and(|entry| entry.confirmed)
+                    );
+                    break;
+                }
+                queue::State::ProveMembership(queue::StateProveMembership::Error(e)) => {
+                    panic!("StateProveMembership::Error: {:?}", e)
+                }
+                _ => sleep(Duration::from_millis(100)).await,
             }
         }
 
@@ -382,16 +1712,1695 @@ mod tests {
         assert_eq!(res.status(), StatusCode::OK);
         serde_json::from_slice::<MainPod>(res.body()).expect("Should be a MainPod.");
 
+        // Prune the pods, after which the next update and its rev pod must still go through
+        let res = warp::test::request()
+            .method("POST")
+            .path("/admin/prune_pods")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let resp: QueueResp = serde_json::from_slice(res.body()).expect("");
+        loop {
+            let res = warp::test::request()
+                .method("GET")
+                .path(&format!("/request/{}", resp.req_id))
+                .reply(&api)
+                .await;
+            let resp: queue::State = serde_json::from_slice(res.body()).expect("");
+            match resp {
+                queue::State::PrunePods(queue::StatePrunePods::Complete { .. }) => break,
+                queue::State::PrunePods(queue::StatePrunePods::Error(e)) => {
+                    panic!("StatePrunePods::Error: {:?}", e)
+                }
+                _ => sleep(Duration::from_millis(100)).await,
+            }
+        }
+
         // Delete Alice.
         helper_membership_list_update(
-            &api,
+            &client,
             Op::Del {
-                group: Group::Red,
-                user: "alice".to_string(),
+                group: Group::RED,
+                user: UserId::new("alice").unwrap(),
             },
         )
         .await;
+        // the rev pod of the delete is built from the rev pod kept by the pruning
+        while db::get_rev_membership_list(&ctx.db_pool, 1).await?.num < 3 {
+            sleep(Duration::from_millis(100)).await;
+        }
+        assert!(
+            ctx.pod_store
+                .entries()
+                .iter()
+                .any(|e| e.key == PodKey::rev_membership_list(1, 3))
+        );
+        // the reverse index caught up so there's nothing to reconcile
+        let res = warp::test::request()
+            .method("GET")
+            .path("/status")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let status: StatusView = serde_json::from_slice(res.body())?;
+        assert_eq!(
+            status
+                .lists
+                .iter()
+                .map(|l| (l.id, l.num, l.rev_lag))
+                .collect::<Vec<_>>(),
+            vec![(1, 3, 0)]
+        );
+        assert_eq!(queue::reconcile_rev(&ctx).await?, 0);
 
-        Ok(())
+        // alice was added at 2 and deleted at 3, so only the init is left from 0 to 3
+        for (from, to, changed) in [(0, 3, false), (1, 2, true), (1, 3, false), (2, 2, false)] {
+            let res = warp::test::request()
+                .method("GET")
+                .path(&format!("/membership_list/1/diff?from={}&to={}", from, to))
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let diff: DiffView = serde_json::from_slice(res.body())?;
+            assert_eq!(diff.groups.is_empty(), !changed);
+            assert_eq!(diff.from_commitment == diff.to_commitment, from == to);
+        }
+        let membership_list = db::get_membership_list(&ctx.db_pool, 1).await?;
+        let res = warp::test::request()
+            .method("GET")
+            .path("/membership_list/1/diff?from=2&to=3")
+            .reply(&api)
+            .await;
+        let diff: DiffView = serde_json::from_slice(res.body())?;
+        assert_eq!(
+            diff.to_commitment,
+            membership_list.state.0.commitment().encode_hex::<String>()
+        );
+        assert_eq!(
+            diff.groups,
+            BTreeMap::from([(
+                "red".to_string(),
+                GroupDiff {
+                    added: vec![],
+                    removed: vec!["alice".to_string()],
+                }
+            )])
+        );
+        for path in [
+            "/membership_list/1/diff?from=2&to=1",
+            "/membership_list/1/diff?from=0&to=4",
+        ] {
+            let res = warp::test::request()
+                .method("GET")
+                .path(path)
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+
+        // the proving times of the 3 updates were recorded
+        let res = warp::test::request()
+            .method("GET")
+            .path("/metrics")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let metrics = std::str::from_utf8(res.body())?;
+        assert!(metrics.contains("ad_server_state_pod_seconds_count 3\n"));
+        assert!(metrics.contains("ad_server_membership_pod_seconds_count 1\n"));
+
+        // costs of the create and the 3 updates, all zero with the mock sends
+        let res = warp::test::request()
+            .method("GET")
+            .path("/membership_list/1/costs")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let costs: CostsView = serde_json::from_slice(res.body())?;
+        assert_eq!(
+            costs.updates.iter().map(|u| u.num).collect::<Vec<_>>(),
+            vec![0, 1, 2, 3]
+        );
+        assert!(
+            costs
+                .updates
+                .iter()
+                .all(|u| u.cost == TxCostInfo::default())
+        );
+        assert_eq!(costs.total_fee, 0);
+        let res = warp::test::request()
+            .method("GET")
+            .path("/membership_list/42/costs")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // export the list and restore it on another server
+        let res = warp::test::request()
+            .method("GET")
+            .path("/snapshot/1")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let snapshot: ListSnapshot = serde_json::from_slice(res.body())?;
+        assert_eq!((snapshot.num, snapshot.rev_num), (3, 3));
+        let membership_list = db::get_membership_list(&ctx.db_pool, 1).await?;
+        assert_eq!(snapshot.commitment, membership_list.state.0.commitment());
+        let ops = snapshot.ops.clone().expect("ops history");
+        assert_eq!(Some(&ops), queue::ops_at(&ctx, 1, 3).await?.as_ref());
+        snapshot.verify(ctx.pod_config()?)?;
+        // the list already exists
+        let res = warp::test::request()
+            .method("POST")
+            .path("/snapshot")
+            .json(&snapshot)
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        let restore_pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(":memory:")
+            .await?;
+        db::init_db(&restore_pool).await?;
+        let (restore_ctx, _restore_queue_rx) = test_context(
+            restore_cfg,
+            restore_pool,
+            CancellationToken::new(),
+            Params::default(),
+        )
+        .await?;
+        let restore_api = routes(restore_ctx.clone());
+        // a state that doesn't match the pods
+        let mut tampered = snapshot.clone();
+        tampered.commitment = tampered.rev_state.commitment();
+        tampered.state = tampered.rev_state.clone();
+        let res = warp::test::request()
+            .method("POST")
+            .path("/snapshot")
+            .json(&tampered)
+            .reply(&restore_api)
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let res = warp::test::request()
+            .method("POST")
+            .path("/snapshot")
+            .json(&snapshot)
+            .reply(&restore_api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        // the list keeps the id of its AD
+        let restored = db::get_membership_list(&restore_ctx.db_pool, 1).await?;
+        assert_eq!(restored.num, 3);
+        assert_eq!(restored.state, membership_list.state);
+        assert_eq!(
+            db::get_rev_membership_list(&restore_ctx.db_pool, 1)
+                .await?
+                .num,
+            3
+        );
+        restore_ctx.load_pod(PodKey::rev_membership_list(1, 3))?;
+        // the restored list keeps publishing its ops history
+        assert_eq!(queue::ops_at(&restore_ctx, 1, 3).await?, Some(ops));
+        // restoring it again conflicts with the restored list
+        let res = warp::test::request()
+            .method("POST")
+            .path("/snapshot")
+            .json(&snapshot)
+            .reply(&restore_api)
+            .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!(
+                "/admin/pod/{}",
+                PodKey::rev_membership_list(1, 3).file_name()
+            ))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let info: PodInfo = serde_json::from_slice(res.body())?;
+        assert_eq!(info.verify_error, None);
+        assert!(info.params_match && info.vd_set_match);
+        assert!(
+            info.pub_statements
+                .iter()
+                .any(|st| st.ends_with("// rev_state::rev_sync"))
+        );
+        let res = warp::test::request()
+            .method("GET")
+            .path("/admin/pod/missing")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // add a group, which shows up empty in the state
+        let purple = Group::new("purple")?;
+        helper_membership_list_update(
+            &client,
+            Op::AddGroup {
+                group: purple.clone(),
+            },
+        )
+        .await;
+        let res = warp::test::request()
+            .method("GET")
+            .path("/membership_list/1")
+            .reply(&api)
+            .await;
+        let view: AdStateView = serde_json::from_slice(res.body())?;
+        assert_eq!(view.num, 4);
+        assert_eq!(view.state.get("purple"), Some(&vec![]));
+        // it can't be added again, nor can the initial groups
+        for group in [purple.clone(), Group::RED] {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/membership_list/1")
+                .json(&Op::AddGroup { group })
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::CONFLICT);
+        }
+        // and users can't be added to groups that don't exist
+        let res = warp::test::request()
+            .method("POST")
+            .path("/membership_list/1")
+            .json(&Op::Add {
+                group: Group::new("orange")?,
+                user: UserId::new("alice")?,
+            })
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // the group is still empty, so it can be dropped, and only once
+        helper_membership_list_update(&client, Op::DropGroup { group: purple }).await;
+        let res = warp::test::request()
+            .method("GET")
+            .path("/membership_list/1")
+            .reply(&api)
+            .await;
+        let view: AdStateView = serde_json::from_slice(res.body())?;
+        assert_eq!(view.num, 5);
+        assert_eq!(view.state.get("purple"), None);
+        let res = warp::test::request()
+            .method("POST")
+            .path("/membership_list/1")
+            .json(&Op::DropGroup {
+                group: Group::new("purple")?,
+            })
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_rev_retry() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.auth_token = None;
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!("ad-server-rev-retry-test-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .min_connections(1) // db config for tests
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(":memory:")
+            .await
+            .expect("cannot connect to db");
+        db::init_db(&db_pool).await?;
+        // list 1 at num 1 with its reverse index behind, the queue isn't running
+        let list = |num| db::AdState {
+            id: 1,
+            num,
+            state: db::DictContainerSql(dict!(depth(), {})),
+            created_at: 0,
+            updated_at: 0,
+        };
+        db::insert_membership_list(&db_pool, &list(1), db::ListKind::Set, None).await?;
+        db::insert_rev_membership_list(&db_pool, &list(0)).await?;
+        let (ctx, _queue_rx) = test_context(
+            cfg.clone(),
+            db_pool,
+            CancellationToken::new(),
+            Params::default(),
+        )
+        .await?;
+        let api = routes(ctx.clone());
+        let retry = async |path: &str| {
+            warp::test::request()
+                .method("POST")
+                .path(path)
+                .reply(&api)
+                .await
+        };
+
+        // the state pod of the update isn't on disk
+        let res = retry("/membership_list/1/rev/1/retry").await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let pod_path = ctx.pod_store.file_path(PodKey::membership_list(1, 1));
+        std::fs::create_dir_all(&cfg.pods_path)?;
+        std::fs::write(&pod_path, "{}")?;
+        let res = retry("/membership_list/1/rev/1/retry").await;
+        assert_eq!(res.status(), StatusCode::OK, "{:?}", res.body());
+        let resp: QueueResp = serde_json::from_slice(res.body())?;
+        assert!(matches!(
+            ctx.queue_state.read().await.get(&resp.req_id),
+            Some(queue::State::UpdateRev(queue::StateUpdateRev::Pending))
+        ));
+
+        // already scheduled, the reverse index isn't at the previous num, or no such list
+        for (path, status) in [
+            ("/membership_list/1/rev/1/retry", StatusCode::CONFLICT),
+            ("/membership_list/1/rev/2/retry", StatusCode::CONFLICT),
+            ("/membership_list/2/rev/1/retry", StatusCode::NOT_FOUND),
+        ] {
+            assert_eq!(retry(path).await.status(), status, "{}", path);
+        }
+
+        std::fs::remove_dir_all(&cfg.pods_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_initializing() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+
+        let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .min_connections(1) // db config for tests
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(":memory:")
+            .await
+            .expect("cannot connect to db");
+        db::init_db(&db_pool).await?;
+        // the setup is only made available below
+        let (queue_tx, queue_rx) = mpsc::channel::<queue::Request>(8);
+        let ctx = Arc::new(Context::new(
+            cfg,
+            db_pool,
+            None,
+            queue_tx,
+            CancellationToken::new(),
+        )?);
+        task::spawn(queue::handle_loop(ctx.clone(), queue_rx));
+        let api = routes(ctx.clone());
+        let create = async || {
+            warp::test::request()
+                .method("POST")
+                .path("/membership_list")
+                .reply(&api)
+                .await
+        };
+        let status = async || -> anyhow::Result<StatusView> {
+            let res = warp::test::request()
+                .method("GET")
+                .path("/status")
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+            Ok(serde_json::from_slice(res.body())?)
+        };
+
+        let res = create().await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_slice(res.body())?;
+        assert_eq!(body["error"]["kind"], "initializing");
+        let initializing = status().await?;
+        assert_eq!(initializing.status, "initializing");
+        assert!(!initializing.progress.ready);
+        assert_eq!(initializing.params_fingerprint, None);
+
+        let shrunk_main_pod_build = ShrunkMainPodSetup::new(&Params::default(), false).build()?;
+        ctx.set_setup(Setup {
+            pod_config: PodConfig::new(Params::default(), DEFAULT_VD_SET.clone()),
+            shrunk_main_pod_build,
+        });
+        wait_ready(&ctx).await;
+        let ready = status().await?;
+        assert_eq!(ready.status, "ready");
+        assert_eq!(
+            ready.params_fingerprint,
+            Some(params_fingerprint(&Params::default()))
+        );
+        let res = create().await;
+        assert_eq!(res.status(), StatusCode::OK, "{:?}", res.body());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_queue_full() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.queue_len = 1;
+
+        let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .min_connections(1) // db config for tests
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(":memory:")
+            .await
+            .expect("cannot connect to db");
+        db::init_db(&db_pool).await?;
+        // the queue isn't handled, so the first request fills it
+        let (ctx, _queue_rx) =
+            test_context(cfg, db_pool, CancellationToken::new(), Params::default()).await?;
+        let api = routes(ctx.clone());
+        let query = async || {
+            warp::test::request()
+                .method("GET")
+                .path("/user/1/alice")
+                .reply(&api)
+                .await
+        };
+
+        let res = query().await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = query().await;
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(res.headers()[RETRY_AFTER], "5");
+        let body: serde_json::Value = serde_json::from_slice(res.body())?;
+        assert_eq!(body["error"]["kind"], "queue_full");
+        // the rejected request isn't tracked
+        assert_eq!(ctx.queue_state.read().await.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_resume() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        // the pods of list 1 of the other tests are in PODS_PATH
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!("ad-server-shutdown-test-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .min_connections(1) // db config for tests
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(":memory:")
+            .await
+            .expect("cannot connect to db");
+        db::init_db(&db_pool).await?;
+
+        let (ctx, queue_rx) = test_context(
+            cfg.clone(),
+            db_pool.clone(),
+            CancellationToken::new(),
+            Params::default(),
+        )
+        .await?;
+        let api = routes(ctx.clone());
+        let queue_loop = task::spawn(queue::handle_loop(ctx.clone(), queue_rx));
+
+        let res = warp::test::request()
+            .method("POST")
+            .path("/membership_list")
+            .reply(&api)
+            .await;
+        let resp: QueueResp = serde_json::from_slice(res.body())?;
+        let state = wait_state(&ctx, resp.req_id, |s| {
+            matches!(
+                s,
+                queue::State::Create(queue::StateCreate::Complete { .. })
+                    | queue::State::Create(queue::StateCreate::Error(_))
+            )
+        })
+        .await;
+        assert!(matches!(
+            state,
+            queue::State::Create(queue::StateCreate::Complete { id: 1, .. })
+        ));
+
+        // enqueue two updates and shut down while the first one is being proven
+        let mut req_ids = Vec::new();
+        for user in ["alice", "bob"] {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/membership_list/1")
+                .json(&Op::Add {
+                    group: Group::RED,
+                    user: UserId::new(user)?,
+                })
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let resp: QueueResp = serde_json::from_slice(res.body())?;
+            req_ids.push(resp.req_id);
+        }
+        wait_state(&ctx, req_ids[0], |s| {
+            matches!(
+                s,
+                queue::State::Update(queue::StateUpdate::ProvingMainPod { .. })
+            )
+        })
+        .await;
+        ctx.shutdown.cancel();
+        queue_loop.await?;
+        match ctx.queue_state.read().await.get(&req_ids[0]) {
+            Some(queue::State::Update(queue::StateUpdate::Error(e))) => {
+                assert_eq!(e.kind, ErrorKind::ShuttingDown)
+            }
+            state => panic!("{:?} != StateUpdate::Error", state),
+        }
+        assert_eq!(db::get_membership_list(&db_pool, 1).await?.num, 0);
+
+        // restart, both updates complete under their req_id
+        let (ctx, queue_rx) = test_context(
+            cfg.clone(),
+            db_pool.clone(),
+            CancellationToken::new(),
+            Params::default(),
+        )
+        .await?;
+        task::spawn(queue::handle_loop(ctx.clone(), queue_rx));
+        queue::resume_requests(&ctx).await?;
+        for req_id in req_ids {
+            let state = wait_state(&ctx, req_id, |s| {
+                matches!(
+                    s,
+                    queue::State::Update(queue::StateUpdate::Complete { .. })
+                        | queue::State::Update(queue::StateUpdate::Error(_))
+                )
+            })
+            .await;
+            assert!(
+                matches!(
+                    state,
+                    queue::State::Update(queue::StateUpdate::Complete { .. })
+                ),
+                "{:?}",
+                state
+            );
+        }
+        assert_eq!(db::get_membership_list(&db_pool, 1).await?.num, 2);
+
+        std::fs::remove_dir_all(&cfg.pods_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_resume_wrap() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.wrap_max_attempts = 3;
+        // the pods of list 1 of the other tests are in PODS_PATH
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!("ad-server-resume-wrap-test-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let (ctx, client) = test_server(cfg.clone(), Params::default()).await?;
+        client.create_list().await?;
+        // the update 1 was proven, but its wrap fails since its pod is lost
+        db::insert_pending_wrap(
+            &ctx.db_pool,
+            &db::PendingWrap {
+                id: 1,
+                num: 1,
+                pod_name: PodKey::membership_list(1, 1).file_name(),
+                op: db::RawValueSql(RawValue::from(1)),
+                new_state: db::DictContainerSql(Dictionary::new(depth(), HashMap::new()).unwrap()),
+                attempts: 1,
+                first_num: Some(1),
+                tx_hash: None,
+                blob_versioned_hash: None,
+            },
+        )
+        .await?;
+        let resume_wrap = |req_id| queue::Request::ResumeWrap {
+            req_id,
+            id: 1,
+            num: 1,
+            other_req_ids: Vec::new(),
+        };
+        let done = |s: &queue::State| {
+            matches!(
+                s,
+                queue::State::Update(queue::StateUpdate::Complete { .. })
+                    | queue::State::Update(queue::StateUpdate::Error(_))
+            )
+        };
+
+        // enqueued again after each failure, until it was started `wrap_max_attempts` times
+        let req_id = queue::enqueue(&ctx, resume_wrap(Uuid::now_v7())).await?;
+        let state = wait_state(&ctx, req_id, done).await;
+        assert!(
+            matches!(state, queue::State::Update(queue::StateUpdate::Error(_))),
+            "{:?}",
+            state
+        );
+        assert_eq!(db::get_pending_wrap(&ctx.db_pool, 1, 1).await?.attempts, 3);
+        assert_eq!(db::get_membership_list(&ctx.db_pool, 1).await?.num, 0);
+
+        // once its payload is sent, it's applied without being sent again whatever its attempts
+        let tx_hash = TxHash::repeat_byte(1);
+        db::set_pending_wrap_sent(&ctx.db_pool, 1, 1, tx_hash, Some(B256::repeat_byte(2))).await?;
+        db::insert_update_cost(&ctx.db_pool, 1, 1, tx_hash, &TxCostInfo::default()).await?;
+        let req_id = queue::enqueue(&ctx, resume_wrap(Uuid::now_v7())).await?;
+        match wait_state(&ctx, req_id, done).await {
+            queue::State::Update(queue::StateUpdate::Complete {
+                tx_hash: sent,
+                blob_versioned_hash,
+                ..
+            }) => {
+                assert_eq!(sent, tx_hash);
+                assert_eq!(blob_versioned_hash, Some(B256::repeat_byte(2)));
+            }
+            state => panic!("{:?} != StateUpdate::Complete", state),
+        }
+        assert_eq!(db::get_membership_list(&ctx.db_pool, 1).await?.num, 1);
+        assert!(db::get_pending_wrap(&ctx.db_pool, 1, 1).await.is_err());
+        assert_eq!(db::get_update_costs(&ctx.db_pool, 1).await?.len(), 1);
+
+        // a sent batch proven from a state the list has moved past isn't a conflict, since its
+        // payload is on chain
+        let tx_hash = TxHash::repeat_byte(3);
+        db::insert_pending_wrap(
+            &ctx.db_pool,
+            &db::PendingWrap {
+                id: 1,
+                num: 2,
+                pod_name: PodKey::membership_list(1, 2).file_name(),
+                op: db::RawValueSql(RawValue::from(2)),
+                new_state: db::DictContainerSql(Dictionary::new(depth(), HashMap::new()).unwrap()),
+                attempts: 1,
+                first_num: Some(1),
+                tx_hash: Some(tx_hash.to_vec()),
+                blob_versioned_hash: None,
+            },
+        )
+        .await?;
+        let req_id = queue::enqueue(
+            &ctx,
+            queue::Request::ResumeWrap {
+                req_id: Uuid::now_v7(),
+                id: 1,
+                num: 2,
+                other_req_ids: Vec::new(),
+            },
+        )
+        .await?;
+        match wait_state(&ctx, req_id, done).await {
+            queue::State::Update(queue::StateUpdate::Complete { tx_hash: sent, .. }) => {
+                assert_eq!(sent, tx_hash)
+            }
+            state => panic!("{:?} != StateUpdate::Complete", state),
+        }
+        assert_eq!(db::get_membership_list(&ctx.db_pool, 1).await?.num, 1);
+        assert!(db::get_pending_wrap(&ctx.db_pool, 1, 2).await.is_err());
+
+        std::fs::remove_dir_all(&cfg.pods_path)?;
+        Ok(())
+    }
+
+    /// Starts a server with an empty db that proves the app pods with `params`, returning its
+    /// context and a client over it
+    async fn test_server(
+        cfg: Config,
+        params: Params,
+    ) -> anyhow::Result<(Arc<Context>, ad_client::Client)> {
+        let (ctx, client, queue_rx) = test_server_paused(cfg, params).await?;
+        task::spawn(queue::handle_loop(ctx.clone(), queue_rx));
+        Ok((ctx, client))
+    }
+
+    /// Like `test_server` without the queue loop, which the test starts on the returned receiver,
+    /// e.g. once it enqueued its requests.
+    async fn test_server_paused(
+        cfg: Config,
+        params: Params,
+    ) -> anyhow::Result<(
+        Arc<Context>,
+        ad_client::Client,
+        mpsc::Receiver<queue::Request>,
+    )> {
+        let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .min_connections(1) // db config for tests
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(":memory:")
+            .await
+            .expect("cannot connect to db");
+        db::init_db(&db_pool).await?;
+        let (ctx, queue_rx) = test_context(cfg, db_pool, CancellationToken::new(), params).await?;
+        let (addr, server) = warp::serve(routes(ctx.clone())).bind_ephemeral(([127, 0, 0, 1], 0));
+        task::spawn(server);
+        let client = ad_client::Client::new(ad_client::Config {
+            poll_interval: Duration::from_millis(100),
+            ..ad_client::Config::new(format!("http://{}", addr))
+        });
+        Ok((ctx, client, queue_rx))
+    }
+
+    #[tokio::test]
+    async fn test_predicates_get() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        let params = Params::default();
+
+        let (ctx, _client) = test_server(cfg, params.clone()).await?;
+        let res = warp::test::request()
+            .method("GET")
+            .path("/predicates")
+            .reply(&routes(ctx))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let batches: Vec<PredicateBatchView> = serde_json::from_slice(res.body())?;
+        let names: Vec<_> = batches.iter().map(|batch| batch.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "state_change",
+                "state",
+                "rev_state_add",
+                "rev_state_del",
+                "rev_state",
+                "kv_state_change",
+                "kv_state"
+            ]
+        );
+        // the sources parse to the same batches, each one with the batches before it
+        let mut parsed = Vec::new();
+        for batch in &batches {
+            let custom_batch = pod2::lang::parse(&batch.source, &params, &parsed)?.custom_batch;
+            assert_eq!(
+                custom_batch.id().encode_hex::<String>(),
+                batch.id,
+                "{}",
+                batch.name
+            );
+            for predicate in &batch.predicates {
+                assert_eq!(
+                    custom_batch
+                        .predicate_ref_by_name(&predicate.name)
+                        .map(|cpr| cpr.index),
+                    Some(predicate.index)
+                );
+            }
+            parsed.push(custom_batch);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_too_many_statements() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        // too few statements for the pod of any update
+        let default = Params::default();
+        let params = Params {
+            max_statements: default.max_public_statements + 2,
+            ..default
+        };
+
+        let (_ctx, client) = test_server(cfg, params).await?;
+        client.create_list().await?;
+        match client.update_list(1, &Op::Init).await {
+            Err(ad_client::Error::Request { kind, .. }) => {
+                assert!(kind == "invalid_op" || kind == "proving_failed", "{}", kind)
+            }
+            res => panic!("{:?} != Error::Request", res),
+        }
+        // the error is reported in the state of the request, the queue keeps going
+        assert_eq!(client.create_list().await?.id, 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_proving_timeout() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.proving_timeout = Some(Duration::from_millis(1));
+
+        let (ctx, client) = test_server(cfg, Params::default()).await?;
+        client.create_list().await?;
+        match client.update_list(1, &Op::Init).await {
+            Err(ad_client::Error::Request { kind, message, .. }) => {
+                assert_eq!(kind, "proving_timed_out");
+                assert!(message.starts_with("proving timed out"), "{}", message);
+            }
+            res => panic!("{:?} != Error::Request", res),
+        }
+        // the update isn't applied, and the detached proving is recorded
+        assert_eq!(db::get_membership_list(&ctx.db_pool, 1).await?.num, 0);
+        let incidents = db::get_proving_incidents(&ctx.db_pool).await?;
+        assert_eq!(incidents.len(), 1);
+        assert_eq!(
+            (
+                incidents[0].id,
+                incidents[0].num,
+                incidents[0].pod_name.as_str()
+            ),
+            (1, 1, PodKey::membership_list(1, 1).file_name().as_str())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_mirror() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.server_mode = ServerMode::Mirror;
+        cfg.primary_url = Some("http://primary:8000".to_string());
+
+        let db_pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .min_connections(1) // db config for tests
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect(":memory:")
+            .await
+            .expect("cannot connect to db");
+        db::init_db(&db_pool).await?;
+        let state = dict!(depth(), {"red" => set(&["alice"])});
+        let rev_state = archive::rev_index(&state, depth())?;
+        db::upsert_mirrored_list(&db_pool, 1, db::ListKind::Set, 3, &state, Some(&rev_state))
+            .await?;
+        // no queue worker: the queries are handled without the queue
+        let (queue_tx, _) = mpsc::channel::<queue::Request>(1);
+        let ctx = Arc::new(Context::new(
+            cfg,
+            db_pool,
+            None,
+            queue_tx,
+            CancellationToken::new(),
+        )?);
+        let (addr, server) = warp::serve(routes(ctx.clone())).bind_ephemeral(([127, 0, 0, 1], 0));
+        task::spawn(server);
+        let client = ad_client::Client::new(ad_client::Config {
+            poll_interval: Duration::from_millis(100),
+            ..ad_client::Config::new(format!("http://{}", addr))
+        });
+
+        let user_groups = client.query_user(1, "alice").await?;
+        assert_eq!(user_groups.groups, vec![Group::RED]);
+        // the queries in progress are bounded like the queue
+        let permits = ctx
+            .query_permits
+            .clone()
+            .acquire_many_owned(ctx.cfg.queue_len as u32)
+            .await?;
+        match client.query_user(1, "alice").await {
+            Err(ad_client::Error::Server { status, kind, .. }) => {
+                assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(kind, "queue_full");
+            }
+            res => panic!("{:?} != Error::Server", res),
+        }
+        drop(permits);
+        client.query_user(1, "alice").await?;
+
+        // the updates, and the endpoints that need the pods, point to the primary
+        let op = Op::Add {
+            group: Group::RED,
+            user: UserId::new("bob")?,
+        };
+        for res in [
+            client.create_list().await.map(|_| ()),
+            client.update_list(1, &op).await.map(|_| ()),
+        ] {
+            match res {
+                Err(ad_client::Error::Server {
+                    status,
+                    kind,
+                    message,
+                }) => {
+                    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+                    assert_eq!(kind, "method_not_allowed");
+                    assert!(message.contains("http://primary:8000"), "{}", message);
+                }
+                res => panic!("{:?} != Error::Server", res),
+            }
+        }
+        let res = reqwest::get(format!("http://{}/membership_pod/1/red/alice", addr)).await?;
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_admin_queue() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = Some("secret".to_string());
+
+        let db_pool = sqlx::SqlitePool::connect(":memory:").await?;
+        db::init_db(&db_pool).await?;
+        // no queue worker: the requests stay in the channel
+        let (queue_tx, _queue_rx) = mpsc::channel::<queue::Request>(4);
+        let ctx = Arc::new(Context::new(
+            cfg,
+            db_pool,
+            None,
+            queue_tx,
+            CancellationToken::new(),
+        )?);
+        let req_id = queue::enqueue(
+            &ctx,
+            queue::Request::PrunePods {
+                req_id: Uuid::now_v7(),
+            },
+        )
+        .await?;
+        // the complete requests aren't listed
+        ctx.queue_state.write().await.insert(
+            Uuid::now_v7(),
+            queue::State::PrunePods(queue::StatePrunePods::Complete { pruned: 0 }),
+        );
+        let api = routes(ctx);
+
+        let res = warp::test::request()
+            .method("GET")
+            .path("/admin/queue")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = warp::test::request()
+            .method("GET")
+            .path("/admin/queue")
+            .header("authorization", "Bearer secret")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let view: QueueView = serde_json::from_slice(res.body())?;
+        assert_eq!(
+            (view.backlog, view.capacity, view.buffered_updates),
+            (1, 4, 0)
+        );
+        match view.requests.as_slice() {
+            [entry] => {
+                assert_eq!(entry.req_id, req_id);
+                assert!(matches!(
+                    entry.state,
+                    queue::State::PrunePods(queue::StatePrunePods::Pending)
+                ));
+                assert!(entry.age_secs.is_some_and(|age| age < 60));
+            }
+            requests => panic!("{} requests listed", requests.len()),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_kv_list() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        // the pods of list 1 of the other tests are in PODS_PATH
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!("ad-server-kv-test-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let (ctx, client) = test_server(cfg, Params::default()).await?;
+        let id = client.create_kv_list().await?.id;
+        assert_eq!(
+            db::get_membership_list_kind(&ctx.db_pool, id).await?,
+            db::ListKind::Kv
+        );
+        let alice = UserId::new("alice")?;
+        for op in [
+            Op::Init,
+            Op::AddKv {
+                group: Group::RED,
+                user: alice.clone(),
+                value: 42,
+            },
+        ] {
+            client.update_list(id, &op).await?;
+        }
+        // the set ops don't apply to the kv lists
+        match client
+            .update_list(
+                id,
+                &Op::Add {
+                    group: Group::BLUE,
+                    user: alice,
+                },
+            )
+            .await
+        {
+            Err(ad_client::Error::Server { kind, .. }) => assert_eq!(kind, "invalid_op"),
+            res => panic!("{:?} != Error::Server", res),
+        }
+
+        let values = client.query_user_values(id, "alice").await?;
+        assert_eq!(values.len(), 1);
+        let red = &values[0];
+        assert_eq!((&red.group, red.value), (&Group::RED, 42));
+        let state = db::get_membership_list(&ctx.db_pool, id).await?.state.0;
+        let red_dict = state.get(&Key::from("red"))?;
+        let hex = |value: Value| value.raw().encode_hex::<String>();
+        assert_eq!(red.proof.key, hex(Value::from("alice")));
+        assert_eq!(red.proof.value, hex(Value::from(42)));
+        // the value is proven against the group, and the group against the state
+        assert_eq!(red.proof.root, hex(red_dict.clone()));
+        assert_eq!(red.group_proof.key, hex(Value::from("red")));
+        assert_eq!(red.group_proof.value, red.proof.root);
+        assert_eq!(
+            red.group_proof.root,
+            state.commitment().encode_hex::<String>()
+        );
+
+        // users in no group
+        match client.query_user_values(id, "bob").await {
+            Err(ad_client::Error::Request { kind, .. }) => assert_eq!(kind, "not_found"),
+            res => panic!("{:?} != Error::Request", res),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_at() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!("ad-server-query-at-test-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let (_ctx, client) = test_server(cfg, Params::default()).await?;
+        let id = client.create_list().await?.id;
+        let alice = UserId::new("alice")?;
+        for op in [
+            Op::Init,
+            Op::Add {
+                group: Group::RED,
+                user: alice.clone(),
+            },
+            Op::Del {
+                group: Group::RED,
+                user: alice,
+            },
+        ] {
+            client.update_list(id, &op).await?;
+        }
+
+        // alice was in red after the update 2, and in no group before and after it
+        let user_groups = client.query_user_at(id, "alice", 2).await?;
+        assert_eq!(user_groups.groups, vec![Group::RED]);
+        for at in [0, 1, 3] {
+            match client.query_user_at(id, "alice", at).await {
+                Err(ad_client::Error::Request { kind, .. }) => assert_eq!(kind, "not_found"),
+                res => panic!("{:?} != Error::Request", res),
+            }
+        }
+        // the list isn't at num 4 yet
+        match client.query_user_at(id, "alice", 4).await {
+            Err(ad_client::Error::Server { status, kind, .. }) => {
+                assert_eq!((status.as_u16(), kind.as_str()), (404, "not_found"))
+            }
+            res => panic!("{:?} != Error::Server", res),
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_update_batch() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.snapshot_interval = 0;
+        cfg.update_batch_max = 2;
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!(
+                "ad-server-update-batch-test-{}",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+
+        let (ctx, client, queue_rx) = test_server_paused(cfg, Params::default()).await?;
+        // the list and a burst of updates, with alice added twice, all enqueued before the queue
+        // starts: the updates are buffered behind the init and proven in pairs
+        let id = 1;
+        queue::enqueue(
+            &ctx,
+            queue::Request::Create {
+                req_id: Uuid::now_v7(),
+                kind: db::ListKind::Set,
+            },
+        )
+        .await?;
+        let mut ops = vec![Op::Init];
+        for user in ["alice", "bob", "carol", "dave", "erin", "alice"] {
+            ops.push(Op::Add {
+                group: Group::RED,
+                user: UserId::new(user)?,
+            });
+        }
+        let mut req_ids = Vec::new();
+        for op in ops {
+            let req_id = Uuid::now_v7();
+            queue::enqueue(&ctx, queue::Request::Update { req_id, id, op }).await?;
+            req_ids.push(req_id);
+        }
+        assert_eq!(ctx.pending_updates.buffered(), 6);
+        task::spawn(queue::handle_loop(ctx.clone(), queue_rx));
+
+        let mut conflicts = 0;
+        for req_id in req_ids {
+            let state = wait_state(&ctx, req_id, |s| {
+                matches!(
+                    s,
+                    queue::State::Update(
+                        queue::StateUpdate::Complete { .. } | queue::StateUpdate::Error(_)
+                    )
+                )
+            })
+            .await;
+            match state {
+                queue::State::Update(queue::StateUpdate::Complete { .. }) => {}
+                // the duplicate is dropped from its batch
+                queue::State::Update(queue::StateUpdate::Error(info))
+                    if info.kind == ErrorKind::Conflict =>
+                {
+                    conflicts += 1
+                }
+                state => panic!("unexpected state {:?}", state),
+            }
+        }
+        assert_eq!(conflicts, 1);
+        // the create and the batches of the updates 1..=2, 3..=4 and 5..=6
+        assert_eq!(ctx.metrics.payloads_sent(), 4);
+        let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+        assert_eq!(membership_list.num, 6);
+        // the pod of a batch is stored once, under its last update
+        let state_pods: Vec<i64> = disk::list_pods(Path::new(&ctx.cfg.pods_path))?
+            .into_iter()
+            .filter(|key| key.kind == disk::PodKind::MembershipList && key.id == id)
+            .map(|key| key.num)
+            .collect();
+        assert_eq!(state_pods, vec![2, 4, 6]);
+        assert_eq!(
+            ctx.pod_store.resolve(PodKey::membership_list(id, 5)),
+            PodKey::membership_list(id, 6)
+        );
+
+        // the pods of a batch prove the state of each of its updates
+        let user_groups = client.query_user_at(id, "alice", 6).await?;
+        assert_eq!(user_groups.groups, vec![Group::RED]);
+        std::fs::remove_dir_all(&ctx.cfg.pods_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_simultaneous_updates() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.snapshot_interval = 0;
+        cfg.update_batch_max = 1;
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!(
+                "ad-server-simultaneous-updates-test-{}",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned();
+
+        let (ctx, client) = test_server(cfg, Params::default()).await?;
+        let (a, b) = (
+            client.create_list().await?.id,
+            client.create_list().await?.id,
+        );
+        client.update_list(a, &Op::Init).await?;
+        client.update_list(b, &Op::Init).await?;
+
+        let add = |user: &str| -> anyhow::Result<Op> {
+            Ok(Op::Add {
+                group: Group::RED,
+                user: UserId::new(user)?,
+            })
+        };
+        // the updates are all enqueued before the first one is handled, and each one is applied
+        // from the state left by the previous update of its list
+        let updates = async |updates: &[(i64, Op)]| -> anyhow::Result<()> {
+            let mut req_ids = Vec::new();
+            for (id, op) in updates {
+                let req = queue::Request::Update {
+                    req_id: Uuid::now_v7(),
+                    id: *id,
+                    op: op.clone(),
+                };
+                req_ids.push(queue::enqueue(&ctx, req).await?);
+            }
+            for req_id in req_ids {
+                let state = wait_state(&ctx, req_id, |s| s.is_terminal()).await;
+                assert!(
+                    matches!(
+                        state,
+                        queue::State::Update(queue::StateUpdate::Complete { .. })
+                    ),
+                    "{:?}",
+                    state
+                );
+            }
+            Ok(())
+        };
+        let ops_log = async |id: i64| -> anyhow::Result<Vec<Op>> {
+            let num = db::get_membership_list(&ctx.db_pool, id).await?.num;
+            let entries = db::get_op_log(&ctx.db_pool, id, 1, num).await?;
+            assert_eq!(entries.len() as i64, num);
+            Ok(entries.into_iter().map(|entry| entry.op).collect())
+        };
+
+        // to one list
+        updates(&[(a, add("alice")?), (a, add("bob")?), (a, add("carol")?)]).await?;
+        assert_eq!(
+            ops_log(a).await?,
+            [Op::Init, add("alice")?, add("bob")?, add("carol")?]
+        );
+        assert_eq!(ops_log(b).await?, [Op::Init]);
+
+        // to two lists, interleaved
+        updates(&[
+            (a, add("dave")?),
+            (b, add("erin")?),
+            (a, add("frank")?),
+            (b, add("grace")?),
+        ])
+        .await?;
+        assert_eq!(
+            ops_log(a).await?,
+            [
+                Op::Init,
+                add("alice")?,
+                add("bob")?,
+                add("carol")?,
+                add("dave")?,
+                add("frank")?
+            ]
+        );
+        assert_eq!(ops_log(b).await?, [Op::Init, add("erin")?, add("grace")?]);
+
+        // the last state of each list has all of its updates
+        for (id, users) in [(a, &["alice", "frank"]), (b, &["erin", "grace"])] {
+            for user in users {
+                let user_groups = client.query_user(id, user).await?;
+                assert_eq!(user_groups.groups, vec![Group::RED]);
+            }
+        }
+        std::fs::remove_dir_all(&ctx.cfg.pods_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_op_proof() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!("ad-server-op-proof-test-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        let params = Params::default();
+        let (ctx, client) = test_server(cfg, params.clone()).await?;
+        let id = client.create_list().await?.id;
+        let alice = UserId::new("alice")?;
+        let ops = [
+            Op::Init,
+            Op::Add {
+                group: Group::RED,
+                user: alice.clone(),
+            },
+            Op::Del {
+                group: Group::RED,
+                user: alice,
+            },
+        ];
+        for op in &ops {
+            client.update_list(id, op).await?;
+        }
+
+        // the ops root the synchronizer derives from the ops of the update payloads
+        let op_raw = |op: &Op, num| RawValue::from(op.clone().into_dict(&params, num).commitment());
+        let mut ops_root = common::ops::empty_ops();
+        for (op, num) in ops.iter().zip(1..) {
+            common::ops::insert_op(&mut ops_root, num, op_raw(op, num))?;
+        }
+        let ops_root = ops_root.commitment();
+
+        let api = routes(ctx.clone());
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!("/membership_list/{}/op_proof/2", id))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let view: OpProofView = serde_json::from_slice(res.body())?;
+        assert_eq!((view.num, &view.op, view.at), (2, &ops[1], 3));
+        assert_eq!(view.proof.root, ops_root.encode_hex::<String>());
+        assert_eq!(view.proof.value, op_raw(&ops[1], 2).encode_hex::<String>());
+        let ops = queue::ops_at(&ctx, id, 3).await?.expect("ops history");
+        let proof = queue::prove_op(&ops, 2)?;
+        common::ops::verify_op_proof(ops_root, 2, op_raw(&view.op, 2), &proof.proof)?;
+        // the ops history is stored with every update, and pruned behind the previous one
+        assert_eq!(db::get_list_ops(&ctx.db_pool, id, 3).await?, Some(ops));
+        assert!(db::get_list_ops(&ctx.db_pool, id, 2).await?.is_some());
+        assert_eq!(db::get_list_ops(&ctx.db_pool, id, 1).await?, None);
+        // a list that publishes its ops history never falls back to the op log
+        assert!(queue::ops_at(&ctx, id, 1).await.is_err());
+
+        for num in [0, 4] {
+            let res = warp::test::request()
+                .method("GET")
+                .path(&format!("/membership_list/{}/op_proof/{}", id, num))
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_audit() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        cfg.pods_path = std::env::temp_dir()
+            .join(format!("ad-server-audit-test-{}", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+
+        // mocked synchronizer, with the updates on chain of every AD in `chain` and failing for
+        // the other ADs
+        let chain: Arc<std::sync::Mutex<HashMap<String, serde_json::Value>>> = Default::default();
+        let updates = warp::path!("ad" / String / "updates").map({
+            let chain = chain.clone();
+            move |ad_id: String| {
+                let chain = chain.lock().expect("lock");
+                match chain.get(&ad_id) {
+                    Some(history) => warp::reply::json(history).into_response(),
+                    None => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+                }
+            }
+        });
+        let (addr, server) = warp::serve(updates).bind_ephemeral(([127, 0, 0, 1], 0));
+        task::spawn(server);
+        cfg.synchronizer_url = Some(format!("http://{}", addr));
+
+        let (ctx, client) = test_server(cfg.clone(), Params::default()).await?;
+        for id in 1..=6 {
+            assert_eq!(client.create_list().await?.id, id);
+            client.update_list(id, &Op::Init).await?;
+        }
+        // the chain has the updates up to `num`, with the states of the db
+        let set_chain = async |id: i64, num: i64| -> anyhow::Result<()> {
+            let state = db::get_membership_list(&ctx.db_pool, id).await?.state.0;
+            let history: Vec<_> = (0..=num)
+                .map(|n| {
+                    let state = match n {
+                        1 => RawValue::from(state.commitment()),
+                        n => RawValue::from(n),
+                    };
+                    serde_json::json!({"num": n, "state": state})
+                })
+                .collect();
+            let ad_id = Hash::from(RawValue::from(id)).encode_hex::<String>();
+            chain.lock().expect("lock").insert(ad_id, history.into());
+            Ok(())
+        };
+
+        // 1 is consistent
+        set_chain(1, 1).await?;
+        // 2 lost its latest pod
+        set_chain(2, 1).await?;
+        std::fs::remove_file(ctx.pod_store.file_path(PodKey::membership_list(2, 1)))?;
+        // the state of 3 isn't the one proven by its pod
+        set_chain(3, 1).await?;
+        let state = dict!(depth(), {"red" => set(&["mallory"])});
+        db::update_membership_list(&ctx.db_pool, 3, 1, 1, state, None).await?;
+        // the chain has an update of 4 that the db doesn't have
+        set_chain(4, 2).await?;
+        // the update of 5 isn't on chain
+        set_chain(5, 0).await?;
+        // the latest pod of 6 is corrupt, and the synchronizer fails to answer for it
+        std::fs::write(
+            ctx.pod_store.file_path(PodKey::membership_list(6, 1)),
+            b"not a pod",
+        )?;
+
+        let api = routes(ctx.clone());
+        let audit_reports = async || -> anyhow::Result<Vec<audit::ListAudit>> {
+            let res = warp::test::request()
+                .method("POST")
+                .path("/admin/audit")
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::OK, "{:?}", res.body());
+            Ok(serde_json::from_slice(res.body())?)
+        };
+        let audit = async || -> anyhow::Result<Vec<(i64, AuditStatus)>> {
+            Ok(audit_reports()
+                .await?
+                .iter()
+                .map(|r| (r.id, r.status))
+                .collect())
+        };
+        let reports = audit_reports().await?;
+        assert_eq!(
+            reports.iter().map(|r| (r.id, r.status)).collect::<Vec<_>>(),
+            vec![
+                (1, AuditStatus::Consistent),
+                (2, AuditStatus::PodMissing),
+                (3, AuditStatus::CommitmentMismatch),
+                (4, AuditStatus::BehindChain),
+                (5, AuditStatus::AheadOfChain),
+                (6, AuditStatus::PodInvalid),
+            ]
+        );
+        // the failed fetch is reported
+        assert!(reports[..5].iter().all(|r| r.chain_error.is_none()));
+        let chain_error = reports[5].chain_error.as_deref().unwrap_or_default();
+        assert!(chain_error.contains("503"), "{}", chain_error);
+        assert_eq!(db::get_audit_flag(&ctx.db_pool, 1).await?, None);
+        assert_eq!(
+            db::get_audit_flag(&ctx.db_pool, 4).await?,
+            Some(AuditStatus::BehindChain)
+        );
+
+        // the updates of the flagged lists are refused
+        let op = Op::Add {
+            group: Group::RED,
+            user: UserId::new("alice")?,
+        };
+        match client.update_list(4, &op).await {
+            Err(ad_client::Error::Server { status, kind, .. }) => {
+                assert_eq!((status.as_u16(), kind.as_str()), (409, "flagged"))
+            }
+            res => panic!("{:?} != Error::Server", res),
+        }
+
+        // until the flag is cleared by an operator
+        let res = warp::test::request()
+            .method("POST")
+            .path("/admin/audit/4/clear")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let resp: AuditClearResp = serde_json::from_slice(res.body())?;
+        assert_eq!(resp.cleared, Some(AuditStatus::BehindChain));
+        assert_eq!(db::get_audit_flag(&ctx.db_pool, 4).await?, None);
+        // refused by the op check instead
+        match client.update_list(4, &Op::Init).await {
+            Err(ad_client::Error::Server { kind, .. }) => assert_eq!(kind, "conflict"),
+            res => panic!("{:?} != Error::Server", res),
+        }
+
+        // or by an audit that finds the list consistent again, here once the chain catches up
+        set_chain(5, 1).await?;
+        let statuses = audit().await?;
+        assert_eq!(
+            (statuses[3], statuses[4]),
+            ((4, AuditStatus::BehindChain), (5, AuditStatus::Consistent))
+        );
+        assert_eq!(db::get_audit_flag(&ctx.db_pool, 5).await?, None);
+
+        // an update whose tx was included is pending while the synchronizer lags behind
+        client.update_list(5, &op).await?;
+        let cost = TxCostInfo::default();
+        db::insert_update_cost(&ctx.db_pool, 5, 2, TxHash::repeat_byte(5), &cost).await?;
+        assert_eq!(audit().await?[4], (5, AuditStatus::Consistent));
+        // for a bounded time
+        let since = std::time::Instant::now() - audit::AHEAD_OF_CHAIN_GRACE;
+        ctx.ahead_of_chain_since
+            .lock()
+            .expect("lock")
+            .insert(5, since);
+        assert_eq!(audit().await?[4], (5, AuditStatus::AheadOfChain));
+        // and no longer once the chain has its update
+        set_chain(5, 3).await?;
+        assert_eq!(audit().await?[4], (5, AuditStatus::BehindChain));
+        assert!(ctx.ahead_of_chain_since.lock().expect("lock").is_empty());
+
+        std::fs::remove_dir_all(&cfg.pods_path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_archive_round_trip() -> anyhow::Result<()> {
+        common::load_dotenv()?;
+        let mut cfg = Config::from_env()?;
+        cfg.priv_keys = Vec::new();
+        cfg.rate_limit_per_minute = 0;
+        cfg.auth_token = None;
+        let pods_path =
+            std::env::temp_dir().join(format!("ad-server-archive-test-{}", std::process::id()));
+        cfg.pods_path = pods_path.join("a").to_string_lossy().into_owned();
+
+        let (ctx, client) = test_server(cfg.clone(), Params::default()).await?;
+        client.create_list().await?;
+        for op in [
+            Op::Init,
+            Op::Add {
+                group: Group::RED,
+                user: UserId::new("alice")?,
+            },
+            Op::Add {
+                group: Group::BLUE,
+                user: UserId::new("bob")?,
+            },
+        ] {
+            client.update_list(1, &op).await?;
+        }
+        let res = warp::test::request()
+            .method("GET")
+            .path("/admin/export/1")
+            .reply(&routes(ctx.clone()))
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let archive = res.body().clone();
+        let paths = tar::Archive::new(&archive[..])
+            .entries()?
+            .map(|entry| Ok(entry?.path()?.to_string_lossy().into_owned()))
+            .collect::<io::Result<Vec<_>>>()?;
+        assert!(paths.iter().any(|path| path == "op_log.json"));
+        // the pods of every update, not only the latest ones
+        let pods = paths.iter().filter(|path| path.starts_with("pods/"));
+        assert!(pods.count() >= 3);
+
+        // on a server with an empty db and no pods
+        cfg.pods_path = pods_path.join("b").to_string_lossy().into_owned();
+        let (ctx, client) = test_server(cfg.clone(), Params::default()).await?;
+        let api = routes(ctx.clone());
+        let res = warp::test::request()
+            .method("POST")
+            .path("/admin/import")
+            .body(&archive)
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK, "{:?}", res.body());
+        let resp: SnapshotResp = serde_json::from_slice(res.body())?;
+        assert_eq!((resp.id, resp.num), (1, 3));
+        assert_eq!(db::get_op_log(&ctx.db_pool, 1, 1, 3).await?.len(), 3);
+
+        // the id of the archive is taken now
+        let res = warp::test::request()
+            .method("POST")
+            .path("/admin/import")
+            .body(&archive)
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::CONFLICT, "{:?}", res.body());
+        assert!(matches!(
+            db::get_membership_list(&ctx.db_pool, 2).await,
+            Err(Error::NotFound(_))
+        ));
+        let res = warp::test::request()
+            .method("POST")
+            .path("/admin/import")
+            .body(archive.slice(..archive.len() / 2))
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        // the imported list keeps being updated from where it was
+        client
+            .update_list(
+                1,
+                &Op::Add {
+                    group: Group::RED,
+                    user: UserId::new("carol")?,
+                },
+            )
+            .await?;
+        assert_eq!(db::get_membership_list(&ctx.db_pool, 1).await?.num, 4);
+
+        std::fs::remove_dir_all(&pods_path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_op_names() {
+        let add = Op::Add {
+            group: Group::RED,
+            user: UserId::new("alice").unwrap(),
+        };
+        assert!(check_op_names(&add, 5).is_ok());
+        assert!(check_op_names(&add, 4).is_err());
+        let add_group = Op::AddGroup {
+            group: Group::new("purple").unwrap(),
+        };
+        assert!(check_op_names(&add_group, 5).is_err());
+        assert!(check_op_names(&Op::Init, 1).is_ok());
     }
 }
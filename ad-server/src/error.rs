@@ -0,0 +1,193 @@
+use std::time::Duration;
+
+use common::rejection::RejectionKind;
+use serde::{Deserialize, Serialize};
+use warp::http::StatusCode;
+
+use crate::{audit::AuditStatus, eth::TxErrorCode};
+
+/// Errors of the ad-server, surfaced to clients through the endpoints and the queue states.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("not found: {0}")]
+    NotFound(String),
+    #[error("membership list {0} not initialized")]
+    NotInitialized(i64),
+    #[error("invalid op: {0}")]
+    InvalidOp(String),
+    // the body isn't the JSON of an op, with the error of its deserialization
+    #[error("malformed op: {0}")]
+    MalformedOp(String),
+    #[error("conflict: {0}")]
+    Conflict(String),
+    // the audit found the list inconsistent, with the status it was flagged with
+    #[error("membership list {0} flagged as {1} by the audit, see POST /admin/audit")]
+    Flagged(i64, AuditStatus),
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    #[error("invalid archive: {0}")]
+    InvalidArchive(String),
+    #[error("invalid range: {0}")]
+    InvalidRange(String),
+    #[error("invalid user: {0}")]
+    InvalidUser(String),
+    #[error("proving failed: {0}")]
+    ProvingFailed(#[source] anyhow::Error),
+    #[error("proving timed out after {0:?}")]
+    ProvingTimedOut(Duration),
+    #[error("eth rpc: {0}")]
+    EthRpc(#[source] anyhow::Error),
+    #[error("db: {0}")]
+    Db(#[source] sqlx::Error),
+    #[error("rate limit exceeded, retry after {0}s")]
+    RateLimited(u64),
+    #[error("missing or invalid bearer token")]
+    Unauthorized,
+    #[error("shutting down")]
+    ShuttingDown,
+    #[error("request queue is full, retry after {0}s")]
+    QueueFull(u64),
+    #[error("initializing, see GET /status")]
+    Initializing,
+    // the server is a mirror, with the URL of its primary
+    #[error("read-only mirror, send the request to the primary at {0}")]
+    ReadOnlyMirror(String),
+    #[error("internal: {0}")]
+    Internal(#[from] anyhow::Error),
+}
+
+impl warp::reject::Reject for Error {}
+
+impl From<sqlx::Error> for Error {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::RowNotFound => Error::NotFound("row".to_string()),
+            err => Error::Db(err),
+        }
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(err: tokio::task::JoinError) -> Self {
+        Error::Internal(err.into())
+    }
+}
+
+/// Machine readable kind of an `Error`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    NotFound,
+    NotInitialized,
+    InvalidOp,
+    MalformedOp,
+    Conflict,
+    Flagged,
+    ProvingFailed,
+    ProvingTimedOut,
+    EthRpc,
+    Db,
+    Internal,
+    RateLimited,
+    Unauthorized,
+    ShuttingDown,
+    QueueFull,
+    Initializing,
+    // errors raised by warp while matching the request
+    MethodNotAllowed,
+    InvalidRequest,
+    PayloadTooLarge,
+    InvalidBody,
+}
+
+impl ErrorKind {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorKind::NotFound => StatusCode::NOT_FOUND,
+            ErrorKind::NotInitialized | ErrorKind::Conflict | ErrorKind::Flagged => {
+                StatusCode::CONFLICT
+            }
+            ErrorKind::InvalidOp | ErrorKind::InvalidRequest | ErrorKind::InvalidBody => {
+                StatusCode::BAD_REQUEST
+            }
+            ErrorKind::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorKind::MalformedOp => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ErrorKind::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorKind::ShuttingDown | ErrorKind::QueueFull | ErrorKind::Initializing => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            ErrorKind::ProvingFailed
+            | ErrorKind::ProvingTimedOut
+            | ErrorKind::EthRpc
+            | ErrorKind::Db
+            | ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<RejectionKind> for ErrorKind {
+    fn from(kind: RejectionKind) -> Self {
+        match kind {
+            RejectionKind::NotFound => ErrorKind::NotFound,
+            RejectionKind::MethodNotAllowed => ErrorKind::MethodNotAllowed,
+            RejectionKind::PayloadTooLarge => ErrorKind::PayloadTooLarge,
+            RejectionKind::InvalidBody => ErrorKind::InvalidBody,
+            RejectionKind::InvalidRequest => ErrorKind::InvalidRequest,
+            RejectionKind::Internal => ErrorKind::Internal,
+        }
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::NotFound(_) => ErrorKind::NotFound,
+            Error::NotInitialized(_) => ErrorKind::NotInitialized,
+            Error::InvalidOp(_) => ErrorKind::InvalidOp,
+            Error::MalformedOp(_) => ErrorKind::MalformedOp,
+            Error::Conflict(_) => ErrorKind::Conflict,
+            Error::Flagged(..) => ErrorKind::Flagged,
+            Error::InvalidSnapshot(_)
+            | Error::InvalidArchive(_)
+            | Error::InvalidRange(_)
+            | Error::InvalidUser(_) => ErrorKind::InvalidRequest,
+            Error::ProvingFailed(_) => ErrorKind::ProvingFailed,
+            Error::ProvingTimedOut(_) => ErrorKind::ProvingTimedOut,
+            Error::EthRpc(_) => ErrorKind::EthRpc,
+            Error::Db(_) => ErrorKind::Db,
+            Error::RateLimited(_) => ErrorKind::RateLimited,
+            Error::Unauthorized => ErrorKind::Unauthorized,
+            Error::ShuttingDown => ErrorKind::ShuttingDown,
+            Error::QueueFull(_) => ErrorKind::QueueFull,
+            Error::Initializing => ErrorKind::Initializing,
+            Error::ReadOnlyMirror(_) => ErrorKind::MethodNotAllowed,
+            Error::Internal(_) => ErrorKind::Internal,
+        }
+    }
+}
+
+/// Serialized form of an `Error`, used as the JSON error body of the endpoints and in the
+/// `Error` queue states.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorInfo {
+    pub kind: ErrorKind,
+    // cause of the `EthRpc` errors of the sends of payloads, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<TxErrorCode>,
+    pub message: String,
+}
+
+impl From<&Error> for ErrorInfo {
+    fn from(err: &Error) -> Self {
+        Self {
+            kind: err.kind(),
+            code: match err {
+                Error::EthRpc(err) => TxErrorCode::classify(err),
+                _ => None,
+            },
+            message: err.to_string(),
+        }
+    }
+}
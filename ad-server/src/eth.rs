@@ -1,42 +1,483 @@
+use std::{collections::BTreeSet, str::FromStr, sync::Arc, time::Instant};
+
 use alloy::{
-    consensus::{SidecarBuilder, SimpleCoder},
-    eips::eip4844::DATA_GAS_PER_BLOB,
+    consensus::{SidecarBuilder, SimpleCoder, Transaction},
+    eips::eip4844::{DATA_GAS_PER_BLOB, kzg_to_versioned_hash},
     network::{TransactionBuilder, TransactionBuilder4844},
-    primitives::{Address, TxHash},
-    providers::{Provider, ProviderBuilder},
+    primitives::{Address, B256, TxHash, U256},
+    providers::{DynProvider, Provider, ProviderBuilder},
     rpc::types::{TransactionReceipt, TransactionRequest},
     signers::local::PrivateKeySigner,
 };
 use anyhow::{Result, anyhow};
-use tokio::time::{Duration, sleep};
-use tracing::{debug, info};
-
-use crate::Config;
-
-pub async fn send_payload(cfg: &Config, b: Vec<u8>) -> Result<TxHash> {
-    if cfg.priv_key.is_empty() {
-        // test mode, return a mock tx_hash
-        return Ok(TxHash::from([0u8; 32]));
-    }
-    // PART 2: send the pod2 proof into a tx blob
-    let signer: PrivateKeySigner = cfg.priv_key.parse()?;
-    let provider = ProviderBuilder::new()
-        .wallet(signer.clone())
-        .connect(&cfg.rpc_url)
-        .await?;
-    let latest_block = provider.get_block_number().await?;
-    info!("Latest block number: {latest_block}");
-
-    let sender = signer.address();
-    let receiver = Address::from([0x42; 20]);
+use common::api::TxCostInfo;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::Mutex,
+    time::{Duration, interval, sleep},
+};
+use tracing::{debug, info, warn};
+
+use crate::{Config, Context};
+
+// intrinsic gas of any tx
+const TX_BASE_GAS: u64 = 21_000;
+// EIP-7623 floor cost per calldata token, where a zero byte is 1 token and a
+// non-zero byte is 4 tokens
+const CALLDATA_FLOOR_GAS_PER_TOKEN: u64 = 10;
+
+/// How the payloads are posted to ethereum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostingMode {
+    // EIP-4844 blob tx
+    Blob,
+    // payload in the calldata of a regular tx
+    Calldata,
+    // pick the cheaper of the two at the time of sending
+    Auto,
+}
+
+impl FromStr for PostingMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blob" => Ok(PostingMode::Blob),
+            "calldata" => Ok(PostingMode::Calldata),
+            "auto" => Ok(PostingMode::Auto),
+            _ => Err(anyhow!("unsupported POSTING_MODE {}", s)),
+        }
+    }
+}
+
+/// Cost of the tx of `receipt`
+pub fn tx_cost(receipt: &TransactionReceipt) -> TxCostInfo {
+    TxCostInfo {
+        sender: Some(receipt.from),
+        ..TxCostInfo::new(
+            receipt.gas_used,
+            receipt.effective_gas_price,
+            receipt.blob_gas_used.unwrap_or(0),
+            receipt.blob_gas_price.unwrap_or(0),
+        )
+    }
+}
+
+/// Hands out sequential nonces for the txs of a sender, so that txs sent before the node sees the
+/// previous ones don't collide.
+#[derive(Debug, Default)]
+pub struct NonceManager {
+    // nonce after the highest one handed out, `None` until the first tx
+    next: Option<u64>,
+    // nonces handed out whose tx is still being sent
+    in_flight: BTreeSet<u64>,
+}
+
+impl NonceManager {
+    /// Returns the nonce for a new tx given the `pending` nonce of the sender reported by the
+    /// node.  A nonce below our next one that's neither pending in the node nor in flight was
+    /// left unused by a tx that didn't make it, and is handed out again to fill the gap.
+    pub fn next(&mut self, pending: u64) -> u64 {
+        let next = self.next.unwrap_or(pending);
+        let nonce = (pending..next)
+            .find(|nonce| !self.in_flight.contains(nonce))
+            .unwrap_or(next.max(pending));
+        self.in_flight.insert(nonce);
+        self.next = Some(next.max(nonce + 1));
+        nonce
+    }
+
+    /// Marks the tx with `nonce` as done, whether it was included or not.
+    pub fn finish(&mut self, nonce: u64) {
+        self.in_flight.remove(&nonce);
+    }
+}
+
+/// Returns the private keys of the signers: the comma separated `priv_keys` (`PRIV_KEYS`), or
+/// `priv_key` (`PRIV_KEY`) if there are none.
+pub fn parse_priv_keys(priv_keys: &str, priv_key: &str) -> Vec<String> {
+    let priv_keys: Vec<String> = priv_keys
+        .split(',')
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .map(str::to_string)
+        .collect();
+    if !priv_keys.is_empty() || priv_key.is_empty() {
+        return priv_keys;
+    }
+    vec![priv_key.to_string()]
+}
+
+/// Picks the signer of each payload: round robin over the signers, skipping for `cooldown` the
+/// ones whose last `max_failures` sends failed, e.g. because their balance is drained.  The
+/// skipped signers are still tried, last, so that sends go on while all of them are failing.
+#[derive(Debug)]
+pub struct SignerPool {
+    max_failures: u32,
+    cooldown: Duration,
+    state: std::sync::Mutex<SignerPoolState>,
+}
+
+#[derive(Debug)]
+struct SignerPoolState {
+    // signer tried first for the next payload
+    next: usize,
+    // consecutive failed sends of every signer
+    failures: Vec<u32>,
+    // end of the cooldown of every signer that's being skipped
+    skipped_until: Vec<Option<Instant>>,
+}
+
+impl SignerPool {
+    pub fn new(len: usize, max_failures: u32, cooldown: Duration) -> Self {
+        Self {
+            max_failures: max_failures.max(1),
+            cooldown,
+            state: std::sync::Mutex::new(SignerPoolState {
+                next: 0,
+                failures: vec![0; len],
+                skipped_until: vec![None; len],
+            }),
+        }
+    }
+
+    /// Returns the indexes of the signers in the order they are tried for the next payload: the
+    /// available ones from the next in the rotation, and then the skipped ones from the one whose
+    /// cooldown ends first.
+    pub fn order(&self, now: Instant) -> Vec<usize> {
+        let mut state = self.state.lock().expect("lock");
+        let len = state.failures.len();
+        let next = state.next;
+        state.next = (next + 1) % len.max(1);
+        let (mut available, mut skipped): (Vec<usize>, Vec<usize>) = (0..len)
+            .map(|i| (next + i) % len)
+            .partition(|i| state.skipped_until[*i].is_none_or(|until| until <= now));
+        skipped.sort_by_key(|i| state.skipped_until[*i]);
+        available.append(&mut skipped);
+        available
+    }
+
+    pub fn record_success(&self, index: usize) {
+        let mut state = self.state.lock().expect("lock");
+        state.failures[index] = 0;
+        state.skipped_until[index] = None;
+    }
+
+    /// Records a failed send of the signer and returns whether it's skipped from now on.
+    pub fn record_failure(&self, index: usize, now: Instant) -> bool {
+        let mut state = self.state.lock().expect("lock");
+        state.failures[index] += 1;
+        if state.failures[index] < self.max_failures {
+            return false;
+        }
+        state.failures[index] = 0;
+        state.skipped_until[index] = Some(now + self.cooldown);
+        true
+    }
+}
+
+/// Signer of txs with its own nonces
+struct Signer {
+    provider: DynProvider,
+    address: Address,
+    nonces: Mutex<NonceManager>,
+}
+
+impl Signer {
+    /// Reserves the nonce for a new tx.
+    async fn reserve_nonce(&self) -> Result<u64> {
+        // hold the lock while fetching so that concurrent sends see each other's nonces
+        let mut nonces = self.nonces.lock().await;
+        let pending = self
+            .provider
+            .get_transaction_count(self.address)
+            .pending()
+            .await?;
+        Ok(nonces.next(pending))
+    }
+
+    async fn release_nonce(&self, nonce: u64) {
+        self.nonces.lock().await.finish(nonce);
+    }
+}
+
+/// Connection to ethereum shared by all the sends of the ad-server, so that the rpc connections
+/// are set up once and concurrent sends get sequential nonces.  The payloads are sent by a pool
+/// of signers, which lets a key be rotated by adding the new one before removing the old one.
+pub struct Eth {
+    signers: Vec<Signer>,
+    pool: SignerPool,
+}
+
+impl Eth {
+    pub async fn connect(cfg: &Config) -> Result<Self> {
+        let mut signers = Vec::with_capacity(cfg.priv_keys.len());
+        for priv_key in &cfg.priv_keys {
+            let signer: PrivateKeySigner = priv_key.parse()?;
+            let address = signer.address();
+            let provider = ProviderBuilder::new()
+                .wallet(signer)
+                .connect(&cfg.rpc_url)
+                .await?
+                .erased();
+            signers.push(Signer {
+                provider,
+                address,
+                nonces: Mutex::new(NonceManager::default()),
+            });
+        }
+        let provider = &signers.first().ok_or(anyhow!("no signers"))?.provider;
+        let latest_block = provider.get_block_number().await?;
+        info!(
+            "Connected to {}, latest block number: {latest_block}",
+            cfg.rpc_url
+        );
+        let eth = Self {
+            pool: SignerPool::new(
+                signers.len(),
+                cfg.signer_max_failures,
+                Duration::from_secs(cfg.signer_cooldown),
+            ),
+            signers,
+        };
+        eth.check_balances(cfg.signer_min_balance).await?;
+        Ok(eth)
+    }
+
+    /// Addresses of the signers, which must all be in the sender allowlist of the synchronizer
+    /// if it has one.
+    pub fn addresses(&self) -> Vec<Address> {
+        self.signers.iter().map(|signer| signer.address).collect()
+    }
+
+    /// Logs the balance of every signer, warning about the ones under `min_balance` wei.
+    pub async fn check_balances(&self, min_balance: u128) -> Result<()> {
+        for signer in &self.signers {
+            let balance = signer.provider.get_balance(signer.address).await?;
+            if balance < U256::from(min_balance) {
+                warn!(signer = %signer.address, %balance, min_balance, "signer balance is low");
+            } else {
+                info!(signer = %signer.address, %balance, "signer balance");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the balances of the signers every `cfg.signer_balance_check_interval` seconds, so that
+/// a signer running out of funds is noticed before its sends start failing.
+pub async fn check_balances_loop(ctx: Arc<Context>) {
+    let Some(eth) = &ctx.eth else {
+        return;
+    };
+    let mut check_interval = interval(Duration::from_secs(ctx.cfg.signer_balance_check_interval));
+    // the balances were checked on connect
+    check_interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = ctx.shutdown.cancelled() => break,
+            _ = check_interval.tick() => {}
+        }
+        if let Err(err) = eth.check_balances(ctx.cfg.signer_min_balance).await {
+            warn!("failed to check the signer balances: {:#}", err);
+        }
+    }
+}
+
+/// Failed send in which none of the txs reached the mempool, so the payload can be sent by
+/// another signer without risking a duplicate.
+#[derive(Debug, thiserror::Error)]
+#[error("tx not sent by {signer}: {err:#}")]
+struct NotSentError {
+    signer: Address,
+    err: anyhow::Error,
+}
+
+/// Cause of a failed send of a payload, surfaced in the `Error` state of its request so that the
+/// client and the operator can react to it, e.g. by topping up the signers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxErrorCode {
+    // the signers can't pay for the tx
+    InsufficientFunds,
+    // the rpc node rejected the requests over its rate limit
+    RateLimited,
+    // the fees reached `MAX_FEE_PERCENTAGE`, or the sends `MAX_SEND_ATTEMPTS`, before inclusion
+    FeeCeiling,
+    // the nonces of the signer kept being used by other txs
+    Nonce,
+}
+
+impl TxErrorCode {
+    /// Classifies the error of `send_payload`, none for the failures of another cause.
+    pub fn classify(err: &anyhow::Error) -> Option<Self> {
+        let message = format!("{:#}", err);
+        if message.contains("insufficient funds") {
+            Some(TxErrorCode::InsufficientFunds)
+        } else if message.contains("Too Many Requests") {
+            Some(TxErrorCode::RateLimited)
+        } else if message.contains("fee ceiling reached") {
+            Some(TxErrorCode::FeeCeiling)
+        } else if message.contains("nonce too low") {
+            Some(TxErrorCode::Nonce)
+        } else {
+            None
+        }
+    }
+}
+
+/// Gas used by a tx carrying `b` as calldata.
+fn calldata_gas(b: &[u8]) -> u64 {
+    let tokens: u64 = b.iter().map(|byte| if *byte == 0 { 1 } else { 4 }).sum();
+    TX_BASE_GAS + tokens * CALLDATA_FLOOR_GAS_PER_TOKEN
+}
+
+/// Sends the payload to ethereum in a blob tx or as calldata (depending on `cfg.posting_mode`)
+/// and returns the tx hash together with the versioned hash of the blob, which is the key under
+/// which the synchronizer indexes it, and the cost of the tx, which has the signer that sent it.
+/// Payloads sent as calldata have no blob versioned hash.  `eth` is `None` in test mode.
+pub async fn send_payload(
+    cfg: &Config,
+    eth: Option<&Eth>,
+    b: Vec<u8>,
+) -> Result<(TxHash, Option<B256>, TxCostInfo)> {
+    let Some(eth) = eth else {
+        // test mode, return a mock tx_hash and blob versioned hash, which cost nothing
+        return Ok((
+            TxHash::from([0u8; 32]),
+            Some(B256::ZERO),
+            TxCostInfo::default(),
+        ));
+    };
+    // must match the address the synchronizer filters on
+    if cfg.to_addr == Address::ZERO {
+        return Err(anyhow!("TO_ADDR must not be the zero address"));
+    }
+    with_failover(&eth.pool, &eth.signers, |signer| {
+        send_payload_with_signer(cfg, signer, &b)
+    })
+    .await
+}
+
+/// Runs `send` with the signers in the order of the pool until one succeeds.  The next signer is
+/// only tried after a `NotSentError`, since otherwise the tx of the failed signer may still be
+/// included.
+async fn with_failover<'a, S, T, F>(
+    pool: &SignerPool,
+    signers: &'a [S],
+    mut send: impl FnMut(&'a S) -> F,
+) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let mut last_err = anyhow!("no signers");
+    for index in pool.order(Instant::now()) {
+        match send(&signers[index]).await {
+            Ok(sent) => {
+                pool.record_success(index);
+                return Ok(sent);
+            }
+            Err(err) => {
+                if pool.record_failure(index, Instant::now()) {
+                    warn!(signer = index, "signer failed repeatedly, skipping it");
+                }
+                if !err.is::<NotSentError>() {
+                    return Err(err);
+                }
+                warn!("{:#}, trying the next signer", err);
+                last_err = err;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+async fn send_payload_with_signer(
+    cfg: &Config,
+    signer: &Signer,
+    b: &[u8],
+) -> Result<(TxHash, Option<B256>, TxCostInfo)> {
+    let provider = &signer.provider;
+    let sender = signer.address;
+    let receiver = cfg.to_addr;
     debug!("{}", sender);
     debug!("{}", receiver);
 
-    let sidecar: SidecarBuilder<SimpleCoder> = SidecarBuilder::from_slice(&b);
+    let use_blob = match cfg.posting_mode {
+        PostingMode::Blob => true,
+        PostingMode::Calldata => false,
+        PostingMode::Auto => {
+            let fees = provider.estimate_eip1559_fees().await?;
+            let blob_base_fee = provider.get_blob_base_fee().await?;
+            let blob_cost = TX_BASE_GAS as u128 * fees.max_fee_per_gas
+                + DATA_GAS_PER_BLOB as u128 * blob_base_fee;
+            let calldata_cost = calldata_gas(b) as u128 * fees.max_fee_per_gas;
+            info!(blob_cost, calldata_cost, "estimated payload posting costs");
+            blob_cost <= calldata_cost
+        }
+    };
+
+    if !use_blob {
+        let tx = TransactionRequest::default()
+            .with_to(receiver)
+            .with_input(b.to_vec());
+        let (receipt, tx_hash) = send_tx(cfg, signer, tx).await?;
+        check_receipt(&receipt, sender, receiver)?;
+        info!(%tx_hash, %sender, "payload posted as calldata");
+        return Ok((tx_hash, None, tx_cost(&receipt)));
+    }
+
+    let sidecar: SidecarBuilder<SimpleCoder> = SidecarBuilder::from_slice(b);
     let sidecar = sidecar.build()?;
+    let blob_versioned_hash = match sidecar.commitments.as_slice() {
+        [commitment] => kzg_to_versioned_hash(commitment.as_slice()),
+        commitments => {
+            return Err(anyhow!(
+                "expected payload to fit in 1 blob, got {}",
+                commitments.len()
+            ));
+        }
+    };
+    debug!("blob versioned hash: {}", blob_versioned_hash);
+
+    let tx = TransactionRequest::default()
+        .with_to(receiver)
+        .with_blob_sidecar(sidecar);
+    let (receipt, tx_hash) = send_tx(cfg, signer, tx).await?;
+    check_receipt(&receipt, sender, receiver)?;
+
+    let blob_gas_used = receipt
+        .blob_gas_used
+        .ok_or(anyhow!("expected EIP-4844 tx"))?;
+    if blob_gas_used != DATA_GAS_PER_BLOB {
+        return Err(anyhow!(
+            "blob_gas_used: {} != DATA_GAS_PER_BLOB: {}",
+            blob_gas_used,
+            DATA_GAS_PER_BLOB
+        ));
+    }
 
-    let (receipt, tx_hash) = send_tx(cfg, provider, sender, receiver, sidecar).await?;
+    // check that the included tx carries the blob we built
+    let tx = provider
+        .get_transaction_by_hash(tx_hash)
+        .await?
+        .ok_or(anyhow!("tx {} not found", tx_hash))?;
+    let tx_blob_versioned_hashes = tx
+        .blob_versioned_hashes()
+        .ok_or(anyhow!("expected EIP-4844 tx"))?;
+    if tx_blob_versioned_hashes != [blob_versioned_hash] {
+        return Err(anyhow!(
+            "tx blob_versioned_hashes: {:?} != [{}]",
+            tx_blob_versioned_hashes,
+            blob_versioned_hash
+        ));
+    }
 
+    info!(%tx_hash, %blob_versioned_hash, %sender, "payload posted in a blob");
+    Ok((tx_hash, Some(blob_versioned_hash), tx_cost(&receipt)))
+}
+
+fn check_receipt(receipt: &TransactionReceipt, sender: Address, receiver: Address) -> Result<()> {
     info!(
         "Transaction included in block {}",
         receipt.block_number.expect("Failed to get block number")
@@ -57,51 +498,110 @@ pub async fn send_payload(cfg: &Config, b: Vec<u8>) -> Result<TxHash> {
             receiver
         ));
     }
-    let blob_gas_used = receipt
-        .blob_gas_used
-        .ok_or(anyhow!("expected EIP-4844 tx"))?;
-    if blob_gas_used != DATA_GAS_PER_BLOB {
+    Ok(())
+}
+
+/// Returns the doubled fee percentage for resending a tx after `attempts` sends, or an error once
+/// it would go over `max_fee_percentage` or `max_send_attempts` sends have been made.
+fn bump_fee_percentage(
+    fee_percentage: u128,
+    attempts: u32,
+    max_fee_percentage: u128,
+    max_send_attempts: u32,
+) -> Result<u128> {
+    let fee_percentage = fee_percentage * 2;
+    if fee_percentage > max_fee_percentage || attempts >= max_send_attempts {
         return Err(anyhow!(
-            "blob_gas_used: {} != DATA_GAS_PER_BLOB: {}",
-            blob_gas_used,
-            DATA_GAS_PER_BLOB
+            "fee ceiling reached: fee_percentage {} (max {}), attempts {} (max {})",
+            fee_percentage,
+            max_fee_percentage,
+            attempts,
+            max_send_attempts
         ));
     }
-
-    Ok(tx_hash)
+    Ok(fee_percentage)
 }
 
-#[allow(clippy::too_many_arguments)]
+/// Sends `tx_base` (which already has its receiver and data or blob sidecar set) with the next
+/// nonce of the signer.  Fails with a `NotSentError` if none of the txs sent reached the mempool.
 async fn send_tx(
     cfg: &Config,
-    provider: impl alloy::providers::Provider + 'static,
-    sender: Address,
-    receiver: Address,
-    sidecar: alloy::eips::eip4844::BlobTransactionSidecar,
+    signer: &Signer,
+    tx_base: TransactionRequest,
 ) -> Result<(TransactionReceipt, TxHash)> {
+    let mut sent = Vec::new();
+    let result = match signer.reserve_nonce().await {
+        Ok(mut nonce) => {
+            let result = send_tx_with_nonce(cfg, signer, tx_base, &mut nonce, &mut sent).await;
+            signer.release_nonce(nonce).await;
+            result
+        }
+        Err(err) => Err(err),
+    };
+    result.map_err(|err| {
+        if sent.is_empty() {
+            NotSentError {
+                signer: signer.address,
+                err,
+            }
+            .into()
+        } else {
+            err
+        }
+    })
+}
+
+/// Returns the hash of the tx among `sent` that was included, if any.
+async fn find_included(provider: &DynProvider, sent: &[TxHash]) -> Result<Option<TxHash>> {
+    for tx_hash in sent.iter().rev() {
+        if provider.get_transaction_receipt(*tx_hash).await?.is_some() {
+            return Ok(Some(*tx_hash));
+        }
+    }
+    Ok(None)
+}
+
+/// Sends `tx_base` with `nonce` and increasing fees until it's included, bounded by
+/// `cfg.max_fee_percentage` and `cfg.max_send_attempts`.  Moves to a new nonce if `nonce` turns
+/// out to be used by a tx that isn't ours.  Returns the receipt of the tx that was included,
+/// which may be any of the replacements sent, which are kept in `sent`.
+async fn send_tx_with_nonce(
+    cfg: &Config,
+    signer: &Signer,
+    tx_base: TransactionRequest,
+    nonce: &mut u64,
+    sent: &mut Vec<TxHash>,
+) -> Result<(TransactionReceipt, TxHash)> {
+    let provider = &signer.provider;
     let fees = provider.estimate_eip1559_fees().await?;
-    let blob_base_fee = provider.get_blob_base_fee().await?;
+    let blob_base_fee = if tx_base.sidecar.is_some() {
+        Some(provider.get_blob_base_fee().await?)
+    } else {
+        None
+    };
     // for a new tx, increase gas price by 10% to reduce the chances of the
     // nodes rejecting it (in practice increase it by 11% to ensure it passes
     // the miner filter)
     let mut fee_percentage: u128 = 111;
-    let nonce = provider.get_transaction_count(sender).latest().await?;
-    let mut tx_hash_prev = None;
+    let mut attempts: u32 = 0;
+    // `sent` has the txs sent with the current nonce, the later ones replacing the earlier ones
     let tx_hash = loop {
-        let tx = TransactionRequest::default()
+        let mut tx = tx_base
+            .clone()
             .with_max_fee_per_gas(fees.max_fee_per_gas * fee_percentage / 100)
             .with_max_priority_fee_per_gas(fees.max_priority_fee_per_gas * fee_percentage / 100)
-            .with_max_fee_per_blob_gas(blob_base_fee * fee_percentage / 100)
-            .with_to(receiver)
-            .with_nonce(nonce)
-            .with_blob_sidecar(sidecar.clone());
+            .with_nonce(*nonce);
+        if let Some(blob_base_fee) = blob_base_fee {
+            tx = tx.with_max_fee_per_blob_gas(blob_base_fee * fee_percentage / 100);
+        }
 
         debug!(
             max_fee_per_gas = tx.max_fee_per_gas.unwrap(),
             max_priority_fee_per_gas = tx.max_priority_fee_per_gas.unwrap(),
-            max_fee_per_blob_gas = tx.max_fee_per_blob_gas.unwrap()
+            max_fee_per_blob_gas = ?tx.max_fee_per_blob_gas
         );
 
+        attempts += 1;
         let send_tx_result = provider.send_transaction(tx).await;
         let pending_tx_result = match send_tx_result {
             Ok(pending_tx_result) => pending_tx_result,
@@ -110,15 +610,70 @@ async fn send_tx(
                     // NOTE: this assumes we're using infura for the rpc_url
                     return Err(anyhow!("rpc-error: {}", e));
                 }
+                if e.to_string().contains("insufficient funds") {
+                    // one of the txs sent with this nonce may have been included in the meantime
+                    if let Some(tx_hash) = find_included(provider, sent).await? {
+                        break tx_hash;
+                    }
+                    if sent.is_empty() {
+                        // higher fees won't help, the next signer may have the funds
+                        warn!(signer = %signer.address, "signer can't pay for the tx, top it up");
+                        return Err(anyhow!("insufficient funds: {}", e));
+                    }
+                    // the replacement can't be paid for but the tx it replaces is still pending,
+                    // so moving to the next signer could get the payload included twice
+                    warn!(signer = %signer.address, "signer can't pay for the replacement tx");
+                    if attempts >= cfg.max_send_attempts {
+                        return Err(anyhow!(
+                            "tx with nonce {} still pending after {} attempts: {}",
+                            nonce,
+                            attempts,
+                            e
+                        ));
+                    }
+                    info!("waiting for the pending tx with nonce {}", nonce);
+                    sleep(Duration::from_secs(10)).await;
+                    continue;
+                }
                 if e.to_string().contains("nonce too low") {
-                    break tx_hash_prev.expect("resend tx with more gas");
+                    // either one of our txs with this nonce got included or the nonce was used
+                    // by a tx that isn't ours
+                    if let Some(tx_hash) = find_included(provider, sent).await? {
+                        break tx_hash;
+                    }
+                    if attempts >= cfg.max_send_attempts {
+                        return Err(anyhow!("nonce too low after {} attempts", attempts));
+                    }
+                    info!("nonce {} already used, sending tx with a new nonce", nonce);
+                    signer.release_nonce(*nonce).await;
+                    *nonce = signer.reserve_nonce().await?;
+                    sent.clear();
+                    continue;
+                }
+                if e.to_string().contains("already known")
+                    || e.to_string()
+                        .contains("replacement transaction underpriced")
+                {
+                    // a tx with this nonce is in the mempool, replace it with higher fees
+                    fee_percentage = bump_fee_percentage(
+                        fee_percentage,
+                        attempts,
+                        cfg.max_fee_percentage,
+                        cfg.max_send_attempts,
+                    )?;
+                    info!("replacing tx with nonce {} with 2x gas price", nonce);
+                    continue;
                 }
 
                 info!("send tx err: {}", e);
+                fee_percentage = bump_fee_percentage(
+                    fee_percentage,
+                    attempts,
+                    cfg.max_fee_percentage,
+                    cfg.max_send_attempts,
+                )?;
                 info!("sending tx again with 2x gas price in 10s");
                 sleep(Duration::from_secs(10)).await;
-
-                fee_percentage *= 2;
                 continue;
             }
         };
@@ -128,7 +683,7 @@ async fn send_tx(
             "watching pending tx {}, timeout of {}",
             tx_hash, cfg.tx_watch_timeout
         );
-        tx_hash_prev = Some(tx_hash);
+        sent.push(tx_hash);
         let pending_tx_result = pending_tx_result
             .with_timeout(Some(std::time::Duration::from_secs(cfg.tx_watch_timeout)))
             .watch()
@@ -138,14 +693,18 @@ async fn send_tx(
             Ok(pending_tx) => pending_tx,
             Err(e) => {
                 if e.to_string().contains("Too Many Requests") {
-                    panic!("error: {}", e);
+                    return Err(anyhow!("rpc-error: {}", e));
                 }
 
                 info!("wait tx err: {}", e);
+                fee_percentage = bump_fee_percentage(
+                    fee_percentage,
+                    attempts,
+                    cfg.max_fee_percentage,
+                    cfg.max_send_attempts,
+                )?;
                 info!("sending tx again with 2x gas price in 2s");
                 sleep(Duration::from_secs(2)).await;
-
-                fee_percentage *= 2;
                 continue;
             }
         };
@@ -160,6 +719,168 @@ async fn send_tx(
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_bump_fee_percentage() {
+        assert_eq!(bump_fee_percentage(111, 1, 1000, 4).unwrap(), 222);
+        assert_eq!(bump_fee_percentage(444, 3, 1000, 4).unwrap(), 888);
+        // over the fee ceiling
+        assert!(bump_fee_percentage(888, 4, 1000, 8).is_err());
+        // out of attempts
+        assert!(bump_fee_percentage(111, 4, 1000, 4).is_err());
+    }
+
+    #[test]
+    fn test_tx_cost_info() {
+        let cost = TxCostInfo::new(21_000, 2_000_000_000, DATA_GAS_PER_BLOB, 3);
+        assert_eq!(
+            cost.total_fee,
+            21_000 * 2_000_000_000 + DATA_GAS_PER_BLOB as u128 * 3
+        );
+        // calldata tx
+        let cost = TxCostInfo::new(30_000, 5, 0, 0);
+        assert_eq!(cost.total_fee, 150_000);
+    }
+
+    #[test]
+    fn test_nonce_manager() {
+        let mut nonces = NonceManager::default();
+        assert_eq!(nonces.next(5), 5);
+        // the node doesn't see the first tx yet
+        assert_eq!(nonces.next(5), 6);
+        nonces.finish(5);
+        assert_eq!(nonces.next(6), 7);
+        // the tx with nonce 6 was dropped, leaving a gap
+        nonces.finish(6);
+        assert_eq!(nonces.next(6), 6);
+        // txs sent by somebody else with the same key
+        nonces.finish(6);
+        nonces.finish(7);
+        assert_eq!(nonces.next(10), 10);
+    }
+
+    #[test]
+    fn test_tx_error_code() {
+        let code = |message: &str| TxErrorCode::classify(&anyhow!(message.to_string()));
+        let not_sent = NotSentError {
+            signer: Address::ZERO,
+            err: anyhow!("insufficient funds: insufficient funds for gas * price + value"),
+        };
+        assert_eq!(
+            TxErrorCode::classify(&not_sent.into()),
+            Some(TxErrorCode::InsufficientFunds)
+        );
+        assert_eq!(
+            code("rpc-error: HTTP error 429 with body: Too Many Requests"),
+            Some(TxErrorCode::RateLimited)
+        );
+        let ceiling = bump_fee_percentage(888, 4, 1000, 8).unwrap_err();
+        assert_eq!(
+            TxErrorCode::classify(&ceiling),
+            Some(TxErrorCode::FeeCeiling)
+        );
+        assert_eq!(
+            code("nonce too low after 4 attempts"),
+            Some(TxErrorCode::Nonce)
+        );
+        assert_eq!(code("expected EIP-4844 tx"), None);
+    }
+
+    #[test]
+    fn test_calldata_gas() -> anyhow::Result<()> {
+        assert_eq!(calldata_gas(&[]), TX_BASE_GAS);
+        assert_eq!(
+            calldata_gas(&[0, 1, 0, 2]),
+            TX_BASE_GAS + (1 + 4 + 1 + 4) * 10
+        );
+        assert_eq!(PostingMode::from_str("auto")?, PostingMode::Auto);
+        assert!(PostingMode::from_str("blobs").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_priv_keys() {
+        assert_eq!(parse_priv_keys(" 0x01, 0x02,", "0x03"), ["0x01", "0x02"]);
+        assert_eq!(parse_priv_keys("", "0x03"), ["0x03"]);
+        // test mode
+        assert!(parse_priv_keys("", "").is_empty());
+    }
+
+    /// Signer whose sends fail with `err` while it's set
+    struct MockSigner {
+        err: std::sync::Mutex<Option<fn(Address) -> anyhow::Error>>,
+        address: Address,
+        sends: std::sync::Mutex<u32>,
+    }
+
+    impl MockSigner {
+        fn new(address: u8) -> Self {
+            Self {
+                err: std::sync::Mutex::new(None),
+                address: Address::repeat_byte(address),
+                sends: std::sync::Mutex::new(0),
+            }
+        }
+
+        async fn send(&self) -> Result<Address> {
+            *self.sends.lock().unwrap() += 1;
+            match *self.err.lock().unwrap() {
+                Some(err) => Err(err(self.address)),
+                None => Ok(self.address),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signer_failover() -> anyhow::Result<()> {
+        fn not_sent(signer: Address) -> anyhow::Error {
+            NotSentError {
+                signer,
+                err: anyhow!("insufficient funds"),
+            }
+            .into()
+        }
+        fn not_included(_: Address) -> anyhow::Error {
+            anyhow!("tx not included")
+        }
+        let signers = [MockSigner::new(1), MockSigner::new(2)];
+        let sends = |i: usize| *signers[i].sends.lock().unwrap();
+        let pool = SignerPool::new(2, 2, Duration::from_secs(60));
+        let send = async || with_failover(&pool, &signers, MockSigner::send).await;
+
+        // round robin
+        assert_eq!(send().await?, signers[0].address);
+        assert_eq!(send().await?, signers[1].address);
+
+        // the first signer is drained, its payloads are sent by the second one
+        *signers[0].err.lock().unwrap() = Some(not_sent);
+        assert_eq!(send().await?, signers[1].address);
+        assert_eq!(send().await?, signers[1].address);
+        assert_eq!(sends(0), 2);
+        // the second failure starts the cooldown of the first signer, which isn't tried anymore
+        assert_eq!(send().await?, signers[1].address);
+        assert_eq!(send().await?, signers[1].address);
+        assert_eq!(send().await?, signers[1].address);
+        assert_eq!((sends(0), sends(1)), (3, 6));
+        let now = Instant::now();
+        assert_eq!([pool.order(now), pool.order(now)], [[1, 0], [1, 0]]);
+        // until the cooldown ends
+        let later = now + Duration::from_secs(61);
+        assert_eq!([pool.order(later), pool.order(later)], [[1, 0], [0, 1]]);
+
+        // the tx may still be included, so the payload isn't sent again by another signer
+        *signers[0].err.lock().unwrap() = None;
+        *signers[1].err.lock().unwrap() = Some(not_included);
+        assert!(send().await.is_err());
+        assert_eq!((sends(0), sends(1)), (3, 7));
+
+        // every signer is tried
+        *signers[0].err.lock().unwrap() = Some(not_sent);
+        *signers[1].err.lock().unwrap() = Some(not_sent);
+        assert!(send().await.unwrap_err().is::<NotSentError>());
+        assert_eq!((sends(0), sends(1)), (4, 8));
+        Ok(())
+    }
+
     // this test is mostly to check the send_payload method isolated from the
     // rest of the AD server logic.
     // To run it:
@@ -172,8 +893,10 @@ mod tests {
         let cfg = Config::from_env()?;
         println!("Loaded config: {:?}", cfg);
 
-        let tx_hash = send_payload(&cfg, b"test".to_vec()).await?;
-        dbg!(tx_hash);
+        let eth = Eth::connect(&cfg).await?;
+        let (tx_hash, blob_versioned_hash, cost) =
+            send_payload(&cfg, Some(&eth), b"test".to_vec()).await?;
+        dbg!(tx_hash, blob_versioned_hash, cost);
 
         Ok(())
     }
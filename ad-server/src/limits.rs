@@ -0,0 +1,195 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use subtle::ConstantTimeEq;
+use warp::{Filter, Rejection};
+
+use crate::Error;
+
+// buckets that have refilled are dropped once this many client IPs are tracked
+const MAX_TRACKED_IPS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token-bucket rate limiter keyed by client IP.  Each client can do `burst` requests at once,
+/// refilled at `per_minute` requests per minute.
+pub struct RateLimiter {
+    // 0 disables the rate limiting
+    per_minute: u32,
+    burst: u32,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_minute: u32, burst: u32) -> Self {
+        Self {
+            per_minute,
+            burst: burst.max(1),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket of `ip`, or returns the number of seconds until one is
+    /// available.
+    fn check(&self, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        if self.per_minute == 0 {
+            return Ok(());
+        }
+        // tokens per second
+        let rate = self.per_minute as f64 / 60.0;
+        let burst = self.burst as f64;
+        let refill = |bucket: &Bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated);
+            (bucket.tokens + elapsed.as_secs_f64() * rate).min(burst)
+        };
+
+        let mut buckets = self.buckets.lock().expect("lock");
+        if buckets.len() >= MAX_TRACKED_IPS {
+            buckets.retain(|_, bucket| refill(bucket) < burst);
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / rate).ceil() as u64)
+        }
+    }
+}
+
+/// Rejects with `Error::RateLimited` once the client IP goes over the rate limit.
+pub fn rate_limit(
+    limiter: Arc<RateLimiter>,
+) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::addr::remote()
+        .and_then(move |addr: Option<SocketAddr>| {
+            let limiter = limiter.clone();
+            async move {
+                // requests without a remote address share a single bucket
+                let ip = addr
+                    .map(|addr| addr.ip())
+                    .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+                limiter
+                    .check(ip, Instant::now())
+                    .map_err(|retry_after| warp::reject::custom(Error::RateLimited(retry_after)))
+            }
+        })
+        .untuple_one()
+}
+
+/// Rejects with `Error::Unauthorized` unless the request carries `Authorization: Bearer
+/// {token}`.  Lets every request through when `token` is `None`.  The tokens are compared in
+/// constant time, so that the time of a rejection doesn't tell how much of the token matched.
+pub fn bearer_auth(token: Option<String>) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let token = token.clone();
+            async move {
+                let Some(token) = token else {
+                    return Ok(());
+                };
+                match header.as_deref().and_then(|h| h.strip_prefix("Bearer ")) {
+                    Some(req_token) if req_token.as_bytes().ct_eq(token.as_bytes()).into() => {
+                        Ok(())
+                    }
+                    _ => Err(warp::reject::custom(Error::Unauthorized)),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, time::Duration};
+
+    use warp::{Reply, http::StatusCode};
+
+    use super::*;
+    use crate::endpoints::handle_rejection;
+
+    fn ok_route(
+        filter: impl Filter<Extract = (), Error = Rejection> + Clone + Send + Sync + 'static,
+    ) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone {
+        filter.map(|| "ok").recover(handle_rejection)
+    }
+
+    #[test]
+    fn test_rate_limiter_check() {
+        let limiter = RateLimiter::new(60, 2);
+        let ip_a = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let ip_b = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let now = Instant::now();
+        assert_eq!(limiter.check(ip_a, now), Ok(()));
+        assert_eq!(limiter.check(ip_a, now), Ok(()));
+        assert_eq!(limiter.check(ip_a, now), Err(1));
+        // buckets are per IP
+        assert_eq!(limiter.check(ip_b, now), Ok(()));
+        // refilled at 1 token per second
+        assert_eq!(limiter.check(ip_a, now + Duration::from_secs(1)), Ok(()));
+        assert_eq!(limiter.check(ip_a, now + Duration::from_secs(1)), Err(1));
+
+        // disabled
+        let limiter = RateLimiter::new(0, 1);
+        for _ in 0..10 {
+            assert_eq!(limiter.check(ip_a, now), Ok(()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_filter() {
+        let api = ok_route(rate_limit(Arc::new(RateLimiter::new(1, 1))));
+        let addr: SocketAddr = ([10, 0, 0, 1], 1234).into();
+
+        let res = warp::test::request().remote_addr(addr).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = warp::test::request().remote_addr(addr).reply(&api).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers()["retry-after"], "60");
+
+        // another client isn't limited
+        let res = warp::test::request()
+            .remote_addr(([10, 0, 0, 2], 1234).into())
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_bearer_auth_filter() {
+        let api = ok_route(bearer_auth(Some("secret".to_string())));
+
+        let res = warp::test::request().reply(&api).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        for header in ["Bearer wrong", "Bearer secre", "Bearer secret2", "secret"] {
+            let res = warp::test::request()
+                .header("authorization", header)
+                .reply(&api)
+                .await;
+            assert_eq!(res.status(), StatusCode::UNAUTHORIZED, "{}", header);
+        }
+        let res = warp::test::request()
+            .header("authorization", "Bearer secret")
+            .reply(&api)
+            .await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // no token configured
+        let api = ok_route(bearer_auth(None));
+        let res = warp::test::request().reply(&api).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
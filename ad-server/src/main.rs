@@ -1,35 +1,73 @@
 #![allow(clippy::uninlined_format_args)]
-use std::{collections::HashMap, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    net::IpAddr,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
 
 use alloy::primitives::Address;
-use anyhow::{Context as _, Result};
-use app::{Predicates, RevPredicates, build_predicates};
+use anyhow::{Context as _, Result, bail};
+use app::{
+    AppPredicates, Genesis, Group, Helper, KvPredicates, Op, Predicates, RevPredicates,
+    USER_ID_MAX_LEN, UserId, build_predicates_with,
+};
+use clap::{Parser, Subcommand};
 use common::{
     ProofType,
+    disk::{PodKey, PodStore},
+    params::{PodParamsConfig, vd_set},
     shrink::{ShrunkMainPodBuild, ShrunkMainPodSetup},
 };
+use hex::ToHex;
+use lru::LruCache;
 use pod2::{
-    backends::plonky2::basetypes::DEFAULT_VD_SET,
-    middleware::{Params, VDSet},
+    backends::plonky2::mock::mainpod::MockProver,
+    frontend::{MainPod, MainPodBuilder},
+    middleware::{
+        CustomPredicateBatch, CustomPredicateRef, Key, Params, VDSet, Value,
+        containers::{Dictionary, Set},
+    },
 };
+use serde::{Deserialize, Serialize};
 use sqlx::{
     migrate::MigrateDatabase,
-    sqlite::{Sqlite, SqlitePool},
+    sqlite::{Sqlite, SqliteJournalMode, SqlitePool, SqliteSynchronous},
 };
 use tokio::{
+    signal::{
+        self,
+        unix::{SignalKind, signal},
+    },
     sync::{
-        RwLock,
+        RwLock, Semaphore,
         mpsc::{self, Sender},
+        watch,
     },
     task,
 };
-use tracing::{info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
+pub mod archive;
+pub mod audit;
+pub mod bloom;
 pub mod db;
 pub mod endpoints;
+pub mod error;
 pub mod eth;
+pub mod limits;
+pub mod metrics;
+pub mod mirror;
 pub mod queue;
+pub mod recover;
+pub mod snapshot;
+
+pub use error::Error;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -37,16 +75,93 @@ pub struct Config {
     pub rpc_url: String,
     // The path to the sqlite database (it will be a file)
     pub sqlite_path: String,
+    // sqlite journal mode, the sqlite default if unset.  WAL lets the queries read while an
+    // update is being written
+    pub sqlite_journal_mode: Option<SqliteJournalMode>,
+    // sqlite synchronous setting, the sqlite default (FULL) if unset
+    pub sqlite_synchronous: Option<SqliteSynchronous>,
     // The path to store pods
     pub pods_path: String,
-    // Ethereum private key to send txs
-    pub priv_key: String,
+    // Ethereum private keys of the signers that send the txs, none in test mode
+    pub priv_keys: Vec<String>,
+    // Consecutive failed sends after which a signer is skipped, and for how many seconds
+    pub signer_max_failures: u32,
+    pub signer_cooldown: u64,
+    // Balance in wei under which a warning is logged for a signer (0 disables the warnings)
+    pub signer_min_balance: u128,
+    // Interval in seconds between checks of the balances of the signers (0 disables the checks)
+    pub signer_balance_check_interval: u64,
     // The address that receives AD update via blobs
     pub to_addr: Address,
     pub tx_watch_timeout: u64,
+    // Max fee, as a percentage of the estimated one, a tx is resent with
+    pub max_fee_percentage: u128,
+    // Max number of times a tx is sent before giving up
+    pub max_send_attempts: u32,
     // set the proving system used to generate the proofs being sent to ethereum
     //   options: plonky2 / groth16
     pub proof_type: ProofType,
+    // build the shrunk main pod circuit of the plonky2 proofs with zero-knowledge
+    pub shrink_zk: bool,
+    // Max number of requests waiting in the queue, the endpoints that enqueue requests return 503
+    // once it's full.  On a mirror, max number of queries in progress.
+    pub queue_len: usize,
+    // Max number of updates of a membership list proven in one main pod and sent in one payload.
+    // The updates of a list that arrive while one of its updates is queued or in progress are
+    // buffered, up to `queue_len` per list, and proven together once it completes (1 disables
+    // the batching).  The setup fails if a batch of this size doesn't fit in a pod of the params.
+    pub update_batch_max: usize,
+    // Max number of loaded pods kept in memory
+    pub pod_cache_size: NonZeroUsize,
+    // gzip level of the stored pods, from 1 (fastest) to 9 (smallest), 0 stores them
+    // uncompressed
+    pub pod_compression_level: u32,
+    // Number of pods kept per membership list when pruning (0 disables pruning)
+    pub pods_retain_last_n: usize,
+    // Interval in seconds between pod prunings
+    pub pods_prune_interval: u64,
+    // Interval in seconds between checks of the reverse indexes that fell behind their
+    // membership lists (0 disables the checks)
+    pub rev_reconcile_interval: u64,
+    // Max number of times the wrapping of a proven main pod is attempted
+    pub wrap_max_attempts: i64,
+    // Time after which the proving of a main pod is given up, none if it's unbounded
+    pub proving_timeout: Option<Duration>,
+    // Publish a snapshot of the full state every this many updates (0 disables snapshots)
+    pub snapshot_interval: i64,
+    // Disclose the ops in the update payloads, so that the synchronizer can tell when each user
+    // joined and left the groups.  Like snapshots, this publishes the users.
+    pub disclose_ops: bool,
+    // how payloads are posted to ethereum
+    //   options: blob / calldata / auto
+    pub posting_mode: eth::PostingMode,
+    // Requests per minute allowed per client IP on the public GET endpoints (0 disables the
+    // rate limiting)
+    pub rate_limit_per_minute: u32,
+    // Requests a client IP can do at once before being rate limited
+    pub rate_limit_burst: u32,
+    // Bearer token required by the mutating endpoints, not required if unset
+    pub auth_token: Option<String>,
+    // Max length of the group and user names of the ops accepted by the update endpoint, at most
+    // `app::USER_ID_MAX_LEN`
+    pub name_max_len: usize,
+    // Groups of the lists after their init op, which are part of the predicates of the pods
+    pub genesis: Genesis,
+    // Params of the pods, the defaults with the overrides of the POD_* vars, which must be the
+    // ones of the synchronizer
+    pub params: Params,
+    // Whether the server owns its lists or is a read-only mirror of the ones of another server
+    pub server_mode: mirror::ServerMode,
+    // URL of the primary server the mirror points the rejected requests to, set in mirror mode
+    pub primary_url: Option<String>,
+    // URL of the synchronizer the mirror takes the states of the lists from, set in mirror mode.
+    // Optional on a primary, where the audit checks the lists against the chain with it
+    pub synchronizer_url: Option<String>,
+    // Interval in seconds between refreshes of the mirrored lists
+    pub mirror_refresh_interval: u64,
+    // Address and port the HTTP server listens on
+    pub http_bind: IpAddr,
+    pub http_port: u16,
 }
 
 impl Config {
@@ -54,14 +169,84 @@ impl Config {
         fn var(v: &str) -> Result<String> {
             dotenvy::var(v).with_context(|| v.to_string())
         }
+        let server_mode = mirror::ServerMode::from_str(&var("SERVER_MODE")?)?;
+        let mirror_var = |v: &str| -> Result<Option<String>> {
+            let value = dotenvy::var(v).ok().filter(|value| !value.is_empty());
+            if server_mode == mirror::ServerMode::Mirror && value.is_none() {
+                bail!("{} must be set in mirror mode", v);
+            }
+            Ok(value)
+        };
         Ok(Self {
             rpc_url: var("RPC_URL")?,
             sqlite_path: var("AD_SERVER_SQLITE_PATH")?,
+            sqlite_journal_mode: dotenvy::var("AD_SERVER_SQLITE_JOURNAL_MODE")
+                .ok()
+                .filter(|m| !m.is_empty())
+                .map(|m| SqliteJournalMode::from_str(&m))
+                .transpose()?,
+            sqlite_synchronous: dotenvy::var("AD_SERVER_SQLITE_SYNCHRONOUS")
+                .ok()
+                .filter(|s| !s.is_empty())
+                .map(|s| SqliteSynchronous::from_str(&s))
+                .transpose()?,
             pods_path: var("PODS_PATH")?,
-            priv_key: var("PRIV_KEY")?,
+            priv_keys: eth::parse_priv_keys(
+                &dotenvy::var("PRIV_KEYS").unwrap_or_default(),
+                &dotenvy::var("PRIV_KEY").unwrap_or_default(),
+            ),
+            signer_max_failures: u32::from_str(&var("SIGNER_MAX_FAILURES")?)?,
+            signer_cooldown: u64::from_str(&var("SIGNER_COOLDOWN")?)?,
+            signer_min_balance: u128::from_str(&var("SIGNER_MIN_BALANCE")?)?,
+            signer_balance_check_interval: u64::from_str(&var("SIGNER_BALANCE_CHECK_INTERVAL")?)?,
             to_addr: Address::from_str(&var("TO_ADDR")?)?,
             tx_watch_timeout: u64::from_str(&var("TX_WATCH_TIMEOUT")?)?,
+            max_fee_percentage: u128::from_str(&var("MAX_FEE_PERCENTAGE")?)?,
+            max_send_attempts: u32::from_str(&var("MAX_SEND_ATTEMPTS")?)?,
             proof_type: ProofType::from_str(&var("PROOF_TYPE")?)?,
+            shrink_zk: bool::from_str(&var("SHRINK_ZK")?)?,
+            queue_len: match usize::from_str(&var("AD_SERVER_QUEUE_LEN")?)? {
+                0 => bail!("AD_SERVER_QUEUE_LEN must be greater than 0"),
+                len => len,
+            },
+            update_batch_max: match usize::from_str(&var("UPDATE_BATCH_MAX")?)? {
+                0 => bail!("UPDATE_BATCH_MAX must be greater than 0"),
+                max => max,
+            },
+            pod_cache_size: NonZeroUsize::from_str(&var("POD_CACHE_SIZE")?)?,
+            pod_compression_level: match u32::from_str(&var("POD_COMPRESSION_LEVEL")?)? {
+                level @ 0..=9 => level,
+                level => bail!("POD_COMPRESSION_LEVEL {} is not in 0..=9", level),
+            },
+            pods_retain_last_n: usize::from_str(&var("PODS_RETAIN_LAST_N")?)?,
+            pods_prune_interval: u64::from_str(&var("PODS_PRUNE_INTERVAL")?)?,
+            rev_reconcile_interval: u64::from_str(&var("REV_RECONCILE_INTERVAL")?)?,
+            wrap_max_attempts: i64::from_str(&var("WRAP_MAX_ATTEMPTS")?)?,
+            proving_timeout: match u64::from_str(&var("PROVING_TIMEOUT_SECS")?)? {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            snapshot_interval: i64::from_str(&var("SNAPSHOT_INTERVAL")?)?,
+            disclose_ops: bool::from_str(&var("DISCLOSE_OPS")?)?,
+            posting_mode: eth::PostingMode::from_str(&var("POSTING_MODE")?)?,
+            rate_limit_per_minute: u32::from_str(&var("RATE_LIMIT_PER_MINUTE")?)?,
+            rate_limit_burst: u32::from_str(&var("RATE_LIMIT_BURST")?)?,
+            auth_token: dotenvy::var("AUTH_TOKEN").ok().filter(|t| !t.is_empty()),
+            name_max_len: match usize::from_str(&var("NAME_MAX_LEN")?)? {
+                len @ 1..=USER_ID_MAX_LEN => len,
+                len => bail!("NAME_MAX_LEN {} is not in 1..={}", len, USER_ID_MAX_LEN),
+            },
+            genesis: Genesis::from_str(&var("GENESIS_GROUPS")?)?,
+            params: PodParamsConfig::from_env()?.params()?,
+            server_mode,
+            primary_url: mirror_var("PRIMARY_URL")?,
+            synchronizer_url: mirror_var("SYNCHRONIZER_URL")?,
+            mirror_refresh_interval: match u64::from_str(&var("MIRROR_REFRESH_INTERVAL")?)? {
+                0 => bail!("MIRROR_REFRESH_INTERVAL must be greater than 0"),
+                secs => secs,
+            },
+            http_bind: IpAddr::from_str(&var("AD_SERVER_HTTP_BIND")?)?,
+            http_port: u16::from_str(&var("AD_SERVER_HTTP_PORT")?)?,
         })
     }
 }
@@ -70,35 +255,249 @@ impl Config {
 pub struct PodConfig {
     params: Params,
     vd_set: VDSet,
+    // the batches of `state_predicates`, `rev_predicates` and `kv_predicates`
+    batches: Vec<Arc<CustomPredicateBatch>>,
+    // name and podlang source of each of `batches`
+    sources: Vec<(String, String)>,
     state_predicates: Predicates,
     rev_predicates: RevPredicates,
+    kv_predicates: KvPredicates,
+}
+
+impl PodConfig {
+    pub fn new(params: Params, vd_set: VDSet) -> Self {
+        Self::with_genesis(params, vd_set, &Genesis::default())
+    }
+
+    /// Config of the pods of the lists that start with the groups of `genesis`
+    pub fn with_genesis(params: Params, vd_set: VDSet, genesis: &Genesis) -> Self {
+        let AppPredicates {
+            state,
+            rev,
+            kv,
+            batches,
+            sources,
+        } = build_predicates_with(&params, genesis);
+        Self {
+            params,
+            vd_set,
+            batches,
+            sources,
+            state_predicates: state,
+            rev_predicates: rev,
+            kv_predicates: kv,
+        }
+    }
+
+    /// Predicate of the updates of the lists of `kind`, registered by their create payload
+    pub fn update_predicate(&self, kind: db::ListKind) -> &CustomPredicateRef {
+        match kind {
+            db::ListKind::Set => &self.state_predicates.update,
+            db::ListKind::Kv => &self.kv_predicates.update,
+        }
+    }
+
+    /// Helper that builds the update statements of the lists of `kind`
+    pub fn helper<'a>(&'a self, builder: &'a mut MainPodBuilder, kind: db::ListKind) -> Helper<'a> {
+        match kind {
+            db::ListKind::Set => Helper::new(builder, &self.state_predicates),
+            db::ListKind::Kv => {
+                Helper::new_kv(builder, &self.state_predicates, &self.kv_predicates)
+            }
+        }
+    }
+
+    /// Checks that a state pod can prove a batch of `n` updates under the params: the pod reveals
+    /// a statement per update, and every update takes about a dozen statements, see
+    /// `Helper::st_update_batch`.  The batch is made of adds, the costliest ops, and proven with
+    /// the mock prover.
+    pub fn check_update_batch(&self, n: usize) -> Result<()> {
+        let params = &self.params;
+        let depth = params.max_depth_mt_containers;
+        let group = Group::new("batch")?;
+        let old = Dictionary::new(
+            depth,
+            HashMap::from([
+                (
+                    Key::from(group.as_str()),
+                    Value::from(Set::new(depth, HashSet::new())?),
+                ),
+                (Key::from("epoch"), Value::from(0i64)),
+            ]),
+        )?;
+        let ops = (1..=n as i64)
+            .map(|epoch| {
+                let user = UserId::new(format!("user{}", epoch))?;
+                Ok(Op::Add {
+                    group: group.clone(),
+                    user,
+                }
+                .into_dict(params, epoch))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut builder = MainPodBuilder::new(params, &self.vd_set);
+        let mut helper = Helper::new(&mut builder, &self.state_predicates);
+        let steps = helper.st_update_batch(old, ops)?;
+        for (_, st_update) in &steps {
+            builder.reveal(st_update);
+        }
+        builder.prove(&MockProver {}).with_context(|| {
+            format!(
+                "a batch of {} updates doesn't fit in a pod of {} statements, {} of them public",
+                n, params.max_statements, params.max_public_statements
+            )
+        })?;
+        Ok(())
+    }
+}
+
+/// Circuits and predicates the pods are built with, which take minutes to set up at startup
+pub struct Setup {
+    pub pod_config: PodConfig,
+    pub shrunk_main_pod_build: ShrunkMainPodBuild,
+}
+
+/// Steps of the `Setup` done so far, reported by `GET /status` while the server initializes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SetupProgress {
+    pub vd_set: bool,
+    pub predicates: bool,
+    pub shrunk_circuit: bool,
+    // also true if the proofs aren't groth16 ones
+    pub groth: bool,
+    // the setup is available in the context
+    pub ready: bool,
 }
 
 pub struct Context {
     pub cfg: Config,
     pub db_pool: SqlitePool,
-    pub pod_config: PodConfig,
-    pub shrunk_main_pod_build: ShrunkMainPodBuild,
+    // set once built by `init`, the endpoints that need it return `Error::Initializing` until then
+    pub setup: OnceLock<Setup>,
+    pub setup_progress: watch::Sender<SetupProgress>,
+    // `None` in test mode (empty PRIV_KEYS and PRIV_KEY)
+    pub eth: Option<eth::Eth>,
     pub queue_tx: Sender<queue::Request>,
     pub queue_state: RwLock<HashMap<Uuid, queue::State>>,
+    // Queries in progress on a mirror, see `queue::spawn_query`
+    pub query_permits: Arc<Semaphore>,
+    pub pod_store: PodStore,
+    // Recently stored/loaded pods
+    pub pod_cache: Mutex<LruCache<PodKey, MainPod>>,
+    pub rate_limiter: Arc<limits::RateLimiter>,
+    pub list_locks: queue::ListLocks,
+    pub pending_updates: queue::PendingUpdates,
+    // Number of scheduled `UpdateRev` requests by membership list id
+    pub rev_pending: Mutex<HashMap<i64, usize>>,
+    // Backoff of the membership lists whose `UpdateRev` failed, see `queue::reconcile_rev`
+    pub rev_backoff: Mutex<HashMap<i64, queue::RevBackoff>>,
+    // When the audit first found each membership list ahead of the chain, see
+    // `audit::AHEAD_OF_CHAIN_GRACE`
+    pub ahead_of_chain_since: Mutex<HashMap<i64, Instant>>,
+    pub metrics: metrics::Metrics,
+    // Cancelled on SIGINT/SIGTERM to stop the server once the queue is drained
+    pub shutdown: CancellationToken,
 }
 
 impl Context {
     pub fn new(
         cfg: Config,
         db_pool: SqlitePool,
-        pod_config: PodConfig,
-        shrunk_main_pod_build: ShrunkMainPodBuild,
+        eth: Option<eth::Eth>,
         queue_tx: Sender<queue::Request>,
-    ) -> Self {
-        Self {
+        shutdown: CancellationToken,
+    ) -> Result<Self> {
+        let pod_store = PodStore::open(Path::new(&cfg.pods_path), cfg.pod_compression_level)?;
+        let pod_cache = Mutex::new(LruCache::new(cfg.pod_cache_size));
+        let query_permits = Arc::new(Semaphore::new(cfg.queue_len));
+        let rate_limiter = Arc::new(limits::RateLimiter::new(
+            cfg.rate_limit_per_minute,
+            cfg.rate_limit_burst,
+        ));
+        Ok(Self {
             cfg,
             db_pool,
-            pod_config,
-            shrunk_main_pod_build,
+            setup: OnceLock::new(),
+            setup_progress: watch::Sender::new(SetupProgress::default()),
+            eth,
             queue_tx,
             queue_state: RwLock::new(HashMap::new()),
+            query_permits,
+            pod_store,
+            pod_cache,
+            rate_limiter,
+            list_locks: queue::ListLocks::default(),
+            pending_updates: queue::PendingUpdates::default(),
+            rev_pending: Mutex::new(HashMap::new()),
+            rev_backoff: Mutex::new(HashMap::new()),
+            ahead_of_chain_since: Mutex::new(HashMap::new()),
+            metrics: metrics::Metrics::default(),
+            shutdown,
+        })
+    }
+
+    pub fn setup(&self) -> Result<&Setup, Error> {
+        self.setup.get().ok_or(Error::Initializing)
+    }
+
+    pub fn pod_config(&self) -> Result<&PodConfig, Error> {
+        Ok(&self.setup()?.pod_config)
+    }
+
+    /// Makes the setup available, which marks the server as ready.
+    pub fn set_setup(&self, setup: Setup) {
+        if self.setup.set(setup).is_err() {
+            warn!("setup already done");
+        }
+        self.setup_progress
+            .send_modify(|progress| progress.ready = true);
+    }
+
+    /// Stores the pod in the pod store and keeps a copy in the pod cache.  `confirmed` is false
+    /// for pods whose payload hasn't been included in a tx yet.
+    pub fn store_pod(&self, key: PodKey, pod: &MainPod, confirmed: bool) -> Result<()> {
+        self.store_batch_pod(key, None, pod, confirmed)
+    }
+
+    /// Like `store_pod` for the state pod of the batch of updates `first_num..=key.num`, see
+    /// `PodStore::store_batch`.
+    pub fn store_batch_pod(
+        &self,
+        key: PodKey,
+        first_num: Option<i64>,
+        pod: &MainPod,
+        confirmed: bool,
+    ) -> Result<()> {
+        self.pod_store.store_batch(key, first_num, pod, confirmed)?;
+        self.pod_cache.lock().expect("lock").put(key, pod.clone());
+        Ok(())
+    }
+
+    /// Loads the pod from the pod cache, falling back to the pod store on a miss.  The key of an
+    /// update of a batch loads the pod of the batch.
+    pub fn load_pod(&self, key: PodKey) -> Result<MainPod> {
+        let key = self.pod_store.resolve(key);
+        if let Some(pod) = self.pod_cache.lock().expect("lock").get(&key) {
+            return Ok(pod.clone());
+        }
+        let pod = self.pod_store.load(key)?;
+        self.pod_cache.lock().expect("lock").put(key, pod.clone());
+        Ok(pod)
+    }
+
+    /// Deletes the pods that are no longer needed, keeping the last `cfg.pods_retain_last_n` of
+    /// every membership list.  Does nothing if pruning is disabled.
+    pub fn prune_pods(&self) -> Result<Vec<PodKey>> {
+        if self.cfg.pods_retain_last_n == 0 {
+            return Ok(Vec::new());
+        }
+        let pruned = self.pod_store.prune(self.cfg.pods_retain_last_n)?;
+        let mut pod_cache = self.pod_cache.lock().expect("lock");
+        for key in &pruned {
+            pod_cache.pop(key);
         }
+        Ok(pruned)
     }
 }
 
@@ -110,15 +509,59 @@ fn log_init() {
         .init();
 }
 
+/// Waits for SIGINT or SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = signal::ctrl_c() => res?,
+        _ = sigterm.recv() => {}
+    }
+    Ok(())
+}
+
+#[derive(Parser)]
+#[command(about = "Serves the membership lists, proving and posting their updates")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve the API and process the queued requests (default)
+    Run,
+    /// Rebuild the lost membership list ID from its updates indexed by the synchronizer and its
+    /// ops, read from the op log in the DB or from OPS, and exit.  The payloads on chain only
+    /// commit to the states, the ops must be kept off chain
+    Recover {
+        id: i64,
+        /// JSON file of the ops, `[{"num": 1, "op": "init"}, ..]` like the op_log.json of an
+        /// archive
+        #[arg(long)]
+        ops: Option<PathBuf>,
+        #[arg(long, default_value = "http://localhost:8001")]
+        synchronizer_url: String,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
     // If a thread panics we have a bug, so we exit the entire process instead of staying in a
-    // crashed state.
-    let default_panic = std::panic::take_hook();
-    std::panic::set_hook(Box::new(move |info| {
-        default_panic(info);
-        std::process::exit(1);
-    }));
+    // crashed state.  Once the shutdown started, a panic in a proving task only fails its request
+    // so that the queue is still drained.
+    let shutdown = CancellationToken::new();
+    {
+        let shutdown = shutdown.clone();
+        let default_panic = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            default_panic(info);
+            if !shutdown.is_cancelled() {
+                std::process::exit(1);
+            }
+        }));
+    }
 
     log_init();
     common::load_dotenv()?;
@@ -129,47 +572,196 @@ async fn main() -> Result<()> {
     if !Sqlite::database_exists(&cfg.sqlite_path).await? {
         Sqlite::create_database(&cfg.sqlite_path).await?;
     }
-    let db_pool = db::db_connection(&cfg.sqlite_path).await?;
+    let db_pool = db::db_connection(
+        &cfg.sqlite_path,
+        cfg.sqlite_journal_mode,
+        cfg.sqlite_synchronous,
+    )
+    .await?;
     db::init_db(&db_pool).await?;
 
-    // initialize pod data
-    let params = Params::default();
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(cfg, db_pool, shutdown).await,
+        Command::Recover {
+            id,
+            ops,
+            synchronizer_url,
+        } => {
+            let ops = ops
+                .map(|path| -> Result<Vec<recover::RecoverOp>> {
+                    let file = std::fs::File::open(&path).with_context(|| format!("{:?}", path))?;
+                    Ok(serde_json::from_reader(file)?)
+                })
+                .transpose()?;
+            let history = recover::fetch_history(&synchronizer_url, id).await?;
+            let vd_set = vd_set(&cfg.params)?;
+            let pod_config = PodConfig::with_genesis(cfg.params.clone(), vd_set, &cfg.genesis);
+            recover::recover(&cfg, &db_pool, &pod_config, id, &history, ops).await?;
+            info!(id, "membership list recovered");
+            Ok(())
+        }
+    }
+}
+
+/// Builds the setup of the pods, reporting every step in `progress`.
+fn build_setup(
+    cfg: &Config,
+    params: Params,
+    progress: &watch::Sender<SetupProgress>,
+) -> Result<Setup> {
     info!("Prebuilding circuits to calculate vd_set...");
-    let vd_set = &*DEFAULT_VD_SET;
+    let vd_set = vd_set(&params)?;
     info!("vd_set calculation complete");
-    let (state_predicates, rev_predicates) = build_predicates(&params);
-    let shrunk_main_pod_build = ShrunkMainPodSetup::new(&params).build()?;
-    let pod_config = PodConfig {
-        params,
-        vd_set: vd_set.clone(),
-        state_predicates,
-        rev_predicates,
-    };
+    progress.send_modify(|progress| progress.vd_set = true);
+    let pod_config = PodConfig::with_genesis(params, vd_set, &cfg.genesis);
+    pod_config
+        .check_update_batch(cfg.update_batch_max)
+        .context("UPDATE_BATCH_MAX is too large for the pod params")?;
+    for batch in &pod_config.batches {
+        info!("predicate batch 0x{}", batch.id().encode_hex::<String>());
+    }
+    progress.send_modify(|progress| progress.predicates = true);
+    let shrunk_main_pod_build =
+        ShrunkMainPodSetup::new(&pod_config.params, cfg.shrink_zk).build()?;
+    info!("shrunk main pod circuit built");
+    progress.send_modify(|progress| progress.shrunk_circuit = true);
 
+    #[cfg(feature = "groth16")]
     if cfg.proof_type == ProofType::Groth16 {
         // initialize groth16 memory
-        warn!(
-            "WARNING: loading Groth16 artifacts, please wait till the pk & vk are loaded (>30s) and the server is running"
-        );
+        warn!("loading Groth16 artifacts, the pk & vk take >30s to load");
         common::groth::init()?;
     }
-
-    let (queue_tx, queue_rx) = mpsc::channel::<queue::Request>(8);
-    let ctx = Arc::new(Context::new(
-        cfg,
-        db_pool,
+    progress.send_modify(|progress| progress.groth = true);
+    Ok(Setup {
         pod_config,
         shrunk_main_pod_build,
-        queue_tx,
-    ));
+    })
+}
+
+/// Builds the setup in the background and, once it's available, resumes the requests that were
+/// in progress, audits the lists and starts the periodic reconciliation of the reverse indexes, or
+/// the refreshes of the mirrored lists on a mirror.
+async fn init(ctx: Arc<Context>, params: Params) -> Result<()> {
+    let setup = task::spawn_blocking({
+        let ctx = ctx.clone();
+        move || build_setup(&ctx.cfg, params, &ctx.setup_progress)
+    })
+    .await??;
+    ctx.set_setup(setup);
+    info!("setup complete, server ready");
+
+    if ctx.cfg.server_mode == mirror::ServerMode::Mirror {
+        // set in mirror mode
+        if let Some(synchronizer_url) = ctx.cfg.synchronizer_url.clone() {
+            task::spawn(mirror::refresh_loop(ctx, synchronizer_url));
+        }
+        return Ok(());
+    }
+    queue::resume_requests(&ctx).await?;
+    task::spawn(audit::startup_audit(ctx.clone()));
+    if ctx.cfg.rev_reconcile_interval > 0 {
+        task::spawn(queue::reconcile_rev_loop(ctx));
+    }
+    Ok(())
+}
+
+async fn run(cfg: Config, db_pool: SqlitePool, shutdown: CancellationToken) -> Result<()> {
+    let eth = if cfg.server_mode == mirror::ServerMode::Mirror {
+        info!(primary_url = ?cfg.primary_url, "running as a read-only mirror, without sending txs");
+        None
+    } else if cfg.priv_keys.is_empty() {
+        warn!("PRIV_KEYS and PRIV_KEY are empty, running in test mode without sending txs");
+        None
+    } else {
+        let eth = eth::Eth::connect(&cfg).await?;
+        info!(signers = ?eth.addresses(), "sending txs");
+        Some(eth)
+    };
+
+    let (queue_tx, queue_rx) = mpsc::channel::<queue::Request>(cfg.queue_len);
+    let ctx = Arc::new(Context::new(cfg, db_pool, eth, queue_tx, shutdown.clone())?);
 
     let routes = endpoints::routes(ctx.clone());
+    // the mirrors don't update their lists, and handle the queries without the queue, see
+    // `queue::spawn_query`
+    let queue_loop = match ctx.cfg.server_mode {
+        mirror::ServerMode::Primary => {
+            let ctx = ctx.clone();
+            Some(task::spawn(async move {
+                queue::handle_loop(ctx, queue_rx).await;
+            }))
+        }
+        mirror::ServerMode::Mirror => None,
+    };
+    // the server listens right away, reporting the progress of the setup in `GET /status`
+    {
+        let ctx = ctx.clone();
+        task::spawn(async move {
+            let params = ctx.cfg.params.clone();
+            if let Err(err) = init(ctx, params).await {
+                error!("setup failed: {:#}", err);
+                std::process::exit(1);
+            }
+        });
+    }
+    if ctx.eth.is_some() && ctx.cfg.signer_balance_check_interval > 0 {
+        let ctx = ctx.clone();
+        task::spawn(async move {
+            eth::check_balances_loop(ctx).await;
+        });
+    }
     task::spawn(async move {
-        queue::handle_loop(ctx, queue_rx).await;
+        match shutdown_signal().await {
+            Ok(()) => {
+                info!("shutting down, draining the queue");
+                shutdown.cancel();
+            }
+            Err(err) => warn!("failed to listen for the shutdown signals: {}", err),
+        }
     });
 
-    info!("server at http://0.0.0.0:8000");
-    warp::serve(routes).run(([0, 0, 0, 0], 8000)).await;
+    // stops accepting requests on shutdown, while the queue finishes the request in progress
+    let (addr, server) = warp::serve(routes).bind_with_graceful_shutdown(
+        (ctx.cfg.http_bind, ctx.cfg.http_port),
+        ctx.shutdown.clone().cancelled_owned(),
+    );
+    info!("server at http://{}", addr);
+    server.await;
+    if let Some(queue_loop) = queue_loop {
+        queue_loop.await?;
+    }
+    info!("shutdown complete");
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use pod2::backends::plonky2::basetypes::DEFAULT_VD_SET;
+
+    use super::*;
+
+    #[test]
+    fn test_pod_config() {
+        let pod_config = PodConfig::new(Params::default(), DEFAULT_VD_SET.clone());
+        let batch_ids: Vec<_> = pod_config.batches.iter().map(|b| b.id()).collect();
+        // the predicates proven by the pods of the server are defined in the batches
+        for cpr in [
+            &pod_config.state_predicates.update,
+            &pod_config.rev_predicates.sync,
+        ] {
+            assert!(batch_ids.contains(&cpr.batch.id()));
+        }
+    }
+
+    #[test]
+    fn test_check_update_batch() -> Result<()> {
+        let pod_config = PodConfig::new(Params::default(), DEFAULT_VD_SET.clone());
+        pod_config.check_update_batch(1)?;
+        // a pod can't reveal more updates than its public statements
+        let n = Params::default().max_public_statements + 1;
+        assert!(pod_config.check_update_batch(n).is_err());
+        Ok(())
+    }
+}
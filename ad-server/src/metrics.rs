@@ -0,0 +1,157 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+// upper bounds in seconds of the histogram buckets, proving takes from seconds to minutes
+const BUCKETS: [f64; 11] = [
+    0.5, 1.0, 2.0, 5.0, 10.0, 20.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+/// Timed proving steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Timing {
+    // main pod of a membership list update
+    StatePod,
+    // main pod of a reverse membership list update
+    RevPod,
+    // shrinking and compressing the state pod for the plonky2 payload
+    Shrink,
+    // groth16 proof of the state pod for the groth16 payload
+    Groth,
+    // membership pod of a user
+    MembershipPod,
+}
+
+impl Timing {
+    fn name(&self) -> &'static str {
+        match self {
+            Timing::StatePod => "ad_server_state_pod_seconds",
+            Timing::RevPod => "ad_server_rev_pod_seconds",
+            Timing::Shrink => "ad_server_shrink_seconds",
+            Timing::Groth => "ad_server_groth_seconds",
+            Timing::MembershipPod => "ad_server_membership_pod_seconds",
+        }
+    }
+
+    fn help(&self) -> &'static str {
+        match self {
+            Timing::StatePod => "Time to prove the state pod of a membership list update",
+            Timing::RevPod => "Time to prove the pod of a reverse membership list update",
+            Timing::Shrink => "Time to shrink and compress a state pod",
+            Timing::Groth => "Time to prove a state pod with groth16",
+            Timing::MembershipPod => "Time to prove the membership pod of a user",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Histogram {
+    // observations per bucket, not cumulative
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        if let Some(i) = BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+/// Histograms of the proving times and count of the payloads sent, rendered in the prometheus
+/// text format.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    histograms: Mutex<BTreeMap<Timing, Histogram>>,
+    // payloads sent in a tx, or mocked in test mode
+    payloads_sent: AtomicU64,
+}
+
+impl Metrics {
+    pub fn observe(&self, timing: Timing, elapsed: Duration) {
+        self.histograms
+            .lock()
+            .expect("lock")
+            .entry(timing)
+            .or_default()
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn count_payload(&self) {
+        self.payloads_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn payloads_sent(&self) -> u64 {
+        self.payloads_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn render(&self) -> String {
+        let histograms = self.histograms.lock().expect("lock");
+        let mut out = String::new();
+        for timing in [
+            Timing::StatePod,
+            Timing::RevPod,
+            Timing::Shrink,
+            Timing::Groth,
+            Timing::MembershipPod,
+        ] {
+            let empty = Histogram::default();
+            let histogram = histograms.get(&timing).unwrap_or(&empty);
+            let name = timing.name();
+            writeln!(out, "# HELP {} {}", name, timing.help()).expect("write to string");
+            writeln!(out, "# TYPE {} histogram", name).expect("write to string");
+            let mut cumulative = 0;
+            for (le, count) in BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative)
+                    .expect("write to string");
+            }
+            writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count)
+                .expect("write to string");
+            writeln!(out, "{}_sum {}", name, histogram.sum).expect("write to string");
+            writeln!(out, "{}_count {}", name, histogram.count).expect("write to string");
+        }
+        let name = "ad_server_payloads_sent_total";
+        writeln!(out, "# HELP {} Payloads sent in a tx", name).expect("write to string");
+        writeln!(out, "# TYPE {} counter", name).expect("write to string");
+        writeln!(out, "{} {}", name, self.payloads_sent()).expect("write to string");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_render() {
+        let metrics = Metrics::default();
+        metrics.observe(Timing::StatePod, Duration::from_millis(1500));
+        metrics.observe(Timing::StatePod, Duration::from_secs(40));
+        metrics.observe(Timing::StatePod, Duration::from_secs(3600));
+        metrics.count_payload();
+
+        let out = metrics.render();
+        assert!(out.contains("# TYPE ad_server_state_pod_seconds histogram\n"));
+        assert!(out.contains("ad_server_state_pod_seconds_bucket{le=\"1\"} 0\n"));
+        assert!(out.contains("ad_server_state_pod_seconds_bucket{le=\"2\"} 1\n"));
+        assert!(out.contains("ad_server_state_pod_seconds_bucket{le=\"60\"} 2\n"));
+        assert!(out.contains("ad_server_state_pod_seconds_bucket{le=\"600\"} 2\n"));
+        assert!(out.contains("ad_server_state_pod_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("ad_server_state_pod_seconds_sum 3641.5\n"));
+        assert!(out.contains("ad_server_state_pod_seconds_count 3\n"));
+        // steps that didn't run yet are rendered empty
+        assert!(out.contains("ad_server_groth_seconds_count 0\n"));
+        assert!(out.contains("ad_server_payloads_sent_total 1\n"));
+    }
+}
@@ -0,0 +1,377 @@
+//! Read-only mirror of the membership lists of a primary server, which serves their queries
+//! without proving nor sending txs.
+//!
+//! The payloads on chain only commit to the states of the lists, so a mirror takes the states from
+//! the snapshots published by the primary (`SNAPSHOT_INTERVAL`), as indexed by the synchronizer in
+//! `GET /ad/{id}/snapshot/latest`.  A mirror serves the state of the latest snapshot of every
+//! list, which is behind the primary by up to `SNAPSHOT_INTERVAL` updates, and doesn't serve the
+//! lists without one.  The requests that update the lists or need their pods are rejected with
+//! `Error::ReadOnlyMirror`, which points to the primary.
+
+use std::{collections::HashSet, str::FromStr, sync::Arc, time::Duration};
+
+use anyhow::{Context as _, Result, anyhow};
+use hex::ToHex;
+use pod2::middleware::{Hash, RawValue, containers::Dictionary};
+use serde::{Deserialize, de::DeserializeOwned};
+use sqlx::SqlitePool;
+use tokio::time::interval;
+use tracing::{debug, info, warn};
+
+use crate::{Context, Error, PodConfig, archive, bloom, db, recover};
+
+/// Whether the server owns the membership lists or mirrors the ones of another server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServerMode {
+    #[default]
+    Primary,
+    Mirror,
+}
+
+impl FromStr for ServerMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "primary" => Ok(ServerMode::Primary),
+            "mirror" => Ok(ServerMode::Mirror),
+            _ => Err(anyhow!("unsupported SERVER_MODE {}", s)),
+        }
+    }
+}
+
+/// Latest snapshot of a membership list as indexed by the synchronizer
+#[derive(Debug, Clone, Deserialize)]
+struct ChainSnapshot {
+    num: i64,
+    // commitment of `dict`, as published on chain by the update `num`
+    state: RawValue,
+    dict: Dictionary,
+}
+
+/// Predicate the AD of a membership list was created with, as indexed by the synchronizer
+#[derive(Debug, Clone, Deserialize)]
+struct ChainPredicate {
+    // hex encoded
+    batch_id: String,
+    index: usize,
+}
+
+/// Number of ids past the mirrored lists and the ADs on chain that are checked for new lists, as
+/// the ids of the primary have gaps where the create of a list never made it on chain.
+const MAX_ID_GAP: i64 = 1024;
+
+/// Returns the hex encoded id of the AD of the list `id`, as in the create payload.
+fn ad_id(id: i64) -> String {
+    Hash::from(RawValue::from(id)).encode_hex()
+}
+
+fn ad_url(synchronizer_url: &str, id: i64, path: &str) -> String {
+    format!(
+        "{}/ad/{}/{}",
+        synchronizer_url.trim_end_matches('/'),
+        ad_id(id),
+        path
+    )
+}
+
+async fn get_json<T: DeserializeOwned>(url: &str) -> Result<T> {
+    reqwest::get(url)
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .with_context(|| url.to_string())
+}
+
+/// Returns the kind of the lists whose AD is proven with `predicate`, none if it isn't one of the
+/// update predicates of this server, e.g. for a list of a server with other genesis groups.
+fn list_kind(pod_config: &PodConfig, predicate: &ChainPredicate) -> Option<db::ListKind> {
+    [db::ListKind::Set, db::ListKind::Kv]
+        .into_iter()
+        .find(|kind| {
+            let cpr = pod_config.update_predicate(*kind);
+            cpr.batch.id().encode_hex::<String>() == predicate.batch_id
+                && cpr.index == predicate.index
+        })
+}
+
+/// Returns the ids of the mirrored lists and of the lists with an AD indexed by the synchronizer
+/// at `synchronizer_url` that aren't mirrored yet, in order.
+async fn list_ids(db_pool: &SqlitePool, synchronizer_url: &str) -> Result<Vec<i64>> {
+    let url = format!("{}/ads", synchronizer_url.trim_end_matches('/'));
+    let mut ad_ids: HashSet<String> = get_json(&url).await?;
+    let mut ids = db::get_membership_list_ids(db_pool).await?;
+    for id in &ids {
+        ad_ids.remove(&ad_id(*id));
+    }
+    // the ids of the lists are consecutive from 1 but for the gaps, while the ADs of the other
+    // servers indexed by the synchronizer are never matched
+    let max_id = ids.len() as i64 + ad_ids.len() as i64 + MAX_ID_GAP;
+    for id in 1..=max_id {
+        if ad_ids.is_empty() {
+            break;
+        }
+        if ad_ids.remove(&ad_id(id)) {
+            ids.push(id);
+        }
+    }
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+/// Updates the mirrored lists to their latest snapshot indexed by the synchronizer at
+/// `synchronizer_url`, and mirrors the new ones.  Returns the ids of the lists whose state
+/// changed.
+pub async fn refresh(
+    db_pool: &SqlitePool,
+    pod_config: &PodConfig,
+    synchronizer_url: &str,
+) -> Result<Vec<i64>> {
+    let depth = pod_config.params.max_depth_mt_containers;
+    let mut refreshed = Vec::new();
+    for id in list_ids(db_pool, synchronizer_url).await? {
+        let num = match db::get_membership_list(db_pool, id).await {
+            Ok(membership_list) => Some(membership_list.num),
+            Err(Error::NotFound(_)) => None,
+            Err(err) => return Err(err.into()),
+        };
+        // only the updates after the mirrored one, which is fetched to tell whether the list is
+        // up to date
+        let history =
+            recover::fetch_history_from(synchronizer_url, id, num.unwrap_or_default()).await?;
+        let Some(latest) = history.last() else {
+            continue;
+        };
+        if num.is_some_and(|num| num >= latest.num) {
+            continue;
+        }
+        // the synchronizer fails the same way whether there is no snapshot or it failed to
+        // load it, so the list is retried on the next refresh
+        let snapshot: ChainSnapshot =
+            match get_json(&ad_url(synchronizer_url, id, "snapshot/latest")).await {
+                Ok(snapshot) => snapshot,
+                Err(err) => {
+                    debug!(id, "no snapshot to mirror: {:#}", err);
+                    continue;
+                }
+            };
+        if num.is_some_and(|num| num >= snapshot.num) {
+            continue;
+        }
+        if history.iter().any(|update| {
+            update
+                .pruned_from
+                .is_some_and(|from| from <= snapshot.num && snapshot.num < update.num)
+        }) {
+            warn!(
+                id,
+                num = snapshot.num,
+                "the synchronizer pruned the update of the snapshot, which can't be checked \
+                 against the chain, skipping it"
+            );
+            continue;
+        }
+        let on_chain = history
+            .iter()
+            .find(|update| update.num == snapshot.num)
+            .map(|update| update.state);
+        if RawValue::from(snapshot.dict.commitment()) != snapshot.state
+            || on_chain != Some(snapshot.state)
+        {
+            warn!(
+                id,
+                num = snapshot.num,
+                "the snapshot isn't the state on chain, skipping it"
+            );
+            continue;
+        }
+
+        let kind = match num {
+            Some(_) => db::get_membership_list_kind(db_pool, id).await?,
+            None => {
+                let predicate: ChainPredicate =
+                    get_json(&ad_url(synchronizer_url, id, "predicate")).await?;
+                match list_kind(pod_config, &predicate) {
+                    Some(kind) => kind,
+                    None => {
+                        warn!(
+                            id,
+                            ?predicate,
+                            "unknown update predicate, skipping the list"
+                        );
+                        continue;
+                    }
+                }
+            }
+        };
+        // the kv lists are queried from their state, without a reverse index
+        let rev_state = match kind {
+            db::ListKind::Set => Some(archive::rev_index(&snapshot.dict, depth)?),
+            db::ListKind::Kv => None,
+        };
+        db::upsert_mirrored_list(
+            db_pool,
+            id,
+            kind,
+            snapshot.num,
+            &snapshot.dict,
+            rev_state.as_ref(),
+        )
+        .await?;
+        let blooms = bloom::group_blooms(&snapshot.dict);
+        db::replace_group_blooms(db_pool, id, snapshot.num, &blooms).await?;
+        info!(id, num = snapshot.num, "mirrored the membership list");
+        refreshed.push(id);
+    }
+    Ok(refreshed)
+}
+
+/// Refreshes the mirrored lists every `cfg.mirror_refresh_interval` seconds from the synchronizer
+/// at `synchronizer_url`.  Runs once the setup is available.
+pub async fn refresh_loop(ctx: Arc<Context>, synchronizer_url: String) {
+    let pod_config = match ctx.pod_config() {
+        Ok(pod_config) => pod_config,
+        Err(err) => {
+            warn!("can't refresh the mirrored lists: {}", err);
+            return;
+        }
+    };
+    let mut refresh_interval = interval(Duration::from_secs(ctx.cfg.mirror_refresh_interval));
+    loop {
+        tokio::select! {
+            _ = ctx.shutdown.cancelled() => break,
+            _ = refresh_interval.tick() => {}
+        }
+        match refresh(&ctx.db_pool, pod_config, &synchronizer_url).await {
+            Ok(refreshed) if refreshed.is_empty() => {}
+            Ok(refreshed) => info!("refreshed {} mirrored lists", refreshed.len()),
+            Err(err) => warn!("failed to refresh the mirrored lists: {:#}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use app::{Group, Helper, Op, UserId};
+    use pod2::{
+        backends::plonky2::basetypes::DEFAULT_VD_SET,
+        frontend::MainPodBuilder,
+        middleware::{Key, Params, Value, containers::Set},
+    };
+    use serde_json::json;
+    use warp::{Filter, Reply, http::StatusCode};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_refresh() -> Result<()> {
+        let pod_config = PodConfig::new(Params::default(), DEFAULT_VD_SET.clone());
+        let params = &pod_config.params;
+        let ops = [
+            Op::Init,
+            Op::Add {
+                group: Group::RED,
+                user: UserId::new("alice")?,
+            },
+        ];
+        let mut state = Dictionary::new(params.max_depth_mt_containers, HashMap::new())?;
+        let mut history = vec![json!({"num": 0, "state": RawValue::from(1)})];
+        for (op, num) in ops.into_iter().zip(1..) {
+            let mut builder = MainPodBuilder::new(params, &pod_config.vd_set);
+            let mut helper = Helper::new(&mut builder, &pod_config.state_predicates);
+            state = helper.st_update(state, op.into_dict(params, num))?.0;
+            history.push(json!({"num": num, "state": RawValue::from(state.commitment())}));
+        }
+        let cpr = pod_config.update_predicate(db::ListKind::Set);
+        let predicate = json!({
+            "batch_id": cpr.batch.id().encode_hex::<String>(),
+            "index": cpr.index,
+        });
+        let snapshot = json!({
+            "num": 2,
+            "state": RawValue::from(state.commitment()),
+            "dict": state,
+        });
+
+        // mocked synchronizer: the lists 1, 2 and 4 are on chain, after a gap at 3, with a
+        // snapshot of the lists 1 and 4.  The updates requested are recorded in `fetched`.
+        let on_chain = [ad_id(1), ad_id(2), ad_id(4)];
+        let fetched: Arc<std::sync::Mutex<Vec<(i64, i64)>>> = Default::default();
+        let ads = warp::path!("ads").map({
+            let on_chain = on_chain.clone();
+            move || warp::reply::json(&on_chain)
+        });
+        let updates = warp::path!("ad" / String / "updates")
+            .and(warp::query::<HashMap<String, i64>>())
+            .map({
+                let on_chain = on_chain.clone();
+                let fetched = fetched.clone();
+                move |ad_id: String, query: HashMap<String, i64>| {
+                    let from = query.get("from").copied().unwrap_or_default();
+                    let Some(id) = on_chain.iter().position(|id| *id == ad_id) else {
+                        return warp::reply::json(&json!([]));
+                    };
+                    fetched.lock().expect("lock").push(([1, 2, 4][id], from));
+                    let history: Vec<_> = history
+                        .iter()
+                        .filter(|update| update["num"].as_i64() >= Some(from))
+                        .collect();
+                    warp::reply::json(&history)
+                }
+            });
+        let latest = warp::path!("ad" / String / "snapshot" / "latest").map(move |id: String| {
+            match id == ad_id(1) || id == ad_id(4) {
+                true => warp::reply::json(&snapshot).into_response(),
+                false => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        });
+        let predicate = warp::path!("ad" / String / "predicate")
+            .map(move |_: String| warp::reply::json(&predicate));
+        let (addr, server) = warp::serve(ads.or(updates).or(latest).or(predicate))
+            .bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let synchronizer_url = format!("http://{}", addr);
+
+        let db_pool = SqlitePool::connect(":memory:").await?;
+        db::init_db(&db_pool).await?;
+        assert_eq!(
+            refresh(&db_pool, &pod_config, &synchronizer_url).await?,
+            vec![1, 4]
+        );
+        let membership_list = db::get_membership_list(&db_pool, 1).await?;
+        assert_eq!(membership_list.num, 2);
+        assert_eq!(membership_list.state.0.commitment(), state.commitment());
+        assert_eq!(
+            db::get_membership_list_kind(&db_pool, 1).await?,
+            db::ListKind::Set
+        );
+        // the reverse index is rebuilt from the state, and the blooms are at its num
+        let rev_membership_list = db::get_rev_membership_list(&db_pool, 1).await?;
+        assert_eq!(rev_membership_list.num, 2);
+        let red = Set::new(
+            params.max_depth_mt_containers,
+            HashSet::from([Value::from("red")]),
+        )?;
+        assert_eq!(
+            rev_membership_list.state.0.get(&Key::from("alice"))?,
+            &Value::from(red)
+        );
+        assert!(!db::get_group_blooms_at_rev(&db_pool, 1).await?.is_empty());
+        // the list without a snapshot isn't mirrored
+        assert!(db::get_membership_list(&db_pool, 2).await.is_err());
+
+        // the mirrored lists are up to date, and only their updates from the mirrored num on are
+        // fetched
+        fetched.lock().expect("lock").clear();
+        assert!(
+            refresh(&db_pool, &pod_config, &synchronizer_url)
+                .await?
+                .is_empty()
+        );
+        assert_eq!(*fetched.lock().expect("lock"), vec![(1, 2), (2, 0), (4, 2)]);
+        Ok(())
+    }
+}
@@ -1,31 +1,49 @@
-use std::{path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    slice,
+    sync::{Arc, Mutex},
+};
 
-use alloy::primitives::TxHash;
-use anyhow::Result;
-use app::{Helper, Op, RevHelper};
+use alloy::primitives::{B256, TxHash};
+use anyhow::{Result, anyhow};
+use app::{Group, Op, RevHelper, apply_ops};
 use common::{
     ProofType,
-    disk::{load_pod, rev_membership_list_pod_file_name, store_pod},
-    groth,
-    payload::{Payload, PayloadCreate, PayloadProof, PayloadUpdate},
+    api::{QueryProofResponse, TxCostInfo},
+    disk::PodKey,
+    ops,
+    payload::{
+        Payload, PayloadCreate, PayloadProof, PayloadSnapshot, PayloadUpdate, UpdateStep,
+        params_fingerprint,
+    },
     set_from_value,
     shrink::shrink_compress_pod,
 };
+use hex::ToHex;
 use pod2::{
     backends::plonky2::{mainpod::Prover, primitives::merkletree::MerkleClaimAndProof},
     dict,
-    frontend::MainPodBuilder,
+    frontend::{MainPod, MainPodBuilder, Operation},
     middleware::{
-        Hash, RawValue, Statement, TypedValue, Value,
-        containers::{Dictionary, Set},
+        EMPTY_VALUE, Hash, Key, RawValue, Statement, TypedValue, Value, containers::Dictionary,
     },
 };
 use serde::{Deserialize, Serialize};
-use tokio::{sync::mpsc::Receiver, task};
-use tracing::{debug, info};
+use tokio::{
+    sync::{
+        OwnedMutexGuard,
+        mpsc::{Receiver, Sender, error::TrySendError},
+        oneshot,
+    },
+    task::{self, JoinSet},
+    time::{Duration, Instant, interval, sleep},
+};
+use tracing::{Instrument, Span, debug, field, info, info_span, warn};
 use uuid::Uuid;
 
-use crate::{Context, db};
+use crate::{
+    Context, Error, archive, audit, bloom, db, error::ErrorInfo, metrics::Timing, snapshot,
+};
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum State {
@@ -33,100 +51,664 @@ pub enum State {
     Update(StateUpdate),
     UpdateRev(StateUpdateRev),
     Query(Box<StateQuery>),
+    QueryKv(Box<StateQueryKv>),
+    QueryAll(Box<StateQueryAll>),
+    PrunePods(StatePrunePods),
+    ProveMembership(StateProveMembership),
+}
+
+impl State {
+    /// Whether the request is complete or failed
+    pub fn is_terminal(&self) -> bool {
+        match self {
+            State::Create(state) => {
+                matches!(state, StateCreate::Complete { .. } | StateCreate::Error(_))
+            }
+            State::Update(state) => {
+                matches!(state, StateUpdate::Complete { .. } | StateUpdate::Error(_))
+            }
+            State::UpdateRev(state) => {
+                matches!(state, StateUpdateRev::Complete | StateUpdateRev::Error(_))
+            }
+            State::Query(state) => {
+                matches!(**state, StateQuery::Complete { .. } | StateQuery::Error(_))
+            }
+            State::QueryKv(_) => true,
+            State::QueryAll(state) => {
+                matches!(
+                    **state,
+                    StateQueryAll::Complete { .. } | StateQueryAll::Error(_)
+                )
+            }
+            State::PrunePods(state) => {
+                matches!(
+                    state,
+                    StatePrunePods::Complete { .. } | StatePrunePods::Error(_)
+                )
+            }
+            State::ProveMembership(state) => matches!(
+                state,
+                StateProveMembership::Complete { .. } | StateProveMembership::Error(_)
+            ),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StateCreate {
     Pending,
     SendingBlobTx,
-    Complete { id: i64, tx_hash: TxHash },
-    Error(String),
+    Complete {
+        id: i64,
+        tx_hash: TxHash,
+        // None if the payload was sent as calldata
+        blob_versioned_hash: Option<B256>,
+        cost: TxCostInfo,
+    },
+    Error(ErrorInfo),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StateUpdate {
     Pending,
-    ProvingMainPod,
+    // with the time spent proving so far
+    ProvingMainPod {
+        elapsed_secs: u64,
+    },
     WrappingMainPod,
     SendingBlobTx,
-    Complete { tx_hash: TxHash },
-    Error(String),
+    Complete {
+        tx_hash: TxHash,
+        // None if the payload was sent as calldata
+        blob_versioned_hash: Option<B256>,
+        cost: TxCostInfo,
+    },
+    Error(ErrorInfo),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StateUpdateRev {
     Pending,
-    ProvingRevMainPod,
+    // with the time spent proving so far
+    ProvingRevMainPod { elapsed_secs: u64 },
     Complete,
-    Error(String),
+    Error(ErrorInfo),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum StateQuery {
     Pending,
     Complete {
-        groups: Set,
-        proof: Box<MerkleClaimAndProof>,
+        // the groups the user is in, in group order
+        groups: Vec<Group>,
+        proof: QueryProofResponse,
+    },
+    Error(ErrorInfo),
+}
+
+/// Value of a user in a group of a kv membership list, with the proof of the value against the
+/// group and the proof of the group against the state of the list
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KvMembership {
+    pub group: Group,
+    pub value: i64,
+    pub proof: QueryProofResponse,
+    pub group_proof: QueryProofResponse,
+}
+
+/// Completed query of a kv membership list.  The query is pending, or fails, as a `StateQuery`
+/// since the kind of the list is only known once it's handled.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StateQueryKv {
+    Complete {
+        // the groups the user is in, in group order
+        values: Vec<KvMembership>,
+    },
+}
+
+/// Groups of a user in a membership list, with the proof against its reverse index
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UserMembership {
+    pub id: i64,
+    // in group order
+    pub groups: Vec<Group>,
+    pub proof: QueryProofResponse,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StateQueryAll {
+    Pending,
+    Complete {
+        // lists of the page the user is a member of, in id order
+        lists: Vec<UserMembership>,
+        // `after_id` of the next page, none after the last page
+        next_after_id: Option<i64>,
     },
-    Error(String),
+    Error(ErrorInfo),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StatePrunePods {
+    Pending,
+    Complete { pruned: usize },
+    Error(ErrorInfo),
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum StateProveMembership {
+    Pending,
+    ProvingMainPod,
+    Complete {
+        num: i64,
+        // commitment of the membership list state the pod proves membership in
+        state: Hash,
+        pod: Box<MainPod>,
+    },
+    Error(ErrorInfo),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Request {
-    Create { req_id: Uuid },
-    Update { req_id: Uuid, id: i64, op: Op },
-    UpdateRev { req_id: Uuid, id: i64, num: i64 },
-    Query { req_id: Uuid, id: i64, user: String },
+    Create {
+        req_id: Uuid,
+        #[serde(default)]
+        kind: db::ListKind,
+    },
+    Update {
+        req_id: Uuid,
+        id: i64,
+        op: Op,
+    },
+    UpdateRev {
+        req_id: Uuid,
+        id: i64,
+        num: i64,
+        // req_id of the update that scheduled it, none if scheduled by the reconciler or a retry
+        #[serde(default)]
+        parent_req_id: Option<Uuid>,
+    },
+    ResumeWrap {
+        req_id: Uuid,
+        id: i64,
+        num: i64,
+        // the other requests of the batch of the update, which follow the state of `req_id`
+        #[serde(default)]
+        other_req_ids: Vec<Uuid>,
+    },
+    Query {
+        req_id: Uuid,
+        id: i64,
+        user: String,
+        // query the state after the update `at` instead of the latest one
+        #[serde(default)]
+        at: Option<i64>,
+    },
+    QueryAll {
+        req_id: Uuid,
+        user: String,
+        // page of up to `limit` lists with id greater than `after_id`
+        after_id: i64,
+        limit: i64,
+    },
+    PrunePods {
+        req_id: Uuid,
+    },
+    ProveMembership {
+        req_id: Uuid,
+        id: i64,
+        user: String,
+        group: Group,
+    },
 }
 
+impl Request {
+    pub fn req_id(&self) -> Uuid {
+        match self {
+            Request::Create { req_id, .. }
+            | Request::Update { req_id, .. }
+            | Request::UpdateRev { req_id, .. }
+            | Request::ResumeWrap { req_id, .. }
+            | Request::Query { req_id, .. }
+            | Request::QueryAll { req_id, .. }
+            | Request::PrunePods { req_id }
+            | Request::ProveMembership { req_id, .. } => *req_id,
+        }
+    }
+
+    /// State of the request while it waits in the queue
+    pub fn pending_state(&self) -> State {
+        match self {
+            Request::Create { .. } => State::Create(StateCreate::Pending),
+            Request::Update { .. } | Request::ResumeWrap { .. } => {
+                State::Update(StateUpdate::Pending)
+            }
+            Request::UpdateRev { .. } => State::UpdateRev(StateUpdateRev::Pending),
+            Request::Query { .. } => State::Query(Box::new(StateQuery::Pending)),
+            Request::QueryAll { .. } => State::QueryAll(Box::new(StateQueryAll::Pending)),
+            Request::PrunePods { .. } => State::PrunePods(StatePrunePods::Pending),
+            Request::ProveMembership { .. } => {
+                State::ProveMembership(StateProveMembership::Pending)
+            }
+        }
+    }
+
+    /// Span of the handling of the request, so that every event logged while handling it
+    /// carries its `req_id`.  The updates record their `num` and `op` once known, where `op` is
+    /// the commitment of the op as logged by the synchronizer when it processes the payload.  The
+    /// `UpdateRev` scheduled by an update records the update's req_id as `parent_req_id`, which
+    /// outlives the span of the update when the request is stored on shutdown.
+    pub fn span(&self) -> Span {
+        let span = info_span!(
+            "request",
+            req_id = %self.req_id(),
+            kind = field::Empty,
+            id = field::Empty,
+            num = field::Empty,
+            op = field::Empty,
+            parent_req_id = field::Empty,
+        );
+        let (kind, id, num) = match self {
+            Request::Create { .. } => ("create", None, None),
+            Request::Update { id, .. } => ("update", Some(*id), None),
+            Request::UpdateRev { id, num, .. } => ("update_rev", Some(*id), Some(*num)),
+            Request::ResumeWrap { id, num, .. } => ("resume_wrap", Some(*id), Some(*num)),
+            Request::Query { id, at, .. } => ("query", Some(*id), *at),
+            Request::QueryAll { .. } => ("query_all", None, None),
+            Request::PrunePods { .. } => ("prune_pods", None, None),
+            Request::ProveMembership { id, .. } => ("prove_membership", Some(*id), None),
+        };
+        span.record("kind", kind);
+        if let Some(id) = id {
+            span.record("id", id);
+        }
+        if let Some(num) = num {
+            span.record("num", num);
+        }
+        if let Request::UpdateRev {
+            parent_req_id: Some(parent_req_id),
+            ..
+        } = self
+        {
+            span.record("parent_req_id", field::display(parent_req_id));
+        }
+        span
+    }
+}
+
+// seconds after which the clients retry the requests rejected because the queue is full
+const QUEUE_FULL_RETRY_AFTER: u64 = 5;
+
+/// Enqueues `req` with its pending state, returning its req_id.  Doesn't wait for room in the
+/// queue: the request is rejected with `Error::QueueFull` if it's full, so that the clients back
+/// off instead of hanging.  With `Config::update_batch_max` over 1, the updates of a list with an
+/// update already queued or in progress are buffered behind it instead, see `PendingUpdates`.
+pub async fn enqueue(ctx: &Context, req: Request) -> Result<Uuid, Error> {
+    let req_id = req.req_id();
+    // set before sending, since the request can be handled right away
+    ctx.queue_state
+        .write()
+        .await
+        .insert(req_id, req.pending_state());
+    let sent = match req {
+        Request::Update { .. } if ctx.cfg.update_batch_max > 1 => {
+            ctx.pending_updates
+                .send(&ctx.queue_tx, req, ctx.cfg.queue_len)
+        }
+        req => ctx.queue_tx.try_send(req),
+    };
+    match sent {
+        Ok(()) => Ok(req_id),
+        Err(err) => {
+            ctx.queue_state.write().await.remove(&req_id);
+            match err {
+                TrySendError::Full(_) => Err(Error::QueueFull(QUEUE_FULL_RETRY_AFTER)),
+                TrySendError::Closed(_) => Err(Error::Internal(anyhow!("queue closed"))),
+            }
+        }
+    }
+}
+
+/// Handles the query `req` in a task of its own instead of the queue, for the mirrors, which
+/// don't run the queue.  Like the states of the queued requests, the state of the query is only
+/// kept in memory, for as long as the server runs.  At most `Config::queue_len` queries are in
+/// progress, the others are rejected with `Error::QueueFull` like when the queue is full.
+pub async fn spawn_query(ctx: Arc<Context>, req: Request) -> Result<Uuid, Error> {
+    let permit = ctx
+        .query_permits
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| Error::QueueFull(QUEUE_FULL_RETRY_AFTER))?;
+    let req_id = req.req_id();
+    ctx.queue_state
+        .write()
+        .await
+        .insert(req_id, req.pending_state());
+    task::spawn(async move {
+        if let Err(err) = handle_req(ctx, req).await {
+            warn!("query {} failed: {:#}", req_id, err);
+        }
+        drop(permit);
+    });
+    Ok(req_id)
+}
+
+/// Records the update being applied in the span of the current request.
+fn record_update(num: i64, op_raw: RawValue) {
+    let span = Span::current();
+    span.record("num", num);
+    span.record("op", field::display(op_raw.encode_hex::<String>()));
+}
+
+/// Per membership list locks, so that the updates of a list are applied strictly one after the
+/// other while the updates of different lists can interleave.
+#[derive(Default)]
+pub struct ListLocks(Mutex<HashMap<i64, Arc<tokio::sync::Mutex<()>>>>);
+
+impl ListLocks {
+    /// Waits until no other update of the list `id` is in progress.  The list stays locked until
+    /// the returned guard is dropped.
+    pub async fn lock(&self, id: i64) -> OwnedMutexGuard<()> {
+        // one lock per list ever updated, which is bounded by the number of lists
+        let lock = self.0.lock().expect("lock").entry(id).or_default().clone();
+        lock.lock_owned().await
+    }
+}
+
+/// Updates of the membership lists buffered behind the update of their list that is queued or in
+/// progress, which proves them together in batches of up to `Config::update_batch_max` once it's
+/// done.  A list has an entry from the time its first update is queued until its buffer is found
+/// empty after a batch, so that the updates arriving meanwhile are never queued on their own.
+#[derive(Default)]
+pub struct PendingUpdates(Mutex<HashMap<i64, Vec<(Uuid, Op)>>>);
+
+impl PendingUpdates {
+    /// Queues the update `req`, or buffers it if its list has an update queued or in progress.
+    /// The buffer of a list holds up to `limit` updates, past which `req` is returned as
+    /// `TrySendError::Full` like when the queue is full.
+    fn send(
+        &self,
+        queue_tx: &Sender<Request>,
+        req: Request,
+        limit: usize,
+    ) -> Result<(), TrySendError<Request>> {
+        let Request::Update { req_id, id, op } = req else {
+            return queue_tx.try_send(req);
+        };
+        // held while sending, so that the entry only exists once the update is queued
+        let mut pending = self.0.lock().expect("lock");
+        match pending.get_mut(&id) {
+            Some(buffered) if buffered.len() >= limit => {
+                Err(TrySendError::Full(Request::Update { req_id, id, op }))
+            }
+            Some(buffered) => {
+                buffered.push((req_id, op));
+                Ok(())
+            }
+            None => {
+                queue_tx.try_send(Request::Update { req_id, id, op })?;
+                pending.insert(id, Vec::new());
+                Ok(())
+            }
+        }
+    }
+
+    /// Number of updates buffered behind the update of their list, which aren't in the queue yet.
+    pub fn buffered(&self) -> usize {
+        self.0.lock().expect("lock").values().map(Vec::len).sum()
+    }
+
+    /// Like `send` for the updates resumed at startup, which aren't bounded: buffers the update
+    /// `op` of the list `id` if the list has an update queued, otherwise returns it as the update
+    /// to queue.
+    fn buffer_or_claim(&self, req_id: Uuid, id: i64, op: Op) -> Option<Request> {
+        let mut pending = self.0.lock().expect("lock");
+        match pending.get_mut(&id) {
+            Some(buffered) => {
+                buffered.push((req_id, op));
+                None
+            }
+            None => {
+                pending.insert(id, Vec::new());
+                Some(Request::Update { req_id, id, op })
+            }
+        }
+    }
+
+    /// Takes up to `max` of the updates buffered behind the list `id`, in arrival order.
+    fn take(&self, id: i64, max: usize) -> Vec<(Uuid, Op)> {
+        match self.0.lock().expect("lock").get_mut(&id) {
+            Some(buffered) => buffered.drain(..max.min(buffered.len())).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Like `take` once a batch of the list `id` is done, releasing the list if its buffer is
+    /// empty so that its next update is queued.
+    fn next_batch(&self, id: i64, max: usize) -> Vec<(Uuid, Op)> {
+        let mut pending = self.0.lock().expect("lock");
+        match pending.get_mut(&id) {
+            Some(buffered) if buffered.is_empty() => {
+                pending.remove(&id);
+                Vec::new()
+            }
+            Some(buffered) => buffered.drain(..max.min(buffered.len())).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Takes every buffered update, releasing all the lists.
+    fn take_all(&self) -> Vec<Request> {
+        self.0
+            .lock()
+            .expect("lock")
+            .drain()
+            .flat_map(|(id, buffered)| {
+                buffered
+                    .into_iter()
+                    .map(move |(req_id, op)| Request::Update { req_id, id, op })
+            })
+            .collect()
+    }
+}
+
+/// Handles the queued requests one after the other until the shutdown of the server.  The request
+/// being handled when the shutdown starts is finished, and the ones still in the queue are stored
+/// to be resumed on the next start.
 pub async fn handle_loop(ctx: Arc<Context>, mut queue_rx: Receiver<Request>) {
+    // pruning runs in the queue loop so that it doesn't race with the updates
+    let mut prune_interval = interval(Duration::from_secs(ctx.cfg.pods_prune_interval.max(1)));
     loop {
-        let res = match queue_rx.recv().await {
-            Some(req) => handle_req(ctx.clone(), req).await,
-            None => panic!("channel closed"),
+        let res = tokio::select! {
+            biased;
+            _ = ctx.shutdown.cancelled() => break,
+            req = queue_rx.recv() => match req {
+                Some(req) => handle_req(ctx.clone(), req).await,
+                None => panic!("channel closed"),
+            },
+            _ = prune_interval.tick(), if ctx.cfg.pods_retain_last_n > 0 => {
+                match ctx.prune_pods() {
+                    Ok(pruned) => info!("pruned {} pods", pruned.len()),
+                    Err(err) => warn!("failed to prune pods: {:#}", err),
+                }
+                Ok(())
+            }
         };
         if let Err(err) = res {
             panic!("Queue: {:?}", err);
         }
     }
+
+    queue_rx.close();
+    let mut stored = 0;
+    let mut queued = Vec::new();
+    while let Ok(req) = queue_rx.try_recv() {
+        queued.push(req);
+    }
+    // the updates buffered behind the queued ones of their list are stored after them, so that
+    // they are resumed in the same order
+    queued.extend(ctx.pending_updates.take_all());
+    for req in queued {
+        match db::insert_queue_request(&ctx.db_pool, &req).await {
+            Ok(()) => stored += 1,
+            Err(err) => warn!("failed to store queued request {:?}: {}", req, err),
+        }
+    }
+    info!("queue stopped, stored {} queued requests", stored);
+}
+
+/// Enqueues again the requests left by a previous run of the server: first the updates it left in
+/// the `WrappingMainPod` stage, then the requests stored by its shutdown, which keep their req_id
+/// so that clients can keep polling them.
+pub async fn resume_requests(ctx: &Context) -> Result<()> {
+    let queued = db::take_queue_requests(&ctx.db_pool).await?;
+    // wraps interrupted by the shutdown are stored with the req_id of their update
+    let queued_wraps: HashSet<(i64, i64)> = queued
+        .iter()
+        .filter_map(|req| match req {
+            Request::ResumeWrap { id, num, .. } => Some((*id, *num)),
+            _ => None,
+        })
+        .collect();
+    resume_pending_wraps(ctx, &queued_wraps).await?;
+
+    for req in queued {
+        let req_id = req.req_id();
+        ctx.queue_state
+            .write()
+            .await
+            .insert(req_id, req.pending_state());
+        if let Request::UpdateRev { id, .. } = req {
+            *ctx.rev_pending.lock().expect("lock").entry(id).or_default() += 1;
+        }
+        if let Request::ResumeWrap { other_req_ids, .. } = &req {
+            let mut queue_state = ctx.queue_state.write().await;
+            for other_req_id in other_req_ids {
+                queue_state.insert(*other_req_id, req.pending_state());
+            }
+        }
+        info!("resuming queued request {:?}", req);
+        let req = match req {
+            Request::Update { req_id, id, op } if ctx.cfg.update_batch_max > 1 => {
+                match ctx.pending_updates.buffer_or_claim(req_id, id, op) {
+                    Some(req) => req,
+                    None => continue,
+                }
+            }
+            req => req,
+        };
+        ctx.queue_tx.send(req).await?;
+    }
+    Ok(())
 }
 
 pub async fn handle_req(ctx: Arc<Context>, req: Request) -> Result<()> {
-    debug!(req = format!("{:?}", req), "handle queue request");
+    let span = req.span();
+    handle_req_in_span(ctx, req).instrument(span).await
+}
+
+async fn handle_req_in_span(ctx: Arc<Context>, req: Request) -> Result<()> {
+    debug!(?req, "handle queue request");
     match req {
-        Request::Create { req_id } => {
-            if let Err(err) = handle_create(ctx.clone(), req_id).await {
-                debug!(req_id = format!("{}", req_id), err = format!("{}", err));
-                ctx.queue_state
-                    .write()
-                    .await
-                    .insert(req_id, State::Create(StateCreate::Error(err.to_string())));
-            }
-        }
-        Request::Update { req_id, id, op } => {
-            if let Err(err) = handle_update(ctx.clone(), req_id, id, op).await {
-                debug!(req_id = format!("{}", req_id), err = format!("{}", err));
-                ctx.queue_state
-                    .write()
-                    .await
-                    .insert(req_id, State::Update(StateUpdate::Error(err.to_string())));
-            }
-        }
-        Request::UpdateRev { req_id, id, num } => {
-            if let Err(err) = handle_update_rev(ctx.clone(), req_id, id, num).await {
-                debug!(req_id = format!("{}", req_id), err = format!("{}", err));
+        Request::Create { req_id, kind } => {
+            if let Err(err) = handle_create(ctx.clone(), req_id, kind).await {
+                warn!(err = %err, "request failed");
+                ctx.queue_state.write().await.insert(
+                    req_id,
+                    State::Create(StateCreate::Error(ErrorInfo::from(&err))),
+                );
+            }
+        }
+        // sets the states of the failed updates of its batches
+        Request::Update { req_id, id, op } => handle_update(ctx.clone(), req_id, id, op).await,
+        Request::UpdateRev {
+            req_id, id, num, ..
+        } => {
+            let res = handle_update_rev(ctx.clone(), req_id, id, num).await;
+            release_rev_pending(&ctx, id);
+            match &res {
+                Ok(()) => {
+                    ctx.rev_backoff.lock().expect("lock").remove(&id);
+                }
+                // a duplicate of an update that went through
+                Err(Error::Conflict(_)) => {}
+                Err(_) => back_off_rev(&ctx, id),
+            }
+            if let Err(err) = res {
+                warn!(err = %err, "request failed");
+                ctx.queue_state.write().await.insert(
+                    req_id,
+                    State::UpdateRev(StateUpdateRev::Error(ErrorInfo::from(&err))),
+                );
+            }
+        }
+        Request::ResumeWrap {
+            req_id,
+            id,
+            num,
+            other_req_ids,
+        } => {
+            let req_ids: Vec<Uuid> = std::iter::once(req_id).chain(other_req_ids).collect();
+            if let Err(err) = handle_resume_wrap(ctx.clone(), &req_ids, id, num).await {
+                warn!(err = %err, "request failed");
+                if !retry_wrap(&ctx, &req_ids, id, num, &err).await {
+                    let mut queue_state = ctx.queue_state.write().await;
+                    for req_id in req_ids {
+                        queue_state.insert(
+                            req_id,
+                            State::Update(StateUpdate::Error(ErrorInfo::from(&err))),
+                        );
+                    }
+                }
+            }
+        }
+        Request::Query {
+            req_id,
+            id,
+            user,
+            at,
+        } => {
+            if let Err(err) = handle_query(ctx.clone(), req_id, id, user, at).await {
+                warn!(err = %err, "request failed");
+                ctx.queue_state.write().await.insert(
+                    req_id,
+                    State::Query(Box::new(StateQuery::Error(ErrorInfo::from(&err)))),
+                );
+            }
+        }
+        Request::QueryAll {
+            req_id,
+            user,
+            after_id,
+            limit,
+        } => {
+            if let Err(err) = handle_query_all(ctx.clone(), req_id, user, after_id, limit).await {
+                warn!(err = %err, "request failed");
                 ctx.queue_state.write().await.insert(
                     req_id,
-                    State::UpdateRev(StateUpdateRev::Error(err.to_string())),
+                    State::QueryAll(Box::new(StateQueryAll::Error(ErrorInfo::from(&err)))),
                 );
             }
         }
-        Request::Query { req_id, id, user } => {
-            if let Err(err) = handle_query(ctx.clone(), req_id, id, user).await {
-                debug!(req_id = format!("{}", req_id), err = format!("{}", err));
+        Request::ProveMembership {
+            req_id,
+            id,
+            user,
+            group,
+        } => {
+            if let Err(err) = handle_prove_membership(ctx.clone(), req_id, id, user, group).await {
+                warn!(err = %err, "request failed");
                 ctx.queue_state.write().await.insert(
                     req_id,
-                    State::Query(Box::new(StateQuery::Error(err.to_string()))),
+                    State::ProveMembership(StateProveMembership::Error(ErrorInfo::from(&err))),
+                );
+            }
+        }
+        Request::PrunePods { req_id } => {
+            if let Err(err) = handle_prune_pods(ctx.clone(), req_id).await {
+                warn!(err = %err, "request failed");
+                ctx.queue_state.write().await.insert(
+                    req_id,
+                    State::PrunePods(StatePrunePods::Error(ErrorInfo::from(&err))),
                 );
             }
         }
@@ -135,7 +717,7 @@ pub async fn handle_req(ctx: Arc<Context>, req: Request) -> Result<()> {
 }
 
 // TODO: Include proof.
-async fn handle_create(ctx: Arc<Context>, req_id: Uuid) -> Result<()> {
+async fn handle_create(ctx: Arc<Context>, req_id: Uuid, kind: db::ListKind) -> Result<(), Error> {
     let set_req_state = async |req_state| {
         ctx.queue_state
             .write()
@@ -143,136 +725,1012 @@ async fn handle_create(ctx: Arc<Context>, req_id: Uuid) -> Result<()> {
             .insert(req_id, State::Create(req_state));
     };
 
-    let latest_membership_list_id = match db::get_latest_membership_list(&ctx.db_pool).await {
-        Ok(Some(membership_list)) => membership_list.id,
-        Ok(None) => 0,
-        Err(e) => return Err(e.into()),
+    let latest_membership_list_id = match db::get_latest_membership_list(&ctx.db_pool).await? {
+        Some(membership_list) => membership_list.id,
+        None => 0,
     };
     let new_id = latest_membership_list_id + 1;
+    let now = db::unix_now();
+    let pod_config = ctx.pod_config()?;
 
     // Form new dictionary
     let membership_list = db::AdState {
         id: new_id,
         num: 0,
-        state: db::DictContainerSql(dict!(ctx.pod_config.params.max_depth_mt_containers, {})?),
+        state: db::DictContainerSql(
+            dict!(pod_config.params.max_depth_mt_containers, {}).map_err(anyhow::Error::from)?,
+        ),
+        created_at: now,
+        updated_at: now,
     };
 
     // send the payload to ethereum
     let payload_bytes = Payload::Create(PayloadCreate {
         id: Hash::from(RawValue::from(new_id)), // TODO hash
-        custom_predicate_ref: ctx.pod_config.state_predicates.update.clone(),
-        vds_root: ctx.pod_config.vd_set.root(),
+        custom_predicate_ref: pod_config.update_predicate(kind).clone(),
+        vds_root: pod_config.vd_set.root(),
+        params_fingerprint: Some(params_fingerprint(&pod_config.params)),
     })
-    .to_bytes();
+    .to_bytes()?;
 
     set_req_state(StateCreate::SendingBlobTx).await;
-    let tx_hash = crate::eth::send_payload(&ctx.cfg, payload_bytes).await?;
+    let (tx_hash, blob_versioned_hash, cost) =
+        crate::eth::send_payload(&ctx.cfg, ctx.eth.as_ref(), payload_bytes)
+            .await
+            .map_err(Error::EthRpc)?;
+    ctx.metrics.count_payload();
 
     // update db
-    db::insert_membership_list(&ctx.db_pool, &membership_list).await?;
-    let rev_membership_list = db::AdState {
-        id: new_id,
-        num: 0,
-        state: db::DictContainerSql(dict!(ctx.pod_config.params.max_depth_mt_containers, {})?),
-    };
-    db::insert_rev_membership_list(&ctx.db_pool, &rev_membership_list).await?;
+    db::insert_membership_list(&ctx.db_pool, &membership_list, kind, blob_versioned_hash).await?;
+    db::insert_update_cost(&ctx.db_pool, new_id, 0, tx_hash, &cost).await?;
+    // the kv lists are queried from their state, without a reverse index
+    if kind == db::ListKind::Set {
+        let rev_membership_list = db::AdState {
+            id: new_id,
+            num: 0,
+            state: db::DictContainerSql(
+                dict!(pod_config.params.max_depth_mt_containers, {})
+                    .map_err(anyhow::Error::from)?,
+            ),
+            created_at: now,
+            updated_at: now,
+        };
+        db::insert_rev_membership_list(&ctx.db_pool, &rev_membership_list).await?;
+    }
 
     set_req_state(StateCreate::Complete {
         id: membership_list.id,
         tx_hash,
+        blob_versioned_hash,
+        cost,
     })
     .await;
     Ok(())
 }
 
-async fn handle_update(ctx: Arc<Context>, req_id: Uuid, id: i64, op: Op) -> Result<()> {
-    let set_req_state = async |req_state| {
-        ctx.queue_state
-            .write()
-            .await
-            .insert(req_id, State::Update(req_state));
+fn group_set(state: &Dictionary, group: &Group) -> Result<Value, Error> {
+    let group_set = state
+        .get(&Key::from(group.as_str()))
+        .map_err(|_| Error::NotFound(format!(r#"Group "{}" doesn't exist."#, group)))?;
+    Ok(group_set.clone())
+}
+
+fn is_member(group_set: &Value, user: &str) -> Result<bool, Error> {
+    match group_set.typed() {
+        TypedValue::Set(set) => Ok(set.contains(&Value::from(user))),
+        // the group of a kv list
+        TypedValue::Dictionary(dict) => Ok(dict.get(&Key::from(user)).is_ok()),
+        _ => Err(anyhow!("group is not a Set: {:?}", group_set).into()),
+    }
+}
+
+/// Checks that `op` applies to the lists of `kind` and changes the membership list, so that
+/// redundant ops are rejected before proving them.
+pub fn check_op(membership_list: &db::AdState, kind: db::ListKind, op: &Op) -> Result<(), Error> {
+    let kv_op = matches!(op, Op::AddKv { .. } | Op::DelKv { .. });
+    let set_op = matches!(op, Op::Add { .. } | Op::Del { .. });
+    if (kv_op && kind != db::ListKind::Kv) || (set_op && kind != db::ListKind::Set) {
+        return Err(Error::InvalidOp(format!(
+            "membership list {} is a {} list",
+            membership_list.id, kind
+        )));
+    }
+    let (group, user, add) = match op {
+        Op::Init if membership_list.num != 0 => {
+            return Err(Error::Conflict(format!(
+                "membership list {} is already initialized",
+                membership_list.id
+            )));
+        }
+        Op::Init => return Ok(()),
+        _ if membership_list.num == 0 => return Err(Error::NotInitialized(membership_list.id)),
+        Op::AddGroup { group } => {
+            return match group_set(&membership_list.state.0, group) {
+                Ok(_) => Err(Error::Conflict(format!(
+                    r#"Group "{}" already exists."#,
+                    group
+                ))),
+                Err(_) => Ok(()),
+            };
+        }
+        Op::DropGroup { group } => {
+            // empty sets and dictionaries commit to EMPTY
+            if group_set(&membership_list.state.0, group)?.raw() != EMPTY_VALUE {
+                return Err(Error::Conflict(format!(
+                    r#"Group "{}" is not empty."#,
+                    group
+                )));
+            }
+            return Ok(());
+        }
+        Op::Add { group, user } | Op::AddKv { group, user, .. } => (group, user, true),
+        Op::Del { group, user } | Op::DelKv { group, user } => (group, user, false),
+    };
+    let is_member = is_member(&group_set(&membership_list.state.0, group)?, user.as_str())?;
+    match (add, is_member) {
+        (true, true) => Err(Error::Conflict(format!(
+            r#"User "{}" is already a member of group "{}"."#,
+            user, group
+        ))),
+        (false, false) => Err(Error::Conflict(format!(
+            r#"User "{}" is not a member of group "{}"."#,
+            user, group
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Proves the pod `pod_key` built by `builder`, setting the state of the requests `req_ids` to
+/// `proving` with the seconds elapsed every second until it's proven.
+///
+/// The proving runs on a dedicated thread and is given up after `Config::proving_timeout`.
+/// plonky2 has no way to interrupt a proving, so on timeout the thread is detached: it keeps a
+/// core busy until the proving completes or the server restarts, which is the price of not
+/// blocking the request (and the shutdown) on a pathological update.  Every detached thread is
+/// recorded in the `proving_incident` table for the operators.  The thread isn't a
+/// `spawn_blocking` one as the runtime waits for those when it shuts down.
+async fn prove_bounded(
+    ctx: &Context,
+    req_ids: &[Uuid],
+    pod_key: PodKey,
+    builder: MainPodBuilder,
+    proving: impl Fn(u64) -> State,
+) -> Result<MainPod, Error> {
+    let (pod_tx, mut pod_rx) = oneshot::channel();
+    std::thread::Builder::new()
+        .name(format!("prove-{}", pod_key.file_name()))
+        .spawn(move || {
+            // the receiver is gone if the proving timed out
+            let _ = pod_tx.send(builder.prove(&Prover {}));
+        })
+        .map_err(anyhow::Error::from)?;
+
+    let start = Instant::now();
+    let timeout = async {
+        match ctx.cfg.proving_timeout {
+            Some(timeout) => sleep(timeout).await,
+            None => std::future::pending().await,
+        }
     };
+    tokio::pin!(timeout);
+    let mut progress = interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            pod = &mut pod_rx => {
+                let pod = pod.map_err(|_| {
+                    anyhow!("proving thread of {} panicked", pod_key.file_name())
+                })?;
+                return pod.map_err(|e| Error::ProvingFailed(e.into()));
+            }
+            _ = &mut timeout => {
+                let elapsed = start.elapsed();
+                warn!(
+                    ?elapsed,
+                    "proving of {} timed out, its thread keeps running detached",
+                    pod_key.file_name()
+                );
+                db::insert_proving_incident(
+                    &ctx.db_pool,
+                    &db::ProvingIncident {
+                        id: pod_key.id,
+                        num: pod_key.num,
+                        pod_name: pod_key.file_name(),
+                        elapsed_ms: elapsed.as_millis() as i64,
+                        created_at: db::unix_now(),
+                    },
+                )
+                .await?;
+                return Err(Error::ProvingTimedOut(elapsed));
+            }
+            _ = progress.tick() => {
+                let mut queue_state = ctx.queue_state.write().await;
+                for req_id in req_ids {
+                    queue_state.insert(*req_id, proving(start.elapsed().as_secs()));
+                }
+            }
+        }
+    }
+}
+
+/// Applies the update `op` of the list `id`, batched with the updates buffered behind it, and then
+/// the rest of the buffered updates in batches of up to `Config::update_batch_max`, until the
+/// buffer of the list is empty.  The buffered updates left by a shutdown are stored by the queue
+/// loop.
+async fn handle_update(ctx: Arc<Context>, req_id: Uuid, id: i64, op: Op) {
+    let max = ctx.cfg.update_batch_max;
+    let mut batch = vec![(req_id, op)];
+    batch.extend(ctx.pending_updates.take(id, max - 1));
+    loop {
+        if let Err(err) = handle_update_batch(ctx.clone(), id, &mut batch).await {
+            warn!(err = %err, batch = batch.len(), "request failed");
+            let mut queue_state = ctx.queue_state.write().await;
+            for (req_id, _) in &batch {
+                queue_state.insert(
+                    *req_id,
+                    State::Update(StateUpdate::Error(ErrorInfo::from(&err))),
+                );
+            }
+        }
+        if ctx.shutdown.is_cancelled() {
+            break;
+        }
+        batch = ctx.pending_updates.next_batch(id, max);
+        if batch.is_empty() {
+            break;
+        }
+    }
+}
+
+/// Proves the updates `batch` of the list `id` in one main pod and sends them in one payload.  An
+/// op that doesn't apply to the state left by the ones before it is dropped from `batch` with an
+/// error state of its own, so that on error `batch` holds the updates that the error fails.
+async fn handle_update_batch(
+    ctx: Arc<Context>,
+    id: i64,
+    batch: &mut Vec<(Uuid, Op)>,
+) -> Result<(), Error> {
     // TODO: User validation
 
+    // held until the updates are applied, so that the next update of the list starts from their
+    // state
+    let _list_guard = ctx.list_locks.lock(id).await;
+
     // get state from db
-    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    let mut membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    let kind = db::get_membership_list_kind(&ctx.db_pool, id).await?;
+    // the endpoint already checked the ops and the flag, but the list may have been updated or
+    // audited since
+    audit::check_not_flagged(&ctx, id).await?;
+    // the next update of the list is proven already, and its wrap is resumed ahead of the batch
+    let resumed = db::get_pending_wrap(&ctx.db_pool, id, membership_list.num + 1)
+        .await
+        .is_ok_and(|pending_wrap| pending_wrap.resumable(ctx.cfg.wrap_max_attempts));
+    if resumed {
+        return Err(Error::Conflict(format!(
+            "update {} of membership list {} is being resumed, retry",
+            membership_list.num + 1,
+            id
+        )));
+    }
+
+    let start = std::time::Instant::now();
+
+    let pod_config = ctx.pod_config()?;
+    let old_state = membership_list.state.0.clone();
+    let first_num = membership_list.num + 1;
+    // every op is checked against the state left by the ones before it, and applied in a builder
+    // of its own so that an op that the predicates reject is dropped alone
+    let mut steps = Vec::new();
+    for (req_id, op) in std::mem::take(batch) {
+        // the op commits to the num of the list after the update, which the proof links to the
+        // num in the state
+        let num = membership_list.num + 1;
+        let op_dict = op.clone().into_dict(&pod_config.params, num);
+        let applied = check_op(&membership_list, kind, &op).and_then(|()| {
+            let mut builder = MainPodBuilder::new(&pod_config.params, &pod_config.vd_set);
+            let mut helper = pod_config.helper(&mut builder, kind);
+            helper
+                .st_update(membership_list.state.0.clone(), op_dict.clone())
+                .map(|(new_state, _)| new_state)
+                .map_err(|e| Error::InvalidOp(format!("{:#}", e)))
+        });
+        match applied {
+            Ok(new_state) => {
+                membership_list.num = num;
+                membership_list.state = db::DictContainerSql(new_state.clone());
+                steps.push((op_dict, new_state));
+                batch.push((req_id, op));
+            }
+            Err(err) => {
+                warn!(%req_id, err = %err, "op dropped from the batch");
+                ctx.queue_state.write().await.insert(
+                    req_id,
+                    State::Update(StateUpdate::Error(ErrorInfo::from(&err))),
+                );
+            }
+        }
+    }
+    let Some((op_dict, new_state)) = steps.last().cloned() else {
+        return Ok(());
+    };
+    let num = membership_list.num;
+    let op_raw = RawValue::from(op_dict.commitment());
+    record_update(num, op_raw);
 
     // with the actual POD
-    let state = membership_list.state;
-    let num = membership_list.num + 1;
+    let mut builder = MainPodBuilder::new(&pod_config.params, &pod_config.vd_set);
+    let mut helper = pod_config.helper(&mut builder, kind);
+    let st_updates = helper
+        .st_update_batch(old_state, steps.iter().map(|(op_dict, _)| op_dict.clone()))
+        .map_err(|e| Error::InvalidOp(format!("{:#}", e)))?;
+    for (_, st_update) in &st_updates {
+        builder.reveal(st_update);
+    }
 
-    let start = std::time::Instant::now();
+    let req_ids: Vec<Uuid> = batch.iter().map(|(req_id, _)| *req_id).collect();
+    let pod_key = PodKey::membership_list(id, num);
+    let pod = prove_bounded(&ctx, &req_ids, pod_key, builder, |elapsed_secs| {
+        State::Update(StateUpdate::ProvingMainPod { elapsed_secs })
+    })
+    .await?;
+    debug!("state pod\n{}", pod);
+    pod.pod
+        .verify()
+        .map_err(|e| Error::ProvingFailed(e.into()))?;
 
-    let mut builder = MainPodBuilder::new(&ctx.pod_config.params, &ctx.pod_config.vd_set);
-    let mut helper = Helper::new(&mut builder, &ctx.pod_config.state_predicates);
+    // the pod proves every update of the batch, and is stored once under the last one
+    ctx.store_batch_pod(pod_key, Some(first_num), &pod, false)?;
+    // from here on the updates can be resumed without proving the main pod again
+    db::insert_pending_wrap(
+        &ctx.db_pool,
+        &db::PendingWrap {
+            id,
+            num,
+            pod_name: pod_key.file_name(),
+            op: db::RawValueSql(op_raw),
+            new_state: db::DictContainerSql(new_state.clone()),
+            attempts: 1,
+            first_num: Some(first_num),
+            tx_hash: None,
+            blob_versioned_hash: None,
+        },
+    )
+    .await?;
+    for (num, ((_, op), (_, state))) in (first_num..).zip(batch.iter().zip(&steps)) {
+        db::insert_op_log(
+            &ctx.db_pool,
+            id,
+            &db::OpLogEntry {
+                num,
+                op: op.clone(),
+                state: state.commitment(),
+            },
+        )
+        .await?;
+    }
+    // the ops history is extended from the one of the state the batch is proven from, and stays
+    // untracked for the lists that don't publish it
+    if let Some(mut ops) = ops_at(&ctx, id, first_num - 1).await? {
+        for (num, (op_dict, _)) in (first_num..).zip(&steps) {
+            ops::insert_op(&mut ops, num, RawValue::from(op_dict.commitment()))
+                .map_err(Error::Internal)?;
+        }
+        db::insert_list_ops(&ctx.db_pool, id, num, &ops).await?;
+    }
+    info!(elapsed = ?start.elapsed(), batch = steps.len(), "state pod proven");
+    ctx.metrics.observe(Timing::StatePod, start.elapsed());
+
+    let batch_steps = steps[..steps.len() - 1]
+        .iter()
+        .map(|(op_dict, state)| UpdateStep {
+            new_state: RawValue::from(state.commitment()),
+            op: RawValue::from(op_dict.commitment()),
+        })
+        .collect();
+    let res = wrap_and_send(
+        ctx.clone(),
+        &req_ids,
+        id,
+        num,
+        pod,
+        new_state,
+        op_raw,
+        batch_steps,
+    )
+    .await;
+    match res {
+        Err(err) if retry_wrap(&ctx, &req_ids, id, num, &err).await => {
+            // the requests follow the resumed wrap
+            batch.clear();
+            Err(err)
+        }
+        res => res,
+    }
+}
+
+/// Retries the wrap/send/db-update tail of an update whose main pod was already proven, or only
+/// the db-update if its payload was sent already.
+async fn handle_resume_wrap(
+    ctx: Arc<Context>,
+    req_ids: &[Uuid],
+    id: i64,
+    num: i64,
+) -> Result<(), Error> {
+    let _list_guard = ctx.list_locks.lock(id).await;
+    let pending_wrap = db::get_pending_wrap(&ctx.db_pool, id, num).await?;
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    if membership_list.num >= num {
+        // the update was applied but the server stopped before clearing the pending wrap
+        db::delete_pending_wrap(&ctx.db_pool, id, num).await?;
+        return Err(anyhow!("update {}-{} already applied", id, num).into());
+    }
+    let first_num = pending_wrap.first_num.unwrap_or(num);
+    if let Some(tx_hash) = &pending_wrap.tx_hash {
+        let tx_hash = TxHash::try_from(tx_hash.as_slice()).map_err(anyhow::Error::from)?;
+        let blob_versioned_hash = pending_wrap
+            .blob_versioned_hash
+            .as_deref()
+            .map(B256::try_from)
+            .transpose()
+            .map_err(anyhow::Error::from)?;
+        // recorded when the payload was sent
+        let cost = db::get_update_costs(&ctx.db_pool, id)
+            .await?
+            .into_iter()
+            .find(|update_cost| update_cost.num == num)
+            .map(|update_cost| update_cost.cost)
+            .unwrap_or_default();
+        info!(%tx_hash, first_num, "update already sent, applying it");
+        record_update(num, pending_wrap.op.0);
+        return apply_sent_update(
+            ctx,
+            req_ids,
+            id,
+            num,
+            first_num,
+            pending_wrap.new_state.0,
+            tx_hash,
+            blob_versioned_hash,
+            cost,
+        )
+        .await;
+    }
+    if pending_wrap.attempts >= ctx.cfg.wrap_max_attempts {
+        return Err(anyhow!(
+            "giving up wrapping {} after {} attempts",
+            pending_wrap.pod_name,
+            pending_wrap.attempts
+        )
+        .into());
+    }
+    db::update_pending_wrap_attempts(&ctx.db_pool, id, num, pending_wrap.attempts + 1).await?;
 
-    let op = Dictionary::from(op);
-    let op_raw = RawValue::from(op.commitment());
+    // the updates of the batch before `num` are rebuilt from the op log
+    let params = &ctx.pod_config()?.params;
+    let batch: Vec<UpdateStep> = db::get_op_log(&ctx.db_pool, id, first_num, num - 1)
+        .await?
+        .into_iter()
+        .map(|entry| UpdateStep {
+            new_state: RawValue::from(entry.state),
+            op: RawValue::from(entry.op.into_dict(params, entry.num).commitment()),
+        })
+        .collect();
+    if batch.len() as i64 != num - first_num {
+        return Err(anyhow!(
+            "op log of the batch {}-{}..={} is incomplete",
+            id,
+            first_num,
+            num
+        )
+        .into());
+    }
 
-    let (new_state, st_update) = helper.st_update(state.0.clone(), op)?;
-    builder.reveal(&st_update);
+    record_update(num, pending_wrap.op.0);
+    let pod = ctx.load_pod(PodKey::membership_list(id, num))?;
+    wrap_and_send(
+        ctx,
+        req_ids,
+        id,
+        num,
+        pod,
+        pending_wrap.new_state.0,
+        pending_wrap.op.0,
+        batch,
+    )
+    .await
+}
 
-    set_req_state(StateUpdate::ProvingMainPod).await;
-    let prover = Prover {};
-    let pod = task::spawn_blocking(move || builder.prove(&prover).unwrap()).await?;
-    println!("# state_pod\n:{}", pod);
-    pod.pod.verify().unwrap();
-
-    store_pod(
-        Path::new(&ctx.cfg.pods_path),
-        &format!("{:08}-{:08}-membership_list", id, num),
-        &pod,
-    )?;
+/// Enqueues a `ResumeWrap` for every update left in the `WrappingMainPod` stage by a previous
+/// run of the server, except the `(id, num)` in `skip`.
+async fn resume_pending_wraps(ctx: &Context, skip: &HashSet<(i64, i64)>) -> Result<()> {
+    for pending_wrap in db::get_pending_wraps(&ctx.db_pool).await? {
+        if skip.contains(&(pending_wrap.id, pending_wrap.num)) {
+            continue;
+        }
+        if !pending_wrap.resumable(ctx.cfg.wrap_max_attempts) {
+            warn!(
+                "skipping pending wrap {} after {} attempts",
+                pending_wrap.pod_name, pending_wrap.attempts
+            );
+            continue;
+        }
+        let req_id = Uuid::now_v7();
+        ctx.queue_state
+            .write()
+            .await
+            .insert(req_id, State::Update(StateUpdate::Pending));
+        ctx.queue_tx
+            .send(Request::ResumeWrap {
+                req_id,
+                id: pending_wrap.id,
+                num: pending_wrap.num,
+                other_req_ids: Vec::new(),
+            })
+            .await?;
+        info!(
+            %req_id,
+            id = pending_wrap.id,
+            num = pending_wrap.num,
+            "scheduling ResumeWrap"
+        );
+    }
+    Ok(())
+}
+
+/// Enqueues a `ResumeWrap` of the update `num` of the list `id` after its wrap failed with `err`,
+/// as long as its pending wrap is kept and resumable.  The `ResumeWrap` keeps the req_ids of the
+/// batch, which stay pending.  Returns whether it was enqueued: the wraps interrupted by the
+/// shutdown are resumed on the next start instead, as are the ones that don't fit in the queue.
+async fn retry_wrap(ctx: &Context, req_ids: &[Uuid], id: i64, num: i64, err: &Error) -> bool {
+    if matches!(err, Error::ShuttingDown) {
+        return false;
+    }
+    match db::get_pending_wrap(&ctx.db_pool, id, num).await {
+        Ok(pending_wrap) if pending_wrap.resumable(ctx.cfg.wrap_max_attempts) => {}
+        _ => return false,
+    }
+    {
+        let mut queue_state = ctx.queue_state.write().await;
+        for req_id in &req_ids[1..] {
+            queue_state.insert(*req_id, State::Update(StateUpdate::Pending));
+        }
+    }
+    let req = Request::ResumeWrap {
+        req_id: req_ids[0],
+        id,
+        num,
+        other_req_ids: req_ids[1..].to_vec(),
+    };
+    match enqueue(ctx, req).await {
+        Ok(req_id) => {
+            info!(%req_id, id, num, "scheduling ResumeWrap");
+            true
+        }
+        Err(enqueue_err) => {
+            warn!(
+                "failed to schedule the ResumeWrap of {}-{}: {}",
+                id, num, enqueue_err
+            );
+            false
+        }
+    }
+}
+
+/// Wraps the main pod `pod` of the updates `num - batch.len()..=num` of the list `id` and sends
+/// them in one payload, where `batch` are the updates before `num`.  `req_ids` are the requests of
+/// the updates, which share the state of the batch.
+#[allow(clippy::too_many_arguments)]
+async fn wrap_and_send(
+    ctx: Arc<Context>,
+    req_ids: &[Uuid],
+    id: i64,
+    num: i64,
+    pod: MainPod,
+    new_state: Dictionary,
+    op_raw: RawValue,
+    batch: Vec<UpdateStep>,
+) -> Result<(), Error> {
+    let set_req_state = async |req_state: StateUpdate| {
+        let mut queue_state = ctx.queue_state.write().await;
+        for req_id in req_ids {
+            queue_state.insert(*req_id, State::Update(req_state.clone()));
+        }
+    };
+    let first_num = num - batch.len() as i64;
+
+    // the proven main pod is kept with its pending wrap, so the updates are resumed on the next
+    // start rather than holding the shutdown while wrapping.  The resumed wrap keeps the req_ids
+    // of the batch.
+    if ctx.shutdown.is_cancelled() {
+        let req = Request::ResumeWrap {
+            req_id: req_ids[0],
+            id,
+            num,
+            other_req_ids: req_ids[1..].to_vec(),
+        };
+        db::insert_queue_request(&ctx.db_pool, &req).await?;
+        return Err(Error::ShuttingDown);
+    }
+
+    let start = std::time::Instant::now();
     set_req_state(StateUpdate::WrappingMainPod).await;
     let compressed_proof = match ctx.cfg.proof_type {
         ProofType::Plonky2 => {
             let ctx = ctx.clone();
             let compressed_proof = task::spawn_blocking(move || {
-                shrink_compress_pod(&ctx.shrunk_main_pod_build, pod).unwrap()
+                shrink_compress_pod(&ctx.setup()?.shrunk_main_pod_build, pod)
             })
-            .await?;
+            .await?
+            .map_err(Error::ProvingFailed)?;
             PayloadProof::Plonky2(Box::new(compressed_proof))
         }
+        #[cfg(feature = "groth16")]
         ProofType::Groth16 => {
-            let (compressed_proof, _) =
-                task::spawn_blocking(move || groth::prove(pod).unwrap()).await?;
+            let sts_hash = pod.statements_hash();
+            let vds_root = ctx.pod_config()?.vd_set.root();
+            let (compressed_proof, pub_inp) =
+                task::spawn_blocking(move || common::groth::prove(pod))
+                    .await?
+                    .map_err(Error::ProvingFailed)?;
+            // checked before spending a tx on a proof that won't verify
+            common::groth::check_public_inputs(&pub_inp, sts_hash, vds_root)
+                .map_err(Error::ProvingFailed)?;
             PayloadProof::Groth16(compressed_proof)
         }
+        // rejected when the config is parsed
+        #[cfg(not(feature = "groth16"))]
+        ProofType::Groth16 => {
+            return Err(Error::ProvingFailed(anyhow!(
+                "groth16 support is not compiled in"
+            )));
+        }
+    };
+    info!(elapsed = ?start.elapsed(), "state pod wrapped");
+    let timing = match ctx.cfg.proof_type {
+        ProofType::Plonky2 => Timing::Shrink,
+        ProofType::Groth16 => Timing::Groth,
     };
-    println!("[TIME] state pod {:?}", start.elapsed());
+    ctx.metrics.observe(timing, start.elapsed());
 
+    let new_state_raw = RawValue::from(new_state.commitment());
+    // the lists restored without their ops history don't publish it
+    let ops_root = ops_at(&ctx, id, num).await?.map(|ops| ops.commitment());
+    let op_dicts = if ctx.cfg.disclose_ops {
+        let params = &ctx.pod_config()?.params;
+        db::get_op_log(&ctx.db_pool, id, first_num, num)
+            .await?
+            .into_iter()
+            .map(|entry| entry.op.into_dict(params, entry.num))
+            .collect()
+    } else {
+        Vec::new()
+    };
     let payload_bytes = Payload::Update(PayloadUpdate {
         id: Hash::from(RawValue::from(id)), // TODO hash
         proof: compressed_proof,
-        new_state: new_state.commitment().into(),
+        new_state: new_state_raw,
         op: op_raw,
+        epoch: Some(num),
+        ops_root,
+        batch,
+        op_dicts,
     })
-    .to_bytes();
+    .to_bytes()?;
+
+    // the payload proves the transition from the state at `first_num - 1`, which must still be
+    // the state of the list when it's published
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    if membership_list.num != first_num - 1 {
+        return Err(Error::Conflict(format!(
+            "concurrent update detected on membership list {} (expected num {}, found {}), retry",
+            id,
+            first_num - 1,
+            membership_list.num
+        )));
+    }
 
     set_req_state(StateUpdate::SendingBlobTx).await;
-    let tx_hash = crate::eth::send_payload(&ctx.cfg, payload_bytes).await?;
+    let (tx_hash, blob_versioned_hash, cost) =
+        crate::eth::send_payload(&ctx.cfg, ctx.eth.as_ref(), payload_bytes)
+            .await
+            .map_err(Error::EthRpc)?;
+    ctx.metrics.count_payload();
+    info!(%tx_hash, first_num, "update sent");
+    // a resumed wrap applies the updates without sending them again.  The batch is sent in one
+    // tx, whose cost is recorded at its last update.
+    db::set_pending_wrap_sent(&ctx.db_pool, id, num, tx_hash, blob_versioned_hash).await?;
+    db::insert_update_cost(&ctx.db_pool, id, num, tx_hash, &cost).await?;
 
-    db::update_membership_list(&ctx.db_pool, id, num, new_state).await?;
+    apply_sent_update(
+        ctx,
+        req_ids,
+        id,
+        num,
+        first_num,
+        new_state,
+        tx_hash,
+        blob_versioned_hash,
+        cost,
+    )
+    .await
+}
 
-    set_req_state(StateUpdate::Complete { tx_hash }).await;
+/// Applies the updates `first_num..=num` of the list `id` once their payload is sent in the tx
+/// `tx_hash`, and schedules the work that follows them.  `req_ids` are the requests of the
+/// updates.
+#[allow(clippy::too_many_arguments)]
+async fn apply_sent_update(
+    ctx: Arc<Context>,
+    req_ids: &[Uuid],
+    id: i64,
+    num: i64,
+    first_num: i64,
+    new_state: Dictionary,
+    tx_hash: TxHash,
+    blob_versioned_hash: Option<B256>,
+    cost: TxCostInfo,
+) -> Result<(), Error> {
+    let set_req_state = async |req_state: StateUpdate| {
+        let mut queue_state = ctx.queue_state.write().await;
+        for req_id in req_ids {
+            queue_state.insert(*req_id, State::Update(req_state.clone()));
+        }
+    };
+    // the payload is on chain, so the updates are applied even if their pod is lost
+    if let Err(err) = ctx
+        .pod_store
+        .set_confirmed(PodKey::membership_list(id, num))
     {
-        let req_id = Uuid::now_v7();
-        ctx.queue_state
-            .write()
-            .await
-            .insert(req_id, State::UpdateRev(StateUpdateRev::Pending));
-        ctx.queue_tx
-            .send(Request::UpdateRev { req_id, id, num })
-            .await?;
-        info!(
-            "self-scheduling UpdateRev {}-{} with req_id={}",
-            id, num, req_id
+        warn!("failed to confirm the pod of {}-{}: {}", id, num, err);
+    }
+
+    let updated = db::update_membership_list(
+        &ctx.db_pool,
+        id,
+        first_num - 1,
+        num,
+        new_state.clone(),
+        blob_versioned_hash,
+    )
+    .await?;
+    db::delete_pending_wrap(&ctx.db_pool, id, num).await?;
+    if updated == 0 {
+        // the conflicts are checked before sending under the lock of the list, so the list was
+        // written outside of the queue.  The payload is on chain anyway, so the requests get its
+        // tx instead of an error that would have them send it again.
+        warn!(
+            %tx_hash,
+            "membership list {} was updated past num {} while sending update {}",
+            id,
+            first_num - 1,
+            num
+        );
+        set_req_state(StateUpdate::Complete {
+            tx_hash,
+            blob_versioned_hash,
+            cost,
+        })
+        .await;
+        return Ok(());
+    }
+    // the blooms are only a cache of the state, so failing to store them doesn't fail the update
+    let blooms = bloom::group_blooms(&new_state);
+    if let Err(err) = db::replace_group_blooms(&ctx.db_pool, id, num, &blooms).await {
+        warn!(
+            "failed to store the group blooms of {}-{}: {}",
+            id, num, err
         );
     }
+    // the ops history before the update stays for the op proofs of the requests that read the list
+    // before it was applied
+    if let Err(err) = db::delete_list_ops_before(&ctx.db_pool, id, first_num - 1).await {
+        warn!("failed to prune the ops history of {}-{}: {}", id, num, err);
+    }
+
+    set_req_state(StateUpdate::Complete {
+        tx_hash,
+        blob_versioned_hash,
+        cost,
+    })
+    .await;
+    // the reconciler schedules them later if the queue is full.  The kv lists have no reverse
+    // index.
+    if db::get_membership_list_kind(&ctx.db_pool, id).await? == db::ListKind::Set {
+        for (i, num) in (first_num..=num).enumerate() {
+            if let Err(err) = schedule_update_rev(&ctx, id, num, req_ids.get(i).copied()).await {
+                warn!(
+                    "failed to schedule the UpdateRev of {}-{}: {}",
+                    id, num, err
+                );
+            }
+        }
+    }
+
+    if ctx.cfg.snapshot_interval > 0
+        && (first_num..=num).any(|num| num % ctx.cfg.snapshot_interval == 0)
+    {
+        // the update is already applied, so a failed snapshot doesn't fail it
+        let snapshot = Payload::Snapshot(PayloadSnapshot {
+            id: Hash::from(RawValue::from(id)), // TODO hash
+            state: RawValue::from(new_state.commitment()),
+            dict: new_state,
+        });
+        let sent = match snapshot.to_bytes() {
+            Ok(snapshot_bytes) => {
+                crate::eth::send_payload(&ctx.cfg, ctx.eth.as_ref(), snapshot_bytes).await
+            }
+            Err(err) => Err(err),
+        };
+        match sent {
+            Ok((tx_hash, _, cost)) => {
+                ctx.metrics.count_payload();
+                info!(
+                    total_fee = cost.total_fee,
+                    "sent snapshot {}-{} in tx {}", id, num, tx_hash
+                )
+            }
+            Err(err) => warn!("failed to send snapshot {}-{}: {}", id, num, err),
+        }
+    }
     Ok(())
 }
 
-async fn handle_update_rev(ctx: Arc<Context>, req_id: Uuid, id: i64, num: i64) -> Result<()> {
+/// Enqueues the `UpdateRev` that moves the reverse index of the list `id` to `num`, returning its
+/// req_id.  `parent_req_id` is the req_id of the update that applied `num`, if any.  Fails with
+/// `Error::QueueFull` if the queue is full, which must not block since the queue loop schedules
+/// them too.
+async fn schedule_update_rev(
+    ctx: &Context,
+    id: i64,
+    num: i64,
+    parent_req_id: Option<Uuid>,
+) -> Result<Uuid, Error> {
+    *ctx.rev_pending.lock().expect("lock").entry(id).or_default() += 1;
+    let req = Request::UpdateRev {
+        req_id: Uuid::now_v7(),
+        id,
+        num,
+        parent_req_id,
+    };
+    match enqueue(ctx, req).await {
+        Ok(req_id) => {
+            info!(%req_id, id, num, "scheduling UpdateRev");
+            Ok(req_id)
+        }
+        Err(err) => {
+            release_rev_pending(ctx, id);
+            Err(err)
+        }
+    }
+}
+
+/// Counts out an `UpdateRev` of the list `id` that was handled or couldn't be enqueued.
+fn release_rev_pending(ctx: &Context, id: i64) {
+    let mut rev_pending = ctx.rev_pending.lock().expect("lock");
+    if let Some(pending) = rev_pending.get_mut(&id) {
+        *pending -= 1;
+        if *pending == 0 {
+            rev_pending.remove(&id);
+        }
+    }
+}
+
+/// Enqueues again the `UpdateRev` that moves the reverse index of the list `id` to `num`, after
+/// it failed.  The reverse index must be at `num - 1` with no `UpdateRev` scheduled, and the state
+/// pod of the update `num` must be on disk since the reverse index pod is built on it.
+pub async fn retry_update_rev(ctx: &Context, id: i64, num: i64) -> Result<Uuid, Error> {
+    let rev_membership_list = db::get_rev_membership_list(&ctx.db_pool, id).await?;
+    if rev_membership_list.num != num - 1 {
+        return Err(Error::Conflict(format!(
+            "reverse index of membership list {} is at num {}, can't move it to {}",
+            id, rev_membership_list.num, num
+        )));
+    }
+    if ctx.rev_pending.lock().expect("lock").contains_key(&id) {
+        return Err(Error::Conflict(format!(
+            "reverse index update of membership list {} already scheduled",
+            id
+        )));
+    }
+    let pod_key = PodKey::membership_list(id, num);
+    if !ctx.pod_store.file_path(pod_key).exists() {
+        return Err(Error::NotFound(format!("pod {}", pod_key.file_name())));
+    }
+    schedule_update_rev(ctx, id, num, None).await
+}
+
+/// Returns the `(id, num)` of the next reverse index update of every list whose reverse index
+/// is behind, skipping the lists with an `UpdateRev` already scheduled so that there's at most
+/// one outstanding reverse index proof per list.
+pub fn rev_updates_to_schedule(lags: &[db::RevLag], pending: &HashSet<i64>) -> Vec<(i64, i64)> {
+    lags.iter()
+        .filter(|lag| lag.rev_num < lag.num && !pending.contains(&lag.id))
+        .map(|lag| (lag.id, lag.rev_num + 1))
+        .collect()
+}
+
+/// Longest wait before the reconciler retries the reverse index of a list whose `UpdateRev` keeps
+/// failing
+pub const REV_BACKOFF_MAX: Duration = Duration::from_secs(60 * 60);
+
+/// Failed `UpdateRev`s in a row of a membership list, which the reconciler doesn't retry before
+/// `retry_at`.  Reset once an `UpdateRev` of the list goes through.
+#[derive(Debug, Clone, Copy)]
+pub struct RevBackoff {
+    pub failures: u32,
+    pub retry_at: Instant,
+}
+
+/// Wait before retrying a list after `failures` failed `UpdateRev`s in a row, doubling from the
+/// reconcile `interval` up to `REV_BACKOFF_MAX`.
+pub fn rev_backoff_delay(interval: Duration, failures: u32) -> Duration {
+    let factor = 1u32 << failures.saturating_sub(1).min(16);
+    interval.saturating_mul(factor).min(REV_BACKOFF_MAX)
+}
+
+/// Pushes back the next retry of the reverse index of the list `id` after a failed `UpdateRev`.
+fn back_off_rev(ctx: &Context, id: i64) {
+    let interval = Duration::from_secs(ctx.cfg.rev_reconcile_interval);
+    let mut rev_backoff = ctx.rev_backoff.lock().expect("lock");
+    let failures = rev_backoff.get(&id).map_or(0, |backoff| backoff.failures) + 1;
+    let delay = rev_backoff_delay(interval, failures);
+    warn!(
+        id,
+        failures,
+        ?delay,
+        "backing off the reverse index updates"
+    );
+    rev_backoff.insert(
+        id,
+        RevBackoff {
+            failures,
+            retry_at: Instant::now() + delay,
+        },
+    );
+}
+
+/// Schedules the missing reverse index updates, which fall behind when an `UpdateRev` fails or
+/// is lost on a restart.  The updates replay the ops of the membership list pods on disk one
+/// step per list at a time, and returns the number of scheduled updates.  The lists whose last
+/// `UpdateRev` failed are skipped until their backoff expires.
+pub async fn reconcile_rev(ctx: &Context) -> Result<usize, Error> {
+    let lags = db::get_rev_lags(&ctx.db_pool).await?;
+    let mut skip: HashSet<i64> = ctx
+        .rev_pending
+        .lock()
+        .expect("lock")
+        .keys()
+        .copied()
+        .collect();
+    let now = Instant::now();
+    skip.extend(
+        ctx.rev_backoff
+            .lock()
+            .expect("lock")
+            .iter()
+            .filter(|(_, backoff)| backoff.retry_at > now)
+            .map(|(id, _)| *id),
+    );
+    let updates = rev_updates_to_schedule(&lags, &skip);
+    for (n, (id, num)) in updates.iter().enumerate() {
+        match schedule_update_rev(ctx, *id, *num, None).await {
+            Ok(_) => {}
+            // the rest are scheduled by the next check
+            Err(Error::QueueFull(_)) => {
+                warn!(
+                    "queue full, scheduled {} of {} reverse index updates",
+                    n,
+                    updates.len()
+                );
+                return Ok(n);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(updates.len())
+}
+
+pub async fn reconcile_rev_loop(ctx: Arc<Context>) {
+    let mut reconcile_interval = interval(Duration::from_secs(ctx.cfg.rev_reconcile_interval));
+    loop {
+        tokio::select! {
+            _ = ctx.shutdown.cancelled() => break,
+            _ = reconcile_interval.tick() => {}
+        }
+        match reconcile_rev(&ctx).await {
+            Ok(0) => {}
+            Ok(scheduled) => info!("scheduled {} lagging reverse index updates", scheduled),
+            Err(err) => warn!("failed to reconcile reverse indexes: {:#}", err),
+        }
+    }
+}
+
+async fn handle_update_rev(
+    ctx: Arc<Context>,
+    req_id: Uuid,
+    id: i64,
+    num: i64,
+) -> Result<(), Error> {
     let set_req_state = async |req_state| {
         ctx.queue_state
             .write()
@@ -281,36 +1739,49 @@ async fn handle_update_rev(ctx: Arc<Context>, req_id: Uuid, id: i64, num: i64) -
     };
 
     if num == 0 {
-        anyhow::bail!("num = 0, state not initialized");
+        return Err(Error::NotInitialized(id));
+    }
+    // the same step can be scheduled after the update and by the reconciler
+    let rev_membership_list = db::get_rev_membership_list(&ctx.db_pool, id).await?;
+    if rev_membership_list.num != num - 1 {
+        return Err(Error::Conflict(format!(
+            "reverse index of membership list {} is at num {}, can't move it to {}",
+            id, rev_membership_list.num, num
+        )));
     }
-    let name = format!("{:08}-{:08}-membership_list", id, num);
-    let state_pod = load_pod(Path::new(&ctx.cfg.pods_path), &name)?;
+    let state_pod = ctx.load_pod(PodKey::membership_list(id, num))?;
+    let pod_config = ctx.pod_config()?;
 
-    let st_update = state_pod.pod.pub_statements()[0].clone();
-    let arg2 = st_update.args()[2].literal().unwrap();
-    let op = if let TypedValue::Dictionary(op) = arg2.typed() {
-        op.clone()
-    } else {
-        panic!("Value not a Dictionary: {:?}", arg2)
+    // update(new, old, op, epoch), of the update `num` if the pod proves a batch
+    let st_update = snapshot::update_statement(
+        &state_pod,
+        pod_config.update_predicate(db::ListKind::Set),
+        num,
+    )
+    .ok_or_else(|| anyhow!("state pod has no update statement at {}", num))?;
+    let op = match st_update.args().get(2).and_then(|arg| arg.literal()) {
+        Some(arg2) => match arg2.typed() {
+            TypedValue::Dictionary(op) => op.clone(),
+            _ => return Err(anyhow!("op of the state pod is not a dictionary: {:?}", arg2).into()),
+        },
+        None => return Err(anyhow!("state pod has no op: {:?}", st_update).into()),
     };
 
     let (old_rev_state_pod, rev_state) = if num > 1 {
-        let rev_name = rev_membership_list_pod_file_name(id, num - 1);
-        let old_rev_state_pod = load_pod(Path::new(&ctx.cfg.pods_path), &rev_name)?;
-        let rev_state = db::get_rev_membership_list(&ctx.db_pool, id).await?.state;
-        (Some(old_rev_state_pod), rev_state.0)
+        let old_rev_state_pod = ctx.load_pod(PodKey::rev_membership_list(id, num - 1))?;
+        (Some(old_rev_state_pod), rev_membership_list.state.0)
     } else {
         // State at num=1 is the base-case for rev_state and doesn't have a previous rev_state
         (
             None,
-            dict!(ctx.pod_config.params.max_depth_mt_containers, {}).unwrap(),
+            dict!(pod_config.params.max_depth_mt_containers, {}).unwrap(),
         )
     };
 
     let start = std::time::Instant::now();
-    set_req_state(StateUpdateRev::ProvingRevMainPod).await;
+    set_req_state(StateUpdateRev::ProvingRevMainPod { elapsed_secs: 0 }).await;
 
-    let mut builder = MainPodBuilder::new(&ctx.pod_config.params, &ctx.pod_config.vd_set);
+    let mut builder = MainPodBuilder::new(&pod_config.params, &pod_config.vd_set);
     builder.add_pod(state_pod);
     let old_st_rev_sync = if let Some(old_rev_state_pod) = old_rev_state_pod {
         builder.add_pod(old_rev_state_pod.clone());
@@ -321,32 +1792,170 @@ async fn handle_update_rev(ctx: Arc<Context>, req_id: Uuid, id: i64, num: i64) -
 
     let mut rev_helper = RevHelper::new(
         &mut builder,
-        &ctx.pod_config.state_predicates,
-        &ctx.pod_config.rev_predicates,
+        &pod_config.state_predicates,
+        &pod_config.rev_predicates,
     );
-    let (rev_state, rev_st_update) =
-        rev_helper.st_rev_sync(rev_state, op, st_update, old_st_rev_sync);
+    let (rev_state, rev_st_update) = rev_helper
+        .st_rev_sync(rev_state, op, st_update, old_st_rev_sync)
+        .map_err(Error::ProvingFailed)?;
 
     builder.reveal(&rev_st_update);
-    let prover = Prover {};
-    let rev_state_pod = task::spawn_blocking(move || builder.prove(&prover).unwrap()).await?;
-    println!("# rev_state_pod\n:{}", rev_state_pod);
-    rev_state_pod.pod.verify().unwrap();
+    let pod_key = PodKey::rev_membership_list(id, num);
+    let rev_state_pod = prove_bounded(&ctx, &[req_id], pod_key, builder, |elapsed_secs| {
+        State::UpdateRev(StateUpdateRev::ProvingRevMainPod { elapsed_secs })
+    })
+    .await?;
+    debug!("rev state pod\n{}", rev_state_pod);
+    rev_state_pod
+        .pod
+        .verify()
+        .map_err(|e| Error::ProvingFailed(e.into()))?;
 
-    println!("[TIME] rev_state_pod {:?}", start.elapsed());
+    info!(elapsed = ?start.elapsed(), "rev state pod proven");
+    ctx.metrics.observe(Timing::RevPod, start.elapsed());
 
-    store_pod(
-        Path::new(&ctx.cfg.pods_path),
-        &rev_membership_list_pod_file_name(id, num),
-        &rev_state_pod,
-    )?;
+    ctx.store_pod(pod_key, &rev_state_pod, true)?;
 
     db::update_rev_membership_list(&ctx.db_pool, id, num, rev_state).await?;
     set_req_state(StateUpdateRev::Complete).await;
+
+    // the new rev pod replaces the previous one as the base of the next rev proof, so the pods
+    // before it can go without waiting for the periodic pruning
+    match ctx.prune_pods() {
+        Ok(pruned) if !pruned.is_empty() => info!("pruned {} pods", pruned.len()),
+        Ok(_) => {}
+        Err(err) => warn!("failed to prune pods: {:#}", err),
+    }
     Ok(())
 }
 
-async fn handle_query(ctx: Arc<Context>, req_id: Uuid, id: i64, user: String) -> Result<()> {
+/// Returns the op log of the membership list `id` up to the update `num`, from which its state at
+/// `num` is replayed, or none if `num` is its latest update.  Fails with `NotFound` if the list
+/// isn't at `num` yet or if its op log doesn't go back to `num`.
+pub(crate) async fn history_at(
+    ctx: &Context,
+    id: i64,
+    num: i64,
+) -> Result<Option<Vec<db::OpLogEntry>>, Error> {
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    if num == membership_list.num {
+        return Ok(None);
+    }
+    let not_found = || {
+        Error::NotFound(format!(
+            "state of membership list {} at num {}, the list is at num {}",
+            id, num, membership_list.num
+        ))
+    };
+    if !(0..membership_list.num).contains(&num) {
+        return Err(not_found());
+    }
+    let op_log = db::get_op_log(&ctx.db_pool, id, 1, num).await?;
+    if !op_log.iter().map(|entry| entry.num).eq(1..=num) {
+        // lists restored from a snapshot, or updated before the op log, have gaps
+        return Err(not_found());
+    }
+    Ok(Some(op_log))
+}
+
+/// Returns the state of the membership list `id` after the update `num`.  Only the latest state is
+/// stored, so the previous ones are replayed from the empty state with the op log by `apply_ops`,
+/// which builds no statement, checking every op against its logged commitment, and the state at
+/// `num` against its pod if it's still on disk.
+async fn state_at(
+    ctx: &Context,
+    id: i64,
+    kind: db::ListKind,
+    num: i64,
+) -> Result<Dictionary, Error> {
+    let Some(op_log) = history_at(ctx, id, num).await? else {
+        return Ok(db::get_membership_list(&ctx.db_pool, id).await?.state.0);
+    };
+
+    let pod_config = ctx.pod_config()?;
+    let genesis = &pod_config.state_predicates.genesis;
+    let kv = kind == db::ListKind::Kv;
+    let mut state = Dictionary::new(pod_config.params.max_depth_mt_containers, HashMap::new())
+        .map_err(anyhow::Error::from)?;
+    for entry in op_log {
+        let new_state =
+            apply_ops(genesis, kv, state, slice::from_ref(&entry.op)).map_err(Error::Internal)?;
+        if new_state.commitment() != entry.state {
+            return Err(Error::Internal(anyhow!(
+                "op {} doesn't lead to the logged state",
+                entry.num
+            )));
+        }
+        state = new_state;
+    }
+    // the pods before the last `PODS_RETAIN_LAST_N` ones may be pruned
+    let key = PodKey::membership_list(id, num);
+    if num > 0 && ctx.pod_store.file_path(key).exists() {
+        let pod = ctx.load_pod(key)?;
+        if snapshot::proven_state_at(&pod, pod_config.update_predicate(kind), num)
+            != Some(RawValue::from(state.commitment()))
+        {
+            return Err(Error::Internal(anyhow!(
+                "the pod of update {} doesn't prove the replayed state",
+                num
+            )));
+        }
+    }
+    Ok(state)
+}
+
+/// Returns the ops history of the membership list `id` after the update `num` (`common::ops`),
+/// or none if the list doesn't publish it.  Once a list has its ops history stored it must have
+/// it at every num it's updated from, so that no update is published without its ops root.
+pub(crate) async fn ops_at(ctx: &Context, id: i64, num: i64) -> Result<Option<Dictionary>, Error> {
+    if let Some(ops) = db::get_list_ops(&ctx.db_pool, id, num).await? {
+        return Ok(Some(ops));
+    }
+    if num == 0 {
+        return Ok(Some(ops::empty_ops()));
+    }
+    if db::has_list_ops(&ctx.db_pool, id).await? {
+        return Err(Error::Internal(anyhow!(
+            "missing the ops history of membership list {} at num {}",
+            id,
+            num
+        )));
+    }
+    // the lists updated before the ops history was stored have it built once from the op log, if
+    // it goes back to the first update
+    let op_log = db::get_op_log(&ctx.db_pool, id, 1, num).await?;
+    if !op_log.iter().map(|entry| entry.num).eq(1..=num) {
+        return Ok(None);
+    }
+    let params = &ctx.pod_config()?.params;
+    let mut ops = ops::empty_ops();
+    for entry in op_log {
+        let op_raw = RawValue::from(entry.op.into_dict(params, entry.num).commitment());
+        ops::insert_op(&mut ops, entry.num, op_raw).map_err(Error::Internal)?;
+    }
+    db::insert_list_ops(&ctx.db_pool, id, num, &ops).await?;
+    Ok(Some(ops))
+}
+
+/// Returns the Merkle proof of the op of the update `num` in the ops history `ops`.
+pub(crate) fn prove_op(ops: &Dictionary, num: i64) -> Result<MerkleClaimAndProof, Error> {
+    let key = ops::op_key(num);
+    let (op_raw, proof) = ops.prove(&key).map_err(anyhow::Error::from)?;
+    Ok(MerkleClaimAndProof {
+        root: ops.commitment(),
+        key: Value::from(key.name()).raw(),
+        value: op_raw.raw(),
+        proof,
+    })
+}
+
+async fn handle_query(
+    ctx: Arc<Context>,
+    req_id: Uuid,
+    id: i64,
+    user: String,
+    at: Option<i64>,
+) -> Result<(), Error> {
     let set_req_state = async |req_state| {
         ctx.queue_state
             .write()
@@ -354,34 +1963,55 @@ async fn handle_query(ctx: Arc<Context>, req_id: Uuid, id: i64, user: String) ->
             .insert(req_id, State::Query(Box::new(req_state)));
     };
 
-    // get state from db
-    let state = db::get_rev_membership_list(&ctx.db_pool, id).await?.state.0;
-
-    // Get Merkle proof + groups to which the user belongs
-    let pf_with_groups = state.prove(&user.clone().into());
+    let not_member =
+        || Error::NotFound(format!(r#"User "{}" is not a member of any group."#, user));
+    let kind = db::get_membership_list_kind(&ctx.db_pool, id).await?;
+    let historical = match at {
+        Some(num) => Some(state_at(&ctx, id, kind, num).await?),
+        None => None,
+    };
+    if kind == db::ListKind::Kv {
+        let state = match historical {
+            Some(state) => state,
+            None => db::get_membership_list(&ctx.db_pool, id).await?.state.0,
+        };
+        let values = prove_user_values(&state, &user)?;
+        if values.is_empty() {
+            return Err(not_member());
+        }
+        ctx.queue_state.write().await.insert(
+            req_id,
+            State::QueryKv(Box::new(StateQueryKv::Complete { values })),
+        );
+        return Ok(());
+    }
 
-    match pf_with_groups {
-        Err(_) => {
-            set_req_state(StateQuery::Error(format!(
-                r#"User "{}" is not a member of any group."#,
-                user
-            )))
-            .await
+    let state = match historical {
+        // the reverse index at `num`, which its rev pod proves if the reverse index was updated at
+        // `num`
+        Some(state) => {
+            archive::rev_index(&state, ctx.pod_config()?.params.max_depth_mt_containers)?
         }
-        Ok((groups, proof)) => {
-            let (groups, proof) = (
-                set_from_value(groups)?,
-                MerkleClaimAndProof {
-                    root: state.commitment(),
-                    key: Value::from(user).raw(),
-                    value: groups.raw(),
-                    proof,
-                },
-            );
+        None => {
+            // users in no group are ruled out by the blooms of the groups without loading the
+            // reverse index, the others are looked up in it
+            let blooms = db::get_group_blooms_at_rev(&ctx.db_pool, id).await?;
+            let member = Value::from(user.as_str());
+            if !blooms.is_empty() && blooms.iter().all(|bloom| !bloom.may_contain(&member)) {
+                return Err(not_member());
+            }
+            db::get_rev_membership_list(&ctx.db_pool, id).await?.state.0
+        }
+    };
 
+    match prove_user_groups(&state, user.clone())? {
+        None => {
+            return Err(not_member());
+        }
+        Some((groups, proof)) => {
             set_req_state(StateQuery::Complete {
                 groups,
-                proof: Box::new(proof),
+                proof: QueryProofResponse::from(&proof),
             })
             .await;
         }
@@ -389,3 +2019,410 @@ async fn handle_query(ctx: Arc<Context>, req_id: Uuid, id: i64, user: String) ->
 
     Ok(())
 }
+
+/// Returns the groups of `user` in the reverse index `state`, in group order, with the Merkle proof
+/// of their set, or none if the user isn't a member of any group.
+fn prove_user_groups(
+    state: &Dictionary,
+    user: String,
+) -> Result<Option<(Vec<Group>, MerkleClaimAndProof)>, Error> {
+    let Ok((groups, proof)) = state.prove(&user.clone().into()) else {
+        return Ok(None);
+    };
+    let proof = MerkleClaimAndProof {
+        root: state.commitment(),
+        key: Value::from(user).raw(),
+        value: groups.raw(),
+        proof,
+    };
+    let mut groups = set_from_value(groups)?
+        .set()
+        .iter()
+        .map(|group| match group.typed() {
+            TypedValue::String(group) => Group::new(group.as_str()).map_err(Error::Internal),
+            _ => Err(Error::Internal(anyhow!("group is not a String: {}", group))),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    groups.sort();
+    Ok(Some((groups, proof)))
+}
+
+/// Returns the values of `user` in the groups of the kv list `state`, with the Merkle proofs of
+/// them.
+fn prove_user_values(state: &Dictionary, user: &str) -> Result<Vec<KvMembership>, Error> {
+    let user_key = Key::from(user);
+    let mut values = Vec::new();
+    for (key, group_value) in state.kvs() {
+        // the epoch
+        let TypedValue::Dictionary(group_dict) = group_value.typed() else {
+            continue;
+        };
+        let Ok((value, proof)) = group_dict.prove(&user_key) else {
+            continue;
+        };
+        let group = Group::new(key.name()).map_err(Error::Internal)?;
+        let (_, group_proof) = state.prove(key).map_err(anyhow::Error::from)?;
+        values.push(KvMembership {
+            group,
+            value: i64::try_from(value.typed()).map_err(anyhow::Error::from)?,
+            proof: QueryProofResponse::from(&MerkleClaimAndProof {
+                root: group_dict.commitment(),
+                key: Value::from(user).raw(),
+                value: value.raw(),
+                proof,
+            }),
+            group_proof: QueryProofResponse::from(&MerkleClaimAndProof {
+                root: state.commitment(),
+                key: Value::from(key.name()).raw(),
+                value: group_value.raw(),
+                proof: group_proof,
+            }),
+        });
+    }
+    values.sort_by(|a, b| a.group.cmp(&b.group));
+    Ok(values)
+}
+
+async fn handle_query_all(
+    ctx: Arc<Context>,
+    req_id: Uuid,
+    user: String,
+    after_id: i64,
+    limit: i64,
+) -> Result<(), Error> {
+    let rev_lists = db::get_rev_membership_lists(&ctx.db_pool, after_id, limit).await?;
+    let next_after_id = rev_lists
+        .last()
+        .map(|rev_list| rev_list.id)
+        .filter(|_| rev_lists.len() as i64 == limit);
+
+    let mut queries = JoinSet::new();
+    for rev_list in rev_lists {
+        let user = user.clone();
+        queries.spawn_blocking(move || {
+            Ok::<_, Error>((rev_list.id, prove_user_groups(&rev_list.state.0, user)?))
+        });
+    }
+    let mut lists = Vec::new();
+    while let Some(res) = queries.join_next().await {
+        if let (id, Some((groups, proof))) = res?? {
+            lists.push(UserMembership {
+                id,
+                groups,
+                proof: QueryProofResponse::from(&proof),
+            });
+        }
+    }
+    lists.sort_by_key(|membership| membership.id);
+
+    ctx.queue_state.write().await.insert(
+        req_id,
+        State::QueryAll(Box::new(StateQueryAll::Complete {
+            lists,
+            next_after_id,
+        })),
+    );
+    Ok(())
+}
+
+/// Proves in a standalone MainPod that `user` is in `group` of the current state of the
+/// membership list, which can be verified offline against the state commitment published by the
+/// synchronizer.
+async fn handle_prove_membership(
+    ctx: Arc<Context>,
+    req_id: Uuid,
+    id: i64,
+    user: String,
+    group: Group,
+) -> Result<(), Error> {
+    let set_req_state = async |req_state| {
+        ctx.queue_state
+            .write()
+            .await
+            .insert(req_id, State::ProveMembership(req_state));
+    };
+
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    if membership_list.num == 0 {
+        return Err(Error::NotInitialized(id));
+    }
+    let state = membership_list.state.0;
+    let group_set = group_set(&state, &group)?;
+    if !is_member(&group_set, &user)? {
+        return Err(Error::NotFound(format!(
+            r#"User "{}" is not a member of group "{}"."#,
+            user, group
+        )));
+    }
+
+    let start = std::time::Instant::now();
+    set_req_state(StateProveMembership::ProvingMainPod).await;
+    let pod_config = ctx.pod_config()?;
+    let mut builder = MainPodBuilder::new(&pod_config.params, &pod_config.vd_set);
+    // DictContains(state, group, group_set)
+    builder
+        .pub_op(Operation::dict_contains(
+            state.clone(),
+            group.as_str(),
+            group_set.clone(),
+        ))
+        .map_err(|e| Error::ProvingFailed(e.into()))?;
+    let st_member = match group_set.typed() {
+        // DictContains(group_dict, user, value), for the kv lists
+        TypedValue::Dictionary(group_dict) => {
+            let value = group_dict
+                .get(&Key::from(user.as_str()))
+                .map_err(anyhow::Error::from)?
+                .clone();
+            Operation::dict_contains(group_set.clone(), user.as_str(), value)
+        }
+        // SetContains(group_set, user)
+        _ => Operation::set_contains(group_set, user.as_str()),
+    };
+    builder
+        .pub_op(st_member)
+        .map_err(|e| Error::ProvingFailed(e.into()))?;
+    let prover = Prover {};
+    let pod = task::spawn_blocking(move || builder.prove(&prover))
+        .await?
+        .map_err(|e| Error::ProvingFailed(e.into()))?;
+    pod.pod
+        .verify()
+        .map_err(|e| Error::ProvingFailed(e.into()))?;
+    info!(elapsed = ?start.elapsed(), "membership pod proven");
+    ctx.metrics.observe(Timing::MembershipPod, start.elapsed());
+
+    let pod_key = PodKey::membership(id, membership_list.num, group.as_str(), &user);
+    ctx.store_pod(pod_key, &pod, true)?;
+
+    set_req_state(StateProveMembership::Complete {
+        num: membership_list.num,
+        state: state.commitment(),
+        pod: Box::new(pod),
+    })
+    .await;
+    Ok(())
+}
+
+async fn handle_prune_pods(ctx: Arc<Context>, req_id: Uuid) -> Result<(), Error> {
+    let pruned = ctx.prune_pods()?;
+    info!("pruned {} pods", pruned.len());
+    ctx.queue_state.write().await.insert(
+        req_id,
+        State::PrunePods(StatePrunePods::Complete {
+            pruned: pruned.len(),
+        }),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use pod2::middleware::containers::Set;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[tokio::test]
+    async fn test_list_locks() {
+        let locks = Arc::new(ListLocks::default());
+        let wait = Duration::from_millis(50);
+
+        let guard = locks.lock(1).await;
+        // a simultaneous update of the same list waits
+        let pending = {
+            let locks = locks.clone();
+            tokio::spawn(async move {
+                let _guard = locks.lock(1).await;
+            })
+        };
+        assert!(timeout(wait, locks.lock(1)).await.is_err());
+        // while an update of another list goes through
+        assert!(timeout(wait, locks.lock(2)).await.is_ok());
+
+        drop(guard);
+        timeout(wait, pending)
+            .await
+            .expect("update of list 1 unblocked")
+            .expect("join");
+        assert!(timeout(wait, locks.lock(1)).await.is_ok());
+    }
+
+    #[test]
+    fn test_pending_updates() {
+        let (queue_tx, mut queue_rx) = tokio::sync::mpsc::channel(8);
+        let pending = PendingUpdates::default();
+        let update = |id| Request::Update {
+            req_id: Uuid::now_v7(),
+            id,
+            op: Op::Init,
+        };
+
+        // the first update of a list is queued, the next ones are buffered up to the limit
+        pending.send(&queue_tx, update(1), 2).expect("queued");
+        pending.send(&queue_tx, update(2), 2).expect("queued");
+        for _ in 0..2 {
+            pending.send(&queue_tx, update(1), 2).expect("buffered");
+        }
+        assert!(matches!(
+            pending.send(&queue_tx, update(1), 2),
+            Err(TrySendError::Full(_))
+        ));
+        assert!(matches!(
+            queue_rx.try_recv(),
+            Ok(Request::Update { id: 1, .. })
+        ));
+        assert!(matches!(
+            queue_rx.try_recv(),
+            Ok(Request::Update { id: 2, .. })
+        ));
+        assert!(queue_rx.try_recv().is_err());
+
+        assert_eq!(pending.take(1, 1).len(), 1);
+        assert_eq!(pending.next_batch(1, 2).len(), 1);
+        // the list is released once its buffer is found empty
+        assert_eq!(pending.next_batch(1, 2).len(), 0);
+        pending.send(&queue_tx, update(1), 2).expect("queued");
+        assert!(matches!(
+            queue_rx.try_recv(),
+            Ok(Request::Update { id: 1, .. })
+        ));
+
+        // the shutdown takes the buffered updates
+        pending.send(&queue_tx, update(2), 2).expect("buffered");
+        assert!(matches!(
+            pending.take_all().as_slice(),
+            [Request::Update { id: 2, .. }]
+        ));
+        assert!(
+            pending
+                .buffer_or_claim(Uuid::now_v7(), 2, Op::Init)
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_check_op_drop_group() -> anyhow::Result<()> {
+        let depth = pod2::middleware::Params::default().max_depth_mt_containers;
+        let set = |members: &[&str]| {
+            Set::new(depth, members.iter().map(|m| Value::from(*m)).collect()).map(Value::from)
+        };
+        let membership_list = db::AdState {
+            id: 1,
+            num: 2,
+            state: db::DictContainerSql(dict!(depth, {
+                "red" => set(&["alice"])?,
+                "purple" => set(&[])?,
+                "epoch" => 2
+            })?),
+            created_at: 0,
+            updated_at: 0,
+        };
+        let drop_group = |group: &str| {
+            let op = Op::DropGroup {
+                group: Group::new(group).unwrap(),
+            };
+            check_op(&membership_list, db::ListKind::Set, &op).map_err(|e| e.kind())
+        };
+
+        assert_eq!(drop_group("purple"), Ok(()));
+        assert_eq!(drop_group("red"), Err(ErrorKind::Conflict));
+        assert_eq!(drop_group("orange"), Err(ErrorKind::NotFound));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rev_updates_to_schedule() {
+        let lag = |id, num, rev_num| db::RevLag { id, num, rev_num };
+        let lags = [lag(1, 3, 3), lag(2, 5, 2), lag(3, 1, 0), lag(4, 0, 0)];
+
+        assert_eq!(
+            rev_updates_to_schedule(&lags, &HashSet::new()),
+            vec![(2, 3), (3, 1)]
+        );
+        // list 2 already has a reverse index proof scheduled
+        assert_eq!(
+            rev_updates_to_schedule(&lags, &HashSet::from([2])),
+            vec![(3, 1)]
+        );
+    }
+
+    #[test]
+    fn test_rev_backoff_delay() {
+        let interval = Duration::from_secs(60);
+        let delays: Vec<_> = (1..=7)
+            .map(|failures| rev_backoff_delay(interval, failures).as_secs())
+            .collect();
+        assert_eq!(delays, vec![60, 120, 240, 480, 960, 1920, 3600]);
+        assert_eq!(rev_backoff_delay(interval, u32::MAX), REV_BACKOFF_MAX);
+    }
+
+    /// Writer of the log lines into a shared buffer
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().expect("lock").extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_span() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer({
+                let capture = capture.clone();
+                move || capture.clone()
+            })
+            .with_ansi(false)
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let req_id = Uuid::now_v7();
+        let op_raw = RawValue::from(42i64);
+        let req = Request::Update {
+            req_id,
+            id: 3,
+            op: Op::Init,
+        };
+        async {
+            info!("proving");
+            record_update(1, op_raw);
+            // events of nested calls, like the ones of `eth::send_payload`, are in the span too
+            async { info!("sent") }.await;
+        }
+        .instrument(req.span())
+        .await;
+        info!("outside");
+        // the reverse index update links to the update that scheduled it
+        let rev_req = Request::UpdateRev {
+            req_id: Uuid::now_v7(),
+            id: 3,
+            num: 1,
+            parent_req_id: Some(req_id),
+        };
+        async { info!("proving rev") }
+            .instrument(rev_req.span())
+            .await;
+
+        let logs = String::from_utf8(capture.0.lock().expect("lock").clone()).expect("utf8");
+        let lines: Vec<&str> = logs.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", logs);
+        let prefix = format!(r#"request{{req_id={} kind="update" id=3"#, req_id);
+        assert!(lines[0].contains(&prefix), "{}", lines[0]);
+        assert!(lines[0].ends_with("proving"), "{}", lines[0]);
+        let op = format!("num=1 op={}", op_raw.encode_hex::<String>());
+        assert!(lines[1].contains(&prefix), "{}", lines[1]);
+        assert!(lines[1].contains(&op), "{}", lines[1]);
+        assert!(!lines[2].contains("req_id"), "{}", lines[2]);
+        let parent = format!(r#"kind="update_rev" id=3 num=1 parent_req_id={}"#, req_id);
+        assert!(lines[3].contains(&parent), "{}", lines[3]);
+    }
+}
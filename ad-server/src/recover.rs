@@ -0,0 +1,358 @@
+//! Recovery of a membership list whose rows were lost, e.g. with the sqlite db of the server, from
+//! its history on chain.
+//!
+//! The payloads of the updates only commit to the state of the list, so the synchronizer only
+//! knows the commitment of the state after every update (`GET /ad/{id}/updates`), never the
+//! members.  The rest has to come from off chain:
+//!
+//! - the op of every update from the first one up to the latest one on chain, in the op log of the
+//!   db or in a JSON file of `[{"num": 1, "op": "init"}, ..]` entries, like the `op_log.json` of
+//!   an archive.  Without them the list can't be recovered.
+//! - the pods in `PODS_PATH` to keep updating the reverse index: it's proven incrementally, so
+//!   it's recovered at its latest pod that proves it, and caught up from the state pods of the
+//!   following updates.  Without them the list can be queried and updated, but its reverse index
+//!   stays behind.
+//!
+//! The ops are replayed from the empty state, checking every intermediate state against the
+//! commitment on chain, so that a wrong or incomplete op log is rejected instead of recovering a
+//! state that no pod can prove.  Only the set lists can be recovered, the kv lists are rejected.
+
+use std::{collections::HashMap, path::Path, slice};
+
+use anyhow::{Context as _, Result, anyhow, bail};
+use app::{Op, apply_ops};
+use common::disk::{self, PodKey, PodKind, PodStore};
+use hex::ToHex;
+use pod2::middleware::{Hash, RawValue, containers::Dictionary};
+use serde::Deserialize;
+use sqlx::SqlitePool;
+use tracing::{info, warn};
+
+use crate::{Config, Error, PodConfig, archive, bloom, db, snapshot};
+
+/// Update of a membership list as indexed by the synchronizer
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChainUpdate {
+    pub num: i64,
+    // commitment of the state after the update
+    pub state: RawValue,
+    // first num of the updates right before this one that the synchronizer pruned, if any
+    #[serde(default)]
+    pub pruned_from: Option<i64>,
+}
+
+/// Fails if the synchronizer pruned some of the updates of `history`, which are then missing.
+pub fn check_not_pruned(history: &[ChainUpdate]) -> Result<()> {
+    match history.iter().find(|update| update.pruned_from.is_some()) {
+        Some(ChainUpdate {
+            num,
+            pruned_from: Some(pruned_from),
+            ..
+        }) => bail!(
+            "the updates {}..{} were pruned by the synchronizer, which must run without \
+             COMPACTION_INTERVAL to keep the full history",
+            pruned_from,
+            num
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Op of the update `num`, in the format of the op log
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecoverOp {
+    pub num: i64,
+    pub op: Op,
+}
+
+/// Returns the updates of the membership list `id` indexed by the synchronizer at
+/// `synchronizer_url`, in num order.
+pub async fn fetch_history(synchronizer_url: &str, id: i64) -> Result<Vec<ChainUpdate>> {
+    fetch_history_from(synchronizer_url, id, 0).await
+}
+
+/// Returns the updates of the membership list `id` from the num `from` on, like `fetch_history`.
+pub async fn fetch_history_from(
+    synchronizer_url: &str,
+    id: i64,
+    from: i64,
+) -> Result<Vec<ChainUpdate>> {
+    // the id of the AD of the list, as in the create payload
+    let ad_id = Hash::from(RawValue::from(id));
+    let url = format!(
+        "{}/ad/{}/updates?from={}",
+        synchronizer_url.trim_end_matches('/'),
+        ad_id.encode_hex::<String>(),
+        from
+    );
+    let mut history: Vec<ChainUpdate> = reqwest::get(&url)
+        .await?
+        .error_for_status()?
+        .json()
+        .await
+        .with_context(|| url.clone())?;
+    history.sort_by_key(|update| update.num);
+    Ok(history)
+}
+
+/// Replays the ops from the empty state up to the latest update in `history`, checking the state
+/// after every op against its commitment on chain.  Returns the op log entry and the state of
+/// every update, the last one being the state of the list.  Fails for the ops of a kv list.
+pub fn replay(
+    pod_config: &PodConfig,
+    history: &[ChainUpdate],
+    ops: &[RecoverOp],
+) -> Result<Vec<(db::OpLogEntry, Dictionary)>> {
+    check_not_pruned(history)?;
+    if let Some(entry) = ops
+        .iter()
+        .find(|entry| matches!(entry.op, Op::AddKv { .. } | Op::DelKv { .. }))
+    {
+        bail!(
+            "op of update {} is a kv op, the kv lists can't be recovered",
+            entry.num
+        );
+    }
+    let commitments: HashMap<i64, RawValue> = history
+        .iter()
+        .map(|update| (update.num, update.state))
+        .collect();
+    // the create payload is at num 0
+    let num = history.last().map_or(0, |update| update.num);
+    if num < 1 {
+        bail!("no update on chain");
+    }
+    let ops: HashMap<i64, &Op> = ops.iter().map(|entry| (entry.num, &entry.op)).collect();
+
+    let depth = pod_config.params.max_depth_mt_containers;
+    let genesis = &pod_config.state_predicates.genesis;
+    let mut state = Dictionary::new(depth, HashMap::new())?;
+    let mut replayed = Vec::new();
+    for n in 1..=num {
+        let op = ops
+            .get(&n)
+            .with_context(|| format!("missing op of update {}", n))?;
+        let commitment = commitments
+            .get(&n)
+            .with_context(|| format!("update {} not on chain", n))?;
+        let new_state = apply_ops(genesis, false, state, slice::from_ref(*op))
+            .with_context(|| format!("op of update {}", n))?;
+        if RawValue::from(new_state.commitment()) != *commitment {
+            bail!("op of update {} doesn't lead to the state on chain", n);
+        }
+        state = new_state;
+        let entry = db::OpLogEntry {
+            num: n,
+            op: (*op).clone(),
+            state: state.commitment(),
+        };
+        replayed.push((entry, state.clone()));
+    }
+    if ops.keys().any(|n| *n > num) {
+        warn!(
+            "ignoring the ops after update {}, which aren't on chain",
+            num
+        );
+    }
+    Ok(replayed)
+}
+
+/// Returns the num and the reverse index of the latest reverse index pod in `pods_path` that
+/// proves the reverse index of the list at its num, with `states[n - 1]` the state after the
+/// update `n`.  Returns num 0 and the empty reverse index if there is none.
+fn latest_rev(
+    pod_config: &PodConfig,
+    pod_store: &PodStore,
+    pods_path: &Path,
+    id: i64,
+    states: &[&Dictionary],
+) -> Result<(i64, Dictionary)> {
+    let depth = pod_config.params.max_depth_mt_containers;
+    let mut rev_nums: Vec<i64> = disk::list_pods(pods_path)?
+        .into_iter()
+        .filter(|key| key.kind == PodKind::RevMembershipList && key.id == id)
+        .map(|key| key.num)
+        .filter(|num| (1..=states.len() as i64).contains(num))
+        .collect();
+    rev_nums.sort_unstable();
+    for num in rev_nums.into_iter().rev() {
+        let rev_state = archive::rev_index(states[num as usize - 1], depth)?;
+        let pod = match pod_store.load(PodKey::rev_membership_list(id, num)) {
+            Ok(pod) => pod,
+            Err(err) => {
+                warn!(
+                    "skipping the reverse index pod of update {}: {:#}",
+                    num, err
+                );
+                continue;
+            }
+        };
+        if snapshot::proven_state(&pod, &pod_config.rev_predicates.sync)
+            == Some(RawValue::from(rev_state.commitment()))
+        {
+            return Ok((num, rev_state));
+        }
+        warn!(
+            "the reverse index pod of update {} doesn't prove the reverse index",
+            num
+        );
+    }
+    Ok((0, Dictionary::new(depth, HashMap::new())?))
+}
+
+/// Recovers the membership list `id` from its history on chain and `ops`, or the op log in the db
+/// if `None`.  The list must not exist in the db.
+pub async fn recover(
+    cfg: &Config,
+    db_pool: &SqlitePool,
+    pod_config: &PodConfig,
+    id: i64,
+    history: &[ChainUpdate],
+    ops: Option<Vec<RecoverOp>>,
+) -> Result<()> {
+    match db::get_membership_list(db_pool, id).await {
+        Ok(_) => return Err(Error::Conflict(format!("membership list {} exists", id)).into()),
+        Err(Error::NotFound(_)) => {}
+        Err(err) => return Err(err.into()),
+    }
+    let ops = match ops {
+        Some(ops) => ops,
+        None => db::get_op_log(db_pool, id, 1, i64::MAX)
+            .await?
+            .into_iter()
+            .map(|entry| RecoverOp {
+                num: entry.num,
+                op: entry.op,
+            })
+            .collect(),
+    };
+    let replayed = replay(pod_config, history, &ops)?;
+    let states: Vec<&Dictionary> = replayed.iter().map(|(_, state)| state).collect();
+    let num = states.len() as i64;
+    let state = (*states.last().ok_or_else(|| anyhow!("no update"))?).clone();
+    info!(id, num, "replayed the ops of the membership list");
+
+    let pods_path = Path::new(&cfg.pods_path);
+    let pod_store = PodStore::open(pods_path, cfg.pod_compression_level)?;
+    let (rev_num, rev_state) = latest_rev(pod_config, &pod_store, pods_path, id, &states)?;
+    // the reverse index is caught up by the reconciler of the server from the state pods
+    if let Some(n) = (rev_num + 1..=num).find(|n| {
+        !pod_store
+            .file_path(PodKey::membership_list(id, *n))
+            .exists()
+    }) {
+        warn!(
+            "missing the state pod of update {}, the reverse index can't move past update {}",
+            n,
+            n - 1
+        );
+    }
+    info!(id, rev_num, "recovered the reverse index");
+
+    for (entry, _) in &replayed {
+        db::insert_op_log(db_pool, id, entry).await?;
+    }
+    let blooms = bloom::group_blooms(&state);
+    db::replace_group_blooms(db_pool, id, num, &blooms).await?;
+    let now = db::unix_now();
+    db::insert_membership_list_snapshot(
+        db_pool,
+        &db::AdState {
+            id,
+            num,
+            state: db::DictContainerSql(state),
+            created_at: now,
+            updated_at: now,
+        },
+        &db::AdState {
+            id,
+            num: rev_num,
+            state: db::DictContainerSql(rev_state),
+            created_at: now,
+            updated_at: now,
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use app::{Group, Helper, UserId};
+    use pod2::{
+        backends::plonky2::basetypes::DEFAULT_VD_SET, frontend::MainPodBuilder, middleware::Params,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_replay() -> Result<()> {
+        let pod_config = PodConfig::new(Params::default(), DEFAULT_VD_SET.clone());
+        let add = |user: &str| -> Result<Op> {
+            Ok(Op::Add {
+                group: Group::RED,
+                user: UserId::new(user)?,
+            })
+        };
+        let ops: Vec<RecoverOp> = [Op::Init, add("alice")?, add("bob")?]
+            .into_iter()
+            .zip(1..)
+            .map(|(op, num)| RecoverOp { num, op })
+            .collect();
+
+        // the history of the ops, after the create payload at num 0
+        let mut history = vec![ChainUpdate {
+            num: 0,
+            state: RawValue::from(1),
+            pruned_from: None,
+        }];
+        let params = &pod_config.params;
+        let mut state = Dictionary::new(params.max_depth_mt_containers, HashMap::new())?;
+        for RecoverOp { num, op } in &ops {
+            let mut builder = MainPodBuilder::new(params, &pod_config.vd_set);
+            let mut helper = Helper::new(&mut builder, &pod_config.state_predicates);
+            state = helper
+                .st_update(state, op.clone().into_dict(params, *num))?
+                .0;
+            history.push(ChainUpdate {
+                num: *num,
+                state: RawValue::from(state.commitment()),
+                pruned_from: None,
+            });
+        }
+
+        let replayed = replay(&pod_config, &history, &ops)?;
+        assert_eq!(replayed.len(), 3);
+        assert_eq!(replayed[2].0.num, 3);
+        assert_eq!(replayed[2].1.commitment(), state.commitment());
+        // the ops that aren't on chain yet are left out
+        assert_eq!(replay(&pod_config, &history[..3], &ops)?.len(), 2);
+
+        // an op is missing, or leads to another state
+        assert!(replay(&pod_config, &history, &ops[1..]).is_err());
+        let mut wrong = ops.clone();
+        wrong[2].op = add("carol")?;
+        assert!(replay(&pod_config, &history, &wrong).is_err());
+        assert!(replay(&pod_config, &history[..1], &ops).is_err());
+        // the synchronizer pruned the updates 1 and 2, listing update 3 right after the create
+        let pruned = [
+            history[0].clone(),
+            ChainUpdate {
+                pruned_from: Some(1),
+                ..history[3].clone()
+            },
+        ];
+        let err = replay(&pod_config, &pruned, &ops).unwrap_err();
+        assert!(err.to_string().contains("pruned"), "{:#}", err);
+        // the kv lists are rejected
+        let mut kv = ops.clone();
+        kv[1].op = Op::AddKv {
+            group: Group::RED,
+            user: UserId::new("alice")?,
+            value: 1,
+        };
+        let err = replay(&pod_config, &history, &kv).unwrap_err();
+        assert!(err.to_string().contains("kv lists"), "{:#}", err);
+        Ok(())
+    }
+}
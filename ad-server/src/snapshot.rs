@@ -0,0 +1,275 @@
+use common::{
+    disk::{PodKey, PodKind},
+    ops,
+};
+use pod2::{
+    frontend::MainPod,
+    middleware::{CustomPredicateRef, Hash, RawValue, Statement, containers::Dictionary},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{Context, Error, PodConfig, db, queue};
+
+// bump when the format of `ListSnapshot` changes
+pub(crate) const SNAPSHOT_VERSION: u32 = 2;
+
+/// Portable dump of a membership list, with everything needed to keep updating it on another
+/// server: the state, the reverse index and the pods the next updates are built from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListSnapshot {
+    pub version: u32,
+    pub id: i64,
+    pub num: i64,
+    // commitment of `state`, which is the state published on chain by the update `num`
+    pub commitment: Hash,
+    pub state: Dictionary,
+    pub rev_num: i64,
+    pub rev_state: Dictionary,
+    // ops history after the update `num` (`common::ops`), if the list publishes it.  Its
+    // commitment is the ops root published by the update `num`.
+    pub ops: Option<Dictionary>,
+    // unix seconds
+    pub created_at: i64,
+    pub updated_at: i64,
+    // the state pods of the updates after `rev_num` up to `num` and the reverse index pod at
+    // `rev_num`
+    pub pods: Vec<SnapshotPod>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotPod {
+    pub key: PodKey,
+    pub pod: MainPod,
+    // first update proven by the state pod of a batch of updates, see `PodStore::store_batch`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_num: Option<i64>,
+}
+
+/// Returns the first argument of the first public statement of `pod` if it's a `predicate`
+/// statement, which is the state proven by the update and reverse index pods.
+pub(crate) fn proven_state(pod: &MainPod, predicate: &CustomPredicateRef) -> Option<RawValue> {
+    match pod.pod.pub_statements().first() {
+        Some(Statement::Custom(cpr, args)) if cpr == predicate => args.first().map(|v| v.raw()),
+        _ => None,
+    }
+}
+
+/// Returns the statement of the update `num` among the public statements of the state pod `pod`
+/// under `predicate`: the one at epoch `num` of a pod that proves a batch of updates, else the
+/// only one.
+pub(crate) fn update_statement(
+    pod: &MainPod,
+    predicate: &CustomPredicateRef,
+    num: i64,
+) -> Option<Statement> {
+    let statements: Vec<Statement> = pod
+        .pod
+        .pub_statements()
+        .into_iter()
+        .filter(|st| matches!(st, Statement::Custom(cpr, _) if cpr == predicate))
+        .collect();
+    match statements.as_slice() {
+        [st] => Some(st.clone()),
+        // update(new, old, op, epoch)
+        _ => statements.into_iter().find(|st| {
+            matches!(
+                st,
+                Statement::Custom(_, args)
+                    if args.get(3).map(|epoch| epoch.raw()) == Some(RawValue::from(num))
+            )
+        }),
+    }
+}
+
+/// Like `proven_state` for the update `num` of the state pod `pod`, see `update_statement`.
+pub(crate) fn proven_state_at(
+    pod: &MainPod,
+    predicate: &CustomPredicateRef,
+    num: i64,
+) -> Option<RawValue> {
+    match update_statement(pod, predicate, num)? {
+        Statement::Custom(_, args) => args.first().map(|v| v.raw()),
+        _ => None,
+    }
+}
+
+impl ListSnapshot {
+    fn pod(&self, key: PodKey) -> Result<&MainPod, Error> {
+        self.pods
+            .iter()
+            .find(|p| p.key == key)
+            .map(|p| &p.pod)
+            .ok_or_else(|| Error::InvalidSnapshot(format!("missing pod {}", key.file_name())))
+    }
+
+    /// Checks that the snapshot is consistent: the state matches the commitment, every pod
+    /// verifies, and the latest pods prove the state and the reverse index under the predicates
+    /// of this server.  The commitment can then be checked against the latest state of the AD
+    /// on chain, for example through the synchronizer.
+    pub fn verify(&self, pod_config: &PodConfig) -> Result<(), Error> {
+        let invalid = |msg: String| Err(Error::InvalidSnapshot(msg));
+        if self.version != SNAPSHOT_VERSION {
+            return invalid(format!("unsupported version {}", self.version));
+        }
+        if self.state.commitment() != self.commitment {
+            return invalid("state doesn't match the commitment".to_string());
+        }
+        if !(0 <= self.rev_num && self.rev_num <= self.num) {
+            return invalid(format!("rev_num {} > num {}", self.rev_num, self.num));
+        }
+        if let Some(ops) = &self.ops {
+            if self.num > 0 && ops.get(&ops::op_key(self.num)).is_err() {
+                return invalid(format!("ops history without the op of update {}", self.num));
+            }
+        }
+        for SnapshotPod {
+            key,
+            pod,
+            first_num,
+        } in &self.pods
+        {
+            if key.id != self.id {
+                return invalid(format!("pod {} of another list", key.file_name()));
+            }
+            pod.pod
+                .verify()
+                .map_err(|e| Error::InvalidSnapshot(format!("pod {}: {}", key.file_name(), e)))?;
+            // the pod of a batch proves each of its updates
+            if let Some(first_num) = first_num {
+                let predicate = &pod_config.state_predicates.update;
+                if key.kind != PodKind::MembershipList
+                    || !(*first_num..=key.num)
+                        .all(|num| update_statement(pod, predicate, num).is_some())
+                {
+                    return invalid(format!(
+                        "pod {} doesn't prove the updates from {}",
+                        key.file_name(),
+                        first_num
+                    ));
+                }
+            }
+        }
+        if self.num > 0 {
+            let pod = self.pod(PodKey::membership_list(self.id, self.num))?;
+            if proven_state_at(pod, &pod_config.state_predicates.update, self.num)
+                != Some(RawValue::from(self.commitment))
+            {
+                return invalid(format!(
+                    "pod of update {} doesn't prove the state",
+                    self.num
+                ));
+            }
+        }
+        if self.rev_num > 0 {
+            let pod = self.pod(PodKey::rev_membership_list(self.id, self.rev_num))?;
+            if proven_state(pod, &pod_config.rev_predicates.sync)
+                != Some(RawValue::from(self.rev_state.commitment()))
+            {
+                return invalid(format!(
+                    "pod of update {} doesn't prove the reverse index",
+                    self.rev_num
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the keys of the pods the next updates of a list are built from: the state pods of the
+/// updates after `rev_num` up to `num` and the reverse index pod at `rev_num`.  `resolve` maps the
+/// key of an update to the key of the pod that proves it, see `PodStore::resolve`.
+pub(crate) fn latest_pod_keys(
+    id: i64,
+    num: i64,
+    rev_num: i64,
+    resolve: impl Fn(PodKey) -> PodKey,
+) -> Vec<PodKey> {
+    let mut keys: Vec<PodKey> = ((rev_num + 1).min(num).max(1)..=num)
+        .map(|n| resolve(PodKey::membership_list(id, n)))
+        .collect();
+    // the updates of a batch share their pod
+    keys.dedup();
+    if rev_num > 0 {
+        keys.push(PodKey::rev_membership_list(id, rev_num));
+    }
+    keys
+}
+
+pub async fn export(ctx: &Context, id: i64) -> Result<ListSnapshot, Error> {
+    // no update of the list is applied while the pods are read
+    let _list_guard = ctx.list_locks.lock(id).await;
+    let membership_list = db::get_membership_list(&ctx.db_pool, id).await?;
+    let rev_membership_list = db::get_rev_membership_list(&ctx.db_pool, id).await?;
+    let (num, rev_num) = (membership_list.num, rev_membership_list.num);
+
+    let pods = latest_pod_keys(id, num, rev_num, |key| ctx.pod_store.resolve(key))
+        .into_iter()
+        .map(|key| {
+            Ok(SnapshotPod {
+                key,
+                pod: ctx.load_pod(key)?,
+                first_num: ctx.pod_store.entry(key).and_then(|entry| entry.first_num),
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    Ok(ListSnapshot {
+        version: SNAPSHOT_VERSION,
+        id,
+        num,
+        commitment: membership_list.state.0.commitment(),
+        state: membership_list.state.0,
+        rev_num,
+        rev_state: rev_membership_list.state.0,
+        ops: queue::ops_at(ctx, id, num).await?,
+        created_at: membership_list.created_at,
+        updated_at: membership_list.updated_at,
+        pods,
+    })
+}
+
+/// Restores a membership list from a snapshot, under its id since it's the id of its AD on chain.
+/// The list must not exist on this server.
+pub async fn import(ctx: &Context, snapshot: ListSnapshot) -> Result<(), Error> {
+    snapshot.verify(ctx.pod_config()?)?;
+    let id = snapshot.id;
+    let _list_guard = ctx.list_locks.lock(id).await;
+    match db::get_membership_list(&ctx.db_pool, id).await {
+        Ok(_) => return Err(Error::Conflict(format!("membership list {} exists", id))),
+        Err(Error::NotFound(_)) => {}
+        Err(err) => return Err(err),
+    }
+
+    // the pods and the ops history are stored first so that they are there once the list is
+    // visible
+    for SnapshotPod {
+        key,
+        pod,
+        first_num,
+    } in &snapshot.pods
+    {
+        // the state of the list is only updated once the payload of its pod is included
+        ctx.store_batch_pod(*key, *first_num, pod, true)?;
+    }
+    if let Some(ops) = &snapshot.ops {
+        db::insert_list_ops(&ctx.db_pool, id, snapshot.num, ops).await?;
+    }
+    db::insert_membership_list_snapshot(
+        &ctx.db_pool,
+        &db::AdState {
+            id,
+            num: snapshot.num,
+            state: db::DictContainerSql(snapshot.state),
+            created_at: snapshot.created_at,
+            updated_at: snapshot.updated_at,
+        },
+        &db::AdState {
+            id,
+            num: snapshot.rev_num,
+            state: db::DictContainerSql(snapshot.rev_state),
+            created_at: snapshot.created_at,
+            updated_at: snapshot.updated_at,
+        },
+    )
+    .await
+}
@@ -0,0 +1,173 @@
+//! Counter app: the state is an integer that starts at 0 and is increased by 1 to 9 at each
+//! update.  It's the minimal example of an app whose updates are proven with custom predicates.
+
+use anyhow::{Context, Result, anyhow};
+use pod2::{
+    frontend::{MainPodBuilder, Operation},
+    lang::parse,
+    middleware::{CustomPredicateRef, Key, Params, Statement, containers::Dictionary},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::dict;
+
+#[derive(Debug, Clone)]
+pub struct Predicates {
+    pub inc: CustomPredicateRef,
+    pub update: CustomPredicateRef,
+}
+
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Inc { n: i64 },
+}
+
+impl Op {
+    pub fn into_dict(self, params: &Params) -> Dictionary {
+        let depth = params.max_depth_mt_containers;
+        match self {
+            Op::Inc { n } => dict!(depth, {"name" => "inc", "n" => n}),
+        }
+    }
+}
+
+/// Op = Dict {
+///   "name" => "inc",
+///   "n" => Int,
+/// }
+///
+/// State = Int
+///
+/// There's no init op because the initial state is `EMPTY_VALUE`, which matches the encoding of
+/// `Int(0)`.  See the counter section of `app-logic.md`.
+pub fn build_predicates(params: &Params) -> Predicates {
+    let input = r#"
+        inc(new, old, op) = AND(
+            // Input validation
+            DictContains(op, "name", "inc")
+            Lt(0, op.n)
+            Lt(op.n, 10)
+            // State transition
+            SumOf(new, old, op.n)
+        )
+
+        update(new, old, op) = OR(
+            inc(new, old, op)
+        )
+    "#;
+
+    let batch = parse(input, params, &[]).unwrap().custom_batch;
+    Predicates {
+        inc: batch.predicate_ref_by_name("inc").unwrap(),
+        update: batch.predicate_ref_by_name("update").unwrap(),
+    }
+}
+
+pub struct Helper<'a> {
+    pub builder: &'a mut MainPodBuilder,
+    pub predicates: &'a Predicates,
+}
+
+impl<'a> Helper<'a> {
+    pub fn new(pod_builder: &'a mut MainPodBuilder, predicates: &'a Predicates) -> Self {
+        Self {
+            builder: pod_builder,
+            predicates,
+        }
+    }
+
+    pub fn st_inc(&mut self, old: i64, op: Dictionary) -> Result<(i64, Statement)> {
+        let n = op.get(&Key::from("n")).context("op has no n")?;
+        let new = old
+            .checked_add(i64::try_from(n.typed())?)
+            .ok_or_else(|| anyhow!("counter overflow"))?;
+        // DictContains(op, "name", "inc")
+        let st0 = self
+            .builder
+            .priv_op(Operation::dict_contains(op.clone(), "name", "inc"))
+            .context("op is not inc")?;
+        // Lt(0, op.n)
+        let st1 = self
+            .builder
+            .priv_op(Operation::lt(0i64, (&op, "n")))
+            .context("op.n is not positive")?;
+        // Lt(op.n, 10)
+        let st2 = self
+            .builder
+            .priv_op(Operation::lt((&op, "n"), 10i64))
+            .context("op.n is not smaller than 10")?;
+        // SumOf(new, old, op.n)
+        let st3 = self
+            .builder
+            .priv_op(Operation::sum_of(new, old, (&op, "n")))
+            .unwrap();
+
+        // inc(new, old, op)
+        let st = self
+            .builder
+            .priv_op(Operation::custom(
+                self.predicates.inc.clone(),
+                [st0, st1, st2, st3],
+            ))
+            .unwrap();
+        Ok((new, st))
+    }
+
+    pub fn st_update(&mut self, old: i64, op: Dictionary) -> Result<(i64, Statement)> {
+        // inc(new, old, op)
+        let (new, st) = self.st_inc(old, op)?;
+
+        // update(new, old, op)
+        let st = self
+            .builder
+            .priv_op(Operation::custom(self.predicates.update.clone(), [st]))
+            .unwrap();
+        Ok((new, st))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pod2::{
+        backends::plonky2::mainpod::Prover,
+        middleware::{DEFAULT_VD_SET, Value},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_counter() -> Result<()> {
+        let (vd_set, prover) = (&*DEFAULT_VD_SET, &Prover {});
+        let params = Params::default();
+        let predicates = build_predicates(&params);
+
+        let mut state = 0;
+        for (op, expected) in [(Op::Inc { n: 3 }, 3), (Op::Inc { n: 4 }, 7)] {
+            let mut builder = MainPodBuilder::new(&params, vd_set);
+            let mut helper = Helper::new(&mut builder, &predicates);
+            let (new_state, st_update) = helper.st_update(state, op.into_dict(&params))?;
+            builder.reveal(&st_update);
+            let pod = builder.prove(prover).unwrap();
+            pod.pod.verify().unwrap();
+            assert_eq!(new_state, expected);
+            match &pod.pod.pub_statements()[0] {
+                Statement::Custom(cpr, args) => {
+                    assert_eq!(cpr, &predicates.update);
+                    assert_eq!(args[0].raw(), Value::from(new_state).raw());
+                }
+                st => panic!("unexpected statement {:?}", st),
+            }
+            state = new_state;
+        }
+
+        // increments of 10 or more, and of 0 or less, can't be proven
+        for n in [10, 0, -1] {
+            let mut builder = MainPodBuilder::new(&params, vd_set);
+            let mut helper = Helper::new(&mut builder, &predicates);
+            let op = Op::Inc { n }.into_dict(&params);
+            assert!(helper.st_update(state, op).is_err(), "n = {}", n);
+        }
+        Ok(())
+    }
+}
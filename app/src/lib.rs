@@ -1,41 +1,53 @@
 #![allow(clippy::uninlined_format_args)]
 
 use std::{
+    borrow::Cow,
     collections::{HashMap, HashSet},
     fmt,
     str::FromStr,
+    sync::Arc,
 };
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, anyhow, bail};
 use common::set_from_value;
 use hex::ToHex;
 use pod2::{
     frontend::{MainPodBuilder, Operation},
-    lang::parse,
+    lang::{PrettyPrint, parse},
     middleware::{
-        CustomPredicateRef, EMPTY_VALUE, Key, Params, Statement, TypedValue, Value,
+        CustomPredicateBatch, CustomPredicateRef, EMPTY_VALUE, Hash, Key, Params, Statement,
+        TypedValue, Value,
         containers::{Dictionary, Set},
     },
 };
 use serde::{Deserialize, Serialize};
 
-pub const DEPTH: usize = 32;
+pub mod counter;
 
+/// Like `pod2::dict!` but unwraps the result.  The depth of the dictionary is the first argument,
+/// usually `params.max_depth_mt_containers`: the helpers refuse the containers of another depth,
+/// see `check_depth`.
 #[macro_export]
 macro_rules! dict {
-    ({ $($key:expr => $val:expr),* , }) => (
-        $crate::dict!({ $($key => $val),* }).unwrap()
+    ($depth:expr, { $($key:expr => $val:expr),* , }) => (
+        $crate::dict!($depth, { $($key => $val),* })
     );
-    ({ $($key:expr => $val:expr),* }) => ({
-        pod2::dict!(DEPTH, { $($key => $val),* }).unwrap()
+    ($depth:expr, { $($key:expr => $val:expr),* }) => ({
+        pod2::dict!($depth, { $($key => $val),* }).unwrap()
     });
 }
 
 #[derive(Debug, Clone)]
 pub struct Predicates {
+    // groups of the state made by `init`
+    pub genesis: Genesis,
     pub init: CustomPredicateRef,
     pub add: CustomPredicateRef,
     pub del: CustomPredicateRef,
+    pub add_group: CustomPredicateRef,
+    pub drop_group: CustomPredicateRef,
+    pub change: CustomPredicateRef,
+    pub step: CustomPredicateRef,
     pub update: CustomPredicateRef,
 }
 
@@ -50,93 +62,343 @@ pub struct RevPredicates {
     pub sync_init: CustomPredicateRef,
     pub sync_add: CustomPredicateRef,
     pub sync_del: CustomPredicateRef,
+    pub sync_add_group: CustomPredicateRef,
+    pub sync_drop_group: CustomPredicateRef,
     pub sync: CustomPredicateRef,
 }
 
+/// Predicates of the lists whose groups are dictionaries user→value instead of sets, which share
+/// `init`, `add_group` and `drop_group` with `Predicates` since empty groups commit to the same
+/// value either way.
+#[derive(Debug, Clone)]
+pub struct KvPredicates {
+    pub add_kv: CustomPredicateRef,
+    pub del_kv: CustomPredicateRef,
+    pub change: CustomPredicateRef,
+    pub step: CustomPredicateRef,
+    pub update: CustomPredicateRef,
+}
+
+/// Predicates of the app, built by `build_predicates`
+#[derive(Debug, Clone)]
+pub struct AppPredicates {
+    pub state: Predicates,
+    pub rev: RevPredicates,
+    pub kv: KvPredicates,
+    // the batches that define the predicates, each one after the batches it uses
+    pub batches: Vec<Arc<CustomPredicateBatch>>,
+    // name and podlang source of each of `batches`, in the same order.  Parsing the sources in
+    // order, each one with the batches before it, gives back `batches`.
+    pub sources: Vec<(String, String)>,
+}
+
+/// Names of the predicate batches by batch id, to render the statements of the app pods in a
+/// readable way for debugging.
+#[derive(Debug, Clone, Default)]
+pub struct BatchNames(HashMap<Hash, &'static str>);
+
+impl BatchNames {
+    pub fn new(
+        predicates: &Predicates,
+        rev_predicates: &RevPredicates,
+        kv_predicates: &KvPredicates,
+    ) -> Self {
+        Self(HashMap::from([
+            (predicates.change.batch.id(), "state_change"),
+            (predicates.update.batch.id(), "state"),
+            (rev_predicates.add.batch.id(), "rev_state_add"),
+            (rev_predicates.del.batch.id(), "rev_state_del"),
+            (rev_predicates.sync.batch.id(), "rev_state"),
+            (kv_predicates.change.batch.id(), "kv_state_change"),
+            (kv_predicates.update.batch.id(), "kv_state"),
+        ]))
+    }
+
+    /// Renders the statement in podlang.  Custom statements of a known batch are annotated with
+    /// the batch and predicate names.
+    pub fn render(&self, st: &Statement) -> String {
+        let podlang = st.to_podlang_string();
+        match st {
+            Statement::Custom(cpr, _) => match self.0.get(&cpr.batch.id()) {
+                Some(batch) => format!("{} // {}::{}", podlang, batch, cpr.predicate().name),
+                None => podlang,
+            },
+            _ => podlang,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum Op {
     Init,
-    Add { group: Group, user: String },
-    Del { group: Group, user: String },
+    Add {
+        group: Group,
+        user: UserId,
+    },
+    Del {
+        group: Group,
+        user: UserId,
+    },
+    // adds an empty group
+    AddGroup {
+        group: Group,
+    },
+    // removes a group, which must be empty
+    DropGroup {
+        group: Group,
+    },
+    // sets the value of a user that isn't in the group yet, in the lists created with the kv
+    // predicates
+    AddKv {
+        group: Group,
+        user: UserId,
+        value: i64,
+    },
+    DelKv {
+        group: Group,
+        user: UserId,
+    },
 }
 
-impl From<Op> for Dictionary {
-    fn from(op: Op) -> Self {
-        match op {
-            Op::Init => dict!({"name" => "init"}),
+impl Op {
+    /// Op dictionary of the update that moves the state to `epoch`, which is the `num` of the
+    /// membership list after the update.
+    pub fn into_dict(self, params: &Params, epoch: i64) -> Dictionary {
+        let depth = params.max_depth_mt_containers;
+        match self {
+            Op::Init => dict!(depth, {"name" => "init", "epoch" => epoch}),
             Op::Add { group, user } => {
-                dict!({"name" => "add", "group" => group, "user" => user})
+                dict!(depth, {"name" => "add", "group" => group, "user" => user.0, "epoch" => epoch})
             }
             Op::Del { group, user } => {
-                dict!({"name" => "del", "group" => group, "user" => user})
+                dict!(depth, {"name" => "del", "group" => group, "user" => user.0, "epoch" => epoch})
+            }
+            Op::AddGroup { group } => {
+                dict!(depth, {"name" => "add_group", "group" => group, "epoch" => epoch})
+            }
+            Op::DropGroup { group } => {
+                dict!(depth, {"name" => "drop_group", "group" => group, "epoch" => epoch})
+            }
+            Op::AddKv { group, user, value } => dict!(depth, {
+                "name" => "add_kv",
+                "group" => group,
+                "user" => user.0,
+                "value" => value,
+                "epoch" => epoch
+            }),
+            Op::DelKv { group, user } => {
+                dict!(depth, {"name" => "del_kv", "group" => group, "user" => user.0, "epoch" => epoch})
             }
         }
     }
 }
 
-#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum Group {
-    Red = 0,
-    Green,
-    Blue,
+/// Group of a membership list, a key of its state: a name with the same characters as a `UserId`
+/// other than `epoch`, which is the key of the epoch in the state.  Lists start with the groups of
+/// the `Genesis` (`RED`, `GREEN` and `BLUE` by default), and more are added with `Op::AddGroup`.
+/// Empty groups are removed with `Op::DropGroup`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Group(Cow<'static, str>);
+
+impl Group {
+    pub const RED: Group = Group(Cow::Borrowed("red"));
+    pub const GREEN: Group = Group(Cow::Borrowed("green"));
+    pub const BLUE: Group = Group(Cow::Borrowed("blue"));
+
+    pub fn new(group: impl Into<String>) -> Result<Self> {
+        let group = group.into();
+        check_name("group", &group, &["epoch"])?;
+        Ok(Self(Cow::Owned(group)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
 }
 
 impl fmt::Display for Group {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let str_rep = match self {
-            Group::Red => "red",
-            Group::Green => "green",
-            Group::Blue => "blue",
-        };
-        write!(f, "{}", str_rep)
+        write!(f, "{}", self.0)
     }
 }
 
 impl FromStr for Group {
-    type Err = Box<dyn std::error::Error>;
+    type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "red" => Ok(Self::Red),
-            "green" => Ok(Self::Green),
-            "blue" => Ok(Self::Blue),
-            _ => Err(format!("Invalid index: {}", s).into()),
-        }
+        Self::new(s)
+    }
+}
+
+impl TryFrom<String> for Group {
+    type Error = anyhow::Error;
+    fn try_from(group: String) -> Result<Self> {
+        Self::new(group)
+    }
+}
+
+impl From<Group> for String {
+    fn from(group: Group) -> Self {
+        group.0.into_owned()
     }
 }
 
 impl From<Group> for TypedValue {
     fn from(val: Group) -> Self {
-        format!("{val}").into()
+        String::from(val).into()
+    }
+}
+
+// bounds the size of the op dictionaries and of the keys of the state and the reverse index
+pub const USER_ID_MAX_LEN: usize = 64;
+// keys of the op dictionaries
+const USER_ID_RESERVED: [&str; 4] = ["name", "group", "user", "epoch"];
+
+/// Checks that `name` has 1 to `USER_ID_MAX_LEN` ASCII letters, digits, `_` or `-`, and isn't
+/// one of the `reserved` names.
+fn check_name(kind: &str, name: &str, reserved: &[&str]) -> Result<()> {
+    if name.is_empty() || name.len() > USER_ID_MAX_LEN {
+        return Err(anyhow!(
+            "{} must have 1 to {} characters, got {}",
+            kind,
+            USER_ID_MAX_LEN,
+            name.len()
+        ));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || *c == '_' || *c == '-'))
+    {
+        return Err(anyhow!("invalid character {:?} in {} {:?}", c, kind, name));
+    }
+    if reserved.contains(&name) {
+        return Err(anyhow!("reserved {} {:?}", kind, name));
+    }
+    Ok(())
+}
+
+/// Groups of the state made by the `init` op, which all start empty.  The `init` predicate only
+/// accepts this state, so it's part of the predicates built by `build_predicates_with`.  The
+/// default is the `RED`, `GREEN` and `BLUE` groups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Genesis {
+    groups: Vec<Group>,
+}
+
+impl Genesis {
+    pub fn new(groups: Vec<Group>) -> Result<Self> {
+        let mut seen = HashSet::new();
+        if let Some(group) = groups.iter().find(|group| !seen.insert(*group)) {
+            bail!("duplicate genesis group {}", group);
+        }
+        Ok(Self { groups })
+    }
+
+    pub fn groups(&self) -> &[Group] {
+        &self.groups
+    }
+}
+
+impl Default for Genesis {
+    fn default() -> Self {
+        Self {
+            groups: vec![Group::RED, Group::GREEN, Group::BLUE],
+        }
+    }
+}
+
+/// Parses a comma separated list of groups, e.g. `red,green,blue`.  The empty string is the
+/// genesis without groups.
+impl FromStr for Genesis {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let groups = s
+            .split(',')
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+            .map(Group::new)
+            .collect::<Result<_>>()?;
+        Self::new(groups)
+    }
+}
+
+/// User of a membership list: 1 to `USER_ID_MAX_LEN` ASCII letters, digits, `_` or `-`, and none
+/// of the keys of the op dictionaries.  Dots are excluded as they anchor keys in podlang
+/// (`op.user`), and non ASCII characters are rejected rather than normalized, so that two
+/// encodings of the same visual string can't silently become different users.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UserId(String);
+
+impl UserId {
+    pub fn new(user: impl Into<String>) -> Result<Self> {
+        let user = user.into();
+        check_name("user", &user, &USER_ID_RESERVED)?;
+        Ok(Self(user))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl TryFrom<String> for UserId {
+    type Error = anyhow::Error;
+    fn try_from(user: String) -> Result<Self> {
+        Self::new(user)
+    }
+}
+
+impl From<UserId> for String {
+    fn from(user: UserId) -> Self {
+        user.0
+    }
+}
+
+impl fmt::Display for UserId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
 /// State = Dict {
+///   // groups of the genesis, red, green and blue by default
 ///   "red" => Set(...),
 ///   "green" => Set(...),
 ///   "blue" => Set(...),
+///   // groups added by `add_group` ops
+///   ... => Set(...),
+///   "epoch" => Int,
 /// }
-pub fn build_predicates(params: &Params) -> (Predicates, RevPredicates) {
+///
+/// The epoch counts the updates of the state, so that the chain of update statements commits to
+/// their order and an update proof can't be replayed when the groups return to a previous state.
+///
+/// The kv predicates prove the same state with the groups as `Dict(user => Int)`, which start
+/// empty as well.
+pub fn build_predicates(params: &Params) -> AppPredicates {
+    build_predicates_with(params, &Genesis::default())
+}
+
+/// Like `build_predicates` with the groups of the state made by `init` taken from `genesis`
+/// instead of the default red, green and blue ones.
+pub fn build_predicates_with(params: &Params, genesis: &Genesis) -> AppPredicates {
     let empty = format!("Raw({:#})", EMPTY_VALUE);
-    let empty_state = format!(
-        r#"{{"{r}": {empty}, "{g}": {empty}, "{b}": {empty}}}"#,
-        r = Group::Red,
-        g = Group::Green,
-        b = Group::Blue
+    let init_state = format!(
+        "{{{}}}",
+        genesis
+            .groups()
+            .iter()
+            .map(|group| format!(r#""{}": {}"#, group, empty))
+            .chain([r#""epoch": 1"#.to_string()])
+            .collect::<Vec<_>>()
+            .join(", ")
     );
 
-    let input_state = format!(
+    let input_state_change = format!(
         r#"
-        // State predicates
-        init(new, old, op) = AND(
-            // Input validation
-            DictContains(op, "name", "init")
-            // State transition
-            Equal(old, {empty})
-            Equal(new, {empty_state})
-        )
-
+        // Group changes, which leave the epoch untouched
         add(new, old, op, private: old_group, new_group) = AND(
             // Input validation
             DictContains(op, "name", "add")
@@ -155,15 +417,72 @@ pub fn build_predicates(params: &Params) -> (Predicates, RevPredicates) {
             DictUpdate(new, old, op.group, new_group)
         )
 
-        update(new, old, op) = OR(
-            init(new, old, op)
+        // New empty group, which is rejected if the group exists
+        add_group(new, old, op) = AND(
+            // Input validation
+            DictContains(op, "name", "add_group")
+            // State transition
+            DictInsert(new, old, op.group, {empty})
+        )
+
+        // Removal of a group, which is rejected if the group has members
+        drop_group(new, old, op) = AND(
+            // Input validation
+            DictContains(op, "name", "drop_group")
+            // State transition
+            DictContains(old, op.group, {empty})
+            DictDelete(new, old, op.group)
+        )
+
+        change(new, old, op) = OR(
             add(new, old, op)
             del(new, old, op)
+            add_group(new, old, op)
+            drop_group(new, old, op)
         )
     "#
     );
 
-    let state_batch = parse(&input_state, params, &[]).unwrap().custom_batch;
+    let state_change_batch = parse(&input_state_change, params, &[])
+        .unwrap()
+        .custom_batch;
+
+    let input_state = format!(
+        r#"
+        use _, _, _, _, change from 0x{state_change_batch}
+
+        // State predicates
+        init(new, old, op, epoch) = AND(
+            // Input validation
+            DictContains(op, "name", "init")
+            DictContains(op, "epoch", epoch)
+            Equal(epoch, 1)
+            // State transition
+            Equal(old, {empty})
+            Equal(new, {init_state})
+        )
+
+        step(new, old, op, epoch, private: mid, old_epoch) = AND(
+            change(mid, old, op)
+            // Input validation
+            DictContains(op, "epoch", epoch)
+            // Epoch transition
+            DictContains(old, "epoch", old_epoch)
+            SumOf(epoch, old_epoch, 1)
+            DictUpdate(new, mid, "epoch", epoch)
+        )
+
+        update(new, old, op, epoch) = OR(
+            init(new, old, op, epoch)
+            step(new, old, op, epoch)
+        )
+    "#,
+        state_change_batch = state_change_batch.id().encode_hex::<String>(),
+    );
+
+    let state_batch = parse(&input_state, params, &[state_change_batch.clone()])
+        .unwrap()
+        .custom_batch;
 
     /* NOTE: Wouldn't this be nice?  We commit to the sequence of ops and at the same time allow
      * batching of updates
@@ -247,35 +566,51 @@ pub fn build_predicates(params: &Params) -> (Predicates, RevPredicates) {
 
     let input_rev = format!(
         r#"
-        use _, _, _, update from 0x{state_batch}
+        use _, _, update from 0x{state_batch}
         use _, _, rev_add from 0x{rev_state_add_batch}
         use _, _, rev_del from 0x{rev_state_del_batch}
 
         // Reverse index & state syncing
-        rev_sync_init(rev_state, state, old_state, op) = AND(
-            update(state, old_state, op)
+        rev_sync_init(rev_state, state, old_state, op, private: epoch) = AND(
+            update(state, old_state, op, epoch)
             DictContains(op, "name", "init")
             Equal(rev_state, {empty})
         )
 
-        rev_sync_add(rev_state, state, old_state, op, private: old_rev_state) = AND(
+        rev_sync_add(rev_state, state, old_state, op, private: old_rev_state, epoch) = AND(
             rev_sync(old_rev_state, old_state)
-            update(state, old_state, op)
+            update(state, old_state, op, epoch)
             DictContains(op, "name", "add")
             rev_add(rev_state, old_rev_state, op)
         )
 
-        rev_sync_del(rev_state, state, old_state, op, private: old_rev_state) = AND(
+        rev_sync_del(rev_state, state, old_state, op, private: old_rev_state, epoch) = AND(
             rev_sync(old_rev_state, old_state)
-            update(state, old_state, op)
+            update(state, old_state, op, epoch)
             DictContains(op, "name", "del")
             rev_del(rev_state, old_rev_state, op)
         )
 
+        // a new group is empty, so the reverse index doesn't change
+        rev_sync_add_group(rev_state, state, old_state, op, private: epoch) = AND(
+            rev_sync(rev_state, old_state)
+            update(state, old_state, op, epoch)
+            DictContains(op, "name", "add_group")
+        )
+
+        // only empty groups are dropped, so the reverse index doesn't change either
+        rev_sync_drop_group(rev_state, state, old_state, op, private: epoch) = AND(
+            rev_sync(rev_state, old_state)
+            update(state, old_state, op, epoch)
+            DictContains(op, "name", "drop_group")
+        )
+
         rev_sync(rev_state, state, private: old_state, op) = OR(
             rev_sync_init(rev_state, state, old_state, op)
             rev_sync_add(rev_state, state, old_state, op)
             rev_sync_del(rev_state, state, old_state, op)
+            rev_sync_add_group(rev_state, state, old_state, op)
+            rev_sync_drop_group(rev_state, state, old_state, op)
         )
         "#,
         state_batch = state_batch.id().encode_hex::<String>(),
@@ -295,12 +630,94 @@ pub fn build_predicates(params: &Params) -> (Predicates, RevPredicates) {
     .unwrap()
     .custom_batch;
 
+    let input_kv_state_change = format!(
+        r#"
+        use _, _, add_group, drop_group, _ from 0x{state_change_batch}
+
+        // Value changes of a user in a group, which leave the epoch untouched
+        add_kv(new, old, op, private: old_group, new_group) = AND(
+            // Input validation
+            DictContains(op, "name", "add_kv")
+            // State transition
+            DictContains(old, op.group, old_group)
+            DictInsert(new_group, old_group, op.user, op.value)
+            DictUpdate(new, old, op.group, new_group)
+        )
+
+        del_kv(new, old, op, private: old_group, new_group) = AND(
+            // Input validation
+            DictContains(op, "name", "del_kv")
+            // State transition
+            DictContains(old, op.group, old_group)
+            DictDelete(new_group, old_group, op.user)
+            DictUpdate(new, old, op.group, new_group)
+        )
+
+        change_kv(new, old, op) = OR(
+            add_kv(new, old, op)
+            del_kv(new, old, op)
+            add_group(new, old, op)
+            drop_group(new, old, op)
+        )
+    "#,
+        state_change_batch = state_change_batch.id().encode_hex::<String>(),
+    );
+
+    let kv_state_change_batch = parse(
+        &input_kv_state_change,
+        params,
+        &[state_change_batch.clone()],
+    )
+    .unwrap()
+    .custom_batch;
+
+    let input_kv_state = format!(
+        r#"
+        use init, _, _ from 0x{state_batch}
+        use _, _, change_kv from 0x{kv_state_change_batch}
+
+        step_kv(new, old, op, epoch, private: mid, old_epoch) = AND(
+            change_kv(mid, old, op)
+            // Input validation
+            DictContains(op, "epoch", epoch)
+            // Epoch transition
+            DictContains(old, "epoch", old_epoch)
+            SumOf(epoch, old_epoch, 1)
+            DictUpdate(new, mid, "epoch", epoch)
+        )
+
+        update_kv(new, old, op, epoch) = OR(
+            init(new, old, op, epoch)
+            step_kv(new, old, op, epoch)
+        )
+    "#,
+        state_batch = state_batch.id().encode_hex::<String>(),
+        kv_state_change_batch = kv_state_change_batch.id().encode_hex::<String>(),
+    );
+
+    let kv_state_batch = parse(
+        &input_kv_state,
+        params,
+        &[state_batch.clone(), kv_state_change_batch.clone()],
+    )
+    .unwrap()
+    .custom_batch;
+
     // State batch predicates
 
     let state_preds = Predicates {
+        genesis: genesis.clone(),
         init: state_batch.predicate_ref_by_name("init").unwrap(),
-        add: state_batch.predicate_ref_by_name("add").unwrap(),
-        del: state_batch.predicate_ref_by_name("del").unwrap(),
+        add: state_change_batch.predicate_ref_by_name("add").unwrap(),
+        del: state_change_batch.predicate_ref_by_name("del").unwrap(),
+        add_group: state_change_batch
+            .predicate_ref_by_name("add_group")
+            .unwrap(),
+        drop_group: state_change_batch
+            .predicate_ref_by_name("drop_group")
+            .unwrap(),
+        change: state_change_batch.predicate_ref_by_name("change").unwrap(),
+        step: state_batch.predicate_ref_by_name("step").unwrap(),
         update: state_batch.predicate_ref_by_name("update").unwrap(),
     };
 
@@ -334,15 +751,240 @@ pub fn build_predicates(params: &Params) -> (Predicates, RevPredicates) {
         sync_del: rev_state_batch
             .predicate_ref_by_name("rev_sync_del")
             .unwrap(),
+        sync_add_group: rev_state_batch
+            .predicate_ref_by_name("rev_sync_add_group")
+            .unwrap(),
+        sync_drop_group: rev_state_batch
+            .predicate_ref_by_name("rev_sync_drop_group")
+            .unwrap(),
         sync: rev_state_batch.predicate_ref_by_name("rev_sync").unwrap(),
     };
 
-    (state_preds, rev_preds)
+    // Kv state predicates
+
+    let kv_preds = KvPredicates {
+        add_kv: kv_state_change_batch
+            .predicate_ref_by_name("add_kv")
+            .unwrap(),
+        del_kv: kv_state_change_batch
+            .predicate_ref_by_name("del_kv")
+            .unwrap(),
+        change: kv_state_change_batch
+            .predicate_ref_by_name("change_kv")
+            .unwrap(),
+        step: kv_state_batch.predicate_ref_by_name("step_kv").unwrap(),
+        update: kv_state_batch.predicate_ref_by_name("update_kv").unwrap(),
+    };
+
+    AppPredicates {
+        state: state_preds,
+        rev: rev_preds,
+        kv: kv_preds,
+        batches: vec![
+            state_change_batch,
+            state_batch,
+            rev_state_add_batch,
+            rev_state_del_batch,
+            rev_state_batch,
+            kv_state_change_batch,
+            kv_state_batch,
+        ],
+        sources: [
+            ("state_change", input_state_change),
+            ("state", input_state),
+            ("rev_state_add", input_rev_add),
+            ("rev_state_del", input_rev_del),
+            ("rev_state", input_rev),
+            ("kv_state_change", input_kv_state_change),
+            ("kv_state", input_kv_state),
+        ]
+        .into_iter()
+        .map(|(name, source)| (name.to_string(), source))
+        .collect(),
+    }
+}
+
+/// Fails if `dict`, or a container it holds, wasn't built with the depth of the containers of the
+/// pods, `params.max_depth_mt_containers`.  A container of another depth has another commitment
+/// than the same container built in a pod, so the statements about it would make an invalid
+/// proof.  `what` names `dict` in the error.
+pub fn check_depth(params: &Params, what: &str, dict: &Dictionary) -> Result<()> {
+    let depth = params.max_depth_mt_containers;
+    if dict.max_depth() != depth {
+        bail!(
+            "{} has depth {} instead of the depth {} of the params",
+            what,
+            dict.max_depth(),
+            depth
+        );
+    }
+    for (key, value) in dict.kvs() {
+        let inner = match value.typed() {
+            TypedValue::Set(set) => set.max_depth(),
+            TypedValue::Dictionary(dict) => dict.max_depth(),
+            _ => continue,
+        };
+        if inner != depth {
+            bail!(
+                "{}.{} has depth {} instead of the depth {} of the params",
+                what,
+                key.name(),
+                inner,
+                depth
+            );
+        }
+    }
+    Ok(())
+}
+
+// a new group, which commits to EMPTY either way: a dictionary in the kv lists, a set otherwise
+fn empty_group(depth: usize, kv: bool) -> Result<Value> {
+    Ok(if kv {
+        Value::from(Dictionary::new(depth, HashMap::new())?)
+    } else {
+        Value::from(Set::new(depth, HashSet::new())?)
+    })
+}
+
+// state after `init`: the genesis groups, empty, at epoch 1
+fn init_state(depth: usize, genesis: &Genesis, empty_group: &Value) -> Result<Dictionary> {
+    Ok(Dictionary::new(
+        depth,
+        genesis
+            .groups()
+            .iter()
+            .map(|group| (Key::from(group.as_str()), empty_group.clone()))
+            .chain([(Key::from("epoch"), Value::from(1i64))])
+            .collect(),
+    )?)
+}
+
+// state and new group after adding `user` to `group` or deleting it
+fn add_del_state(
+    old: &Dictionary,
+    group: &Key,
+    user: &Value,
+    add: bool,
+) -> Result<(Dictionary, Set)> {
+    let mut new_group = set_from_value(old.get(group)?)?;
+    if add {
+        new_group.insert(user)?;
+    } else {
+        new_group.delete(user)?;
+    }
+    let mut new = old.clone();
+    new.update(group, &Value::from(new_group.clone()))?;
+    Ok((new, new_group))
+}
+
+// state and new group after setting the value of `user` in the kv `group`, or deleting it
+fn add_del_kv_state(
+    old: &Dictionary,
+    group: &Key,
+    user: &Key,
+    value: Option<&Value>,
+) -> Result<(Dictionary, Dictionary)> {
+    let mut new_group = match old.get(group)?.typed() {
+        TypedValue::Dictionary(dict) => dict.clone(),
+        _ => bail!("group {} is not a dictionary", group.name()),
+    };
+    match value {
+        Some(value) => new_group.insert(user, value)?,
+        None => new_group.delete(user)?,
+    }
+    let mut new = old.clone();
+    new.update(group, &Value::from(new_group.clone()))?;
+    Ok((new, new_group))
+}
+
+// state after adding the empty `group`, which must not exist
+fn add_group_state(old: &Dictionary, group: &Key, empty_group: &Value) -> Result<Dictionary> {
+    if old.get(group).is_ok() {
+        return Err(anyhow!("group {} already exists", group.name()));
+    }
+    let mut new = old.clone();
+    new.insert(group, empty_group)?;
+    Ok(new)
+}
+
+// state after removing `group`, which must exist and be empty
+fn drop_group_state(old: &Dictionary, group: &Key) -> Result<Dictionary> {
+    let old_group = old
+        .get(group)
+        .map_err(|_| anyhow!("group {} doesn't exist", group.name()))?;
+    // the groups of the kv lists are dictionaries, which commit to EMPTY as well
+    if old_group.raw() != EMPTY_VALUE {
+        bail!("group {} not empty", group.name());
+    }
+    let mut new = old.clone();
+    new.delete(group)?;
+    Ok(new)
+}
+
+/// Returns the state after `ops`, applied in order from `initial`, without building nor proving
+/// any pod, e.g. for a client to predict the commitment of a list before sending its updates.  The
+/// state is the one that `Helper::st_update` proves, with the groups of `genesis` at init and the
+/// groups of the kv lists if `kv`, and the ops it refuses fail the same way.
+pub fn apply_ops(
+    genesis: &Genesis,
+    kv: bool,
+    initial: Dictionary,
+    ops: &[Op],
+) -> Result<Dictionary> {
+    let depth = initial.max_depth();
+    let mut state = initial;
+    for op in ops {
+        if let Op::Init = op {
+            if Value::from(state.clone()).raw() != EMPTY_VALUE {
+                bail!("old state is not empty");
+            }
+            state = init_state(depth, genesis, &empty_group(depth, kv)?)?;
+            continue;
+        }
+        let epoch = i64::try_from(
+            state
+                .get(&Key::from("epoch"))
+                .context("old state has no epoch")?
+                .typed(),
+        )
+        .context("old epoch is not an int")?;
+        let key = |group: &Group| Key::from(group.as_str());
+        let mut new = match op {
+            Op::Init => unreachable!("init is applied above"),
+            Op::Add { group, user } | Op::Del { group, user } if !kv => {
+                let add = matches!(op, Op::Add { .. });
+                add_del_state(&state, &key(group), &Value::from(user.as_str()), add)?.0
+            }
+            Op::AddKv { group, user, value } if kv => {
+                let value = Value::from(*value);
+                add_del_kv_state(&state, &key(group), &Key::from(user.as_str()), Some(&value))?.0
+            }
+            Op::DelKv { group, user } if kv => {
+                add_del_kv_state(&state, &key(group), &Key::from(user.as_str()), None)?.0
+            }
+            Op::AddGroup { group } => {
+                add_group_state(&state, &key(group), &empty_group(depth, kv)?)?
+            }
+            Op::DropGroup { group } => drop_group_state(&state, &key(group))?,
+            op => bail!("op {:?} doesn't apply to this kind of list", op),
+        };
+        new.update(&Key::from("epoch"), &Value::from(epoch + 1))?;
+        state = new;
+    }
+    Ok(state)
+}
+
+fn op_name(op: &Dictionary) -> Result<String> {
+    let name = op.get(&Key::from("name")).context("op has no name")?;
+    String::try_from(name.typed()).context("op name is not a string")
 }
 
 pub struct Helper<'a> {
     pub builder: &'a mut MainPodBuilder,
     pub predicates: &'a Predicates,
+    // the updates are proven with the kv predicates if set, which take the kv ops instead of
+    // `add` and `del`
+    pub kv_predicates: Option<&'a KvPredicates>,
 }
 
 impl<'a> Helper<'a> {
@@ -350,43 +992,68 @@ impl<'a> Helper<'a> {
         Self {
             builder: pod_builder,
             predicates,
+            kv_predicates: None,
+        }
+    }
+
+    pub fn new_kv(
+        pod_builder: &'a mut MainPodBuilder,
+        predicates: &'a Predicates,
+        kv_predicates: &'a KvPredicates,
+    ) -> Self {
+        Self {
+            builder: pod_builder,
+            predicates,
+            kv_predicates: Some(kv_predicates),
         }
     }
 
+    // depth of the containers created by the helper, from the params of the pod being built
+    fn depth(&self) -> usize {
+        self.builder.params.max_depth_mt_containers
+    }
+
+    fn empty_group(&self) -> Result<Value> {
+        empty_group(self.depth(), self.kv_predicates.is_some())
+    }
+
     pub fn st_init(&mut self, old: Dictionary, op: Dictionary) -> Result<(Dictionary, Statement)> {
-        let name = String::try_from(op.get(&Key::from("name")).unwrap().typed()).unwrap();
-        assert_eq!(name, "init");
+        let name = op_name(&op)?;
+        if name != "init" {
+            bail!("invalid op.name = {} for init", name);
+        }
         // DictContains(op, "name", "init")
         let st0 = self
             .builder
-            .priv_op(Operation::dict_contains(op.clone(), "name", "init"))
-            .unwrap();
+            .priv_op(Operation::dict_contains(op.clone(), "name", "init"))?;
+        let epoch = op.get(&Key::from("epoch")).context("op has no epoch")?;
+        // DictContains(op, "epoch", epoch)
+        let st1 =
+            self.builder
+                .priv_op(Operation::dict_contains(op.clone(), "epoch", epoch.clone()))?;
+        // Equal(epoch, 1)
+        let st2 = self
+            .builder
+            .priv_op(Operation::eq(epoch.clone(), 1i64))
+            .context("init epoch is not 1")?;
         // Equal(old, EMPTY)
-        let st1 = self
+        let st3 = self
             .builder
             .priv_op(Operation::eq(old.clone(), EMPTY_VALUE))
             .context("old state is not empty")?;
 
-        let empty_group = Value::from(Set::new(DEPTH, HashSet::new()).unwrap());
-        let init_state = dict!({
-            "red" => empty_group.clone(),
-            "green" => empty_group.clone(),
-            "blue" => empty_group}
-        );
-        // Equal(new, {"red": EMPTY, "green": EMPTY, "blue": EMPTY})
-        let st2 = self
+        let init_state = init_state(self.depth(), &self.predicates.genesis, &self.empty_group()?)?;
+        // Equal(new, {"red": EMPTY, "green": EMPTY, "blue": EMPTY, "epoch": 1}) with the genesis
+        // groups
+        let st4 = self
             .builder
-            .priv_op(Operation::eq(init_state.clone(), init_state.clone()))
-            .unwrap();
+            .priv_op(Operation::eq(init_state.clone(), init_state.clone()))?;
 
-        // init(new, old, op)
-        let st = self
-            .builder
-            .priv_op(Operation::custom(
-                self.predicates.init.clone(),
-                [st0, st1, st2],
-            ))
-            .unwrap();
+        // init(new, old, op, epoch)
+        let st = self.builder.priv_op(Operation::custom(
+            self.predicates.init.clone(),
+            [st0, st1, st2, st3, st4],
+        ))?;
         Ok((init_state, st))
     }
 
@@ -395,41 +1062,37 @@ impl<'a> Helper<'a> {
         old: Dictionary,
         op: Dictionary,
     ) -> Result<(Dictionary, Statement)> {
-        let name = String::try_from(op.get(&Key::from("name")).unwrap().typed()).unwrap();
-        assert!(name == "add" || name == "del");
+        let name = op_name(&op)?;
+        if name != "add" && name != "del" {
+            bail!("invalid op.name = {} for add or del", name);
+        }
 
         let st0 = if name == "add" {
             // DictContains(op, "name", "add")
             self.builder
-                .priv_op(Operation::dict_contains(op.clone(), "name", "add"))
-                .unwrap()
+                .priv_op(Operation::dict_contains(op.clone(), "name", "add"))?
         } else {
             // DictContains(op, "name", "del")
             self.builder
-                .priv_op(Operation::dict_contains(op.clone(), "name", "del"))
-                .unwrap()
+                .priv_op(Operation::dict_contains(op.clone(), "name", "del"))?
         };
 
-        let group = Key::try_from(op.get(&Key::from("group")).unwrap().typed()).unwrap();
-        let old_group = old.get(&group).unwrap();
+        let group = Key::try_from(op.get(&Key::from("group"))?.typed())?;
+        let user = op.get(&Key::from("user"))?;
+        // the ops built from `Op` always have a valid group and user, this rejects op dictionaries
+        // built by other means
+        Group::new(group.name())?;
+        UserId::new(String::try_from(user.typed()).context("user is not a string")?)?;
+        let (new, new_group) = add_del_state(&old, &group, user, name == "add")?;
+        let old_group = old.get(&group)?;
         // DictContains(old, op.group, old_group)
-        let st1 = self
-            .builder
-            .priv_op(Operation::dict_contains(
-                old.clone(),
-                (&op, "group"),
-                old_group.clone(),
-            ))
-            .unwrap();
+        let st1 = self.builder.priv_op(Operation::dict_contains(
+            old.clone(),
+            (&op, "group"),
+            old_group.clone(),
+        ))?;
 
-        let user = op.get(&Key::from("user")).unwrap();
-        let mut new_group = if let TypedValue::Set(set) = old_group.typed() {
-            set.clone()
-        } else {
-            panic!("Value not a Set: {:?}", old_group)
-        };
         let st2 = if name == "add" {
-            new_group.insert(user).unwrap();
             // SetInsert(new_group, old_group, op.user)
             self.builder
                 .priv_op(Operation::set_insert(
@@ -439,7 +1102,6 @@ impl<'a> Helper<'a> {
                 ))
                 .context("old_group already contains user")?
         } else {
-            new_group.delete(user).unwrap();
             // SetDelete(new_group, old_group, op.user)
             self.builder
                 .priv_op(Operation::set_delete(
@@ -450,72 +1112,362 @@ impl<'a> Helper<'a> {
                 .context("old_group doesn't contain user")?
         };
 
-        let mut new = old.clone();
-        new.update(&group, &Value::from(new_group.clone())).unwrap();
         // DictUpdate(new, old, op.group, new_group)
-        let st3 = self
-            .builder
-            .priv_op(Operation::dict_update(
-                new.clone(),
-                old.clone(),
-                (&op, "group"),
-                new_group,
-            ))
-            .unwrap();
+        let st3 = self.builder.priv_op(Operation::dict_update(
+            new.clone(),
+            old.clone(),
+            (&op, "group"),
+            new_group,
+        ))?;
 
         let st = if name == "add" {
             // add(new, old, op, private: old_group, new_group)
-            self.builder
-                .priv_op(Operation::custom(
-                    self.predicates.add.clone(),
-                    [st0, st1, st2, st3],
-                ))
-                .unwrap()
+            self.builder.priv_op(Operation::custom(
+                self.predicates.add.clone(),
+                [st0, st1, st2, st3],
+            ))?
         } else {
             // del(new, old, op, private: old_group, new_group)
-            self.builder
-                .priv_op(Operation::custom(
-                    self.predicates.del.clone(),
-                    [st0, st1, st2, st3],
-                ))
-                .unwrap()
+            self.builder.priv_op(Operation::custom(
+                self.predicates.del.clone(),
+                [st0, st1, st2, st3],
+            ))?
         };
         Ok((new, st))
     }
 
+    pub fn st_add_group(
+        &mut self,
+        old: Dictionary,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement)> {
+        let name = op_name(&op)?;
+        if name != "add_group" {
+            bail!("invalid op.name = {} for add_group", name);
+        }
+        // DictContains(op, "name", "add_group")
+        let st0 =
+            self.builder
+                .priv_op(Operation::dict_contains(op.clone(), "name", "add_group"))?;
+
+        let group = op.get(&Key::from("group")).context("op has no group")?;
+        // the ops built from `Op` always have a valid group, this rejects op dictionaries built
+        // by other means
+        let group = Group::new(String::try_from(group.typed()).context("group is not a string")?)?;
+        let group = Key::from(group.as_str());
+        let empty_group = self.empty_group()?;
+        let new = add_group_state(&old, &group, &empty_group)?;
+        // DictInsert(new, old, op.group, EMPTY)
+        let st1 = self.builder.priv_op(Operation::dict_insert(
+            new.clone(),
+            old,
+            (&op, "group"),
+            empty_group,
+        ))?;
+
+        // add_group(new, old, op)
+        let st = self.builder.priv_op(Operation::custom(
+            self.predicates.add_group.clone(),
+            [st0, st1],
+        ))?;
+        Ok((new, st))
+    }
+
+    pub fn st_drop_group(
+        &mut self,
+        old: Dictionary,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement)> {
+        let name = op_name(&op)?;
+        if name != "drop_group" {
+            bail!("invalid op.name = {} for drop_group", name);
+        }
+        // DictContains(op, "name", "drop_group")
+        let st0 =
+            self.builder
+                .priv_op(Operation::dict_contains(op.clone(), "name", "drop_group"))?;
+
+        let group = op.get(&Key::from("group")).context("op has no group")?;
+        let group = Group::new(String::try_from(group.typed()).context("group is not a string")?)?;
+        let group = Key::from(group.as_str());
+        let new = drop_group_state(&old, &group)?;
+        let old_group = old.get(&group)?;
+        // DictContains(old, op.group, EMPTY)
+        let st1 = self.builder.priv_op(Operation::dict_contains(
+            old.clone(),
+            (&op, "group"),
+            old_group.clone(),
+        ))?;
+
+        // DictDelete(new, old, op.group)
+        let st2 = self
+            .builder
+            .priv_op(Operation::dict_delete(new.clone(), old, (&op, "group")))?;
+
+        // drop_group(new, old, op)
+        let st = self.builder.priv_op(Operation::custom(
+            self.predicates.drop_group.clone(),
+            [st0, st1, st2],
+        ))?;
+        Ok((new, st))
+    }
+
+    pub fn st_add_del_kv(
+        &mut self,
+        old: Dictionary,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement)> {
+        let name = op_name(&op)?;
+        if name != "add_kv" && name != "del_kv" {
+            bail!("invalid op.name = {} for add_kv or del_kv", name);
+        }
+        let kv_predicates = self
+            .kv_predicates
+            .context("the kv ops need the kv predicates")?;
+
+        // DictContains(op, "name", "add_kv") or DictContains(op, "name", "del_kv")
+        let st0 =
+            self.builder
+                .priv_op(Operation::dict_contains(op.clone(), "name", name.as_str()))?;
+
+        let group = Key::try_from(op.get(&Key::from("group"))?.typed())?;
+        let user = op.get(&Key::from("user"))?;
+        // the ops built from `Op` always have a valid group and user, this rejects op dictionaries
+        // built by other means
+        Group::new(group.name())?;
+        let user = UserId::new(String::try_from(user.typed()).context("user is not a string")?)?;
+        let user = Key::from(user.as_str());
+        let value = if name == "add_kv" {
+            let value = op.get(&Key::from("value")).context("op has no value")?;
+            i64::try_from(value.typed()).context("value is not an int")?;
+            Some(value)
+        } else {
+            None
+        };
+        let (new, new_group) = add_del_kv_state(&old, &group, &user, value)?;
+        let old_group = old.get(&group)?;
+        // DictContains(old, op.group, old_group)
+        let st1 = self.builder.priv_op(Operation::dict_contains(
+            old.clone(),
+            (&op, "group"),
+            old_group.clone(),
+        ))?;
+
+        let st2 = if name == "add_kv" {
+            // DictInsert(new_group, old_group, op.user, op.value)
+            self.builder
+                .priv_op(Operation::dict_insert(
+                    new_group.clone(),
+                    old_group.clone(),
+                    (&op, "user"),
+                    (&op, "value"),
+                ))
+                .context("old_group already contains user")?
+        } else {
+            // DictDelete(new_group, old_group, op.user)
+            self.builder
+                .priv_op(Operation::dict_delete(
+                    new_group.clone(),
+                    old_group.clone(),
+                    (&op, "user"),
+                ))
+                .context("old_group doesn't contain user")?
+        };
+
+        // DictUpdate(new, old, op.group, new_group)
+        let st3 = self.builder.priv_op(Operation::dict_update(
+            new.clone(),
+            old.clone(),
+            (&op, "group"),
+            new_group,
+        ))?;
+
+        // add_kv or del_kv(new, old, op, private: old_group, new_group)
+        let pred = if name == "add_kv" {
+            &kv_predicates.add_kv
+        } else {
+            &kv_predicates.del_kv
+        };
+        let st = self
+            .builder
+            .priv_op(Operation::custom(pred.clone(), [st0, st1, st2, st3]))?;
+        Ok((new, st))
+    }
+
+    pub fn st_change(
+        &mut self,
+        old: Dictionary,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement)> {
+        let name = op_name(&op)?;
+        let kv = self.kv_predicates.is_some();
+        // the branch of the op in change(new, old, op), or in change_kv(new, old, op) which has
+        // the kv ops in place of `add` and `del`
+        let (new, st, branch) = match name.as_str() {
+            "add" | "del" if !kv => {
+                // add or del(new, old, op, private: old_group, new_group)
+                let (new, st) = self.st_add_del(old, op)?;
+                (new, st, if name == "add" { 0 } else { 1 })
+            }
+            "add_kv" | "del_kv" if kv => {
+                // add_kv or del_kv(new, old, op, private: old_group, new_group)
+                let (new, st) = self.st_add_del_kv(old, op)?;
+                (new, st, if name == "add_kv" { 0 } else { 1 })
+            }
+            "add_group" => {
+                // add_group(new, old, op)
+                let (new, st) = self.st_add_group(old, op)?;
+                (new, st, 2)
+            }
+            "drop_group" => {
+                // drop_group(new, old, op)
+                let (new, st) = self.st_drop_group(old, op)?;
+                (new, st, 3)
+            }
+            _ => bail!("invalid op.name = {}", name),
+        };
+        let mut sts: [Statement; 4] = std::array::from_fn(|_| Statement::None);
+        sts[branch] = st;
+
+        // change(new, old, op) or change_kv(new, old, op)
+        let change = match self.kv_predicates {
+            Some(kv_predicates) => &kv_predicates.change,
+            None => &self.predicates.change,
+        };
+        let st = self
+            .builder
+            .priv_op(Operation::custom(change.clone(), sts))?;
+        Ok((new, st))
+    }
+
+    pub fn st_step(&mut self, old: Dictionary, op: Dictionary) -> Result<(Dictionary, Statement)> {
+        let epoch = op
+            .get(&Key::from("epoch"))
+            .context("op has no epoch")?
+            .clone();
+        let old_epoch = old
+            .get(&Key::from("epoch"))
+            .context("old state has no epoch")?
+            .clone();
+
+        // change(mid, old, op)
+        let (mid, st0) = self.st_change(old.clone(), op.clone())?;
+        // DictContains(op, "epoch", epoch)
+        let st1 = self
+            .builder
+            .priv_op(Operation::dict_contains(op, "epoch", epoch.clone()))?;
+        // DictContains(old, "epoch", old_epoch)
+        let st2 =
+            self.builder
+                .priv_op(Operation::dict_contains(old, "epoch", old_epoch.clone()))?;
+        // SumOf(epoch, old_epoch, 1)
+        let st3 = self
+            .builder
+            .priv_op(Operation::sum_of(epoch.clone(), old_epoch, 1i64))
+            .context("epoch doesn't follow the old epoch")?;
+
+        let mut new = mid.clone();
+        new.update(&Key::from("epoch"), &epoch)?;
+        // DictUpdate(new, mid, "epoch", epoch)
+        let st4 = self
+            .builder
+            .priv_op(Operation::dict_update(new.clone(), mid, "epoch", epoch))?;
+
+        // step(new, old, op, epoch, private: mid, old_epoch) or step_kv(..)
+        let step = match self.kv_predicates {
+            Some(kv_predicates) => &kv_predicates.step,
+            None => &self.predicates.step,
+        };
+        let st = self
+            .builder
+            .priv_op(Operation::custom(step.clone(), [st0, st1, st2, st3, st4]))?;
+        Ok((new, st))
+    }
+
     pub fn st_update(
         &mut self,
         old: Dictionary,
         op: Dictionary,
     ) -> Result<(Dictionary, Statement)> {
-        let name = String::try_from(op.get(&Key::from("name")).unwrap().typed()).unwrap();
+        check_depth(&self.builder.params, "state", &old)?;
+        check_depth(&self.builder.params, "op", &op)?;
+        let name = op_name(&op)?;
         let st_none = Statement::None;
         let (new, sts) = match name.as_str() {
             "init" => {
-                // init(new, old, op)
+                // init(new, old, op, epoch)
                 let (new, st) = self.st_init(old, op)?;
-                (new, [st, st_none.clone(), st_none.clone()])
-            }
-            "add" => {
-                // add(new, old, op, private: old_group, new_group)
-                let (new, st) = self.st_add_del(old, op)?;
-                (new, [st_none.clone(), st, st_none.clone()])
+                (new, [st, st_none])
             }
-            "del" => {
-                // del(new, old, op, private: old_group, new_group)
-                let (new, st) = self.st_add_del(old, op)?;
-                (new, [st_none.clone(), st_none.clone(), st])
+            "add" | "del" | "add_kv" | "del_kv" | "add_group" | "drop_group" => {
+                // step(new, old, op, epoch, private: mid, old_epoch)
+                let (new, st) = self.st_step(old, op)?;
+                (new, [st_none, st])
             }
-            _ => panic!("invalid op.name = {}", name),
+            _ => bail!("invalid op.name = {}", name),
         };
 
-        // update(new, old, op)
+        // update(new, old, op, epoch) or update_kv(new, old, op, epoch)
+        let update = match self.kv_predicates {
+            Some(kv_predicates) => &kv_predicates.update,
+            None => &self.predicates.update,
+        };
         let st = self
             .builder
-            .priv_op(Operation::custom(self.predicates.update.clone(), sts))
-            .unwrap();
+            .priv_op(Operation::custom(update.clone(), sts))?;
         Ok((new, st))
     }
+
+    /// Chains `st_update` over `ops`, each applied to the state left by the previous one, so that
+    /// a single pod proves several consecutive updates of a list.  Returns the state and the
+    /// update statement after each op, whose statements are to be revealed in order.  Every
+    /// update takes about a dozen statements, so the ops that fit in a pod are bounded by
+    /// `Params::max_statements`, and the ones it reveals by `Params::max_public_statements`.
+    pub fn st_update_batch(
+        &mut self,
+        old: Dictionary,
+        ops: impl IntoIterator<Item = Dictionary>,
+    ) -> Result<Vec<(Dictionary, Statement)>> {
+        let mut state = old;
+        let mut steps = Vec::new();
+        for op in ops {
+            let (new, st) = self.st_update(state, op)?;
+            state = new.clone();
+            steps.push((new, st));
+        }
+        Ok(steps)
+    }
+
+    /// Like `st_update` for an `add` op, also returning the statements that the user is in the
+    /// group in the new state: `DictContains(new, group, new_group)` and
+    /// `SetContains(new_group, user)`.  Revealing them together with the update statement makes a
+    /// single pod prove both the transition and the membership of the added user.
+    pub fn st_update_with_membership(
+        &mut self,
+        old: Dictionary,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement, [Statement; 2])> {
+        let name = op_name(&op)?;
+        if name != "add" {
+            return Err(anyhow!("op {} doesn't add a user", name));
+        }
+        let group = Key::try_from(op.get(&Key::from("group"))?.typed())?;
+        let user = op.get(&Key::from("user"))?.clone();
+        let (new, st_update) = self.st_update(old, op)?;
+
+        let new_group = new.get(&group)?.clone();
+        // DictContains(new, group, new_group)
+        let st_group = self.builder.priv_op(Operation::dict_contains(
+            new.clone(),
+            group.name(),
+            new_group.clone(),
+        ))?;
+        // SetContains(new_group, user)
+        let st_member = self
+            .builder
+            .priv_op(Operation::set_contains(new_group, user))?;
+        Ok((new, st_update, [st_group, st_member]))
+    }
 }
 
 pub struct RevHelper<'a> {
@@ -537,29 +1489,30 @@ impl<'a> RevHelper<'a> {
         }
     }
 
+    // depth of the containers created by the helper, from the params of the pod being built
+    fn depth(&self) -> usize {
+        self.builder.params.max_depth_mt_containers
+    }
+
     pub fn st_rev_sync_init(
         &mut self,
         st_update: Statement,
         op: Dictionary,
-    ) -> (Dictionary, Statement) {
-        let init_rev_state = Dictionary::new(DEPTH, HashMap::new()).unwrap();
+    ) -> Result<(Dictionary, Statement)> {
+        let init_rev_state = Dictionary::new(self.depth(), HashMap::new())?;
         let st1 = self
             .builder
-            .priv_op(Operation::dict_contains(op.clone(), "name", "init"))
-            .unwrap();
+            .priv_op(Operation::dict_contains(op.clone(), "name", "init"))?;
         let st2 = self
             .builder
-            .priv_op(Operation::eq(init_rev_state.clone(), EMPTY_VALUE))
-            .unwrap();
-        (
+            .priv_op(Operation::eq(init_rev_state.clone(), EMPTY_VALUE))?;
+        Ok((
             init_rev_state,
-            self.builder
-                .priv_op(Operation::custom(
-                    self.rev_predicates.sync_init.clone(),
-                    [st_update, st1, st2],
-                ))
-                .unwrap(),
-        )
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.sync_init.clone(),
+                [st_update, st1, st2],
+            ))?,
+        ))
     }
 
     pub fn st_rev_add_fresh(
@@ -568,40 +1521,30 @@ impl<'a> RevHelper<'a> {
         op: Dictionary,
         user: &Key,
         group: &Value,
-    ) -> (Dictionary, Statement) {
-        let empty_set = Set::new(DEPTH, HashSet::new()).unwrap();
+    ) -> Result<(Dictionary, Statement)> {
+        let empty_set = Set::new(self.depth(), HashSet::new())?;
         let mut user_groups = empty_set.clone();
-        user_groups.insert(group).unwrap();
+        user_groups.insert(group)?;
         let mut new_rev = old_rev.clone();
-        new_rev
-            .insert(user, &Value::from(user_groups.clone()))
-            .unwrap();
-        let st0 = self
-            .builder
-            .priv_op(Operation::set_insert(
-                user_groups.clone(),
-                empty_set,
-                (&op, "group"),
-            ))
-            .unwrap();
-        let st1 = self
-            .builder
-            .priv_op(Operation::dict_insert(
-                new_rev.clone(),
-                old_rev,
-                (&op, "user"),
-                user_groups,
-            ))
-            .unwrap();
-        (
+        new_rev.insert(user, &Value::from(user_groups.clone()))?;
+        let st0 = self.builder.priv_op(Operation::set_insert(
+            user_groups.clone(),
+            empty_set,
+            (&op, "group"),
+        ))?;
+        let st1 = self.builder.priv_op(Operation::dict_insert(
+            new_rev.clone(),
+            old_rev,
+            (&op, "user"),
+            user_groups,
+        ))?;
+        Ok((
             new_rev,
-            self.builder
-                .priv_op(Operation::custom(
-                    self.rev_predicates.add_fresh.clone(),
-                    [st0, st1],
-                ))
-                .unwrap(),
-        )
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.add_fresh.clone(),
+                [st0, st1],
+            ))?,
+        ))
     }
 
     pub fn st_rev_add_existing(
@@ -610,77 +1553,61 @@ impl<'a> RevHelper<'a> {
         op: Dictionary,
         user: &Key,
         group: &Value,
-    ) -> (Dictionary, Statement) {
-        let old_user_groups = old_rev.get(user).unwrap();
-        let mut user_groups = if let TypedValue::Set(set) = old_user_groups.typed() {
-            set.clone()
-        } else {
-            panic!("Value not a Set: {:?}", old_user_groups)
-        };
-        user_groups.insert(group).unwrap();
+    ) -> Result<(Dictionary, Statement)> {
+        let old_user_groups = old_rev.get(user)?;
+        let mut user_groups = set_from_value(old_user_groups)?;
+        user_groups.insert(group)?;
         let mut new_rev = old_rev.clone();
-        new_rev
-            .update(user, &Value::from(user_groups.clone()))
-            .unwrap();
-
-        let st0 = self
-            .builder
-            .priv_op(Operation::dict_contains(
-                old_rev.clone(),
-                (&op, "user"),
-                old_user_groups.clone(),
-            ))
-            .unwrap();
-        let st1 = self
-            .builder
-            .priv_op(Operation::set_insert(
-                user_groups.clone(),
-                old_user_groups.clone(),
-                (&op, "group"),
-            ))
-            .unwrap();
-        let st2 = self
-            .builder
-            .priv_op(Operation::dict_update(
-                new_rev.clone(),
-                old_rev,
-                (&op, "user"),
-                user_groups,
-            ))
-            .unwrap();
-        (
+        new_rev.update(user, &Value::from(user_groups.clone()))?;
+
+        let st0 = self.builder.priv_op(Operation::dict_contains(
+            old_rev.clone(),
+            (&op, "user"),
+            old_user_groups.clone(),
+        ))?;
+        let st1 = self.builder.priv_op(Operation::set_insert(
+            user_groups.clone(),
+            old_user_groups.clone(),
+            (&op, "group"),
+        ))?;
+        let st2 = self.builder.priv_op(Operation::dict_update(
+            new_rev.clone(),
+            old_rev,
+            (&op, "user"),
+            user_groups,
+        ))?;
+        Ok((
             new_rev,
-            self.builder
-                .priv_op(Operation::custom(
-                    self.rev_predicates.add_existing.clone(),
-                    [st0, st1, st2],
-                ))
-                .unwrap(),
-        )
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.add_existing.clone(),
+                [st0, st1, st2],
+            ))?,
+        ))
     }
 
-    pub fn st_rev_add(&mut self, old_rev: Dictionary, op: Dictionary) -> (Dictionary, Statement) {
-        let user =
-            Key::from(String::try_from(op.get(&Key::from("user")).unwrap().typed()).unwrap());
-        let group =
-            Value::from(String::try_from(op.get(&Key::from("group")).unwrap().typed()).unwrap());
+    pub fn st_rev_add(
+        &mut self,
+        old_rev: Dictionary,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement)> {
+        let user = Key::from(String::try_from(op.get(&Key::from("user"))?.typed())?);
+        let group = Value::from(String::try_from(op.get(&Key::from("group"))?.typed())?);
         let st_none = Statement::None;
         let (new, sts) = match old_rev.get(&user) {
             Err(_) => {
-                let (new, st) = self.st_rev_add_fresh(old_rev, op, &user, &group);
+                let (new, st) = self.st_rev_add_fresh(old_rev, op, &user, &group)?;
                 (new, [st, st_none])
             }
             Ok(_) => {
-                let (new, st) = self.st_rev_add_existing(old_rev, op, &user, &group);
+                let (new, st) = self.st_rev_add_existing(old_rev, op, &user, &group)?;
                 (new, [st_none, st])
             }
         };
-        (
+        Ok((
             new,
             self.builder
-                .priv_op(Operation::custom(self.rev_predicates.add.clone(), sts))
-                .unwrap(),
-        )
+                .priv_op(Operation::custom(self.rev_predicates.add.clone(), sts))?,
+        ))
     }
 
     pub fn st_rev_del_singleton(
@@ -688,45 +1615,34 @@ impl<'a> RevHelper<'a> {
         old_rev: Dictionary,
         op: Dictionary,
         user: &Key,
-    ) -> (Dictionary, Statement) {
-        let old_user_groups = old_rev.get(user).unwrap();
-        let empty_set = Set::new(DEPTH, HashSet::new()).unwrap();
+    ) -> Result<(Dictionary, Statement)> {
+        let old_user_groups = old_rev.get(user)?;
+        let empty_set = Set::new(self.depth(), HashSet::new())?;
         let mut new_rev = old_rev.clone();
-        new_rev.delete(user).unwrap();
-
-        let st0 = self
-            .builder
-            .priv_op(Operation::dict_contains(
-                old_rev.clone(),
-                (&op, "user"),
-                old_user_groups.clone(),
-            ))
-            .unwrap();
-        let st1 = self
-            .builder
-            .priv_op(Operation::set_delete(
-                empty_set,
-                old_user_groups.clone(),
-                (&op, "group"),
-            ))
-            .unwrap();
-        let st2 = self
-            .builder
-            .priv_op(Operation::dict_delete(
-                new_rev.clone(),
-                old_rev,
-                (&op, "user"),
-            ))
-            .unwrap();
-        (
+        new_rev.delete(user)?;
+
+        let st0 = self.builder.priv_op(Operation::dict_contains(
+            old_rev.clone(),
+            (&op, "user"),
+            old_user_groups.clone(),
+        ))?;
+        let st1 = self.builder.priv_op(Operation::set_delete(
+            empty_set,
+            old_user_groups.clone(),
+            (&op, "group"),
+        ))?;
+        let st2 = self.builder.priv_op(Operation::dict_delete(
+            new_rev.clone(),
+            old_rev,
+            (&op, "user"),
+        ))?;
+        Ok((
             new_rev,
-            self.builder
-                .priv_op(Operation::custom(
-                    self.rev_predicates.del_singleton.clone(),
-                    [st0, st1, st2],
-                ))
-                .unwrap(),
-        )
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.del_singleton.clone(),
+                [st0, st1, st2],
+            ))?,
+        ))
     }
 
     pub fn st_rev_del_else(
@@ -735,84 +1651,68 @@ impl<'a> RevHelper<'a> {
         op: Dictionary,
         user: &Key,
         group: &Value,
-    ) -> (Dictionary, Statement) {
-        let old_user_groups = old_rev.get(user).unwrap();
-        let mut user_groups = if let TypedValue::Set(set) = old_user_groups.typed() {
-            set.clone()
-        } else {
-            panic!("Value not a Set: {:?}", old_user_groups)
-        };
-        user_groups.delete(group).unwrap();
+    ) -> Result<(Dictionary, Statement)> {
+        let old_user_groups = old_rev.get(user)?;
+        let mut user_groups = set_from_value(old_user_groups)?;
+        user_groups.delete(group)?;
         let mut new_rev = old_rev.clone();
-        new_rev
-            .update(user, &Value::from(user_groups.clone()))
-            .unwrap();
-
-        let st0 = self
-            .builder
-            .priv_op(Operation::dict_contains(
-                old_rev.clone(),
-                (&op, "user"),
-                old_user_groups.clone(),
-            ))
-            .unwrap();
-        let st1 = self
-            .builder
-            .priv_op(Operation::set_delete(
-                user_groups.clone(),
-                old_user_groups.clone(),
-                (&op, "group"),
-            ))
-            .unwrap();
-        let st2 = self
-            .builder
-            .priv_op(Operation::dict_update(
-                new_rev.clone(),
-                old_rev,
-                (&op, "user"),
-                user_groups,
-            ))
-            .unwrap();
-        (
+        new_rev.update(user, &Value::from(user_groups.clone()))?;
+
+        let st0 = self.builder.priv_op(Operation::dict_contains(
+            old_rev.clone(),
+            (&op, "user"),
+            old_user_groups.clone(),
+        ))?;
+        let st1 = self.builder.priv_op(Operation::set_delete(
+            user_groups.clone(),
+            old_user_groups.clone(),
+            (&op, "group"),
+        ))?;
+        let st2 = self.builder.priv_op(Operation::dict_update(
+            new_rev.clone(),
+            old_rev,
+            (&op, "user"),
+            user_groups,
+        ))?;
+        Ok((
             new_rev,
-            self.builder
-                .priv_op(Operation::custom(
-                    self.rev_predicates.del_else.clone(),
-                    [st0, st1, st2],
-                ))
-                .unwrap(),
-        )
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.del_else.clone(),
+                [st0, st1, st2],
+            ))?,
+        ))
     }
 
-    pub fn st_rev_del(&mut self, old_rev: Dictionary, op: Dictionary) -> (Dictionary, Statement) {
-        let user =
-            Key::from(String::try_from(op.get(&Key::from("user")).unwrap().typed()).unwrap());
-        let group =
-            Value::from(String::try_from(op.get(&Key::from("group")).unwrap().typed()).unwrap());
+    pub fn st_rev_del(
+        &mut self,
+        old_rev: Dictionary,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement)> {
+        let user = Key::from(String::try_from(op.get(&Key::from("user"))?.typed())?);
+        let group = Value::from(String::try_from(op.get(&Key::from("group"))?.typed())?);
         let st_none = Statement::None;
-        let groups = set_from_value(old_rev.get(&user).unwrap()).unwrap();
+        let groups = set_from_value(old_rev.get(&user)?)?;
 
         let (new, sts) = match groups.set().len() {
             1 => {
                 if groups.contains(&group) {
-                    let (new, st) = self.st_rev_del_singleton(old_rev, op, &user);
+                    let (new, st) = self.st_rev_del_singleton(old_rev, op, &user)?;
                     (new, [st, st_none])
                 } else {
-                    panic!("User is not a member of the specified group.")
+                    bail!("user is not a member of the specified group")
                 }
             }
             _ => {
-                let (new, st) = self.st_rev_del_else(old_rev, op, &user, &group);
+                let (new, st) = self.st_rev_del_else(old_rev, op, &user, &group)?;
                 (new, [st_none, st])
             }
         };
 
-        (
+        Ok((
             new,
             self.builder
-                .priv_op(Operation::custom(self.rev_predicates.del.clone(), sts))
-                .unwrap(),
-        )
+                .priv_op(Operation::custom(self.rev_predicates.del.clone(), sts))?,
+        ))
     }
 
     pub fn st_rev_sync_add(
@@ -821,21 +1721,18 @@ impl<'a> RevHelper<'a> {
         st_update: Statement,
         old_st_rev_sync: Statement,
         op: Dictionary,
-    ) -> (Dictionary, Statement) {
+    ) -> Result<(Dictionary, Statement)> {
         let st2 = self
             .builder
-            .priv_op(Operation::dict_contains(op.clone(), "name", "add"))
-            .unwrap();
-        let (new, st3) = self.st_rev_add(old_rev, op);
-        (
+            .priv_op(Operation::dict_contains(op.clone(), "name", "add"))?;
+        let (new, st3) = self.st_rev_add(old_rev, op)?;
+        Ok((
             new,
-            self.builder
-                .priv_op(Operation::custom(
-                    self.rev_predicates.sync_add.clone(),
-                    [old_st_rev_sync, st_update, st2, st3],
-                ))
-                .unwrap(),
-        )
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.sync_add.clone(),
+                [old_st_rev_sync, st_update, st2, st3],
+            ))?,
+        ))
     }
 
     pub fn st_rev_sync_del(
@@ -844,21 +1741,56 @@ impl<'a> RevHelper<'a> {
         st_update: Statement,
         old_st_rev_sync: Statement,
         op: Dictionary,
-    ) -> (Dictionary, Statement) {
+    ) -> Result<(Dictionary, Statement)> {
         let st2 = self
             .builder
-            .priv_op(Operation::dict_contains(op.clone(), "name", "del"))
-            .unwrap();
-        let (new, st3) = self.st_rev_del(old_rev, op);
-        (
+            .priv_op(Operation::dict_contains(op.clone(), "name", "del"))?;
+        let (new, st3) = self.st_rev_del(old_rev, op)?;
+        Ok((
             new,
-            self.builder
-                .priv_op(Operation::custom(
-                    self.rev_predicates.sync_del.clone(),
-                    [old_st_rev_sync, st_update, st2, st3],
-                ))
-                .unwrap(),
-        )
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.sync_del.clone(),
+                [old_st_rev_sync, st_update, st2, st3],
+            ))?,
+        ))
+    }
+
+    pub fn st_rev_sync_add_group(
+        &mut self,
+        rev: Dictionary,
+        st_update: Statement,
+        old_st_rev_sync: Statement,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement)> {
+        let st2 = self
+            .builder
+            .priv_op(Operation::dict_contains(op, "name", "add_group"))?;
+        Ok((
+            rev,
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.sync_add_group.clone(),
+                [old_st_rev_sync, st_update, st2],
+            ))?,
+        ))
+    }
+
+    pub fn st_rev_sync_drop_group(
+        &mut self,
+        rev: Dictionary,
+        st_update: Statement,
+        old_st_rev_sync: Statement,
+        op: Dictionary,
+    ) -> Result<(Dictionary, Statement)> {
+        let st2 = self
+            .builder
+            .priv_op(Operation::dict_contains(op, "name", "drop_group"))?;
+        Ok((
+            rev,
+            self.builder.priv_op(Operation::custom(
+                self.rev_predicates.sync_drop_group.clone(),
+                [old_st_rev_sync, st_update, st2],
+            ))?,
+        ))
     }
 
     pub fn st_rev_sync(
@@ -867,48 +1799,96 @@ impl<'a> RevHelper<'a> {
         op: Dictionary,
         st_update: Statement,
         old_st_rev_sync: Statement,
-    ) -> (Dictionary, Statement) {
-        let name = String::try_from(op.get(&Key::from("name")).unwrap().typed()).unwrap();
+    ) -> Result<(Dictionary, Statement)> {
+        check_depth(&self.builder.params, "reverse state", &old_rev)?;
+        check_depth(&self.builder.params, "op", &op)?;
+        let name = op_name(&op)?;
         let st_none = Statement::None;
         let (new, sts) = match name.as_str() {
             "init" => {
                 // rev_sync_init(rev_state, state)
-                let (new, st) = self.st_rev_sync_init(st_update, op);
-                (new, [st, st_none.clone(), st_none.clone()])
+                let (new, st) = self.st_rev_sync_init(st_update, op)?;
+                let sts = [
+                    st,
+                    st_none.clone(),
+                    st_none.clone(),
+                    st_none.clone(),
+                    st_none,
+                ];
+                (new, sts)
             }
             "add" => {
                 // rev_sync_add(rev_state, state)
-                let (new, st) = self.st_rev_sync_add(old_rev, st_update, old_st_rev_sync, op);
-                (new, [st_none.clone(), st, st_none.clone()])
+                let (new, st) = self.st_rev_sync_add(old_rev, st_update, old_st_rev_sync, op)?;
+                let sts = [
+                    st_none.clone(),
+                    st,
+                    st_none.clone(),
+                    st_none.clone(),
+                    st_none,
+                ];
+                (new, sts)
             }
             "del" => {
                 // rev_sync_del(rev_state, state)
-                let (new, st) = self.st_rev_sync_del(old_rev, st_update, old_st_rev_sync, op);
-                (new, [st_none.clone(), st_none.clone(), st])
+                let (new, st) = self.st_rev_sync_del(old_rev, st_update, old_st_rev_sync, op)?;
+                let sts = [
+                    st_none.clone(),
+                    st_none.clone(),
+                    st,
+                    st_none.clone(),
+                    st_none,
+                ];
+                (new, sts)
             }
-            _ => panic!("invalid op.name = {}", name),
+            "add_group" => {
+                // rev_sync_add_group(rev_state, state)
+                let (new, st) =
+                    self.st_rev_sync_add_group(old_rev, st_update, old_st_rev_sync, op)?;
+                let sts = [
+                    st_none.clone(),
+                    st_none.clone(),
+                    st_none.clone(),
+                    st,
+                    st_none,
+                ];
+                (new, sts)
+            }
+            "drop_group" => {
+                // rev_sync_drop_group(rev_state, state)
+                let (new, st) =
+                    self.st_rev_sync_drop_group(old_rev, st_update, old_st_rev_sync, op)?;
+                let sts = [
+                    st_none.clone(),
+                    st_none.clone(),
+                    st_none.clone(),
+                    st_none,
+                    st,
+                ];
+                (new, sts)
+            }
+            _ => bail!("invalid op.name = {}", name),
         };
 
-        (
+        Ok((
             new,
             // rev_sync(rev_state, state)
             self.builder
-                .priv_op(Operation::custom(self.rev_predicates.sync.clone(), sts))
-                .unwrap(),
-        )
+                .priv_op(Operation::custom(self.rev_predicates.sync.clone(), sts))?,
+        ))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use common::params::PodParamsConfig;
     use pod2::{
-        backends::plonky2::mainpod::Prover,
+        backends::plonky2::{mainpod::Prover, mock::mainpod::MockProver},
         frontend::{MainPod, MainPodBuilder},
-        lang::PrettyPrint,
         middleware::{DEFAULT_VD_SET, MainPodProver, Params, VDSet},
     };
 
-    use super::{Group::*, *};
+    use super::*;
 
     #[allow(clippy::too_many_arguments)]
     fn update(
@@ -920,15 +1900,15 @@ mod tests {
         state: Dictionary,
         rev_state: Dictionary,
         op: Op,
+        epoch: i64,
         old_rev_state_pod: Option<MainPod>,
     ) -> (Dictionary, Dictionary, Option<MainPod>) {
         let mut builder = MainPodBuilder::new(params, vd_set);
         let mut helper = Helper::new(&mut builder, predicates);
+        let op = op.into_dict(params, epoch);
 
         // State Pod
-        let (state, st_update) = helper
-            .st_update(state, Dictionary::from(op.clone()))
-            .unwrap();
+        let (state, st_update) = helper.st_update(state, op.clone()).unwrap();
         builder.reveal(&st_update);
 
         let state_pod = builder.prove(prover).unwrap();
@@ -949,8 +1929,9 @@ mod tests {
             Statement::None
         };
         let mut rev_helper = RevHelper::new(&mut builder, predicates, rev_predicates);
-        let (rev_state, rev_st_update) =
-            rev_helper.st_rev_sync(rev_state, Dictionary::from(op), st_update, old_st_rev_sync);
+        let (rev_state, rev_st_update) = rev_helper
+            .st_rev_sync(rev_state, op, st_update, old_st_rev_sync)
+            .unwrap();
         builder.reveal(&rev_st_update);
 
         let rev_state_pod = builder.prove(prover).unwrap();
@@ -964,6 +1945,375 @@ mod tests {
         (state, rev_state, Some(rev_state_pod))
     }
 
+    #[test]
+    fn test_build_predicates() {
+        let predicates = build_predicates(&Params::default());
+        let batch_ids: Vec<_> = predicates.batches.iter().map(|b| b.id()).collect();
+        assert_eq!(batch_ids.iter().collect::<HashSet<_>>().len(), 7);
+        assert_eq!(predicates.sources.len(), batch_ids.len());
+        let (state, rev, kv) = (&predicates.state, &predicates.rev, &predicates.kv);
+        for cpr in [
+            &state.init,
+            &state.add,
+            &state.del,
+            &state.add_group,
+            &state.drop_group,
+            &state.change,
+            &state.step,
+            &state.update,
+            &rev.add_fresh,
+            &rev.add_existing,
+            &rev.add,
+            &rev.del_singleton,
+            &rev.del_else,
+            &rev.del,
+            &rev.sync_init,
+            &rev.sync_add,
+            &rev.sync_del,
+            &rev.sync_add_group,
+            &rev.sync_drop_group,
+            &rev.sync,
+            &kv.add_kv,
+            &kv.del_kv,
+            &kv.change,
+            &kv.step,
+            &kv.update,
+        ] {
+            assert!(batch_ids.contains(&cpr.batch.id()), "{:?}", cpr);
+        }
+    }
+
+    #[test]
+    fn test_helper_errors() {
+        let params = Params::default();
+        let predicates = build_predicates(&params);
+        let depth = params.max_depth_mt_containers;
+        let bogus_op = dict!(depth, {"name" => "mul", "epoch" => 1});
+
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let mut helper = Helper::new(&mut builder, &predicates.state);
+        assert!(
+            helper
+                .st_update(dict!(depth, {}), bogus_op.clone())
+                .is_err()
+        );
+        let mut rev_helper = RevHelper::new(&mut builder, &predicates.state, &predicates.rev);
+        assert!(
+            rev_helper
+                .st_rev_sync(dict!(depth, {}), bogus_op, Statement::None, Statement::None)
+                .is_err()
+        );
+
+        // the statement of an op fails on the ops of another name instead of panicking
+        let mut helper = Helper::new(&mut builder, &predicates.state);
+        let init = Op::Init.into_dict(&params, 1);
+        let add = Op::Add {
+            group: Group::RED,
+            user: UserId::new("alice").unwrap(),
+        }
+        .into_dict(&params, 2);
+        let err = helper.st_init(dict!(depth, {}), add).unwrap_err();
+        assert_eq!(err.to_string(), "invalid op.name = add for init");
+        assert!(helper.st_add_del(dict!(depth, {}), init.clone()).is_err());
+        assert!(helper.st_add_group(dict!(depth, {}), init.clone()).is_err());
+        assert!(
+            helper
+                .st_drop_group(dict!(depth, {}), init.clone())
+                .is_err()
+        );
+        assert!(helper.st_add_del_kv(dict!(depth, {}), init).is_err());
+
+        // containers of another depth than the params
+        let mut helper = Helper::new(&mut builder, &predicates.state);
+        let op = Op::Init.into_dict(&params, 1);
+        assert!(helper.st_update(dict!(depth + 1, {}), op.clone()).is_err());
+        let red = Set::new(depth - 1, HashSet::new()).unwrap();
+        let state = dict!(depth, {"red" => red, "epoch" => 1});
+        let err = check_depth(&params, "state", &state).unwrap_err();
+        assert!(
+            err.to_string().starts_with("state.red has depth"),
+            "{}",
+            err
+        );
+        assert!(helper.st_update(state, op).is_err());
+
+        // too few statements for the init op
+        let params = Params {
+            max_statements: params.max_public_statements + 2,
+            ..params
+        };
+        let predicates = build_predicates(&params);
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let mut helper = Helper::new(&mut builder, &predicates.state);
+        let res = helper.st_update(dict!(depth, {}), Op::Init.into_dict(&params, 1));
+        assert!(res.is_err() || builder.prove(&MockProver {}).is_err());
+    }
+
+    #[test]
+    fn test_genesis() -> Result<()> {
+        let params = Params::default();
+        let depth = params.max_depth_mt_containers;
+        // the default reproduces the red, green and blue init state
+        assert_eq!("red, green,blue".parse::<Genesis>()?, Genesis::default());
+        assert_eq!(
+            build_predicates(&params).state.init,
+            build_predicates_with(&params, &Genesis::default())
+                .state
+                .init
+        );
+        assert!("red,red".parse::<Genesis>().is_err());
+        assert!("red,epoch".parse::<Genesis>().is_err());
+
+        let genesis: Genesis = "alpha,beta".parse()?;
+        let predicates = build_predicates_with(&params, &genesis);
+        assert_ne!(predicates.state.init, build_predicates(&params).state.init);
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let mut helper = Helper::new(&mut builder, &predicates.state);
+        let (state, st_update) =
+            helper.st_update(dict!(depth, {}), Op::Init.into_dict(&params, 1))?;
+        builder.reveal(&st_update);
+        builder.prove(&MockProver {})?.pod.verify()?;
+        for group in ["alpha", "beta"] {
+            assert_eq!(state.get(&Key::from(group))?.raw(), EMPTY_VALUE);
+        }
+        assert!(state.get(&Key::from(Group::RED.as_str())).is_err());
+
+        // an init state of other groups doesn't satisfy the predicate
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let predicates = Predicates {
+            genesis: Genesis::default(),
+            ..predicates.state
+        };
+        let mut helper = Helper::new(&mut builder, &predicates);
+        let res = helper.st_update(dict!(depth, {}), Op::Init.into_dict(&params, 1));
+        assert!(res.is_err() || builder.prove(&MockProver {}).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kv() -> Result<()> {
+        let params = Params::default();
+        let predicates = build_predicates(&params);
+        let depth = params.max_depth_mt_containers;
+        let alice = UserId::new("alice")?;
+        let purple = Group::new("purple")?;
+
+        let mut state = dict!(depth, {});
+        let ops = [
+            Op::Init,
+            Op::AddKv {
+                group: Group::RED,
+                user: alice.clone(),
+                value: 42,
+            },
+            Op::AddGroup {
+                group: purple.clone(),
+            },
+            Op::AddKv {
+                group: purple.clone(),
+                user: alice.clone(),
+                value: 7,
+            },
+            Op::DelKv {
+                group: purple.clone(),
+                user: alice.clone(),
+            },
+            Op::DropGroup {
+                group: purple.clone(),
+            },
+        ];
+        for (epoch, op) in (1..).zip(ops.clone()) {
+            let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+            let mut helper = Helper::new_kv(&mut builder, &predicates.state, &predicates.kv);
+            let (new_state, st_update) = helper.st_update(state, op.into_dict(&params, epoch))?;
+            builder.reveal(&st_update);
+            let pod = builder.prove(&MockProver {})?;
+            pod.pod.verify()?;
+            assert!(matches!(
+                &st_update,
+                Statement::Custom(cpr, _) if *cpr == predicates.kv.update
+            ));
+            state = new_state;
+        }
+        let applied = apply_ops(&predicates.state.genesis, true, dict!(depth, {}), &ops)?;
+        assert_eq!(applied.commitment(), state.commitment());
+        let red = state.get(&Key::from(Group::RED.as_str()))?;
+        match red.typed() {
+            TypedValue::Dictionary(red) => {
+                assert_eq!(red.get(&Key::from("alice"))?, &Value::from(42i64))
+            }
+            _ => panic!("red is not a dictionary: {:?}", red),
+        }
+        assert!(state.get(&Key::from(purple.as_str())).is_err());
+
+        // a user is added once, and the set ops don't apply to the kv lists nor the other way
+        // around
+        let add_kv = Op::AddKv {
+            group: Group::RED,
+            user: alice.clone(),
+            value: 1,
+        };
+        let add = Op::Add {
+            group: Group::GREEN,
+            user: alice,
+        };
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let mut helper = Helper::new_kv(&mut builder, &predicates.state, &predicates.kv);
+        for op in [add_kv.clone(), add] {
+            assert!(
+                helper
+                    .st_update(state.clone(), op.into_dict(&params, 7))
+                    .is_err()
+            );
+        }
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let mut helper = Helper::new(&mut builder, &predicates.state);
+        assert!(
+            helper
+                .st_update(state.clone(), add_kv.into_dict(&params, 7))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_del_after_add_group() -> Result<()> {
+        // the state batch imports the predicates of the change batch by position, a wrong import
+        // only shows once the pods of the add and del ops are proven
+        let params = Params::default();
+        let predicates = build_predicates(&params);
+        let depth = params.max_depth_mt_containers;
+        let purple = Group::new("purple")?;
+        let alice = UserId::new("alice")?;
+        let mut state = dict!(depth, {});
+        for (epoch, op) in (1..).zip([
+            Op::Init,
+            Op::AddGroup {
+                group: purple.clone(),
+            },
+            Op::Add {
+                group: purple.clone(),
+                user: alice.clone(),
+            },
+            Op::Add {
+                group: Group::RED,
+                user: alice.clone(),
+            },
+            Op::Del {
+                group: purple.clone(),
+                user: alice.clone(),
+            },
+        ]) {
+            let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+            let mut helper = Helper::new(&mut builder, &predicates.state);
+            let (new_state, st_update) = helper.st_update(state, op.into_dict(&params, epoch))?;
+            builder.reveal(&st_update);
+            let pod = builder.prove(&MockProver {})?;
+            pod.pod.verify()?;
+            assert!(matches!(
+                &st_update,
+                Statement::Custom(cpr, _) if *cpr == predicates.state.update
+            ));
+            state = new_state;
+        }
+        // alice is only left in red, and the added group stays empty
+        let purple = state.get(&Key::from(purple.as_str()))?;
+        assert_eq!(purple, &Value::from(Set::new(depth, HashSet::new())?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_batch() -> Result<()> {
+        let params = Params::default();
+        let predicates = build_predicates(&params);
+        let depth = params.max_depth_mt_containers;
+        let ops = [
+            Op::Init,
+            Op::Add {
+                group: Group::RED,
+                user: UserId::new("alice")?,
+            },
+        ];
+        let op_dicts = || {
+            (1..)
+                .zip(ops.clone())
+                .map(|(epoch, op)| op.into_dict(&params, epoch))
+        };
+
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let mut helper = Helper::new(&mut builder, &predicates.state);
+        let steps = helper.st_update_batch(dict!(depth, {}), op_dicts())?;
+        for (_, st) in &steps {
+            builder.reveal(st);
+        }
+        let pod = builder.prove(&MockProver {})?;
+        pod.pod.verify()?;
+
+        // every step is the update proven alone
+        let mut state = dict!(depth, {});
+        for (op, (new_state, _)) in op_dicts().zip(&steps) {
+            let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+            let mut helper = Helper::new(&mut builder, &predicates.state);
+            state = helper.st_update(state, op)?.0;
+            assert_eq!(state.commitment(), new_state.commitment());
+        }
+        assert_eq!(steps.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_app_depth() -> Result<()> {
+        // the whole pipeline with containers shallower than the default ones
+        let params = PodParamsConfig {
+            max_depth_mt_containers: Some(16),
+            ..Default::default()
+        }
+        .params()?;
+        let AppPredicates {
+            state: state_predicates,
+            rev: rev_predicates,
+            ..
+        } = build_predicates(&params);
+        let depth = params.max_depth_mt_containers;
+        let ops = [
+            Op::Init,
+            Op::Add {
+                group: Group::RED,
+                user: UserId::new("alice")?,
+            },
+            Op::Add {
+                group: Group::BLUE,
+                user: UserId::new("bob")?,
+            },
+            Op::Del {
+                group: Group::RED,
+                user: UserId::new("alice")?,
+            },
+        ];
+        let (mut state, mut rev_state, mut rev_state_pod) =
+            (dict!(depth, {}), dict!(depth, {}), None);
+        for (epoch, op) in (1..).zip(ops.clone()) {
+            (state, rev_state, rev_state_pod) = update(
+                &params,
+                &DEFAULT_VD_SET,
+                &MockProver {},
+                &state_predicates,
+                &rev_predicates,
+                state,
+                rev_state,
+                op,
+                epoch,
+                rev_state_pod,
+            );
+        }
+        check_depth(&params, "state", &state)?;
+        check_depth(&params, "rev_state", &rev_state)?;
+        assert!(check_depth(&Params::default(), "state", &state).is_err());
+        let applied = apply_ops(&state_predicates.genesis, false, dict!(depth, {}), &ops)?;
+        assert_eq!(applied.commitment(), state.commitment());
+        Ok(())
+    }
+
     #[test]
     fn test_app() {
         env_logger::init();
@@ -971,39 +2321,69 @@ mod tests {
         let (vd_set, prover) = (&*DEFAULT_VD_SET, &Prover {});
 
         let params = Params::default();
-        let (state_predicates, rev_predicates) = build_predicates(&params);
+        let AppPredicates {
+            state: state_predicates,
+            rev: rev_predicates,
+            ..
+        } = build_predicates(&params);
 
         // Initial state
-        let mut state = dict!({});
-        let mut rev_state = dict!({});
+        let depth = params.max_depth_mt_containers;
+        let mut state = dict!(depth, {});
+        let mut rev_state = dict!(depth, {});
         println!(
             "# state\n:{}",
             Value::from(state.clone()).to_podlang_string()
         );
         let mut rev_state_pod = None;
-        for op in [
+        let purple = Group::new("purple").unwrap();
+        let yellow = Group::new("yellow").unwrap();
+        let ops = [
             Op::Init,
             Op::Add {
-                group: Red,
-                user: "alice".to_string(),
+                group: Group::RED,
+                user: UserId::new("alice").unwrap(),
             },
             Op::Add {
-                group: Blue,
-                user: "alice".to_string(),
+                group: Group::BLUE,
+                user: UserId::new("alice").unwrap(),
             },
             Op::Add {
-                group: Blue,
-                user: "bob".to_string(),
+                group: Group::BLUE,
+                user: UserId::new("bob").unwrap(),
             },
             Op::Add {
-                group: Red,
-                user: "carol".to_string(),
+                group: Group::RED,
+                user: UserId::new("carol").unwrap(),
             },
             Op::Del {
-                group: Red,
-                user: "alice".to_string(),
+                group: Group::RED,
+                user: UserId::new("alice").unwrap(),
             },
-        ] {
+            Op::AddGroup {
+                group: purple.clone(),
+            },
+            Op::Add {
+                group: purple.clone(),
+                user: UserId::new("bob").unwrap(),
+            },
+            Op::AddGroup {
+                group: yellow.clone(),
+            },
+            Op::DropGroup {
+                group: yellow.clone(),
+            },
+        ];
+        for (epoch, op) in (1..).zip(ops.clone()) {
+            let old_rev_state = rev_state.clone();
+            let group_op = matches!(op, Op::AddGroup { .. } | Op::DropGroup { .. });
+            let applied = apply_ops(
+                &state_predicates.genesis,
+                false,
+                state.clone(),
+                std::slice::from_ref(&op),
+            )
+            .unwrap();
             (state, rev_state, rev_state_pod) = update(
                 &params,
                 vd_set,
@@ -1013,8 +2393,162 @@ mod tests {
                 state,
                 rev_state,
                 op,
+                epoch,
                 rev_state_pod,
             );
+            // the groups that are added or dropped are empty
+            if group_op {
+                assert_eq!(rev_state, old_rev_state);
+            }
+            // the pure replay reaches the proven state
+            assert_eq!(applied.commitment(), state.commitment());
+        }
+        let applied = apply_ops(&state_predicates.genesis, false, dict!(depth, {}), &ops).unwrap();
+        assert_eq!(applied.commitment(), state.commitment());
+        // and refuses the ops the proving path refuses
+        for op in [
+            Op::Init,
+            Op::AddGroup { group: Group::RED },
+            Op::DropGroup {
+                group: purple.clone(),
+            },
+            Op::Add {
+                group: purple.clone(),
+                user: UserId::new("bob").unwrap(),
+            },
+            Op::AddKv {
+                group: Group::RED,
+                user: UserId::new("dave").unwrap(),
+                value: 1,
+            },
+        ] {
+            assert!(apply_ops(&state_predicates.genesis, false, state.clone(), &[op]).is_err());
+        }
+        let purple_set = state.get(&Key::from(purple.as_str())).unwrap();
+        assert!(
+            set_from_value(purple_set)
+                .unwrap()
+                .contains(&Value::from("bob"))
+        );
+        assert!(state.get(&Key::from(yellow.as_str())).is_err());
+
+        // an existing group can't be added again
+        let mut builder = MainPodBuilder::new(&params, vd_set);
+        let mut helper = Helper::new(&mut builder, &state_predicates);
+        for group in [Group::RED, purple.clone()] {
+            let op = Op::AddGroup { group };
+            assert!(
+                helper
+                    .st_update(state.clone(), op.into_dict(&params, 11))
+                    .is_err()
+            );
+        }
+        // and only an existing empty group can be dropped
+        for group in [purple, yellow] {
+            let op = Op::DropGroup { group };
+            assert!(
+                helper
+                    .st_update(state.clone(), op.into_dict(&params, 11))
+                    .is_err()
+            );
+        }
+        // op dictionaries not built from an `Op`, with an invalid group or user
+        let depth = params.max_depth_mt_containers;
+        for (group, user) in [
+            ("red", "alice bob"),
+            ("red", "alice\n"),
+            ("red green", "alice"),
+        ] {
+            let op =
+                dict!(depth, {"name" => "add", "group" => group, "user" => user, "epoch" => 11});
+            assert!(helper.st_update(state.clone(), op).is_err());
+        }
+
+        // an op that doesn't follow the epoch of the state, like a replayed one, can't be proven
+        let mut builder = MainPodBuilder::new(&params, vd_set);
+        let mut helper = Helper::new(&mut builder, &state_predicates);
+        let op = Op::Add {
+            group: Group::RED,
+            user: UserId::new("alice").unwrap(),
+        };
+        assert!(
+            helper
+                .st_update(state.clone(), op.clone().into_dict(&params, 10))
+                .is_err()
+        );
+        assert!(
+            helper
+                .st_update(state.clone(), op.clone().into_dict(&params, 11))
+                .is_ok()
+        );
+
+        // a single pod proving the update and the membership of the added user
+        let mut builder = MainPodBuilder::new(&params, vd_set);
+        let mut helper = Helper::new(&mut builder, &state_predicates);
+        let (new_state, st_update, sts_membership) = helper
+            .st_update_with_membership(state.clone(), op.into_dict(&params, 11))
+            .unwrap();
+        for st in [&st_update, &sts_membership[0], &sts_membership[1]] {
+            builder.reveal(st);
+        }
+        let pod = builder.prove(prover).unwrap();
+        pod.pod.verify().unwrap();
+        let pub_statements = pod.pod.pub_statements();
+        for st in [&st_update, &sts_membership[0], &sts_membership[1]] {
+            assert!(pub_statements.contains(st), "{:?}", st);
+        }
+        let red = new_state.get(&Key::from("red")).unwrap();
+        assert!(set_from_value(red).unwrap().contains(&Value::from("alice")));
+        // only for ops that add a user
+        let mut builder = MainPodBuilder::new(&params, vd_set);
+        let mut helper = Helper::new(&mut builder, &state_predicates);
+        let op = Op::Del {
+            group: Group::RED,
+            user: UserId::new("carol").unwrap(),
+        };
+        assert!(
+            helper
+                .st_update_with_membership(state, op.into_dict(&params, 11))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_group() {
+        assert_eq!(Group::new("red").unwrap(), Group::RED);
+        assert_eq!(Group::from_str("purple").unwrap().as_str(), "purple");
+        assert_eq!(String::from(Group::BLUE), "blue");
+        // the key of the epoch in the state
+        for group in ["", "epoch", "op.group", "red green"] {
+            assert!(Group::new(group).is_err(), "{:?}", group);
+        }
+    }
+
+    #[test]
+    fn test_user_id() {
+        for user in [
+            "alice",
+            "user-1",
+            "user_2",
+            "A",
+            "a".repeat(USER_ID_MAX_LEN).as_str(),
+        ] {
+            assert_eq!(UserId::new(user).unwrap().as_str(), user);
+        }
+        // the precomposed and decomposed encodings of "é" are both rejected rather than mapped to
+        // different users
+        for user in [
+            "",
+            "a".repeat(USER_ID_MAX_LEN + 1).as_str(),
+            "op.user",
+            "alice bob",
+            "alice/bob",
+            "caf\u{e9}",
+            "cafe\u{301}",
+            "name",
+            "epoch",
+        ] {
+            assert!(UserId::new(user).is_err(), "{:?}", user);
         }
     }
 }
@@ -0,0 +1,80 @@
+#![allow(clippy::uninlined_format_args)]
+
+use anyhow::Result;
+use app::{Group, UserId, counter};
+use clap::{Parser, ValueEnum};
+use common::params::PodParamsConfig;
+use pod2::{
+    backends::plonky2::mainpod::Prover,
+    frontend::MainPodBuilder,
+    lang::PrettyPrint,
+    middleware::{DEFAULT_VD_SET, Params, Value, containers::Dictionary},
+};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum AppKind {
+    Counter,
+    Membership,
+}
+
+/// Proves a few example updates of an app and prints the pods
+#[derive(Parser)]
+struct Cli {
+    #[arg(long, value_enum, default_value_t = AppKind::Membership)]
+    app: AppKind,
+}
+
+fn run_counter(params: &Params) -> Result<()> {
+    let predicates = counter::build_predicates(params);
+    let mut state = 0;
+    for op in [counter::Op::Inc { n: 3 }, counter::Op::Inc { n: 4 }] {
+        let mut builder = MainPodBuilder::new(params, &DEFAULT_VD_SET);
+        let mut helper = counter::Helper::new(&mut builder, &predicates);
+        let (new_state, st_update) = helper.st_update(state, op.into_dict(params))?;
+        builder.reveal(&st_update);
+        let pod = builder.prove(&Prover {})?;
+        println!("# pod\n:{}", pod);
+        println!("# state\n:{}", new_state);
+        state = new_state;
+    }
+    Ok(())
+}
+
+fn run_membership(params: &Params) -> Result<()> {
+    let predicates = app::build_predicates(params);
+    let mut state = Dictionary::new(params.max_depth_mt_containers, Default::default())?;
+    for (epoch, op) in (1..).zip([
+        app::Op::Init,
+        app::Op::Add {
+            group: Group::RED,
+            user: UserId::new("alice")?,
+        },
+        app::Op::Del {
+            group: Group::RED,
+            user: UserId::new("alice")?,
+        },
+    ]) {
+        let mut builder = MainPodBuilder::new(params, &DEFAULT_VD_SET);
+        let mut helper = app::Helper::new(&mut builder, &predicates.state);
+        let (new_state, st_update) = helper.st_update(state, op.into_dict(params, epoch))?;
+        builder.reveal(&st_update);
+        let pod = builder.prove(&Prover {})?;
+        println!("# pod\n:{}", pod);
+        println!(
+            "# state\n:{}",
+            Value::from(new_state.clone()).to_podlang_string()
+        );
+        state = new_state;
+    }
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    env_logger::init();
+    let cli = Cli::parse();
+    let params = PodParamsConfig::from_env()?.params()?;
+    match cli.app {
+        AppKind::Counter => run_counter(&params),
+        AppKind::Membership => run_membership(&params),
+    }
+}
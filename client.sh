@@ -9,12 +9,23 @@ usage () {
 	echo "ARGS:"
 	echo "    request_get REQ_ID"
 	echo "    membership_list_get AD_ID"
+	echo "    membership_list_costs_get AD_ID"
+	echo "    membership_list_diff_get AD_ID FROM_NUM TO_NUM"
 	echo "    membership_list_create"
 	echo "    membership_list_update AD_ID OP"
 	echo "    user_get AD_ID USER"
+	echo "    user_all_get USER [AFTER_ID]"
+	echo "    snapshot_get AD_ID"
+	echo "    snapshot_post FILE"
+	echo "    admin_pod_get NAME"
 }
 
 CURL_OPTS="--silent"
+# token for the mutating endpoints when the server is started with AUTH_TOKEN
+AUTH_OPTS=()
+if [[ -n "$AUTH_TOKEN" ]]; then
+	AUTH_OPTS=(-H "Authorization: Bearer $AUTH_TOKEN")
+fi
 
 wait_complete=false
 if [[ "$1" == "--wait-complete" ]]; then
@@ -33,23 +44,55 @@ case "$1" in
 		resp=$(curl $CURL_OPTS -X GET "$BASE_URL/membership_list/$ad_id")
 		wait_complete=false
 		;;
+	membership_list_costs_get)
+		ad_id=$2
+		resp=$(curl $CURL_OPTS -X GET "$BASE_URL/membership_list/$ad_id/costs")
+		wait_complete=false
+		;;
+	membership_list_diff_get)
+		ad_id=$2
+		from=$3
+		to=$4
+		resp=$(curl $CURL_OPTS -X GET "$BASE_URL/membership_list/$ad_id/diff?from=$from&to=$to")
+		wait_complete=false
+		;;
 	membership_list_create)
-		resp=$(curl $CURL_OPTS -X POST "$BASE_URL/membership_list")
+		resp=$(curl $CURL_OPTS "${AUTH_OPTS[@]}" -X POST "$BASE_URL/membership_list")
 		;;
 	membership_list_update)
 		ad_id=$2
 		op=$3
-		resp=$(curl $CURL_OPTS --json "$op" "$BASE_URL/membership_list/$ad_id")
+		resp=$(curl $CURL_OPTS "${AUTH_OPTS[@]}" --json "$op" "$BASE_URL/membership_list/$ad_id")
 		;;
 	user_get)
 		ad_id=$2
 		user=$3
 		resp=$(curl $CURL_OPTS -X GET "$BASE_URL/user/$ad_id/$user")
 		;;
+	user_all_get)
+		user=$2
+		after_id=${3:-0}
+		resp=$(curl $CURL_OPTS -X GET "$BASE_URL/user/$user?after_id=$after_id")
+		;;
 	reverse_membership_list_pod_get)
 		ad_id=$2
 		resp=$(curl $CURL_OPTS -X GET "$BASE_URL/reverse_membership_list_pod/$ad_id")
 		;;
+	snapshot_get)
+		ad_id=$2
+		resp=$(curl $CURL_OPTS "${AUTH_OPTS[@]}" -X GET "$BASE_URL/snapshot/$ad_id")
+		wait_complete=false
+		;;
+	snapshot_post)
+		file=$2
+		resp=$(curl $CURL_OPTS "${AUTH_OPTS[@]}" --json "@$file" "$BASE_URL/snapshot")
+		wait_complete=false
+		;;
+	admin_pod_get)
+		name=$2
+		resp=$(curl $CURL_OPTS "${AUTH_OPTS[@]}" -X GET "$BASE_URL/admin/pod/$name")
+		wait_complete=false
+		;;
 	*)
 		usage
 		exit 1
@@ -0,0 +1,81 @@
+//! Types of the ad-server API responses that its clients decode, shared by the server and
+//! `ad-client` so that both sides agree on their serialization.
+
+use alloy_primitives::Address;
+use hex::ToHex;
+use pod2::backends::plonky2::primitives::merkletree::MerkleClaimAndProof;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Response of the requests that go through the queue, whose state is then polled in
+/// `GET /request/{req_id}`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueueResp {
+    pub req_id: Uuid,
+}
+
+/// On chain cost of the tx that posted a payload, in wei
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxCostInfo {
+    pub gas_used: u64,
+    // price per gas paid, base fee plus priority fee
+    pub effective_gas_price: u128,
+    // 0 for payloads sent as calldata
+    pub blob_gas_used: u64,
+    pub blob_gas_price: u128,
+    // execution fee plus blob fee
+    pub total_fee: u128,
+    // signer of the tx, none for the mock sends of the test mode and the costs recorded before
+    // the signers were rotated
+    #[serde(default)]
+    pub sender: Option<Address>,
+}
+
+impl TxCostInfo {
+    pub fn new(
+        gas_used: u64,
+        effective_gas_price: u128,
+        blob_gas_used: u64,
+        blob_gas_price: u128,
+    ) -> Self {
+        Self {
+            gas_used,
+            effective_gas_price,
+            blob_gas_used,
+            blob_gas_price,
+            total_fee: gas_used as u128 * effective_gas_price
+                + blob_gas_used as u128 * blob_gas_price,
+            sender: None,
+        }
+    }
+}
+
+/// Merkle proof of the groups of a user against the reverse index of a membership list, as
+/// returned by the queries.  The values are hex encoded like the commitments of the other
+/// responses, so that clients don't depend on the serialization of the pod2 types: `key` is the
+/// raw value of the user string, `value` the commitment of the set of groups and `root` the
+/// commitment of the reverse index.  `siblings` are the hashes along the path of `key`, from the
+/// root down to the leaf.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueryProofResponse {
+    pub root: String,
+    pub key: String,
+    pub value: String,
+    pub siblings: Vec<String>,
+}
+
+impl From<&MerkleClaimAndProof> for QueryProofResponse {
+    fn from(proof: &MerkleClaimAndProof) -> Self {
+        Self {
+            root: proof.root.encode_hex::<String>(),
+            key: proof.key.encode_hex::<String>(),
+            value: proof.value.encode_hex::<String>(),
+            siblings: proof
+                .proof
+                .siblings
+                .iter()
+                .map(|sibling| sibling.encode_hex::<String>())
+                .collect(),
+        }
+    }
+}
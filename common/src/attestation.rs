@@ -0,0 +1,327 @@
+//! Attestations of the view of a synchronizer, for the light clients that don't verify the update
+//! proofs themselves: a JSON document stating that, as of the last slot the synchronizer indexed,
+//! an AD has the state of its latest update, along with the blobs of all its updates.
+//!
+//! The updates are chained from the create of the AD (num 0): each one carries the hash of its
+//! fields and of the link of the previous one, so that a client that cached an attestation can
+//! check that a later one extends it by comparing the links at the num of its cached one.  The
+//! updates pruned by the synchronizer aren't listed, and the update after them carries the link
+//! the synchronizer stored at its checkpoint, so the links don't change once pruned.  The
+//! synchronizer signs the document with its ed25519 key (`ATTESTATION_PRIV_KEY`) if it has one,
+//! and only a signed attestation can be trusted.
+
+use alloy_primitives::B256;
+use anyhow::{Context, Result, anyhow, bail};
+use ed25519_dalek::{Signature, Signer, Verifier};
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+use pod2::middleware::{EMPTY_HASH, Hash, RawValue, hash_str};
+use serde::{Deserialize, Serialize};
+
+/// Predicate the updates of the AD are proven with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestedPredicate {
+    pub batch_id: Hash,
+    pub index: usize,
+}
+
+/// Update of an AD: its state after the update `num`, published in the blob
+/// `blob_versioned_hash`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestedUpdate {
+    pub num: i64,
+    pub state: RawValue,
+    pub blob_versioned_hash: B256,
+    // slot of the blob, none for the payloads published as calldata
+    pub slot: Option<i64>,
+    // hash of the fields above and of the link of the previous update
+    pub link: Hash,
+    // first num of the pruned updates right before this one, if any, whose links chain to `link`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pruned_from: Option<i64>,
+}
+
+impl AttestedUpdate {
+    /// Returns the update chained after the one whose link is `prev`, `EMPTY_HASH` for the first
+    /// one.
+    pub fn chain(
+        prev: Hash,
+        num: i64,
+        state: RawValue,
+        blob_versioned_hash: B256,
+        slot: Option<i64>,
+    ) -> Self {
+        let mut update = Self {
+            num,
+            state,
+            blob_versioned_hash,
+            slot,
+            link: EMPTY_HASH,
+            pruned_from: None,
+        };
+        update.link = update.link_after(prev);
+        update
+    }
+
+    /// Returns the update listed after the pruned ones `pruned_from..num`, with the `link` it got
+    /// when it was chained after them.
+    pub fn after_pruned(
+        pruned_from: i64,
+        num: i64,
+        state: RawValue,
+        blob_versioned_hash: B256,
+        slot: Option<i64>,
+        link: Hash,
+    ) -> Self {
+        Self {
+            num,
+            state,
+            blob_versioned_hash,
+            slot,
+            link,
+            pruned_from: Some(pruned_from),
+        }
+    }
+
+    fn link_after(&self, prev: Hash) -> Hash {
+        let fields = (
+            prev,
+            self.num,
+            self.state,
+            self.blob_versioned_hash,
+            self.slot,
+        );
+        hash_str(&serde_json::to_string(&fields).expect("serialize link fields"))
+    }
+}
+
+/// Signed part of an attestation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationBody {
+    pub ad_id: Hash,
+    // what the synchronizer verified the updates against
+    pub custom_predicate_ref: AttestedPredicate,
+    pub vds_root: Hash,
+    // last slot indexed by the synchronizer, none before the first one
+    pub slot: Option<u64>,
+    // latest update of the AD
+    pub num: i64,
+    pub state: RawValue,
+    // updates in num order from the creation of the AD (num 0), without the ones pruned by the
+    // synchronizer
+    pub updates: Vec<AttestedUpdate>,
+}
+
+impl AttestationBody {
+    /// Bytes the signature is made over: the JSON serialization of the body, whose fields are
+    /// serialized in declaration order.
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("serialize attestation body")
+    }
+}
+
+/// ed25519 signature of an attestation, hex encoded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttestationSignature {
+    pub public_key: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    #[serde(flatten)]
+    pub body: AttestationBody,
+    // none if the synchronizer has no attestation key
+    pub signature: Option<AttestationSignature>,
+}
+
+impl Attestation {
+    /// Signs `body` with `key`, if any.
+    pub fn new(body: AttestationBody, key: Option<&SigningKey>) -> Self {
+        let signature = key.map(|key| AttestationSignature {
+            public_key: hex::encode(key.verifying_key().as_bytes()),
+            signature: hex::encode(key.sign(&body.canonical_bytes()).to_bytes()),
+        });
+        Self { body, signature }
+    }
+}
+
+/// Parses a hex encoded ed25519 secret key, with or without `0x`.
+pub fn signing_key_from_hex(s: &str) -> Result<SigningKey> {
+    let bytes: [u8; 32] = hex::decode(s.trim_start_matches("0x"))
+        .context("attestation key is not hex")?
+        .try_into()
+        .map_err(|bytes: Vec<u8>| anyhow!("attestation key has {} bytes, not 32", bytes.len()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Checks that the updates of `attestation` are chained from the create of the AD, and that its
+/// latest update is the last one.  It says nothing of who made the attestation, see
+/// `verify_attestation`.
+pub fn check_attestation_updates(attestation: &Attestation) -> Result<()> {
+    let body = &attestation.body;
+    let mut prev = EMPTY_HASH;
+    let mut prev_num = -1;
+    for update in &body.updates {
+        match update.pruned_from {
+            // the links of the pruned updates can't be checked, the link of this one is the one
+            // it got after them
+            Some(pruned_from) if prev_num < pruned_from && pruned_from < update.num => {}
+            Some(_) => bail!("update {} has an invalid pruned range", update.num),
+            None if update.num != prev_num + 1 => {
+                bail!("update {} doesn't follow update {}", update.num, prev_num)
+            }
+            None if update.link_after(prev) != update.link => {
+                bail!("link of the update {} doesn't match its fields", update.num)
+            }
+            None => {}
+        }
+        prev = update.link;
+        prev_num = update.num;
+    }
+    let Some(last) = body.updates.last() else {
+        bail!("attestation has no update");
+    };
+    if (last.num, last.state) != (body.num, body.state) {
+        bail!("latest state isn't the one of the last update {}", last.num);
+    }
+    Ok(())
+}
+
+/// Checks that `attestation` is signed by `public_key`, the key of a trusted synchronizer, and
+/// that its updates are chained as in `check_attestation_updates`.
+pub fn verify_attestation(attestation: &Attestation, public_key: &VerifyingKey) -> Result<()> {
+    check_attestation_updates(attestation)?;
+    let Some(signature) = &attestation.signature else {
+        bail!("attestation isn't signed");
+    };
+    let bytes: [u8; 32] = hex::decode(&signature.public_key)?
+        .try_into()
+        .map_err(|_| anyhow!("public key isn't 32 bytes"))?;
+    if VerifyingKey::from_bytes(&bytes)? != *public_key {
+        bail!("attestation is signed by another key");
+    }
+    let bytes: [u8; 64] = hex::decode(&signature.signature)?
+        .try_into()
+        .map_err(|_| anyhow!("signature isn't 64 bytes"))?;
+    public_key
+        .verify(
+            &attestation.body.canonical_bytes(),
+            &Signature::from_bytes(&bytes),
+        )
+        .context("invalid attestation signature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn updates(n: i64) -> Vec<AttestedUpdate> {
+        let mut updates: Vec<AttestedUpdate> = Vec::new();
+        for num in 0..n {
+            let prev = updates.last().map_or(EMPTY_HASH, |update| update.link);
+            updates.push(AttestedUpdate::chain(
+                prev,
+                num,
+                RawValue::from(num * 10),
+                B256::repeat_byte(num as u8),
+                Some(100 + num),
+            ));
+        }
+        updates
+    }
+
+    fn attestation(key: Option<&SigningKey>) -> Attestation {
+        let updates = updates(3);
+        let body = AttestationBody {
+            ad_id: hash_str("ad"),
+            custom_predicate_ref: AttestedPredicate {
+                batch_id: hash_str("batch"),
+                index: 1,
+            },
+            vds_root: hash_str("vds"),
+            slot: Some(110),
+            num: 2,
+            state: RawValue::from(20),
+            updates,
+        };
+        Attestation::new(body, key)
+    }
+
+    #[test]
+    fn test_verify_attestation() -> Result<()> {
+        let key = signing_key_from_hex(&"07".repeat(32))?;
+        let other = SigningKey::from_bytes(&[8; 32]);
+        let signed = attestation(Some(&key));
+        verify_attestation(&signed, &key.verifying_key())?;
+        assert!(verify_attestation(&signed, &other.verifying_key()).is_err());
+        // the JSON document verifies once parsed back
+        let json = serde_json::to_string(&signed)?;
+        verify_attestation(&serde_json::from_str(&json)?, &key.verifying_key())?;
+
+        // unsigned, well formed but not trusted
+        let unsigned = attestation(None);
+        check_attestation_updates(&unsigned)?;
+        assert!(verify_attestation(&unsigned, &key.verifying_key()).is_err());
+
+        // a tampered entry breaks its link, even in an unsigned attestation
+        let mut tampered = unsigned.clone();
+        tampered.body.updates[1].state = RawValue::from(11);
+        assert!(check_attestation_updates(&tampered).is_err());
+        // relinking it breaks the link of the next entry
+        let prev = tampered.body.updates[0].link;
+        tampered.body.updates[1].link = tampered.body.updates[1].link_after(prev);
+        assert!(check_attestation_updates(&tampered).is_err());
+        // and a fully relinked chain breaks the signature
+        let mut tampered = signed.clone();
+        tampered.body.updates[1].state = RawValue::from(11);
+        for i in 1..3 {
+            let prev = tampered.body.updates[i - 1].link;
+            tampered.body.updates[i].link = tampered.body.updates[i].link_after(prev);
+        }
+        check_attestation_updates(&tampered)?;
+        let err = verify_attestation(&tampered, &key.verifying_key()).unwrap_err();
+        assert!(err.to_string().contains("signature"), "{}", err);
+
+        // a latest state that isn't the last update
+        let mut tampered = signed;
+        tampered.body.num = 1;
+        assert!(check_attestation_updates(&tampered).is_err());
+        // the chain starts at the create, and leaves no update out
+        let mut tampered = unsigned.clone();
+        tampered.body.updates.remove(0);
+        assert!(check_attestation_updates(&tampered).is_err());
+        let mut tampered = unsigned;
+        tampered.body.updates.remove(1);
+        assert!(check_attestation_updates(&tampered).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_attestation_pruned() -> Result<()> {
+        // the updates 1..4 pruned: the update 4 keeps the link it had after them
+        let full = updates(6);
+        let update = &full[4];
+        let mut pruned = vec![
+            full[0].clone(),
+            AttestedUpdate::after_pruned(
+                1,
+                update.num,
+                update.state,
+                update.blob_versioned_hash,
+                update.slot,
+                update.link,
+            ),
+        ];
+        pruned.push(full[5].clone());
+        let mut attestation = attestation(None);
+        attestation.body.num = 5;
+        attestation.body.state = full[5].state;
+        attestation.body.updates = pruned;
+        check_attestation_updates(&attestation)?;
+
+        // a pruned range that doesn't end before its update
+        attestation.body.updates[1].pruned_from = Some(4);
+        assert!(check_attestation_updates(&attestation).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File, create_dir_all, rename},
+    hash::{Hash, Hasher},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use alloy_primitives::{B256, keccak256};
+use anyhow::Result;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tracing::{info, warn};
+
+// lock file of the workspace, which pins the pod2 revision
+const CARGO_LOCK: &str = include_str!("../../Cargo.lock");
+
+/// Revision of pod2 the crate is built with, which the circuits change with
+pub fn pod2_rev() -> &'static str {
+    CARGO_LOCK
+        .split("[[package]]")
+        .find(|package| package.contains("\nname = \"pod2\"\n"))
+        .and_then(|package| {
+            package
+                .lines()
+                .find_map(|line| line.strip_prefix("source = "))
+        })
+        .and_then(|source| source.trim_matches('"').rsplit_once('#'))
+        .map_or("unknown", |(_, rev)| rev)
+}
+
+/// Cached data together with what it was built from
+#[derive(Serialize, Deserialize)]
+struct Entry<T> {
+    // description of everything the data depends on, see `get_or_build`
+    fingerprint: String,
+    // digest of the encoding of the data when it was built
+    digest: B256,
+    data: T,
+}
+
+fn digest<T: Serialize>(data: &T) -> Result<B256> {
+    Ok(keccak256(minicbor_serde::to_vec(data)?))
+}
+
+/// Key of the entries, the fingerprint of the caller together with the pod2 revision
+fn cache_key(fingerprint: &str) -> String {
+    format!("pod2={} {}", pod2_rev(), fingerprint)
+}
+
+fn entry_path(dir: &Path, name: &str, fingerprint: &str) -> PathBuf {
+    // the hash only spreads the entries over files, the full fingerprint is checked on load
+    let mut hasher = DefaultHasher::new();
+    fingerprint.hash(&mut hasher);
+    dir.join(format!("{}-{:016x}.cbor", name, hasher.finish()))
+}
+
+fn load<T: DeserializeOwned>(path: &Path) -> Result<Option<Entry<T>>> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(Some(minicbor_serde::from_slice(&bytes)?)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Loads the data cached under `name` for `fingerprint`, or builds and caches it.  The
+/// fingerprint must describe everything else the data depends on (versions, parameters, digests
+/// of the circuits it's built from) so that changing any of them misses the cache instead of
+/// reusing stale data; the entries are also keyed on the pod2 revision.  On load the data is
+/// encoded again and its digest must match the one recorded when it was built, otherwise the
+/// entry is rebuilt with a warning.
+pub fn get_or_build<T: Serialize + DeserializeOwned>(
+    dir: &Path,
+    name: &str,
+    fingerprint: &str,
+    build: impl FnOnce() -> T,
+) -> Result<T> {
+    let fingerprint = cache_key(fingerprint);
+    let path = entry_path(dir, name, &fingerprint);
+    match load::<T>(&path) {
+        Ok(Some(entry)) if entry.fingerprint != fingerprint => {
+            warn!("{} cache entry {:?} is stale, rebuilding", name, path)
+        }
+        Ok(Some(entry)) if digest(&entry.data)? != entry.digest => {
+            warn!(
+                "{} cache entry {:?} doesn't match its digest, rebuilding",
+                name, path
+            )
+        }
+        Ok(Some(entry)) => return Ok(entry.data),
+        Ok(None) => info!("{} not cached, building", name),
+        Err(err) => warn!(
+            "failed to load {} cache entry {:?}, rebuilding: {:#}",
+            name, path, err
+        ),
+    }
+
+    let data = build();
+    let entry = Entry {
+        fingerprint,
+        digest: digest(&data)?,
+        data: &data,
+    };
+    create_dir_all(dir)?;
+    let path_tmp = path.with_extension("cbor.tmp");
+    File::create(&path_tmp)?.write_all(&minicbor_serde::to_vec(&entry)?)?;
+    rename(path_tmp, path)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn test_get_or_build() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("circuit-cache-test-{}", std::process::id()));
+        let builds = Cell::new(0);
+        let get = |fingerprint: &str, data: &[u64]| {
+            get_or_build(&dir, "test", fingerprint, || {
+                builds.set(builds.get() + 1);
+                data.to_vec()
+            })
+        };
+
+        assert_eq!(get("v1", &[1, 2])?, vec![1, 2]);
+        assert_eq!(builds.get(), 1);
+        // cached
+        assert_eq!(get("v1", &[3])?, vec![1, 2]);
+        assert_eq!(builds.get(), 1);
+        // a new fingerprint doesn't reuse the entry
+        assert_eq!(get("v2", &[3])?, vec![3]);
+        assert_eq!(builds.get(), 2);
+
+        // poisoned entry with data that doesn't match its digest
+        let path = entry_path(&dir, "test", &cache_key("v1"));
+        let poisoned = Entry {
+            fingerprint: cache_key("v1"),
+            digest: digest(&vec![1u64, 2])?,
+            data: vec![6u64, 6],
+        };
+        fs::write(&path, minicbor_serde::to_vec(&poisoned)?)?;
+        assert_eq!(get("v1", &[1, 2])?, vec![1, 2]);
+        assert_eq!(builds.get(), 3);
+        // entries written by a build with another fingerprint or pod2 revision (or a colliding
+        // hash)
+        for fingerprint in [cache_key("v0"), "pod2=0000 v1".to_string()] {
+            let stale = Entry {
+                fingerprint,
+                digest: digest(&vec![6u64, 6])?,
+                data: vec![6u64, 6],
+            };
+            fs::write(&path, minicbor_serde::to_vec(&stale)?)?;
+            assert_eq!(get("v1", &[1, 2])?, vec![1, 2]);
+        }
+        assert_eq!(builds.get(), 5);
+        // garbage
+        fs::write(&path, b"garbage")?;
+        assert_eq!(get("v1", &[1, 2])?, vec![1, 2]);
+        assert_eq!(builds.get(), 6);
+        // and the rebuilt entry is used
+        assert_eq!(get("v1", &[3])?, vec![1, 2]);
+        assert_eq!(builds.get(), 6);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_pod2_rev() {
+        let rev = pod2_rev();
+        assert_eq!(rev.len(), 40, "{}", rev);
+        assert!(rev.chars().all(|c| c.is_ascii_hexdigit()), "{}", rev);
+    }
+}
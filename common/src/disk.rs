@@ -1,34 +1,703 @@
 use std::{
-    fs::{File, create_dir_all, rename},
-    io::{Read, Write},
-    path::Path,
+    collections::BTreeMap,
+    fs::{File, create_dir_all, read_dir, remove_file, rename},
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
 };
 
-use anyhow::Result;
-use pod2::frontend::MainPod;
+use anyhow::{Result, anyhow};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use hex::FromHex;
+use pod2::{frontend::MainPod, middleware::hash_str};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::payload::write_elems;
+
+// first bytes of a gzip stream, which tell the compressed pod files from the plain JSON ones
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
 
 // TODO: Make async
-pub fn store_pod(path: &Path, name: &str, pod: &MainPod) -> Result<()> {
+/// Stores the pod and returns the size in bytes of its file.  The JSON of the pod is gzipped
+/// with `compression_level`, from 1 (fastest) to 9 (smallest), or stored as is if it's 0.  The
+/// file keeps the `.pod2.json` extension either way.
+pub fn store_pod(path: &Path, name: &str, pod: &MainPod, compression_level: u32) -> Result<u64> {
     create_dir_all(path)?;
     let file_path = path.join(format!("{name}.pod2.json"));
     let file_path_tmp = path.join(format!("{name}.pod2.json.tmp"));
     let mut file_tmp = File::create(&file_path_tmp)?;
-    let pod_json = serde_json::to_string(pod)?;
-    file_tmp.write_all(pod_json.as_bytes())?;
+    let pod_json = serde_json::to_vec(pod)?;
+    let bytes = if compression_level == 0 {
+        pod_json
+    } else {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::new(compression_level));
+        encoder.write_all(&pod_json)?;
+        encoder.finish()?
+    };
+    file_tmp.write_all(&bytes)?;
     rename(file_path_tmp, file_path)?;
-    Ok(())
+    Ok(bytes.len() as u64)
 }
 
 // TODO: Make async
+/// Loads a pod stored by `store_pod`, compressed or not.
 pub fn load_pod(path: &Path, name: &str) -> Result<MainPod> {
     let file_path = path.join(format!("{name}.pod2.json"));
     let mut file = File::open(&file_path)?;
-    let mut pod_json = Vec::new();
-    file.read_to_end(&mut pod_json)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    let pod_json = if bytes.starts_with(&GZIP_MAGIC) {
+        let mut pod_json = Vec::new();
+        GzDecoder::new(bytes.as_slice()).read_to_end(&mut pod_json)?;
+        pod_json
+    } else {
+        bytes
+    };
     let pod: MainPod = serde_json::from_slice(&pod_json)?;
     Ok(pod)
 }
 
-pub fn rev_membership_list_pod_file_name(id: i64, num: i64) -> String {
-    format!("{id:08}-{num:08}-rev_membership_list")
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum PodKind {
+    // proves the state of the membership list after an update
+    MembershipList,
+    // proves the reverse index (user -> groups) of the membership list
+    RevMembershipList,
+    // proves that a user is a member of a group of the membership list, identified by the hash
+    // of the group and the user since user names are free form
+    Membership([u8; 32]),
+}
+
+/// Identifies a pod of the membership list `id` at update `num`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct PodKey {
+    pub kind: PodKind,
+    pub id: i64,
+    pub num: i64,
+}
+
+impl PodKey {
+    pub fn membership_list(id: i64, num: i64) -> Self {
+        Self {
+            kind: PodKind::MembershipList,
+            id,
+            num,
+        }
+    }
+
+    pub fn rev_membership_list(id: i64, num: i64) -> Self {
+        Self {
+            kind: PodKind::RevMembershipList,
+            id,
+            num,
+        }
+    }
+
+    pub fn membership(id: i64, num: i64, group: &str, user: &str) -> Self {
+        // names can't contain '/'
+        let hash = hash_str(&format!("{group}/{user}"));
+        let mut member = Vec::new();
+        write_elems(&mut member, &hash.0);
+        Self {
+            kind: PodKind::Membership(member.try_into().expect("32 bytes")),
+            id,
+            num,
+        }
+    }
+
+    /// Name of the pod file.  The id and num are padded to the 19 digits of `i64::MAX` so that
+    /// file names sort by id and num.
+    pub fn file_name(&self) -> String {
+        let kind = match self.kind {
+            PodKind::MembershipList => "membership_list".to_string(),
+            PodKind::RevMembershipList => "rev_membership_list".to_string(),
+            PodKind::Membership(member) => format!("membership-{}", hex::encode(member)),
+        };
+        format!("{:019}-{:019}-{}", self.id, self.num, kind)
+    }
+
+    /// Parses a name returned by `file_name`, or by the `file_name` of the versions that padded the
+    /// id and num to 8 digits.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let mut parts = name.splitn(3, '-');
+        let id = parts.next()?.parse().ok()?;
+        let num = parts.next()?.parse().ok()?;
+        let kind = match parts.next()? {
+            "membership_list" => PodKind::MembershipList,
+            "rev_membership_list" => PodKind::RevMembershipList,
+            kind => {
+                PodKind::Membership(<[u8; 32]>::from_hex(kind.strip_prefix("membership-")?).ok()?)
+            }
+        };
+        Some(Self { kind, id, num })
+    }
+}
+
+/// Returns the pods stored in the directory with the name of their file, without the
+/// `.pod2.json` extension, sorted by key.  Other files, like the membership pods stored before
+/// they had a key, are skipped.
+fn list_pod_files(path: &Path) -> Result<Vec<(PodKey, String)>> {
+    let dir = match read_dir(path) {
+        Ok(dir) => dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut pods = Vec::new();
+    for entry in dir {
+        let file_name = entry?.file_name();
+        let pod = file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".pod2.json"))
+            .and_then(|name| Some((PodKey::from_file_name(name)?, name.to_string())));
+        pods.extend(pod);
+    }
+    pods.sort();
+    Ok(pods)
+}
+
+/// Returns the keys of the pods stored in the directory, sorted.  Other files, like the
+/// membership pods stored before they had a key, are skipped.
+pub fn list_pods(path: &Path) -> Result<Vec<PodKey>> {
+    Ok(list_pod_files(path)?
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect())
+}
+
+/// Renames the pod files of the directory that have the 8 digits names of the previous versions
+/// to their `PodKey::file_name`, and returns how many were renamed.  A pod stored with both names
+/// keeps the file of the new one.
+fn rename_legacy_pod_files(path: &Path) -> Result<usize> {
+    let mut renamed = 0;
+    for (key, name) in list_pod_files(path)? {
+        if name == key.file_name() {
+            continue;
+        }
+        let legacy_path = path.join(format!("{name}.pod2.json"));
+        let file_path = path.join(format!("{}.pod2.json", key.file_name()));
+        if file_path.exists() {
+            remove_file(legacy_path)?;
+            continue;
+        }
+        rename(legacy_path, file_path)?;
+        renamed += 1;
+    }
+    Ok(renamed)
+}
+
+/// Deletes the pods of the directory that are no longer needed, keeping the last `keep_last_n`
+/// of every membership list, and returns their keys.  Meant for a directory that isn't in use by
+/// a running server, which prunes through its own `PodStore`.
+pub fn gc_pods(path: &Path, keep_last_n: usize) -> Result<Vec<PodKey>> {
+    // nothing is stored, so the compression level doesn't matter
+    PodStore::open(path, 0)?.prune(keep_last_n)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PodEntry {
+    pub key: PodKey,
+    // size in bytes of the pod file
+    pub size: u64,
+    // whether the tx with the payload of the pod was included.  Always true for pods that aren't
+    // posted to ethereum.
+    pub confirmed: bool,
+    // first update proven by the pod if it proves the batch of updates `first_num..=key.num`, whose
+    // keys all resolve to its key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_num: Option<i64>,
+}
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+/// Pods stored in a directory together with a manifest of the stored pods, which is what pruning
+/// works from.  Pods stored before the manifest existed are picked up from the directory when
+/// pruning, as confirmed pods if a rev pod of their list proves the same or a later update.  They
+/// aren't added to the manifest, so that a pod found unconfirmed is checked again on the next
+/// pruning.
+pub struct PodStore {
+    path: PathBuf,
+    // see `store_pod`
+    compression_level: u32,
+    manifest: Mutex<BTreeMap<PodKey, PodEntry>>,
+}
+
+impl PodStore {
+    /// Opens the store in `path`, renaming the pod files stored with the names of the previous
+    /// versions.
+    pub fn open(path: &Path, compression_level: u32) -> Result<Self> {
+        let renamed = rename_legacy_pod_files(path)?;
+        if renamed > 0 {
+            info!("renamed {} pod files to their padded names", renamed);
+        }
+        let manifest = match File::open(path.join(MANIFEST_FILE_NAME)) {
+            Ok(file) => {
+                let entries: Vec<PodEntry> = serde_json::from_reader(file)?;
+                entries.into_iter().map(|e| (e.key, e)).collect()
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            compression_level,
+            manifest: Mutex::new(manifest),
+        })
+    }
+
+    fn write_manifest(&self, manifest: &BTreeMap<PodKey, PodEntry>) -> Result<()> {
+        create_dir_all(&self.path)?;
+        let file_path = self.path.join(MANIFEST_FILE_NAME);
+        let file_path_tmp = self.path.join(format!("{MANIFEST_FILE_NAME}.tmp"));
+        let entries: Vec<&PodEntry> = manifest.values().collect();
+        let mut file_tmp = File::create(&file_path_tmp)?;
+        file_tmp.write_all(&serde_json::to_vec(&entries)?)?;
+        rename(file_path_tmp, file_path)?;
+        Ok(())
+    }
+
+    pub fn store(&self, key: PodKey, pod: &MainPod, confirmed: bool) -> Result<()> {
+        self.store_batch(key, None, pod, confirmed)
+    }
+
+    /// Like `store` for a pod that proves the batch of updates `first_num..=key.num`, if
+    /// `first_num` is some.  The pod is stored once under `key`, and found under the keys of all
+    /// the updates of the batch.
+    pub fn store_batch(
+        &self,
+        key: PodKey,
+        first_num: Option<i64>,
+        pod: &MainPod,
+        confirmed: bool,
+    ) -> Result<()> {
+        let size = store_pod(&self.path, &key.file_name(), pod, self.compression_level)?;
+        let mut manifest = self.manifest.lock().expect("lock");
+        manifest.insert(
+            key,
+            PodEntry {
+                key,
+                size,
+                confirmed,
+                first_num: first_num.filter(|first_num| *first_num < key.num),
+            },
+        );
+        self.write_manifest(&manifest)
+    }
+
+    /// Returns the key the pod of `key` is stored under: the key of the pod of the batch that
+    /// proves the update of `key`, if any, else `key`.
+    pub fn resolve(&self, key: PodKey) -> PodKey {
+        let manifest = self.manifest.lock().expect("lock");
+        if manifest.contains_key(&key) {
+            return key;
+        }
+        // the batches are stored under their last update, the first pod after `key` of the list
+        manifest
+            .range(key..)
+            .next()
+            .filter(|(next, entry)| {
+                (next.kind, next.id) == (key.kind, key.id)
+                    && entry
+                        .first_num
+                        .is_some_and(|first_num| first_num <= key.num)
+            })
+            .map_or(key, |(next, _)| *next)
+    }
+
+    pub fn load(&self, key: PodKey) -> Result<MainPod> {
+        load_pod(&self.path, &self.resolve(key).file_name())
+    }
+
+    /// Path of the file of the pod, which may be gzipped, see `resolve`.
+    pub fn file_path(&self, key: PodKey) -> PathBuf {
+        self.path
+            .join(format!("{}.pod2.json", self.resolve(key).file_name()))
+    }
+
+    /// Moves a pod file written by `store_pod`, e.g. into a staging directory in the same
+    /// filesystem, into the store under `key`, as the pod of the batch `first_num..=key.num` if
+    /// `first_num` is some.
+    pub fn insert_file(
+        &self,
+        key: PodKey,
+        first_num: Option<i64>,
+        file_path: &Path,
+        confirmed: bool,
+    ) -> Result<()> {
+        create_dir_all(&self.path)?;
+        let size = std::fs::metadata(file_path)?.len();
+        rename(
+            file_path,
+            self.path.join(format!("{}.pod2.json", key.file_name())),
+        )?;
+        let mut manifest = self.manifest.lock().expect("lock");
+        manifest.insert(
+            key,
+            PodEntry {
+                key,
+                size,
+                confirmed,
+                first_num: first_num.filter(|first_num| *first_num < key.num),
+            },
+        );
+        self.write_manifest(&manifest)
+    }
+
+    /// Marks the pod as confirmed once the tx with its payload is included.
+    pub fn set_confirmed(&self, key: PodKey) -> Result<()> {
+        let key = self.resolve(key);
+        let mut manifest = self.manifest.lock().expect("lock");
+        let entry = manifest
+            .get_mut(&key)
+            .ok_or_else(|| anyhow!("pod {} not in manifest", key.file_name()))?;
+        entry.confirmed = true;
+        self.write_manifest(&manifest)
+    }
+
+    /// Returns the manifest entry of the pod of `key`, see `resolve`.
+    pub fn entry(&self, key: PodKey) -> Option<PodEntry> {
+        let key = self.resolve(key);
+        self.manifest.lock().expect("lock").get(&key).cloned()
+    }
+
+    pub fn entries(&self) -> Vec<PodEntry> {
+        self.manifest
+            .lock()
+            .expect("lock")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Deletes the pods selected by `pods_to_prune` and returns their keys.
+    pub fn prune(&self, retain_last_n: usize) -> Result<Vec<PodKey>> {
+        let mut manifest = self.manifest.lock().expect("lock");
+        let files = list_pod_files(&self.path)?;
+        // the updates up to the latest rev pod of a list were applied to its reverse index,
+        // which only follows the confirmed updates
+        let mut latest_rev: BTreeMap<i64, i64> = BTreeMap::new();
+        let rev_keys = manifest.keys().chain(files.iter().map(|(key, _)| key));
+        for key in rev_keys.filter(|key| key.kind == PodKind::RevMembershipList) {
+            let num = latest_rev.entry(key.id).or_insert(key.num);
+            *num = (*num).max(key.num);
+        }
+        let mut file_names = BTreeMap::new();
+        let mut pods = manifest.clone();
+        for (key, name) in files {
+            if !pods.contains_key(&key) {
+                let size = std::fs::metadata(self.path.join(format!("{name}.pod2.json")))?.len();
+                let confirmed = key.kind != PodKind::MembershipList
+                    || latest_rev.get(&key.id).is_some_and(|num| key.num <= *num);
+                pods.insert(
+                    key,
+                    PodEntry {
+                        key,
+                        size,
+                        confirmed,
+                        first_num: None,
+                    },
+                );
+            }
+            file_names.insert(key, name);
+        }
+        let pruned = pods_to_prune(&pods, retain_last_n);
+        for key in &pruned {
+            let name = file_names.remove(key).unwrap_or_else(|| key.file_name());
+            let file_path = self.path.join(format!("{name}.pod2.json"));
+            match remove_file(file_path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            manifest.remove(key);
+        }
+        self.write_manifest(&manifest)?;
+        Ok(pruned)
+    }
+}
+
+/// Returns the pods that can be deleted while keeping the last `retain_last_n` pods of every list
+/// and the ones still needed to continue the lists:
+/// - the latest rev pod, which is the base of the next rev proof
+/// - the membership list pods after the latest rev pod, which the next rev proofs consume
+/// - the unconfirmed membership list pods, which may still be wrapped and sent
+///
+/// The membership pods of every group and user count as a list of their own.
+pub fn pods_to_prune(manifest: &BTreeMap<PodKey, PodEntry>, retain_last_n: usize) -> Vec<PodKey> {
+    // latest rev pod by membership list id
+    let mut latest_rev: BTreeMap<i64, i64> = BTreeMap::new();
+    for key in manifest.keys() {
+        if key.kind == PodKind::RevMembershipList {
+            let num = latest_rev.entry(key.id).or_insert(key.num);
+            *num = (*num).max(key.num);
+        }
+    }
+
+    // pods of every list from the newest to the oldest
+    let mut lists: BTreeMap<(PodKind, i64), Vec<&PodEntry>> = BTreeMap::new();
+    for entry in manifest.values().rev() {
+        lists
+            .entry((entry.key.kind, entry.key.id))
+            .or_default()
+            .push(entry);
+    }
+
+    let mut pruned = Vec::new();
+    for ((kind, id), entries) in lists {
+        for entry in entries.into_iter().skip(retain_last_n) {
+            let needed = match kind {
+                PodKind::RevMembershipList => latest_rev.get(&id) == Some(&entry.key.num),
+                PodKind::MembershipList => {
+                    !entry.confirmed
+                        || latest_rev
+                            .get(&id)
+                            .is_none_or(|rev_num| entry.key.num > *rev_num)
+                }
+                PodKind::Membership(_) => false,
+            };
+            if !needed {
+                pruned.push(entry.key);
+            }
+        }
+    }
+    pruned.sort();
+    pruned
+}
+
+#[cfg(test)]
+mod tests {
+    use pod2::{
+        backends::plonky2::mainpod::Prover,
+        frontend::{MainPodBuilder, Operation},
+        middleware::{DEFAULT_VD_SET, Params},
+    };
+
+    use super::*;
+
+    fn manifest(entries: &[(PodKey, bool)]) -> BTreeMap<PodKey, PodEntry> {
+        entries
+            .iter()
+            .map(|(key, confirmed)| {
+                (
+                    *key,
+                    PodEntry {
+                        key: *key,
+                        size: 1,
+                        confirmed: *confirmed,
+                        first_num: None,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pod_key_file_name() {
+        assert_eq!(
+            PodKey::rev_membership_list(1, 100_000_000).file_name(),
+            "0000000000000000001-0000000000100000000-rev_membership_list"
+        );
+        assert!(
+            PodKey::membership_list(1, 99_999_999).file_name()
+                < PodKey::membership_list(1, 100_000_000).file_name()
+        );
+        for key in [
+            PodKey::membership_list(0, 1),
+            PodKey::rev_membership_list(1, 100_000_000),
+            PodKey::membership(1, 2, "red", "alice"),
+        ] {
+            assert_eq!(PodKey::from_file_name(&key.file_name()), Some(key));
+        }
+        assert_ne!(
+            PodKey::membership(1, 2, "red", "alice"),
+            PodKey::membership(1, 2, "red", "bob")
+        );
+        // the membership pods stored before they had a key
+        assert_eq!(
+            PodKey::from_file_name("0000000000000000001-0000000000000000002-membership-red-0x01"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_store_pod_compression() -> Result<()> {
+        let params = Params::default();
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let st = builder.priv_op(Operation::eq(1i64, 1i64)).unwrap();
+        builder.reveal(&st);
+        let pod = builder.prove(&Prover {}).unwrap();
+        let pod_json = serde_json::to_string(&pod)?;
+
+        let path =
+            std::env::temp_dir().join(format!("disk-compression-test-{}", std::process::id()));
+        let plain_size = store_pod(&path, "plain", &pod, 0)?;
+        let gzip_size = store_pod(&path, "gzip", &pod, 1)?;
+        assert_eq!(plain_size, pod_json.len() as u64);
+        assert!(gzip_size < plain_size);
+        // the plain files, like the ones stored before the compression, still load
+        let plain_bytes = std::fs::read(path.join("plain.pod2.json"))?;
+        assert_eq!(plain_bytes, pod_json.as_bytes());
+        assert!(std::fs::read(path.join("gzip.pod2.json"))?.starts_with(&GZIP_MAGIC));
+        for name in ["plain", "gzip"] {
+            assert_eq!(serde_json::to_string(&load_pod(&path, name)?)?, pod_json);
+        }
+
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_store_batch() -> Result<()> {
+        let ml = PodKey::membership_list;
+        let params = Params::default();
+        let mut builder = MainPodBuilder::new(&params, &DEFAULT_VD_SET);
+        let st = builder.priv_op(Operation::eq(1i64, 1i64)).unwrap();
+        builder.reveal(&st);
+        let pod = builder.prove(&Prover {}).unwrap();
+
+        let path = std::env::temp_dir().join(format!("disk-batch-test-{}", std::process::id()));
+        let store = PodStore::open(&path, 0)?;
+        store.store(ml(1, 1), &pod, true)?;
+        // the pod of the updates 2..=4, stored once
+        store.store_batch(ml(1, 4), Some(2), &pod, false)?;
+        assert_eq!(list_pods(&path)?, vec![ml(1, 1), ml(1, 4)]);
+        for num in 2..=4 {
+            assert_eq!(store.resolve(ml(1, num)), ml(1, 4));
+            assert_eq!(store.file_path(ml(1, num)), store.file_path(ml(1, 4)));
+            store.load(ml(1, num))?;
+        }
+        for key in [
+            ml(1, 1),
+            ml(1, 5),
+            ml(2, 3),
+            PodKey::rev_membership_list(1, 3),
+        ] {
+            assert_eq!(store.resolve(key), key);
+        }
+        store.set_confirmed(ml(1, 3))?;
+        // the batch is in the manifest
+        let store = PodStore::open(&path, 0)?;
+        assert_eq!(store.resolve(ml(1, 2)), ml(1, 4));
+        assert!(store.entries().iter().all(|entry| entry.confirmed));
+
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_gc_pods() -> Result<()> {
+        let ml = PodKey::membership_list;
+        let rev = PodKey::rev_membership_list;
+        let path = std::env::temp_dir().join(format!("disk-gc-test-{}", std::process::id()));
+        create_dir_all(&path)?;
+        // pods written without a manifest, the content isn't read
+        for key in [ml(1, 1), ml(1, 2), ml(1, 3), rev(1, 1), rev(1, 2)] {
+            std::fs::write(path.join(format!("{}.pod2.json", key.file_name())), "{}")?;
+        }
+        std::fs::write(
+            path.join("0000000000000000001-0000000000000000002-membership-red-0x01.pod2.json"),
+            "{}",
+        )?;
+        std::fs::write(path.join("other.txt"), "")?;
+        assert_eq!(
+            list_pods(&path)?,
+            vec![ml(1, 1), ml(1, 2), ml(1, 3), rev(1, 1), rev(1, 2)]
+        );
+
+        assert_eq!(gc_pods(&path, 1)?, vec![ml(1, 1), ml(1, 2), rev(1, 1)]);
+        assert_eq!(list_pods(&path)?, vec![ml(1, 3), rev(1, 2)]);
+        assert_eq!(gc_pods(&path, 1)?, vec![]);
+        // the pod after the latest rev pod isn't taken as confirmed, and is pruned once a rev pod
+        // proves its update
+        assert!(PodStore::open(&path, 0)?.entries().is_empty());
+        std::fs::write(
+            path.join(format!("{}.pod2.json", rev(1, 3).file_name())),
+            "{}",
+        )?;
+        assert_eq!(gc_pods(&path, 0)?, vec![ml(1, 3), rev(1, 2)]);
+
+        std::fs::remove_dir_all(&path)?;
+        assert_eq!(list_pods(&path)?, vec![]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_legacy_pod_files() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("disk-legacy-test-{}", std::process::id()));
+        create_dir_all(&path)?;
+        let legacy_name = |key: PodKey| {
+            let kind = match key.kind {
+                PodKind::MembershipList => "membership_list",
+                PodKind::RevMembershipList => "rev_membership_list",
+                PodKind::Membership(_) => unreachable!("no legacy membership pod keys"),
+            };
+            format!("{:08}-{:08}-{}.pod2.json", key.id, key.num, kind)
+        };
+        let keys = [
+            PodKey::membership_list(1, 1),
+            PodKey::membership_list(1, 2),
+            PodKey::rev_membership_list(1, 1),
+        ];
+        for (key, content) in keys.iter().zip(["a", "b", "c"]) {
+            std::fs::write(path.join(legacy_name(*key)), content)?;
+        }
+        // stored with both names, the new file is the one kept
+        std::fs::write(path.join(legacy_name(keys[1])), "old")?;
+        std::fs::write(path.join(format!("{}.pod2.json", keys[1].file_name())), "b")?;
+
+        let store = PodStore::open(&path, 0)?;
+        for (key, content) in keys.iter().zip(["a", "b", "c"]) {
+            assert_eq!(std::fs::read_to_string(store.file_path(*key))?, content);
+            assert!(!path.join(legacy_name(*key)).exists());
+        }
+        assert_eq!(list_pods(&path)?, keys.to_vec());
+        // a second open has nothing left to rename
+        assert_eq!(rename_legacy_pod_files(&path)?, 0);
+
+        std::fs::remove_dir_all(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_pods_to_prune() {
+        let ml = PodKey::membership_list;
+        let rev = PodKey::rev_membership_list;
+        // list 1: updates 1..=6, with 6 unconfirmed and rev pods up to 3
+        // list 2: updates 1..=2 and no rev pods yet
+        let m = manifest(&[
+            (ml(1, 1), true),
+            (ml(1, 2), true),
+            (ml(1, 3), true),
+            (ml(1, 4), true),
+            (ml(1, 5), true),
+            (ml(1, 6), false),
+            (rev(1, 1), true),
+            (rev(1, 2), true),
+            (rev(1, 3), true),
+            (ml(2, 1), true),
+            (ml(2, 2), true),
+        ]);
+
+        assert_eq!(pods_to_prune(&m, 3), vec![ml(1, 1), ml(1, 2), ml(1, 3)]);
+
+        let pruned = pods_to_prune(&m, 0);
+        assert_eq!(
+            pruned,
+            vec![ml(1, 1), ml(1, 2), ml(1, 3), rev(1, 1), rev(1, 2)]
+        );
+        let kept: Vec<PodKey> = m.keys().filter(|k| !pruned.contains(k)).copied().collect();
+        // the next rev proof of list 1 (num 4) needs the rev pod 3 and the membership list pod 4,
+        // and the ones after it need the membership list pods 5 and 6
+        for key in [rev(1, 3), ml(1, 4), ml(1, 5), ml(1, 6)] {
+            assert!(kept.contains(&key), "{:?} pruned", key);
+        }
+        // list 2 has no rev pods yet so all its membership list pods are needed
+        assert!(kept.contains(&ml(2, 1)) && kept.contains(&ml(2, 2)));
+
+        // only the last membership pods of every group and user are kept
+        let alice = |num| PodKey::membership(1, num, "red", "alice");
+        let bob = |num| PodKey::membership(1, num, "red", "bob");
+        let m = manifest(&[(alice(1), true), (alice(2), true), (bob(1), true)]);
+        assert_eq!(pods_to_prune(&m, 1), vec![alice(1)]);
+    }
 }
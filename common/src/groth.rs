@@ -1,11 +1,16 @@
 use std::{path::Path, time::Instant};
 
-use anyhow::{Result, anyhow};
+use anyhow::{Result, anyhow, bail};
+use plonky2::field::types::{Field, Field64};
+use pod2::middleware::{F, Hash};
 use tracing::info;
 
 const INPUT_PATH: &str = "../tmp/plonky2-proof";
 const OUTPUT_PATH: &str = "../tmp/groth-artifacts";
 
+// the public inputs are a bn254 scalar each in the gnark encoding
+const GNARK_ELEM_BYTES: usize = 32;
+
 /// initializes the groth16 prover memory, loading the artifacts. This method
 /// must be called before the `prove` method.
 pub fn init() -> Result<()> {
@@ -59,13 +64,78 @@ pub fn prove(pod: pod2::frontend::MainPod) -> Result<(Vec<u8>, Vec<u8>)> {
     Ok((g16_proof, g16_pub_inp))
 }
 
+/// Decodes the public inputs encoded by `pod2_onchain::encode_public_inputs_gnark`, a gnark
+/// public witness: the number of public inputs, of secret inputs (none) and the length of the
+/// vector as big-endian u32, followed by every input as a big-endian bn254 scalar.
+fn decode_public_inputs_gnark(bytes: &[u8]) -> Result<Vec<F>> {
+    let (header, elems) = bytes
+        .split_at_checked(12)
+        .ok_or_else(|| anyhow!("public inputs of {} bytes", bytes.len()))?;
+    let [nb_public, nb_secret, len] = [0, 4, 8]
+        .map(|i| u32::from_be_bytes(header[i..i + 4].try_into().expect("4 bytes")) as usize);
+    if nb_secret != 0 || nb_public != len || elems.len() != len * GNARK_ELEM_BYTES {
+        bail!(
+            "invalid public inputs header: {} public, {} secret, {} elements in {} bytes",
+            nb_public,
+            nb_secret,
+            len,
+            elems.len()
+        );
+    }
+    elems
+        .chunks_exact(GNARK_ELEM_BYTES)
+        .map(|elem| {
+            // a goldilocks element only takes the last 8 bytes of the scalar
+            let (high, low) = elem.split_at(GNARK_ELEM_BYTES - 8);
+            let n = u64::from_be_bytes(low.try_into().expect("8 bytes"));
+            if high.iter().any(|b| *b != 0) || n >= F::ORDER {
+                bail!("public input 0x{} >= F::ORDER", hex::encode(elem));
+            }
+            Ok(F::from_canonical_u64(n))
+        })
+        .collect()
+}
+
+/// Checks that the public inputs `pub_inp` returned by `prove` are the statements hash and the
+/// vd set root that the verification of the payload expects, so that a mismatch is caught before
+/// the payload is sent.
+pub fn check_public_inputs(
+    pub_inp: &[u8],
+    expected_sts_hash: Hash,
+    expected_vds_root: Hash,
+) -> Result<()> {
+    let inputs = decode_public_inputs_gnark(pub_inp)?;
+    if inputs.len() != 8 {
+        bail!("{} public inputs, expected 8", inputs.len());
+    }
+    let (sts_hash, vds_root) = inputs.split_at(4);
+    if *sts_hash != expected_sts_hash.0 {
+        bail!(
+            "public inputs encode statements hash {:?}, expected {}",
+            sts_hash,
+            expected_sts_hash
+        );
+    }
+    if *vds_root != expected_vds_root.0 {
+        bail!(
+            "public inputs encode vd set root {:?}, expected {}",
+            vds_root,
+            expected_vds_root
+        );
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
     use pod2::{
-        backends::plonky2::{basetypes::DEFAULT_VD_SET, mainpod::Prover},
+        backends::plonky2::{
+            basetypes::DEFAULT_VD_SET,
+            mainpod::{Prover, calculate_statements_hash},
+        },
         frontend::MainPodBuilder,
-        middleware::{Params, containers::Dictionary},
+        middleware::{Params, Statement, containers::Dictionary},
     };
 
     use super::*;
@@ -74,7 +144,7 @@ mod tests {
     fn compute_pod_proof() -> Result<pod2::frontend::MainPod> {
         let params = Params::default();
         let vd_set = &*DEFAULT_VD_SET;
-        let (state_predicates, _) = app::build_predicates(&params);
+        let state_predicates = app::build_predicates(&params).state;
 
         let mut builder = MainPodBuilder::new(&params, vd_set);
         let mut helper = app::Helper::new(&mut builder, &state_predicates);
@@ -84,13 +154,14 @@ mod tests {
             std::collections::HashMap::new(),
         )
         .unwrap();
-        let (state, _st_update) = helper.st_update(initial_state.clone(), app::Op::Init.into())?;
+        let (state, _st_update) =
+            helper.st_update(initial_state.clone(), app::Op::Init.into_dict(&params, 1))?;
 
         let op = app::Op::Add {
-            group: app::Group::Red,
-            user: "user1".to_string(),
+            group: app::Group::RED,
+            user: app::UserId::new("user1").unwrap(),
         };
-        let op = Dictionary::from(op);
+        let op = op.into_dict(&params, 2);
 
         let (_new_state, st_update) = helper.st_update(state.clone(), op)?;
         builder.reveal(&st_update);
@@ -100,6 +171,24 @@ mod tests {
         Ok(pod)
     }
 
+    #[test]
+    fn test_check_public_inputs() -> Result<()> {
+        let params = Params::default();
+        let sts_hash = calculate_statements_hash(&[Statement::None.into()], &params);
+        let vds_root = DEFAULT_VD_SET.root();
+        let pub_inp = pod2_onchain::encode_public_inputs_gnark([sts_hash.0, vds_root.0].concat());
+        check_public_inputs(&pub_inp, sts_hash, vds_root)?;
+
+        // a corrupted byte of the first element of the statements hash
+        let mut corrupted = pub_inp.clone();
+        corrupted[12 + GNARK_ELEM_BYTES - 1] ^= 1;
+        assert!(check_public_inputs(&corrupted, sts_hash, vds_root).is_err());
+        // the hashes in the wrong order, or truncated public inputs
+        assert!(check_public_inputs(&pub_inp, vds_root, sts_hash).is_err());
+        assert!(check_public_inputs(&pub_inp[..pub_inp.len() - 1], sts_hash, vds_root).is_err());
+        Ok(())
+    }
+
     // The following test is ignored by default since it requires the trusted
     // setup and takes too long to run. To run it:
     //   cargo test --release -p common gen_sample_pod_proof -- --nocapture --ignored
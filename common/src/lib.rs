@@ -1,5 +1,11 @@
+pub mod api;
+pub mod attestation;
+pub mod circuit_cache;
 pub mod disk;
+pub mod ops;
+pub mod params;
 pub mod payload;
+pub mod rejection;
 
 /// 2 options to prepare the POD proofs:
 ///   A) "groth":
@@ -8,6 +14,8 @@ pub mod payload;
 ///     configuration of the plonky2 prover, in order to make it compatible with the
 ///     Groth16 circuit.
 ///     Then compute a Groth16 proof which verifies the last plonky2 proof
+///     Only built with the `groth16` feature.
+#[cfg(feature = "groth16")]
 pub mod groth;
 ///   B) "shrink":
 ///     first shrinks the given MainPod's proof, and then compresses it,
@@ -19,7 +27,10 @@ use std::{io, str::FromStr, time::Duration};
 use anyhow::{Result, anyhow};
 use log::LevelFilter;
 use pod2::middleware::{Value, containers};
-use sqlx::{ConnectOptions, SqlitePool, sqlite::SqliteConnectOptions};
+use sqlx::{
+    ConnectOptions, SqlitePool,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous},
+};
 
 /// struct used to convert sqlx errors to warp errors
 #[allow(dead_code)]
@@ -27,6 +38,12 @@ use sqlx::{ConnectOptions, SqlitePool, sqlite::SqliteConnectOptions};
 pub struct CustomError(pub String);
 impl warp::reject::Reject for CustomError {}
 
+/// Rejection of a request with a malformed parameter, e.g. an id that isn't hex, answered with a
+/// 400 instead of the 500 of a `CustomError`
+#[derive(Debug)]
+pub struct InvalidRequest(pub String);
+impl warp::reject::Reject for InvalidRequest {}
+
 pub fn load_dotenv() -> Result<()> {
     for filename in [".env.default", ".env"] {
         if let Err(err) = dotenvy::from_filename_override(filename) {
@@ -39,8 +56,14 @@ pub fn load_dotenv() -> Result<()> {
     Ok(())
 }
 
-pub async fn db_connection(url: &str) -> Result<SqlitePool, sqlx::Error> {
-    let opts = SqliteConnectOptions::from_str(url)?
+/// Opens a pool to the sqlite database at `url`.  `journal_mode` and `synchronous` are left to
+/// the sqlite defaults (or to the mode the database was created with) when `None`.
+pub async fn db_connection(
+    url: &str,
+    journal_mode: Option<SqliteJournalMode>,
+    synchronous: Option<SqliteSynchronous>,
+) -> Result<SqlitePool, sqlx::Error> {
+    let mut opts = SqliteConnectOptions::from_str(url)?
         // https://docs.rs/sqlx/latest/sqlx/sqlite/struct.SqliteConnectOptions.html#method.serialized
         // > Setting this to true may help if you are getting access violation errors or
         // segmentation faults, but will also incur a significant performance penalty. You should
@@ -49,6 +72,12 @@ pub async fn db_connection(url: &str) -> Result<SqlitePool, sqlx::Error> {
         .busy_timeout(Duration::from_secs(3600))
         .log_statements(LevelFilter::Debug)
         .log_slow_statements(LevelFilter::Warn, Duration::from_millis(800));
+    if let Some(journal_mode) = journal_mode {
+        opts = opts.journal_mode(journal_mode);
+    }
+    if let Some(synchronous) = synchronous {
+        opts = opts.synchronous(synchronous);
+    }
     SqlitePool::connect_with(opts).await
 }
 
@@ -63,7 +92,10 @@ impl std::str::FromStr for ProofType {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "plonky2" => Ok(ProofType::Plonky2),
-            "groth16" => Ok(ProofType::Groth16),
+            "groth16" if cfg!(feature = "groth16") => Ok(ProofType::Groth16),
+            "groth16" => Err(anyhow!(
+                "PROOF_TYPE groth16 is not supported by this build, rebuild with the groth16 feature"
+            )),
             _ => Err(anyhow!("unsupported PROOF_TYPE {}", s)),
         }
     }
@@ -91,3 +123,23 @@ pub fn set_from_value(v: &Value) -> Result<containers::Set> {
         _ => Err(anyhow!("Invalid set")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proof_type_from_str() {
+        assert_eq!(ProofType::from_str("plonky2").unwrap(), ProofType::Plonky2);
+        let groth16 = ProofType::from_str("groth16");
+        if cfg!(feature = "groth16") {
+            assert_eq!(groth16.unwrap(), ProofType::Groth16);
+        } else {
+            assert_eq!(
+                groth16.unwrap_err().to_string(),
+                "PROOF_TYPE groth16 is not supported by this build, rebuild with the groth16 feature"
+            );
+        }
+        assert!(ProofType::from_str("stark").is_err());
+    }
+}
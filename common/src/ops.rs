@@ -0,0 +1,78 @@
+//! Ops history of an AD: a dictionary from the epoch of each update to the commitment of its op,
+//! whose commitment the update payloads publish as `ops_root`.  Every update inserts exactly the
+//! op of its epoch, which the synchronizer checks by applying the insert to its own copy, so a
+//! Merkle proof against an `ops_root` indexed by the synchronizer proves that an op was the
+//! update `epoch` of the AD without trusting the server that published it.
+
+use std::collections::HashMap;
+
+use anyhow::{Context, Result};
+use pod2::{
+    backends::plonky2::primitives::merkletree::MerkleProof,
+    middleware::{Hash, Key, RawValue, Value, containers::Dictionary},
+};
+
+/// Max depth of the ops dictionaries, which hold one entry per update
+pub const OPS_MAX_DEPTH: usize = 32;
+
+/// Key of the op of the update `epoch`
+pub fn op_key(epoch: i64) -> Key {
+    Key::from(epoch.to_string())
+}
+
+/// Ops of an AD before its first update
+pub fn empty_ops() -> Dictionary {
+    Dictionary::new(OPS_MAX_DEPTH, HashMap::new()).expect("empty dictionary")
+}
+
+/// Inserts the op commitment `op` of the update `epoch` into `ops`.  Fails, leaving `ops` as it
+/// was, if `ops` already has an op at `epoch`.
+pub fn insert_op(ops: &mut Dictionary, epoch: i64, op: RawValue) -> Result<()> {
+    ops.insert(&op_key(epoch), &Value::from(op))
+        .with_context(|| format!("insert op of epoch {}", epoch))
+}
+
+/// Checks the Merkle proof that `op` is the op commitment of the update `epoch` in the ops whose
+/// commitment is `ops_root`.
+pub fn verify_op_proof(
+    ops_root: Hash,
+    epoch: i64,
+    op: RawValue,
+    proof: &MerkleProof,
+) -> Result<()> {
+    Dictionary::verify(
+        OPS_MAX_DEPTH,
+        ops_root,
+        proof,
+        &op_key(epoch),
+        &Value::from(op),
+    )
+    .with_context(|| format!("op of epoch {} not in ops root", epoch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ops() -> Result<()> {
+        let op = |n: i64| RawValue::from(n * 100);
+        let mut ops = empty_ops();
+        for epoch in 1..=3 {
+            insert_op(&mut ops, epoch, op(epoch))?;
+        }
+        // an epoch is inserted once
+        let root = ops.commitment();
+        assert!(insert_op(&mut ops, 2, op(9)).is_err());
+        assert_eq!(ops.commitment(), root);
+
+        let (value, proof) = ops.prove(&op_key(2))?;
+        assert_eq!(value.raw(), op(2));
+        verify_op_proof(ops.commitment(), 2, op(2), &proof)?;
+        // another op, epoch or root
+        assert!(verify_op_proof(ops.commitment(), 2, op(3), &proof).is_err());
+        assert!(verify_op_proof(ops.commitment(), 3, op(2), &proof).is_err());
+        assert!(verify_op_proof(empty_ops().commitment(), 2, op(2), &proof).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,178 @@
+//! Params of the pods, `Params::default()` with the overrides of the `POD_*` env vars.  The
+//! ad-server and the synchronizer must run with the same params, which the synchronizer checks
+//! with the `params_fingerprint` of the create payloads.  The predicates and the circuits are built
+//! from the params, and the circuit cache keys its entries by them, so the params can be tuned
+//! without rebuilding the binaries.  The main pod circuit changes with the params, so the set of
+//! verifier datas the pods are proven with is derived from them too, see `vd_set`.
+
+use anyhow::{Context, Result, bail};
+use pod2::{
+    backends::plonky2::{
+        basetypes::{DEFAULT_VD_LIST, DEFAULT_VD_SET},
+        mainpod::cache_get_rec_main_pod_verifier_circuit_data,
+    },
+    middleware::{Params, VDSet},
+};
+
+/// Overrides of the default params, none for the params left to their default
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PodParamsConfig {
+    // depth of the containers (the states of the lists and their groups), which bounds the
+    // number of their entries to 2^depth
+    pub max_depth_mt_containers: Option<usize>,
+    pub max_input_pods: Option<usize>,
+    pub max_statements: Option<usize>,
+    pub max_public_statements: Option<usize>,
+}
+
+impl PodParamsConfig {
+    pub fn from_env() -> Result<Self> {
+        Self::from_vars(|v| dotenvy::var(v).ok())
+    }
+
+    /// Reads the overrides with `var`, empty values are left to their default.
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let parse = |v: &str| -> Result<Option<usize>> {
+            var(v)
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse().with_context(|| v.to_string()))
+                .transpose()
+        };
+        Ok(Self {
+            max_depth_mt_containers: parse("POD_MAX_DEPTH_MT_CONTAINERS")?,
+            max_input_pods: parse("POD_MAX_INPUT_PODS")?,
+            max_statements: parse("POD_MAX_STATEMENTS")?,
+            max_public_statements: parse("POD_MAX_PUBLIC_STATEMENTS")?,
+        })
+    }
+
+    /// Returns the default params with the overrides.  Fails if the public statements don't fit
+    /// in the statements of a pod.
+    pub fn params(&self) -> Result<Params> {
+        let default = Params::default();
+        let params = Params {
+            max_depth_mt_containers: self
+                .max_depth_mt_containers
+                .unwrap_or(default.max_depth_mt_containers),
+            max_input_pods: self.max_input_pods.unwrap_or(default.max_input_pods),
+            max_statements: self.max_statements.unwrap_or(default.max_statements),
+            max_public_statements: self
+                .max_public_statements
+                .unwrap_or(default.max_public_statements),
+            ..default
+        };
+        if params.max_depth_mt_containers == 0 {
+            bail!("POD_MAX_DEPTH_MT_CONTAINERS must be greater than 0");
+        }
+        if params.max_public_statements >= params.max_statements {
+            bail!(
+                "POD_MAX_PUBLIC_STATEMENTS {} must be less than POD_MAX_STATEMENTS {}",
+                params.max_public_statements,
+                params.max_statements
+            );
+        }
+        Ok(params)
+    }
+}
+
+/// Returns the set of verifier datas of the pods proven with `params`: `DEFAULT_VD_SET`, which
+/// is built for the default params, with the main pod circuit of `params` in place of the default
+/// one.  A main pod taking other main pods as inputs only verifies them if their circuit is in
+/// the set, so the pods of overridden params can't be proven with `DEFAULT_VD_SET`.
+pub fn vd_set(params: &Params) -> Result<VDSet> {
+    if *params == Params::default() {
+        return Ok(DEFAULT_VD_SET.clone());
+    }
+    let default_main_pod = cache_get_rec_main_pod_verifier_circuit_data(&Params::default());
+    let main_pod = cache_get_rec_main_pod_verifier_circuit_data(params);
+    let vds: Vec<_> = DEFAULT_VD_LIST
+        .iter()
+        .map(|vd| {
+            if *vd == default_main_pod.verifier_only {
+                main_pod.verifier_only.clone()
+            } else {
+                vd.clone()
+            }
+        })
+        .collect();
+    VDSet::new(params.max_depth_mt_vds, &vds).context("vd set of the params")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use pod2::{
+        backends::plonky2::mainpod::Prover,
+        frontend::{MainPodBuilder, Operation},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_pod_params_config() -> Result<()> {
+        let from_vars = |vars: &[(&str, &str)]| {
+            let vars: HashMap<String, String> = vars
+                .iter()
+                .map(|(v, value)| (v.to_string(), value.to_string()))
+                .collect();
+            PodParamsConfig::from_vars(|v| vars.get(v).cloned())
+        };
+        // unset or empty vars are the defaults
+        assert_eq!(from_vars(&[])?.params()?, Params::default());
+        assert_eq!(
+            from_vars(&[("POD_MAX_DEPTH_MT_CONTAINERS", "")])?.params()?,
+            Params::default()
+        );
+
+        let params = from_vars(&[("POD_MAX_DEPTH_MT_CONTAINERS", "16")])?.params()?;
+        assert_eq!(params.max_depth_mt_containers, 16);
+        assert_eq!(
+            Params {
+                max_depth_mt_containers: Params::default().max_depth_mt_containers,
+                ..params
+            },
+            Params::default()
+        );
+
+        assert!(from_vars(&[("POD_MAX_STATEMENTS", "many")]).is_err());
+        assert!(
+            from_vars(&[("POD_MAX_DEPTH_MT_CONTAINERS", "0")])?
+                .params()
+                .is_err()
+        );
+        let statements = Params::default().max_public_statements.to_string();
+        assert!(
+            from_vars(&[("POD_MAX_STATEMENTS", &statements)])?
+                .params()
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_vd_set() -> Result<()> {
+        assert_eq!(vd_set(&Params::default())?.root(), DEFAULT_VD_SET.root());
+
+        let params = PodParamsConfig {
+            max_depth_mt_containers: Some(16),
+            ..Default::default()
+        }
+        .params()?;
+        let vd_set = vd_set(&params)?;
+        assert_ne!(vd_set.root(), DEFAULT_VD_SET.root());
+        // a pod of the overridden params taken as the input of another one, as the update pods
+        // take the pod of the previous state
+        let mut builder = MainPodBuilder::new(&params, &vd_set);
+        let st = builder.priv_op(Operation::eq(1i64, 1i64)).unwrap();
+        builder.reveal(&st);
+        let pod = builder.prove(&Prover {}).unwrap();
+        let mut builder = MainPodBuilder::new(&params, &vd_set);
+        builder.add_pod(pod);
+        let st = builder.priv_op(Operation::eq(2i64, 2i64)).unwrap();
+        builder.reveal(&st);
+        let pod = builder.prove(&Prover {}).unwrap();
+        pod.pod.verify().unwrap();
+        Ok(())
+    }
+}
@@ -1,13 +1,17 @@
-use std::io::{Read, Write};
+use std::{
+    io::{Read, Write},
+    iter,
+};
 
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use plonky2::{
     field::types::{Field, Field64, PrimeField64},
     plonk::proof::CompressedProof,
     util::serialization::Buffer,
 };
 use pod2::middleware::{
-    C, CommonCircuitData, CustomPredicateBatch, CustomPredicateRef, D, F, Hash, RawValue,
+    C, CommonCircuitData, CustomPredicateBatch, CustomPredicateRef, D, F, Hash, Params, RawValue,
+    Statement, Value, containers::Dictionary, hash_str,
 };
 
 use crate::ProofType;
@@ -20,6 +24,30 @@ pub fn write_elems<const N: usize>(bytes: &mut Vec<u8>, elems: &[F; N]) {
     }
 }
 
+/// Writes `dict` in CBOR, after its length.
+fn write_dict(buffer: &mut Vec<u8>, dict: &Dictionary) {
+    let dict_bytes = minicbor_serde::to_vec(dict).expect("dict serialization");
+    buffer
+        .write_all(&(dict_bytes.len() as u64).to_le_bytes())
+        .expect("dict bytes length write");
+    buffer.write_all(&dict_bytes).expect("dict bytes write");
+}
+
+/// Reads a dictionary written by `write_dict`, advancing `bytes` past it.
+fn read_dict(bytes: &mut &[u8]) -> Result<Dictionary> {
+    let len = {
+        let mut buffer = [0; 8];
+        bytes.read_exact(&mut buffer)?;
+        u64::from_le_bytes(buffer) as usize
+    };
+    let dict_bytes = bytes
+        .get(..len)
+        .ok_or_else(|| anyhow!("dict bytes length {} out of bounds", len))?;
+    let dict = minicbor_serde::from_slice(dict_bytes)?;
+    *bytes = &bytes[len..];
+    Ok(dict)
+}
+
 pub fn read_elems<const N: usize>(bytes: &mut impl Read) -> Result<[F; N]> {
     let mut elems = [F::ZERO; N];
     let mut elem_bytes = [0; 8];
@@ -60,33 +88,71 @@ pub fn read_custom_predicate_ref(bytes: &mut impl Read) -> Result<CustomPredicat
 pub enum Payload {
     Create(PayloadCreate),
     Update(PayloadUpdate),
+    Snapshot(PayloadSnapshot),
 }
 
 const PAYLOAD_MAGIC: u16 = 0xad00;
 const PAYLOAD_TYPE_CREATE: u8 = 1;
 const PAYLOAD_TYPE_UPDATE: u8 = 2;
+const PAYLOAD_TYPE_SNAPSHOT: u8 = 3;
+// update of an AD whose update predicate commits to the epoch
+const PAYLOAD_TYPE_UPDATE_EPOCH: u8 = 4;
+// create of an AD that carries the fingerprint of the params of its proofs
+const PAYLOAD_TYPE_CREATE_PARAMS: u8 = 5;
+// update with an epoch that carries the commitment of the ops history of the AD, see `ops`
+const PAYLOAD_TYPE_UPDATE_OPS: u8 = 6;
+// updates with an epoch, without or with the ops root, that batch the updates before them, see
+// `PayloadUpdate::batch`
+const PAYLOAD_TYPE_UPDATE_BATCH: u8 = 7;
+const PAYLOAD_TYPE_UPDATE_BATCH_OPS: u8 = 8;
+// update with an epoch that discloses the op dictionaries of its updates, see
+// `PayloadUpdate::op_dicts`, with flags for the ops root and the batch
+const PAYLOAD_TYPE_UPDATE_OP_DICTS: u8 = 9;
+
+/// Compact hash of the `Params` the update proofs are built with.  The producer and the consumer
+/// of the payloads must agree on them for the proofs to verify, which they check by comparing
+/// their fingerprints.
+pub fn params_fingerprint(params: &Params) -> Hash {
+    hash_str(&serde_json::to_string(params).expect("params serialize"))
+}
 
 impl Payload {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    /// Encodes the payload.  Fails for an update without epoch that carries an ops root, a batch
+    /// or op dictionaries, which are keyed by the epoch.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
         let mut buffer = Vec::new();
         buffer
             .write_all(&PAYLOAD_MAGIC.to_le_bytes())
             .expect("vec write");
         match self {
             Self::Create(payload) => {
-                buffer
-                    .write_all(&PAYLOAD_TYPE_CREATE.to_le_bytes())
-                    .expect("vec write");
+                let type_ = match payload.params_fingerprint {
+                    Some(_) => PAYLOAD_TYPE_CREATE_PARAMS,
+                    None => PAYLOAD_TYPE_CREATE,
+                };
+                buffer.write_all(&type_.to_le_bytes()).expect("vec write");
                 payload.write_bytes(&mut buffer);
             }
             Self::Update(payload) => {
+                let type_ = match (payload.epoch, payload.ops_root, payload.batch.is_empty()) {
+                    (Some(_), _, _) if !payload.op_dicts.is_empty() => PAYLOAD_TYPE_UPDATE_OP_DICTS,
+                    (Some(_), Some(_), false) => PAYLOAD_TYPE_UPDATE_BATCH_OPS,
+                    (Some(_), None, false) => PAYLOAD_TYPE_UPDATE_BATCH,
+                    (Some(_), Some(_), true) => PAYLOAD_TYPE_UPDATE_OPS,
+                    (Some(_), None, true) => PAYLOAD_TYPE_UPDATE_EPOCH,
+                    (None, _, _) => PAYLOAD_TYPE_UPDATE,
+                };
+                buffer.write_all(&type_.to_le_bytes()).expect("vec write");
+                payload.write_bytes(&mut buffer)?;
+            }
+            Self::Snapshot(payload) => {
                 buffer
-                    .write_all(&PAYLOAD_TYPE_UPDATE.to_le_bytes())
+                    .write_all(&PAYLOAD_TYPE_SNAPSHOT.to_le_bytes())
                     .expect("vec write");
                 payload.write_bytes(&mut buffer);
             }
         }
-        buffer
+        Ok(buffer)
     }
 
     pub fn from_bytes(bytes: &[u8], common_data: &CommonCircuitData) -> Result<Self> {
@@ -105,8 +171,57 @@ impl Payload {
             u8::from_le_bytes(buffer)
         };
         Ok(match type_ {
-            PAYLOAD_TYPE_CREATE => Payload::Create(PayloadCreate::from_bytes(bytes)?),
-            PAYLOAD_TYPE_UPDATE => Payload::Update(PayloadUpdate::from_bytes(bytes, common_data)?),
+            PAYLOAD_TYPE_CREATE => Payload::Create(PayloadCreate::from_bytes(bytes, false)?),
+            PAYLOAD_TYPE_CREATE_PARAMS => Payload::Create(PayloadCreate::from_bytes(bytes, true)?),
+            PAYLOAD_TYPE_UPDATE => Payload::Update(PayloadUpdate::from_bytes(
+                bytes,
+                common_data,
+                false,
+                false,
+                false,
+                false,
+            )?),
+            PAYLOAD_TYPE_UPDATE_EPOCH => Payload::Update(PayloadUpdate::from_bytes(
+                bytes,
+                common_data,
+                true,
+                false,
+                false,
+                false,
+            )?),
+            PAYLOAD_TYPE_UPDATE_OPS => Payload::Update(PayloadUpdate::from_bytes(
+                bytes,
+                common_data,
+                true,
+                true,
+                false,
+                false,
+            )?),
+            PAYLOAD_TYPE_UPDATE_BATCH => Payload::Update(PayloadUpdate::from_bytes(
+                bytes,
+                common_data,
+                true,
+                false,
+                true,
+                false,
+            )?),
+            PAYLOAD_TYPE_UPDATE_BATCH_OPS => Payload::Update(PayloadUpdate::from_bytes(
+                bytes,
+                common_data,
+                true,
+                true,
+                true,
+                false,
+            )?),
+            PAYLOAD_TYPE_UPDATE_OP_DICTS => Payload::Update(PayloadUpdate::from_bytes(
+                bytes,
+                common_data,
+                true,
+                false,
+                false,
+                true,
+            )?),
+            PAYLOAD_TYPE_SNAPSHOT => Payload::Snapshot(PayloadSnapshot::from_bytes(bytes)?),
             t => return Err(anyhow!("Invalid payload type: {}", t)),
         })
     }
@@ -117,6 +232,9 @@ pub struct PayloadCreate {
     pub id: Hash,
     pub custom_predicate_ref: CustomPredicateRef,
     pub vds_root: Hash,
+    // `params_fingerprint` of the params of the update proofs, none for ADs created before it was
+    // published
+    pub params_fingerprint: Option<Hash>,
 }
 
 impl PayloadCreate {
@@ -124,17 +242,26 @@ impl PayloadCreate {
         write_elems(buffer, &self.id.0);
         write_custom_predicate_ref(buffer, &self.custom_predicate_ref);
         write_elems(buffer, &self.vds_root.0);
+        if let Some(params_fingerprint) = self.params_fingerprint {
+            write_elems(buffer, &params_fingerprint.0);
+        }
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    pub fn from_bytes(bytes: &[u8], with_params: bool) -> Result<Self> {
         let mut bytes = bytes;
         let id = Hash(read_elems(&mut bytes)?);
         let custom_predicate_ref = read_custom_predicate_ref(&mut bytes)?;
         let vds_root = Hash(read_elems(&mut bytes)?);
+        let params_fingerprint = if with_params {
+            Some(Hash(read_elems(&mut bytes)?))
+        } else {
+            None
+        };
         Ok(Self {
             id,
             custom_predicate_ref,
             vds_root,
+            params_fingerprint,
         })
     }
 }
@@ -170,8 +297,9 @@ impl PayloadProof {
         }
     }
     pub fn from_bytes(bytes: &[u8], common_data: &CommonCircuitData) -> Result<(Self, usize)> {
-        let proof_type = ProofType::from_byte(&bytes[0])?;
-        let bytes = &bytes[1..];
+        // the payloads come from the chain, so truncated ones are errors instead of panics
+        let (proof_type, bytes) = bytes.split_first().context("missing proof type")?;
+        let proof_type = ProofType::from_byte(proof_type)?;
         let (proof, len): (Self, usize) = match proof_type {
             ProofType::Plonky2 => {
                 let mut buffer = Buffer::new(bytes);
@@ -185,10 +313,14 @@ impl PayloadProof {
             }
             ProofType::Groth16 => {
                 // get the length
-                let len_bytes: [u8; 8] = bytes[0..8].try_into()?;
+                let len_bytes: [u8; 8] = bytes
+                    .get(0..8)
+                    .context("truncated Groth16 proof length")?
+                    .try_into()?;
                 let len: usize = u64::from_le_bytes(len_bytes) as usize;
                 // return the rest of bytes of the Groth16 proof
-                (PayloadProof::Groth16(bytes[8..8 + len].to_vec()), 8 + len)
+                let proof = bytes[8..].get(..len).context("truncated Groth16 proof")?;
+                (PayloadProof::Groth16(proof.to_vec()), 8 + len)
             }
         };
 
@@ -204,30 +336,243 @@ pub struct PayloadUpdate {
     pub proof: PayloadProof,
     pub new_state: RawValue,
     pub op: RawValue,
+    // num of the AD after the update, none for ADs registered with an update predicate that
+    // doesn't commit to it
+    pub epoch: Option<i64>,
+    // commitment of the ops history of the AD after the update (`ops`), keyed by the epoch so
+    // only published along with it.  None for the updates of ADs that don't publish their ops.
+    pub ops_root: Option<Hash>,
+    // updates of the AD before this one that the proof proves along with it, in order, so the
+    // first one is at epoch `epoch - batch.len()`.  Empty for an update proven alone, and only
+    // published along with the epoch.
+    pub batch: Vec<UpdateStep>,
+    // op dictionaries of the updates in `steps` order, disclosed so that the indexers can follow
+    // the contents of the AD, which each op commits to.  Empty if they aren't disclosed, and only
+    // published along with the epoch.
+    pub op_dicts: Vec<Dictionary>,
+}
+
+/// State and op of one of the updates of a batched update payload
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UpdateStep {
+    pub new_state: RawValue,
+    pub op: RawValue,
 }
 
 impl PayloadUpdate {
-    pub fn write_bytes(&self, buffer: &mut Vec<u8>) {
+    pub fn write_bytes(&self, buffer: &mut Vec<u8>) -> Result<()> {
+        if self.epoch.is_none()
+            && (self.ops_root.is_some() || !self.batch.is_empty() || !self.op_dicts.is_empty())
+        {
+            bail!("update payload without epoch with an ops root, a batch or op dictionaries");
+        }
         write_elems(buffer, &self.id.0);
         self.proof.write_bytes(buffer);
         write_elems(buffer, &self.new_state.0);
         write_elems(buffer, &self.op.0);
+        if let Some(epoch) = self.epoch {
+            buffer.write_all(&epoch.to_le_bytes()).expect("vec write");
+            if !self.op_dicts.is_empty() {
+                let flags =
+                    u8::from(self.ops_root.is_some()) | (u8::from(!self.batch.is_empty()) << 1);
+                buffer.write_all(&[flags]).expect("vec write");
+            }
+            if let Some(ops_root) = self.ops_root {
+                write_elems(buffer, &ops_root.0);
+            }
+            if !self.batch.is_empty() {
+                let batch_len = u16::try_from(self.batch.len())
+                    .with_context(|| format!("update batch of {} updates", self.batch.len()))?;
+                buffer
+                    .write_all(&batch_len.to_le_bytes())
+                    .expect("vec write");
+                for step in &self.batch {
+                    write_elems(buffer, &step.new_state.0);
+                    write_elems(buffer, &step.op.0);
+                }
+            }
+            if !self.op_dicts.is_empty() {
+                if self.op_dicts.len() as i64 != self.num_updates() {
+                    bail!(
+                        "{} op dictionaries for {} updates",
+                        self.op_dicts.len(),
+                        self.num_updates()
+                    );
+                }
+                for op_dict in &self.op_dicts {
+                    write_dict(buffer, op_dict);
+                }
+            }
+        }
+        Ok(())
     }
 
-    pub fn from_bytes(bytes: &[u8], common_data: &CommonCircuitData) -> Result<Self> {
+    /// Decodes an update payload.  With `with_op_dicts`, whether it has the ops root and the batch
+    /// is read from its flags instead of `with_ops` and `with_batch`.
+    pub fn from_bytes(
+        bytes: &[u8],
+        common_data: &CommonCircuitData,
+        with_epoch: bool,
+        with_ops: bool,
+        with_batch: bool,
+        with_op_dicts: bool,
+    ) -> Result<Self> {
         let mut bytes = bytes;
         let id = Hash(read_elems(&mut bytes)?);
         let (proof, len) = PayloadProof::from_bytes(bytes, common_data)?;
         bytes = &bytes[len..];
         let new_state = RawValue(read_elems(&mut bytes)?);
         let op = RawValue(read_elems(&mut bytes)?);
+        let epoch = if with_epoch {
+            let mut buffer = [0; 8];
+            bytes.read_exact(&mut buffer)?;
+            Some(i64::from_le_bytes(buffer))
+        } else {
+            None
+        };
+        let (with_ops, with_batch) = if with_op_dicts {
+            let mut flags = [0; 1];
+            bytes.read_exact(&mut flags)?;
+            (flags[0] & 1 != 0, flags[0] & 2 != 0)
+        } else {
+            (with_ops, with_batch)
+        };
+        let ops_root = if with_ops {
+            Some(Hash(read_elems(&mut bytes)?))
+        } else {
+            None
+        };
+        let mut batch = Vec::new();
+        if with_batch {
+            let mut buffer = [0; 2];
+            bytes.read_exact(&mut buffer)?;
+            let len = u16::from_le_bytes(buffer);
+            if len == 0 {
+                return Err(anyhow!("empty update batch"));
+            }
+            for _ in 0..len {
+                batch.push(UpdateStep {
+                    new_state: RawValue(read_elems(&mut bytes)?),
+                    op: RawValue(read_elems(&mut bytes)?),
+                });
+            }
+        }
+        let mut op_dicts = Vec::new();
+        if with_op_dicts {
+            for _ in 0..=batch.len() {
+                op_dicts.push(read_dict(&mut bytes)?);
+            }
+        }
         Ok(Self {
             id,
             proof,
             new_state,
             op,
+            epoch,
+            ops_root,
+            batch,
+            op_dicts,
         })
     }
+
+    /// Checks that the disclosed op dictionaries are the ones the updates commit to.
+    pub fn verify_op_dicts(&self) -> Result<()> {
+        for (step, op_dict) in self.steps().zip(&self.op_dicts) {
+            let commitment = RawValue::from(op_dict.commitment());
+            if commitment != step.op {
+                return Err(anyhow!(
+                    "op dict commitment {:?} != op {:?}",
+                    commitment,
+                    step.op
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of updates proven by the payload, the batched ones and the last one
+    pub fn num_updates(&self) -> i64 {
+        self.batch.len() as i64 + 1
+    }
+
+    /// Epoch of the first update proven by the payload, `epoch` unless it's batched
+    pub fn first_epoch(&self) -> Option<i64> {
+        self.epoch.map(|epoch| epoch - self.batch.len() as i64)
+    }
+
+    /// States and ops of the updates proven by the payload, in order
+    pub fn steps(&self) -> impl Iterator<Item = UpdateStep> + '_ {
+        self.batch.iter().cloned().chain(iter::once(UpdateStep {
+            new_state: self.new_state,
+            op: self.op,
+        }))
+    }
+
+    /// Statements proven by the update, the transitions from `old_state` under the update
+    /// predicate `custom_predicate_ref` of the AD: one per update, each from the state of the
+    /// previous one.
+    pub fn statements(
+        &self,
+        custom_predicate_ref: &CustomPredicateRef,
+        old_state: RawValue,
+    ) -> Vec<Statement> {
+        let mut old_state = old_state;
+        let mut epoch = self.first_epoch();
+        self.steps()
+            .map(|step| {
+                let mut args = vec![
+                    Value::from(step.new_state),
+                    Value::from(old_state),
+                    Value::from(step.op),
+                ];
+                if let Some(epoch) = epoch {
+                    args.push(Value::from(epoch));
+                }
+                old_state = step.new_state;
+                epoch = epoch.map(|epoch| epoch + 1);
+                Statement::Custom(custom_predicate_ref.clone(), args)
+            })
+            .collect()
+    }
+}
+
+/// Full contents of the state of an AD, published every few updates so that clients can bootstrap
+/// from it instead of only following the state commitments.  Valid only if the commitment of
+/// `dict` is `state`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PayloadSnapshot {
+    pub id: Hash,
+    pub state: RawValue,
+    pub dict: Dictionary,
+}
+
+impl PayloadSnapshot {
+    pub fn write_bytes(&self, buffer: &mut Vec<u8>) {
+        write_elems(buffer, &self.id.0);
+        write_elems(buffer, &self.state.0);
+        write_dict(buffer, &self.dict);
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut bytes = bytes;
+        let id = Hash(read_elems(&mut bytes)?);
+        let state = RawValue(read_elems(&mut bytes)?);
+        let dict = read_dict(&mut bytes)?;
+        Ok(Self { id, state, dict })
+    }
+
+    /// Checks that the dictionary matches the state commitment.
+    pub fn verify(&self) -> Result<()> {
+        let commitment = RawValue::from(self.dict.commitment());
+        if commitment != self.state {
+            return Err(anyhow!(
+                "snapshot dict commitment {:?} != state {:?}",
+                commitment,
+                self.state
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -235,28 +580,75 @@ mod tests {
     use std::collections::HashMap;
 
     use app::Op;
-    use plonky2::plonk::proof::CompressedProofWithPublicInputs;
     use pod2::{
-        backends::plonky2::{
-            basetypes::DEFAULT_VD_SET,
-            mainpod::{Prover, calculate_statements_hash},
-        },
+        backends::plonky2::{basetypes::DEFAULT_VD_SET, mainpod::Prover},
         frontend::MainPodBuilder,
-        middleware::{Params, Statement, Value, containers, containers::Dictionary},
+        middleware::{Value, containers, containers::Dictionary},
     };
 
     use super::*;
-    use crate::shrink::{ShrunkMainPodSetup, shrink_compress_pod};
+    use crate::shrink::{ShrunkMainPodSetup, shrink_compress_pod, verify_shrunk_update};
+
+    #[test]
+    fn test_payload_snapshot_roundtrip() -> Result<()> {
+        let params = Params::default();
+        let id = Hash([F(1), F(2), F(3), F(4)]);
+        let dict = Dictionary::new(
+            params.max_depth_mt_containers,
+            HashMap::from([("red".into(), Value::from("alice"))]),
+        )
+        .unwrap();
+        let payload = PayloadSnapshot {
+            id,
+            state: RawValue::from(dict.commitment()),
+            dict: dict.clone(),
+        };
+        payload.verify()?;
+
+        let mut bytes = Vec::new();
+        payload.write_bytes(&mut bytes);
+        assert_eq!(payload, PayloadSnapshot::from_bytes(&bytes)?);
+        assert!(PayloadSnapshot::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+        // dictionary not matching the state
+        let payload = PayloadSnapshot {
+            id,
+            state: RawValue::from(id),
+            dict,
+        };
+        assert!(payload.verify().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_params_fingerprint() {
+        let params = Params::default();
+        assert_eq!(
+            params_fingerprint(&params),
+            params_fingerprint(&params.clone())
+        );
+        // any field of the params changes it
+        let params_deeper = Params {
+            max_depth_mt_containers: params.max_depth_mt_containers + 1,
+            ..params.clone()
+        };
+        assert_ne!(
+            params_fingerprint(&params),
+            params_fingerprint(&params_deeper)
+        );
+    }
 
     #[test]
     fn test_payload_roundtrip() -> Result<()> {
+        #[cfg(feature = "groth16")]
         let test_groth = false; // set to false by default since it takes much longer
 
         let params = Params::default();
         println!("ShrunkMainPod setup");
-        let shrunk_main_pod_build = ShrunkMainPodSetup::new(&params).build().unwrap();
+        let shrunk_main_pod_build = ShrunkMainPodSetup::new(&params, false).build().unwrap();
         let common_data = &shrunk_main_pod_build.circuit_data.common;
-        let (state_predicates, _rev_predicates) = app::build_predicates(&params);
+        let state_predicates = app::build_predicates(&params).state;
         let id = Hash([F(1), F(2), F(3), F(4)]);
         let custom_predicate_ref = CustomPredicateRef {
             batch: CustomPredicateBatch::new_opaque(
@@ -271,22 +663,50 @@ mod tests {
             id,
             custom_predicate_ref: custom_predicate_ref.clone(),
             vds_root,
+            params_fingerprint: Some(params_fingerprint(&params)),
+        });
+        // create payload of an AD from before the fingerprint of the params
+        let payload_create_legacy = Payload::Create(PayloadCreate {
+            id,
+            custom_predicate_ref: custom_predicate_ref.clone(),
+            vds_root,
+            params_fingerprint: None,
         });
 
         println!("PayloadInit roundtrip");
-        let payload_create_bytes = payload_create.to_bytes();
-        let payload_create_decoded =
-            Payload::from_bytes(&payload_create_bytes, common_data).unwrap();
-        assert_eq!(payload_create, payload_create_decoded);
+        for payload_create in [&payload_create, &payload_create_legacy] {
+            let payload_create_bytes = payload_create.to_bytes()?;
+            let payload_create_decoded =
+                Payload::from_bytes(&payload_create_bytes, common_data).unwrap();
+            assert_eq!(payload_create, &payload_create_decoded);
+        }
+        assert_ne!(
+            payload_create.to_bytes()?,
+            payload_create_legacy.to_bytes()?
+        );
+
+        println!("PayloadSnapshot roundtrip");
+        let dict =
+            containers::Dictionary::new(params.max_depth_mt_containers, HashMap::new()).unwrap();
+        let payload_snapshot = Payload::Snapshot(PayloadSnapshot {
+            id,
+            state: RawValue::from(dict.commitment()),
+            dict,
+        });
+        let payload_snapshot_bytes = payload_snapshot.to_bytes()?;
+        let payload_snapshot_decoded =
+            Payload::from_bytes(&payload_snapshot_bytes, common_data).unwrap();
+        assert_eq!(payload_snapshot, payload_snapshot_decoded);
 
         let mut builder = MainPodBuilder::new(&params, vd_set);
-        let (state_predicates, _rev_predicates) = app::build_predicates(&params);
+        let state_predicates = app::build_predicates(&params).state;
         let mut helper = app::Helper::new(&mut builder, &state_predicates);
 
         let state =
             containers::Dictionary::new(params.max_depth_mt_containers, HashMap::new()).unwrap();
         let state_raw = RawValue::from(state.commitment());
-        let op = Dictionary::from(Op::Init);
+        let op = Op::Init.into_dict(&params, 1);
+        let op_dict = op.clone();
         let op_raw = RawValue::from(op.commitment());
         let (new_state, st_update) = helper.st_update(state.clone(), op).unwrap();
         let new_state_raw = RawValue::from(new_state.commitment());
@@ -302,13 +722,18 @@ mod tests {
         let shrunk_main_pod_proof =
             shrink_compress_pod(&shrunk_main_pod_build, pod.clone()).unwrap();
 
-        let payload_update = Payload::Update(PayloadUpdate {
+        let payload_update = PayloadUpdate {
             id,
             proof: PayloadProof::Plonky2(Box::new(shrunk_main_pod_proof.clone())),
             new_state: new_state_raw,
             op: op_raw,
-        });
+            epoch: Some(1),
+            ops_root: None,
+            batch: Vec::new(),
+            op_dicts: Vec::new(),
+        };
 
+        #[cfg(feature = "groth16")]
         let (g16_payload_update, g16_payload_update_bytes) = if test_groth {
             // load groth artifacts
             crate::groth::init()?;
@@ -322,47 +747,172 @@ mod tests {
                 proof: PayloadProof::Groth16(g16_proof),
                 new_state: new_state_raw,
                 op: op_raw,
+                epoch: Some(1),
+                ops_root: None,
+                batch: Vec::new(),
+                op_dicts: Vec::new(),
             });
-            (g16_payload_update.clone(), g16_payload_update.to_bytes())
+            (g16_payload_update.clone(), g16_payload_update.to_bytes()?)
         } else {
-            (payload_update.clone(), vec![])
+            (Payload::Update(payload_update.clone()), vec![])
         };
 
         println!("PayloadUpdate roundtrip");
-        let payload_update_bytes = payload_update.to_bytes();
-        let payload_update_decoded =
-            Payload::from_bytes(&payload_update_bytes, common_data).unwrap();
-        assert_eq!(payload_update, payload_update_decoded);
+        let mut ops = crate::ops::empty_ops();
+        crate::ops::insert_op(&mut ops, 1, op_raw)?;
+        let ops_root = Some(ops.commitment());
+        // a batch of the update 1 and this one as the update 2
+        let batch = vec![UpdateStep {
+            new_state: state_raw,
+            op: op_raw,
+        }];
+        let op_dicts = vec![op_dict.clone()];
+        let batch_op_dicts = vec![op_dict.clone(), op_dict.clone()];
+        for (epoch, ops_root, batch, op_dicts) in [
+            (Some(1), ops_root, Vec::new(), Vec::new()),
+            (Some(1), None, Vec::new(), Vec::new()),
+            (None, None, Vec::new(), Vec::new()),
+            (Some(2), ops_root, batch.clone(), Vec::new()),
+            (Some(2), None, batch.clone(), Vec::new()),
+            (Some(1), ops_root, Vec::new(), op_dicts.clone()),
+            (Some(1), None, Vec::new(), op_dicts.clone()),
+            (Some(2), ops_root, batch.clone(), batch_op_dicts.clone()),
+            (Some(2), None, batch.clone(), batch_op_dicts.clone()),
+        ] {
+            let payload_update = Payload::Update(PayloadUpdate {
+                epoch,
+                ops_root,
+                batch,
+                op_dicts,
+                ..payload_update.clone()
+            });
+            let payload_update_bytes = payload_update.to_bytes()?;
+            let payload_update_decoded =
+                Payload::from_bytes(&payload_update_bytes, common_data).unwrap();
+            assert_eq!(payload_update, payload_update_decoded);
+        }
+        // the ops root, the batch and the op dictionaries are keyed by the epoch
+        for (ops_root, batch, op_dicts) in [
+            (ops_root, Vec::new(), Vec::new()),
+            (None, batch.clone(), Vec::new()),
+            (None, Vec::new(), op_dicts.clone()),
+        ] {
+            let payload_update = Payload::Update(PayloadUpdate {
+                epoch: None,
+                ops_root,
+                batch,
+                op_dicts,
+                ..payload_update.clone()
+            });
+            assert!(payload_update.to_bytes().is_err());
+        }
+        // one op dictionary per update, each the one its op commits to
+        let payload_update_disclosed = PayloadUpdate {
+            op_dicts: op_dicts.clone(),
+            ..payload_update.clone()
+        };
+        payload_update_disclosed.verify_op_dicts()?;
+        let payload_update_other_op = PayloadUpdate {
+            op_dicts: vec![Op::Init.into_dict(&params, 2)],
+            ..payload_update.clone()
+        };
+        assert!(payload_update_other_op.verify_op_dicts().is_err());
+        let payload_update_missing_op = Payload::Update(PayloadUpdate {
+            epoch: Some(2),
+            batch,
+            op_dicts,
+            ..payload_update.clone()
+        });
+        assert!(payload_update_missing_op.to_bytes().is_err());
+        // the length of the batch is encoded in 16 bits
+        let step = UpdateStep {
+            new_state: state_raw,
+            op: op_raw,
+        };
+        let payload_update_long = Payload::Update(PayloadUpdate {
+            batch: vec![step; u16::MAX as usize + 1],
+            ..payload_update.clone()
+        });
+        assert!(payload_update_long.to_bytes().is_err());
+
+        println!("Truncated payloads");
+        // the decoding doesn't check the Groth16 proof, so arbitrary bytes do for the encoding
+        let payload_update_g16 = Payload::Update(PayloadUpdate {
+            proof: PayloadProof::Groth16(vec![7; 100]),
+            ..payload_update.clone()
+        });
+        let payload_update_g16_disclosed = Payload::Update(PayloadUpdate {
+            proof: PayloadProof::Groth16(vec![7; 100]),
+            ..payload_update_disclosed.clone()
+        });
+        for payload in [
+            &payload_create,
+            &payload_update_g16,
+            &payload_update_g16_disclosed,
+        ] {
+            let bytes = payload.to_bytes()?;
+            assert_eq!(&Payload::from_bytes(&bytes, common_data)?, payload);
+            for len in 0..bytes.len() {
+                assert!(Payload::from_bytes(&bytes[..len], common_data).is_err());
+            }
+        }
 
         // Verify the proof
 
         println!("Verify shrunk mainPod");
-        let st = Statement::Custom(
-            custom_predicate_ref,
-            vec![
-                Value::from(new_state_raw),
-                Value::from(state_raw),
-                Value::from(op_raw),
-            ],
-        );
-        println!("st: {st:?}");
-        let sts_hash = calculate_statements_hash(&[st.clone().into()], &params);
-        let public_inputs = [sts_hash.0, vds_root.0].concat();
-        let proof_with_pis = CompressedProofWithPublicInputs {
-            proof: shrunk_main_pod_proof,
-            public_inputs,
+        let sts = payload_update.statements(&custom_predicate_ref, state_raw);
+        println!("sts: {sts:?}");
+        let verifier_data = shrunk_main_pod_build.circuit_data.verifier_data();
+        verify_shrunk_update(
+            common_data,
+            &verifier_data,
+            &params,
+            vds_root,
+            &sts,
+            &shrunk_main_pod_proof,
+        )
+        .unwrap();
+        // the proof doesn't verify as a transition from another state, at another epoch, under
+        // the update predicate of another AD or as a batch of more updates
+        let other_custom_predicate_ref = CustomPredicateRef {
+            batch: CustomPredicateBatch::new_opaque(
+                "unknown".to_string(),
+                Hash([F(5), F(6), F(7), F(8)]),
+            ),
+            index: custom_predicate_ref.index,
         };
-        let proof = proof_with_pis
-            .decompress(
-                &shrunk_main_pod_build
-                    .circuit_data
-                    .verifier_only
-                    .circuit_digest,
-                &shrunk_main_pod_build.circuit_data.common,
-            )
-            .unwrap();
-        shrunk_main_pod_build.circuit_data.verify(proof).unwrap();
+        for sts in [
+            payload_update.statements(&custom_predicate_ref, new_state_raw),
+            payload_update.statements(&other_custom_predicate_ref, state_raw),
+            PayloadUpdate {
+                epoch: Some(2),
+                ..payload_update.clone()
+            }
+            .statements(&custom_predicate_ref, state_raw),
+            PayloadUpdate {
+                epoch: Some(2),
+                batch: vec![UpdateStep {
+                    new_state: state_raw,
+                    op: op_raw,
+                }],
+                ..payload_update.clone()
+            }
+            .statements(&custom_predicate_ref, state_raw),
+        ] {
+            assert!(
+                verify_shrunk_update(
+                    common_data,
+                    &verifier_data,
+                    &params,
+                    vds_root,
+                    &sts,
+                    &shrunk_main_pod_proof,
+                )
+                .is_err()
+            );
+        }
 
+        #[cfg(feature = "groth16")]
         if test_groth {
             let g16_payload_update_decoded =
                 // PayloadUpdate::from_bytes(&g16_payload_update_bytes, common_data).unwrap();
@@ -376,7 +926,7 @@ mod tests {
             );
 
             // prepare the public inputs for the groth16 verification
-            let pub_inp = pod2_onchain::prepare_public_inputs(&params, vds_root, &[st])?;
+            let pub_inp = pod2_onchain::prepare_public_inputs(&params, vds_root, &sts)?;
             // encode it as big-endian bytes compatible with Gnark
             let pub_inp_bytes = pod2_onchain::encode_public_inputs_gnark(pub_inp);
 
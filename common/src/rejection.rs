@@ -0,0 +1,93 @@
+//! JSON error bodies of the rejections raised by warp while matching a request, shared by the
+//! HTTP servers of the ad-server and the synchronizer.
+
+use std::convert::Infallible;
+
+use serde::{Deserialize, Serialize};
+use warp::{Rejection, Reply, http::StatusCode};
+
+use crate::{CustomError, InvalidRequest};
+
+/// Machine readable kind of a rejection of warp or of a `CustomError`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectionKind {
+    NotFound,
+    MethodNotAllowed,
+    // the body is over the `content_length_limit` of the endpoint
+    PayloadTooLarge,
+    // the JSON body doesn't deserialize
+    InvalidBody,
+    InvalidRequest,
+    Internal,
+}
+
+impl RejectionKind {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RejectionKind::NotFound => StatusCode::NOT_FOUND,
+            RejectionKind::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            RejectionKind::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            RejectionKind::InvalidBody | RejectionKind::InvalidRequest => StatusCode::BAD_REQUEST,
+            RejectionKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Error of a response, in its `ErrorBody`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RejectionInfo {
+    pub kind: RejectionKind,
+    pub message: String,
+}
+
+/// JSON body of the error responses of both servers, `{"error": {"kind": .., "message": ..}}`,
+/// with a `RejectionInfo` or the `ErrorInfo` of the ad-server, which has the same fields and
+/// more kinds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorBody<T> {
+    pub error: T,
+}
+
+/// Returns the kind and message of a rejection raised by warp or of a `CustomError`, none for the
+/// other custom rejections.
+pub fn find_rejection(err: &Rejection) -> Option<RejectionInfo> {
+    let (kind, message) = if err.is_not_found() {
+        (RejectionKind::NotFound, "route not found".to_string())
+    } else if let Some(err) = err.find::<warp::reject::MethodNotAllowed>() {
+        (RejectionKind::MethodNotAllowed, err.to_string())
+    } else if err.find::<warp::reject::PayloadTooLarge>().is_some() {
+        (
+            RejectionKind::PayloadTooLarge,
+            "request body is over the size limit of the endpoint".to_string(),
+        )
+    } else if let Some(err) = err.find::<warp::filters::body::BodyDeserializeError>() {
+        (RejectionKind::InvalidBody, err.to_string())
+    } else if let Some(err) = err.find::<warp::reject::InvalidQuery>() {
+        (RejectionKind::InvalidRequest, err.to_string())
+    } else if let Some(err) = err.find::<warp::reject::LengthRequired>() {
+        (RejectionKind::InvalidRequest, err.to_string())
+    } else if let Some(err) = err.find::<warp::reject::UnsupportedMediaType>() {
+        (RejectionKind::InvalidRequest, err.to_string())
+    } else if let Some(InvalidRequest(message)) = err.find::<InvalidRequest>() {
+        (RejectionKind::InvalidRequest, message.clone())
+    } else if let Some(CustomError(message)) = err.find::<CustomError>() {
+        (RejectionKind::Internal, message.clone())
+    } else {
+        return None;
+    };
+    Some(RejectionInfo { kind, message })
+}
+
+/// Converts rejections into a status code and a JSON `ErrorBody`
+pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
+    let info = find_rejection(&err).unwrap_or_else(|| RejectionInfo {
+        kind: RejectionKind::Internal,
+        message: format!("{:?}", err),
+    });
+    let status = info.kind.status();
+    Ok(warp::reply::with_status(
+        warp::reply::json(&ErrorBody { error: info }),
+        status,
+    ))
+}
@@ -1,12 +1,12 @@
 use std::time::Instant;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use itertools::Itertools;
 use plonky2::{
     iop::witness::{PartialWitness, WitnessWrite},
     plonk::{
         circuit_data::CircuitConfig,
-        proof::{CompressedProof, ProofWithPublicInputsTarget},
+        proof::{CompressedProof, CompressedProofWithPublicInputs, ProofWithPublicInputsTarget},
     },
 };
 use pod2::{
@@ -14,15 +14,23 @@ use pod2::{
         basetypes::{CircuitBuilder, CircuitData, Proof, ProofWithPublicInputs},
         mainpod::{
             cache_get_rec_main_pod_common_circuit_data,
-            cache_get_rec_main_pod_verifier_circuit_data,
+            cache_get_rec_main_pod_verifier_circuit_data, calculate_statements_hash,
         },
     },
-    middleware::{C, CommonCircuitData, D, F, Params, ToFields, VerifierCircuitData},
+    middleware::{
+        C, CommonCircuitData, D, F, Hash, Params, Statement, ToFields, VerifierCircuitData,
+    },
 };
 use tracing::info;
 
+/// Bump when the shrunk main pod circuit changes in a way that isn't captured by
+/// `ShrunkMainPodSetup::fingerprint`, to invalidate the cached circuit data.
+const SHRUNK_MAIN_POD_CIRCUIT_VERSION: u32 = 1;
+
 pub struct ShrunkMainPodSetup {
     params: Params,
+    // build the shrunk main pod circuit with zero-knowledge, which makes proving slower
+    zk: bool,
     main_pod_common_circuit_data: CommonCircuitData,
     main_pod_verifier_circuit_data: VerifierCircuitData,
 }
@@ -35,15 +43,33 @@ pub struct ShrunkMainPodBuild {
 }
 
 impl ShrunkMainPodSetup {
-    pub fn new(params: &Params) -> Self {
+    /// `zk` selects the zero-knowledge recursion config for the shrunk main pod circuit.  The
+    /// pods posted to ethereum carry public state, so `false` (faster proving) is usually fine.
+    /// Provers and verifiers must agree on `zk`, since it changes the circuit.
+    pub fn new(params: &Params, zk: bool) -> Self {
         let common_circuit_data = cache_get_rec_main_pod_common_circuit_data(params);
         let verifier_circuit_data = cache_get_rec_main_pod_verifier_circuit_data(params);
         Self {
             params: params.clone(),
+            zk,
             main_pod_common_circuit_data: (**common_circuit_data).clone(),
             main_pod_verifier_circuit_data: (**verifier_circuit_data).clone(),
         }
     }
+
+    /// Describes everything the shrunk main pod circuit is built from, to key cached circuit
+    /// data.  The digest of the main pod verifier circuit changes with the pod2 version.
+    pub fn fingerprint(&self) -> Result<String> {
+        Ok(format!(
+            "shrunk_main_pod v{} zk={} params={} main_pod_digest={:?}",
+            SHRUNK_MAIN_POD_CIRCUIT_VERSION,
+            self.zk,
+            serde_json::to_string(&self.params)?,
+            self.main_pod_verifier_circuit_data
+                .verifier_only
+                .circuit_digest
+        ))
+    }
 }
 
 pub struct ShrunkMainPodTarget {
@@ -90,7 +116,11 @@ impl ShrunkMainPodSetup {
     }
 
     pub fn build(&self) -> Result<ShrunkMainPodBuild> {
-        let config = CircuitConfig::standard_recursion_config();
+        let config = if self.zk {
+            CircuitConfig::standard_recursion_zk_config()
+        } else {
+            CircuitConfig::standard_recursion_config()
+        };
         let mut builder = CircuitBuilder::new(config);
         let shrunk_main_pod = self.new_virtual(&mut builder);
         self.verify_shrunk_mainpod_circuit(&mut builder, &shrunk_main_pod)?;
@@ -174,3 +204,29 @@ pub fn shrink_compress_pod(
     );
     Ok(compressed_proof)
 }
+
+/// Verifies a shrunk and compressed proof (as produced by `shrink_compress_pod`) of the update
+/// statements `sts` (see `PayloadUpdate::statements`) under the vd set `vds_root`.  `common_data`
+/// and `verifier_data` are the ones of the shrunk main pod circuit.
+pub fn verify_shrunk_update(
+    common_data: &CommonCircuitData,
+    verifier_data: &VerifierCircuitData,
+    params: &Params,
+    vds_root: Hash,
+    sts: &[Statement],
+    proof: &CompressedProof<F, C, D>,
+) -> Result<()> {
+    let sts_hash = calculate_statements_hash(
+        &sts.iter().cloned().map(Into::into).collect::<Vec<_>>(),
+        params,
+    );
+    let public_inputs = [sts_hash.0, vds_root.0].concat();
+    let proof_with_pis = CompressedProofWithPublicInputs {
+        proof: proof.clone(),
+        public_inputs,
+    };
+    let proof = proof_with_pis
+        .decompress(&verifier_data.verifier_only.circuit_digest, common_data)
+        .context("CompressedProofWithPublicInputs::decompress")?;
+    verifier_data.verify(proof)
+}
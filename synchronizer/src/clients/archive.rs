@@ -0,0 +1,217 @@
+use std::{collections::HashMap, fmt::Debug};
+
+use alloy::{
+    eips::eip4844::{
+        BlobTransactionSidecar, HeapBlob, env_settings::EnvKzgSettings, kzg_to_versioned_hash,
+    },
+    primitives::B256,
+};
+use anyhow::{Context as AnyhowContext, Result, anyhow};
+use async_trait::async_trait;
+use backoff::ExponentialBackoff;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::clients::{
+    beacon::types::{Blob, KzgCommitment, Proof},
+    common::{ClientResult, json_get, result_some},
+};
+
+/// Source of the blobs that the beacon node no longer serves.  Public beacon nodes only keep blob
+/// sidecars for ~18 days.
+#[async_trait]
+pub trait BlobArchive: Debug + Send + Sync {
+    async fn get_blob_by_versioned_hash(&self, versioned_hash: B256) -> ClientResult<Option<Blob>>;
+}
+
+/// Client of a blobscan compatible API
+#[derive(Debug, Clone)]
+pub struct BlobscanClient {
+    base_url: Url,
+    client: Client,
+    exp_backoff: Option<ExponentialBackoff>,
+}
+
+pub struct Config {
+    pub base_url: String,
+    pub exp_backoff: Option<ExponentialBackoff>,
+}
+
+#[derive(Deserialize, Debug)]
+struct BlobResponse {
+    commitment: KzgCommitment,
+    proof: Proof,
+    data: HeapBlob,
+}
+
+impl From<BlobResponse> for Blob {
+    fn from(res: BlobResponse) -> Self {
+        Blob {
+            // the archive doesn't give the position of the blob in its block, which
+            // `fill_missing_blobs` sets
+            index: 0,
+            kzg_commitment: res.commitment,
+            kzg_proof: res.proof,
+            blob: res.data,
+        }
+    }
+}
+
+impl BlobscanClient {
+    pub fn try_with_client(client: Client, config: Config) -> ClientResult<Self> {
+        let base_url = Url::parse(&format!("{}/", config.base_url.trim_end_matches('/')))
+            .with_context(|| "Failed to parse base URL")?;
+        let exp_backoff = config.exp_backoff;
+
+        Ok(Self {
+            base_url,
+            client,
+            exp_backoff,
+        })
+    }
+}
+
+#[async_trait]
+impl BlobArchive for BlobscanClient {
+    async fn get_blob_by_versioned_hash(&self, versioned_hash: B256) -> ClientResult<Option<Blob>> {
+        let path = format!("blobs/{}", versioned_hash);
+        let url = self.base_url.join(path.as_str())?;
+
+        result_some(
+            json_get::<BlobResponse>(
+                &self.client,
+                url,
+                None,
+                None,
+                self.exp_backoff.clone(),
+                None,
+            )
+            .await
+            .map(|res| res.into()),
+        )
+    }
+}
+
+/// Fetches from the archive the blobs identified by `indexed_vhs`, the versioned hashes with the
+/// index of their blob in the block, that are missing in `blobs`.  Blobs not found in the archive
+/// are left missing, and a blob whose commitment doesn't match the requested versioned hash, or
+/// whose KZG proof doesn't verify against its commitment, is an error.
+pub async fn fill_missing_blobs(
+    archive: &dyn BlobArchive,
+    blobs: &mut HashMap<B256, Blob>,
+    indexed_vhs: &[(B256, u32)],
+) -> Result<()> {
+    for (vh, index) in indexed_vhs {
+        if blobs.contains_key(vh) {
+            continue;
+        }
+        let Some(mut blob) = archive.get_blob_by_versioned_hash(*vh).await? else {
+            debug!("blob {} not found in archive", vh);
+            continue;
+        };
+        let blob_vh = kzg_to_versioned_hash(blob.kzg_commitment.as_ref());
+        if blob_vh != *vh {
+            return Err(anyhow!(
+                "archive returned blob with versioned hash {} for {}",
+                blob_vh,
+                vh
+            ));
+        }
+        let sidecar = BlobTransactionSidecar::new(
+            vec![blob.blob.inner().as_ref().try_into()?],
+            vec![blob.kzg_commitment],
+            vec![blob.kzg_proof],
+        );
+        sidecar
+            .validate(&[*vh], EnvKzgSettings::Default.get())
+            .map_err(|e| anyhow!("archive returned blob {} with invalid proof: {}", vh, e))?;
+        debug!("got blob {} from archive", vh);
+        blob.index = *index;
+        blobs.insert(*vh, blob);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloy::consensus::{SidecarBuilder, SimpleCoder};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct MockArchive {
+        blobs: HashMap<B256, Blob>,
+    }
+
+    #[async_trait]
+    impl BlobArchive for MockArchive {
+        async fn get_blob_by_versioned_hash(
+            &self,
+            versioned_hash: B256,
+        ) -> ClientResult<Option<Blob>> {
+            Ok(self.blobs.get(&versioned_hash).cloned())
+        }
+    }
+
+    fn blob(data: u8) -> (B256, Blob) {
+        let sidecar: BlobTransactionSidecar = SidecarBuilder::<SimpleCoder>::from_slice(&[data])
+            .build()
+            .unwrap();
+        let kzg_commitment = sidecar.commitments[0];
+        let blob = Blob {
+            index: 0,
+            kzg_commitment,
+            kzg_proof: sidecar.proofs[0],
+            blob: HeapBlob::new(sidecar.blobs[0].as_slice()).unwrap(),
+        };
+        (kzg_to_versioned_hash(kzg_commitment.as_ref()), blob)
+    }
+
+    #[tokio::test]
+    async fn test_fill_missing_blobs() -> Result<()> {
+        let (vh_beacon, blob_beacon) = blob(1);
+        let (vh_archive, blob_archive) = blob(2);
+        let (vh_missing, _) = blob(3);
+        let archive = MockArchive {
+            blobs: HashMap::from([(vh_archive, blob_archive)]),
+        };
+
+        // the beacon node only had one of the blobs
+        let mut blobs = HashMap::from([(vh_beacon, blob_beacon)]);
+        let indexed_vhs = [(vh_beacon, 0), (vh_archive, 2), (vh_missing, 3)];
+        fill_missing_blobs(&archive, &mut blobs, &indexed_vhs).await?;
+        assert!(blobs.contains_key(&vh_beacon));
+        assert_eq!(blobs[&vh_archive].index, 2);
+        assert!(!blobs.contains_key(&vh_missing));
+
+        // the archive returns a blob that doesn't match the versioned hash
+        let (_, blob_wrong) = blob(4);
+        let archive = MockArchive {
+            blobs: HashMap::from([(vh_missing, blob_wrong)]),
+        };
+        let mut blobs = HashMap::new();
+        assert!(
+            fill_missing_blobs(&archive, &mut blobs, &[(vh_missing, 0)])
+                .await
+                .is_err()
+        );
+        assert!(blobs.is_empty());
+
+        // the archive returns a blob whose proof doesn't verify against its commitment
+        let (_, blob_other) = blob(5);
+        let (vh_bad_proof, mut blob_bad_proof) = blob(6);
+        blob_bad_proof.kzg_proof = blob_other.kzg_proof;
+        let archive = MockArchive {
+            blobs: HashMap::from([(vh_bad_proof, blob_bad_proof)]),
+        };
+        assert!(
+            fill_missing_blobs(&archive, &mut blobs, &[(vh_bad_proof, 0)])
+                .await
+                .is_err()
+        );
+        assert!(blobs.is_empty());
+
+        Ok(())
+    }
+}
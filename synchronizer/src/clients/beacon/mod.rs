@@ -21,38 +21,116 @@
 
 pub mod types;
 
-use std::fmt::Debug;
+use std::{collections::HashMap, fmt::Debug, time::Duration};
 
-use anyhow::Context as AnyhowContext;
+use alloy::{eips::eip4844::kzg_to_versioned_hash, primitives::B256};
+use anyhow::{Context as AnyhowContext, Result, anyhow};
+use async_trait::async_trait;
 use backoff::ExponentialBackoff;
-use reqwest::{Client, Url};
+use reqwest::{
+    Client, RequestBuilder, Url,
+    header::{HeaderMap, HeaderName, HeaderValue},
+};
 use reqwest_eventsource::EventSource;
+use serde::de::DeserializeOwned;
+use tracing::debug;
 use types::BlockHeader;
 
 use self::types::{Blob, BlobsResponse, Block, BlockId, BlockResponse, Topic};
 use crate::clients::{
     beacon::types::{BlockHeaderResponse, Spec, SpecResponse},
-    common::{ClientError, ClientResult, json_get},
+    common::{ClientResult, json_get, result_some},
 };
 
+/// Beacon API requests of the synchronizer, implemented by `BeaconClient` and by the clients of
+/// `clients::fixtures` that record and replay its responses.
+#[async_trait]
+pub trait CommonBeaconClient: Debug + Send + Sync {
+    async fn get_block(&self, block_id: BlockId) -> ClientResult<Option<Block>>;
+
+    async fn get_block_header(&self, block_id: BlockId) -> ClientResult<Option<BlockHeader>>;
+
+    async fn get_blobs(&self, block_id: BlockId) -> ClientResult<Vec<Blob>>;
+
+    async fn get_spec(&self) -> ClientResult<Spec>;
+
+    /// Returns the blobs of `block_id` identified by `versioned_hashes`.  The commitments of the
+    /// block are consensus data so its blobs exist, but some nodes omit sidecars from their
+    /// responses: while a blob is missing the request is retried up to `retries` times, doubling
+    /// `delay` every time, and then the last (incomplete) response is returned.  The same blob
+    /// can be posted twice in a block, in which case the sidecar with the lowest index is kept.
+    async fn get_blobs_by_versioned_hash(
+        &self,
+        block_id: BlockId,
+        versioned_hashes: &[B256],
+        retries: u32,
+        mut delay: Duration,
+    ) -> ClientResult<HashMap<B256, Blob>> {
+        let mut attempt = 0;
+        loop {
+            let mut blobs = HashMap::new();
+            // sorted by index
+            for blob in self.get_blobs(block_id.clone()).await? {
+                let versioned_hash = kzg_to_versioned_hash(blob.kzg_commitment.as_ref());
+                if versioned_hashes.contains(&versioned_hash) {
+                    blobs.entry(versioned_hash).or_insert(blob);
+                }
+            }
+            let missing = versioned_hashes
+                .iter()
+                .filter(|vh| !blobs.contains_key(*vh))
+                .count();
+            if missing == 0 || attempt == retries {
+                return Ok(blobs);
+            }
+            attempt += 1;
+            debug!(
+                "{} blobs missing in the sidecars of {}, retry {}/{} in {:?}",
+                missing, block_id, attempt, retries, delay
+            );
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BeaconClient {
     base_url: Url,
     client: Client,
     exp_backoff: Option<ExponentialBackoff>,
+    // sent as `Authorization: Bearer {auth_token}`
+    auth_token: Option<String>,
+    // sent in every request, for APIs authenticated with headers like `x-api-key`
+    extra_headers: HeaderMap,
+    timeout: Option<Duration>,
+    blob_timeout: Option<Duration>,
 }
 
 pub struct Config {
     pub base_url: String,
     pub exp_backoff: Option<ExponentialBackoff>,
+    pub auth_token: Option<String>,
+    pub extra_headers: Vec<(String, String)>,
+    // timeout of the blob sidecars requests, whose responses are up to 6 blobs of 128 KiB
+    // encoded in hex, and of the other requests.  None keeps the timeout of the reqwest client.
+    pub blob_timeout: Option<Duration>,
+    pub timeout: Option<Duration>,
 }
 
-fn result_some<T>(r: ClientResult<T>) -> ClientResult<Option<T>> {
-    match r {
-        Ok(r) => Ok(Some(r)),
-        Err(ClientError::NotFound(_)) => Ok(None),
-        Err(e) => Err(e),
-    }
+/// Parses a comma separated list of `name:value` headers.
+pub fn parse_extra_headers(headers: &str) -> Result<Vec<(String, String)>> {
+    headers
+        .split(',')
+        .map(str::trim)
+        .filter(|header| !header.is_empty())
+        .map(|header| {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| anyhow!("invalid header {:?}, expected name:value", header))?;
+            Ok((name.trim().to_string(), value.trim().to_string()))
+        })
+        .collect()
 }
 
 impl BeaconClient {
@@ -60,67 +138,251 @@ impl BeaconClient {
         let base_url = Url::parse(&format!("{}/eth/", config.base_url))
             .with_context(|| "Failed to parse base URL")?;
         let exp_backoff = config.exp_backoff;
+        let mut extra_headers = HeaderMap::new();
+        for (name, value) in config.extra_headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("Invalid header name {:?}", name))?;
+            let value = HeaderValue::from_str(&value)
+                .with_context(|| format!("Invalid value of header {}", name))?;
+            extra_headers.insert(name, value);
+        }
 
         Ok(Self {
             base_url,
             client,
             exp_backoff,
+            auth_token: config.auth_token,
+            extra_headers,
+            timeout: config.timeout,
+            blob_timeout: config.blob_timeout,
         })
     }
 
-    pub async fn get_block(&self, block_id: BlockId) -> ClientResult<Option<Block>> {
+    async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: Url,
+        timeout: Option<Duration>,
+    ) -> ClientResult<T> {
+        json_get::<T>(
+            &self.client,
+            url,
+            self.auth_token.as_deref(),
+            Some(&self.extra_headers),
+            self.exp_backoff.clone(),
+            timeout,
+        )
+        .await
+    }
+
+    fn request(&self, url: Url) -> RequestBuilder {
+        let mut req = self.client.get(url).headers(self.extra_headers.clone());
+        if let Some(auth_token) = &self.auth_token {
+            req = req.bearer_auth(auth_token);
+        }
+        req
+    }
+
+    pub fn subscribe_to_events(&self, topics: &[Topic]) -> ClientResult<EventSource> {
+        let topics = topics
+            .iter()
+            .map(|topic| topic.into())
+            .collect::<Vec<String>>()
+            .join(",");
+        let path = format!("v1/events?topics={topics}");
+        let url = self.base_url.join(&path)?;
+
+        EventSource::new(self.request(url)).map_err(|e| anyhow!("{}", e).into())
+    }
+}
+
+#[async_trait]
+impl CommonBeaconClient for BeaconClient {
+    async fn get_block(&self, block_id: BlockId) -> ClientResult<Option<Block>> {
         let path = format!("v2/beacon/blocks/{}", { block_id.to_detailed_string() });
         let url = self.base_url.join(path.as_str())?;
 
         result_some(
-            json_get::<BlockResponse>(&self.client, url, None, self.exp_backoff.clone())
+            self.get_json::<BlockResponse>(url, self.timeout)
                 .await
                 .map(|res| res.into()),
         )
     }
 
-    pub async fn get_block_header(&self, block_id: BlockId) -> ClientResult<Option<BlockHeader>> {
+    async fn get_block_header(&self, block_id: BlockId) -> ClientResult<Option<BlockHeader>> {
         let path = format!("v1/beacon/headers/{}", { block_id.to_detailed_string() });
         let url = self.base_url.join(path.as_str())?;
 
         result_some(
-            json_get::<BlockHeaderResponse>(&self.client, url, None, self.exp_backoff.clone())
+            self.get_json::<BlockHeaderResponse>(url, self.timeout)
                 .await
                 .map(|res| res.into()),
         )
     }
 
-    pub async fn get_blobs(&self, block_id: BlockId) -> ClientResult<Vec<Blob>> {
+    async fn get_blobs(&self, block_id: BlockId) -> ClientResult<Vec<Blob>> {
         let path = format!("v1/beacon/blob_sidecars/{}", {
             block_id.to_detailed_string()
         });
         let url = self.base_url.join(path.as_str())?;
 
-        let mut blobs =
-            json_get::<BlobsResponse>(&self.client, url, None, self.exp_backoff.clone())
-                .await
-                .map(|res| res.data)?;
+        let mut blobs = self
+            .get_json::<BlobsResponse>(url, self.blob_timeout)
+            .await
+            .map(|res| res.data)?;
         blobs.sort_by_key(|blob| blob.index);
         Ok(blobs)
     }
 
-    pub async fn get_spec(&self) -> ClientResult<Spec> {
+    async fn get_spec(&self) -> ClientResult<Spec> {
         let url = self.base_url.join("v1/config/spec")?;
 
-        json_get::<SpecResponse>(&self.client, url, None, self.exp_backoff.clone())
+        self.get_json::<SpecResponse>(url, self.timeout)
             .await
             .map(|res| res.data)
     }
+}
 
-    pub fn subscribe_to_events(&self, topics: &[Topic]) -> ClientResult<EventSource> {
-        let topics = topics
-            .iter()
-            .map(|topic| topic.into())
-            .collect::<Vec<String>>()
-            .join(",");
-        let path = format!("v1/events?topics={topics}");
-        let url = self.base_url.join(&path)?;
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use alloy::eips::eip4844::HeapBlob;
+    use warp::Filter;
+
+    use super::*;
+    use crate::clients::beacon::types::{KzgCommitment, Proof};
+
+    #[test]
+    fn test_parse_extra_headers() -> Result<()> {
+        assert_eq!(
+            parse_extra_headers("x-api-key: abc, x-other:d:e,")?,
+            vec![
+                ("x-api-key".to_string(), "abc".to_string()),
+                ("x-other".to_string(), "d:e".to_string())
+            ]
+        );
+        assert!(parse_extra_headers("")?.is_empty());
+        assert!(parse_extra_headers("x-api-key").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auth_headers() -> Result<()> {
+        // the mock beacon node only serves requests with both headers
+        let spec = warp::path!("eth" / "v1" / "config" / "spec")
+            .and(warp::header::exact("authorization", "Bearer secret"))
+            .and(warp::header::exact("x-api-key", "abc"))
+            .map(|| warp::reply::json(&serde_json::json!({"data": {"DEPOSIT_NETWORK_ID": "1"}})));
+        let (addr, server) = warp::serve(spec).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let config = |auth_token: Option<&str>| Config {
+            base_url: format!("http://{}", addr),
+            exp_backoff: None,
+            auth_token: auth_token.map(|t| t.to_string()),
+            extra_headers: vec![("x-api-key".to_string(), "abc".to_string())],
+            blob_timeout: None,
+            timeout: None,
+        };
+        let client = BeaconClient::try_with_client(Client::new(), config(Some("secret")))?;
+        assert_eq!(client.get_spec().await?.deposit_network_id, 1);
+
+        let client = BeaconClient::try_with_client(Client::new(), config(None))?;
+        assert!(client.get_spec().await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() -> Result<()> {
+        // the mock beacon node takes a while to answer every request
+        let slow = |body: serde_json::Value| async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            Ok::<_, Infallible>(warp::reply::json(&body))
+        };
+        let spec = warp::path!("eth" / "v1" / "config" / "spec")
+            .and_then(move || slow(serde_json::json!({"data": {"DEPOSIT_NETWORK_ID": "1"}})));
+        let sidecars = warp::path!("eth" / "v1" / "beacon" / "blob_sidecars" / String)
+            .and_then(move |_| slow(serde_json::json!({"data": []})));
+        let (addr, server) = warp::serve(spec.or(sidecars)).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+
+        let client = BeaconClient::try_with_client(
+            Client::new(),
+            Config {
+                base_url: format!("http://{}", addr),
+                exp_backoff: None,
+                auth_token: None,
+                extra_headers: Vec::new(),
+                blob_timeout: Some(Duration::from_secs(5)),
+                timeout: Some(Duration::from_millis(50)),
+            },
+        )?;
+        assert!(client.get_blobs(BlockId::Slot(7)).await?.is_empty());
+        assert!(client.get_spec().await.is_err());
+        Ok(())
+    }
+
+    fn blob(index: u32, commitment_byte: u8) -> Blob {
+        Blob {
+            index,
+            kzg_commitment: KzgCommitment::from([commitment_byte; 48]),
+            kzg_proof: Proof::default(),
+            blob: HeapBlob::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_blobs_by_versioned_hash() -> Result<()> {
+        // the mock beacon node omits the blob at index 2 from its first 2 responses, and serves
+        // the blob at index 1 twice
+        let requests = Arc::new(AtomicUsize::new(0));
+        let sidecars = {
+            let requests = requests.clone();
+            warp::path!("eth" / "v1" / "beacon" / "blob_sidecars" / String).map(move |_| {
+                let mut blobs = vec![blob(3, 1), blob(0, 1), blob(1, 2)];
+                if requests.fetch_add(1, Ordering::SeqCst) >= 2 {
+                    blobs.push(blob(2, 3));
+                }
+                warp::reply::json(&serde_json::json!({ "data": blobs }))
+            })
+        };
+        let (addr, server) = warp::serve(sidecars).bind_ephemeral(([127, 0, 0, 1], 0));
+        tokio::spawn(server);
+        let client = BeaconClient::try_with_client(
+            Client::new(),
+            Config {
+                base_url: format!("http://{}", addr),
+                exp_backoff: None,
+                auth_token: None,
+                extra_headers: Vec::new(),
+                blob_timeout: None,
+                timeout: None,
+            },
+        )?;
 
-        Ok(EventSource::get(url))
+        let vh = |commitment_byte| kzg_to_versioned_hash(&[commitment_byte; 48]);
+        let versioned_hashes = [vh(1), vh(3)];
+        let delay = Duration::from_millis(1);
+        // not enough retries
+        let blobs = client
+            .get_blobs_by_versioned_hash(BlockId::Slot(7), &versioned_hashes, 1, delay)
+            .await?;
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+        assert!(!blobs.contains_key(&vh(3)));
+        // the third response is complete
+        let blobs = client
+            .get_blobs_by_versioned_hash(BlockId::Slot(7), &versioned_hashes, 3, delay)
+            .await?;
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert_eq!(blobs.len(), 2);
+        assert_eq!((blobs[&vh(1)].index, blobs[&vh(3)].index), (0, 2));
+        Ok(())
     }
 }
@@ -26,7 +26,7 @@ use std::{fmt, str::FromStr};
 use alloy::{consensus::Bytes48, eips::eip4844::HeapBlob, primitives::B256};
 use serde::{Deserialize, Serialize, Serializer};
 
-use super::BeaconClient;
+use super::CommonBeaconClient;
 use crate::clients::common::ClientError;
 
 pub type KzgCommitment = Bytes48;
@@ -37,7 +37,7 @@ pub type Proof = Bytes48;
 pub enum BlockId {
     Head,
     Finalized,
-    Slot(u32),
+    Slot(u64),
     Hash(B256),
 }
 
@@ -62,26 +62,30 @@ pub struct SpecResponse {
     pub data: Spec,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Spec {
-    #[serde(rename = "DEPOSIT_NETWORK_ID", deserialize_with = "deserialize_u64")]
+    #[serde(
+        rename = "DEPOSIT_NETWORK_ID",
+        deserialize_with = "deserialize_u64",
+        serialize_with = "serialize_u64"
+    )]
     pub deposit_network_id: u64,
 }
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct Block {
     pub blob_kzg_commitments: Option<Vec<KzgCommitment>>,
     pub execution_payload: Option<ExecutionPayload>,
     pub parent_root: B256,
-    #[serde(deserialize_with = "deserialize_u32")]
-    pub slot: u32,
+    #[serde(deserialize_with = "deserialize_u64", serialize_with = "serialize_u64")]
+    pub slot: u64,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug)]
 pub struct ExecutionPayload {
     pub block_hash: B256,
-    #[serde(deserialize_with = "deserialize_u32")]
+    #[serde(deserialize_with = "deserialize_u32", serialize_with = "serialize_u32")]
     pub block_number: u32,
-    #[serde(deserialize_with = "deserialize_u64")]
+    #[serde(deserialize_with = "deserialize_u64", serialize_with = "serialize_u64")]
     pub timestamp: u64,
 }
 
@@ -94,8 +98,8 @@ pub struct BlockBody {
 pub struct BlockMessage {
     pub body: BlockBody,
     pub parent_root: B256,
-    #[serde(deserialize_with = "deserialize_u32")]
-    pub slot: u32,
+    #[serde(deserialize_with = "deserialize_u64")]
+    pub slot: u64,
 }
 
 #[derive(Deserialize, Debug)]
@@ -108,7 +112,7 @@ pub struct BlockResponse {
     pub data: BlockData,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Blob {
     #[serde(deserialize_with = "deserialize_u32", serialize_with = "serialize_u32")]
     pub index: u32,
@@ -127,11 +131,11 @@ pub struct BlockHeaderResponse {
     pub data: BlockHeaderData,
 }
 
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BlockHeader {
     pub root: B256,
     pub parent_root: B256,
-    pub slot: u32,
+    pub slot: u64,
 }
 
 #[derive(Deserialize, Debug)]
@@ -147,14 +151,14 @@ pub struct InnerBlockHeader {
 #[derive(Deserialize, Debug)]
 pub struct BlockHeaderMessage {
     pub parent_root: B256,
-    #[serde(deserialize_with = "deserialize_u32")]
-    pub slot: u32,
+    #[serde(deserialize_with = "deserialize_u64")]
+    pub slot: u64,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct HeadEventData {
-    #[serde(deserialize_with = "deserialize_u32")]
-    pub slot: u32,
+    #[serde(deserialize_with = "deserialize_u64")]
+    pub slot: u64,
     #[allow(dead_code)]
     pub block: B256,
 }
@@ -171,6 +175,13 @@ where
     serializer.serialize_str(&v.to_string())
 }
 
+fn serialize_u64<S>(v: &u64, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(&v.to_string())
+}
+
 fn deserialize_u32<'de, D>(deserializer: D) -> Result<u32, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -218,7 +229,7 @@ impl FromStr for BlockId {
         match s {
             "head" => Ok(BlockId::Head),
             "finalized" => Ok(BlockId::Finalized),
-            _ => match s.parse::<u32>() {
+            _ => match s.parse::<u64>() {
                 Ok(num) => Ok(BlockId::Slot(num)),
                 Err(_) => {
                     if s.starts_with("0x") {
@@ -252,8 +263,8 @@ impl From<B256> for BlockId {
     }
 }
 
-impl From<u32> for BlockId {
-    fn from(value: u32) -> Self {
+impl From<u64> for BlockId {
+    fn from(value: u64) -> Self {
         BlockId::Slot(value)
     }
 }
@@ -294,7 +305,7 @@ pub enum BlockIdResolutionError {
 impl BlockId {
     async fn resolve_to_slot(
         &self,
-        beacon_client: &BeaconClient,
+        beacon_client: &dyn CommonBeaconClient,
     ) -> Result<u32, BlockIdResolutionError> {
         match self {
             BlockId::Slot(slot) => Ok(*slot),
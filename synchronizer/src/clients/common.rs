@@ -19,10 +19,10 @@
 // CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::{fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr, time::Duration};
 
 use backoff::ExponentialBackoff;
-use reqwest::{Client, Url};
+use reqwest::{Client, Url, header::HeaderMap};
 use serde::{Deserialize, de::DeserializeOwned};
 use tracing::trace;
 
@@ -139,11 +139,23 @@ impl Display for ErrorResponse {
     }
 }
 
+/// Maps a NotFound error to `None`
+pub(crate) fn result_some<T>(r: ClientResult<T>) -> ClientResult<Option<T>> {
+    match r {
+        Ok(r) => Ok(Some(r)),
+        Err(ClientError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 pub(crate) async fn json_get<ExpectedResponse: DeserializeOwned>(
     client: &Client,
     url: Url,
     auth_token: Option<&str>,
+    headers: Option<&HeaderMap>,
     exp_backoff: Option<ExponentialBackoff>,
+    // overrides the timeout of `client`, until the whole body is received
+    timeout: Option<Duration>,
 ) -> Result<ExpectedResponse, ClientError> {
     let auth_token = auth_token.unwrap_or("");
     trace!(
@@ -157,6 +169,12 @@ pub(crate) async fn json_get<ExpectedResponse: DeserializeOwned>(
     if !auth_token.is_empty() {
         req = req.bearer_auth(auth_token);
     }
+    if let Some(headers) = headers {
+        req = req.headers(headers.clone());
+    }
+    if let Some(timeout) = timeout {
+        req = req.timeout(timeout);
+    }
 
     let resp = if let Some(e) = exp_backoff {
         match backoff::future::retry_notify(
@@ -0,0 +1,41 @@
+use std::fmt::Debug;
+
+use alloy::{
+    eips::BlockId,
+    primitives::B256,
+    providers::{Provider, RootProvider},
+    rpc::types::{Block, Transaction},
+};
+use async_trait::async_trait;
+
+use crate::clients::common::{ClientError, ClientResult};
+
+/// Execution RPC requests of the synchronizer, implemented by `RootProvider` and by the clients
+/// of `clients::fixtures` that record and replay its responses.
+#[async_trait]
+pub trait ExecutionClient: Debug + Send + Sync {
+    /// Returns the block with hash `block_hash`, with its full txs.
+    async fn get_block(&self, block_hash: B256) -> ClientResult<Option<Block>>;
+
+    async fn get_transaction_by_hash(&self, tx_hash: B256) -> ClientResult<Option<Transaction>>;
+}
+
+fn rpc_error(err: impl std::error::Error + Send + Sync + 'static) -> ClientError {
+    ClientError::Other(err.into())
+}
+
+#[async_trait]
+impl ExecutionClient for RootProvider {
+    async fn get_block(&self, block_hash: B256) -> ClientResult<Option<Block>> {
+        Provider::get_block(self, BlockId::Hash(block_hash.into()))
+            .full()
+            .await
+            .map_err(rpc_error)
+    }
+
+    async fn get_transaction_by_hash(&self, tx_hash: B256) -> ClientResult<Option<Transaction>> {
+        Provider::get_transaction_by_hash(self, tx_hash)
+            .await
+            .map_err(rpc_error)
+    }
+}
@@ -0,0 +1,266 @@
+//! Record and replay of the responses of the beacon and execution clients, to run the
+//! synchronizer offline against fixtures instead of live nodes.
+//!
+//! Every response is stored as a JSON file named after the request and its argument, under
+//! `beacon/` and `execution/` in the fixtures directory, e.g. `beacon/get_block_header-123.json`.
+//! Only the successful responses are recorded: replaying a request without a fixture fails, like
+//! a request that failed while recording would.
+
+use std::{
+    fs::{create_dir_all, read, write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use alloy::{
+    primitives::B256,
+    rpc::types::{Block as ExecutionBlock, Transaction},
+};
+use anyhow::Context;
+use async_trait::async_trait;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::clients::{
+    beacon::{
+        CommonBeaconClient,
+        types::{Blob, Block, BlockHeader, BlockId, Spec},
+    },
+    common::ClientResult,
+    execution::ExecutionClient,
+};
+
+/// Directory of the fixtures of one client
+#[derive(Debug, Clone)]
+struct Fixtures {
+    dir: PathBuf,
+}
+
+impl Fixtures {
+    fn new(dir: &Path, client: &str) -> Self {
+        Self {
+            dir: dir.join(client),
+        }
+    }
+
+    fn path(&self, request: &str, arg: &str) -> PathBuf {
+        let name = match arg {
+            "" => request.to_string(),
+            _ => format!("{}-{}", request, arg),
+        };
+        self.dir.join(format!("{}.json", name))
+    }
+
+    fn save<T: Serialize>(&self, request: &str, arg: &str, response: &T) -> ClientResult<()> {
+        create_dir_all(&self.dir).with_context(|| format!("{:?}", self.dir))?;
+        let path = self.path(request, arg);
+        write(&path, serde_json::to_vec_pretty(response)?)
+            .with_context(|| format!("{:?}", path))?;
+        Ok(())
+    }
+
+    fn load<T: DeserializeOwned>(&self, request: &str, arg: &str) -> ClientResult<T> {
+        let path = self.path(request, arg);
+        let data = read(&path).with_context(|| format!("no fixture {:?}", path))?;
+        Ok(serde_json::from_slice(&data)?)
+    }
+}
+
+/// Beacon client that stores the responses of `inner` as fixtures
+#[derive(Debug)]
+pub struct RecordingBeaconClient {
+    inner: Arc<dyn CommonBeaconClient>,
+    fixtures: Fixtures,
+}
+
+impl RecordingBeaconClient {
+    pub fn new(inner: Arc<dyn CommonBeaconClient>, dir: &Path) -> Self {
+        Self {
+            inner,
+            fixtures: Fixtures::new(dir, "beacon"),
+        }
+    }
+}
+
+#[async_trait]
+impl CommonBeaconClient for RecordingBeaconClient {
+    async fn get_block(&self, block_id: BlockId) -> ClientResult<Option<Block>> {
+        let block = self.inner.get_block(block_id.clone()).await?;
+        self.fixtures
+            .save("get_block", &block_id.to_detailed_string(), &block)?;
+        Ok(block)
+    }
+
+    async fn get_block_header(&self, block_id: BlockId) -> ClientResult<Option<BlockHeader>> {
+        let header = self.inner.get_block_header(block_id.clone()).await?;
+        self.fixtures
+            .save("get_block_header", &block_id.to_detailed_string(), &header)?;
+        Ok(header)
+    }
+
+    async fn get_blobs(&self, block_id: BlockId) -> ClientResult<Vec<Blob>> {
+        let blobs = self.inner.get_blobs(block_id.clone()).await?;
+        self.fixtures
+            .save("get_blobs", &block_id.to_detailed_string(), &blobs)?;
+        Ok(blobs)
+    }
+
+    async fn get_spec(&self) -> ClientResult<Spec> {
+        let spec = self.inner.get_spec().await?;
+        self.fixtures.save("get_spec", "", &spec)?;
+        Ok(spec)
+    }
+}
+
+/// Beacon client that serves the fixtures stored by `RecordingBeaconClient`
+#[derive(Debug)]
+pub struct ReplayBeaconClient {
+    fixtures: Fixtures,
+}
+
+impl ReplayBeaconClient {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            fixtures: Fixtures::new(dir, "beacon"),
+        }
+    }
+}
+
+#[async_trait]
+impl CommonBeaconClient for ReplayBeaconClient {
+    async fn get_block(&self, block_id: BlockId) -> ClientResult<Option<Block>> {
+        self.fixtures
+            .load("get_block", &block_id.to_detailed_string())
+    }
+
+    async fn get_block_header(&self, block_id: BlockId) -> ClientResult<Option<BlockHeader>> {
+        self.fixtures
+            .load("get_block_header", &block_id.to_detailed_string())
+    }
+
+    async fn get_blobs(&self, block_id: BlockId) -> ClientResult<Vec<Blob>> {
+        self.fixtures
+            .load("get_blobs", &block_id.to_detailed_string())
+    }
+
+    async fn get_spec(&self) -> ClientResult<Spec> {
+        self.fixtures.load("get_spec", "")
+    }
+}
+
+/// Execution client that stores the responses of `inner` as fixtures
+#[derive(Debug)]
+pub struct RecordingExecutionClient {
+    inner: Arc<dyn ExecutionClient>,
+    fixtures: Fixtures,
+}
+
+impl RecordingExecutionClient {
+    pub fn new(inner: Arc<dyn ExecutionClient>, dir: &Path) -> Self {
+        Self {
+            inner,
+            fixtures: Fixtures::new(dir, "execution"),
+        }
+    }
+}
+
+#[async_trait]
+impl ExecutionClient for RecordingExecutionClient {
+    async fn get_block(&self, block_hash: B256) -> ClientResult<Option<ExecutionBlock>> {
+        let block = self.inner.get_block(block_hash).await?;
+        self.fixtures
+            .save("get_block", &block_hash.to_string(), &block)?;
+        Ok(block)
+    }
+
+    async fn get_transaction_by_hash(&self, tx_hash: B256) -> ClientResult<Option<Transaction>> {
+        let tx = self.inner.get_transaction_by_hash(tx_hash).await?;
+        self.fixtures
+            .save("get_transaction_by_hash", &tx_hash.to_string(), &tx)?;
+        Ok(tx)
+    }
+}
+
+/// Execution client that serves the fixtures stored by `RecordingExecutionClient`
+#[derive(Debug)]
+pub struct ReplayExecutionClient {
+    fixtures: Fixtures,
+}
+
+impl ReplayExecutionClient {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            fixtures: Fixtures::new(dir, "execution"),
+        }
+    }
+}
+
+#[async_trait]
+impl ExecutionClient for ReplayExecutionClient {
+    async fn get_block(&self, block_hash: B256) -> ClientResult<Option<ExecutionBlock>> {
+        self.fixtures.load("get_block", &block_hash.to_string())
+    }
+
+    async fn get_transaction_by_hash(&self, tx_hash: B256) -> ClientResult<Option<Transaction>> {
+        self.fixtures
+            .load("get_transaction_by_hash", &tx_hash.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // slot of the committed fixtures
+    const SLOT: u64 = 1000;
+
+    #[tokio::test]
+    async fn test_record_replay() -> anyhow::Result<()> {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/ad-blob");
+        let dir = std::env::temp_dir().join(format!("fixtures-test-{}", std::process::id()));
+
+        // the committed fixtures are recorded again, and replayed from the new ones
+        let recording =
+            RecordingBeaconClient::new(Arc::new(ReplayBeaconClient::new(&fixtures)), &dir);
+        let replay = ReplayBeaconClient::new(&dir);
+        let header = recording
+            .get_block_header(BlockId::Slot(SLOT))
+            .await?
+            .context("header")?;
+        let block = recording
+            .get_block(BlockId::Hash(header.root))
+            .await?
+            .context("block")?;
+        assert_eq!(recording.get_blobs(BlockId::Slot(SLOT)).await?.len(), 1);
+
+        let replayed = replay
+            .get_block(BlockId::Hash(header.root))
+            .await?
+            .context("replayed block")?;
+        assert_eq!(replayed.slot, SLOT);
+        assert_eq!(replayed.blob_kzg_commitments, block.blob_kzg_commitments);
+        let blobs = replay.get_blobs(BlockId::Slot(SLOT)).await?;
+        assert_eq!(
+            block.blob_kzg_commitments,
+            Some(vec![blobs[0].kzg_commitment])
+        );
+        // not recorded
+        assert!(replay.get_block_header(BlockId::Slot(1)).await.is_err());
+
+        let recording =
+            RecordingExecutionClient::new(Arc::new(ReplayExecutionClient::new(&fixtures)), &dir);
+        let execution_block_hash = block.execution_payload.context("payload")?.block_hash;
+        let execution_block = recording
+            .get_block(execution_block_hash)
+            .await?
+            .context("execution block")?;
+        let replayed = ReplayExecutionClient::new(&dir)
+            .get_block(execution_block_hash)
+            .await?
+            .context("replayed execution block")?;
+        assert_eq!(replayed.header.hash, execution_block.header.hash);
+        assert_eq!(replayed.transactions.len(), 1);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
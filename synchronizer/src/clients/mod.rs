@@ -19,5 +19,8 @@
 // CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
 // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+pub mod archive;
 pub mod beacon;
 pub mod common;
+pub mod execution;
+pub mod fixtures;
@@ -1,7 +1,9 @@
+use alloy::primitives::{Address, B256};
 use anyhow::Result;
 use pod2::middleware::{Hash, RawValue};
+use serde::Serialize;
 use sqlx::SqlitePool;
-use tables::{HashSql, RawValueSql};
+use tables::{B256Sql, HashSql, RawValueSql};
 
 // To dump the formatted table via cli:
 // ```
@@ -17,7 +19,8 @@ pub(crate) async fn init_db(db: &SqlitePool) -> Result<()> {
                 slot INTEGER NOT NULL,
                 block INTEGER NOT NULL,
                 blob_index INTEGER NOT NULL,
-                timestamp INTEGER NOT NULL
+                timestamp INTEGER NOT NULL,
+                sender BLOB
             );
             "#,
     )
@@ -45,7 +48,74 @@ pub(crate) async fn init_db(db: &SqlitePool) -> Result<()> {
                 id BLOB PRIMARY KEY,
                 custom_predicate_ref BLOB NOT NULL,
                 vds_root BLOB NOT NULL,
-                blob_versioned_hash BLOB NOT NULL
+                blob_versioned_hash BLOB NOT NULL,
+                params_fingerprint BLOB
+            );
+            "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS ad_snapshot (
+                id BLOB NOT NULL,
+                num INTEGER NOT NULL,
+                state BLOB NOT NULL,
+                dict BLOB NOT NULL,
+                blob_versioned_hash BLOB NOT NULL,
+
+                PRIMARY KEY (id, num)
+            );
+            "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // ops history of the ADs whose updates publish it, as of their update `num`, see `common::ops`.
+    // It's only rewritten every `OPS_CHECKPOINT_INTERVAL` updates, the ops of the updates after it
+    // are in `ad_op`.
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS ad_ops (
+                id BLOB PRIMARY KEY,
+                num INTEGER NOT NULL,
+                ops BLOB NOT NULL
+            );
+            "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // op of every update of the ADs in `ad_ops` after its num, with the ops root after it
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS ad_op (
+                id BLOB NOT NULL,
+                num INTEGER NOT NULL,
+                op BLOB NOT NULL,
+                ops_root BLOB NOT NULL,
+
+                PRIMARY KEY (id, num)
+            );
+            "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // copies of the `ad_update` rows every `CHECKPOINT_INTERVAL` updates, which stay once the
+    // updates before the last retained ones are pruned
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS ad_checkpoint (
+                id BLOB NOT NULL,
+                num INTEGER NOT NULL,
+                state BLOB NOT NULL,
+                blob_versioned_hash BLOB NOT NULL,
+                -- attestation link of the update, chained from the create of the AD
+                link BLOB,
+
+                PRIMARY KEY (id, num)
             );
             "#,
     )
@@ -62,6 +132,78 @@ pub(crate) async fn init_db(db: &SqlitePool) -> Result<()> {
     .execute(&mut *tx)
     .await?;
 
+    // membership of the users in the groups of the ADs whose state is a dictionary of sets, as
+    // seen by the ops their updates disclose or else by their snapshots: an interval opens at the
+    // update that adds the user to the group, or the first snapshot with it, and closes at the
+    // update that removes it, or the first snapshot without it
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS membership_interval (
+                id BLOB NOT NULL,
+                grp TEXT NOT NULL,
+                usr TEXT NOT NULL,
+                from_num INTEGER NOT NULL,
+                from_blob_versioned_hash BLOB NOT NULL,
+                to_num INTEGER,
+                to_blob_versioned_hash BLOB,
+
+                PRIMARY KEY (id, grp, usr, from_num)
+            );
+            "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // kind and tx of the payloads applied, by the `blob_versioned_hash` they're stored with, so
+    // that the ones published as calldata are told apart from the blobs
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS payload_source (
+                source BLOB PRIMARY KEY,
+                kind TEXT NOT NULL,
+                tx_hash BLOB
+            );
+            "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // slots skipped because some of their blobs couldn't be fetched
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS failed_slot (
+                slot INTEGER PRIMARY KEY,
+                error TEXT NOT NULL
+            );
+            "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // update payloads that don't follow the last update of their AD, e.g. because an update in a
+    // failed slot was skipped, kept with the blob that published them until the updates they follow
+    // are stored
+    sqlx::query(
+        r#"
+            CREATE TABLE IF NOT EXISTS orphan_update (
+                blob_versioned_hash BLOB PRIMARY KEY,
+                id BLOB NOT NULL,
+                epoch INTEGER,
+                payload BLOB NOT NULL,
+                slot INTEGER,
+                block INTEGER,
+                blob_index INTEGER,
+                timestamp INTEGER,
+                sender BLOB,
+                checked_num INTEGER NOT NULL,
+                diagnosis TEXT NOT NULL,
+                first_num INTEGER NOT NULL
+            );
+            "#,
+    )
+    .execute(&mut *tx)
+    .await?;
+
     // sqlx::query(
     //     r#"
     //     CREATE TABLE IF NOT EXISTS blob (
@@ -126,11 +268,148 @@ pub(crate) async fn init_db(db: &SqlitePool) -> Result<()> {
     // .execute(&mut *tx)
     // .await?;
 
+    // columns added after the tables were first created
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM pragma_table_info('blob') WHERE name = 'sender'")
+            .fetch_one(&mut *tx)
+            .await?;
+    if count == 0 {
+        sqlx::query("ALTER TABLE blob ADD COLUMN sender BLOB")
+            .execute(&mut *tx)
+            .await?;
+    }
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM pragma_table_info('ad') WHERE name = 'params_fingerprint'",
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if count == 0 {
+        sqlx::query("ALTER TABLE ad ADD COLUMN params_fingerprint BLOB")
+            .execute(&mut *tx)
+            .await?;
+    }
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM pragma_table_info('ad_checkpoint') WHERE name = 'link'",
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if count == 0 {
+        sqlx::query("ALTER TABLE ad_checkpoint ADD COLUMN link BLOB")
+            .execute(&mut *tx)
+            .await?;
+    }
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM pragma_table_info('orphan_update') WHERE name = 'first_num'",
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+    if count == 0 {
+        sqlx::query("ALTER TABLE orphan_update ADD COLUMN first_num INTEGER NOT NULL DEFAULT 0")
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("UPDATE orphan_update SET first_num = checked_num")
+            .execute(&mut *tx)
+            .await?;
+    }
+
     tx.commit().await?;
 
     Ok(())
 }
 
+/// Latest state of an AD
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AdState {
+    pub id: Hash,
+    pub num: i64,
+    pub state: RawValue,
+    pub vds_root: Hash,
+    // blob (or tx for calldata) of the update that set `state`
+    pub blob_versioned_hash: B256,
+}
+
+#[derive(sqlx::FromRow)]
+struct AdStateRow {
+    #[sqlx(try_from = "Vec<u8>")]
+    id: HashSql,
+    num: i64,
+    #[sqlx(try_from = "Vec<u8>")]
+    state: RawValueSql,
+    #[sqlx(try_from = "Vec<u8>")]
+    vds_root: HashSql,
+    #[sqlx(try_from = "Vec<u8>")]
+    blob_versioned_hash: B256Sql,
+}
+
+/// Update of an AD, with the blob that published it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AdUpdateEntry {
+    pub num: i64,
+    pub state: RawValue,
+    pub blob_versioned_hash: B256,
+    // slot and timestamp of the blob, none for payloads published as calldata
+    pub slot: Option<i64>,
+    pub timestamp: Option<i64>,
+    // address that posted the blob, none for payloads published as calldata and for blobs
+    // indexed before the senders were recorded
+    pub sender: Option<Address>,
+    // first num of the updates right before this one that were pruned, if any: the updates
+    // `pruned_from..num` aren't listed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pruned_from: Option<i64>,
+    // attestation link stored at the checkpoint of the update, if it's one
+    #[serde(skip)]
+    pub checkpoint_link: Option<Hash>,
+}
+
+#[derive(sqlx::FromRow)]
+struct AdUpdateEntryRow {
+    num: i64,
+    #[sqlx(try_from = "Vec<u8>")]
+    state: RawValueSql,
+    #[sqlx(try_from = "Vec<u8>")]
+    blob_versioned_hash: B256Sql,
+    slot: Option<i64>,
+    timestamp: Option<i64>,
+    sender: Option<Vec<u8>>,
+    link: Option<Vec<u8>>,
+}
+
+/// Update (or snapshot) bounding a membership interval, with the blob that published it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UpdateRef {
+    pub num: i64,
+    pub blob_versioned_hash: B256,
+    // slot and timestamp of the blob, none for payloads published as calldata
+    pub slot: Option<i64>,
+    pub timestamp: Option<i64>,
+}
+
+/// Interval in which a user was a member of a group.  The bounds are the updates that added and
+/// removed the user, for the ADs that disclose their ops.  For the others, they are the snapshots
+/// where the membership was first seen changed, so the user joined after the snapshot before
+/// `from` and at the latest in it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MembershipInterval {
+    pub group: String,
+    pub from: UpdateRef,
+    // none while the user is still a member
+    pub to: Option<UpdateRef>,
+}
+
+#[derive(sqlx::FromRow)]
+struct MembershipIntervalRow {
+    grp: String,
+    from_num: i64,
+    from_blob_versioned_hash: Vec<u8>,
+    from_slot: Option<i64>,
+    from_timestamp: Option<i64>,
+    to_num: Option<i64>,
+    to_blob_versioned_hash: Option<Vec<u8>>,
+    to_slot: Option<i64>,
+    to_timestamp: Option<i64>,
+}
+
 pub(crate) struct Database<E>(pub(crate) E);
 
 /// Implementation of database queries that works with transactions and database:
@@ -144,27 +423,40 @@ where
 {
     pub(crate) async fn add_blob(self, blob: &tables::Blob) -> Result<()> {
         sqlx::query(
-            "INSERT INTO blob (versioned_hash, slot, block, blob_index, timestamp) VALUES (?, ?, ?, ?, ?)",
+            "INSERT INTO blob (versioned_hash, slot, block, blob_index, timestamp, sender) VALUES (?, ?, ?, ?, ?, ?)",
         )
         .bind(blob.versioned_hash.as_slice())
         .bind(blob.slot)
         .bind(blob.block)
         .bind(blob.blob_index)
         .bind(blob.timestamp)
+        .bind(&blob.sender)
         .execute(self.0)
         .await?;
 
         Ok(())
     }
 
+    pub(crate) async fn add_payload_source(self, source: &tables::PayloadSource) -> Result<()> {
+        sqlx::query("INSERT INTO payload_source (source, kind, tx_hash) VALUES (?, ?, ?)")
+            .bind(source.source.as_slice())
+            .bind(&source.kind)
+            .bind(&source.tx_hash)
+            .execute(self.0)
+            .await?;
+
+        Ok(())
+    }
+
     pub(crate) async fn add_ad(self, ad: &tables::Ad) -> Result<()> {
         sqlx::query(
-            "INSERT INTO ad (id, custom_predicate_ref, vds_root, blob_versioned_hash) VALUES (?, ?, ?, ?)",
+            "INSERT INTO ad (id, custom_predicate_ref, vds_root, blob_versioned_hash, params_fingerprint) VALUES (?, ?, ?, ?, ?)",
         )
         .bind(ad.id.to_bytes())
         .bind(ad.custom_predicate_ref.to_bytes())
         .bind(ad.vds_root.to_bytes())
         .bind(ad.blob_versioned_hash.as_slice())
+        .bind(ad.params_fingerprint.as_ref().map(HashSql::to_bytes))
         .execute(self.0)
         .await?;
 
@@ -185,15 +477,144 @@ where
         Ok(())
     }
 
-    pub(crate) async fn add_visited_slot(self, slot: i64) -> Result<()> {
+    pub(crate) async fn add_ad_snapshot(self, snapshot: &tables::AdSnapshot) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO ad_snapshot (id, num, state, dict, blob_versioned_hash) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(snapshot.id.to_bytes())
+        .bind(snapshot.num)
+        .bind(snapshot.state.to_bytes())
+        .bind(snapshot.dict.to_bytes())
+        .bind(snapshot.blob_versioned_hash.as_slice())
+        .execute(self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn set_ad_ops(self, ad_ops: &tables::AdOps) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO ad_ops (id, num, ops) VALUES (?, ?, ?)")
+            .bind(ad_ops.id.to_bytes())
+            .bind(ad_ops.num)
+            .bind(ad_ops.ops.to_bytes())
+            .execute(self.0)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn add_ad_op(self, ad_op: &tables::AdOp) -> Result<()> {
+        sqlx::query("INSERT INTO ad_op (id, num, op, ops_root) VALUES (?, ?, ?, ?)")
+            .bind(ad_op.id.to_bytes())
+            .bind(ad_op.num)
+            .bind(ad_op.op.to_bytes())
+            .bind(ad_op.ops_root.to_bytes())
+            .execute(self.0)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the ops of the updates of the AD up to `num`, once they are in its `ad_ops`.
+    pub(crate) async fn delete_ad_ops_until(self, ad_id: Hash, num: i64) -> Result<()> {
+        sqlx::query("DELETE FROM ad_op WHERE id = ? AND num <= ?")
+            .bind(HashSql(ad_id).to_bytes())
+            .bind(num)
+            .execute(self.0)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn add_visited_slot(self, slot: u64) -> Result<()> {
         sqlx::query("INSERT INTO visited_slot (slot) VALUES (?)")
-            .bind(slot)
+            .bind(i64::try_from(slot)?)
+            .execute(self.0)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn add_failed_slot(self, slot: u64, error: &str) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO failed_slot (slot, error) VALUES (?, ?)")
+            .bind(i64::try_from(slot)?)
+            .bind(error)
+            .execute(self.0)
+            .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn delete_failed_slot(self, slot: u64) -> Result<()> {
+        sqlx::query("DELETE FROM failed_slot WHERE slot = ?")
+            .bind(i64::try_from(slot)?)
             .execute(self.0)
             .await?;
 
         Ok(())
     }
 
+    /// Stores the orphan update, or updates the num it was checked at and its diagnosis if it's
+    /// already stored, keeping the num it was first stored at.
+    pub(crate) async fn add_orphan_update(self, orphan: &tables::OrphanUpdate) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO orphan_update (blob_versioned_hash, id, epoch, payload, slot, block, blob_index, timestamp, sender, checked_num, diagnosis, first_num) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT (blob_versioned_hash) DO UPDATE SET checked_num = excluded.checked_num, diagnosis = excluded.diagnosis",
+        )
+        .bind(orphan.blob_versioned_hash.as_slice())
+        .bind(orphan.id.to_bytes())
+        .bind(orphan.epoch)
+        .bind(&orphan.payload)
+        .bind(orphan.slot)
+        .bind(orphan.block)
+        .bind(orphan.blob_index)
+        .bind(orphan.timestamp)
+        .bind(&orphan.sender)
+        .bind(orphan.checked_num)
+        .bind(&orphan.diagnosis)
+        .bind(orphan.first_num)
+        .execute(self.0)
+        .await?;
+
+        Ok(())
+    }
+
+    pub(crate) async fn delete_orphan_update(self, blob_versioned_hash: B256Sql) -> Result<()> {
+        sqlx::query("DELETE FROM orphan_update WHERE blob_versioned_hash = ?")
+            .bind(blob_versioned_hash.as_slice())
+            .execute(self.0)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes the orphan updates without an epoch whose AD is more than `max_age` updates past the
+    /// one they were first stored at, returning how many were deleted.
+    pub(crate) async fn delete_expired_orphan_updates(self, max_age: i64) -> Result<u64> {
+        Ok(sqlx::query(
+            "DELETE FROM orphan_update WHERE epoch IS NULL AND first_num + ? <
+             (SELECT MAX(num) FROM ad_update WHERE ad_update.id = orphan_update.id)",
+        )
+        .bind(max_age)
+        .execute(self.0)
+        .await?
+        .rows_affected())
+    }
+
+    /// Returns the orphan updates worth trying again, by AD and epoch: those that follow the last
+    /// update of their AD or are behind it, and those without an epoch last tried before the last
+    /// update.
+    pub(crate) async fn get_orphan_updates_ready(self) -> Result<Vec<tables::OrphanUpdate>> {
+        Ok(sqlx::query_as(
+            "SELECT o.* FROM orphan_update o
+             JOIN (SELECT id, MAX(num) AS num FROM ad_update GROUP BY id) l ON l.id = o.id
+             WHERE o.epoch <= l.num + 1 OR (o.epoch IS NULL AND o.checked_num < l.num)
+             ORDER BY o.id, o.epoch",
+        )
+        .fetch_all(self.0)
+        .await?)
+    }
+
     pub(crate) async fn get_ad(self, ad_id: Hash) -> Result<tables::Ad> {
         Ok(sqlx::query_as("SELECT * FROM ad WHERE id = ?")
             .bind(HashSql(ad_id).to_bytes())
@@ -210,20 +631,314 @@ where
         )
     }
 
-    pub(crate) async fn get_ad_update_last_state(self, ad_id: Hash) -> Result<RawValue> {
-        let (state,): (Vec<u8>,) =
-            sqlx::query_as("SELECT state FROM ad_update WHERE id = ? ORDER BY num DESC LIMIT 1")
+    /// Returns the updates of the AD ordered by num, starting from the creation (num 0).
+    pub(crate) async fn get_ad_updates(self, ad_id: Hash) -> Result<Vec<tables::AdUpdate>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM ad_update WHERE id = ? ORDER BY num ASC")
                 .bind(HashSql(ad_id).to_bytes())
-                .fetch_one(self.0)
-                .await?;
-        Ok(RawValueSql::try_from(state).expect("32 bytes").0)
+                .fetch_all(self.0)
+                .await?,
+        )
     }
 
-    pub(crate) async fn get_visited_slot_last(self) -> Result<u32> {
-        let (slot,) = sqlx::query_as("SELECT slot FROM visited_slot ORDER BY slot DESC LIMIT 1")
-            .fetch_one(self.0)
+    /// Returns the updates of the AD ordered by num, with the blobs that published them.  The
+    /// pruned updates are only listed at their checkpoints, and the entry after a range of them
+    /// has its `pruned_from`.
+    pub(crate) async fn get_ad_update_history(self, ad_id: Hash) -> Result<Vec<AdUpdateEntry>> {
+        let rows: Vec<AdUpdateEntryRow> = sqlx::query_as(
+            "SELECT u.num, u.state, u.blob_versioned_hash, blob.slot, blob.timestamp, blob.sender,
+                    c.link
+             FROM (SELECT num, state, blob_versioned_hash FROM ad_update WHERE id = ?
+                   UNION SELECT num, state, blob_versioned_hash FROM ad_checkpoint WHERE id = ?) u
+             LEFT JOIN blob ON blob.versioned_hash = u.blob_versioned_hash
+             LEFT JOIN ad_checkpoint c ON c.id = ? AND c.num = u.num
+             ORDER BY u.num ASC",
+        )
+        .bind(HashSql(ad_id).to_bytes())
+        .bind(HashSql(ad_id).to_bytes())
+        .bind(HashSql(ad_id).to_bytes())
+        .fetch_all(self.0)
+        .await?;
+        let mut prev_num = None;
+        rows.into_iter()
+            .map(|row| -> Result<AdUpdateEntry> {
+                let pruned_from = prev_num
+                    .map(|prev_num: i64| prev_num + 1)
+                    .filter(|next_num| *next_num < row.num);
+                prev_num = Some(row.num);
+                Ok(AdUpdateEntry {
+                    num: row.num,
+                    state: row.state.0,
+                    blob_versioned_hash: B256::from(row.blob_versioned_hash),
+                    slot: row.slot,
+                    timestamp: row.timestamp,
+                    sender: row
+                        .sender
+                        .map(|sender| Address::try_from(sender.as_slice()))
+                        .transpose()?,
+                    pruned_from,
+                    checkpoint_link: row
+                        .link
+                        .map(|link| HashSql::try_from(link).map(|link| link.0))
+                        .transpose()?,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) async fn get_ad_ids(self) -> Result<Vec<Hash>> {
+        let ids: Vec<(Vec<u8>,)> = sqlx::query_as("SELECT id FROM ad")
+            .fetch_all(self.0)
+            .await?;
+        ids.into_iter()
+            .map(|(id,)| Ok(HashSql::try_from(id)?.0))
+            .collect()
+    }
+
+    /// Copies the update `num` of the AD to `ad_checkpoint`, with its attestation `link`.  Returns
+    /// false if it's already there.
+    pub(crate) async fn create_checkpoint(self, ad_id: Hash, num: i64, link: Hash) -> Result<bool> {
+        let res = sqlx::query(
+            "INSERT OR IGNORE INTO ad_checkpoint (id, num, state, blob_versioned_hash, link)
+             SELECT id, num, state, blob_versioned_hash, ? FROM ad_update WHERE id = ? AND num = ?",
+        )
+        .bind(HashSql(link).to_bytes())
+        .bind(HashSql(ad_id).to_bytes())
+        .bind(num)
+        .execute(self.0)
+        .await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    /// Returns the checkpoints of the AD ordered by num.
+    pub(crate) async fn get_ad_checkpoints(self, ad_id: Hash) -> Result<Vec<tables::AdUpdate>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM ad_checkpoint WHERE id = ? ORDER BY num ASC")
+                .bind(HashSql(ad_id).to_bytes())
+                .fetch_all(self.0)
+                .await?,
+        )
+    }
+
+    /// Deletes the updates of the AD before `num`, except the creation (num 0).  Returns the
+    /// number of updates deleted.
+    pub(crate) async fn prune_ad_updates(self, ad_id: Hash, num: i64) -> Result<u64> {
+        let res = sqlx::query("DELETE FROM ad_update WHERE id = ? AND num > 0 AND num < ?")
+            .bind(HashSql(ad_id).to_bytes())
+            .bind(num)
+            .execute(self.0)
             .await?;
-        Ok(slot)
+        Ok(res.rows_affected())
+    }
+
+    /// Whether the blob was already applied.  Its row is inserted last, in the transaction of its
+    /// slot, so a blob whose slot failed halfway isn't found and is applied again.
+    pub(crate) async fn blob_exists(self, versioned_hash: tables::B256Sql) -> Result<bool> {
+        Ok(
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM blob WHERE versioned_hash = ?)")
+                .bind(versioned_hash.as_slice())
+                .fetch_one(self.0)
+                .await?,
+        )
+    }
+
+    /// Whether a payload was already applied from `source`, the versioned hash of its blob or the
+    /// hash of its calldata tx.
+    pub(crate) async fn payload_source_exists(self, source: tables::B256Sql) -> Result<bool> {
+        Ok(
+            sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM payload_source WHERE source = ?)")
+                .bind(source.as_slice())
+                .fetch_one(self.0)
+                .await?,
+        )
+    }
+
+    pub(crate) async fn get_payload_source(
+        self,
+        source: tables::B256Sql,
+    ) -> Result<Option<tables::PayloadSource>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM payload_source WHERE source = ?")
+                .bind(source.as_slice())
+                .fetch_optional(self.0)
+                .await?,
+        )
+    }
+
+    pub(crate) async fn get_blob(
+        self,
+        versioned_hash: tables::B256Sql,
+    ) -> Result<Option<tables::Blob>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM blob WHERE versioned_hash = ?")
+                .bind(versioned_hash.as_slice())
+                .fetch_optional(self.0)
+                .await?,
+        )
+    }
+
+    /// Returns the AD with the state of its latest update.
+    pub(crate) async fn get_ad_state(self, ad_id: Hash) -> Result<AdState> {
+        let row: AdStateRow = sqlx::query_as(
+            "SELECT ad.id, ad_update.num, ad_update.state, ad.vds_root, ad_update.blob_versioned_hash
+             FROM ad JOIN ad_update ON ad_update.id = ad.id
+             WHERE ad.id = ? ORDER BY ad_update.num DESC LIMIT 1",
+        )
+        .bind(HashSql(ad_id).to_bytes())
+        .fetch_one(self.0)
+        .await?;
+        Ok(AdState {
+            id: row.id.0,
+            num: row.num,
+            state: row.state.0,
+            vds_root: row.vds_root.0,
+            blob_versioned_hash: B256::from(row.blob_versioned_hash),
+        })
+    }
+
+    pub(crate) async fn get_ad_snapshot_last(self, ad_id: Hash) -> Result<tables::AdSnapshot> {
+        Ok(
+            sqlx::query_as("SELECT * FROM ad_snapshot WHERE id = ? ORDER BY num DESC LIMIT 1")
+                .bind(HashSql(ad_id).to_bytes())
+                .fetch_one(self.0)
+                .await?,
+        )
+    }
+
+    /// Returns the last checkpoint of the ops history of the AD, none if its updates don't
+    /// publish it.  The ops of the updates after it are returned by `get_ad_ops_after`.
+    pub(crate) async fn get_ad_ops(self, ad_id: Hash) -> Result<Option<tables::AdOps>> {
+        Ok(sqlx::query_as("SELECT * FROM ad_ops WHERE id = ?")
+            .bind(HashSql(ad_id).to_bytes())
+            .fetch_optional(self.0)
+            .await?)
+    }
+
+    /// Returns the ops of the updates of the AD after `num` ordered by num.
+    pub(crate) async fn get_ad_ops_after(self, ad_id: Hash, num: i64) -> Result<Vec<tables::AdOp>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM ad_op WHERE id = ? AND num > ? ORDER BY num ASC")
+                .bind(HashSql(ad_id).to_bytes())
+                .bind(num)
+                .fetch_all(self.0)
+                .await?,
+        )
+    }
+
+    /// Returns the op of the last update of the AD past its ops checkpoint, if any.
+    pub(crate) async fn get_ad_op_last(self, ad_id: Hash) -> Result<Option<tables::AdOp>> {
+        Ok(
+            sqlx::query_as("SELECT * FROM ad_op WHERE id = ? ORDER BY num DESC LIMIT 1")
+                .bind(HashSql(ad_id).to_bytes())
+                .fetch_optional(self.0)
+                .await?,
+        )
+    }
+
+    /// Returns the `(group, user)` of the open membership intervals of the AD.
+    pub(crate) async fn get_open_memberships(self, ad_id: Hash) -> Result<Vec<(String, String)>> {
+        Ok(sqlx::query_as(
+            "SELECT grp, usr FROM membership_interval WHERE id = ? AND to_num IS NULL",
+        )
+        .bind(HashSql(ad_id).to_bytes())
+        .fetch_all(self.0)
+        .await?)
+    }
+
+    pub(crate) async fn open_membership_interval(
+        self,
+        ad_id: Hash,
+        group: &str,
+        user: &str,
+        num: i64,
+        blob_versioned_hash: B256Sql,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO membership_interval (id, grp, usr, from_num, from_blob_versioned_hash) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(HashSql(ad_id).to_bytes())
+        .bind(group)
+        .bind(user)
+        .bind(num)
+        .bind(blob_versioned_hash.as_slice())
+        .execute(self.0)
+        .await?;
+        Ok(())
+    }
+
+    pub(crate) async fn close_membership_interval(
+        self,
+        ad_id: Hash,
+        group: &str,
+        user: &str,
+        num: i64,
+        blob_versioned_hash: B256Sql,
+    ) -> Result<()> {
+        sqlx::query(
+            "UPDATE membership_interval SET to_num = ?, to_blob_versioned_hash = ? WHERE id = ? AND grp = ? AND usr = ? AND to_num IS NULL",
+        )
+        .bind(num)
+        .bind(blob_versioned_hash.as_slice())
+        .bind(HashSql(ad_id).to_bytes())
+        .bind(group)
+        .bind(user)
+        .execute(self.0)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the membership intervals of `user` in the groups of the AD, by group and start.
+    pub(crate) async fn get_membership_history(
+        self,
+        ad_id: Hash,
+        user: &str,
+    ) -> Result<Vec<MembershipInterval>> {
+        let rows: Vec<MembershipIntervalRow> = sqlx::query_as(
+            "SELECT m.grp, m.from_num, m.from_blob_versioned_hash, f.slot AS from_slot, f.timestamp AS from_timestamp,
+                    m.to_num, m.to_blob_versioned_hash, t.slot AS to_slot, t.timestamp AS to_timestamp
+             FROM membership_interval m
+             LEFT JOIN blob f ON f.versioned_hash = m.from_blob_versioned_hash
+             LEFT JOIN blob t ON t.versioned_hash = m.to_blob_versioned_hash
+             WHERE m.id = ? AND m.usr = ? ORDER BY m.grp, m.from_num",
+        )
+        .bind(HashSql(ad_id).to_bytes())
+        .bind(user)
+        .fetch_all(self.0)
+        .await?;
+        rows.into_iter()
+            .map(|row| -> Result<MembershipInterval> {
+                let to = match (row.to_num, row.to_blob_versioned_hash) {
+                    (Some(num), Some(blob_versioned_hash)) => Some(UpdateRef {
+                        num,
+                        blob_versioned_hash: B256::try_from(blob_versioned_hash.as_slice())?,
+                        slot: row.to_slot,
+                        timestamp: row.to_timestamp,
+                    }),
+                    _ => None,
+                };
+                Ok(MembershipInterval {
+                    group: row.grp,
+                    from: UpdateRef {
+                        num: row.from_num,
+                        blob_versioned_hash: B256::try_from(
+                            row.from_blob_versioned_hash.as_slice(),
+                        )?,
+                        slot: row.from_slot,
+                        timestamp: row.from_timestamp,
+                    },
+                    to,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the last visited slot, none before the first one.
+    pub(crate) async fn get_visited_slot_last(self) -> Result<Option<u64>> {
+        let slot: Option<(i64,)> =
+            sqlx::query_as("SELECT slot FROM visited_slot ORDER BY slot DESC LIMIT 1")
+                .fetch_optional(self.0)
+                .await?;
+        Ok(slot.map(|(slot,)| u64::try_from(slot)).transpose()?)
     }
 }
 
@@ -233,11 +948,16 @@ pub mod tables {
     use common::payload::{
         read_custom_predicate_ref, read_elems, write_custom_predicate_ref, write_elems,
     };
-    use pod2::middleware::{CustomPredicateRef, Hash, RawValue};
+    use pod2::middleware::{CustomPredicateRef, Hash, RawValue, containers::Dictionary};
+    use sqlx::{Database, Decode, Sqlite, Type, error::BoxDynError};
 
     pub type B256Sql = [u8; 32];
 
-    #[derive(Debug, Eq, PartialEq)]
+    // NOTE: the `blob_versioned_hash` of payloads published as calldata holds the hash of the tx
+    // that carries them.  Their kind is in `payload_source`, except for the payloads applied
+    // before it was recorded.
+
+    #[derive(Debug, Clone, Eq, PartialEq)]
     pub struct HashSql(pub Hash);
 
     impl TryFrom<Vec<u8>> for HashSql {
@@ -256,7 +976,24 @@ pub mod tables {
         }
     }
 
-    #[derive(Debug, Eq, PartialEq)]
+    // decoded as a column on its own for the nullable hash columns, which `try_from` can't map
+    impl Type<Sqlite> for HashSql {
+        fn type_info() -> <Sqlite as Database>::TypeInfo {
+            <Vec<u8> as Type<Sqlite>>::type_info()
+        }
+
+        fn compatible(ty: &<Sqlite as Database>::TypeInfo) -> bool {
+            <Vec<u8> as Type<Sqlite>>::compatible(ty)
+        }
+    }
+
+    impl<'r> Decode<'r, Sqlite> for HashSql {
+        fn decode(value: <Sqlite as Database>::ValueRef<'r>) -> Result<Self, BoxDynError> {
+            Ok(Self::try_from(<Vec<u8> as Decode<Sqlite>>::decode(value)?)?)
+        }
+    }
+
+    #[derive(Debug, Clone, Eq, PartialEq)]
     pub struct RawValueSql(pub RawValue);
 
     impl TryFrom<Vec<u8>> for RawValueSql {
@@ -275,7 +1012,7 @@ pub mod tables {
         }
     }
 
-    #[derive(Debug, Eq, PartialEq)]
+    #[derive(Debug, Clone, Eq, PartialEq)]
     pub struct CustomPredicateRefSql(pub CustomPredicateRef);
 
     impl TryFrom<Vec<u8>> for CustomPredicateRefSql {
@@ -294,7 +1031,24 @@ pub mod tables {
         }
     }
 
-    #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
+    #[derive(Debug, Eq, PartialEq)]
+    pub struct DictSql(pub Dictionary);
+
+    impl TryFrom<Vec<u8>> for DictSql {
+        type Error = Error;
+
+        fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+            Ok(Self(minicbor_serde::from_slice(&bytes)?))
+        }
+    }
+
+    impl DictSql {
+        pub fn to_bytes(&self) -> Vec<u8> {
+            minicbor_serde::to_vec(&self.0).expect("dict serialization")
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
     pub struct Ad {
         #[sqlx(try_from = "Vec<u8>")]
         pub id: HashSql,
@@ -304,9 +1058,11 @@ pub mod tables {
         pub vds_root: HashSql,
         #[sqlx(try_from = "Vec<u8>")]
         pub blob_versioned_hash: B256Sql,
+        // `params_fingerprint` of the create payload, none for ADs created before it was published
+        pub params_fingerprint: Option<HashSql>,
     }
 
-    #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
+    #[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
     pub struct AdUpdate {
         #[sqlx(try_from = "Vec<u8>")]
         pub id: HashSql,
@@ -317,6 +1073,43 @@ pub mod tables {
         pub blob_versioned_hash: B256Sql,
     }
 
+    #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
+    pub struct AdSnapshot {
+        #[sqlx(try_from = "Vec<u8>")]
+        pub id: HashSql,
+        // num of the update whose state is `dict`
+        pub num: i64,
+        #[sqlx(try_from = "Vec<u8>")]
+        pub state: RawValueSql,
+        #[sqlx(try_from = "Vec<u8>")]
+        pub dict: DictSql,
+        #[sqlx(try_from = "Vec<u8>")]
+        pub blob_versioned_hash: B256Sql,
+    }
+
+    #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
+    pub struct AdOps {
+        #[sqlx(try_from = "Vec<u8>")]
+        pub id: HashSql,
+        // num of the update whose `ops_root` is the commitment of `ops`
+        pub num: i64,
+        #[sqlx(try_from = "Vec<u8>")]
+        pub ops: DictSql,
+    }
+
+    #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
+    pub struct AdOp {
+        #[sqlx(try_from = "Vec<u8>")]
+        pub id: HashSql,
+        pub num: i64,
+        // commitment of the op of the update `num`
+        #[sqlx(try_from = "Vec<u8>")]
+        pub op: RawValueSql,
+        // commitment of the ops history after the update `num`
+        #[sqlx(try_from = "Vec<u8>")]
+        pub ops_root: HashSql,
+    }
+
     #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
     pub struct Blob {
         #[sqlx(try_from = "Vec<u8>")]
@@ -325,6 +1118,61 @@ pub mod tables {
         pub block: i64,
         pub blob_index: i64,
         pub timestamp: i64,
+        // address of the tx signer, none for blobs indexed before the senders were recorded
+        pub sender: Option<Vec<u8>>,
+    }
+
+    /// Where an applied payload was published
+    #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
+    pub struct PayloadSource {
+        // `blob_versioned_hash` of the rows of the payload
+        #[sqlx(try_from = "Vec<u8>")]
+        pub source: B256Sql,
+        // `PayloadSource::BLOB` or `PayloadSource::CALLDATA`
+        pub kind: String,
+        // hash of the tx that carries the payload, none for the blobs of orphan updates
+        pub tx_hash: Option<Vec<u8>>,
+    }
+
+    impl PayloadSource {
+        pub const BLOB: &str = "blob";
+        pub const CALLDATA: &str = "calldata";
+    }
+
+    /// Update payload that doesn't follow the last update of its AD
+    #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
+    pub struct OrphanUpdate {
+        #[sqlx(try_from = "Vec<u8>")]
+        pub blob_versioned_hash: B256Sql,
+        #[sqlx(try_from = "Vec<u8>")]
+        pub id: HashSql,
+        pub epoch: Option<i64>,
+        // encoding of the `Payload::Update`
+        pub payload: Vec<u8>,
+        // fields of the blob that published it, none for calldata
+        pub slot: Option<i64>,
+        pub block: Option<i64>,
+        pub blob_index: Option<i64>,
+        pub timestamp: Option<i64>,
+        pub sender: Option<Vec<u8>>,
+        // num of the last update of the AD when the payload was last tried
+        pub checked_num: i64,
+        pub diagnosis: String,
+        // num of the last update of the AD when the payload was first stored
+        pub first_num: i64,
+    }
+
+    impl OrphanUpdate {
+        pub fn blob(&self) -> Option<Blob> {
+            Some(Blob {
+                versioned_hash: self.blob_versioned_hash,
+                slot: self.slot?,
+                block: self.block?,
+                blob_index: self.blob_index?,
+                timestamp: self.timestamp?,
+                sender: self.sender.clone(),
+            })
+        }
     }
 
     #[derive(Debug, PartialEq, Eq, sqlx::FromRow)]
@@ -332,3 +1180,259 @@ pub mod tables {
         pub slot: i64,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use pod2::middleware::{
+        CustomPredicateBatch, CustomPredicateRef, Params, containers::Dictionary, hash_str,
+    };
+
+    use super::{tables::*, *};
+
+    #[tokio::test]
+    async fn test_database() -> Result<()> {
+        let db = SqlitePool::connect(":memory:").await?;
+        init_db(&db).await?;
+        // init_db is idempotent
+        init_db(&db).await?;
+
+        // blobs
+        let blob = Blob {
+            versioned_hash: [1; 32],
+            slot: 10,
+            block: 20,
+            blob_index: 2,
+            timestamp: 1000,
+            sender: Some(vec![7; 20]),
+        };
+        Database(&db).add_blob(&blob).await?;
+        assert_eq!(Database(&db).get_blob([1; 32]).await?, Some(blob));
+        assert_eq!(Database(&db).get_blob([2; 32]).await?, None);
+        assert!(Database(&db).blob_exists([1; 32]).await?);
+        assert!(!Database(&db).blob_exists([2; 32]).await?);
+
+        // ads
+        let ad_id = hash_str("ad");
+        assert!(Database(&db).get_ad(ad_id).await.is_err());
+        let ad = Ad {
+            id: HashSql(ad_id),
+            custom_predicate_ref: CustomPredicateRefSql(CustomPredicateRef {
+                batch: CustomPredicateBatch::new_opaque("unknown".to_string(), hash_str("batch")),
+                index: 3,
+            }),
+            vds_root: HashSql(hash_str("vds_root")),
+            blob_versioned_hash: [1; 32],
+            params_fingerprint: Some(HashSql(hash_str("params"))),
+        };
+        Database(&db).add_ad(&ad).await?;
+        let ad_db = Database(&db).get_ad(ad_id).await?;
+        assert_eq!((&ad_db.id, &ad_db.vds_root), (&ad.id, &ad.vds_root));
+        assert_eq!(ad_db.blob_versioned_hash, ad.blob_versioned_hash);
+        assert_eq!(ad_db.params_fingerprint, ad.params_fingerprint);
+        let (cpr, cpr_db) = (&ad.custom_predicate_ref.0, &ad_db.custom_predicate_ref.0);
+        assert_eq!(
+            (cpr_db.batch.id(), cpr_db.index),
+            (cpr.batch.id(), cpr.index)
+        );
+        assert!(Database(&db).add_ad(&ad).await.is_err());
+
+        // updates, the creation one first
+        assert!(Database(&db).get_ad_state(ad_id).await.is_err());
+        let updates: Vec<_> = (0..3)
+            .map(|num| AdUpdate {
+                id: HashSql(ad_id),
+                num,
+                state: RawValueSql(RawValue::from(num)),
+                blob_versioned_hash: [num as u8 + 1; 32],
+            })
+            .collect();
+        // inserted out of order
+        for update in updates.iter().rev() {
+            Database(&db).add_ad_update(update).await?;
+        }
+        assert!(Database(&db).add_ad_update(&updates[1]).await.is_err());
+        assert_eq!(Database(&db).get_ad_updates(ad_id).await?, updates);
+        assert_eq!(Database(&db).get_ad_update_last(ad_id).await?, updates[2]);
+        assert_eq!(
+            Database(&db).get_ad_state(ad_id).await?,
+            AdState {
+                id: ad_id,
+                num: 2,
+                state: RawValue::from(2),
+                vds_root: hash_str("vds_root"),
+                blob_versioned_hash: B256::from([3; 32]),
+            }
+        );
+        // only the first update was published in a stored blob
+        let history = Database(&db).get_ad_update_history(ad_id).await?;
+        assert_eq!(history.len(), 3);
+        assert_eq!(
+            history[0],
+            AdUpdateEntry {
+                num: 0,
+                state: RawValue::from(0),
+                blob_versioned_hash: B256::from([1; 32]),
+                slot: Some(10),
+                timestamp: Some(1000),
+                sender: Some(Address::from([7; 20])),
+                pruned_from: None,
+                checkpoint_link: None,
+            }
+        );
+        assert_eq!(
+            (history[2].num, history[2].slot, history[2].sender),
+            (2, None, None)
+        );
+        // updates of other ads are not returned
+        assert!(
+            Database(&db)
+                .get_ad_updates(hash_str("other"))
+                .await?
+                .is_empty()
+        );
+        assert!(
+            Database(&db)
+                .get_ad_update_last(hash_str("other"))
+                .await
+                .is_err()
+        );
+
+        // checkpoints, the pruned updates are still listed at them
+        assert_eq!(Database(&db).get_ad_ids().await?, vec![ad_id]);
+        let link = hash_str("link");
+        assert!(Database(&db).create_checkpoint(ad_id, 2, link).await?);
+        assert!(!Database(&db).create_checkpoint(ad_id, 2, link).await?);
+        // no update 3 to copy
+        assert!(!Database(&db).create_checkpoint(ad_id, 3, link).await?);
+        assert_eq!(
+            Database(&db).get_ad_checkpoints(ad_id).await?,
+            vec![updates[2].clone()]
+        );
+        assert_eq!(Database(&db).prune_ad_updates(ad_id, 2).await?, 1);
+        assert_eq!(
+            Database(&db).get_ad_updates(ad_id).await?,
+            vec![updates[0].clone(), updates[2].clone()]
+        );
+        let history = Database(&db).get_ad_update_history(ad_id).await?;
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.num, entry.pruned_from))
+                .collect::<Vec<_>>(),
+            vec![(0, None), (2, Some(1))]
+        );
+        assert_eq!(history[1].checkpoint_link, Some(link));
+
+        // snapshots
+        assert!(Database(&db).get_ad_snapshot_last(ad_id).await.is_err());
+        let dict = Dictionary::new(Params::default().max_depth_mt_containers, HashMap::new())?;
+        for num in [1, 2] {
+            Database(&db)
+                .add_ad_snapshot(&AdSnapshot {
+                    id: HashSql(ad_id),
+                    num,
+                    state: RawValueSql(RawValue::from(dict.commitment())),
+                    dict: DictSql(dict.clone()),
+                    blob_versioned_hash: [num as u8 + 1; 32],
+                })
+                .await?;
+        }
+        let snapshot = Database(&db).get_ad_snapshot_last(ad_id).await?;
+        assert_eq!((snapshot.num, &snapshot.dict.0), (2, &dict));
+
+        // membership intervals, alice is re-added in red
+        Database(&db)
+            .open_membership_interval(ad_id, "red", "alice", 1, [1; 32])
+            .await?;
+        Database(&db)
+            .open_membership_interval(ad_id, "blue", "alice", 1, [1; 32])
+            .await?;
+        Database(&db)
+            .open_membership_interval(ad_id, "red", "bob", 2, [2; 32])
+            .await?;
+        Database(&db)
+            .close_membership_interval(ad_id, "red", "alice", 2, [2; 32])
+            .await?;
+        Database(&db)
+            .open_membership_interval(ad_id, "red", "alice", 3, [3; 32])
+            .await?;
+        let mut open = Database(&db).get_open_memberships(ad_id).await?;
+        open.sort();
+        let membership = |group: &str, user: &str| (group.to_string(), user.to_string());
+        assert_eq!(
+            open,
+            vec![
+                membership("blue", "alice"),
+                membership("red", "alice"),
+                membership("red", "bob")
+            ]
+        );
+        let update_ref = |num: i64, slot| UpdateRef {
+            num,
+            blob_versioned_hash: B256::from([num as u8; 32]),
+            slot,
+            timestamp: slot.map(|_| 1000),
+        };
+        assert_eq!(
+            Database(&db).get_membership_history(ad_id, "alice").await?,
+            vec![
+                MembershipInterval {
+                    group: "blue".to_string(),
+                    from: update_ref(1, Some(10)),
+                    to: None,
+                },
+                MembershipInterval {
+                    group: "red".to_string(),
+                    from: update_ref(1, Some(10)),
+                    to: Some(update_ref(2, None)),
+                },
+                MembershipInterval {
+                    group: "red".to_string(),
+                    from: update_ref(3, None),
+                    to: None,
+                },
+            ]
+        );
+        assert!(
+            Database(&db)
+                .get_membership_history(hash_str("other"), "alice")
+                .await?
+                .is_empty()
+        );
+
+        // visited slots
+        assert_eq!(Database(&db).get_visited_slot_last().await?, None);
+        for slot in [5, 7, 6] {
+            Database(&db).add_visited_slot(slot).await?;
+        }
+        assert_eq!(Database(&db).get_visited_slot_last().await?, Some(7));
+
+        // queries within a transaction
+        let mut tx = db.begin().await?;
+        Database(&mut *tx).add_visited_slot(8).await?;
+        assert_eq!(Database(&mut *tx).get_visited_slot_last().await?, Some(8));
+        tx.rollback().await?;
+        assert_eq!(Database(&db).get_visited_slot_last().await?, Some(7));
+        // slots past u32 are kept, but they are stored as sqlite integers
+        let slot = u64::from(u32::MAX) + 1;
+        Database(&db).add_visited_slot(slot).await?;
+        assert_eq!(Database(&db).get_visited_slot_last().await?, Some(slot));
+        assert!(Database(&db).add_visited_slot(u64::MAX).await.is_err());
+
+        // failed slots, cleared once they are processed
+        Database(&db).add_failed_slot(9, "blob missing").await?;
+        Database(&db)
+            .add_failed_slot(9, "blob still missing")
+            .await?;
+        Database(&db).add_failed_slot(10, "blob missing").await?;
+        Database(&db).delete_failed_slot(10).await?;
+        let failed: Vec<(i64, String)> = sqlx::query_as("SELECT slot, error FROM failed_slot")
+            .fetch_all(&db)
+            .await?;
+        assert_eq!(failed, vec![(9, "blob still missing".to_string())]);
+
+        Ok(())
+    }
+}
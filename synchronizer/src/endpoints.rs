@@ -1,11 +1,25 @@
-use std::sync::Arc;
+use std::{convert::Infallible, str::FromStr, sync::Arc};
 
-use common::CustomError;
-use hex::FromHex;
-use pod2::middleware::Hash;
-use warp::Filter;
+use alloy::primitives::B256;
+use app::UserId;
+use common::{
+    CustomError, InvalidRequest,
+    attestation::{Attestation, AttestationBody, AttestedPredicate, AttestedUpdate},
+    payload::Payload,
+    rejection::handle_rejection,
+};
+use futures_util::{SinkExt, StreamExt};
+use hex::{FromHex, ToHex};
+use pod2::middleware::{EMPTY_HASH, Hash, RawValue, containers::Dictionary};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
+use warp::{
+    Filter,
+    ws::{Message, WebSocket, Ws},
+};
 
-use crate::{Database, Node};
+use crate::{AdUpdateEvent, Database, Node, db::AdUpdateEntry};
 
 // HANDLERS:
 
@@ -14,21 +28,427 @@ pub(crate) async fn handler_get_ad_state(
     ad_id_str: String,
     node: Arc<Node>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| CustomError(e.to_string()))?;
+    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| InvalidRequest(e.to_string()))?;
     let ad_state = Database(&node.db)
-        .get_ad_update_last_state(ad_id)
+        .get_ad_state(ad_id)
         .await
         .map_err(|e| CustomError(e.to_string()))?;
     Ok(warp::reply::json(&ad_state))
 }
 
+#[derive(Serialize)]
+pub(crate) struct AdSnapshotResp {
+    num: i64,
+    state: RawValue,
+    dict: Dictionary,
+}
+
+// GET /ad/{id}/snapshot/latest
+pub(crate) async fn handler_get_ad_snapshot_latest(
+    ad_id_str: String,
+    node: Arc<Node>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| InvalidRequest(e.to_string()))?;
+    let ad_snapshot = Database(&node.db)
+        .get_ad_snapshot_last(ad_id)
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    Ok(warp::reply::json(&AdSnapshotResp {
+        num: ad_snapshot.num,
+        state: ad_snapshot.state.0,
+        dict: ad_snapshot.dict.0,
+    }))
+}
+
+#[derive(Serialize)]
+pub(crate) struct AdPredicateResp {
+    // hex encoded, to compare with the batches listed by `GET /predicates` of the ad-server
+    batch_id: String,
+    index: usize,
+}
+
+// GET /ad/{id}/predicate
+pub(crate) async fn handler_get_ad_predicate(
+    ad_id_str: String,
+    node: Arc<Node>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| InvalidRequest(e.to_string()))?;
+    let ad = Database(&node.db)
+        .get_ad(ad_id)
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    let cpr = ad.custom_predicate_ref.0;
+    Ok(warp::reply::json(&AdPredicateResp {
+        batch_id: cpr.batch.id().encode_hex(),
+        index: cpr.index,
+    }))
+}
+
+#[derive(Serialize)]
+pub(crate) struct AdOpsRootResp {
+    // num of the last update of the AD, whose ops root is `ops_root`
+    num: i64,
+    ops_root: Hash,
+}
+
+// GET /ad/{id}/ops_root
+pub(crate) async fn handler_get_ad_ops_root(
+    ad_id_str: String,
+    node: Arc<Node>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| InvalidRequest(e.to_string()))?;
+    // the ops root of the last update is stored with its op, unless the ops history was just
+    // written as a whole
+    let ad_op = Database(&node.db)
+        .get_ad_op_last(ad_id)
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    let resp = match ad_op {
+        Some(ad_op) => AdOpsRootResp {
+            num: ad_op.num,
+            ops_root: ad_op.ops_root.0,
+        },
+        None => {
+            let ad_ops = Database(&node.db)
+                .get_ad_ops(ad_id)
+                .await
+                .map_err(|e| CustomError(e.to_string()))?
+                .ok_or_else(|| CustomError(format!("AD {} doesn't publish its ops", ad_id_str)))?;
+            AdOpsRootResp {
+                num: ad_ops.num,
+                ops_root: ad_ops.ops.0.commitment(),
+            }
+        }
+    };
+    Ok(warp::reply::json(&resp))
+}
+
+#[derive(Deserialize)]
+pub(crate) struct AdUpdatesQuery {
+    // only the updates from this num on, for the clients that already have the earlier ones
+    #[serde(default)]
+    from: i64,
+}
+
+// GET /ad/{id}/updates?from={num}
+pub(crate) async fn handler_get_ad_updates(
+    ad_id_str: String,
+    query: AdUpdatesQuery,
+    node: Arc<Node>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| InvalidRequest(e.to_string()))?;
+    let mut ad_updates = Database(&node.db)
+        .get_ad_update_history(ad_id)
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    // filtered once the pruned ranges are marked, so that the first update keeps its
+    // `pruned_from`
+    ad_updates.retain(|update| update.num >= query.from);
+    Ok(warp::reply::json(&ad_updates))
+}
+
+// GET /ads
+pub(crate) async fn handler_get_ads(node: Arc<Node>) -> Result<impl warp::Reply, warp::Rejection> {
+    let ad_ids: Vec<String> = Database(&node.db)
+        .get_ad_ids()
+        .await
+        .map_err(|e| CustomError(e.to_string()))?
+        .iter()
+        .map(|ad_id| ad_id.encode_hex())
+        .collect();
+    Ok(warp::reply::json(&ad_ids))
+}
+
+/// Chains the updates of `history` from the create of the AD.  The update after a range of pruned
+/// ones takes the link stored at its checkpoint, the one it was chained with before the pruning.
+pub(crate) fn attested_updates(history: &[AdUpdateEntry]) -> Vec<AttestedUpdate> {
+    let mut updates: Vec<AttestedUpdate> = Vec::with_capacity(history.len());
+    for entry in history {
+        let prev = updates.last().map_or(EMPTY_HASH, |update| update.link);
+        let update = match (entry.pruned_from, entry.checkpoint_link) {
+            (Some(pruned_from), Some(link)) => AttestedUpdate::after_pruned(
+                pruned_from,
+                entry.num,
+                entry.state,
+                entry.blob_versioned_hash,
+                entry.slot,
+                link,
+            ),
+            // a checkpoint made before the links were stored, chained after the listed update
+            (Some(pruned_from), None) => AttestedUpdate {
+                pruned_from: Some(pruned_from),
+                ..AttestedUpdate::chain(
+                    prev,
+                    entry.num,
+                    entry.state,
+                    entry.blob_versioned_hash,
+                    entry.slot,
+                )
+            },
+            (None, _) => AttestedUpdate::chain(
+                prev,
+                entry.num,
+                entry.state,
+                entry.blob_versioned_hash,
+                entry.slot,
+            ),
+        };
+        updates.push(update);
+    }
+    updates
+}
+
+// GET /ad/{id}/attestation
+pub(crate) async fn handler_get_ad_attestation(
+    ad_id_str: String,
+    node: Arc<Node>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| InvalidRequest(e.to_string()))?;
+    let db = Database(&node.db);
+    let ad = db
+        .get_ad(ad_id)
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    let history = db
+        .get_ad_update_history(ad_id)
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    let slot = db
+        .get_visited_slot_last()
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    let updates = attested_updates(&history);
+    // the create is indexed with the AD, so there is always a last update
+    let last = updates
+        .last()
+        .ok_or_else(|| CustomError(format!("AD {} has no update", ad_id_str)))?;
+    let cpr = ad.custom_predicate_ref.0;
+    let body = AttestationBody {
+        ad_id,
+        custom_predicate_ref: AttestedPredicate {
+            batch_id: cpr.batch.id(),
+            index: cpr.index,
+        },
+        vds_root: ad.vds_root.0,
+        slot,
+        num: last.num,
+        state: last.state,
+        updates,
+    };
+    Ok(warp::reply::json(&Attestation::new(
+        body,
+        node.cfg.attestation_key.as_ref(),
+    )))
+}
+
+// GET /ad/{id}/ws
+pub(crate) async fn handler_ad_ws(
+    ad_id_str: String,
+    ws: Ws,
+    ad_updates: broadcast::Sender<AdUpdateEvent>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| InvalidRequest(e.to_string()))?;
+    // subscribed before the upgrade, so that the updates committed meanwhile are sent
+    let ad_updates = ad_updates.subscribe();
+    Ok(ws.on_upgrade(move |socket| push_ad_updates(socket, ad_id, ad_updates)))
+}
+
+/// Sends the updates of the AD `ad_id` to `socket` as they're committed, as JSON text messages
+/// with the entries of `GET /ad/{id}/updates`, until the client goes away.  A client that falls
+/// `AD_UPDATE_EVENTS_CAPACITY` updates behind is disconnected, and can catch up with
+/// `GET /ad/{id}/updates`.
+async fn push_ad_updates(
+    socket: WebSocket,
+    ad_id: Hash,
+    mut ad_updates: broadcast::Receiver<AdUpdateEvent>,
+) {
+    let (mut socket_tx, mut socket_rx) = socket.split();
+    loop {
+        tokio::select! {
+            event = ad_updates.recv() => match event {
+                Ok(event) if event.ad_id == ad_id => {
+                    let msg = serde_json::to_string(&event.update).expect("update serializes");
+                    if socket_tx.send(Message::text(msg)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(
+                        skipped,
+                        "closing the update stream of AD {}, the client lagged behind",
+                        ad_id.encode_hex::<String>()
+                    );
+                    // 1013: try again later
+                    let _ = socket_tx.send(Message::close_with(1013u16, "lagged behind")).await;
+                    return;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            // the client only sends pings, answered by warp, and the close
+            msg = socket_rx.next() => match msg {
+                Some(Ok(msg)) if !msg.is_close() => {}
+                _ => return,
+            },
+        }
+    }
+}
+
+/// AD payload of a blob, with the hashes and values hex encoded
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum PayloadView {
+    Create {
+        id: String,
+        batch_id: String,
+        index: usize,
+        vds_root: String,
+        params_fingerprint: Option<String>,
+    },
+    Update {
+        id: String,
+        new_state: String,
+        op: String,
+        epoch: Option<i64>,
+        ops_root: Option<String>,
+        // the updates before this one in a batched update
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        batch: Vec<UpdateStepView>,
+    },
+    Snapshot {
+        id: String,
+        state: String,
+    },
+}
+
+/// Update of a batched update payload, with the hashes hex encoded
+#[derive(Serialize)]
+pub(crate) struct UpdateStepView {
+    epoch: Option<i64>,
+    new_state: String,
+    op: String,
+}
+
+impl From<&Payload> for PayloadView {
+    fn from(payload: &Payload) -> Self {
+        match payload {
+            Payload::Create(create) => PayloadView::Create {
+                id: create.id.encode_hex(),
+                batch_id: create.custom_predicate_ref.batch.id().encode_hex(),
+                index: create.custom_predicate_ref.index,
+                vds_root: create.vds_root.encode_hex(),
+                params_fingerprint: create.params_fingerprint.map(|hash| hash.encode_hex()),
+            },
+            Payload::Update(update) => PayloadView::Update {
+                id: update.id.encode_hex(),
+                new_state: update.new_state.encode_hex(),
+                op: update.op.encode_hex(),
+                epoch: update.epoch,
+                ops_root: update.ops_root.map(|hash| hash.encode_hex()),
+                batch: update
+                    .batch
+                    .iter()
+                    .zip(0..)
+                    .map(|(step, i)| UpdateStepView {
+                        epoch: update.first_epoch().map(|epoch| epoch + i),
+                        new_state: step.new_state.encode_hex(),
+                        op: step.op.encode_hex(),
+                    })
+                    .collect(),
+            },
+            Payload::Snapshot(snapshot) => PayloadView::Snapshot {
+                id: snapshot.id.encode_hex(),
+                state: snapshot.state.encode_hex(),
+            },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub(crate) struct BlobPayloadResp {
+    slot: i64,
+    blob_index: i64,
+    payload: PayloadView,
+}
+
+// GET /blob/{versioned_hash}
+pub(crate) async fn handler_get_blob(
+    versioned_hash_str: String,
+    node: Arc<Node>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let versioned_hash =
+        B256::from_str(&versioned_hash_str).map_err(|e| CustomError(e.to_string()))?;
+    let (blob, payload) = node
+        .fetch_blob_payload(versioned_hash)
+        .await
+        .map_err(|e| CustomError(format!("{:#}", e)))?
+        .ok_or_else(|| CustomError(format!("blob {} not indexed", versioned_hash)))?;
+    Ok(warp::reply::json(&BlobPayloadResp {
+        slot: blob.slot,
+        blob_index: blob.blob_index,
+        payload: PayloadView::from(&payload),
+    }))
+}
+
+// GET /ad/{id}/user/{user}/history
+pub(crate) async fn handler_get_user_history(
+    ad_id_str: String,
+    user: String,
+    node: Arc<Node>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ad_id = Hash::from_hex(&ad_id_str).map_err(|e| InvalidRequest(e.to_string()))?;
+    let user = UserId::new(user).map_err(|e| InvalidRequest(e.to_string()))?;
+    let intervals = Database(&node.db)
+        .get_membership_history(ad_id, user.as_str())
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    Ok(warp::reply::json(&intervals))
+}
+
+#[derive(Serialize)]
+pub(crate) struct StatusResp {
+    // `params_fingerprint` of the params the updates are verified with, which the create payloads
+    // must match; compare with `GET /status` of the ad-server
+    params_fingerprint: Hash,
+    // none before the first slot is processed
+    last_slot: Option<u64>,
+    // slots the last slot is kept behind the head of the beacon chain
+    confirmation_depth: u64,
+}
+
+// GET /status
+pub(crate) async fn handler_get_status(
+    node: Arc<Node>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let last_slot = Database(&node.db)
+        .get_visited_slot_last()
+        .await
+        .map_err(|e| CustomError(e.to_string()))?;
+    Ok(warp::reply::json(&StatusResp {
+        params_fingerprint: node.params_fingerprint(),
+        last_slot,
+        confirmation_depth: node.cfg.confirmation_depth,
+    }))
+}
+
 // ROUTES:
 
 // build the routes
 pub(crate) fn routes(
     node: Arc<Node>,
-) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
-    get_ad_state(node)
+) -> impl Filter<Extract = impl warp::Reply, Error = Infallible> + Clone {
+    get_ad_state(node.clone())
+        .or(get_ad_snapshot_latest(node.clone()))
+        .or(get_ad_predicate(node.clone()))
+        .or(get_ad_ops_root(node.clone()))
+        .or(get_ad_updates(node.clone()))
+        .or(get_ads(node.clone()))
+        .or(get_ad_attestation(node.clone()))
+        .or(get_ad_ws(node.ad_updates.clone()))
+        .or(get_user_history(node.clone()))
+        .or(get_blob(node.clone()))
+        .or(get_status(node))
+        .recover(handle_rejection)
 }
 
 fn get_ad_state(
@@ -41,3 +461,206 @@ fn get_ad_state(
         .and(node_filter)
         .and_then(handler_get_ad_state)
 }
+
+fn get_ad_snapshot_latest(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("ad" / String / "snapshot" / "latest")
+        .and(warp::get())
+        .and(node_filter)
+        .and_then(handler_get_ad_snapshot_latest)
+}
+
+fn get_ad_predicate(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("ad" / String / "predicate")
+        .and(warp::get())
+        .and(node_filter)
+        .and_then(handler_get_ad_predicate)
+}
+
+fn get_ad_ops_root(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("ad" / String / "ops_root")
+        .and(warp::get())
+        .and(node_filter)
+        .and_then(handler_get_ad_ops_root)
+}
+
+fn get_ad_updates(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("ad" / String / "updates")
+        .and(warp::get())
+        .and(warp::query::<AdUpdatesQuery>())
+        .and(node_filter)
+        .and_then(handler_get_ad_updates)
+}
+
+fn get_ads(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("ads")
+        .and(warp::get())
+        .and(node_filter)
+        .and_then(handler_get_ads)
+}
+
+fn get_ad_attestation(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("ad" / String / "attestation")
+        .and(warp::get())
+        .and(node_filter)
+        .and_then(handler_get_ad_attestation)
+}
+
+fn get_ad_ws(
+    ad_updates: broadcast::Sender<AdUpdateEvent>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let ad_updates_filter = warp::any().map(move || ad_updates.clone());
+
+    warp::path!("ad" / String / "ws")
+        .and(warp::ws())
+        .and(ad_updates_filter)
+        .and_then(handler_ad_ws)
+}
+
+fn get_user_history(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("ad" / String / "user" / String / "history")
+        .and(warp::get())
+        .and(node_filter)
+        .and_then(handler_get_user_history)
+}
+
+fn get_blob(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("blob" / String)
+        .and(warp::get())
+        .and(node_filter)
+        .and_then(handler_get_blob)
+}
+
+fn get_status(
+    node: Arc<Node>,
+) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
+    let node_filter = warp::any().map(move || node.clone());
+
+    warp::path!("status")
+        .and(warp::get())
+        .and(node_filter)
+        .and_then(handler_get_status)
+}
+
+#[cfg(test)]
+mod tests {
+    use pod2::middleware::hash_str;
+
+    use super::*;
+    use crate::db::AdUpdateEntry;
+
+    #[tokio::test]
+    async fn test_ad_ws() -> anyhow::Result<()> {
+        let (ad_updates, _) = broadcast::channel(8);
+        let (ad_a, ad_b) = (hash_str("a"), hash_str("b"));
+        let event = |ad_id, num| AdUpdateEvent {
+            ad_id,
+            update: AdUpdateEntry {
+                num,
+                state: RawValue::from(num),
+                blob_versioned_hash: [num as u8; 32].into(),
+                slot: Some(num),
+                timestamp: Some(0),
+                sender: None,
+                pruned_from: None,
+                checkpoint_link: None,
+            },
+        };
+
+        let mut client = warp::test::ws()
+            .path(&format!("/ad/{}/ws", ad_a.encode_hex::<String>()))
+            .handshake(get_ad_ws(ad_updates.clone()))
+            .await?;
+        for (ad_id, num) in [(ad_b, 1), (ad_a, 1), (ad_b, 2), (ad_a, 2)] {
+            ad_updates.send(event(ad_id, num))?;
+        }
+        // only the updates of the AD, in order
+        for num in [1, 2] {
+            let msg = client.recv().await?;
+            assert_eq!(
+                msg.to_str().ok(),
+                Some(serde_json::to_string(&event(ad_a, num).update)?.as_str())
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_handle_rejection() -> anyhow::Result<()> {
+        let filter = warp::path!("ad")
+            .and(warp::post())
+            .and(warp::body::content_length_limit(64))
+            .and(warp::body::json())
+            .map(|body: serde_json::Value| warp::reply::json(&body))
+            .recover(handle_rejection);
+        let post = async |body: &str| {
+            warp::test::request()
+                .method("POST")
+                .path("/ad")
+                .body(body)
+                .reply(&filter)
+                .await
+        };
+        // the error of the `{"error": ..}` envelope
+        let json = |res: &warp::http::Response<warp::hyper::body::Bytes>| {
+            let body = serde_json::from_slice::<serde_json::Value>(res.body()).expect("JSON body");
+            assert_eq!(body.as_object().map(|body| body.len()), Some(1), "{}", body);
+            body["error"].clone()
+        };
+
+        let res = post(&format!("\"{}\"", "a".repeat(64))).await;
+        assert_eq!(res.status(), 413);
+        assert_eq!(
+            json(&res),
+            serde_json::json!({
+                "kind": "payload_too_large",
+                "message": "request body is over the size limit of the endpoint"
+            })
+        );
+        let res = post("{\"id\": ").await;
+        assert_eq!(res.status(), 400);
+        let body = json(&res);
+        assert_eq!(body["kind"], "invalid_body");
+        assert!(body["message"].as_str().is_some_and(|m| m.contains("EOF")));
+
+        let res = warp::test::request()
+            .method("GET")
+            .path("/ad")
+            .reply(&filter)
+            .await;
+        assert_eq!(res.status(), 405);
+        assert_eq!(json(&res)["kind"], "method_not_allowed");
+        Ok(())
+    }
+}
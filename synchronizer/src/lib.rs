@@ -2,12 +2,16 @@
 
 pub mod clients;
 
+use std::{collections::BTreeSet, str::FromStr};
+
 use alloy::{
     eips::eip4844::FIELD_ELEMENT_BYTES_USIZE,
+    primitives::Address,
     rpc::types::beacon::sidecar::{BeaconBlobBundle, BlobData},
     transports::http::reqwest,
 };
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
+use pod2::middleware::{Key, TypedValue, containers::Dictionary};
 
 #[allow(dead_code)]
 pub(crate) async fn get_blobs(beacon_url: &str, block_id: u64) -> Result<Vec<BlobData>> {
@@ -17,14 +21,107 @@ pub(crate) async fn get_blobs(beacon_url: &str, block_id: u64) -> Result<Vec<Blo
     Ok(blob_bundle.data)
 }
 
-/// Extracts bytes from a blob in the 'simple' encoding.
+/// Parses a comma separated list of addresses.  An empty list gives no allowlist, which allows
+/// every sender.
+pub fn parse_addr_allowlist(s: &str) -> Result<Option<Vec<Address>>> {
+    let addrs = s
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| Address::from_str(addr).with_context(|| format!("invalid address {}", addr)))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Some(addrs).filter(|addrs| !addrs.is_empty()))
+}
+
+/// Whether the AD txs sent by `sender` are indexed.
+pub fn is_sender_allowed(allowlist: Option<&[Address]>, sender: Address) -> bool {
+    allowlist.is_none_or(|allowlist| allowlist.contains(&sender))
+}
+
+/// Returns the `(group, user)` memberships of a state that is a dictionary of sets of strings,
+/// like the one of a membership list.  The other entries (e.g. the epoch) and members are skipped,
+/// so the states of other ADs give no memberships.
+pub fn dict_memberships(dict: &Dictionary) -> BTreeSet<(String, String)> {
+    let mut memberships = BTreeSet::new();
+    for (key, value) in dict.kvs() {
+        let TypedValue::Set(set) = value.typed() else {
+            continue;
+        };
+        for member in set.set() {
+            if let TypedValue::String(user) = member.typed() {
+                memberships.insert((key.name().to_string(), user.clone()));
+            }
+        }
+    }
+    memberships
+}
+
+/// Change of membership of an op of the membership lists
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MembershipChange {
+    // whether the user joins or leaves the group
+    pub added: bool,
+    pub group: String,
+    pub user: String,
+}
+
+/// Returns the change of membership of an op dictionary like the ones of the membership lists:
+/// the `(group, user)` of an add or a del, with a value or not.  None for the other ops, and for
+/// the op dictionaries of other ADs.
+pub fn op_membership_change(op_dict: &Dictionary) -> Option<MembershipChange> {
+    let string = |key: &str| match op_dict.get(&Key::from(key)).ok()?.typed() {
+        TypedValue::String(s) => Some(s.clone()),
+        _ => None,
+    };
+    let added = match string("name")?.as_str() {
+        "add" | "add_kv" => true,
+        "del" | "del_kv" => false,
+        _ => return None,
+    };
+    Some(MembershipChange {
+        added,
+        group: string("group")?,
+        user: string("user")?,
+    })
+}
+
+// data bytes per field element in the 'simple' encoding
+const SIMPLE_BYTES_PER_FE: usize = FIELD_ELEMENT_BYTES_USIZE - 1;
+
+/// Extracts bytes from a blob in the 'simple' encoding.  Errors on blobs that `SimpleCoder`
+/// wouldn't produce instead of decoding them into garbage.
 pub fn bytes_from_simple_blob(blob_bytes: &[u8]) -> Result<Vec<u8>> {
     // Blob = [0x00] ++ 8_BYTE_LEN ++ [0x00,...,0x00] ++ X.
-    let data_len = u64::from_be_bytes(std::array::from_fn(|i| blob_bytes[1 + i])) as usize;
+    if blob_bytes.len() < FIELD_ELEMENT_BYTES_USIZE {
+        return Err(anyhow!(
+            "Given blob of length {} is shorter than a field element.",
+            blob_bytes.len()
+        ));
+    }
+    if !blob_bytes.len().is_multiple_of(FIELD_ELEMENT_BYTES_USIZE) {
+        return Err(anyhow!(
+            "Given blob of length {} is not a whole number of field elements.",
+            blob_bytes.len()
+        ));
+    }
+    // the leading byte of every field element is zero, otherwise the data bytes that
+    // `SimpleCoder` puts in the other 31 bytes wouldn't be the whole field element
+    if let Some(i) = blob_bytes
+        .chunks(FIELD_ELEMENT_BYTES_USIZE)
+        .position(|fe| fe[0] != 0)
+    {
+        return Err(anyhow!("non-canonical field element at index {}", i));
+    }
+    let (len_fe, data_fes) = blob_bytes.split_at(FIELD_ELEMENT_BYTES_USIZE);
+    if len_fe[9..].iter().any(|b| *b != 0) {
+        return Err(anyhow!(
+            "Length field element of the blob has non-zero padding."
+        ));
+    }
+    let data_len = u64::from_be_bytes(len_fe[1..9].try_into().expect("8 bytes"));
 
     // Sanity check: Blob must be able to accommodate the specified data length.
-    let max_data_len =
-        (blob_bytes.len() / FIELD_ELEMENT_BYTES_USIZE - 1) * (FIELD_ELEMENT_BYTES_USIZE - 1);
+    let max_data_len = (data_fes.len() / FIELD_ELEMENT_BYTES_USIZE * SIMPLE_BYTES_PER_FE) as u64;
     if data_len > max_data_len {
         return Err(anyhow!(
             "Given blob of length {} cannot accommodate {} bytes.",
@@ -33,14 +130,26 @@ pub fn bytes_from_simple_blob(blob_bytes: &[u8]) -> Result<Vec<u8>> {
         ));
     }
 
-    Ok(blob_bytes
+    Ok(data_fes
         .chunks(FIELD_ELEMENT_BYTES_USIZE)
-        .skip(1)
-        .flat_map(|chunk| chunk[1..].to_vec())
-        .take(data_len)
+        .flat_map(|fe| fe[1..].to_vec())
+        .take(data_len as usize)
         .collect())
 }
 
+/// Encodes `data` in the 'simple' encoding of `SimpleCoder`.  The result isn't padded to the size
+/// of a blob, padding it with zeros doesn't change the decoded data.
+pub fn bytes_to_simple_blob(data: &[u8]) -> Vec<u8> {
+    let mut blob = vec![0; FIELD_ELEMENT_BYTES_USIZE];
+    blob[1..9].copy_from_slice(&(data.len() as u64).to_be_bytes());
+    for chunk in data.chunks(SIMPLE_BYTES_PER_FE) {
+        blob.push(0);
+        blob.extend_from_slice(chunk);
+        blob.resize(blob.len() + SIMPLE_BYTES_PER_FE - chunk.len(), 0);
+    }
+    blob
+}
+
 #[cfg(test)]
 mod tests {
     // use plonky2::plonk::proof::CompressedProofWithPublicInputs;
@@ -51,8 +160,114 @@ mod tests {
     // };
 
     // use pod2_onchain::poseidon_bn128::config::PoseidonBN128GoldilocksConfig;
+    use std::collections::{HashMap, HashSet};
+
+    use alloy::eips::eip4844::BYTES_PER_BLOB;
+    use app::{Group, Op, UserId};
+    use pod2::middleware::{Params, Value, containers::Set};
+
     use super::*;
 
+    // deterministic pseudo-random bytes (xorshift)
+    fn pseudo_random_bytes(len: usize, mut seed: u64) -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                seed as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_simple_blob_round_trip() -> Result<()> {
+        let max_len = (BYTES_PER_BLOB / FIELD_ELEMENT_BYTES_USIZE - 1) * SIMPLE_BYTES_PER_FE;
+        let mut lens = vec![0, 1, 30, 31, 32, 62, 1000, max_len - 1, max_len];
+        lens.extend((1..=20).map(|i| (i * 7919) % max_len));
+        for (i, len) in lens.into_iter().enumerate() {
+            let data = pseudo_random_bytes(len, i as u64 + 1);
+            let mut blob = bytes_to_simple_blob(&data);
+            assert!(blob.len() <= BYTES_PER_BLOB, "len {}", len);
+            assert_eq!(bytes_from_simple_blob(&blob)?, data, "len {}", len);
+            // padded to a full blob
+            blob.resize(BYTES_PER_BLOB, 0);
+            assert_eq!(bytes_from_simple_blob(&blob)?, data, "len {}", len);
+        }
+        assert_eq!(
+            bytes_to_simple_blob(&pseudo_random_bytes(max_len, 1)).len(),
+            BYTES_PER_BLOB
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_simple_blob_malformed() {
+        let blob = bytes_to_simple_blob(&pseudo_random_bytes(100, 1));
+        // too short
+        assert!(bytes_from_simple_blob(&[]).is_err());
+        assert!(bytes_from_simple_blob(&blob[..FIELD_ELEMENT_BYTES_USIZE - 1]).is_err());
+        // not a whole number of field elements
+        assert!(bytes_from_simple_blob(&blob[..blob.len() - 1]).is_err());
+        // data truncated below the declared length
+        assert!(bytes_from_simple_blob(&blob[..2 * FIELD_ELEMENT_BYTES_USIZE]).is_err());
+        // non-zero leading byte of a field element
+        for i in [
+            0,
+            FIELD_ELEMENT_BYTES_USIZE,
+            blob.len() - FIELD_ELEMENT_BYTES_USIZE,
+        ] {
+            let mut corrupted = blob.clone();
+            corrupted[i] = 1;
+            let err = bytes_from_simple_blob(&corrupted).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "non-canonical field element at index {}",
+                    i / FIELD_ELEMENT_BYTES_USIZE
+                )
+            );
+        }
+        // and the padding of a full blob is checked too
+        let mut corrupted = blob.clone();
+        corrupted.resize(BYTES_PER_BLOB, 0);
+        corrupted[BYTES_PER_BLOB - FIELD_ELEMENT_BYTES_USIZE] = 0x80;
+        assert!(bytes_from_simple_blob(&corrupted).is_err());
+        // non-zero padding of the length
+        let mut corrupted = blob.clone();
+        corrupted[FIELD_ELEMENT_BYTES_USIZE - 1] = 1;
+        assert!(bytes_from_simple_blob(&corrupted).is_err());
+        // huge declared length
+        let mut corrupted = blob;
+        corrupted[1] = 0xff;
+        assert!(bytes_from_simple_blob(&corrupted).is_err());
+    }
+
+    #[test]
+    fn test_sender_allowlist() -> Result<()> {
+        let (alice, bob) = (Address::repeat_byte(0xa1), Address::repeat_byte(0xb0));
+        assert_eq!(parse_addr_allowlist("")?, None);
+        assert_eq!(parse_addr_allowlist(" , ")?, None);
+        let allowlist = parse_addr_allowlist(&format!("{}, {}", alice, Address::ZERO))?;
+        assert_eq!(allowlist, Some(vec![alice, Address::ZERO]));
+        assert!(parse_addr_allowlist("0x42").is_err());
+
+        // two senders of AD txs in the same block, only the allowlisted one is indexed
+        let senders = [bob, alice, bob];
+        let indexed: Vec<_> = senders
+            .iter()
+            .filter(|sender| is_sender_allowed(allowlist.as_deref(), **sender))
+            .collect();
+        assert_eq!(indexed, vec![&alice]);
+        // without an allowlist every sender is indexed
+        assert!(
+            senders
+                .iter()
+                .all(|sender| is_sender_allowed(None, *sender))
+        );
+        Ok(())
+    }
+
     #[ignore]
     #[tokio::test]
     async fn test_get_blobs() -> Result<()> {
@@ -63,6 +278,98 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_dict_memberships() {
+        let depth = Params::default().max_depth_mt_containers;
+        let set = |members: &[Value]| {
+            Value::from(Set::new(depth, members.iter().cloned().collect::<HashSet<_>>()).unwrap())
+        };
+        let dict = Dictionary::new(
+            depth,
+            HashMap::from([
+                (
+                    Key::from("red"),
+                    set(&[Value::from("alice"), Value::from("bob")]),
+                ),
+                (Key::from("green"), set(&[])),
+                // not a user
+                (
+                    Key::from("blue"),
+                    set(&[Value::from("carol"), Value::from(7i64)]),
+                ),
+                (Key::from("epoch"), Value::from(3i64)),
+            ]),
+        )
+        .unwrap();
+        let membership = |group: &str, user: &str| (group.to_string(), user.to_string());
+        assert_eq!(
+            dict_memberships(&dict),
+            BTreeSet::from([
+                membership("blue", "carol"),
+                membership("red", "alice"),
+                membership("red", "bob"),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_op_membership_change() -> Result<()> {
+        let params = Params::default();
+        let (red, alice) = (Group::RED, UserId::new("alice")?);
+        let change = |added| MembershipChange {
+            added,
+            group: "red".to_string(),
+            user: "alice".to_string(),
+        };
+        for (op, expected) in [
+            (
+                Op::Add {
+                    group: red.clone(),
+                    user: alice.clone(),
+                },
+                Some(change(true)),
+            ),
+            (
+                Op::Del {
+                    group: red.clone(),
+                    user: alice.clone(),
+                },
+                Some(change(false)),
+            ),
+            (
+                Op::AddKv {
+                    group: red.clone(),
+                    user: alice.clone(),
+                    value: 7,
+                },
+                Some(change(true)),
+            ),
+            (
+                Op::DelKv {
+                    group: red.clone(),
+                    user: alice,
+                },
+                Some(change(false)),
+            ),
+            (Op::Init, None),
+            (Op::AddGroup { group: red }, None),
+        ] {
+            assert_eq!(
+                op_membership_change(&op.clone().into_dict(&params, 1)),
+                expected,
+                "{:?}",
+                op
+            );
+        }
+        // not an op of a membership list
+        let dict = Dictionary::new(
+            params.max_depth_mt_containers,
+            HashMap::from([(Key::from("name"), Value::from("add"))]),
+        )?;
+        assert_eq!(op_membership_change(&dict), None);
+        Ok(())
+    }
+
     // Culled from https://github.com/arnaucube/pod2-blob-example/blob/13ca6ba9fe06b1295330c2f50107b6cd8a3251ce/src/main.rs#L30
     // pub fn compute_pod_proof() -> Result<pod2::frontend::MainPod> {
     //     let params = Params {
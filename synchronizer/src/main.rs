@@ -1,10 +1,12 @@
 #![allow(clippy::uninlined_format_args)]
 use std::{
-    collections::HashMap,
+    collections::{BTreeSet, HashMap},
     fs::{File, create_dir_all, read_dir, rename},
     io,
     io::{Read, Write},
-    path::PathBuf,
+    net::{IpAddr, SocketAddr},
+    num::{NonZeroU64, NonZeroUsize},
+    path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
     time::Duration,
@@ -12,73 +14,107 @@ use std::{
 
 use alloy::{
     consensus::Transaction,
-    eips as alloy_eips,
     eips::eip4844::kzg_to_versioned_hash,
-    network as alloy_network,
+    network::Ethereum,
     primitives::{Address, B256},
-    providers as alloy_provider,
+    providers::RootProvider,
 };
-use alloy_network::Ethereum;
-use alloy_provider::{Provider, RootProvider};
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use backoff::ExponentialBackoffBuilder;
 use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
 use common::{
-    ProofType, load_dotenv,
-    payload::{Payload, PayloadCreate, PayloadProof, PayloadUpdate},
-    shrink::ShrunkMainPodSetup,
+    ProofType, circuit_cache, load_dotenv, ops,
+    params::PodParamsConfig,
+    payload::{
+        Payload, PayloadCreate, PayloadProof, PayloadSnapshot, PayloadUpdate, params_fingerprint,
+    },
+    shrink::{ShrunkMainPodSetup, verify_shrunk_update},
 };
-use hex::ToHex;
-use plonky2::plonk::proof::CompressedProofWithPublicInputs;
+use hex::{FromHex, ToHex};
 use pod2::{
-    backends::plonky2::{
-        mainpod::calculate_statements_hash,
-        serialization::{CommonCircuitDataSerializer, VerifierCircuitDataSerializer},
+    backends::plonky2::serialization::{
+        CommonCircuitDataSerializer, VerifierCircuitDataSerializer,
     },
-    cache,
-    cache::CacheEntry,
     middleware::{
-        CommonCircuitData, EMPTY_VALUE, F, Params, Statement, Value, VerifierCircuitData,
+        CommonCircuitData, EMPTY_VALUE, Hash, Params, RawValue, VerifierCircuitData,
+        containers::Dictionary,
     },
 };
 use sqlx::{SqlitePool, migrate::MigrateDatabase, sqlite::Sqlite};
 use synchronizer::{
     bytes_from_simple_blob,
-    clients::beacon::{
-        self, BeaconClient,
-        types::{Blob, BlockHeader, BlockId},
+    clients::{
+        archive::{self, BlobArchive, BlobscanClient, fill_missing_blobs},
+        beacon::{
+            self, BeaconClient, CommonBeaconClient,
+            types::{Blob, BlockHeader, BlockId},
+        },
+        common::ClientError,
+        execution::ExecutionClient,
+        fixtures::{
+            RecordingBeaconClient, RecordingExecutionClient, ReplayBeaconClient,
+            ReplayExecutionClient,
+        },
     },
+    dict_memberships, is_sender_allowed, op_membership_change, parse_addr_allowlist,
+};
+use tables::{CustomPredicateRefSql, DictSql, HashSql, RawValueSql};
+use tokio::{
+    runtime::Runtime,
+    sync::{Semaphore, broadcast},
+    task::{self, JoinHandle},
+    time::sleep,
 };
-use tables::{CustomPredicateRefSql, HashSql, RawValueSql};
-use tokio::{runtime::Runtime, time::sleep};
-use tracing::{debug, info, trace};
+use tracing::{Instrument, debug, error, info, info_span, trace, warn};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
 pub mod db;
-use db::{Database, init_db, tables};
+use db::{AdUpdateEntry, Database, init_db, tables};
 pub mod endpoints;
 
+/// Loads the shrunk main pod circuit data from the cache at `cache_path`, building it if it's
+/// missing, was built from another circuit or pod2 version, or doesn't match its digest.
 pub fn cache_get_shrunk_main_pod_circuit_data(
+    cache_path: &Path,
     params: &Params,
-) -> CacheEntry<(CommonCircuitDataSerializer, VerifierCircuitDataSerializer)> {
-    cache::get("shrunk_main_pod_circuit_data", &params, |params| {
-        let shrunk_main_pod_build = ShrunkMainPodSetup::new(params)
-            .build()
-            .expect("successful build");
-        let verifier = shrunk_main_pod_build.circuit_data.verifier_data();
-        let common = shrunk_main_pod_build.circuit_data.common;
-        (
-            CommonCircuitDataSerializer(common),
-            VerifierCircuitDataSerializer(verifier),
-        )
-    })
-    .expect("cache ok")
+    zk: bool,
+) -> Result<(CommonCircuitDataSerializer, VerifierCircuitDataSerializer)> {
+    let setup = ShrunkMainPodSetup::new(params, zk);
+    circuit_cache::get_or_build(
+        cache_path,
+        "shrunk_main_pod_circuit_data",
+        &setup.fingerprint()?,
+        || {
+            let shrunk_main_pod_build = setup.build().expect("successful build");
+            let verifier = shrunk_main_pod_build.circuit_data.verifier_data();
+            let common = shrunk_main_pod_build.circuit_data.common;
+            (
+                CommonCircuitDataSerializer(common),
+                VerifierCircuitDataSerializer(verifier),
+            )
+        },
+    )
 }
 
 #[derive(Clone, Debug)]
 pub struct Config {
     // The URL for the Beacon API
     pub beacon_url: String,
+    // Bearer token for the Beacon API, none if unset
+    pub beacon_auth_token: Option<String>,
+    // Headers sent in every Beacon API request, parsed from a comma separated list of
+    // `name:value`
+    pub beacon_extra_headers: Vec<(String, String)>,
+    // Beacon API queried for the blobs missing in the responses of `beacon_url`, with the same
+    // credentials, none if unset
+    pub beacon_url_fallback: Option<String>,
+    // Times the blob sidecars of a slot are requested again while some blob is missing
+    pub blob_fetch_retries: u32,
+    // Timeout in seconds of the Beacon API blob sidecars requests, which return large payloads,
+    // and of the other Beacon API requests
+    pub beacon_blob_timeout: u64,
+    pub beacon_timeout: u64,
     // The URL for the Ethereum RPC API
     pub rpc_url: String,
     // The path to the sqlite database (it will be a file)
@@ -86,14 +122,52 @@ pub struct Config {
     // The path to the ad blob storage directory
     pub blobs_path: String,
     // The slot where the AD updates begins
-    pub ad_genesis_slot: u32,
+    pub ad_genesis_slot: u64,
+    // Slots a slot must be behind the head before it's processed, so that the blocks that are
+    // reorged out shortly after they are proposed aren't indexed
+    pub confirmation_depth: u64,
     // The address that receives AD update via blobs
     pub to_addr: Address,
+    // Only index the AD txs signed by these addresses, any sender if none
+    pub from_addr_allowlist: Option<Vec<Address>>,
+    // Also index AD payloads sent as tx calldata.  This requires fetching the execution block of
+    // every slot, not only of the slots with blobs.
+    pub index_calldata: bool,
     // Max Beacon API + RPC requests per second
     pub request_rate: u64,
+    // Blobscan compatible API to fetch the blobs that the beacon node no longer has
+    pub blob_archive_url: Option<String>,
     // set the proving system used to generate the proofs being sent to ethereum
     //   options: plonky2 / groth16
     pub proof_type: ProofType,
+    // whether the shrunk main pod circuit of the plonky2 proofs is built with zero-knowledge,
+    // must match the ad-server
+    pub shrink_zk: bool,
+    // The path to the directory where the built circuit data is cached
+    pub circuit_cache_path: String,
+    // Params the update proofs are verified with, the defaults with the overrides of the POD_*
+    // vars, which must be the ones of the ad-server
+    pub params: Params,
+    // Max number of update proofs verified at once
+    pub verify_concurrency: usize,
+    // Directory of the recorded Beacon API and RPC responses, which are replayed instead of
+    // querying the nodes, none if unset
+    pub fixtures_path: Option<String>,
+    // Record the responses of the nodes to `fixtures_path` instead of replaying them
+    pub record_fixtures: bool,
+    // Address and port the HTTP server listens on
+    pub http_bind: IpAddr,
+    pub http_port: u16,
+    // Updates between the checkpoints of the ADs, 0 disables the compaction
+    pub checkpoint_interval: u64,
+    // Updates kept in full behind the last one of an AD when compacting, the older ones are
+    // pruned up to a checkpoint
+    pub ad_update_retention: u64,
+    // Interval in seconds between compactions
+    pub compaction_interval: u64,
+    // ed25519 key the attestations of `GET /ad/{id}/attestation` are signed with, unsigned if
+    // none
+    pub attestation_key: Option<common::attestation::SigningKey>,
 }
 
 impl Config {
@@ -103,27 +177,320 @@ impl Config {
         }
         Ok(Self {
             beacon_url: var("BEACON_URL")?,
+            beacon_auth_token: dotenvy::var("BEACON_AUTH_TOKEN")
+                .ok()
+                .filter(|t| !t.is_empty()),
+            beacon_extra_headers: beacon::parse_extra_headers(
+                &dotenvy::var("BEACON_EXTRA_HEADERS").unwrap_or_default(),
+            )?,
+            beacon_url_fallback: dotenvy::var("BEACON_URL_FALLBACK")
+                .ok()
+                .filter(|url| !url.is_empty()),
+            blob_fetch_retries: match dotenvy::var("BLOB_FETCH_RETRIES") {
+                Ok(retries) if !retries.is_empty() => u32::from_str(&retries)?,
+                _ => 3,
+            },
+            beacon_blob_timeout: match dotenvy::var("BEACON_BLOB_TIMEOUT") {
+                Ok(secs) if !secs.is_empty() => u64::from_str(&secs)?,
+                _ => 30,
+            },
+            beacon_timeout: match dotenvy::var("BEACON_TIMEOUT") {
+                Ok(secs) if !secs.is_empty() => u64::from_str(&secs)?,
+                _ => 8,
+            },
             rpc_url: var("RPC_URL")?,
             sqlite_path: var("SYNCHRONIZER_SQLITE_PATH")?,
             blobs_path: var("BLOBS_PATH")?,
-            ad_genesis_slot: u32::from_str(&var("AD_GENESIS_SLOT")?)?,
+            ad_genesis_slot: u64::from_str(&var("AD_GENESIS_SLOT")?)?,
+            confirmation_depth: match dotenvy::var("CONFIRMATION_DEPTH") {
+                Ok(slots) if !slots.is_empty() => u64::from_str(&slots)?,
+                _ => 0,
+            },
             to_addr: Address::from_str(&var("TO_ADDR")?)?,
+            from_addr_allowlist: parse_addr_allowlist(
+                &dotenvy::var("FROM_ADDR_ALLOWLIST").unwrap_or_default(),
+            )?,
+            index_calldata: bool::from_str(&var("INDEX_CALLDATA")?)?,
             request_rate: u64::from_str(&var("REQUEST_RATE")?)?,
+            blob_archive_url: dotenvy::var("BLOB_ARCHIVE_URL")
+                .ok()
+                .filter(|url| !url.is_empty()),
             proof_type: ProofType::from_str(&var("PROOF_TYPE")?)?,
+            shrink_zk: bool::from_str(&var("SHRINK_ZK")?)?,
+            circuit_cache_path: var("CIRCUIT_CACHE_PATH")?,
+            params: PodParamsConfig::from_env()?.params()?,
+            verify_concurrency: match dotenvy::var("VERIFY_CONCURRENCY") {
+                Ok(n) if !n.is_empty() => NonZeroUsize::from_str(&n)?.get(),
+                _ => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            },
+            fixtures_path: dotenvy::var("FIXTURES_PATH")
+                .ok()
+                .filter(|path| !path.is_empty()),
+            record_fixtures: dotenvy::var("RECORD_FIXTURES").is_ok_and(|v| v == "1"),
+            http_bind: IpAddr::from_str(&var("SYNCHRONIZER_HTTP_BIND")?)?,
+            http_port: u16::from_str(&var("SYNCHRONIZER_HTTP_PORT")?)?,
+            checkpoint_interval: match dotenvy::var("CHECKPOINT_INTERVAL") {
+                Ok(n) if !n.is_empty() => u64::from_str(&n)?,
+                _ => 0,
+            },
+            // the link of the orphan updates is searched in the updates behind the last one
+            ad_update_retention: match dotenvy::var("AD_UPDATE_RETENTION") {
+                Ok(n) if !n.is_empty() => match u64::from_str(&n)? {
+                    n if n > LINK_LOOKBACK as u64 => n,
+                    n => bail!("AD_UPDATE_RETENTION {} must be over {}", n, LINK_LOOKBACK),
+                },
+                _ => 1024,
+            },
+            compaction_interval: match dotenvy::var("COMPACTION_INTERVAL") {
+                Ok(secs) if !secs.is_empty() => NonZeroU64::from_str(&secs)?.get(),
+                _ => 3600,
+            },
+            attestation_key: match dotenvy::var("ATTESTATION_PRIV_KEY") {
+                Ok(key) if !key.is_empty() => {
+                    Some(common::attestation::signing_key_from_hex(&key)?)
+                }
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Blob committed in a beacon block that neither the beacon nodes nor the archive returned
+#[derive(Debug, thiserror::Error)]
+#[error("blob {versioned_hash} of slot {slot} not found in the beacon nodes or the archive")]
+struct MissingBlobError {
+    slot: u64,
+    versioned_hash: B256,
+}
+
+/// Number of updates before the last one of an AD that an update without an epoch which doesn't
+/// verify is tried against, to find the state it links to
+const LINK_LOOKBACK: usize = 16;
+
+/// Number of missed updates up to which an update whose epoch is ahead of the last update of its
+/// AD is kept as orphan.  Its proof can't be verified until the updates it follows are stored, so
+/// the ones further ahead are rejected rather than kept indefinitely.
+const ORPHAN_MAX_GAP: i64 = 64;
+
+/// Number of updates after which the ops history of an AD is written again in `ad_ops`, instead of
+/// only the ops of the updates in `ad_op`
+const OPS_CHECKPOINT_INTERVAL: i64 = 64;
+
+/// State that an update payload which doesn't verify from the last update of its AD links to
+#[derive(Debug, thiserror::Error)]
+enum UpdateLink {
+    #[error("update links to num {num}, behind the last num {last_num}: replayed or out of order")]
+    Stale { num: i64, last_num: i64 },
+    #[error("update epoch {epoch} is ahead of the last num {last_num}: missed updates {}..={}", .last_num + 1, .epoch - 1)]
+    Gap { epoch: i64, last_num: i64 },
+    #[error(
+        "update epoch {epoch} is too far ahead of the last num {last_num}: more than {} missed updates",
+        ORPHAN_MAX_GAP
+    )]
+    FarAhead { epoch: i64, last_num: i64 },
+    #[error("update links to no state in nums {}..={last_num} nor the empty state: missed update or invalid proof", (.last_num - LINK_LOOKBACK as i64).max(0))]
+    Unknown { last_num: i64 },
+}
+
+/// Update payload stored in `orphan_update` to be applied once the updates it follows are
+/// stored, i.e. whose link to its AD is a `Gap` or `Unknown`
+#[derive(Debug, thiserror::Error)]
+#[error("{link}, kept as orphan")]
+struct OrphanUpdateError {
+    link: UpdateLink,
+}
+
+/// Create payload of an AD whose update proofs are built with other params than the ones of the
+/// synchronizer, so none of them would verify
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "AD {ad_id} is proven with params {payload_params} but the synchronizer verifies with params \
+     {params}: the ad-server and the synchronizer must run with the same params"
+)]
+struct ParamsMismatchError {
+    ad_id: String,
+    payload_params: String,
+    params: String,
+}
+
+/// Checks that the updates of the AD created by `payload` are proven with the params of
+/// `params_fingerprint`.  The ADs created before the fingerprint was published are accepted.
+fn check_params(payload: &PayloadCreate, params_fingerprint: Hash) -> Result<()> {
+    match payload.params_fingerprint {
+        Some(payload_params) if payload_params != params_fingerprint => Err(ParamsMismatchError {
+            ad_id: payload.id.encode_hex(),
+            payload_params: payload_params.encode_hex(),
+            params: params_fingerprint.encode_hex(),
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Verifies the proof of an update payload as a transition from the last update of its AD
+trait VerifyUpdate: Send + Sync + 'static {
+    /// `params_fingerprint` of the params the proofs are verified with
+    fn params_fingerprint(&self) -> Hash;
+
+    fn verify_update(
+        &self,
+        ad: &tables::Ad,
+        ad_update_last: &tables::AdUpdate,
+        payload: &PayloadUpdate,
+    ) -> Result<()>;
+}
+
+/// Verifies the update proofs with the shrunk main pod circuit data, or with the groth16 vk
+#[derive(Debug)]
+struct UpdateVerifier {
+    params: Params,
+    common_circuit_data: CommonCircuitData,
+    verifier_circuit_data: VerifierCircuitData,
+}
+
+impl VerifyUpdate for UpdateVerifier {
+    fn params_fingerprint(&self) -> Hash {
+        params_fingerprint(&self.params)
+    }
+
+    /// Verifies the proof of an update of `ad` from its last state.  The update payload doesn't
+    /// carry a predicate: the statement checked against the proof is built from the
+    /// `custom_predicate_ref` registered by the create payload of the AD, so a proof of a
+    /// transition under any other predicate doesn't verify.  Each AD is verified against its
+    /// own predicate, so ADs registered before the update predicate committed to the epoch keep
+    /// verifying with updates that don't carry one.  A batched update is a single proof of the
+    /// statements of all its updates.
+    fn verify_update(
+        &self,
+        ad: &tables::Ad,
+        ad_update_last: &tables::AdUpdate,
+        payload: &PayloadUpdate,
+    ) -> Result<()> {
+        // the proof commits to the epoch, which rejects updates replayed or published out of
+        // order even if the state returns to a previous value
+        if let Some(epoch) = payload
+            .first_epoch()
+            .filter(|epoch| *epoch != ad_update_last.num + 1)
+        {
+            return Err(anyhow!(
+                "update epoch {} doesn't follow num {} of AD {}",
+                epoch,
+                ad_update_last.num,
+                payload.id.encode_hex::<String>()
+            ));
+        }
+        let sts = payload.statements(&ad.custom_predicate_ref.0, ad_update_last.state.0);
+        match &payload.proof {
+            PayloadProof::Plonky2(compressed_proof) => {
+                verify_shrunk_update(
+                    &self.common_circuit_data,
+                    &self.verifier_circuit_data,
+                    &self.params,
+                    ad.vds_root.0,
+                    &sts,
+                    compressed_proof,
+                )?;
+            }
+            PayloadProof::Groth16(g16_proof) => {
+                let pub_inp =
+                    pod2_onchain::prepare_public_inputs(&self.params, ad.vds_root.0, &sts)?;
+                // encode it as big-endian bytes compatible with Gnark
+                let pub_inp_bytes = pod2_onchain::encode_public_inputs_gnark(pub_inp);
+
+                pod2_onchain::groth16_verify(g16_proof.clone(), pub_inp_bytes)?;
+            }
+        };
+        Ok(())
+    }
+}
+
+/// Runs the verifications of update proofs in the blocking thread pool, at most `concurrency` at
+/// once, so that they don't stall the sync loop and the endpoints that share the runtime.
+#[derive(Debug)]
+struct VerifyPool<V> {
+    verifier: Arc<V>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl<V> Clone for VerifyPool<V> {
+    fn clone(&self) -> Self {
+        Self {
+            verifier: self.verifier.clone(),
+            semaphore: self.semaphore.clone(),
+        }
+    }
+}
+
+impl<V: VerifyUpdate> VerifyPool<V> {
+    fn new(verifier: V, concurrency: usize) -> Self {
+        Self {
+            verifier: Arc::new(verifier),
+            semaphore: Arc::new(Semaphore::new(concurrency)),
+        }
+    }
+
+    /// Starts the verification once there's a free slot.  The result is taken with
+    /// `join_verification`.
+    async fn spawn(
+        &self,
+        ad: tables::Ad,
+        ad_update_last: tables::AdUpdate,
+        payload: PayloadUpdate,
+    ) -> JoinHandle<Result<()>> {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let verifier = self.verifier.clone();
+        task::spawn_blocking(move || {
+            let _permit = permit;
+            verifier.verify_update(&ad, &ad_update_last, &payload)
         })
     }
+
+    async fn verify(
+        &self,
+        ad: &tables::Ad,
+        ad_update_last: &tables::AdUpdate,
+        payload: &PayloadUpdate,
+    ) -> Result<()> {
+        let handle = self
+            .spawn(ad.clone(), ad_update_last.clone(), payload.clone())
+            .await;
+        join_verification(handle).await
+    }
+}
+
+/// Waits for a verification started by `VerifyPool::spawn`.  A verification that panics only
+/// fails its own update.
+async fn join_verification(handle: JoinHandle<Result<()>>) -> Result<()> {
+    handle
+        .await
+        .unwrap_or_else(|e| Err(anyhow!("verification task failed: {}", e)))
+}
+
+/// Max number of update events kept for the subscribers of `GET /ad/{id}/ws` that fall behind
+const AD_UPDATE_EVENTS_CAPACITY: usize = 1024;
+
+/// Update of an AD, sent to the subscribers of `GET /ad/{id}/ws` once it's committed
+#[derive(Clone, Debug)]
+struct AdUpdateEvent {
+    ad_id: Hash,
+    update: AdUpdateEntry,
 }
 
 #[derive(Clone, Debug)]
 struct Node {
     cfg: Config,
-    #[allow(dead_code)]
-    params: Params,
-    beacon_cli: BeaconClient,
-    rpc_cli: RootProvider,
+    beacon_cli: Arc<dyn CommonBeaconClient>,
+    beacon_cli_fallback: Option<Arc<dyn CommonBeaconClient>>,
+    blob_archive: Option<Arc<dyn BlobArchive>>,
+    rpc_cli: Arc<dyn ExecutionClient>,
     db: SqlitePool,
-    common_circuit_data: CommonCircuitData,
-    verifier_circuit_data: VerifierCircuitData,
+    verify_pool: VerifyPool<UpdateVerifier>,
+    ad_updates: broadcast::Sender<AdUpdateEvent>,
 }
 
 impl Node {
@@ -131,38 +498,107 @@ impl Node {
         if !Sqlite::database_exists(&cfg.sqlite_path).await? {
             Sqlite::create_database(&cfg.sqlite_path).await?;
         }
-        let db_pool = common::db_connection(&cfg.sqlite_path).await?;
+        let db_pool = common::db_connection(&cfg.sqlite_path, None, None).await?;
         init_db(&db_pool).await?;
 
+        // timeout of the archive requests, the beacon clients set their own
         let http_cli = reqwest::Client::builder()
             .timeout(Duration::from_secs(8))
             .build()?;
 
         let exp_backoff = Some(ExponentialBackoffBuilder::default().build());
-        let beacon_cli_cfg = beacon::Config {
-            base_url: cfg.beacon_url.clone(),
-            exp_backoff,
+        let beacon_cli_cfg = |base_url: &str| beacon::Config {
+            base_url: base_url.to_string(),
+            exp_backoff: exp_backoff.clone(),
+            auth_token: cfg.beacon_auth_token.clone(),
+            extra_headers: cfg.beacon_extra_headers.clone(),
+            blob_timeout: Some(Duration::from_secs(cfg.beacon_blob_timeout)),
+            timeout: Some(Duration::from_secs(cfg.beacon_timeout)),
+        };
+        let mut beacon_cli: Arc<dyn CommonBeaconClient> = Arc::new(BeaconClient::try_with_client(
+            http_cli.clone(),
+            beacon_cli_cfg(&cfg.beacon_url),
+        )?);
+        let mut beacon_cli_fallback = match cfg.beacon_url_fallback.as_deref() {
+            Some(url) => Some(Arc::new(BeaconClient::try_with_client(
+                http_cli.clone(),
+                beacon_cli_cfg(url),
+            )?) as Arc<dyn CommonBeaconClient>),
+            None => None,
+        };
+        let mut blob_archive = match &cfg.blob_archive_url {
+            Some(base_url) => {
+                let archive_cfg = archive::Config {
+                    base_url: base_url.clone(),
+                    exp_backoff: Some(ExponentialBackoffBuilder::default().build()),
+                };
+                let archive_cli = BlobscanClient::try_with_client(http_cli, archive_cfg)?;
+                Some(Arc::new(archive_cli) as Arc<dyn BlobArchive>)
+            }
+            None => None,
         };
-        let beacon_cli = BeaconClient::try_with_client(http_cli, beacon_cli_cfg)?;
-        let rpc_cli = RootProvider::<Ethereum>::new_http(cfg.rpc_url.parse()?);
+        let mut rpc_cli: Arc<dyn ExecutionClient> =
+            Arc::new(RootProvider::<Ethereum>::new_http(cfg.rpc_url.parse()?));
 
-        let params = Params::default();
+        // only the responses of `beacon_url` and `rpc_url` are recorded, so a replayed slot
+        // whose blobs came from the fallback or the archive misses them
+        if let Some(fixtures_path) = &cfg.fixtures_path {
+            let fixtures_path = Path::new(fixtures_path);
+            if cfg.record_fixtures {
+                info!(
+                    "Recording the beacon and RPC responses to {:?}",
+                    fixtures_path
+                );
+                beacon_cli = Arc::new(RecordingBeaconClient::new(beacon_cli, fixtures_path));
+                rpc_cli = Arc::new(RecordingExecutionClient::new(rpc_cli, fixtures_path));
+            } else {
+                info!(
+                    "Replaying the beacon and RPC responses from {:?}",
+                    fixtures_path
+                );
+                beacon_cli = Arc::new(ReplayBeaconClient::new(fixtures_path));
+                beacon_cli_fallback = None;
+                blob_archive = None;
+                rpc_cli = Arc::new(ReplayExecutionClient::new(fixtures_path));
+            }
+        }
+
+        let params = cfg.params.clone();
         info!("Loading circuit data...");
-        let (common_circuit_data, verifier_circuit_data) =
-            &*cache_get_shrunk_main_pod_circuit_data(&params);
+        let (common_circuit_data, verifier_circuit_data) = cache_get_shrunk_main_pod_circuit_data(
+            Path::new(&cfg.circuit_cache_path),
+            &params,
+            cfg.shrink_zk,
+        )?;
+
+        let verifier = UpdateVerifier {
+            params,
+            common_circuit_data: common_circuit_data.0,
+            verifier_circuit_data: verifier_circuit_data.0,
+        };
+        let verify_pool = VerifyPool::new(verifier, cfg.verify_concurrency);
 
         Ok(Self {
             cfg,
             db: db_pool,
             beacon_cli,
+            beacon_cli_fallback,
+            blob_archive,
             rpc_cli,
-            params,
-            common_circuit_data: (**common_circuit_data).clone(),
-            verifier_circuit_data: (**verifier_circuit_data).clone(),
+            verify_pool,
+            ad_updates: broadcast::channel(AD_UPDATE_EVENTS_CAPACITY).0,
         })
     }
 
-    fn slot_dir(&self, slot: u32) -> PathBuf {
+    fn common_circuit_data(&self) -> &CommonCircuitData {
+        &self.verify_pool.verifier.common_circuit_data
+    }
+
+    fn params_fingerprint(&self) -> Hash {
+        self.verify_pool.verifier.params_fingerprint()
+    }
+
+    fn slot_dir(&self, slot: u64) -> PathBuf {
         let slot_hi = slot / 1_000_000;
         let slot_mid = (slot - slot_hi * 1_000_000) / 1_000;
         let slot_lo = slot - slot_hi * 1_000_000 - slot_mid * 1_000;
@@ -177,7 +613,7 @@ impl Node {
         slot_dir
     }
 
-    async fn load_blobs_disk(&self, slot: u32) -> Result<HashMap<B256, Blob>> {
+    async fn load_blobs_disk(&self, slot: u64) -> Result<HashMap<B256, Blob>> {
         let slot_dir = self.slot_dir(slot);
         let rd = match read_dir(&slot_dir) {
             Err(e) => {
@@ -208,7 +644,7 @@ impl Node {
         Ok(blobs)
     }
 
-    async fn store_blobs_disk(&self, slot: u32, blobs: &HashMap<B256, Blob>) -> Result<()> {
+    async fn store_blobs_disk(&self, slot: u64, blobs: &HashMap<B256, Blob>) -> Result<()> {
         let slot_dir = self.slot_dir(slot);
         debug!("storing blobs of slot {} to {:?}", slot, slot_dir);
         create_dir_all(&slot_dir)?;
@@ -235,35 +671,69 @@ impl Node {
         None
     }
 
-    async fn get_blobs(&self, slot: u32, versioned_hashes: &[B256]) -> Result<HashMap<B256, Blob>> {
+    /// Gets the blobs of `slot` identified by their versioned hash, given with the index of the
+    /// blob in the block, from the disk, the beacon nodes or the archive.
+    async fn get_blobs(
+        &self,
+        slot: u64,
+        indexed_vhs: &[(B256, u32)],
+    ) -> Result<HashMap<B256, Blob>> {
+        let versioned_hashes: Vec<B256> = indexed_vhs.iter().map(|(vh, _)| *vh).collect();
+        let versioned_hashes = versioned_hashes.as_slice();
         let blobs = self.load_blobs_disk(slot).await?;
-        if Self::validate_blobs(&blobs, versioned_hashes).is_some() {
-            let blobs = self.beacon_cli.get_blobs(slot.into()).await?;
-            debug!("got {} AD blobs from beacon_cli", blobs.len());
-            let blobs: HashMap<_, _> = blobs
-                .into_iter()
-                .filter_map(|blob| {
-                    let versioned_hash = kzg_to_versioned_hash(blob.kzg_commitment.as_ref());
-                    versioned_hashes
-                        .contains(&versioned_hash)
-                        .then_some((versioned_hash, blob))
-                })
-                .collect();
-            if let Some(vh) = Self::validate_blobs(&blobs, versioned_hashes) {
-                return Err(anyhow!("Blob {} not found in beacon_cli response", vh));
+        if Self::validate_blobs(&blobs, versioned_hashes).is_none() {
+            return Ok(blobs);
+        }
+
+        let mut blobs = HashMap::new();
+        let beacon_clis = std::iter::once(&self.beacon_cli).chain(&self.beacon_cli_fallback);
+        for (i, beacon_cli) in beacon_clis.enumerate() {
+            let res = beacon_cli
+                .get_blobs_by_versioned_hash(
+                    slot.into(),
+                    versioned_hashes,
+                    self.cfg.blob_fetch_retries,
+                    Duration::from_secs(1),
+                )
+                .await;
+            match res {
+                Ok(beacon_blobs) => {
+                    debug!("got {} AD blobs from beacon_cli {}", beacon_blobs.len(), i);
+                    for (vh, blob) in beacon_blobs {
+                        blobs.entry(vh).or_insert(blob);
+                    }
+                }
+                // the beacon node pruned the blobs of this slot
+                Err(ClientError::NotFound(_)) => debug!("blobs of slot {} not found", slot),
+                Err(e) if i == 0 && self.beacon_cli_fallback.is_some() => {
+                    warn!("getting the blobs of slot {} failed: {}", slot, e)
+                }
+                Err(e) => return Err(e.into()),
             }
-            self.store_blobs_disk(slot, &blobs).await?;
-            Ok(blobs)
-        } else {
-            Ok(blobs)
+            if Self::validate_blobs(&blobs, versioned_hashes).is_none() {
+                break;
+            }
+        }
+        if let Some(blob_archive) = &self.blob_archive {
+            fill_missing_blobs(blob_archive.as_ref(), &mut blobs, indexed_vhs).await?;
+        }
+        if let Some(versioned_hash) = Self::validate_blobs(&blobs, versioned_hashes) {
+            return Err(MissingBlobError {
+                slot,
+                versioned_hash,
+            }
+            .into());
         }
+        self.store_blobs_disk(slot, &blobs).await?;
+        Ok(blobs)
     }
 
+    /// Processes the AD payloads of the beacon block, returning the updates applied.
     async fn process_beacon_block_header(
         &self,
         db_tx: &mut sqlx::SqliteTransaction<'_>,
         beacon_block_header: &BlockHeader,
-    ) -> Result<Option<()>> {
+    ) -> Result<Vec<AdUpdateEvent>> {
         let beacon_block_root = beacon_block_header.root;
         let slot = beacon_block_header.slot;
 
@@ -275,14 +745,14 @@ impl Node {
             Some(block) => block,
             None => {
                 debug!("slot {} has empty block", slot);
-                return Ok(None);
+                return Ok(Vec::new());
             }
         };
         let execution_payload = match beacon_block.execution_payload {
             Some(payload) => payload,
             None => {
                 debug!("slot {} has no execution payload", slot);
-                return Ok(None);
+                return Ok(Vec::new());
             }
         };
         debug!(
@@ -297,320 +767,2657 @@ impl Node {
                 .unwrap_or_default(),
         );
 
-        let has_kzg_blob_commitments = match beacon_block.blob_kzg_commitments {
-            Some(commitments) => !commitments.is_empty(),
-            None => false,
-        };
-        if !has_kzg_blob_commitments {
+        // versioned hashes of the blobs of the block, in sidecar order
+        let block_blob_vhs: Vec<B256> = beacon_block
+            .blob_kzg_commitments
+            .iter()
+            .flatten()
+            .map(|commitment| kzg_to_versioned_hash(commitment.as_ref()))
+            .collect();
+        let has_kzg_blob_commitments = !block_blob_vhs.is_empty();
+        if !has_kzg_blob_commitments && !self.cfg.index_calldata {
             debug!("slot {} has no blobs", slot);
-            return Ok(None);
+            return Ok(Vec::new());
         }
 
         let execution_block_hash = execution_payload.block_hash;
 
-        let execution_block_id = alloy_eips::eip1898::BlockId::Hash(execution_block_hash.into());
         let execution_block = self
             .rpc_cli
-            .get_block(execution_block_id)
-            .full()
+            .get_block(execution_block_hash)
             .await?
             .with_context(|| format!("Execution block {execution_block_hash} not found"))?;
 
-        let indexed_ad_blob_txs: Vec<_> = match execution_block.transactions.as_transactions() {
-            Some(txs) => txs
-                .iter()
-                .enumerate()
-                .filter(|(_index, tx)| {
-                    tx.inner.blob_versioned_hashes().is_some()
-                        && tx.as_recovered().to() == Some(self.cfg.to_addr)
-                })
-                .collect(),
+        let txs = match execution_block.transactions.as_transactions() {
+            Some(txs) => txs,
+            None if !has_kzg_blob_commitments => &[],
             None => {
                 return Err(anyhow!(
                     "Consensus block {beacon_block_root} has blobs but the execution block doesn't have txs"
                 ));
             }
         };
+        // the blobs are attributed to their txs before the AD txs are picked, so that the other
+        // blob txs of the block can't get in the way.  A blob carried by several txs is applied
+        // once, from the first AD tx that carries it.
+        let ad_blobs: Vec<_> = attribute_blobs(slot, &block_blob_vhs, txs)
+            .into_iter()
+            .filter_map(|blob| {
+                let tx = blob.txs.into_iter().find(|tx| {
+                    tx.as_recovered().to() == Some(self.cfg.to_addr) && self.is_sender_allowed(tx)
+                })?;
+                Some((blob.versioned_hash, blob.index, tx))
+            })
+            .collect();
+        let ad_calldata_txs: Vec<_> = if self.cfg.index_calldata {
+            txs.iter()
+                .filter(|tx| {
+                    tx.inner.blob_versioned_hashes().is_none()
+                        && tx.as_recovered().to() == Some(self.cfg.to_addr)
+                        && !tx.inner.input().is_empty()
+                        && self.is_sender_allowed(tx)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
 
-        if indexed_ad_blob_txs.is_empty() {
-            return Ok(None);
+        if ad_blobs.is_empty() && ad_calldata_txs.is_empty() {
+            return Ok(Vec::new());
         }
 
-        let txs_blobs_vhs: Vec<B256> = indexed_ad_blob_txs
+        let txs_blobs_vhs: Vec<(B256, u32)> = ad_blobs
             .iter()
-            .flat_map(|(_, tx)| {
-                tx.as_recovered()
-                    .blob_versioned_hashes()
-                    .expect("tx has blobs")
-            })
-            .cloned()
+            .map(|(vh, index, _)| (*vh, *index))
             .collect();
-        let blobs = self.get_blobs(slot, &txs_blobs_vhs).await?;
+        let blobs = if txs_blobs_vhs.is_empty() {
+            HashMap::new()
+        } else {
+            self.get_blobs(slot, &txs_blobs_vhs).await?
+        };
 
-        for (_tx_index, tx) in indexed_ad_blob_txs {
+        // the payloads are decoded first and applied in the order of their txs in the block,
+        // whether they're in blobs or in calldata, and of their blobs within a tx
+        let tx_indexes: HashMap<B256, usize> = txs
+            .iter()
+            .enumerate()
+            .map(|(tx_index, tx)| (*tx.as_recovered().hash(), tx_index))
+            .collect();
+        let mut payloads = Vec::new();
+        for (versioned_hash, _, tx) in ad_blobs {
             let tx = tx.as_recovered();
             let hash = tx.hash();
             let from = tx.signer();
             let to = tx.to();
-            let tx_blobs: Vec<_> = tx
-                .blob_versioned_hashes()
-                .expect("tx has blobs")
-                .iter()
-                .map(|blob_versioned_hash| &blobs[blob_versioned_hash])
-                .collect();
             trace!(?hash, ?from, ?to);
-
-            for blob in tx_blobs.iter() {
-                match self.process_ad_blob(db_tx, blob).await {
-                    Ok(_) => {
-                        info!("Valid ad_blob at slot {}, blob_index {}!", slot, blob.index);
-                    }
-                    Err(e) => {
-                        info!("Invalid ad_blob: {:?}", e);
-                        continue;
-                    }
-                };
-
-                Database(&mut **db_tx)
-                    .add_blob(&tables::Blob {
-                        versioned_hash: kzg_to_versioned_hash(blob.kzg_commitment.as_ref()).0,
-                        slot: slot as i64,
+            let blob = &blobs[&versioned_hash];
+            let payload = match bytes_from_simple_blob(blob.blob.inner())
+                .context("Invalid byte encoding in blob")
+            {
+                Ok(bytes) => self.decode_payload(bytes).await,
+                Err(err) => Err(err),
+            };
+            let order = (tx_indexes[hash], blob.index);
+            payloads.push((
+                order,
+                SlotPayload {
+                    label: format!("ad_blob at slot {}, blob_index {}", slot, blob.index),
+                    source: versioned_hash.0,
+                    tx_hash: hash.0,
+                    blob: Some(tables::Blob {
+                        versioned_hash: versioned_hash.0,
+                        slot: i64::try_from(slot)?,
                         block: execution_block.header.number as i64,
                         blob_index: blob.index as i64,
                         timestamp: execution_block.header.timestamp as i64,
-                    })
-                    .await?;
-            }
+                        sender: Some(from.to_vec()),
+                    }),
+                    payload,
+                },
+            ));
         }
-        Ok(Some(()))
+        for tx in ad_calldata_txs {
+            let tx = tx.as_recovered();
+            let hash = tx.hash();
+            trace!(?hash, from = ?tx.signer(), to = ?tx.to());
+            payloads.push((
+                (tx_indexes[hash], 0),
+                SlotPayload {
+                    label: format!("ad calldata at slot {}, tx {}", slot, hash),
+                    source: hash.0,
+                    tx_hash: hash.0,
+                    blob: None,
+                    payload: self.decode_payload(tx.input().to_vec()).await,
+                },
+            ));
+        }
+        payloads.sort_by_key(|(order, _)| *order);
+        let payloads = payloads.into_iter().map(|(_, payload)| payload).collect();
+
+        let mut ad_updates = apply_slot_payloads(&self.verify_pool, db_tx, payloads).await?;
+        ad_updates.extend(
+            apply_orphan_updates(&self.verify_pool, db_tx, |bytes| {
+                Payload::from_bytes(bytes, self.common_circuit_data())
+            })
+            .await?,
+        );
+        Ok(ad_updates)
     }
 
-    async fn process_ad_blob(
-        &self,
-        db_tx: &mut sqlx::SqliteTransaction<'_>,
-        blob: &Blob,
-    ) -> Result<()> {
-        let bytes =
-            bytes_from_simple_blob(blob.blob.inner()).context("Invalid byte encoding in blob")?;
-        let payload = Payload::from_bytes(&bytes, &self.common_circuit_data)?;
+    // whether the AD tx is indexed according to the allowlist of senders
+    fn is_sender_allowed(&self, tx: &alloy::rpc::types::Transaction) -> bool {
+        let tx = tx.as_recovered();
+        let allowed = is_sender_allowed(self.cfg.from_addr_allowlist.as_deref(), tx.signer());
+        if !allowed {
+            info!(
+                "Skipping AD tx {} from {}, not in the allowlist",
+                tx.hash(),
+                tx.signer()
+            );
+        }
+        allowed
+    }
 
-        match payload {
-            Payload::Create(payload) => self.process_payload_init(db_tx, blob, payload).await,
-            Payload::Update(payload) => self.process_payload_update(db_tx, blob, payload).await,
+    /// Fetches again the AD payload bytes published in the blob with versioned hash `source`, or
+    /// in the calldata of the tx with hash `source`, according to the kind of its payload source.
+    /// The payloads applied before their kind was recorded are taken as calldata if no blob was
+    /// indexed with that hash.
+    async fn fetch_payload_bytes(&self, source: tables::B256Sql) -> Result<Vec<u8>> {
+        let hash = B256::from(source);
+        let blob = match Database(&self.db).get_payload_source(source).await? {
+            Some(payload_source) if payload_source.kind == tables::PayloadSource::CALLDATA => None,
+            Some(_) => Some(
+                Database(&self.db)
+                    .get_blob(source)
+                    .await?
+                    .with_context(|| format!("Blob {} not found", hash))?,
+            ),
+            None => Database(&self.db).get_blob(source).await?,
+        };
+        match blob {
+            Some(blob) => {
+                let blob_index = u32::try_from(blob.blob_index)?;
+                let blobs = self
+                    .get_blobs(u64::try_from(blob.slot)?, &[(hash, blob_index)])
+                    .await?;
+                bytes_from_simple_blob(blobs[&hash].blob.inner())
+                    .context("Invalid byte encoding in blob")
+            }
+            None => {
+                let tx = self
+                    .rpc_cli
+                    .get_transaction_by_hash(hash)
+                    .await?
+                    .with_context(|| format!("Neither blob nor tx {} found", hash))?;
+                Ok(tx.inner.input().to_vec())
+            }
         }
     }
 
-    async fn process_payload_init(
+    /// Fetches again the blob with versioned hash `versioned_hash` and decodes its AD payload.
+    /// Returns none if no blob was indexed with that hash.
+    async fn fetch_blob_payload(
         &self,
-        db_tx: &mut sqlx::SqliteTransaction<'_>,
-        blob: &Blob,
-        payload: PayloadCreate,
-    ) -> Result<()> {
-        match Database(&mut **db_tx).get_ad(payload.id).await {
-            Err(err) => match err.root_cause().downcast_ref::<sqlx::Error>() {
-                Some(&sqlx::Error::RowNotFound) => {}
-                _ => return Err(err),
-            },
-            Ok(ad) => {
-                return Err(anyhow!(
-                    "got init payload {:?} but AD already exists {:?}",
-                    payload,
-                    ad
-                ));
-            }
+        versioned_hash: B256,
+    ) -> Result<Option<(tables::Blob, Payload)>> {
+        let Some(blob) = Database(&self.db).get_blob(versioned_hash.0).await? else {
+            return Ok(None);
         };
+        let blob_index = u32::try_from(blob.blob_index)?;
+        let blobs = self
+            .get_blobs(u64::try_from(blob.slot)?, &[(versioned_hash, blob_index)])
+            .await?;
+        let bytes = bytes_from_simple_blob(blobs[&versioned_hash].blob.inner())
+            .context("Invalid byte encoding in blob")?;
+        let payload = self.decode_payload(bytes).await?;
+        Ok(Some((blob, payload)))
+    }
 
-        let blob_versioned_hash = kzg_to_versioned_hash(blob.kzg_commitment.as_ref()).0;
-        let ad = tables::Ad {
-            id: HashSql(payload.id),
-            custom_predicate_ref: CustomPredicateRefSql(payload.custom_predicate_ref),
-            vds_root: HashSql(payload.vds_root),
-            blob_versioned_hash,
-        };
-        Database(&mut **db_tx).add_ad(&ad).await?;
-        let ad_update = tables::AdUpdate {
-            id: HashSql(payload.id),
-            num: 0,
-            state: RawValueSql(EMPTY_VALUE),
-            blob_versioned_hash,
-        };
-        Database(&mut **db_tx).add_ad_update(&ad_update).await?;
-        info!(
-            payload = "Create",
-            ad_id = payload.id.encode_hex::<String>()
-        );
-        Ok(())
+    /// Decodes an AD payload in the blocking thread pool, since reading the compressed proof of an
+    /// update is CPU bound like verifying it.
+    async fn decode_payload(&self, bytes: Vec<u8>) -> Result<Payload> {
+        let verifier = self.verify_pool.verifier.clone();
+        task::spawn_blocking(move || Payload::from_bytes(&bytes, &verifier.common_circuit_data))
+            .await?
     }
 
-    async fn process_payload_update(
+    /// Processes the beacon block (if any) of `slot`, and marks the slot as visited if
+    /// `mark_visited`.  Only the slots followed by `run` are marked, since it resumes after the
+    /// last visited one, which the slots processed by the one-shot commands would skip ahead.  The
+    /// events logged while processing it are in a `slot` span.
+    async fn process_slot(
         &self,
-        db_tx: &mut sqlx::SqliteTransaction<'_>,
-        blob: &Blob,
-        payload: PayloadUpdate,
+        slot: u64,
+        beacon_block_header: Option<BlockHeader>,
+        mark_visited: bool,
     ) -> Result<()> {
-        let ad = Database(&mut **db_tx).get_ad(payload.id).await?;
-        let ad_update_last = Database(&mut **db_tx)
-            .get_ad_update_last(payload.id)
-            .await?;
+        self.process_slot_in_span(slot, beacon_block_header, mark_visited)
+            .instrument(info_span!("slot", slot))
+            .await
+    }
 
-        let st = Statement::Custom(
-            ad.custom_predicate_ref.0,
-            vec![
-                Value::from(payload.new_state),
-                Value::from(ad_update_last.state.0),
-                Value::from(payload.op),
-            ],
-        );
-        let sts_hash = calculate_statements_hash(&[st.clone().into()], &self.params);
-        let public_inputs: Vec<F> = [sts_hash.0, ad.vds_root.0.0].concat();
-        match payload.proof {
-            PayloadProof::Plonky2(compressed_proof) => {
-                let proof_with_pis = CompressedProofWithPublicInputs {
-                    proof: *compressed_proof,
-                    public_inputs,
-                };
-                let proof = proof_with_pis
-                    .decompress(
-                        &self.verifier_circuit_data.verifier_only.circuit_digest,
-                        &self.common_circuit_data,
-                    )
-                    .context("CompressedProofWithPublicInputs::decompress")?;
-                self.verifier_circuit_data.verify(proof)?;
+    async fn process_slot_in_span(
+        &self,
+        slot: u64,
+        beacon_block_header: Option<BlockHeader>,
+        mark_visited: bool,
+    ) -> Result<()> {
+        let beacon_block_header = match beacon_block_header {
+            Some(block) => block,
+            None => {
+                debug!("slot {} has empty block", slot);
+                if mark_visited {
+                    Database(&self.db).add_visited_slot(slot).await?;
+                }
+                return Ok(());
             }
-            PayloadProof::Groth16(g16_proof) => {
-                let pub_inp =
-                    pod2_onchain::prepare_public_inputs(&self.params, ad.vds_root.0, &[st])?;
-                // encode it as big-endian bytes compatible with Gnark
-                let pub_inp_bytes = pod2_onchain::encode_public_inputs_gnark(pub_inp);
+        };
 
-                pod2_onchain::groth16_verify(g16_proof, pub_inp_bytes)?;
+        let mut tx = self.db.begin().await?;
+        let ad_updates = match self
+            .process_beacon_block_header(&mut tx, &beacon_block_header)
+            .await
+        {
+            Ok(ad_updates) => {
+                Database(&mut *tx).delete_failed_slot(slot).await?;
+                ad_updates
             }
+            // the slot is recorded to be processed again later (e.g. with `process-slot`) instead
+            // of stopping the sync, and nothing else of it is stored
+            Err(err) if err.downcast_ref::<MissingBlobError>().is_some() => {
+                warn!("skipping slot {}: {:#}", slot, err);
+                tx.rollback().await?;
+                tx = self.db.begin().await?;
+                Database(&mut *tx)
+                    .add_failed_slot(slot, &format!("{:#}", err))
+                    .await?;
+                Vec::new()
+            }
+            Err(err) => return Err(err),
         };
+        if mark_visited {
+            Database(&mut *tx).add_visited_slot(slot).await?;
+        }
+        tx.commit().await?;
+        for ad_update in ad_updates {
+            // no subscribers otherwise
+            let _ = self.ad_updates.send(ad_update);
+        }
 
-        let blob_versioned_hash = kzg_to_versioned_hash(blob.kzg_commitment.as_ref()).0;
-        let ad_update = tables::AdUpdate {
-            id: HashSql(payload.id),
-            num: ad_update_last.num + 1,
-            state: RawValueSql(payload.new_state),
-            blob_versioned_hash,
-        };
-        Database(&mut **db_tx).add_ad_update(&ad_update).await?;
-        info!(
-            payload = "Update",
-            ad_id = payload.id.encode_hex::<String>(),
-            num = ad_update.num,
-            old_state = ad_update_last.state.0.encode_hex::<String>(),
-            new_state = payload.new_state.encode_hex::<String>(),
-            op = payload.op.encode_hex::<String>()
-        );
+        if self.cfg.request_rate != 0 {
+            let requests = 5;
+            let delay_ms = 1000 * requests / self.cfg.request_rate;
+            sleep(Duration::from_millis(delay_ms)).await;
+        }
         Ok(())
     }
-}
 
-fn log_init() {
-    tracing_subscriber::registry()
-        .with(fmt::layer())
-        .with(EnvFilter::from_default_env())
-        .init();
-}
+    /// Processes the closed slot range `[from_slot, to_slot]`, using `ad_genesis_slot` as floor.
+    /// The slots aren't marked as visited, so `run` still goes through the ones it hasn't followed.
+    async fn backfill(&self, from_slot: u64, to_slot: u64) -> Result<()> {
+        let from_slot = from_slot.max(self.cfg.ad_genesis_slot);
+        info!("backfilling slots {}..={}", from_slot, to_slot);
+        for slot in from_slot..=to_slot {
+            debug!("checking slot {}", slot);
+            let beacon_block_header = self
+                .beacon_cli
+                .get_block_header(BlockId::Slot(slot))
+                .await?;
+            self.process_slot(slot, beacon_block_header, false).await?;
+        }
+        info!("backfill of slots {}..={} complete", from_slot, to_slot);
+        Ok(())
+    }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    log_init();
-    load_dotenv()?;
-    let cfg = Config::from_env()?;
-    info!(?cfg, "Loaded config");
+    /// Compacts the updates of every AD, see `compact_ad`.  Returns the number of updates
+    /// pruned.
+    async fn compact(&self) -> Result<u64> {
+        let mut pruned = 0;
+        for ad_id in Database(&self.db).get_ad_ids().await? {
+            pruned += compact_ad(
+                &self.db,
+                ad_id,
+                self.cfg.checkpoint_interval,
+                self.cfg.ad_update_retention,
+            )
+            .await
+            .with_context(|| format!("AD {}", ad_id.encode_hex::<String>()))?;
+        }
+        Ok(pruned)
+    }
 
-    if cfg.proof_type == ProofType::Groth16 {
-        // initialize groth16 memory with the vk
-        common::groth::load_vk()?;
+    /// Compacts the updates every `cfg.compaction_interval` seconds.
+    async fn compact_loop(&self) {
+        let mut compaction_interval =
+            tokio::time::interval(Duration::from_secs(self.cfg.compaction_interval));
+        loop {
+            compaction_interval.tick().await;
+            match self.compact().await {
+                Ok(0) => {}
+                Ok(pruned) => info!("compaction pruned {} updates", pruned),
+                Err(err) => warn!("compaction failed: {:#}", err),
+            }
+        }
     }
+}
 
-    let node = Node::new(cfg).await?;
+/// Blob of a block attributed to the blob txs that carry it
+struct BlockBlob<'a> {
+    versioned_hash: B256,
+    // first position of the blob in the sidecars
+    index: u32,
+    // in block order, several if the blob was sent again with the same sidecar
+    txs: Vec<&'a alloy::rpc::types::Transaction>,
+}
 
-    let spec = node.beacon_cli.get_spec().await?;
-    info!(?spec, "Beacon spec");
-    let head = node
-        .beacon_cli
-        .get_block_header(BlockId::Head)
-        .await?
-        .expect("head is not None");
-    info!(?head, "Beacon head");
+/// Attributes the blobs of the block at `slot`, given by their versioned hashes in sidecar order,
+/// to the txs of the block that carry them, whatever their receiver.  The blobs that are in the
+/// sidecars more than once are only attributed once, and the ones that no tx carries are skipped,
+/// with a warning, as are the versioned hashes of the txs without a blob in the sidecars.
+fn attribute_blobs<'a>(
+    slot: u64,
+    block_blob_vhs: &[B256],
+    txs: &'a [alloy::rpc::types::Transaction],
+) -> Vec<BlockBlob<'a>> {
+    let mut carriers: HashMap<B256, Vec<&alloy::rpc::types::Transaction>> = HashMap::new();
+    for tx in txs {
+        for versioned_hash in tx.inner.blob_versioned_hashes().into_iter().flatten() {
+            carriers.entry(*versioned_hash).or_default().push(tx);
+            if !block_blob_vhs.contains(versioned_hash) {
+                warn!(
+                    "blob {} of tx {} at slot {} isn't in the sidecars",
+                    versioned_hash,
+                    tx.as_recovered().hash(),
+                    slot
+                );
+            }
+        }
+    }
 
-    {
+    let mut blobs: Vec<BlockBlob> = Vec::new();
+    for (index, versioned_hash) in block_blob_vhs.iter().enumerate() {
+        if blobs
+            .iter()
+            .any(|blob| blob.versioned_hash == *versioned_hash)
+        {
+            warn!(
+                "blob {} at slot {} is in the sidecars more than once, skipping index {}",
+                versioned_hash, slot, index
+            );
+            continue;
+        }
+        match carriers.remove(versioned_hash) {
+            Some(txs) => {
+                if txs.len() > 1 {
+                    warn!(
+                        "blob {} at slot {} is carried by {} txs",
+                        versioned_hash,
+                        slot,
+                        txs.len()
+                    );
+                }
+                blobs.push(BlockBlob {
+                    versioned_hash: *versioned_hash,
+                    index: index as u32,
+                    txs,
+                });
+            }
+            None => warn!(
+                "blob {} at slot {} isn't carried by any tx, skipping it",
+                versioned_hash, slot
+            ),
+        }
+    }
+    blobs
+}
+
+/// AD payload found in a slot, decoded but not applied yet
+struct SlotPayload {
+    // describes where the payload comes from in the logs
+    label: String,
+    // versioned hash of the blob, or hash of the tx of the calldata
+    source: tables::B256Sql,
+    // hash of the tx that carries the payload
+    tx_hash: tables::B256Sql,
+    // stored once the payload is applied, none for calldata
+    blob: Option<tables::Blob>,
+    payload: Result<Payload>,
+}
+
+/// Verification of an update started before its turn to be applied, from the last update of the
+/// AD at that point
+struct PreVerified {
+    num: i64,
+    state: RawValue,
+    handle: JoinHandle<Result<()>>,
+}
+
+/// Starts the verification of the update payloads, which run concurrently.  The updates of an AD
+/// in the slot are verified as a chain, each from the state of the previous one, so a
+/// verification only holds if the AD is still at the same update when it's applied, which isn't
+/// the case after an invalid update of the AD.
+async fn preverify_updates<V: VerifyUpdate>(
+    verify_pool: &VerifyPool<V>,
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    payloads: &[SlotPayload],
+) -> Vec<Option<PreVerified>> {
+    // AD and last update of the ADs as they'll be when each payload is applied, assuming the
+    // previous ones are valid
+    let mut ads: HashMap<Hash, (tables::Ad, tables::AdUpdate)> = HashMap::new();
+    let mut preverified = Vec::with_capacity(payloads.len());
+    for slot_payload in payloads {
+        let payload = match &slot_payload.payload {
+            Ok(Payload::Create(payload)) => {
+                if !ads.contains_key(&payload.id)
+                    && check_params(payload, verify_pool.verifier.params_fingerprint()).is_ok()
+                    && Database(&mut **db_tx).get_ad(payload.id).await.is_err()
+                {
+                    let ad = tables::Ad {
+                        id: HashSql(payload.id),
+                        custom_predicate_ref: CustomPredicateRefSql(
+                            payload.custom_predicate_ref.clone(),
+                        ),
+                        vds_root: HashSql(payload.vds_root),
+                        blob_versioned_hash: slot_payload.source,
+                        params_fingerprint: payload.params_fingerprint.map(HashSql),
+                    };
+                    let ad_update = tables::AdUpdate {
+                        id: HashSql(payload.id),
+                        num: 0,
+                        state: RawValueSql(EMPTY_VALUE),
+                        blob_versioned_hash: slot_payload.source,
+                    };
+                    ads.insert(payload.id, (ad, ad_update));
+                }
+                preverified.push(None);
+                continue;
+            }
+            Ok(Payload::Update(payload)) => payload,
+            _ => {
+                preverified.push(None);
+                continue;
+            }
+        };
+        let last = match ads.remove(&payload.id) {
+            Some(last) => Some(last),
+            None => match Database(&mut **db_tx).get_ad(payload.id).await {
+                Ok(ad) => Database(&mut **db_tx)
+                    .get_ad_update_last(payload.id)
+                    .await
+                    .ok()
+                    .map(|ad_update_last| (ad, ad_update_last)),
+                Err(_) => None,
+            },
+        };
+        // unknown AD, which fails when applied
+        let Some((ad, ad_update_last)) = last else {
+            preverified.push(None);
+            continue;
+        };
+        let next = tables::AdUpdate {
+            id: HashSql(payload.id),
+            num: ad_update_last.num + payload.num_updates(),
+            state: RawValueSql(payload.new_state),
+            blob_versioned_hash: slot_payload.source,
+        };
+        preverified.push(Some(PreVerified {
+            num: ad_update_last.num,
+            state: ad_update_last.state.0,
+            handle: verify_pool
+                .spawn(ad.clone(), ad_update_last, payload.clone())
+                .await,
+        }));
+        ads.insert(payload.id, (ad, next));
+    }
+    preverified
+}
+
+/// Applies the payloads of a slot in order, returning the updates applied.  Their proofs are
+/// verified concurrently beforehand, and an invalid payload is skipped without affecting the
+/// others.  The blobs and calldata txs already applied in an earlier slot, e.g. included again by
+/// a re-broadcast tx, are skipped.
+async fn apply_slot_payloads<V: VerifyUpdate>(
+    verify_pool: &VerifyPool<V>,
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    slot_payloads: Vec<SlotPayload>,
+) -> Result<Vec<AdUpdateEvent>> {
+    let mut payloads = Vec::with_capacity(slot_payloads.len());
+    for slot_payload in slot_payloads {
+        let applied = match &slot_payload.blob {
+            Some(blob) => {
+                Database(&mut **db_tx)
+                    .blob_exists(blob.versioned_hash)
+                    .await?
+            }
+            None => {
+                Database(&mut **db_tx)
+                    .payload_source_exists(slot_payload.source)
+                    .await?
+            }
+        };
+        if applied {
+            info!("Skipping {}, it's already applied", slot_payload.label);
+        } else {
+            payloads.push(slot_payload);
+        }
+    }
+
+    let mut ad_updates = Vec::new();
+    let preverified = preverify_updates(verify_pool, db_tx, &payloads).await;
+    for (slot_payload, preverified) in payloads.into_iter().zip(preverified) {
+        let SlotPayload {
+            label,
+            source,
+            tx_hash,
+            blob,
+            payload,
+        } = slot_payload;
+        let res = match payload {
+            Ok(Payload::Create(payload)) => {
+                process_payload_init(
+                    db_tx,
+                    source,
+                    payload,
+                    verify_pool.verifier.params_fingerprint(),
+                )
+                .await
+            }
+            Ok(Payload::Update(payload)) => {
+                let ad_id = payload.id;
+                process_payload_update(
+                    verify_pool,
+                    db_tx,
+                    source,
+                    blob.as_ref(),
+                    payload,
+                    preverified,
+                )
+                .await
+                .map(|updates| {
+                    ad_updates.extend(
+                        updates
+                            .into_iter()
+                            .map(|update| AdUpdateEvent { ad_id, update }),
+                    )
+                })
+            }
+            Ok(Payload::Snapshot(payload)) => {
+                process_payload_snapshot(db_tx, source, payload).await
+            }
+            Err(err) => Err(err),
+        };
+        match res {
+            Err(e) if e.downcast_ref::<OrphanUpdateError>().is_some() => {
+                info!("Orphan {}: {:#}", label, e);
+                continue;
+            }
+            Err(e) if e.downcast_ref::<ParamsMismatchError>().is_some() => {
+                error!("Rejected {}: {:#}", label, e);
+                continue;
+            }
+            Err(e) => {
+                info!("Invalid {}: {:?}", label, e);
+                continue;
+            }
+            Ok(()) => {}
+        }
+        info!("Valid {}!", label);
+
+        let kind = match blob {
+            Some(blob) => {
+                Database(&mut **db_tx).add_blob(&blob).await?;
+                tables::PayloadSource::BLOB
+            }
+            None => tables::PayloadSource::CALLDATA,
+        };
+        Database(&mut **db_tx)
+            .add_payload_source(&tables::PayloadSource {
+                source,
+                kind: kind.to_string(),
+                tx_hash: Some(tx_hash.to_vec()),
+            })
+            .await?;
+    }
+    Ok(ad_updates)
+}
+
+/// Applies the orphan updates that follow the last update of their AD now that the updates they
+/// were missing are stored, e.g. once a failed slot is processed again.  The orphans without an
+/// epoch are tried again after each new update of their AD, until it's more than `LINK_LOOKBACK`
+/// updates past the one they were first stored at, and the ones that turn out to be stale or
+/// invalid are dropped.  Returns the updates applied.
+async fn apply_orphan_updates<V: VerifyUpdate>(
+    verify_pool: &VerifyPool<V>,
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    decode: impl Fn(&[u8]) -> Result<Payload>,
+) -> Result<Vec<AdUpdateEvent>> {
+    let mut ad_updates = Vec::new();
+    loop {
+        // the updates they were missing would have been applied by then
+        let expired = Database(&mut **db_tx)
+            .delete_expired_orphan_updates(LINK_LOOKBACK as i64)
+            .await?;
+        if expired > 0 {
+            info!(
+                "Dropped {} orphan updates without an epoch, their AD moved on",
+                expired
+            );
+        }
+        let orphans = Database(&mut **db_tx).get_orphan_updates_ready().await?;
+        if orphans.is_empty() {
+            return Ok(ad_updates);
+        }
+        for orphan in orphans {
+            let label = format!(
+                "orphan update {}",
+                orphan.blob_versioned_hash.encode_hex::<String>()
+            );
+            let blob = orphan.blob();
+            let res = match decode(&orphan.payload) {
+                Ok(Payload::Update(payload)) => {
+                    let ad_id = payload.id;
+                    process_payload_update(
+                        verify_pool,
+                        db_tx,
+                        orphan.blob_versioned_hash,
+                        blob.as_ref(),
+                        payload,
+                        None,
+                    )
+                    .await
+                    .map(|updates| (ad_id, updates))
+                }
+                Ok(_) => Err(anyhow!("not an update payload")),
+                Err(e) => Err(e),
+            };
+            match res {
+                // kept by `process_payload_update` with the num it was checked at
+                Err(e) if e.downcast_ref::<OrphanUpdateError>().is_some() => {
+                    debug!("Still orphan {}: {:#}", label, e);
+                    continue;
+                }
+                Err(e) => {
+                    info!("Invalid {}: {:?}", label, e);
+                    Database(&mut **db_tx)
+                        .delete_orphan_update(orphan.blob_versioned_hash)
+                        .await?;
+                    continue;
+                }
+                Ok((ad_id, updates)) => ad_updates.extend(
+                    updates
+                        .into_iter()
+                        .map(|update| AdUpdateEvent { ad_id, update }),
+                ),
+            }
+            info!("Valid {}!", label);
+            Database(&mut **db_tx)
+                .delete_orphan_update(orphan.blob_versioned_hash)
+                .await?;
+
+            // the tx of an orphan blob isn't kept, while the source of an orphan in calldata is
+            // its tx
+            let (kind, tx_hash) = match blob {
+                Some(blob) => {
+                    Database(&mut **db_tx).add_blob(&blob).await?;
+                    (tables::PayloadSource::BLOB, None)
+                }
+                None => (
+                    tables::PayloadSource::CALLDATA,
+                    Some(orphan.blob_versioned_hash.to_vec()),
+                ),
+            };
+            Database(&mut **db_tx)
+                .add_payload_source(&tables::PayloadSource {
+                    source: orphan.blob_versioned_hash,
+                    kind: kind.to_string(),
+                    tx_hash,
+                })
+                .await?;
+        }
+    }
+}
+
+async fn process_payload_init(
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    blob_versioned_hash: tables::B256Sql,
+    payload: PayloadCreate,
+    params_fingerprint: Hash,
+) -> Result<()> {
+    check_params(&payload, params_fingerprint)?;
+    match Database(&mut **db_tx).get_ad(payload.id).await {
+        Err(err) => match err.root_cause().downcast_ref::<sqlx::Error>() {
+            Some(&sqlx::Error::RowNotFound) => {}
+            _ => return Err(err),
+        },
+        Ok(ad) => {
+            return Err(anyhow!(
+                "got init payload {:?} but AD already exists {:?}",
+                payload,
+                ad
+            ));
+        }
+    };
+
+    let ad = tables::Ad {
+        id: HashSql(payload.id),
+        custom_predicate_ref: CustomPredicateRefSql(payload.custom_predicate_ref),
+        vds_root: HashSql(payload.vds_root),
+        blob_versioned_hash,
+        params_fingerprint: payload.params_fingerprint.map(HashSql),
+    };
+    Database(&mut **db_tx).add_ad(&ad).await?;
+    let ad_update = tables::AdUpdate {
+        id: HashSql(payload.id),
+        num: 0,
+        state: RawValueSql(EMPTY_VALUE),
+        blob_versioned_hash,
+    };
+    Database(&mut **db_tx).add_ad_update(&ad_update).await?;
+    info!(
+        payload = "Create",
+        ad_id = payload.id.encode_hex::<String>()
+    );
+    Ok(())
+}
+
+/// Finds the state that an update payload which doesn't verify from the last update of its AD
+/// links to.  The epoch of the update (of the first one of a batch) names it; without one, the
+/// `LINK_LOOKBACK` updates before the last one and the creation of the AD (whose state is
+/// `EMPTY_VALUE`) are tried.  Returns `None` if the update does follow the last one, so its proof
+/// is just invalid.
+async fn diagnose_update_link<V: VerifyUpdate>(
+    verify_pool: &VerifyPool<V>,
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    ad: &tables::Ad,
+    ad_update_last: &tables::AdUpdate,
+    payload: &PayloadUpdate,
+) -> Result<Option<UpdateLink>> {
+    let last_num = ad_update_last.num;
+    let updates = Database(&mut **db_tx).get_ad_updates(payload.id).await?;
+    let candidates: Vec<&tables::AdUpdate> = match payload.first_epoch() {
+        Some(epoch) if epoch == last_num + 1 => return Ok(None),
+        Some(epoch) if epoch > last_num + 1 + ORPHAN_MAX_GAP => {
+            return Ok(Some(UpdateLink::FarAhead { epoch, last_num }));
+        }
+        Some(epoch) if epoch > last_num + 1 => {
+            return Ok(Some(UpdateLink::Gap { epoch, last_num }));
+        }
+        Some(epoch) => updates.iter().filter(|u| u.num == epoch - 1).collect(),
+        None => {
+            let mut candidates: Vec<_> = updates.iter().rev().skip(1).take(LINK_LOOKBACK).collect();
+            if last_num > LINK_LOOKBACK as i64 {
+                candidates.extend(updates.first());
+            }
+            candidates
+        }
+    };
+    for candidate in candidates {
+        if verify_pool.verify(ad, candidate, payload).await.is_ok() {
+            return Ok(Some(UpdateLink::Stale {
+                num: candidate.num,
+                last_num,
+            }));
+        }
+    }
+    Ok(match payload.epoch {
+        Some(_) => None,
+        None => Some(UpdateLink::Unknown { last_num }),
+    })
+}
+
+/// Ops history of an AD: its checkpoint in `ad_ops` with the ops of the updates after it inserted
+struct AdOpsHistory {
+    // num of the checkpoint
+    checkpoint_num: i64,
+    // num of the last update
+    num: i64,
+    ops: Dictionary,
+}
+
+/// Returns the ops history of the AD after its last update, none if the AD doesn't publish it.
+async fn load_ad_ops(
+    conn: &mut sqlx::SqliteConnection,
+    ad_id: Hash,
+) -> Result<Option<AdOpsHistory>> {
+    let Some(checkpoint) = Database(&mut *conn).get_ad_ops(ad_id).await? else {
+        return Ok(None);
+    };
+    let mut history = AdOpsHistory {
+        checkpoint_num: checkpoint.num,
+        num: checkpoint.num,
+        ops: checkpoint.ops.0,
+    };
+    for ad_op in Database(&mut *conn)
+        .get_ad_ops_after(ad_id, checkpoint.num)
+        .await?
+    {
+        ops::insert_op(&mut history.ops, ad_op.num, ad_op.op.0)?;
+        history.num = ad_op.num;
+    }
+    Ok(Some(history))
+}
+
+/// Checks the ops root of `payload`, the update (or batch of updates) that follows
+/// `ad_update_last`, and stores the ops of its updates if the AD publishes its ops history.  The
+/// ops history is tracked from the first update of the AD on, and then every update must publish
+/// the ops root with its op inserted at its num, a batch with the ops of all its updates.  The ops
+/// roots of the ADs that start publishing them later can't be checked, so they are ignored.
+async fn update_ad_ops(
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    ad_update_last: &tables::AdUpdate,
+    payload: &PayloadUpdate,
+) -> Result<()> {
+    let num = ad_update_last.num + 1;
+    let (checkpoint_num, mut ops) = match load_ad_ops(&mut **db_tx, payload.id).await? {
+        Some(history) if history.num == ad_update_last.num => {
+            (Some(history.checkpoint_num), history.ops)
+        }
+        Some(history) => {
+            return Err(anyhow!(
+                "ops history at num {} behind the last update {}",
+                history.num,
+                ad_update_last.num
+            ));
+        }
+        None if num == 1 && payload.ops_root.is_some() => (None, ops::empty_ops()),
+        None => {
+            if payload.ops_root.is_some() {
+                debug!(num, "ignoring the ops root of an AD without ops history");
+            }
+            return Ok(());
+        }
+    };
+    let ops_root = payload
+        .ops_root
+        .with_context(|| format!("update {} doesn't publish the ops root", num))?;
+    let mut ad_ops = Vec::new();
+    for (step, num) in payload.steps().zip(num..) {
+        ops::insert_op(&mut ops, num, step.op)?;
+        ad_ops.push(tables::AdOp {
+            id: HashSql(payload.id),
+            num,
+            op: RawValueSql(step.op),
+            ops_root: HashSql(ops.commitment()),
+        });
+    }
+    let last_num = num + payload.num_updates() - 1;
+    if ops.commitment() != ops_root {
+        return Err(anyhow!(
+            "ops root {} isn't the ops history with the op of update {} inserted",
+            ops_root.encode_hex::<String>(),
+            last_num
+        ));
+    }
+
+    // the whole ops history is only written every `OPS_CHECKPOINT_INTERVAL` updates
+    match checkpoint_num {
+        Some(checkpoint_num) if last_num - checkpoint_num < OPS_CHECKPOINT_INTERVAL => {
+            for ad_op in &ad_ops {
+                Database(&mut **db_tx).add_ad_op(ad_op).await?;
+            }
+        }
+        _ => {
+            Database(&mut **db_tx)
+                .set_ad_ops(&tables::AdOps {
+                    id: HashSql(payload.id),
+                    num: last_num,
+                    ops: DictSql(ops),
+                })
+                .await?;
+            Database(&mut **db_tx)
+                .delete_ad_ops_until(payload.id, last_num)
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+/// Applies an update payload published in `blob` (none for calldata), returning the updates as
+/// listed by `GET /ad/{id}/updates`, one per update of a batch.  An update that doesn't verify is
+/// diagnosed with `diagnose_update_link`, and kept in `orphan_update` if it may follow updates
+/// that were missed.
+async fn process_payload_update<V: VerifyUpdate>(
+    verify_pool: &VerifyPool<V>,
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    blob_versioned_hash: tables::B256Sql,
+    blob: Option<&tables::Blob>,
+    payload: PayloadUpdate,
+    preverified: Option<PreVerified>,
+) -> Result<Vec<AdUpdateEntry>> {
+    let ad = Database(&mut **db_tx).get_ad(payload.id).await?;
+    let ad_update_last = Database(&mut **db_tx)
+        .get_ad_update_last(payload.id)
+        .await?;
+
+    let verified = match preverified {
+        Some(preverified)
+            if preverified.num == ad_update_last.num
+                && preverified.state == ad_update_last.state.0 =>
+        {
+            join_verification(preverified.handle).await
+        }
+        // not verified ahead, or verified from another state because an earlier update of the
+        // AD in the slot was invalid
+        _ => verify_pool.verify(&ad, &ad_update_last, &payload).await,
+    };
+    if let Err(err) = verified {
+        let link = diagnose_update_link(verify_pool, db_tx, &ad, &ad_update_last, &payload).await?;
+        let ad_id = payload.id.encode_hex::<String>();
+        return Err(match link {
+            None => err,
+            Some(link @ (UpdateLink::Stale { .. } | UpdateLink::FarAhead { .. })) => {
+                warn!(ad_id, "{}", link);
+                err.context(link)
+            }
+            Some(link) => {
+                warn!(ad_id, "{}", link);
+                Database(&mut **db_tx)
+                    .add_orphan_update(&tables::OrphanUpdate {
+                        blob_versioned_hash,
+                        id: HashSql(payload.id),
+                        epoch: payload.first_epoch(),
+                        payload: Payload::Update(payload.clone()).to_bytes()?,
+                        slot: blob.map(|blob| blob.slot),
+                        block: blob.map(|blob| blob.block),
+                        blob_index: blob.map(|blob| blob.blob_index),
+                        timestamp: blob.map(|blob| blob.timestamp),
+                        sender: blob.and_then(|blob| blob.sender.clone()),
+                        checked_num: ad_update_last.num,
+                        diagnosis: link.to_string(),
+                        first_num: ad_update_last.num,
+                    })
+                    .await?;
+                OrphanUpdateError { link }.into()
+            }
+        });
+    }
+
+    update_ad_ops(db_tx, &ad_update_last, &payload).await?;
+    // the updates of a batch share the blob they were published in
+    let mut entries = Vec::new();
+    let mut old_state = ad_update_last.state.0;
+    for (step, num) in payload.steps().zip(ad_update_last.num + 1..) {
+        Database(&mut **db_tx)
+            .add_ad_update(&tables::AdUpdate {
+                id: HashSql(payload.id),
+                num,
+                state: RawValueSql(step.new_state),
+                blob_versioned_hash,
+            })
+            .await?;
+        info!(
+            payload = "Update",
+            ad_id = payload.id.encode_hex::<String>(),
+            num,
+            old_state = old_state.encode_hex::<String>(),
+            new_state = step.new_state.encode_hex::<String>(),
+            // matches the `op` of the span of the request that sent the update in the ad-server
+            op = step.op.encode_hex::<String>(),
+            epoch = ?payload.epoch.map(|_| num),
+            batch = payload.num_updates()
+        );
+        old_state = step.new_state;
+        entries.push(AdUpdateEntry {
+            num,
+            state: step.new_state,
+            blob_versioned_hash: B256::from(blob_versioned_hash),
+            slot: blob.map(|blob| blob.slot),
+            timestamp: blob.map(|blob| blob.timestamp),
+            sender: blob
+                .and_then(|blob| blob.sender.as_deref())
+                .and_then(|sender| Address::try_from(sender).ok()),
+            pruned_from: None,
+            checkpoint_link: None,
+        });
+    }
+    if !payload.op_dicts.is_empty() {
+        update_membership_intervals(db_tx, &ad_update_last, &payload, blob_versioned_hash).await?;
+    }
+    Ok(entries)
+}
+
+/// Opens and closes the membership intervals of the AD at the updates of `payload`, from the op
+/// dictionaries it discloses.  The op dictionaries that aren't the ones the updates commit to are
+/// ignored, without failing the updates, which are proven anyway.
+async fn update_membership_intervals(
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    ad_update_last: &tables::AdUpdate,
+    payload: &PayloadUpdate,
+    blob_versioned_hash: tables::B256Sql,
+) -> Result<()> {
+    let ad_id = payload.id.encode_hex::<String>();
+    if let Err(err) = payload.verify_op_dicts() {
+        warn!(
+            ad_id,
+            "ignoring the op dictionaries of the update: {:#}", err
+        );
+        return Ok(());
+    }
+    let mut open: BTreeSet<(String, String)> = Database(&mut **db_tx)
+        .get_open_memberships(payload.id)
+        .await?
+        .into_iter()
+        .collect();
+    for (op_dict, num) in payload.op_dicts.iter().zip(ad_update_last.num + 1..) {
+        let Some(change) = op_membership_change(op_dict) else {
+            continue;
+        };
+        let membership = (change.group, change.user);
+        let (group, user) = (&membership.0, &membership.1);
+        match change.added {
+            // a user added again after a del gets an interval of its own
+            true if !open.contains(&membership) => {
+                Database(&mut **db_tx)
+                    .open_membership_interval(payload.id, group, user, num, blob_versioned_hash)
+                    .await?;
+                open.insert(membership);
+            }
+            false if open.contains(&membership) => {
+                Database(&mut **db_tx)
+                    .close_membership_interval(payload.id, group, user, num, blob_versioned_hash)
+                    .await?;
+                open.remove(&membership);
+            }
+            // e.g. the del of a user added before the AD disclosed its ops
+            _ => debug!(ad_id, num, group, user, "membership interval unchanged"),
+        }
+    }
+    Ok(())
+}
+
+async fn process_payload_snapshot(
+    db_tx: &mut sqlx::SqliteTransaction<'_>,
+    blob_versioned_hash: tables::B256Sql,
+    payload: PayloadSnapshot,
+) -> Result<()> {
+    payload.verify()?;
+    // the snapshot is only trusted if its state is the latest one proven for the AD
+    let ad_update_last = Database(&mut **db_tx)
+        .get_ad_update_last(payload.id)
+        .await?;
+    if ad_update_last.state.0 != payload.state {
+        return Err(anyhow!(
+            "snapshot state {} != last state {} of AD {}",
+            payload.state.encode_hex::<String>(),
+            ad_update_last.state.0.encode_hex::<String>(),
+            payload.id.encode_hex::<String>()
+        ));
+    }
+
+    let memberships = dict_memberships(&payload.dict);
+    let ad_snapshot = tables::AdSnapshot {
+        id: HashSql(payload.id),
+        num: ad_update_last.num,
+        state: RawValueSql(payload.state),
+        dict: DictSql(payload.dict),
+        blob_versioned_hash,
+    };
+    Database(&mut **db_tx).add_ad_snapshot(&ad_snapshot).await?;
+
+    // the membership intervals follow the changes between consecutive snapshots, for the ADs
+    // whose update payloads only carry the commitment of their op.  They match the intervals of
+    // the ADs that disclose their ops, which are already up to date.
+    let open: BTreeSet<(String, String)> = Database(&mut **db_tx)
+        .get_open_memberships(payload.id)
+        .await?
+        .into_iter()
+        .collect();
+    for (group, user) in open.difference(&memberships) {
+        Database(&mut **db_tx)
+            .close_membership_interval(
+                payload.id,
+                group,
+                user,
+                ad_snapshot.num,
+                blob_versioned_hash,
+            )
+            .await?;
+    }
+    for (group, user) in memberships.difference(&open) {
+        Database(&mut **db_tx)
+            .open_membership_interval(
+                payload.id,
+                group,
+                user,
+                ad_snapshot.num,
+                blob_versioned_hash,
+            )
+            .await?;
+    }
+    info!(
+        payload = "Snapshot",
+        ad_id = payload.id.encode_hex::<String>(),
+        num = ad_snapshot.num,
+        state = payload.state.encode_hex::<String>()
+    );
+    Ok(())
+}
+
+fn log_init() {
+    tracing_subscriber::registry()
+        .with(fmt::layer())
+        .with(EnvFilter::from_default_env())
+        .init();
+}
+
+#[derive(Parser)]
+#[command(about = "Follows the beacon chain indexing AD blobs")]
+struct Cli {
+    /// Replay the beacon and RPC responses recorded in DIR instead of querying the nodes, or
+    /// record them with RECORD_FIXTURES=1 (overrides FIXTURES_PATH)
+    #[arg(long, value_name = "DIR", global = true)]
+    fixtures: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Follow the beacon chain head processing every slot (default)
+    Run,
+    /// Process the closed slot range FROM_SLOT..=TO_SLOT and exit
+    Backfill { from_slot: u64, to_slot: u64 },
+    /// Process a single slot and exit
+    ProcessSlot { slot: u64 },
+    /// Decode and print the AD payload of a blob file (hex or binary)
+    DecodeBlob { file: PathBuf },
+    /// Decode the AD update payload of a blob file and verify it against the AD in the DB
+    VerifyUpdate { file: PathBuf },
+    /// Replay the payloads of an AD (hex id) and check them against its updates in the DB
+    Verify { ad_id: String },
+}
+
+/// Reads the blob bytes from a file, either hex encoded (with optional 0x prefix) or raw.
+fn read_blob_file(path: &Path) -> Result<Vec<u8>> {
+    let data = std::fs::read(path).with_context(|| format!("{:?}", path))?;
+    if let Ok(text) = std::str::from_utf8(&data) {
+        let text = text.trim();
+        let text = text.strip_prefix("0x").unwrap_or(text);
+        if !text.is_empty() && text.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(hex::decode(text)?);
+        }
+    }
+    Ok(data)
+}
+
+/// Checkpoints the updates of the AD every `checkpoint_interval` nums, and prunes the updates
+/// before the last checkpoint that is at least `ad_update_retention` updates behind the last one.
+/// The retained updates start at that checkpoint, so they can still be replayed.  Returns the
+/// number of updates pruned.
+async fn compact_ad(
+    db: &SqlitePool,
+    ad_id: Hash,
+    checkpoint_interval: u64,
+    ad_update_retention: u64,
+) -> Result<u64> {
+    let interval = checkpoint_interval as i64;
+    let mut tx = db.begin().await?;
+    let last_num = Database(&mut *tx).get_ad_update_last(ad_id).await?.num;
+    let checkpoints = Database(&mut *tx).get_ad_checkpoints(ad_id).await?;
+    let mut checkpoint_nums: Vec<i64> = checkpoints.iter().map(|c| c.num).collect();
+    let from = checkpoint_nums
+        .last()
+        .map_or(interval, |num| num + interval);
+    // the links of the new checkpoints are chained before the updates they follow are pruned
+    let history = Database(&mut *tx).get_ad_update_history(ad_id).await?;
+    let links: HashMap<i64, Hash> = endpoints::attested_updates(&history)
+        .into_iter()
+        .map(|update| (update.num, update.link))
+        .collect();
+    for num in (from..=last_num).step_by(checkpoint_interval as usize) {
+        let Some(link) = links.get(&num) else {
+            continue;
+        };
+        if Database(&mut *tx)
+            .create_checkpoint(ad_id, num, *link)
+            .await?
+        {
+            checkpoint_nums.push(num);
+        }
+    }
+    let retained_from = last_num - ad_update_retention as i64;
+    let pruned = match checkpoint_nums
+        .iter()
+        .rev()
+        .find(|num| **num <= retained_from)
+    {
+        Some(num) => Database(&mut *tx).prune_ad_updates(ad_id, *num).await?,
+        None => 0,
+    };
+    tx.commit().await?;
+    Ok(pruned)
+}
+
+fn print_payload(payload: &Payload) {
+    match payload {
+        Payload::Create(payload) => {
+            println!("Create");
+            println!("  id: {}", payload.id.encode_hex::<String>());
+            println!(
+                "  custom_predicate_ref: {}/{}",
+                payload
+                    .custom_predicate_ref
+                    .batch
+                    .id()
+                    .encode_hex::<String>(),
+                payload.custom_predicate_ref.index
+            );
+            println!("  vds_root: {}", payload.vds_root.encode_hex::<String>());
+            if let Some(params_fingerprint) = payload.params_fingerprint {
+                println!(
+                    "  params_fingerprint: {}",
+                    params_fingerprint.encode_hex::<String>()
+                );
+            }
+        }
+        Payload::Update(payload) => {
+            let proof_type = match payload.proof {
+                PayloadProof::Plonky2(_) => ProofType::Plonky2,
+                PayloadProof::Groth16(_) => ProofType::Groth16,
+            };
+            println!("Update");
+            println!("  id: {}", payload.id.encode_hex::<String>());
+            println!("  proof: {:?}", proof_type);
+            println!("  new_state: {}", payload.new_state.encode_hex::<String>());
+            println!("  op: {}", payload.op.encode_hex::<String>());
+            if let Some(epoch) = payload.epoch {
+                println!("  epoch: {}", epoch);
+            }
+            for (step, epoch) in payload
+                .batch
+                .iter()
+                .zip(payload.first_epoch().unwrap_or(0)..)
+            {
+                println!(
+                    "  batched update {}: new_state {}, op {}",
+                    epoch,
+                    step.new_state.encode_hex::<String>(),
+                    step.op.encode_hex::<String>()
+                );
+            }
+        }
+        Payload::Snapshot(payload) => {
+            println!("Snapshot");
+            println!("  id: {}", payload.id.encode_hex::<String>());
+            println!("  state: {}", payload.state.encode_hex::<String>());
+            println!("  entries: {}", payload.dict.kvs().len());
+        }
+    }
+}
+
+fn decode_blob(node: &Node, path: &Path) -> Result<Payload> {
+    let blob_bytes = read_blob_file(path)?;
+    let bytes = bytes_from_simple_blob(&blob_bytes).context("Invalid byte encoding in blob")?;
+    Payload::from_bytes(&bytes, node.common_circuit_data())
+}
+
+async fn verify_update(node: &Node, path: &Path) -> Result<()> {
+    let payload = match decode_blob(node, path)? {
+        Payload::Update(payload) => payload,
+        payload => return Err(anyhow!("expected Update payload, got {:?}", payload)),
+    };
+    let ad = Database(&node.db).get_ad(payload.id).await?;
+    let ad_update_last = Database(&node.db).get_ad_update_last(payload.id).await?;
+    node.verify_pool
+        .verify(&ad, &ad_update_last, &payload)
+        .await?;
+    println!(
+        "Valid update of AD {} from num {} to {}",
+        payload.id.encode_hex::<String>(),
+        ad_update_last.num,
+        ad_update_last.num + payload.num_updates()
+    );
+    Ok(())
+}
+
+/// Checks that the checkpoint `update` of the AD, whose previous updates were pruned, has the state
+/// of the payload it was derived from.  Its proof isn't verified, which needs the state before it.
+/// A checkpoint in a batch only has the state of its own update checked.
+async fn check_checkpoint(node: &Node, ad: &tables::Ad, update: &tables::AdUpdate) -> Result<()> {
+    let bytes = node.fetch_payload_bytes(update.blob_versioned_hash).await?;
+    match Payload::from_bytes(&bytes, node.common_circuit_data())? {
+        Payload::Update(payload)
+            if payload.id == ad.id.0
+                && payload
+                    .steps()
+                    .zip(payload.first_epoch().unwrap_or(update.num)..)
+                    .any(|(step, num)| num == update.num && step.new_state == update.state.0) =>
+        {
+            Ok(())
+        }
+        payload => Err(anyhow!(
+            "checkpoint doesn't match its payload {:?}",
+            payload
+        )),
+    }
+}
+
+/// Checks that the first of `updates` of the AD follows from `last` by fetching, decoding and
+/// verifying again the payload it was derived from.  Returns the number of updates the payload
+/// proves, the first ones of `updates`, whose states are all checked.
+async fn replay_ad_update(
+    node: &Node,
+    ad: &tables::Ad,
+    last: &tables::AdUpdate,
+    updates: &[tables::AdUpdate],
+) -> Result<usize> {
+    let update = &updates[0];
+    if update.num != last.num + 1 {
+        return Err(anyhow!("expected num {}", last.num + 1));
+    }
+    let bytes = node.fetch_payload_bytes(update.blob_versioned_hash).await?;
+    let payload = match Payload::from_bytes(&bytes, node.common_circuit_data())? {
+        Payload::Update(payload) if payload.id == ad.id.0 => payload,
+        payload => return Err(anyhow!("expected Update payload, got {:?}", payload)),
+    };
+    node.verify_pool.verify(ad, last, &payload).await?;
+    let steps: Vec<_> = payload.steps().collect();
+    if updates.len() < steps.len() {
+        return Err(anyhow!(
+            "payload batches {} updates, only {} stored",
+            steps.len(),
+            updates.len()
+        ));
+    }
+    for (step, update) in steps.iter().zip(updates) {
+        if step.new_state != update.state.0 {
+            return Err(anyhow!(
+                "payload state {} != stored state {} at num {}",
+                step.new_state.encode_hex::<String>(),
+                update.state.0.encode_hex::<String>(),
+                update.num
+            ));
+        }
+    }
+    Ok(steps.len())
+}
+
+/// Replays the payloads of the AD from the blobs (or calldata) its rows were derived from and
+/// checks that they reproduce the stored chain of states.  Fails at the first divergence.
+async fn verify_ad(node: &Node, ad_id: Hash) -> Result<()> {
+    let ad_id_hex = ad_id.encode_hex::<String>();
+    let ad = Database(&node.db).get_ad(ad_id).await?;
+    let updates = Database(&node.db).get_ad_updates(ad_id).await?;
+
+    let Some((init, updates)) = updates.split_first() else {
+        return Err(anyhow!("AD {} has no updates", ad_id_hex));
+    };
+    let bytes = node.fetch_payload_bytes(ad.blob_versioned_hash).await?;
+    match Payload::from_bytes(&bytes, node.common_circuit_data())? {
+        Payload::Create(payload)
+            if payload.id == ad_id
+                && payload.custom_predicate_ref == ad.custom_predicate_ref.0
+                && payload.vds_root == ad.vds_root.0 => {}
+        payload => {
+            return Err(anyhow!(
+                "AD {} diverges at creation: payload {:?}",
+                ad_id_hex,
+                payload
+            ));
+        }
+    }
+    if init.num != 0
+        || init.state.0 != EMPTY_VALUE
+        || init.blob_versioned_hash != ad.blob_versioned_hash
+    {
+        return Err(anyhow!(
+            "AD {} diverges at creation: update {:?}",
+            ad_id_hex,
+            init
+        ));
+    }
+
+    let mut last = init;
+    let mut updates = updates;
+    // the updates were pruned up to a checkpoint, which the replay starts from
+    if let Some((checkpoint, rest)) = updates.split_first().filter(|(u, _)| u.num > 1) {
+        if !Database(&node.db)
+            .get_ad_checkpoints(ad_id)
+            .await?
+            .contains(checkpoint)
+        {
+            return Err(anyhow!(
+                "AD {} misses the updates before num {}, which isn't a checkpoint",
+                ad_id_hex,
+                checkpoint.num
+            ));
+        }
+        check_checkpoint(node, &ad, checkpoint)
+            .await
+            .with_context(|| format!("AD {} diverges at num {}", ad_id_hex, checkpoint.num))?;
+        last = checkpoint;
+        updates = rest;
+    }
+    // a checkpoint in a batch is followed by the rest of the batch, which is replayed with it
+    while let Some(update) = updates
+        .first()
+        .filter(|update| update.blob_versioned_hash == last.blob_versioned_hash && last.num > 0)
+    {
+        last = update;
+        updates = &updates[1..];
+    }
+    while let Some(update) = updates.first() {
+        let replayed = replay_ad_update(node, &ad, last, updates)
+            .await
+            .with_context(|| format!("AD {} diverges at num {}", ad_id_hex, update.num))?;
+        last = &updates[replayed - 1];
+        updates = &updates[replayed..];
+    }
+    println!(
+        "AD {} matches its payloads up to num {}",
+        ad_id_hex, last.num
+    );
+    Ok(())
+}
+
+async fn run(node: Node) -> Result<()> {
+    let spec = node.beacon_cli.get_spec().await?;
+    info!(?spec, "Beacon spec");
+    let mut head = node
+        .beacon_cli
+        .get_block_header(BlockId::Head)
+        .await?
+        .expect("head is not None");
+    info!(?head, "Beacon head");
+    // a slot is processed once it's `confirmation_depth` slots behind the head
+    let depth = node.cfg.confirmation_depth;
+
+    let http_addr = SocketAddr::from((node.cfg.http_bind, node.cfg.http_port));
+    {
         let node = node.clone();
         std::thread::spawn(move || -> Result<_, std::io::Error> {
             Runtime::new().map(|rt| {
                 rt.block_on(async {
                     let routes = endpoints::routes(Arc::new(node));
-                    warp::serve(routes).run(([0, 0, 0, 0], 8001)).await
+                    warp::serve(routes).run(http_addr).await
                 })
             })
         });
     }
-    info!("Started HTTP server");
+    info!("Started HTTP server at http://{}", http_addr);
+    if node.cfg.checkpoint_interval > 0 {
+        let node = node.clone();
+        tokio::spawn(async move { node.compact_loop().await });
+    }
 
-    let initial_slot = Database(&node.db)
-        .get_visited_slot_last()
-        .await
-        .map(|x| x + 1)
-        .unwrap_or(node.cfg.ad_genesis_slot)
-        .max(node.cfg.ad_genesis_slot);
+    let initial_slot = match Database(&node.db).get_visited_slot_last().await? {
+        Some(slot) => slot.checked_add(1).context("visited slot overflow")?,
+        // no slot visited yet
+        None => node.cfg.ad_genesis_slot,
+    }
+    .max(node.cfg.ad_genesis_slot);
 
     let mut slot = initial_slot;
     loop {
         debug!("checking slot {}", slot);
-        let some_beacon_block_header = if slot <= head.slot {
-            node.beacon_cli
-                .get_block_header(BlockId::Slot(slot))
-                .await?
-        } else {
+        if head.slot.saturating_sub(depth) < slot {
             // TODO: Be more fancy and replace this with a stream from an event subscription to
             // Beacon Headers
             tokio::time::sleep(Duration::from_secs(5)).await;
             loop {
-                let head = node
+                head = node
                     .beacon_cli
                     .get_block_header(BlockId::Head)
                     .await?
                     .expect("head is not None");
-                if head.slot > slot {
-                    debug!(
-                        "head is {}, slot {} was skipped, retrieving...",
-                        head.slot, slot
-                    );
-                    break node
-                        .beacon_cli
-                        .get_block_header(BlockId::Slot(slot))
-                        .await?;
-                } else if head.slot == slot {
-                    break Some(head);
+                if head.slot.saturating_sub(depth) >= slot {
+                    break;
                 }
                 tokio::time::sleep(Duration::from_secs(1)).await;
             }
+        }
+        let some_beacon_block_header = if head.slot == slot {
+            Some(head.clone())
+        } else {
+            // an empty slot has no block header
+            node.beacon_cli
+                .get_block_header(BlockId::Slot(slot))
+                .await?
         };
-        let beacon_block_header = match some_beacon_block_header {
-            Some(block) => block,
-            None => {
-                debug!("slot {} has empty block", slot);
-                Database(&node.db).add_visited_slot(slot as i64).await?;
-                slot += 1;
-                continue;
+        node.process_slot(slot, some_beacon_block_header, true)
+            .await?;
+
+        slot = slot.checked_add(1).context("slot overflow")?;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    log_init();
+    load_dotenv()?;
+    let mut cfg = Config::from_env()?;
+    if let Some(fixtures_path) = cli.fixtures {
+        cfg.fixtures_path = Some(fixtures_path);
+    }
+    info!(?cfg, "Loaded config");
+
+    if cfg.proof_type == ProofType::Groth16 {
+        // initialize groth16 memory with the vk
+        common::groth::load_vk()?;
+    }
+
+    let node = Node::new(cfg).await?;
+
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run(node).await,
+        Command::Backfill { from_slot, to_slot } => node.backfill(from_slot, to_slot).await,
+        Command::ProcessSlot { slot } => {
+            let beacon_block_header = node
+                .beacon_cli
+                .get_block_header(BlockId::Slot(slot))
+                .await?;
+            node.process_slot(slot, beacon_block_header, false).await
+        }
+        Command::DecodeBlob { file } => {
+            print_payload(&decode_blob(&node, &file)?);
+            Ok(())
+        }
+        Command::VerifyUpdate { file } => verify_update(&node, &file).await,
+        Command::Verify { ad_id } => verify_ad(&node, Hash::from_hex(&ad_id)?).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::remove_dir_all,
+        sync::atomic::{AtomicU64, Ordering},
+    };
+
+    use app::{Group, Op, UserId};
+    use common::{
+        attestation::{Attestation, SigningKey, verify_attestation},
+        payload::UpdateStep,
+    };
+    use plonky2::field::types::Field;
+    use pod2::middleware::{CustomPredicateBatch, CustomPredicateRef, F, hash_str};
+    use synchronizer::clients::{
+        beacon::types::{Block, Spec},
+        common::ClientResult,
+    };
+
+    use super::*;
+    use crate::db::{MembershipInterval, UpdateRef};
+
+    /// Accepts the updates whose proof is the encoding of the state they transition from
+    struct StateVerifier;
+
+    impl VerifyUpdate for StateVerifier {
+        fn params_fingerprint(&self) -> Hash {
+            hash_str("params")
+        }
+
+        fn verify_update(
+            &self,
+            _ad: &tables::Ad,
+            ad_update_last: &tables::AdUpdate,
+            payload: &PayloadUpdate,
+        ) -> Result<()> {
+            match &payload.proof {
+                PayloadProof::Groth16(proof) if *proof == ad_update_last.state.to_bytes() => Ok(()),
+                _ => Err(anyhow!("invalid proof")),
             }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_apply_slot_payloads() -> Result<()> {
+        let db = SqlitePool::connect(":memory:").await?;
+        init_db(&db).await?;
+        let verify_pool = VerifyPool::new(StateVerifier, 2);
+
+        let state = |n: i64| RawValue::from(n);
+        let (ad_a, ad_b) = (hash_str("a"), hash_str("b"));
+        let (ad_c, ad_d) = (hash_str("c"), hash_str("d"));
+        let create_with_params = |id, params_fingerprint| {
+            Payload::Create(PayloadCreate {
+                id,
+                custom_predicate_ref: CustomPredicateRef {
+                    batch: CustomPredicateBatch::new_opaque(
+                        "unknown".to_string(),
+                        hash_str("batch"),
+                    ),
+                    index: 0,
+                },
+                vds_root: hash_str("vds_root"),
+                params_fingerprint,
+            })
+        };
+        // created before the fingerprint of the params was published
+        let create = |id| create_with_params(id, None);
+        let update = |id, from: RawValue, to: RawValue| {
+            Payload::Update(PayloadUpdate {
+                id,
+                proof: PayloadProof::Groth16(RawValueSql(from).to_bytes()),
+                new_state: to,
+                op: EMPTY_VALUE,
+                epoch: None,
+                ops_root: None,
+                batch: Vec::new(),
+                op_dicts: Vec::new(),
+            })
+        };
+        let slot_payload_at = |slot: i64, index: u8, payload| SlotPayload {
+            label: format!("payload {}", index),
+            source: [index; 32],
+            tx_hash: [index; 32],
+            blob: Some(tables::Blob {
+                versioned_hash: [index; 32],
+                slot,
+                block: slot,
+                blob_index: index as i64,
+                timestamp: 0,
+                sender: None,
+            }),
+            payload,
         };
+        let slot_payload = |index, payload| slot_payload_at(1, index, payload);
+        let payloads = vec![
+            slot_payload(0, Ok(create(ad_a))),
+            slot_payload(1, Ok(create(ad_b))),
+            slot_payload(2, Ok(update(ad_a, EMPTY_VALUE, state(1)))),
+            slot_payload(3, Ok(update(ad_b, EMPTY_VALUE, state(11)))),
+            // proves a transition from another state
+            slot_payload(4, Ok(update(ad_a, state(99), state(2)))),
+            // verified ahead from state 2, and again from state 1 once 4 is rejected
+            slot_payload(5, Ok(update(ad_a, state(1), state(3)))),
+            slot_payload(6, Err(anyhow!("invalid payload encoding"))),
+            slot_payload(7, Ok(update(ad_b, state(11), state(12)))),
+            // proven with other params, along with an update of the AD
+            slot_payload(
+                8,
+                Ok(create_with_params(ad_c, Some(hash_str("other params")))),
+            ),
+            slot_payload(9, Ok(update(ad_c, EMPTY_VALUE, state(21)))),
+            // proven with the params of the synchronizer
+            slot_payload(10, Ok(create_with_params(ad_d, Some(hash_str("params"))))),
+        ];
 
-        let mut tx = node.db.begin().await?;
-        node.process_beacon_block_header(&mut tx, &beacon_block_header)
+        let mut db_tx = db.begin().await?;
+        let ad_updates = apply_slot_payloads(&verify_pool, &mut db_tx, payloads).await?;
+        db_tx.commit().await?;
+        // the applied updates, to push to the subscribers
+        assert_eq!(
+            ad_updates
+                .iter()
+                .map(|event| (event.ad_id, event.update.num, event.update.slot))
+                .collect::<Vec<_>>(),
+            vec![
+                (ad_a, 1, Some(1)),
+                (ad_b, 1, Some(1)),
+                (ad_a, 2, Some(1)),
+                (ad_b, 2, Some(1))
+            ]
+        );
+
+        // (num, state, payload index) of the updates of the AD
+        let chain = async |id: Hash| -> Result<Vec<(i64, RawValue, u8)>> {
+            Ok(Database(&db)
+                .get_ad_updates(id)
+                .await?
+                .into_iter()
+                .map(|u| (u.num, u.state.0, u.blob_versioned_hash[0]))
+                .collect())
+        };
+        assert_eq!(
+            chain(ad_a).await?,
+            vec![(0, EMPTY_VALUE, 0), (1, state(1), 2), (2, state(3), 5)]
+        );
+        assert_eq!(
+            chain(ad_b).await?,
+            vec![(0, EMPTY_VALUE, 1), (1, state(11), 3), (2, state(12), 7)]
+        );
+        // only the blobs of the valid payloads are stored
+        // the AD proven with other params isn't created, and the one with the same params is
+        assert!(Database(&db).get_ad(ad_c).await.is_err());
+        assert_eq!(
+            Database(&db).get_ad(ad_d).await?.params_fingerprint,
+            Some(HashSql(hash_str("params")))
+        );
+        for index in 0..11 {
+            let stored = Database(&db).get_blob([index; 32]).await?.is_some();
+            assert_eq!(stored, ![4, 6, 8, 9].contains(&index), "blob {}", index);
+        }
+
+        // a blob included again in a later slot is only applied once, even if its update would
+        // verify again, as this one which keeps the state
+        for slot in [2, 3] {
+            let payloads = vec![slot_payload_at(
+                slot,
+                11,
+                Ok(update(ad_d, EMPTY_VALUE, EMPTY_VALUE)),
+            )];
+            let mut db_tx = db.begin().await?;
+            let ad_updates = apply_slot_payloads(&verify_pool, &mut db_tx, payloads).await?;
+            db_tx.commit().await?;
+            assert_eq!(ad_updates.len(), usize::from(slot == 2), "slot {}", slot);
+        }
+        assert_eq!(Database(&db).get_ad_updates(ad_d).await?.len(), 2);
+        assert_eq!(
+            Database(&db)
+                .get_blob([11; 32])
+                .await?
+                .map(|blob| blob.slot),
+            Some(2)
+        );
+
+        // and so is a calldata tx, which has no blob row
+        for slot in [4, 5] {
+            let payloads = vec![SlotPayload {
+                label: format!("calldata at slot {}", slot),
+                source: [12; 32],
+                tx_hash: [12; 32],
+                blob: None,
+                payload: Ok(update(ad_d, EMPTY_VALUE, EMPTY_VALUE)),
+            }];
+            let mut db_tx = db.begin().await?;
+            let ad_updates = apply_slot_payloads(&verify_pool, &mut db_tx, payloads).await?;
+            db_tx.commit().await?;
+            assert_eq!(ad_updates.len(), usize::from(slot == 4), "slot {}", slot);
+        }
+        assert_eq!(Database(&db).get_ad_updates(ad_d).await?.len(), 3);
+        assert_eq!(
+            Database(&db).get_payload_source([12; 32]).await?,
+            Some(tables::PayloadSource {
+                source: [12; 32],
+                kind: tables::PayloadSource::CALLDATA.to_string(),
+                tx_hash: Some([12; 32].to_vec()),
+            })
+        );
+        assert_eq!(
+            Database(&db)
+                .get_payload_source([11; 32])
+                .await?
+                .map(|source| source.kind),
+            Some(tables::PayloadSource::BLOB.to_string())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_orphan_updates() -> Result<()> {
+        let db = SqlitePool::connect(":memory:").await?;
+        init_db(&db).await?;
+        let verify_pool = VerifyPool::new(StateVerifier, 2);
+
+        let state = |n: i64| RawValue::from(n);
+        let (ad_a, ad_b) = (hash_str("a"), hash_str("b"));
+        let create = |id| {
+            Payload::Create(PayloadCreate {
+                id,
+                custom_predicate_ref: CustomPredicateRef {
+                    batch: CustomPredicateBatch::new_opaque(
+                        "unknown".to_string(),
+                        hash_str("batch"),
+                    ),
+                    index: 0,
+                },
+                vds_root: hash_str("vds_root"),
+                params_fingerprint: None,
+            })
+        };
+        let update = |id, from: RawValue, to: RawValue, epoch| {
+            Payload::Update(PayloadUpdate {
+                id,
+                proof: PayloadProof::Groth16(RawValueSql(from).to_bytes()),
+                new_state: to,
+                op: EMPTY_VALUE,
+                epoch,
+                ops_root: None,
+                batch: Vec::new(),
+                op_dicts: Vec::new(),
+            })
+        };
+        let slot_payload = |index: u8, payload: Payload| SlotPayload {
+            label: format!("payload {}", index),
+            source: [index; 32],
+            tx_hash: [index; 32],
+            blob: Some(tables::Blob {
+                versioned_hash: [index; 32],
+                slot: index as i64,
+                block: index as i64,
+                blob_index: 0,
+                timestamp: 0,
+                sender: None,
+            }),
+            payload: Ok(payload),
+        };
+        // the orphans are decoded from the payloads of the test, since decoding the proofs needs
+        // the circuit data
+        let mut payloads = vec![
+            create(ad_a),
+            create(ad_b),
+            update(ad_a, EMPTY_VALUE, state(1), Some(1)),
+            update(ad_b, EMPTY_VALUE, state(11), None),
+            // follows the update of ad_a with epoch 2, which is missed
+            update(ad_a, state(2), state(3), Some(3)),
+            // replayed
+            update(ad_a, EMPTY_VALUE, state(1), Some(1)),
+            update(ad_b, EMPTY_VALUE, state(11), None),
+            // follows the missed update of ad_b to state 12
+            update(ad_b, state(12), state(13), None),
+            // missed updates
+            update(ad_a, state(1), state(2), Some(2)),
+            update(ad_b, state(11), state(12), None),
+            // too many updates of ad_a after its last one are missed
+            update(ad_a, state(98), state(99), Some(4 + ORPHAN_MAX_GAP + 1)),
+            // follows an update of ad_b that is never found
+            update(ad_b, state(98), state(99), None),
+        ];
+        // later updates of ad_b, from num 4 on
+        payloads.extend(
+            (13..14 + LINK_LOOKBACK as i64).map(|n| update(ad_b, state(n), state(n + 1), None)),
+        );
+        let encoded: HashMap<Vec<u8>, Payload> = payloads
+            .iter()
+            .map(|payload| Ok((payload.to_bytes()?, payload.clone())))
+            .collect::<Result<_>>()?;
+        let decode = |bytes: &[u8]| {
+            encoded
+                .get(bytes)
+                .cloned()
+                .ok_or_else(|| anyhow!("unknown payload"))
+        };
+        let orphans = async || -> Result<Vec<(u8, String)>> {
+            let orphans: Vec<tables::OrphanUpdate> =
+                sqlx::query_as("SELECT * FROM orphan_update ORDER BY blob_versioned_hash")
+                    .fetch_all(&db)
+                    .await?;
+            Ok(orphans
+                .into_iter()
+                .map(|o| (o.blob_versioned_hash[0], o.diagnosis))
+                .collect())
+        };
+
+        let mut db_tx = db.begin().await?;
+        let slot_payloads = (0..8).map(|i| slot_payload(i, payloads[i as usize].clone()));
+        apply_slot_payloads(&verify_pool, &mut db_tx, slot_payloads.collect()).await?;
+        apply_orphan_updates(&verify_pool, &mut db_tx, decode).await?;
+        db_tx.commit().await?;
+        // the replays are rejected, and the updates that follow missed ones are kept
+        assert_eq!(
+            orphans().await?,
+            vec![
+                (
+                    4,
+                    "update epoch 3 is ahead of the last num 1: missed updates 2..=2".to_string()
+                ),
+                (
+                    7,
+                    "update links to no state in nums 0..=1 nor the empty state: missed update or invalid proof"
+                        .to_string()
+                ),
+            ]
+        );
+        for index in 4..8 {
+            assert_eq!(Database(&db).get_blob([index; 32]).await?, None);
+        }
+
+        // the missed updates are found, e.g. in a failed slot processed again
+        let mut db_tx = db.begin().await?;
+        let slot_payloads = (8..10).map(|i| slot_payload(i, payloads[i as usize].clone()));
+        apply_slot_payloads(&verify_pool, &mut db_tx, slot_payloads.collect()).await?;
+        apply_orphan_updates(&verify_pool, &mut db_tx, decode).await?;
+        db_tx.commit().await?;
+        assert_eq!(orphans().await?, vec![]);
+        let chain = async |id: Hash| -> Result<Vec<(i64, RawValue, u8)>> {
+            Ok(Database(&db)
+                .get_ad_updates(id)
+                .await?
+                .into_iter()
+                .map(|u| (u.num, u.state.0, u.blob_versioned_hash[0]))
+                .collect())
+        };
+        assert_eq!(
+            chain(ad_a).await?,
+            vec![
+                (0, EMPTY_VALUE, 0),
+                (1, state(1), 2),
+                (2, state(2), 8),
+                (3, state(3), 4)
+            ]
+        );
+        assert_eq!(
+            chain(ad_b).await?,
+            vec![
+                (0, EMPTY_VALUE, 1),
+                (1, state(11), 3),
+                (2, state(12), 9),
+                (3, state(13), 7)
+            ]
+        );
+        // with the blobs of the orphans applied
+        assert_eq!(
+            Database(&db).get_blob([7; 32]).await?.map(|b| b.slot),
+            Some(7)
+        );
+
+        // the update too far ahead isn't kept, unlike the one without an epoch
+        let mut db_tx = db.begin().await?;
+        let slot_payloads = (10..12).map(|i| slot_payload(i, payloads[i as usize].clone()));
+        apply_slot_payloads(&verify_pool, &mut db_tx, slot_payloads.collect()).await?;
+        apply_orphan_updates(&verify_pool, &mut db_tx, decode).await?;
+        db_tx.commit().await?;
+        assert_eq!(
+            orphans().await?,
+            vec![(
+                11,
+                "update links to no state in nums 0..=3 nor the empty state: missed update or invalid proof"
+                    .to_string()
+            )]
+        );
+        // which is tried again after each update of its AD, until it's `LINK_LOOKBACK` updates
+        // behind
+        for index in 12..payloads.len() as u8 {
+            let mut db_tx = db.begin().await?;
+            let slot_payloads = vec![slot_payload(index, payloads[index as usize].clone())];
+            apply_slot_payloads(&verify_pool, &mut db_tx, slot_payloads).await?;
+            apply_orphan_updates(&verify_pool, &mut db_tx, decode).await?;
+            db_tx.commit().await?;
+            let updates = usize::from(index - 11);
+            assert_eq!(
+                orphans().await?.len(),
+                usize::from(updates <= LINK_LOOKBACK),
+                "after {} updates",
+                updates
+            );
+        }
+        assert_eq!(chain(ad_b).await?.len(), 4 + LINK_LOOKBACK + 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ad_ops() -> Result<()> {
+        let db = SqlitePool::connect(":memory:").await?;
+        init_db(&db).await?;
+        let verify_pool = VerifyPool::new(StateVerifier, 2);
+
+        let (ad_a, ad_b) = (hash_str("a"), hash_str("b"));
+        let create = |id| {
+            Payload::Create(PayloadCreate {
+                id,
+                custom_predicate_ref: CustomPredicateRef {
+                    batch: CustomPredicateBatch::new_opaque(
+                        "unknown".to_string(),
+                        hash_str("batch"),
+                    ),
+                    index: 0,
+                },
+                vds_root: hash_str("vds_root"),
+                params_fingerprint: None,
+            })
+        };
+        let state = |num: i64| match num {
+            0 => EMPTY_VALUE,
+            _ => RawValue::from(num),
+        };
+        let op = |num: i64| RawValue::from(100 + num);
+        // ops history of the publisher of the updates
+        let ops_at = |num: i64| -> Result<Dictionary> {
+            let mut ops = ops::empty_ops();
+            for num in 1..=num {
+                ops::insert_op(&mut ops, num, op(num))?;
+            }
+            Ok(ops)
+        };
+        let update = |id, num: i64, ops_root: Option<Hash>| {
+            Payload::Update(PayloadUpdate {
+                id,
+                proof: PayloadProof::Groth16(RawValueSql(state(num - 1)).to_bytes()),
+                new_state: state(num),
+                op: op(num),
+                epoch: Some(num),
+                ops_root,
+                batch: Vec::new(),
+                op_dicts: Vec::new(),
+            })
+        };
+        // the updates `from..=to` proven at once
+        let batch = |id, from: i64, to: i64| -> Result<Payload> {
+            Ok(Payload::Update(PayloadUpdate {
+                id,
+                proof: PayloadProof::Groth16(RawValueSql(state(from - 1)).to_bytes()),
+                new_state: state(to),
+                op: op(to),
+                epoch: Some(to),
+                ops_root: Some(ops_at(to)?.commitment()),
+                batch: (from..to)
+                    .map(|num| UpdateStep {
+                        new_state: state(num),
+                        op: op(num),
+                    })
+                    .collect(),
+                op_dicts: Vec::new(),
+            }))
+        };
+        let root = |num| -> Result<Option<Hash>> { Ok(Some(ops_at(num)?.commitment())) };
+        let payloads = vec![
+            create(ad_a),
+            create(ad_b),
+            update(ad_a, 1, root(1)?),
+            // the ops root has the op inserted at another num
+            update(ad_a, 2, {
+                let mut ops = ops_at(1)?;
+                ops::insert_op(&mut ops, 3, op(2))?;
+                Some(ops.commitment())
+            }),
+            update(ad_a, 2, root(2)?),
+            // an AD that publishes its ops must keep publishing them
+            update(ad_a, 3, None),
+            batch(ad_a, 3, 5)?,
+            // the ops history isn't tracked from the first update
+            update(ad_b, 1, None),
+            update(ad_b, 2, root(2)?),
+        ];
+        let slot_payloads = payloads
+            .into_iter()
+            .zip(0..)
+            .map(|(payload, index): (Payload, u8)| SlotPayload {
+                label: format!("payload {}", index),
+                source: [index; 32],
+                tx_hash: [index; 32],
+                blob: None,
+                payload: Ok(payload),
+            })
+            .collect();
+        let mut db_tx = db.begin().await?;
+        let ad_updates = apply_slot_payloads(&verify_pool, &mut db_tx, slot_payloads).await?;
+        db_tx.commit().await?;
+        assert_eq!(
+            ad_updates
+                .iter()
+                .map(|event| (event.ad_id, event.update.num))
+                .collect::<Vec<_>>(),
+            vec![
+                (ad_a, 1),
+                (ad_a, 2),
+                (ad_a, 3),
+                (ad_a, 4),
+                (ad_a, 5),
+                (ad_b, 1),
+                (ad_b, 2)
+            ]
+        );
+        // the updates of the batch are stored with their states and the blob of the batch
+        let updates = Database(&db).get_ad_updates(ad_a).await?;
+        assert_eq!(
+            updates
+                .iter()
+                .map(|u| (u.num, u.state.0, u.blob_versioned_hash[0]))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, EMPTY_VALUE, 0),
+                (1, state(1), 2),
+                (2, state(2), 4),
+                (3, state(3), 6),
+                (4, state(4), 6),
+                (5, state(5), 6)
+            ]
+        );
+
+        // the ops history is written at the first update, and only the ops of the next ones
+        let ad_ops = Database(&db).get_ad_ops(ad_a).await?.context("ops of a")?;
+        assert_eq!(ad_ops.num, 1);
+        let ad_op = Database(&db)
+            .get_ad_op_last(ad_a)
+            .await?
+            .context("op of a")?;
+        assert_eq!((ad_op.num, ad_op.ops_root.0), (5, ops_at(5)?.commitment()));
+
+        // the op of update 1 is proven against the root derived by the synchronizer
+        let mut conn = db.acquire().await?;
+        let history = load_ad_ops(&mut conn, ad_a).await?.context("ops of a")?;
+        assert_eq!(history.num, 5);
+        let ops_root = history.ops.commitment();
+        let (_, proof) = ops_at(5)?.prove(&ops::op_key(1))?;
+        ops::verify_op_proof(ops_root, 1, op(1), &proof)?;
+        assert!(ops::verify_op_proof(ops_root, 1, op(2), &proof).is_err());
+        assert!(load_ad_ops(&mut conn, ad_b).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_membership_intervals() -> Result<()> {
+        let db = SqlitePool::connect(":memory:").await?;
+        init_db(&db).await?;
+        let verify_pool = VerifyPool::new(StateVerifier, 2);
+
+        let params = Params::default();
+        let ad_id = hash_str("a");
+        let (alice, bob) = (UserId::new("alice")?, UserId::new("bob")?);
+        let add = |user: &UserId| Op::Add {
+            group: Group::RED,
+            user: user.clone(),
+        };
+        let del = |user: &UserId| Op::Del {
+            group: Group::RED,
+            user: user.clone(),
+        };
+        let state = |num: i64| match num {
+            0 => EMPTY_VALUE,
+            _ => RawValue::from(num),
+        };
+        let op_dict = |op: &Op, num: i64| op.clone().into_dict(&params, num);
+        let op_raw = |op: &Op, num: i64| RawValue::from(op_dict(op, num).commitment());
+        // the updates `from..` of `ops` proven at once, disclosing `op_dicts`
+        let update = |from: i64, ops: &[Op], op_dicts: Vec<Dictionary>| {
+            let (last, batch) = ops.split_last().expect("ops");
+            let to = from + batch.len() as i64;
+            Payload::Update(PayloadUpdate {
+                id: ad_id,
+                proof: PayloadProof::Groth16(RawValueSql(state(from - 1)).to_bytes()),
+                new_state: state(to),
+                op: op_raw(last, to),
+                epoch: Some(to),
+                ops_root: None,
+                batch: batch
+                    .iter()
+                    .zip(from..)
+                    .map(|(op, num)| UpdateStep {
+                        new_state: state(num),
+                        op: op_raw(op, num),
+                    })
+                    .collect(),
+                op_dicts,
+            })
+        };
+        let disclosed = |from: i64, ops: &[Op]| {
+            let op_dicts = ops
+                .iter()
+                .zip(from..)
+                .map(|(op, num)| op_dict(op, num))
+                .collect();
+            update(from, ops, op_dicts)
+        };
+        let payloads = vec![
+            Payload::Create(PayloadCreate {
+                id: ad_id,
+                custom_predicate_ref: CustomPredicateRef {
+                    batch: CustomPredicateBatch::new_opaque(
+                        "unknown".to_string(),
+                        hash_str("batch"),
+                    ),
+                    index: 0,
+                },
+                vds_root: hash_str("vds_root"),
+                params_fingerprint: None,
+            }),
+            disclosed(1, &[add(&alice)]),
+            disclosed(2, &[add(&bob), del(&alice)]),
+            // a user added again gets an interval of its own
+            disclosed(4, &[add(&alice)]),
+            // the disclosed op isn't the one the update commits to, so it's ignored while the
+            // update is still applied
+            update(5, &[del(&bob)], vec![op_dict(&del(&alice), 5)]),
+        ];
+        let slot_payloads = payloads
+            .into_iter()
+            .zip(0..)
+            .map(|(payload, index): (Payload, u8)| SlotPayload {
+                label: format!("payload {}", index),
+                source: [index; 32],
+                tx_hash: [index; 32],
+                blob: None,
+                payload: Ok(payload),
+            })
+            .collect();
+        let mut db_tx = db.begin().await?;
+        let ad_updates = apply_slot_payloads(&verify_pool, &mut db_tx, slot_payloads).await?;
+        db_tx.commit().await?;
+        assert_eq!(ad_updates.len(), 5);
+
+        // the intervals are bounded by the updates that added and removed the user, with the
+        // blobs of their payloads
+        let update_ref = |num: i64, index: u8| UpdateRef {
+            num,
+            blob_versioned_hash: B256::from([index; 32]),
+            slot: None,
+            timestamp: None,
+        };
+        let red = |from, to| MembershipInterval {
+            group: "red".to_string(),
+            from,
+            to,
+        };
+        assert_eq!(
+            Database(&db).get_membership_history(ad_id, "alice").await?,
+            vec![
+                red(update_ref(1, 1), Some(update_ref(3, 2))),
+                red(update_ref(4, 3), None),
+            ]
+        );
+        assert_eq!(
+            Database(&db).get_membership_history(ad_id, "bob").await?,
+            vec![red(update_ref(2, 2), None)]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compact_ad() -> Result<()> {
+        let db = SqlitePool::connect(":memory:").await?;
+        init_db(&db).await?;
+        let ad_id = hash_str("a");
+        for num in 0..=40 {
+            Database(&db)
+                .add_ad_update(&tables::AdUpdate {
+                    id: HashSql(ad_id),
+                    num,
+                    state: RawValueSql(RawValue::from(num)),
+                    blob_versioned_hash: [num as u8; 32],
+                })
+                .await?;
+        }
+        let nums = |updates: Vec<tables::AdUpdate>| -> Vec<i64> {
+            updates.into_iter().map(|u| u.num).collect()
+        };
+
+        let links = |history: &[AdUpdateEntry]| -> HashMap<i64, Hash> {
+            endpoints::attested_updates(history)
+                .into_iter()
+                .map(|update| (update.num, update.link))
+                .collect()
+        };
+        let links_before = links(&Database(&db).get_ad_update_history(ad_id).await?);
+
+        // the last checkpoint 17 updates behind 40 is 20, the updates before it are pruned
+        assert_eq!(compact_ad(&db, ad_id, 10, 17).await?, 19);
+        assert_eq!(
+            nums(Database(&db).get_ad_checkpoints(ad_id).await?),
+            vec![10, 20, 30, 40]
+        );
+        let updates = nums(Database(&db).get_ad_updates(ad_id).await?);
+        assert_eq!(updates[..2], [0, 20]);
+        assert_eq!(updates.len(), 22);
+        let history = Database(&db).get_ad_update_history(ad_id).await?;
+        assert_eq!(
+            history
+                .iter()
+                .map(|entry| (entry.num, entry.pruned_from))
+                .take(4)
+                .collect::<Vec<_>>(),
+            vec![(0, None), (10, Some(1)), (20, Some(11)), (21, None)]
+        );
+        assert_eq!(history.len(), 23);
+        // the attestation links of the listed updates are the ones they had before the pruning
+        for (num, link) in links(&history) {
+            assert_eq!(links_before[&num], link, "link of update {}", num);
+        }
+
+        // already compacted
+        assert_eq!(compact_ad(&db, ad_id, 10, 17).await?, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_attribute_blobs() -> Result<()> {
+        let vh = B256::repeat_byte;
+        // blob tx as returned by the execution node
+        let blob_tx = |to: &str, tx_hash: u8, vhs: &[B256]| {
+            serde_json::from_value::<alloy::rpc::types::Transaction>(serde_json::json!({
+                "type": "0x3",
+                "chainId": "0xaa36a7",
+                "nonce": "0x0",
+                "gas": "0x5208",
+                "maxFeePerGas": "0x3b9aca00",
+                "maxPriorityFeePerGas": "0x3b9aca00",
+                "to": to,
+                "value": "0x0",
+                "accessList": [],
+                "blobVersionedHashes": vhs,
+                "maxFeePerBlobGas": "0x1",
+                "input": "0x",
+                "r": "0x1",
+                "s": "0x1",
+                "yParity": "0x0",
+                "v": "0x0",
+                "hash": B256::repeat_byte(tx_hash),
+                "blockHash": B256::repeat_byte(0xe1),
+                "blockNumber": "0x7d0",
+                "transactionIndex": "0x0",
+                "from": "0x1111111111111111111111111111111111111111",
+                "gasPrice": "0x3b9aca00"
+            }))
+        };
+        let to_addr = Address::from_str("0x00000000000000000000000000000000000000ad")?;
+        let other = "0x00000000000000000000000000000000000000bb";
+        let txs = [
+            // unrelated blob tx, and a blob sent again by an AD tx with the same sidecar
+            blob_tx(other, 1, &[vh(0x0a)])?,
+            blob_tx(other, 2, &[vh(0x0d)])?,
+            blob_tx(&to_addr.to_string(), 3, &[vh(0x0d)])?,
+            // the AD tx, with a blob missing from the sidecars
+            blob_tx(&to_addr.to_string(), 4, &[vh(0x0c), vh(0x0e)])?,
+        ];
+        // a blob without tx before the AD one, which is in the sidecars twice
+        let block_blob_vhs = [vh(0x0a), vh(0x0b), vh(0x0c), vh(0x0c), vh(0x0d)];
+
+        let blobs = attribute_blobs(1000, &block_blob_vhs, &txs);
+        let attributed: Vec<_> = blobs
+            .iter()
+            .map(|blob| {
+                let txs: Vec<_> = blob
+                    .txs
+                    .iter()
+                    .map(|tx| *tx.as_recovered().hash())
+                    .collect();
+                (blob.versioned_hash, blob.index, txs)
+            })
+            .collect();
+        let tx_hash = B256::repeat_byte;
+        assert_eq!(
+            attributed,
+            [
+                (vh(0x0a), 0, vec![tx_hash(1)]),
+                (vh(0x0c), 2, vec![tx_hash(4)]),
+                (vh(0x0d), 4, vec![tx_hash(2), tx_hash(3)])
+            ]
+        );
+        // the AD blobs, from the first AD tx that carries them
+        let ad_blobs: Vec<_> = blobs
+            .iter()
+            .filter_map(|blob| {
+                let tx = blob
+                    .txs
+                    .iter()
+                    .find(|tx| tx.as_recovered().to() == Some(to_addr))?;
+                Some((blob.versioned_hash, *tx.as_recovered().hash()))
+            })
+            .collect();
+        assert_eq!(ad_blobs, [(vh(0x0c), tx_hash(4)), (vh(0x0d), tx_hash(3))]);
+        Ok(())
+    }
+
+    const FIXTURES_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/ad-blob");
+    // slot of the blob tx of the fixtures, which posts the create payload of an AD
+    const FIXTURES_SLOT: u64 = 1000;
+
+    /// Config of a node that replays the fixtures from `slot` on, with its db and blobs in `dir`
+    fn fixtures_config(dir: &Path, slot: u64) -> Result<Config> {
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        Ok(Config {
+            beacon_url: "http://localhost:5052".to_string(),
+            beacon_auth_token: None,
+            beacon_extra_headers: Vec::new(),
+            beacon_url_fallback: None,
+            blob_fetch_retries: 0,
+            beacon_blob_timeout: 30,
+            beacon_timeout: 8,
+            rpc_url: "http://localhost:8545".to_string(),
+            sqlite_path: path("db.sqlite"),
+            blobs_path: path("blobs"),
+            ad_genesis_slot: slot,
+            confirmation_depth: 0,
+            to_addr: Address::from_str("0x00000000000000000000000000000000000000ad")?,
+            from_addr_allowlist: Some(vec![Address::from_str(
+                "0x1111111111111111111111111111111111111111",
+            )?]),
+            index_calldata: false,
+            request_rate: 0,
+            blob_archive_url: None,
+            proof_type: ProofType::Plonky2,
+            shrink_zk: false,
+            // shared by the test runs, building the circuit data takes a while
+            circuit_cache_path: std::env::temp_dir()
+                .join("synchronizer-test-circuit-cache")
+                .to_string_lossy()
+                .into_owned(),
+            params: Params::default(),
+            verify_concurrency: 1,
+            fixtures_path: Some(FIXTURES_PATH.to_string()),
+            record_fixtures: false,
+            http_bind: IpAddr::from([127, 0, 0, 1]),
+            http_port: 8001,
+            checkpoint_interval: 0,
+            ad_update_retention: 1024,
+            compaction_interval: 3600,
+            attestation_key: Some(SigningKey::from_bytes(&[7; 32])),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_process_fixtures() -> Result<()> {
+        let slot = FIXTURES_SLOT;
+        let sender = Address::from_str("0x1111111111111111111111111111111111111111")?;
+        let dir =
+            std::env::temp_dir().join(format!("synchronizer-fixtures-test-{}", std::process::id()));
+        create_dir_all(&dir)?;
+        let cfg = fixtures_config(&dir, slot)?;
+        let node = Node::new(cfg).await?;
+        node.backfill(slot, slot).await?;
+        // left for `run` to follow
+        assert_eq!(Database(&node.db).get_visited_slot_last().await?, None);
+
+        let hash = |elems: [u64; 4]| Hash(elems.map(F::from_canonical_u64));
+        let ad = Database(&node.db).get_ad(hash([1, 2, 3, 4])).await?;
+        assert_eq!(ad.custom_predicate_ref.0.batch.id(), hash([5, 6, 7, 8]));
+        assert_eq!(ad.custom_predicate_ref.0.index, 2);
+        assert_eq!(ad.vds_root.0, hash([9, 10, 11, 12]));
+        let blob = Database(&node.db)
+            .get_blob(ad.blob_versioned_hash)
+            .await?
+            .context("blob not stored")?;
+        assert_eq!(
+            (blob.slot, blob.block, blob.sender),
+            (1000, 2000, Some(sender.to_vec()))
+        );
+        // and stored on disk, so it isn't requested again
+        let versioned_hash = B256::from(ad.blob_versioned_hash);
+        assert!(
+            node.load_blobs_disk(slot)
+                .await?
+                .contains_key(&versioned_hash)
+        );
+
+        // the registered predicate, to compare with the batches of the ad-server
+        let node = Arc::new(node);
+        let routes = endpoints::routes(node.clone());
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!("/ad/{}/predicate", ad.id.0.encode_hex::<String>()))
+            .reply(&routes)
+            .await;
+        let predicate: serde_json::Value = serde_json::from_slice(res.body())?;
+        assert_eq!(
+            predicate,
+            serde_json::json!({
+                "batch_id": hash([5, 6, 7, 8]).encode_hex::<String>(),
+                "index": 2
+            })
+        );
+        // an id that isn't hex is a bad request
+        let res = warp::test::request()
+            .method("GET")
+            .path("/ad/not-hex/updates")
+            .reply(&routes)
+            .await;
+        assert_eq!(res.status(), 400);
+        let body: serde_json::Value = serde_json::from_slice(res.body())?;
+        assert_eq!(body["error"]["kind"], "invalid_request");
+        // the ADs indexed, and their updates from a num on
+        let res = warp::test::request()
+            .method("GET")
+            .path("/ads")
+            .reply(&routes)
+            .await;
+        let ad_ids: Vec<String> = serde_json::from_slice(res.body())?;
+        assert_eq!(ad_ids, vec![ad.id.0.encode_hex::<String>()]);
+        for (from, len) in [(0, 1), (1, 0)] {
+            let res = warp::test::request()
+                .method("GET")
+                .path(&format!(
+                    "/ad/{}/updates?from={}",
+                    ad.id.0.encode_hex::<String>(),
+                    from
+                ))
+                .reply(&routes)
+                .await;
+            let updates: Vec<serde_json::Value> = serde_json::from_slice(res.body())?;
+            assert_eq!(updates.len(), len, "from {}", from);
+        }
+        // the payload decoded from the blob
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!("/blob/{}", versioned_hash))
+            .reply(&routes)
+            .await;
+        let blob_payload: serde_json::Value = serde_json::from_slice(res.body())?;
+        assert_eq!(
+            blob_payload,
+            serde_json::json!({
+                "slot": 1000,
+                "blob_index": blob.blob_index,
+                "payload": {
+                    "type": "create",
+                    "id": hash([1, 2, 3, 4]).encode_hex::<String>(),
+                    "batch_id": hash([5, 6, 7, 8]).encode_hex::<String>(),
+                    "index": 2,
+                    "vds_root": hash([9, 10, 11, 12]).encode_hex::<String>(),
+                    "params_fingerprint": null
+                }
+            })
+        );
+        // the params the updates are verified with, to compare with the ad-server
+        let res = warp::test::request()
+            .method("GET")
+            .path("/status")
+            .reply(&routes)
+            .await;
+        let status: serde_json::Value = serde_json::from_slice(res.body())?;
+        assert_eq!(
+            status,
+            serde_json::json!({
+                "params_fingerprint": params_fingerprint(&Params::default()),
+                "last_slot": slot,
+                "confirmation_depth": 0
+            })
+        );
+        // the signed view of the AD, at its create
+        let res = warp::test::request()
+            .method("GET")
+            .path(&format!(
+                "/ad/{}/attestation",
+                ad.id.0.encode_hex::<String>()
+            ))
+            .reply(&routes)
+            .await;
+        let attestation: Attestation = serde_json::from_slice(res.body())?;
+        verify_attestation(
+            &attestation,
+            &SigningKey::from_bytes(&[7; 32]).verifying_key(),
+        )?;
+        assert_eq!(
+            (attestation.body.num, attestation.body.slot),
+            (0, Some(slot))
+        );
+        assert_eq!(attestation.body.vds_root, hash([9, 10, 11, 12]));
+        assert_eq!(
+            attestation.body.updates[0].blob_versioned_hash,
+            versioned_hash
+        );
+
+        // the stored create matches its payload
+        verify_ad(&node, ad.id.0).await?;
+        // an update that doesn't come from an update payload
+        Database(&node.db)
+            .add_ad_update(&tables::AdUpdate {
+                id: HashSql(ad.id.0),
+                num: 1,
+                state: RawValueSql(RawValue::from(1)),
+                blob_versioned_hash: ad.blob_versioned_hash,
+            })
             .await?;
-        Database(&mut *tx).add_visited_slot(slot as i64).await?;
-        tx.commit().await?;
+        let err = verify_ad(&node, ad.id.0)
+            .await
+            .expect_err("tampered update");
+        assert!(err.to_string().contains("diverges at num 1"), "{:#}", err);
 
-        if node.cfg.request_rate != 0 {
-            let requests = 5;
-            let delay_ms = 1000 * requests / node.cfg.request_rate;
-            sleep(Duration::from_millis(delay_ms)).await;
+        remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    /// Beacon client of the fixtures with a head set by the test, at which it serves the header
+    /// of the fixtures.  The other slots are empty.
+    #[derive(Debug)]
+    struct HeadBeaconClient {
+        fixtures: ReplayBeaconClient,
+        head: AtomicU64,
+    }
+
+    #[async_trait::async_trait]
+    impl CommonBeaconClient for HeadBeaconClient {
+        async fn get_block(&self, block_id: BlockId) -> ClientResult<Option<Block>> {
+            self.fixtures.get_block(block_id).await
+        }
+
+        async fn get_block_header(&self, block_id: BlockId) -> ClientResult<Option<BlockHeader>> {
+            match block_id {
+                BlockId::Head => {
+                    let header = self
+                        .fixtures
+                        .get_block_header(BlockId::Slot(FIXTURES_SLOT))
+                        .await?;
+                    Ok(header.map(|header| BlockHeader {
+                        slot: self.head.load(Ordering::SeqCst),
+                        ..header
+                    }))
+                }
+                BlockId::Slot(FIXTURES_SLOT) => self.fixtures.get_block_header(block_id).await,
+                _ => Ok(None),
+            }
         }
 
-        slot += 1;
+        async fn get_blobs(&self, block_id: BlockId) -> ClientResult<Vec<Blob>> {
+            self.fixtures.get_blobs(block_id).await
+        }
+
+        async fn get_spec(&self) -> ClientResult<Spec> {
+            Ok(Spec {
+                deposit_network_id: 1,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_confirmation_depth() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("synchronizer-depth-test-{}", std::process::id()));
+        create_dir_all(&dir)?;
+        let mut cfg = fixtures_config(&dir, FIXTURES_SLOT)?;
+        cfg.confirmation_depth = 2;
+        cfg.http_port = 0;
+        let mut node = Node::new(cfg).await?;
+        // the slot of the fixtures is 1 slot behind the head, within the buffer
+        let beacon = Arc::new(HeadBeaconClient {
+            fixtures: ReplayBeaconClient::new(Path::new(FIXTURES_PATH)),
+            head: AtomicU64::new(FIXTURES_SLOT + 1),
+        });
+        node.beacon_cli = beacon.clone();
+        let db = node.db.clone();
+        let ad_id = Hash([1, 2, 3, 4].map(F::from_canonical_u64));
+        let task = tokio::spawn(run(node));
+
+        // the head is polled again after 5s, and then every second
+        sleep(Duration::from_secs(7)).await;
+        assert!(!task.is_finished());
+        assert_eq!(Database(&db).get_visited_slot_last().await?, None);
+        assert!(Database(&db).get_ad(ad_id).await.is_err());
+
+        // 2 slots behind the head, the slot is deep enough
+        beacon.head.store(FIXTURES_SLOT + 2, Ordering::SeqCst);
+        tokio::time::timeout(Duration::from_secs(10), async {
+            while Database(&db).get_visited_slot_last().await? != Some(FIXTURES_SLOT) {
+                sleep(Duration::from_millis(100)).await;
+            }
+            Ok::<_, anyhow::Error>(())
+        })
+        .await
+        .context("slot not processed")??;
+        Database(&db).get_ad(ad_id).await?;
+
+        task.abort();
+        remove_dir_all(&dir)?;
+        Ok(())
     }
 }